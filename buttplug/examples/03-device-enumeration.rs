@@ -146,15 +146,15 @@ async fn device_enumeration_example() {
           // Spawn off the reaction, so if we get multiple devices they all fire
           // simultaneously instead of waiting for each other.
           async_manager::spawn(async move {
-            println!("We got a device: {}", device.name);
+            println!("We got a device: {}", device.name());
             device.vibrate(VibrateCommand::Speed(1.0)).await.unwrap();
-            println!("{} should start vibrating!", device.name);
+            println!("{} should start vibrating!", device.name());
             Delay::new(Duration::from_secs(1)).await;
             // All devices also have a "stop" command that will make
             // them stop whatever they're doing.
             device.stop().await.unwrap();
             println!("Battery: {}", device.battery_level().await.unwrap());
-            println!("{} should stop vibrating!", device.name);
+            println!("{} should stop vibrating!", device.name());
             Delay::new(Duration::from_secs(1)).await;
           })
           .unwrap();
@@ -198,7 +198,7 @@ async fn device_enumeration_example() {
   // will error if that has happened.
   println!("Devices currently connected:");
   for dev in client.devices() {
-    println!("- {}", dev.name);
+    println!("- {}", dev.name());
   }
   // And now we're done!
   println!("Exiting example");
@@ -8,13 +8,7 @@
 // Let's make something move! In this example, we'll see how to tell what a
 // device can do, then send it a command (assuming it vibrates)!
 
-use buttplug::{
-  client::{
-    ButtplugClient, ButtplugClientDevice, ButtplugClientDeviceMessageType, ButtplugClientEvent,
-    VibrateCommand,
-  },
-  server::ButtplugServerOptions,
-};
+use buttplug::prelude::*;
 use futures::StreamExt;
 use futures_timer::Delay;
 use std::{sync::Arc, time::Duration};
@@ -86,19 +80,19 @@ async fn device_control_example() {
       //
       // For this example, we'll use the simple single value.
       if dev
-        .allowed_messages
+        .allowed_messages()
         .contains_key(&ButtplugClientDeviceMessageType::VibrateCmd)
       {
         dev.vibrate(VibrateCommand::Speed(1.0)).await.unwrap();
-        println!("{} should start vibrating!", dev.name);
+        println!("{} should start vibrating!", dev.name());
         Delay::new(Duration::from_secs(1)).await;
         // All devices also have a "stop" command that will make
         // them stop whatever they're doing.
         dev.stop().await.unwrap();
-        println!("{} should stop vibrating!", dev.name);
+        println!("{} should stop vibrating!", dev.name());
         Delay::new(Duration::from_secs(1)).await;
       } else {
-        println!("{} doesn't vibrate! This example should be updated to handle rotation and linear movement!", dev.name);
+        println!("{} doesn't vibrate! This example should be updated to handle rotation and linear movement!", dev.name());
       }
     }
   };
@@ -106,7 +100,7 @@ async fn device_control_example() {
   loop {
     match event_stream.next().await.unwrap() {
       ButtplugClientEvent::DeviceAdded(dev) => {
-        println!("We got a device: {}", dev.name);
+        println!("We got a device: {}", dev.name());
         let fut = vibrate_device(dev);
         tokio::spawn(async move {
           fut.await;
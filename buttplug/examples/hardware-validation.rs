@@ -0,0 +1,73 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+// Runs the hardware validation pass against real devices, for maintainers
+// checking for regressions on physical hardware. Takes a config listing the
+// attached devices (see buttplug::test::HardwareTestConfig), prints the JSON
+// report to stdout, and exits with an error code if anything failed.
+//
+// cargo run --example hardware-validation --features hardware-tests -- devices.json
+
+use buttplug::{
+  client::ButtplugClient,
+  connector::ButtplugInProcessClientConnector,
+  server::ButtplugServerBuilder,
+  test::{run_hardware_tests, HardwareTestConfig},
+  util::async_manager,
+};
+
+async fn hardware_validation() -> i32 {
+  tracing_subscriber::fmt()
+    .with_writer(std::io::stderr)
+    .init();
+  let config_path = match std::env::args().nth(1) {
+    Some(path) => path,
+    None => {
+      eprintln!("Usage: hardware-validation <config.json>");
+      return 2;
+    }
+  };
+  let config = match std::fs::read_to_string(&config_path)
+    .map_err(|err| err.to_string())
+    .and_then(|json| HardwareTestConfig::from_json(&json).map_err(|err| err.to_string()))
+  {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("Cannot load config {}: {}", config_path, err);
+      return 2;
+    }
+  };
+
+  let server = match ButtplugServerBuilder::default()
+    .with_default_device_managers()
+    .finish()
+  {
+    Ok(server) => server,
+    Err(err) => {
+      eprintln!("Cannot create in-process server: {}", err);
+      return 2;
+    }
+  };
+  let connector = ButtplugInProcessClientConnector::new_with_server(server);
+
+  let client = ButtplugClient::new("Hardware Validation");
+  if let Err(err) = client.connect(connector).await {
+    eprintln!("Cannot connect to in-process server: {}", err);
+    return 2;
+  }
+  let report = run_hardware_tests(&client, &config).await;
+  println!("{}", report.to_json());
+  if report.passed {
+    0
+  } else {
+    1
+  }
+}
+
+fn main() {
+  std::process::exit(async_manager::block_on(hardware_validation()));
+}
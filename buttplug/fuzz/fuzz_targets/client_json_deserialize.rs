@@ -0,0 +1,29 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Feeds arbitrary strings to the client side JSON serializer, which is what
+//! handles whatever a (possibly malicious) remote server sends us.
+
+#![no_main]
+use buttplug::core::messages::{
+  serializer::{ButtplugClientJSONSerializer, ButtplugMessageSerializer, ButtplugSerializedMessage},
+  ButtplugMessageValidator,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let input = match std::str::from_utf8(data) {
+    Ok(input) => input,
+    Err(_) => return,
+  };
+  let serializer = ButtplugClientJSONSerializer::default();
+  if let Ok(msgs) = serializer.deserialize(ButtplugSerializedMessage::Text(input.to_owned())) {
+    for msg in msgs {
+      let _ = msg.is_valid();
+    }
+  }
+});
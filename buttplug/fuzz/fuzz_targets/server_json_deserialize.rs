@@ -0,0 +1,52 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Feeds arbitrary strings to the server side JSON serializer, both before and
+//! after a handshake has set the message spec version, then runs whatever
+//! comes out through validation and back out through serialization, same as
+//! the remote server would.
+
+#![no_main]
+use buttplug::core::messages::{
+  serializer::{ButtplugMessageSerializer, ButtplugSerializedMessage, ButtplugServerJSONSerializer},
+  ButtplugMessageValidator,
+};
+use libfuzzer_sys::fuzz_target;
+
+const HANDSHAKES: [&str; 3] = [
+  r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Fuzz","MessageVersion":0}}]"#,
+  r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Fuzz","MessageVersion":1}}]"#,
+  r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Fuzz","MessageVersion":2}}]"#,
+];
+
+fn run(serializer: &ButtplugServerJSONSerializer, input: &str) {
+  match serializer.deserialize(ButtplugSerializedMessage::Text(input.to_owned())) {
+    Ok(msgs) => {
+      for msg in msgs {
+        let _ = msg.is_valid();
+      }
+    }
+    Err(err) => {
+      let _ = serializer.serialize_error(&err);
+    }
+  }
+}
+
+fuzz_target!(|data: &[u8]| {
+  let input = match std::str::from_utf8(data) {
+    Ok(input) => input,
+    Err(_) => return,
+  };
+  run(&ButtplugServerJSONSerializer::default(), input);
+  for handshake in HANDSHAKES.iter() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer
+      .deserialize(ButtplugSerializedMessage::Text((*handshake).to_owned()))
+      .expect("Handshake message should always deserialize.");
+    run(&serializer, input);
+  }
+});
@@ -0,0 +1,194 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Builder for setting up a [ButtplugClient] before it connects.
+//!
+//! The server sends its device list as part of connecting, so DeviceAdded
+//! events can go out before [ButtplugClient::connect] even returns.
+//! Applications that only call [ButtplugClient::event_stream] after
+//! connecting will miss those. Handlers registered on a
+//! [ButtplugClientBuilder] are hooked up before anything can be emitted, so
+//! they see every event the client sends.
+//!
+//! Handlers run in registration order, on a task of their own, so they
+//! shouldn't block for long. Anything slow should be spawned off.
+
+use super::{
+  middleware::ButtplugClientMiddleware, ButtplugClient, ButtplugClientDevice, ButtplugClientError,
+  ButtplugClientEvent,
+};
+use crate::{
+  connector::ButtplugConnector,
+  core::{
+    errors::ButtplugError,
+    messages::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+  },
+  util::async_manager,
+};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+type ButtplugClientEventHandler = Box<dyn Fn(ButtplugClientEvent) + Send + Sync>;
+
+/// Sets up a [ButtplugClient] with its name, middleware, and event handlers,
+/// before it connects.
+///
+/// ```no_run
+/// # use buttplug::client::ButtplugClientBuilder;
+/// # use buttplug::connector::ButtplugInProcessClientConnector;
+/// # async fn example() {
+/// let client = ButtplugClientBuilder::new("Example Client")
+///   .on_device_added(|device| println!("Device added: {}", device.name()))
+///   .on_server_disconnect(|| println!("Server disconnected"))
+///   .connect(ButtplugInProcessClientConnector::default())
+///   .await
+///   .unwrap();
+/// # }
+/// ```
+pub struct ButtplugClientBuilder {
+  name: String,
+  middleware: Vec<Arc<dyn ButtplugClientMiddleware>>,
+  event_handlers: Vec<ButtplugClientEventHandler>,
+}
+
+impl ButtplugClientBuilder {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      middleware: vec![],
+      event_handlers: vec![],
+    }
+  }
+
+  /// Changes the client name sent to the server during the handshake.
+  pub fn name(mut self, name: &str) -> Self {
+    self.name = name.to_owned();
+    self
+  }
+
+  /// Adds middleware, same as [ButtplugClient::add_middleware].
+  pub fn middleware<T>(mut self, middleware: T) -> Self
+  where
+    T: ButtplugClientMiddleware + 'static,
+  {
+    self.middleware.push(Arc::new(middleware));
+    self
+  }
+
+  /// Registers a handler that gets every client event.
+  pub fn on_event<F>(mut self, handler: F) -> Self
+  where
+    F: Fn(ButtplugClientEvent) + Send + Sync + 'static,
+  {
+    self.event_handlers.push(Box::new(handler));
+    self
+  }
+
+  pub fn on_device_added<F>(self, handler: F) -> Self
+  where
+    F: Fn(Arc<ButtplugClientDevice>) + Send + Sync + 'static,
+  {
+    self.on_event(move |event| {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        handler(device);
+      }
+    })
+  }
+
+  pub fn on_device_removed<F>(self, handler: F) -> Self
+  where
+    F: Fn(Arc<ButtplugClientDevice>) + Send + Sync + 'static,
+  {
+    self.on_event(move |event| {
+      if let ButtplugClientEvent::DeviceRemoved(device) = event {
+        handler(device);
+      }
+    })
+  }
+
+  pub fn on_scanning_finished<F>(self, handler: F) -> Self
+  where
+    F: Fn() + Send + Sync + 'static,
+  {
+    self.on_event(move |event| {
+      if let ButtplugClientEvent::ScanningFinished = event {
+        handler();
+      }
+    })
+  }
+
+  pub fn on_server_disconnect<F>(self, handler: F) -> Self
+  where
+    F: Fn() + Send + Sync + 'static,
+  {
+    self.on_event(move |event| {
+      if let ButtplugClientEvent::ServerDisconnect = event {
+        handler();
+      }
+    })
+  }
+
+  /// Registers a handler for errors from the server that weren't replies to
+  /// any request.
+  pub fn on_error<F>(self, handler: F) -> Self
+  where
+    F: Fn(ButtplugError) + Send + Sync + 'static,
+  {
+    self.on_event(move |event| {
+      if let ButtplugClientEvent::Error(err) = event {
+        handler(err);
+      }
+    })
+  }
+
+  /// Builds the client, without connecting it. Handlers are already hooked up
+  /// at this point.
+  pub fn finish(self) -> ButtplugClient {
+    let client = ButtplugClient::new(&self.name);
+    for middleware in self.middleware {
+      client.middleware.add(middleware);
+    }
+    if !self.event_handlers.is_empty() {
+      // Subscribe here, not in the task, so nothing sent between now and the
+      // task starting gets missed.
+      let mut receiver = client.event_stream.subscribe();
+      let handlers = self.event_handlers;
+      async_manager::spawn(async move {
+        loop {
+          match receiver.recv().await {
+            Ok(event) => {
+              for handler in &handlers {
+                handler(event.clone());
+              }
+            }
+            Err(RecvError::Lagged(count)) => {
+              warn!("Client event handlers fell behind, {} events dropped.", count);
+            }
+            // Client is gone.
+            Err(RecvError::Closed) => break,
+          }
+        }
+      })
+      .unwrap();
+    }
+    client
+  }
+
+  /// Builds the client and connects it using `connector`.
+  pub async fn connect<ConnectorType>(
+    self,
+    connector: ConnectorType,
+  ) -> Result<ButtplugClient, ButtplugClientError>
+  where
+    ConnectorType: ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>
+      + 'static,
+  {
+    let client = self.finish();
+    client.connect(connector).await?;
+    Ok(client)
+  }
+}
@@ -8,19 +8,23 @@
 //! Implementation of internal Buttplug Client event loop.
 
 use super::{
-  client_message_sorter::ClientMessageSorter,
+  client_request_multiplexer::ButtplugClientRequestMultiplexer,
+  client_event_queue::ButtplugClientQueuedEvent,
+  clock_sync::ClientClock,
   device::{ButtplugClientDevice, ButtplugClientDeviceEvent},
-  ButtplugClientEvent, ButtplugClientMessageFuturePair,
+  middleware::ButtplugClientMiddlewareList,
+  ButtplugClientEvent,
 };
 use crate::{
   connector::{ButtplugConnector, ButtplugConnectorStateShared},
   core::{
-    errors::{ButtplugDeviceError, ButtplugError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
     messages::{
       ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage, ButtplugDeviceMessage,
-      ButtplugMessageValidator, DeviceList, DeviceMessageInfo,
+      ButtplugMessage, ButtplugMessageValidator, DeviceList, DeviceMessageInfo, DeviceMetadata,
     },
   },
+  util::future::ButtplugFutureStateShared,
 };
 use dashmap::DashMap;
 use futures::FutureExt;
@@ -35,14 +39,14 @@ use tokio::sync::{broadcast, mpsc};
 pub(super) enum ButtplugClientRequest {
   /// Client request to disconnect, via already sent connector instance.
   Disconnect(ButtplugConnectorStateShared),
-  /// Given a DeviceList message, update the inner loop values and create
-  /// events for additions.
-  HandleDeviceList(DeviceList),
+  /// Given a DeviceList message, reconcile it with the device map, creating
+  /// events for any additions or removals. Replies once all events are sent.
+  HandleDeviceList(DeviceList, ButtplugFutureStateShared<()>),
   /// Client request to send a message via the connector.
   ///
-  /// Bundled future should have reply set and waker called when this is
-  /// finished.
-  Message(ButtplugClientMessageFuturePair),
+  /// The message id has already been set and registered with the
+  /// [ButtplugClientRequestMultiplexer], which will receive the response.
+  Message(ButtplugCurrentSpecClientMessage),
 }
 
 /// Event loop for running [ButtplugClient] connections.
@@ -88,12 +92,18 @@ where
   device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
   /// Sends events to the [ButtplugClient] instance.
   to_client_sender: broadcast::Sender<ButtplugClientEvent>,
-  /// Sends events to the client receiver. Stored here so it can be handed to
-  /// new ButtplugClientDevice instances.
-  from_client_sender: broadcast::Sender<ButtplugClientRequest>,
+  /// Sends client and device events to any [ButtplugClientEventQueue]s.
+  queued_event_sender: broadcast::Sender<ButtplugClientQueuedEvent>,
   /// Receives incoming messages from client instances.
   from_client_receiver: broadcast::Receiver<ButtplugClientRequest>,
-  sorter: ClientMessageSorter,
+  /// Pairs responses with requests. Also handed to new ButtplugClientDevice
+  /// instances, so they can send requests.
+  multiplexer: Arc<ButtplugClientRequestMultiplexer>,
+  /// Middleware run on messages to and from the connector, shared with the
+  /// client so more can be added while connected.
+  middleware: ButtplugClientMiddlewareList,
+  /// Server clock estimate, handed to new ButtplugClientDevice instances.
+  clock: Arc<ClientClock>,
 }
 
 impl<ConnectorType> ButtplugClientEventLoop<ConnectorType>
@@ -106,24 +116,33 @@ where
   /// Given the [ButtplugClientConnector] object, as well as the channels used
   /// for communicating with the client, creates an event loop structure and
   /// returns it.
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     connected_status: Arc<AtomicBool>,
     connector: ConnectorType,
     from_connector_receiver: mpsc::Receiver<ButtplugCurrentSpecServerMessage>,
     to_client_sender: broadcast::Sender<ButtplugClientEvent>,
-    from_client_sender: broadcast::Sender<ButtplugClientRequest>,
+    queued_event_sender: broadcast::Sender<ButtplugClientQueuedEvent>,
+    multiplexer: Arc<ButtplugClientRequestMultiplexer>,
     device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
+    middleware: ButtplugClientMiddlewareList,
+    clock: Arc<ClientClock>,
   ) -> Self {
     trace!("Creating ButtplugClientEventLoop instance.");
+    // Subscribe before opening, so nothing sent after this is missed.
+    let from_client_receiver = multiplexer.subscribe_requests();
+    multiplexer.open();
     Self {
       connected_status,
       device_map,
-      from_client_receiver: from_client_sender.subscribe(),
-      from_client_sender,
+      from_client_receiver,
       to_client_sender,
+      queued_event_sender,
       from_connector_receiver,
       connector,
-      sorter: ClientMessageSorter::default(),
+      multiplexer,
+      middleware,
+      clock,
     }
   }
 
@@ -132,7 +151,11 @@ where
   /// Given a [DeviceMessageInfo] from a [DeviceAdded] or [DeviceList] message,
   /// creates a ButtplugClientDevice and adds it the internal device map, then
   /// returns the instance.
-  fn create_client_device(&mut self, info: &DeviceMessageInfo) -> Arc<ButtplugClientDevice> {
+  fn create_client_device(
+    &mut self,
+    info: &DeviceMessageInfo,
+    metadata: Option<DeviceMetadata>,
+  ) -> Arc<ButtplugClientDevice> {
     debug!(
       "Trying to create a client device from DeviceMessageInfo: {:?}",
       info
@@ -148,7 +171,9 @@ where
         debug!("Device does not exist, creating new entry.");
         let device = Arc::new(ButtplugClientDevice::new_from_device_info(
           info,
-          self.from_client_sender.clone(),
+          metadata,
+          self.multiplexer.clone(),
+          self.clock.clone(),
         ));
         self.device_map.insert(info.device_index, device.clone());
         device
@@ -159,6 +184,11 @@ where
   fn send_client_event(&mut self, event: ButtplugClientEvent) {
     trace!("Forwarding event {:?} to client", event);
 
+    // Errors here just mean nobody is polling an event queue.
+    let _ = self
+      .queued_event_sender
+      .send(ButtplugClientQueuedEvent::Client(event.clone()));
+
     if self.to_client_sender.receiver_count() == 0 {
       error!(
         "Client event {:?} dropped, no client event listener available.",
@@ -172,6 +202,14 @@ where
     self.to_client_sender.send(event).unwrap();
   }
 
+  fn send_device_event(&mut self, device: &ButtplugClientDevice, event: ButtplugClientDeviceEvent) {
+    let _ = self.queued_event_sender.send(ButtplugClientQueuedEvent::Device {
+      device_index: device.index(),
+      event: event.clone(),
+    });
+    device.queue_event(event);
+  }
+
   fn disconnect_device(&mut self, device_index: u32) {
     if !self.device_map.contains_key(&device_index) {
       return;
@@ -179,7 +217,7 @@ where
     // Checked for device index existence, can unwrap here.
     let device = (*self.device_map.get(&device_index).unwrap()).clone();
     device.set_device_connected(false);
-    device.queue_event(ButtplugClientDeviceEvent::DeviceRemoved);
+    self.send_device_event(&device, ButtplugClientDeviceEvent::DeviceRemoved);
     // Then remove it from our storage map
     self.device_map.remove(&device_index);
     self.send_client_event(ButtplugClientEvent::DeviceRemoved(device));
@@ -188,12 +226,13 @@ where
   /// Parse device messages from the connector.
   ///
   /// Since the event loop maintains the state of all devices reported from the
-  /// server, it will catch [DeviceAdded]/[DeviceList]/[DeviceRemoved] messages
-  /// and update its map accordingly. After that, it will pass the information
-  /// on as a [ButtplugClientEvent] to the [ButtplugClient].
+  /// server, it will catch [DeviceAdded]/[DeviceList]/[DeviceRemoved]/
+  /// [DeviceUpdated] messages and update its map accordingly. After that, it
+  /// will pass the information on as a [ButtplugClientEvent] to the
+  /// [ButtplugClient].
   async fn parse_connector_message(&mut self, msg: ButtplugCurrentSpecServerMessage) {
-    if self.sorter.maybe_resolve_result(&msg) {
-      trace!("Message future found, returning");
+    if self.multiplexer.resolve(&msg) {
+      trace!("Message request found, returning");
       return;
     }
     if let Err(e) = msg.is_valid() {
@@ -201,6 +240,23 @@ where
       self.send_client_event(ButtplugClientEvent::Error(ButtplugError::from(e)));
       return;
     }
+    // Anything that isn't a reply to one of our requests has to be an event,
+    // which always has an id of 0.
+    if !msg.is_server_event() {
+      error!(
+        "Message id {} does not match any request and is not an event: {:?}",
+        msg.id(),
+        msg
+      );
+      self.send_client_event(ButtplugClientEvent::Error(
+        ButtplugMessageError::InvalidMessageContents(format!(
+          "Server sent message with id {}, which does not match any request.",
+          msg.id()
+        ))
+        .into(),
+      ));
+      return;
+    }
     trace!("Message future not found, assuming server event.");
     info!("{:?}", msg);
     match msg {
@@ -217,8 +273,9 @@ where
           ));
           return;
         }
+        let metadata = dev.device_metadata().clone();
         let info = DeviceMessageInfo::from(dev);
-        let device = self.create_client_device(&info);
+        let device = self.create_client_device(&info, metadata);
         self.send_client_event(ButtplugClientEvent::DeviceAdded(device));
       }
       ButtplugCurrentSpecServerMessage::DeviceRemoved(dev) => {
@@ -230,18 +287,42 @@ where
           self.send_client_event(ButtplugClientEvent::Error(ButtplugDeviceError::DeviceConnectionError("Device removal requested for a device the client does not know about. Server may be in a weird state.".to_owned()).into()));
         }
       }
+      ButtplugCurrentSpecServerMessage::DeviceUpdated(dev) => {
+        let device = self
+          .device_map
+          .get(&dev.device_index())
+          .map(|device| device.value().clone());
+        if let Some(device) = device {
+          trace!("Device updated, sending to client");
+          device.update(&DeviceMessageInfo::from(dev));
+          self.send_device_event(&device, ButtplugClientDeviceEvent::DeviceUpdated);
+          self.send_client_event(ButtplugClientEvent::DeviceUpdated(device));
+        } else {
+          error!("Received DeviceUpdated for non-existent device index");
+          self.send_client_event(ButtplugClientEvent::Error(
+            ButtplugDeviceError::DeviceConnectionError(
+              "Device update sent for an unknown device. Server may be in a weird state."
+                .to_owned(),
+            )
+            .into(),
+          ));
+        }
+      }
       ButtplugCurrentSpecServerMessage::ScanningFinished(_) => {
         trace!("Scanning finished event received, forwarding to client.");
         self.send_client_event(ButtplugClientEvent::ScanningFinished);
       }
       ButtplugCurrentSpecServerMessage::RawReading(msg) => {
         let device_idx = msg.device_index();
-        if let Some(device) = self.device_map.get(&device_idx) {
-          device
-            .value()
-            .queue_event(ButtplugClientDeviceEvent::Message(
-              ButtplugCurrentSpecServerMessage::from(msg),
-            ));
+        let device = self
+          .device_map
+          .get(&device_idx)
+          .map(|device| device.value().clone());
+        if let Some(device) = device {
+          self.send_device_event(
+            &device,
+            ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::from(msg)),
+          );
         }
       }
       ButtplugCurrentSpecServerMessage::Error(e) => {
@@ -252,19 +333,14 @@ where
   }
 
   /// Send a message from the [ButtplugClient] to the [ButtplugClientConnector].
-  async fn send_message(&mut self, mut msg_fut: ButtplugClientMessageFuturePair) {
-    if let Err(e) = &msg_fut.msg.is_valid() {
-      error!("Message not valid: {:?} - Error: {}", msg_fut.msg, e);
-      msg_fut
-        .waker
-        .set_reply(Err(ButtplugError::from(e.clone()).into()));
-      return;
+  async fn send_message(&mut self, msg: ButtplugCurrentSpecClientMessage) {
+    let msg = self.middleware.outgoing(msg);
+    trace!("Sending message to connector: {:?}", msg);
+    let id = msg.id();
+    if let Err(e) = self.connector.send(msg).await {
+      error!("Connector send failed: {}", e);
+      self.multiplexer.fail(id, e.into());
     }
-
-    trace!("Sending message to connector: {:?}", msg_fut.msg);
-    self.sorter.register_future(&mut msg_fut);
-    // TODO What happens if the connector isn't connected?
-    self.connector.send(msg_fut.msg).await.unwrap();
   }
 
   /// Parses message types from the client, returning false when disconnect
@@ -277,9 +353,9 @@ where
   /// - For RequestDeviceList, builds a reply out of its own
   async fn parse_client_request(&mut self, msg: ButtplugClientRequest) -> bool {
     match msg {
-      ButtplugClientRequest::Message(msg_fut) => {
-        trace!("Sending message through connector: {:?}", msg_fut.msg);
-        self.send_message(msg_fut).await;
+      ButtplugClientRequest::Message(msg) => {
+        trace!("Sending message through connector: {:?}", msg);
+        self.send_message(msg).await;
         true
       }
       ButtplugClientRequest::Disconnect(state) => {
@@ -287,15 +363,31 @@ where
         state.set_reply(self.connector.disconnect().await);
         false
       }
-      ButtplugClientRequest::HandleDeviceList(device_list) => {
+      ButtplugClientRequest::HandleDeviceList(device_list, state) => {
         trace!("Device list received, updating map.");
+        // Anything we know about that isn't in the list (or has had its index
+        // reused by another device) went away without us hearing about it.
+        let stale_indexes: Vec<u32> = self
+          .device_map
+          .iter()
+          .filter(|entry| {
+            !device_list.devices().iter().any(|info| {
+              info.device_index == *entry.key() && info.device_name == entry.value().name()
+            })
+          })
+          .map(|entry| *entry.key())
+          .collect();
+        for device_index in stale_indexes {
+          self.disconnect_device(device_index);
+        }
         for d in device_list.devices() {
           if self.device_map.contains_key(&d.device_index) {
             continue;
           }
-          let device = self.create_client_device(&d);
+          let device = self.create_client_device(d, None);
           self.send_client_event(ButtplugClientEvent::DeviceAdded(device));
         }
+        state.set_reply(());
         true
       }
     }
@@ -309,17 +401,20 @@ where
         event = self.from_connector_receiver.recv().fuse() => match event {
           None => {
             info!("Connector disconnected, exiting loop.");
+            self.multiplexer.cancel_all();
             self.send_client_event(ButtplugClientEvent::ServerDisconnect);
             return;
           }
-          Some(msg) => {
-            self.parse_connector_message(msg).await;
-          }
+          Some(msg) => match self.middleware.incoming(msg) {
+            Some(msg) => self.parse_connector_message(msg).await,
+            None => trace!("Incoming message dropped by client middleware."),
+          },
         },
         client = self.from_client_receiver.recv().fuse() => match client {
           Err(_) => {
             info!("Client disconnected, exiting loop.");
             self.connected_status.store(false, Ordering::SeqCst);
+            self.multiplexer.cancel_all();
             self.device_map.iter().for_each(|val| val.value().set_client_connected(false));
             self.send_client_event(ButtplugClientEvent::ServerDisconnect);
             return;
@@ -333,6 +428,7 @@ where
       };
     }
 
+    self.multiplexer.cancel_all();
     let device_indexes: Vec<u32> = self.device_map.iter().map(|k| *k.key()).collect();
     device_indexes
       .iter()
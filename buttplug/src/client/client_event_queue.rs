@@ -0,0 +1,108 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Polling interface for client and device events.
+//!
+//! Language bindings usually can't do much with Rust streams, and bridging a
+//! separate stream per device gets ugly fast. [ButtplugClientEventQueue] puts
+//! client events and events for every device into one queue, in the order the
+//! client event loop handled them, and hands them out through plain function
+//! calls that don't require an async runtime on the caller side.
+
+use super::{device::ButtplugClientDeviceEvent, ButtplugClientEvent};
+use futures::FutureExt;
+use futures_timer::Delay;
+use std::time::Duration;
+use tokio::sync::broadcast::{
+  self,
+  error::{RecvError, TryRecvError},
+};
+
+/// An event taken from a [ButtplugClientEventQueue].
+#[derive(Clone, Debug)]
+pub enum ButtplugClientQueuedEvent {
+  /// Event for the client as a whole (connection changes, device
+  /// additions/removals, scanning, errors).
+  Client(ButtplugClientEvent),
+  /// Event for a specific device.
+  Device {
+    device_index: u32,
+    event: ButtplugClientDeviceEvent,
+  },
+}
+
+/// Ordered queue of client and device events, created via
+/// [ButtplugClient::event_queue][super::ButtplugClient::event_queue].
+///
+/// Events are buffered from the time the queue is created. If the consumer
+/// falls too far behind, the oldest events are dropped (and a warning is
+/// logged) so the client itself never blocks on a slow consumer.
+pub struct ButtplugClientEventQueue {
+  receiver: broadcast::Receiver<ButtplugClientQueuedEvent>,
+}
+
+impl ButtplugClientEventQueue {
+  pub(super) fn new(receiver: broadcast::Receiver<ButtplugClientQueuedEvent>) -> Self {
+    Self { receiver }
+  }
+
+  /// Returns the next event if one is waiting, without blocking.
+  ///
+  /// Returns None if the queue is empty, or if the client has been dropped and
+  /// all remaining events have been read.
+  pub fn try_next_event(&mut self) -> Option<ButtplugClientQueuedEvent> {
+    loop {
+      match self.receiver.try_recv() {
+        Ok(event) => return Some(event),
+        Err(TryRecvError::Lagged(count)) => {
+          warn!("Event queue lagged behind, {} events dropped.", count);
+        }
+        Err(_) => return None,
+      }
+    }
+  }
+
+  /// Blocks the calling thread until the next event arrives or `timeout`
+  /// passes. With no timeout, waits until an event arrives or the client is
+  /// dropped.
+  ///
+  /// Returns None on timeout, or if the client has been dropped and all
+  /// remaining events have been read. This blocks the thread it's called on,
+  /// so don't call it from an async task; use
+  /// [next_event_async][Self::next_event_async] there instead.
+  pub fn next_event(&mut self, timeout: Option<Duration>) -> Option<ButtplugClientQueuedEvent> {
+    futures::executor::block_on(self.next_event_async(timeout))
+  }
+
+  /// Async version of [next_event][Self::next_event].
+  pub async fn next_event_async(
+    &mut self,
+    timeout: Option<Duration>,
+  ) -> Option<ButtplugClientQueuedEvent> {
+    let receiver = &mut self.receiver;
+    let recv_fut = async move {
+      loop {
+        match receiver.recv().await {
+          Ok(event) => return Some(event),
+          Err(RecvError::Lagged(count)) => {
+            warn!("Event queue lagged behind, {} events dropped.", count);
+          }
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    };
+    match timeout {
+      Some(duration) => {
+        select! {
+          event = recv_fut.fuse() => event,
+          _ = Delay::new(duration).fuse() => None,
+        }
+      }
+      None => recv_fut.await,
+    }
+  }
+}
@@ -0,0 +1,275 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Pairing of client requests with server responses.
+
+use super::{
+  client_event_loop::ButtplugClientRequest, ButtplugClientError, ButtplugServerMessageResult,
+  ButtplugServerMessageResultFuture,
+};
+use crate::{
+  connector::ButtplugConnectorError,
+  core::{
+    errors::ButtplugError,
+    messages::{
+      ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage, ButtplugMessage,
+      ButtplugMessageValidator,
+    },
+  },
+};
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use tokio::sync::{broadcast, oneshot};
+
+/// Request/response multiplexer for client connections.
+///
+/// We expect that whenever a client sends the server a request message, the
+/// server will always send back a response message with the same `id`. Any
+/// message that comes from the server without an originating client message
+/// ([DeviceAdded][crate::core::messages::DeviceAdded],
+/// [Log][crate::core::messages::Log], etc...) will have an `id` of 0 and is
+/// considered an *event*, meaning something happened on the server that was
+/// not directly tied to a client request.
+///
+/// The multiplexer is shared between the [ButtplugClient][super::ButtplugClient],
+/// its devices, and the event loop. Requesters call
+/// [send](ButtplugClientRequestMultiplexer::send), which stamps the message
+/// with a fresh `id`, stores a response channel for that `id`, and hands the
+/// message to the event loop for sending through the connector. The event loop
+/// is the only thing dispatching responses, running every message it gets from
+/// the connector through [resolve](ButtplugClientRequestMultiplexer::resolve),
+/// with one of 2 outcomes:
+///
+/// - If a request with a matching `id` is pending, the response is sent to it.
+/// - Otherwise, the message is considered an *event*.
+///
+/// Dropping a request future is all that's needed to cancel it. The response
+/// is still consumed when it arrives, it just doesn't go anywhere.
+///
+/// When the connection goes away, the event loop calls
+/// [cancel_all](ButtplugClientRequestMultiplexer::cancel_all), which fails
+/// everything pending with a connector error. Requests made after that fail
+/// right away, until the next connection calls
+/// [open](ButtplugClientRequestMultiplexer::open), so no request future can be
+/// left waiting on a response that will never come.
+pub(super) struct ButtplugClientRequestMultiplexer {
+  /// Message `id` counter
+  ///
+  /// We assume that unsigned 2^32 will be enough (Buttplug isn't THAT chatty),
+  /// and use it as a monotonically increasing counter for setting `id`s, but
+  /// still skip 0 in case it wraps, since that's reserved for events.
+  current_id: AtomicU32,
+  /// Response channels for requests that are waiting on the server.
+  pending_requests: DashMap<u32, oneshot::Sender<ButtplugServerMessageResult>>,
+  /// Sends outgoing messages to the event loop.
+  event_loop_sender: broadcast::Sender<ButtplugClientRequest>,
+  /// True while there's an event loop that will send requests and resolve
+  /// their responses.
+  open: AtomicBool,
+}
+
+impl ButtplugClientRequestMultiplexer {
+  pub fn new(event_loop_sender: broadcast::Sender<ButtplugClientRequest>) -> Self {
+    Self {
+      current_id: AtomicU32::new(1),
+      pending_requests: DashMap::new(),
+      event_loop_sender,
+      open: AtomicBool::new(false),
+    }
+  }
+
+  /// Starts accepting requests. Called when a new connection's event loop is
+  /// set up.
+  pub fn open(&self) {
+    self.open.store(true, Ordering::SeqCst);
+  }
+
+  /// Subscribes to the requests going to the event loop. Only the event loop
+  /// should call this.
+  pub fn subscribe_requests(&self) -> broadcast::Receiver<ButtplugClientRequest> {
+    self.event_loop_sender.subscribe()
+  }
+
+  fn next_id(&self) -> u32 {
+    loop {
+      let id = self.current_id.fetch_add(1, Ordering::SeqCst);
+      if id != 0 {
+        return id;
+      }
+    }
+  }
+
+  /// Queues a message for the server, returning the receiver for its
+  /// response. If the message can't be sent, the error is already waiting in
+  /// the receiver.
+  fn queue_request(
+    &self,
+    mut msg: ButtplugCurrentSpecClientMessage,
+  ) -> oneshot::Receiver<ButtplugServerMessageResult> {
+    let (sender, receiver) = oneshot::channel();
+    if let Err(e) = msg.is_valid() {
+      error!("Message not valid: {:?} - Error: {}", msg, e);
+      let _ = sender.send(Err(ButtplugError::from(e).into()));
+      return receiver;
+    }
+    let id = self.next_id();
+    trace!("Setting message id to {}", id);
+    msg.set_id(id);
+    self.pending_requests.insert(id, sender);
+    // Check after inserting, so that if the connection closes at the same
+    // time, either this or cancel_all() will fail the request.
+    if !self.open.load(Ordering::SeqCst) {
+      error!("Client not connected, cannot send message.");
+      self.fail(id, ButtplugConnectorError::ConnectorNotConnected.into());
+    } else if self
+      .event_loop_sender
+      .send(ButtplugClientRequest::Message(msg))
+      .is_err()
+    {
+      error!("Client event loop not running, cannot send message.");
+      self.fail(id, ButtplugConnectorError::ConnectorChannelClosed.into());
+    }
+    receiver
+  }
+
+  /// Sends a message to the server, resolving with the server's response.
+  pub fn send(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugServerMessageResultFuture {
+    let receiver = self.queue_request(msg);
+    Box::pin(async move {
+      match receiver.await {
+        Ok(result) => result,
+        // Only happens if the multiplexer itself goes away.
+        Err(_) => Err(ButtplugConnectorError::ConnectorNotConnected.into()),
+      }
+    })
+  }
+
+  /// Sends a message to the server without waiting on a response. Since this
+  /// doesn't need to be polled, it's usable from places like Drop impls. Any
+  /// errors are logged and dropped.
+  pub fn send_nowait(&self, msg: ButtplugCurrentSpecClientMessage) {
+    // Dropping the receiver means the response is discarded when it shows up.
+    drop(self.queue_request(msg));
+  }
+
+  /// Given a message from the server, send it to the related request if we
+  /// have one.
+  ///
+  /// Returns true if the message was a response to a request, otherwise returns
+  /// false. False returns mean the message should be considered as an *event*.
+  pub fn resolve(&self, msg: &ButtplugCurrentSpecServerMessage) -> bool {
+    let id = msg.id();
+    trace!("Trying to resolve request for id {}.", id);
+    match self.pending_requests.remove(&id) {
+      Some((_, sender)) => {
+        trace!("Resolved id {} to a request.", id);
+        let result = if let Err(e) = msg.is_valid() {
+          error!("Message not valid: {:?} - Error: {}", msg, e);
+          Err(ButtplugClientError::ButtplugError(e.into()))
+        } else if let ButtplugCurrentSpecServerMessage::Error(e) = msg {
+          Err(e.original_error().into())
+        } else {
+          Ok(msg.clone())
+        };
+        // If the requester stopped waiting, there's nothing to do here.
+        let _ = sender.send(result);
+        true
+      }
+      None => {
+        trace!("Message id {} not found, considering it an event.", id);
+        false
+      }
+    }
+  }
+
+  /// Fails a single pending request, for when its message couldn't be sent.
+  pub fn fail(&self, id: u32, err: ButtplugClientError) {
+    if let Some((_, sender)) = self.pending_requests.remove(&id) {
+      let _ = sender.send(Err(err));
+    }
+  }
+
+  /// Fails all pending requests, and any new ones until
+  /// [open](Self::open) is called again. Used when the connection goes away,
+  /// since those responses are never going to show up.
+  pub fn cancel_all(&self) {
+    self.open.store(false, Ordering::SeqCst);
+    let ids: Vec<u32> = self.pending_requests.iter().map(|r| *r.key()).collect();
+    for id in ids {
+      if let Some((_, sender)) = self.pending_requests.remove(&id) {
+        let _ = sender.send(Err(ButtplugConnectorError::ConnectorNotConnected.into()));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    core::messages::{self, Ping},
+    util::async_manager,
+  };
+  use futures::FutureExt;
+
+  #[test]
+  fn test_multiplexer_resolve_and_cancel() {
+    async_manager::block_on(async {
+      let (sender, mut receiver) = broadcast::channel(256);
+      let multiplexer = ButtplugClientRequestMultiplexer::new(sender);
+      multiplexer.open();
+      let ping_fut = multiplexer.send(Ping::default().into());
+      let id = match receiver.recv().await.unwrap() {
+        ButtplugClientRequest::Message(msg) => msg.id(),
+        _ => panic!("Expected outgoing message"),
+      };
+      assert_ne!(id, 0);
+      assert!(multiplexer.resolve(&messages::Ok::new(id).into()));
+      assert!(ping_fut.await.is_ok());
+      // Already resolved, so a repeat is treated as an event.
+      assert!(!multiplexer.resolve(&messages::Ok::new(id).into()));
+
+      let ping_fut = multiplexer.send(Ping::default().into());
+      multiplexer.cancel_all();
+      assert!(matches!(
+        ping_fut.await.unwrap_err(),
+        ButtplugClientError::ButtplugConnectorError(ButtplugConnectorError::ConnectorNotConnected)
+      ));
+    });
+  }
+  #[test]
+  fn test_multiplexer_fails_requests_after_cancel() {
+    async_manager::block_on(async {
+      let (sender, _receiver) = broadcast::channel(256);
+      let multiplexer = ButtplugClientRequestMultiplexer::new(sender);
+      // Not open yet.
+      assert!(multiplexer
+        .send(Ping::default().into())
+        .now_or_never()
+        .unwrap()
+        .is_err());
+      multiplexer.open();
+      let pending: Vec<_> = (0..5)
+        .map(|_| multiplexer.send(Ping::default().into()))
+        .collect();
+      multiplexer.cancel_all();
+      // Everything resolves right away, nothing is left waiting.
+      for fut in pending {
+        assert!(matches!(
+          fut.now_or_never().unwrap().unwrap_err(),
+          ButtplugClientError::ButtplugConnectorError(ButtplugConnectorError::ConnectorNotConnected)
+        ));
+      }
+      assert!(multiplexer
+        .send(Ping::default().into())
+        .now_or_never()
+        .unwrap()
+        .is_err());
+      assert!(multiplexer.pending_requests.is_empty());
+    });
+  }
+}
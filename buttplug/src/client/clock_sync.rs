@@ -0,0 +1,129 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Estimating how far the server's clock is from the client's.
+//!
+//! [ScheduledCmd][crate::core::messages::ScheduledCmd] timestamps are by the
+//! server's clock, which for remote servers can be seconds away from ours.
+//! [ButtplugClient::sync_clock][super::ButtplugClient::sync_clock] works out
+//! the difference with a small NTP-style exchange: it sends a few
+//! RequestServerTime messages, notes when each went out and how long the
+//! answer took, and assumes the server read its clock halfway through the
+//! round trip. If that's wrong, it can't be wrong by more than half the round
+//! trip, so the sample with the shortest round trip is the one that's kept.
+//!
+//! Once there's an estimate, [DeviceCommandOptions::run_at] times are
+//! converted with it. Until then, the clocks are assumed to match.
+//!
+//! [DeviceCommandOptions::run_at]: super::DeviceCommandOptions::run_at
+
+use std::{
+  convert::TryFrom,
+  sync::RwLock,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Number of round trips [ButtplugClient::sync_clock][super::ButtplugClient::sync_clock]
+/// times before settling on an estimate.
+pub const CLOCK_SYNC_SAMPLES: usize = 8;
+
+/// Estimate of how far the server's clock is from the client's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffset {
+  /// Milliseconds to add to the client's clock to get the server's.
+  pub offset_ms: i64,
+  /// How far `offset_ms` could be off, either way.
+  pub uncertainty: Duration,
+  /// Round trip time of the exchange the estimate came from.
+  pub round_trip: Duration,
+}
+
+impl ClockOffset {
+  /// Works out the offset from a single exchange, sent at `sent` by our clock,
+  /// that took `round_trip` and got back `server_timestamp`.
+  pub(super) fn from_sample(sent: SystemTime, round_trip: Duration, server_timestamp: u64) -> Self {
+    let sent_us = sent
+      .duration_since(UNIX_EPOCH)
+      .map(|since_epoch| since_epoch.as_micros() as i128)
+      .unwrap_or(0);
+    let midpoint_us = sent_us + round_trip.as_micros() as i128 / 2;
+    let offset_us = i128::from(server_timestamp) * 1000 - midpoint_us;
+    Self {
+      offset_ms: i64::try_from(offset_us / 1000).unwrap_or(0),
+      // The server only reports whole milliseconds, so add one for rounding.
+      uncertainty: round_trip / 2 + Duration::from_millis(1),
+      round_trip,
+    }
+  }
+
+  /// Converts a time by the client's clock to a server timestamp, in
+  /// milliseconds since the Unix epoch.
+  pub fn to_server_timestamp(&self, time: SystemTime) -> u64 {
+    let client_ms = time
+      .duration_since(UNIX_EPOCH)
+      .map(|since_epoch| since_epoch.as_millis() as i128)
+      .unwrap_or(0);
+    u64::try_from(client_ms + i128::from(self.offset_ms)).unwrap_or(0)
+  }
+}
+
+/// The current clock estimate, shared between the client and its devices.
+#[derive(Default)]
+pub(super) struct ClientClock {
+  offset: RwLock<Option<ClockOffset>>,
+}
+
+impl ClientClock {
+  pub fn offset(&self) -> Option<ClockOffset> {
+    *self.offset.read().unwrap()
+  }
+
+  pub fn set_offset(&self, offset: Option<ClockOffset>) {
+    *self.offset.write().unwrap() = offset;
+  }
+
+  /// Converts a time by the client's clock to a server timestamp, using the
+  /// current estimate if there is one.
+  pub fn to_server_timestamp(&self, time: SystemTime) -> u64 {
+    self
+      .offset()
+      .unwrap_or(ClockOffset {
+        offset_ms: 0,
+        uncertainty: Duration::from_secs(0),
+        round_trip: Duration::from_secs(0),
+      })
+      .to_server_timestamp(time)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_clock_offset_from_sample() {
+    let sent = UNIX_EPOCH + Duration::from_millis(10_000);
+    // Server is 5 seconds ahead, and read its clock 20ms into a 40ms trip.
+    let offset = ClockOffset::from_sample(sent, Duration::from_millis(40), 15_020);
+    assert_eq!(offset.offset_ms, 5000);
+    assert_eq!(offset.uncertainty, Duration::from_millis(21));
+    assert_eq!(offset.to_server_timestamp(sent), 15_000);
+    // Server behind us.
+    let offset = ClockOffset::from_sample(sent, Duration::from_millis(10), 7_005);
+    assert_eq!(offset.offset_ms, -3000);
+    assert_eq!(offset.to_server_timestamp(sent), 7_000);
+  }
+
+  #[test]
+  fn test_client_clock_defaults_to_no_offset() {
+    let clock = ClientClock::default();
+    let time = UNIX_EPOCH + Duration::from_millis(1234);
+    assert_eq!(clock.to_server_timestamp(time), 1234);
+    clock.set_offset(Some(ClockOffset::from_sample(time, Duration::from_secs(0), 2234)));
+    assert_eq!(clock.to_server_timestamp(time), 2234);
+  }
+}
@@ -7,32 +7,43 @@
 
 //! Representation and management of devices connected to the server.
 
-use super::{ButtplugClientError, ButtplugClientRequest, ButtplugClientResultFuture};
+use super::{
+  client_request_multiplexer::ButtplugClientRequestMultiplexer, clock_sync::ClientClock,
+  ButtplugClientError, ButtplugClientResultFuture,
+};
 use crate::{
-  client::{ButtplugClientMessageFuturePair, ButtplugServerMessageFuture},
   connector::ButtplugConnectorError,
   core::{
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
     messages::{
-      BatteryLevelCmd, ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecDeviceMessageType,
-      ButtplugCurrentSpecServerMessage, ButtplugMessage, DeviceMessageAttributes,
-      DeviceMessageAttributesMap, DeviceMessageInfo, LinearCmd, RSSILevelCmd, RawReadCmd,
-      RawSubscribeCmd, RawUnsubscribeCmd, RawWriteCmd, RotateCmd, RotationSubcommand,
-      StopDeviceCmd, VectorSubcommand, VibrateCmd, VibrateSubcommand,
+      AngleSubcommand, BatteryLevelCmd, ButtplugClientMessage, ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecDeviceMessageType, ButtplugCurrentSpecServerMessage,
+      ButtplugDeviceCommandMessageUnion, ButtplugMessage,
+      DeviceMessageAttributes, DeviceMessageAttributesMap, DeviceMessageInfo, DeviceMetadata,
+      DeviceSessionLimitCmd, DeviceStatistics, DeviceStatisticsCmd, DeviceStatus,
+      DeviceStatusCmd, KeyedDeviceCmd, LinearAttributes, LinearCmd, RSSILevelCmd, RawReadCmd,
+      RawSubscribeCmd, RawUnsubscribeCmd, RawWriteCmd, RotateAttributes, RotateCmd,
+      RotateToAngleCmd, RotationSubcommand, ScheduledCmd, StopDeviceCmd, SuctionCmd,
+      SuctionSubcommand,
+      TemperatureCmd, TemperatureSubcommand, VectorSubcommand, VibrateAttributes, VibrateCmd,
+      VibrateSubcommand,
     },
   },
   device::Endpoint,
   util::stream::convert_broadcast_receiver_to_stream,
 };
-use futures::{future, Stream};
+use futures::{future, FutureExt, Stream};
+use futures_timer::Delay;
 use std::{
   collections::HashMap,
   convert::TryFrom,
   fmt,
+  ops::Deref,
   sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, RwLock,
   },
+  time::{Duration, SystemTime},
 };
 use tokio::sync::broadcast;
 use tracing_futures::Instrument;
@@ -42,16 +53,80 @@ use tracing_futures::Instrument;
 pub enum ButtplugClientDeviceEvent {
   /// Device has disconnected from server.
   DeviceRemoved,
+  /// Server sent a new name or message attributes for the device, which
+  /// every clone of the device now uses.
+  DeviceUpdated,
   /// Client has disconnected from server.
   ClientDisconnect,
   /// Message was received from server for that specific device.
   Message(ButtplugCurrentSpecServerMessage),
 }
 
+/// Per-call options for device commands, for the `*_with_options` versions of
+/// [ButtplugClientDevice] command methods.
+///
+/// Commands sent many times a second (i.e. speeds from a game loop) usually
+/// want a short timeout, or no waiting at all, so a slow device doesn't pile
+/// up futures. Commands that matter want to wait as long as it takes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeviceCommandOptions {
+  /// Fail with [ButtplugClientError::Timeout] if the server hasn't replied
+  /// within this long. None waits for the reply however long it takes.
+  pub timeout: Option<Duration>,
+  /// Resolve as soon as the command is queued for sending, without waiting
+  /// for the reply. Errors from the server are logged and dropped. Commands
+  /// are still sent in the order they're made. Overrides `timeout`.
+  pub fire_and_forget: bool,
+  /// Have the server run the command at this time instead of as soon as it
+  /// arrives. Sending commands a little ahead this way smooths out network
+  /// jitter on remote connections. The time is converted to the server's
+  /// clock with the estimate from
+  /// [ButtplugClient::sync_clock][super::ButtplugClient::sync_clock], if
+  /// there is one. The reply comes back as soon as the server has accepted
+  /// the command. Sent as a [ScheduledCmd].
+  pub run_at: Option<SystemTime>,
+}
+
+impl DeviceCommandOptions {
+  pub fn timeout(timeout: Duration) -> Self {
+    Self {
+      timeout: Some(timeout),
+      ..Default::default()
+    }
+  }
+
+  pub fn fire_and_forget() -> Self {
+    Self {
+      fire_and_forget: true,
+      ..Default::default()
+    }
+  }
+
+  pub fn run_at(run_at: SystemTime) -> Self {
+    Self {
+      run_at: Some(run_at),
+      ..Default::default()
+    }
+  }
+}
+
+/// Wraps a device command in a [ScheduledCmd] for `run_at`, converted to the
+/// server's clock with whatever estimate `clock` has.
+fn schedule_message(
+  msg: ButtplugCurrentSpecClientMessage,
+  run_at: SystemTime,
+  clock: &ClientClock,
+) -> Result<ButtplugCurrentSpecClientMessage, ButtplugError> {
+  let command = ButtplugDeviceCommandMessageUnion::try_from(ButtplugClientMessage::from(msg))
+    .map_err(|err| ButtplugMessageError::MessageConversionError(err.to_owned()))?;
+  Ok(ScheduledCmd::new(clock.to_server_timestamp(run_at), command).into())
+}
+
 /// Convenience enum for forming [VibrateCmd] commands.
 ///
 /// Allows users to easily specify speeds across different vibration features in
 /// a device. Units are in absolute speed values (0.0-1.0).
+#[derive(Debug, Clone, PartialEq)]
 pub enum VibrateCommand {
   /// Sets all vibration features of a device to the same speed.
   Speed(f64),
@@ -65,6 +140,30 @@ pub enum VibrateCommand {
   SpeedMap(HashMap<u32, f64>),
 }
 
+impl From<f64> for VibrateCommand {
+  fn from(speed: f64) -> Self {
+    VibrateCommand::Speed(speed)
+  }
+}
+
+impl From<Vec<f64>> for VibrateCommand {
+  fn from(speeds: Vec<f64>) -> Self {
+    VibrateCommand::SpeedVec(speeds)
+  }
+}
+
+impl From<&[f64]> for VibrateCommand {
+  fn from(speeds: &[f64]) -> Self {
+    VibrateCommand::SpeedVec(speeds.to_vec())
+  }
+}
+
+impl From<HashMap<u32, f64>> for VibrateCommand {
+  fn from(speeds: HashMap<u32, f64>) -> Self {
+    VibrateCommand::SpeedMap(speeds)
+  }
+}
+
 /// Convenience enum for forming [RotateCmd] commands.
 ///
 /// Allows users to easily specify speeds/directions across different rotation
@@ -101,11 +200,64 @@ pub enum LinearCommand {
   LinearMap(HashMap<u32, (u32, f64)>),
 }
 
+/// Convenience enum for forming [RotateToAngleCmd] commands.
+///
+/// Same layout as [LinearCommand], with angles (0.0-1.0 across the rotator's
+/// range) in place of positions, and durations in milliseconds.
+pub enum RotateToAngleCommand {
+  /// Turns all angle capable rotators of a device to the same angle/duration.
+  Angle(u32, f64),
+  /// Turns rotators based on the index of the duration/angle pair in the vec.
+  AngleVec(Vec<(u32, f64)>),
+  /// Turns rotators indicated by index to the requested duration/angle.
+  AngleMap(HashMap<u32, (u32, f64)>),
+}
+
+/// Picks out a device for [ButtplugClient::await_device][super::ButtplugClient::await_device].
+///
+/// Clients only know device names and indexes. Addresses stay on the server,
+/// so a device can't be picked out by address. Can be created from a `&str` or
+/// `String` (name) or a `u32` (index).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ButtplugClientDeviceSelector {
+  /// Device with this name, ignoring case.
+  Name(String),
+  /// Device at this index.
+  Index(u32),
+}
+
+impl ButtplugClientDeviceSelector {
+  pub fn matches(&self, device: &ButtplugClientDevice) -> bool {
+    match self {
+      ButtplugClientDeviceSelector::Name(name) => device.name().eq_ignore_ascii_case(name),
+      ButtplugClientDeviceSelector::Index(index) => device.index() == *index,
+    }
+  }
+}
+
+impl From<&str> for ButtplugClientDeviceSelector {
+  fn from(name: &str) -> Self {
+    ButtplugClientDeviceSelector::Name(name.to_owned())
+  }
+}
+
+impl From<String> for ButtplugClientDeviceSelector {
+  fn from(name: String) -> Self {
+    ButtplugClientDeviceSelector::Name(name)
+  }
+}
+
+impl From<u32> for ButtplugClientDeviceSelector {
+  fn from(index: u32) -> Self {
+    ButtplugClientDeviceSelector::Index(index)
+  }
+}
+
 // Using a macro here so we can encabe the return statement. Otherwise we'd have
 // to do validity checks on every call since we return futures, not results.
 macro_rules! check_message_support {
   ($self:ident, $msg:expr) => {
-    if !$self.allowed_messages.contains_key(&$msg) {
+    if !$self.supports_message($msg) {
       return $self.create_boxed_future_client_error(
         ButtplugDeviceError::MessageNotSupported($msg.into()).into(),
       );
@@ -129,35 +281,115 @@ fn convert_to_client_device_map(
   current_map
 }
 
+/// Typed attributes for `message_type`, or the reason the device can't take
+/// it.
+fn typed_attributes<T>(
+  allowed_messages: &ClientDeviceMessageAttributesMap,
+  message_type: ButtplugClientDeviceMessageType,
+) -> Result<T, ButtplugDeviceError>
+where
+  T: for<'a> TryFrom<&'a DeviceMessageAttributes, Error = ButtplugDeviceError>,
+{
+  let attributes = allowed_messages
+    .get(&message_type)
+    .ok_or_else(|| ButtplugDeviceError::MessageNotSupported(message_type.into()))?;
+  T::try_from(attributes).map_err(|err| {
+    warn!("Server sent bad attributes, device will refuse {:?}: {}", message_type, err);
+    err
+  })
+}
+
+/// What the server has said about the device. Replaced as a whole when the
+/// server sends DeviceUpdated, so a command never sees half of an update.
+struct ButtplugClientDeviceCapabilities {
+  name: String,
+  allowed_messages: ClientDeviceMessageAttributesMap,
+  vibrate_attributes: Result<VibrateAttributes, ButtplugDeviceError>,
+  rotate_attributes: Result<RotateAttributes, ButtplugDeviceError>,
+  linear_attributes: Result<LinearAttributes, ButtplugDeviceError>,
+}
+
+impl ButtplugClientDeviceCapabilities {
+  fn new(name: &str, allowed_messages: ClientDeviceMessageAttributesMap) -> Self {
+    Self {
+      name: name.to_owned(),
+      vibrate_attributes: typed_attributes(
+        &allowed_messages,
+        ButtplugClientDeviceMessageType::VibrateCmd,
+      ),
+      rotate_attributes: typed_attributes(
+        &allowed_messages,
+        ButtplugClientDeviceMessageType::RotateCmd,
+      ),
+      linear_attributes: typed_attributes(
+        &allowed_messages,
+        ButtplugClientDeviceMessageType::LinearCmd,
+      ),
+      allowed_messages,
+    }
+  }
+}
+
+/// State shared by every clone of a [ButtplugClientDevice].
+struct ButtplugClientDeviceState {
+  capabilities: RwLock<Arc<ButtplugClientDeviceCapabilities>>,
+  event_sender: broadcast::Sender<ButtplugClientDeviceEvent>,
+  /// True if the device is currently connected to the
+  /// [ButtplugServer][crate::server::ButtplugServer].
+  device_connected: AtomicBool,
+  /// True if the [ButtplugClient][super::ButtplugClient] that generated the
+  /// device is still connected to the
+  /// [ButtplugServer][crate::server::ButtplugServer].
+  client_connected: AtomicBool,
+}
+
+impl ButtplugClientDeviceState {
+  /// Returns the error for a device command if either the client or the
+  /// device is disconnected.
+  fn connection_error(&self, device_name: &str) -> Option<ButtplugClientError> {
+    if !self.client_connected.load(Ordering::SeqCst) {
+      error!("Client not connected, cannot run device command");
+      Some(ButtplugConnectorError::ConnectorNotConnected.into())
+    } else if !self.device_connected.load(Ordering::SeqCst) {
+      error!("Device not connected, cannot run device command");
+      let err = ButtplugDeviceError::DeviceNotConnected(device_name.to_owned());
+      Some(ButtplugError::from(err).into())
+    } else {
+      None
+    }
+  }
+}
+
 /// Client-usable representation of device connected to the corresponding
 /// [ButtplugServer][crate::server::ButtplugServer]
 ///
 /// [ButtplugClientDevice] instances are obtained from the
 /// [ButtplugClient][super::ButtplugClient], and allow the user to send commands
 /// to a device connected to the server.
+///
+/// Cloning a device is cheap, and clones all refer to the same device:
+/// connection state and events are shared, so a clone sees the device get
+/// removed (and gets the DeviceRemoved event) just like the original does.
+/// The same goes for the name and allowed messages, when the server updates
+/// them.
+#[derive(Clone)]
 pub struct ButtplugClientDevice {
-  /// Name of the device
-  pub name: String,
   /// Index of the device, matching the index in the
   /// [ButtplugServer][crate::server::ButtplugServer]'s
   /// [DeviceManager][crate::server::device_manager::DeviceManager].
   index: u32,
-  /// Map of messages the device can take, along with the attributes of those
-  /// messages.
-  pub allowed_messages: ClientDeviceMessageAttributesMap,
+  /// What the server knew about the device when it was found. Only sent with
+  /// DeviceAdded, so devices that came from a device list won't have it.
+  metadata: Option<DeviceMetadata>,
   /// Sends commands from the [ButtplugClientDevice] instance to the
   /// [ButtplugClient][super::ButtplugClient]'s event loop, which will then send
   /// the message on to the [ButtplugServer][crate::server::ButtplugServer]
   /// through the connector.
-  event_loop_sender: broadcast::Sender<ButtplugClientRequest>,
-  internal_event_sender: broadcast::Sender<ButtplugClientDeviceEvent>,
-  /// True if this [ButtplugClientDevice] is currently connected to the
-  /// [ButtplugServer][crate::server::ButtplugServer].
-  device_connected: Arc<AtomicBool>,
-  /// True if the [ButtplugClient][super::ButtplugClient] that generated this
-  /// [ButtplugClientDevice] instance is still connected to the
-  /// [ButtplugServer][crate::server::ButtplugServer].
-  client_connected: Arc<AtomicBool>,
+  multiplexer: Arc<ButtplugClientRequestMultiplexer>,
+  /// Server clock estimate from the client, for converting
+  /// [DeviceCommandOptions::run_at] times.
+  clock: Arc<ClientClock>,
+  state: Arc<ButtplugClientDeviceState>,
 }
 
 unsafe impl Send for ButtplugClientDevice {}
@@ -166,9 +398,9 @@ unsafe impl Sync for ButtplugClientDevice {}
 impl ButtplugClientDevice {
   /// Creates a new [ButtplugClientDevice] instance
   ///
-  /// Fills out the struct members for [ButtplugClientDevice].
-  /// `device_connected` and `client_connected` are automatically set to true
-  /// because we assume we're only created connected devices.
+  /// Fills out the struct members for [ButtplugClientDevice]. The device and
+  /// client are both marked as connected, because we assume we're only
+  /// created for connected devices.
   ///
   /// # Why is this pub(super)?
   ///
@@ -181,41 +413,181 @@ impl ButtplugClientDevice {
     name: &str,
     index: u32,
     allowed_messages: ClientDeviceMessageAttributesMap,
-    message_sender: broadcast::Sender<ButtplugClientRequest>,
+    metadata: Option<DeviceMetadata>,
+    multiplexer: Arc<ButtplugClientRequestMultiplexer>,
+    clock: Arc<ClientClock>,
   ) -> Self {
     info!(
       "Creating client device {} with index {} and messages {:?}.",
       name, index, allowed_messages
     );
     let (event_sender, _) = broadcast::channel(256);
-    let device_connected = Arc::new(AtomicBool::new(true));
-    let client_connected = Arc::new(AtomicBool::new(true));
 
     Self {
-      name: name.to_owned(),
       index,
-      allowed_messages,
-      event_loop_sender: message_sender,
-      internal_event_sender: event_sender,
-      device_connected,
-      client_connected,
+      metadata,
+      multiplexer,
+      clock,
+      state: Arc::new(ButtplugClientDeviceState {
+        capabilities: RwLock::new(Arc::new(ButtplugClientDeviceCapabilities::new(
+          name,
+          allowed_messages,
+        ))),
+        event_sender,
+        device_connected: AtomicBool::new(true),
+        client_connected: AtomicBool::new(true),
+      }),
     }
   }
 
   pub(super) fn new_from_device_info(
     info: &DeviceMessageInfo,
-    sender: broadcast::Sender<ButtplugClientRequest>,
+    metadata: Option<DeviceMetadata>,
+    multiplexer: Arc<ButtplugClientRequestMultiplexer>,
+    clock: Arc<ClientClock>,
   ) -> Self {
     ButtplugClientDevice::new(
       &*info.device_name,
       info.device_index,
       convert_to_client_device_map(&info.device_messages),
-      sender,
+      metadata,
+      multiplexer,
+      clock,
     )
   }
 
+  fn capabilities(&self) -> Arc<ButtplugClientDeviceCapabilities> {
+    self.state.capabilities.read().unwrap().clone()
+  }
+
+  /// Replaces the device's name and messages with what the server sent in
+  /// DeviceUpdated. Every clone of the device sees the change.
+  pub(super) fn update(&self, info: &DeviceMessageInfo) {
+    info!(
+      "Updating client device {} with name {} and messages {:?}.",
+      self.index, info.device_name, info.device_messages
+    );
+    let capabilities = ButtplugClientDeviceCapabilities::new(
+      &info.device_name,
+      convert_to_client_device_map(&info.device_messages),
+    );
+    *self.state.capabilities.write().unwrap() = Arc::new(capabilities);
+  }
+
+  /// Name of the device. Can change while connected, if the server sends
+  /// DeviceUpdated.
+  pub fn name(&self) -> String {
+    self.capabilities().name.clone()
+  }
+
+  /// Map of messages the device can take, along with the attributes of those
+  /// messages. Can change while connected, if the server sends DeviceUpdated.
+  pub fn allowed_messages(&self) -> ClientDeviceMessageAttributesMap {
+    self.capabilities().allowed_messages.clone()
+  }
+
+  /// Transport, signal strength, USB IDs, etc. the server reported when the
+  /// device was added, if any.
+  pub fn metadata(&self) -> Option<&DeviceMetadata> {
+    self.metadata.as_ref()
+  }
+
   pub fn connected(&self) -> bool {
-    self.device_connected.load(Ordering::SeqCst)
+    self.state.device_connected.load(Ordering::SeqCst)
+  }
+
+  /// True if the device accepts messages of the given type.
+  pub fn supports_message(&self, message_type: ButtplugClientDeviceMessageType) -> bool {
+    self
+      .capabilities()
+      .allowed_messages
+      .contains_key(&message_type)
+  }
+
+  /// Number of features the device has for a message type, or None if the
+  /// device doesn't support the message or didn't report a count.
+  pub fn feature_count(&self, message_type: ButtplugClientDeviceMessageType) -> Option<u32> {
+    self
+      .capabilities()
+      .allowed_messages
+      .get(&message_type)
+      .and_then(|attributes| attributes.feature_count)
+  }
+
+  pub fn supports_vibrate(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::VibrateCmd)
+  }
+
+  /// Vibrator and step counts, or None if the device can't vibrate (or the
+  /// server sent attributes that don't make sense).
+  pub fn vibrate_attributes(&self) -> Option<VibrateAttributes> {
+    self.capabilities().vibrate_attributes.clone().ok()
+  }
+
+  /// Number of vibrators, or None if the device can't vibrate.
+  pub fn vibrate_feature_count(&self) -> Option<u32> {
+    self.vibrate_attributes().map(|attributes| attributes.feature_count())
+  }
+
+  pub fn supports_linear(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::LinearCmd)
+  }
+
+  /// Linear axis and step counts, or None if the device doesn't support
+  /// linear movement.
+  pub fn linear_attributes(&self) -> Option<LinearAttributes> {
+    self.capabilities().linear_attributes.clone().ok()
+  }
+
+  /// Number of linear axes, or None if the device doesn't support linear
+  /// movement.
+  pub fn linear_feature_count(&self) -> Option<u32> {
+    self.linear_attributes().map(|attributes| attributes.feature_count())
+  }
+
+  pub fn supports_rotate(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::RotateCmd)
+  }
+
+  /// Rotator and step counts, or None if the device can't rotate.
+  pub fn rotate_attributes(&self) -> Option<RotateAttributes> {
+    self.capabilities().rotate_attributes.clone().ok()
+  }
+
+  /// Number of rotators, or None if the device can't rotate.
+  pub fn rotate_feature_count(&self) -> Option<u32> {
+    self.rotate_attributes().map(|attributes| attributes.feature_count())
+  }
+
+  pub fn supports_rotate_to_angle(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::RotateToAngleCmd)
+  }
+
+  pub fn supports_temperature(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::TemperatureCmd)
+  }
+
+  pub fn supports_suction(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::SuctionCmd)
+  }
+
+  pub fn supports_battery_level(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::BatteryLevelCmd)
+  }
+
+  pub fn supports_rssi_level(&self) -> bool {
+    self.supports_message(ButtplugCurrentSpecDeviceMessageType::RSSILevelCmd)
+  }
+
+  /// Keys the device accepts in [keyed_command][Self::keyed_command]. Empty if
+  /// the device has no device specific commands.
+  pub fn keyed_command_keys(&self) -> Vec<String> {
+    self
+      .capabilities()
+      .allowed_messages
+      .get(&ButtplugCurrentSpecDeviceMessageType::KeyedDeviceCmd)
+      .and_then(|attrs| attrs.keys.clone())
+      .unwrap_or_default()
   }
 
   /// Sends a message through the owning
@@ -227,38 +599,16 @@ impl ButtplugClientDevice {
     &self,
     msg: ButtplugCurrentSpecClientMessage,
   ) -> ButtplugClientResultFuture<ButtplugCurrentSpecServerMessage> {
-    let message_sender = self.event_loop_sender.clone();
-    let client_connected = self.client_connected.clone();
-    let device_connected = self.device_connected.clone();
+    let state = self.state.clone();
     let id = msg.id();
-    let device_name = self.name.clone();
+    let device_name = self.name();
+    let multiplexer = self.multiplexer.clone();
     Box::pin(
       async move {
-        if !client_connected.load(Ordering::SeqCst) {
-          error!("Client not connected, cannot run device command");
-          return Err(ButtplugConnectorError::ConnectorNotConnected.into());
-        } else if !device_connected.load(Ordering::SeqCst) {
-          error!("Device not connected, cannot run device command");
-          return Err(
-            ButtplugError::from(ButtplugDeviceError::DeviceNotConnected(device_name)).into(),
-          );
-        }
-        let fut = ButtplugServerMessageFuture::default();
-        message_sender
-          .send(ButtplugClientRequest::Message(
-            ButtplugClientMessageFuturePair::new(msg.clone(), fut.get_state_clone()),
-          ))
-          .map_err(|_| {
-            ButtplugClientError::ButtplugConnectorError(
-              ButtplugConnectorError::ConnectorChannelClosed,
-            )
-          })?;
-        let msg = fut.await?;
-        if let ButtplugCurrentSpecServerMessage::Error(_err) = msg {
-          Err(ButtplugError::from(_err).into())
-        } else {
-          Ok(msg)
+        if let Some(err) = state.connection_error(&device_name) {
+          return Err(err);
         }
+        multiplexer.send(msg).await
       }
       .instrument(tracing::trace_span!("ClientDeviceSendFuture for {}", id)),
     )
@@ -266,7 +616,7 @@ impl ButtplugClientDevice {
 
   pub fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin> {
     Box::new(Box::pin(convert_broadcast_receiver_to_stream(
-      self.internal_event_sender.subscribe(),
+      self.state.event_sender.subscribe(),
     )))
   }
 
@@ -283,9 +633,45 @@ impl ButtplugClientDevice {
     &self,
     msg: ButtplugCurrentSpecClientMessage,
   ) -> ButtplugClientResultFuture {
+    self.send_message_expect_ok_with_options(msg, DeviceCommandOptions::default())
+  }
+
+  fn send_message_expect_ok_with_options(
+    &self,
+    msg: ButtplugCurrentSpecClientMessage,
+    options: DeviceCommandOptions,
+  ) -> ButtplugClientResultFuture {
+    let msg = match options.run_at {
+      Some(run_at) => match schedule_message(msg, run_at, &self.clock) {
+        Ok(msg) => msg,
+        Err(err) => return self.create_boxed_future_client_error(err),
+      },
+      None => msg,
+    };
+    if options.fire_and_forget {
+      // Queue now instead of when the future is polled, so commands keep
+      // their order even if nobody awaits them.
+      let result = match self.state.connection_error(&self.name()) {
+        Some(err) => Err(err),
+        None => {
+          self.multiplexer.send_nowait(msg);
+          Ok(())
+        }
+      };
+      return Box::pin(future::ready(result));
+    }
     let send_fut = self.send_message(msg);
     Box::pin(async move {
-      match send_fut.await? {
+      let reply = match options.timeout {
+        Some(timeout) => {
+          select! {
+            reply = send_fut.fuse() => reply?,
+            _ = Delay::new(timeout).fuse() => return Err(ButtplugClientError::Timeout(timeout)),
+          }
+        }
+        None => send_fut.await?,
+      };
+      match reply {
         ButtplugCurrentSpecServerMessage::Ok(_) => Ok(()),
         ButtplugCurrentSpecServerMessage::Error(_err) => Err(ButtplugError::from(_err).into()),
         msg => Err(
@@ -300,19 +686,27 @@ impl ButtplugClientDevice {
   }
 
   /// Commands device to vibrate, assuming it has the features to do so.
-  pub fn vibrate(&self, speed_cmd: VibrateCommand) -> ButtplugClientResultFuture {
-    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::VibrateCmd);
-    let mut vibrator_count: u32 = 0;
-    if let Some(features) = self
-      .allowed_messages
-      .get(&ButtplugCurrentSpecDeviceMessageType::VibrateCmd)
-    {
-      if let Some(v) = features.feature_count {
-        vibrator_count = v;
-      }
-    }
+  ///
+  /// Takes anything that converts to a [VibrateCommand], so along with the
+  /// enum itself, a single `f64` (all motors), a `Vec<f64>` or `&[f64]` (per
+  /// motor by position), or a `HashMap<u32, f64>` (per motor by index) can be
+  /// passed directly, i.e. `device.vibrate(0.5)`.
+  pub fn vibrate(&self, speed_cmd: impl Into<VibrateCommand>) -> ButtplugClientResultFuture {
+    self.vibrate_with_options(speed_cmd, DeviceCommandOptions::default())
+  }
+
+  /// [vibrate](Self::vibrate), with a timeout or fire-and-forget sending.
+  pub fn vibrate_with_options(
+    &self,
+    speed_cmd: impl Into<VibrateCommand>,
+    options: DeviceCommandOptions,
+  ) -> ButtplugClientResultFuture {
+    let vibrator_count = match &self.capabilities().vibrate_attributes {
+      Ok(attributes) => attributes.feature_count(),
+      Err(err) => return self.create_boxed_future_client_error(err.clone().into()),
+    };
     let mut speed_vec: Vec<VibrateSubcommand>;
-    match speed_cmd {
+    match speed_cmd.into() {
       VibrateCommand::Speed(speed) => {
         speed_vec = Vec::with_capacity(vibrator_count as usize);
         for i in 0..vibrator_count {
@@ -328,7 +722,7 @@ impl ButtplugClientDevice {
         }
         speed_vec = Vec::with_capacity(map.len() as usize);
         for (idx, speed) in map {
-          if idx > vibrator_count - 1 {
+          if idx >= vibrator_count {
             return self.create_boxed_future_client_error(
               ButtplugDeviceError::DeviceFeatureIndexError(vibrator_count, idx).into(),
             );
@@ -350,21 +744,24 @@ impl ButtplugClientDevice {
       }
     }
     let msg = VibrateCmd::new(self.index, speed_vec).into();
-    self.send_message_expect_ok(msg)
+    self.send_message_expect_ok_with_options(msg, options)
   }
 
   /// Commands device to move linearly, assuming it has the features to do so.
   pub fn linear(&self, linear_cmd: LinearCommand) -> ButtplugClientResultFuture {
-    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::LinearCmd);
-    let mut linear_count: u32 = 0;
-    if let Some(features) = self
-      .allowed_messages
-      .get(&ButtplugCurrentSpecDeviceMessageType::LinearCmd)
-    {
-      if let Some(v) = features.feature_count {
-        linear_count = v;
-      }
-    }
+    self.linear_with_options(linear_cmd, DeviceCommandOptions::default())
+  }
+
+  /// [linear](Self::linear), with a timeout or fire-and-forget sending.
+  pub fn linear_with_options(
+    &self,
+    linear_cmd: LinearCommand,
+    options: DeviceCommandOptions,
+  ) -> ButtplugClientResultFuture {
+    let linear_count = match &self.capabilities().linear_attributes {
+      Ok(attributes) => attributes.feature_count(),
+      Err(err) => return self.create_boxed_future_client_error(err.clone().into()),
+    };
     let mut linear_vec: Vec<VectorSubcommand>;
     match linear_cmd {
       LinearCommand::Linear(dur, pos) => {
@@ -381,7 +778,7 @@ impl ButtplugClientDevice {
         }
         linear_vec = Vec::with_capacity(map.len() as usize);
         for (idx, (dur, pos)) in map {
-          if idx > linear_count - 1 {
+          if idx >= linear_count {
             return self.create_boxed_future_client_error(
               ButtplugDeviceError::DeviceFeatureIndexError(linear_count, idx).into(),
             );
@@ -402,21 +799,24 @@ impl ButtplugClientDevice {
       }
     }
     let msg = LinearCmd::new(self.index, linear_vec).into();
-    self.send_message_expect_ok(msg)
+    self.send_message_expect_ok_with_options(msg, options)
   }
 
   /// Commands device to rotate, assuming it has the features to do so.
   pub fn rotate(&self, rotate_cmd: RotateCommand) -> ButtplugClientResultFuture {
-    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::RotateCmd);
-    let mut rotate_count: u32 = 0;
-    if let Some(features) = self
-      .allowed_messages
-      .get(&ButtplugCurrentSpecDeviceMessageType::RotateCmd)
-    {
-      if let Some(v) = features.feature_count {
-        rotate_count = v;
-      }
-    }
+    self.rotate_with_options(rotate_cmd, DeviceCommandOptions::default())
+  }
+
+  /// [rotate](Self::rotate), with a timeout or fire-and-forget sending.
+  pub fn rotate_with_options(
+    &self,
+    rotate_cmd: RotateCommand,
+    options: DeviceCommandOptions,
+  ) -> ButtplugClientResultFuture {
+    let rotate_count = match &self.capabilities().rotate_attributes {
+      Ok(attributes) => attributes.feature_count(),
+      Err(err) => return self.create_boxed_future_client_error(err.clone().into()),
+    };
     let mut rotate_vec: Vec<RotationSubcommand>;
     match rotate_cmd {
       RotateCommand::Rotate(speed, clockwise) => {
@@ -433,7 +833,7 @@ impl ButtplugClientDevice {
         }
         rotate_vec = Vec::with_capacity(map.len() as usize);
         for (idx, (speed, clockwise)) in map {
-          if idx > rotate_count - 1 {
+          if idx >= rotate_count {
             return self.create_boxed_future_client_error(
               ButtplugDeviceError::DeviceFeatureIndexError(rotate_count, idx).into(),
             );
@@ -454,7 +854,94 @@ impl ButtplugClientDevice {
       }
     }
     let msg = RotateCmd::new(self.index, rotate_vec).into();
-    self.send_message_expect_ok(msg)
+    self.send_message_expect_ok_with_options(msg, options)
+  }
+
+  /// Commands device to turn its rotators to absolute angles, assuming it
+  /// has the features to do so.
+  pub fn rotate_to_angle(&self, angle_cmd: RotateToAngleCommand) -> ButtplugClientResultFuture {
+    self.rotate_to_angle_with_options(angle_cmd, DeviceCommandOptions::default())
+  }
+
+  /// [rotate_to_angle](Self::rotate_to_angle), with a timeout or
+  /// fire-and-forget sending.
+  pub fn rotate_to_angle_with_options(
+    &self,
+    angle_cmd: RotateToAngleCommand,
+    options: DeviceCommandOptions,
+  ) -> ButtplugClientResultFuture {
+    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::RotateToAngleCmd);
+    let angle_count = self
+      .feature_count(ButtplugCurrentSpecDeviceMessageType::RotateToAngleCmd)
+      .unwrap_or(0);
+    let mut angle_vec: Vec<AngleSubcommand>;
+    match angle_cmd {
+      RotateToAngleCommand::Angle(dur, angle) => {
+        angle_vec = Vec::with_capacity(angle_count as usize);
+        for i in 0..angle_count {
+          angle_vec.push(AngleSubcommand::new(i, dur, angle));
+        }
+      }
+      RotateToAngleCommand::AngleMap(map) => {
+        if map.len() as u32 > angle_count {
+          return self.create_boxed_future_client_error(
+            ButtplugDeviceError::DeviceFeatureCountMismatch(angle_count, map.len() as u32).into(),
+          );
+        }
+        angle_vec = Vec::with_capacity(map.len());
+        for (idx, (dur, angle)) in map {
+          if idx >= angle_count {
+            return self.create_boxed_future_client_error(
+              ButtplugDeviceError::DeviceFeatureIndexError(angle_count, idx).into(),
+            );
+          }
+          angle_vec.push(AngleSubcommand::new(idx, dur, angle));
+        }
+      }
+      RotateToAngleCommand::AngleVec(vec) => {
+        if vec.len() as u32 > angle_count {
+          return self.create_boxed_future_client_error(
+            ButtplugDeviceError::DeviceFeatureCountMismatch(angle_count, vec.len() as u32).into(),
+          );
+        }
+        angle_vec = Vec::with_capacity(vec.len());
+        for (i, v) in vec.iter().enumerate() {
+          angle_vec.push(AngleSubcommand::new(i as u32, v.0, v.1));
+        }
+      }
+    }
+    let msg = RotateToAngleCmd::new(self.index, angle_vec).into();
+    self.send_message_expect_ok_with_options(msg, options)
+  }
+
+  /// Sets every heater on the device to `level` (0.0 is off, 1.0 is the
+  /// hottest the device config allows). The server may lower this further,
+  /// via its `max_temperature_level` option.
+  pub fn set_temperature(&self, level: f64) -> ButtplugClientResultFuture {
+    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::TemperatureCmd);
+    let heater_count = self
+      .feature_count(ButtplugCurrentSpecDeviceMessageType::TemperatureCmd)
+      .unwrap_or(0);
+    let levels = (0..heater_count)
+      .map(|index| TemperatureSubcommand::new(index, level))
+      .collect();
+    self.send_message_expect_ok(TemperatureCmd::new(self.index, levels).into())
+  }
+
+  /// Sets every pump on the device to `level` (0.0 turns the pump off, 1.0 is
+  /// the strongest pressure the device config allows). As with
+  /// [set_temperature](Self::set_temperature), the server may lower this, via
+  /// its `max_suction_level` option. [stop](Self::stop) also releases any
+  /// pressure the pump is holding.
+  pub fn set_suction(&self, level: f64) -> ButtplugClientResultFuture {
+    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::SuctionCmd);
+    let pump_count = self
+      .feature_count(ButtplugCurrentSpecDeviceMessageType::SuctionCmd)
+      .unwrap_or(0);
+    let levels = (0..pump_count)
+      .map(|index| SuctionSubcommand::new(index, level))
+      .collect();
+    self.send_message_expect_ok(SuctionCmd::new(self.index, levels).into())
   }
 
   pub fn battery_level(&self) -> ButtplugClientResultFuture<f64> {
@@ -497,6 +984,83 @@ impl ButtplugClientDevice {
     })
   }
 
+  /// Triggers a device specific feature, like a light or a mode. `key` needs
+  /// to be one of [keyed_command_keys][Self::keyed_command_keys], and what
+  /// `value` can be depends on the key.
+  pub fn keyed_command(&self, key: &str, value: &str) -> ButtplugClientResultFuture {
+    check_message_support!(self, ButtplugCurrentSpecDeviceMessageType::KeyedDeviceCmd);
+    if !self.keyed_command_keys().iter().any(|k| k == key) {
+      return self.create_boxed_future_client_error(
+        ButtplugDeviceError::KeyNotSupported(key.to_owned()).into(),
+      );
+    }
+    self.send_message_expect_ok(KeyedDeviceCmd::new(self.index, key, value).into())
+  }
+  /// Write latency and error statistics the server has kept for the device.
+  /// Available for every device, regardless of its message attributes.
+  pub fn statistics(&self) -> ButtplugClientResultFuture<DeviceStatistics> {
+    let msg =
+      ButtplugCurrentSpecClientMessage::DeviceStatisticsCmd(DeviceStatisticsCmd::new(self.index));
+    let send_fut = self.send_message(msg);
+    Box::pin(async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::DeviceStatistics(statistics) => Ok(statistics),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    })
+  }
+
+  /// What the server last told the device to do, i.e. to set sliders to the
+  /// device's real speeds after reconnecting. Available for every device.
+  pub fn status(&self) -> ButtplugClientResultFuture<DeviceStatus> {
+    let msg = ButtplugCurrentSpecClientMessage::DeviceStatusCmd(DeviceStatusCmd::new(self.index));
+    let send_fut = self.send_message(msg);
+    Box::pin(async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::DeviceStatus(status) => Ok(status),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    })
+  }
+
+  /// Has the server wind the device down to a stop over `wind_down`, once
+  /// `duration` has passed since the first command sent to it. Commands sent
+  /// after that fail, until a new limit is set. If the user has set a shorter
+  /// limit for the device, theirs is used instead. Setting a limit starts the
+  /// device's session over.
+  pub fn set_session_limit(
+    &self,
+    duration: Duration,
+    wind_down: Duration,
+  ) -> ButtplugClientResultFuture {
+    // A duration of 0 clears the limit, so round anything shorter up.
+    let duration = u32::try_from(duration.as_millis()).unwrap_or(u32::MAX).max(1);
+    let wind_down = u32::try_from(wind_down.as_millis()).unwrap_or(u32::MAX);
+    self.send_message_expect_ok(
+      DeviceSessionLimitCmd::new(self.index, duration, wind_down).into(),
+    )
+  }
+
+  /// Removes the limit set with [set_session_limit][Self::set_session_limit].
+  /// Limits the user set for the device still apply.
+  pub fn clear_session_limit(&self) -> ButtplugClientResultFuture {
+    self.send_message_expect_ok(DeviceSessionLimitCmd::new(self.index, 0, 0).into())
+  }
+
   pub fn raw_write(
     &self,
     endpoint: Endpoint,
@@ -568,26 +1132,90 @@ impl ButtplugClientDevice {
     self.send_message_expect_ok(StopDeviceCmd::new(self.index).into())
   }
 
+  /// Returns a guard that stops the device when it's dropped.
+  ///
+  /// Useful for tying device activity to a scope (a game round, a scene, etc),
+  /// so the device is stopped however that scope is left, including early
+  /// returns and panics. The stop command is sent without waiting for a
+  /// response, and is skipped if the device or client has already
+  /// disconnected.
+  ///
+  /// ```no_run
+  /// # use buttplug::client::ButtplugClientDevice;
+  /// # use std::sync::Arc;
+  /// # async fn play_round(device: Arc<ButtplugClientDevice>) {
+  /// let _guard = device.stop_guard();
+  /// device.vibrate(0.5).await.unwrap();
+  /// // Device is stopped here, when the guard goes out of scope.
+  /// # }
+  /// ```
+  pub fn stop_guard(self: &Arc<Self>) -> ButtplugClientDeviceStopGuard {
+    ButtplugClientDeviceStopGuard {
+      device: self.clone(),
+    }
+  }
+
+  /// Sends StopDeviceCmd without waiting on the reply. Since this doesn't need
+  /// to be polled, it's usable from places like Drop impls.
+  fn stop_nowait(&self) {
+    if !self.state.client_connected.load(Ordering::SeqCst)
+      || !self.state.device_connected.load(Ordering::SeqCst)
+    {
+      return;
+    }
+    self
+      .multiplexer
+      .send_nowait(StopDeviceCmd::new(self.index).into());
+  }
+
   pub fn index(&self) -> u32 {
     self.index
   }
 
   pub(super) fn set_device_connected(&self, connected: bool) {
-    self.device_connected.store(connected, Ordering::SeqCst);
+    self.state.device_connected.store(connected, Ordering::SeqCst);
   }
 
   pub(super) fn set_client_connected(&self, connected: bool) {
-    self.client_connected.store(connected, Ordering::SeqCst);
+    self.state.client_connected.store(connected, Ordering::SeqCst);
   }
 
   pub(super) fn queue_event(&self, event: ButtplugClientDeviceEvent) {
-    if self.internal_event_sender.receiver_count() == 0 {
+    if self.state.event_sender.receiver_count() == 0 {
       error!("No handlers for device event, dropping event: {:?}", event);
       return;
     }
     // The only reason a send will fail is if we have no receivers. Since we
     // already checked for receivers here, we can unwrap without issue.
-    self.internal_event_sender.send(event).unwrap();
+    self.state.event_sender.send(event).unwrap();
+  }
+}
+
+/// Stops a [ButtplugClientDevice] when dropped.
+///
+/// Created via [ButtplugClientDevice::stop_guard]. Derefs to the device, so
+/// commands can be sent through the guard itself.
+pub struct ButtplugClientDeviceStopGuard {
+  device: Arc<ButtplugClientDevice>,
+}
+
+impl ButtplugClientDeviceStopGuard {
+  pub fn device(&self) -> &Arc<ButtplugClientDevice> {
+    &self.device
+  }
+}
+
+impl Deref for ButtplugClientDeviceStopGuard {
+  type Target = ButtplugClientDevice;
+
+  fn deref(&self) -> &Self::Target {
+    &self.device
+  }
+}
+
+impl Drop for ButtplugClientDeviceStopGuard {
+  fn drop(&mut self) {
+    self.device.stop_nowait();
   }
 }
 
@@ -602,8 +1230,121 @@ impl PartialEq for ButtplugClientDevice {
 impl fmt::Debug for ButtplugClientDevice {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("ButtplugClientDevice")
-      .field("name", &self.name)
+      .field("name", &self.name())
       .field("index", &self.index)
       .finish()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{client::client_event_loop::ButtplugClientRequest, util::async_manager};
+
+  fn test_device() -> (ButtplugClientDevice, broadcast::Receiver<ButtplugClientRequest>) {
+    // Nothing ever answers requests sent through this multiplexer.
+    let (sender, receiver) = broadcast::channel(256);
+    let multiplexer = Arc::new(ButtplugClientRequestMultiplexer::new(sender));
+    multiplexer.open();
+    let mut allowed_messages = ClientDeviceMessageAttributesMap::new();
+    allowed_messages.insert(
+      ButtplugClientDeviceMessageType::VibrateCmd,
+      DeviceMessageAttributes {
+        feature_count: Some(1),
+        ..Default::default()
+      },
+    );
+    let device = ButtplugClientDevice::new(
+      "Test Device",
+      0,
+      allowed_messages,
+      None,
+      multiplexer,
+      Arc::new(ClientClock::default()),
+    );
+    (device, receiver)
+  }
+
+  #[test]
+  fn test_device_command_timeout() {
+    async_manager::block_on(async {
+      let (device, mut receiver) = test_device();
+      let timeout = Duration::from_millis(20);
+      assert!(matches!(
+        device
+          .vibrate_with_options(0.5, DeviceCommandOptions::timeout(timeout))
+          .await,
+        Err(ButtplugClientError::Timeout(t)) if t == timeout
+      ));
+      assert!(matches!(
+        receiver.try_recv(),
+        Ok(ButtplugClientRequest::Message(ButtplugCurrentSpecClientMessage::VibrateCmd(_)))
+      ));
+    });
+  }
+
+  #[test]
+  fn test_device_command_fire_and_forget() {
+    async_manager::block_on(async {
+      let (device, mut receiver) = test_device();
+      // Resolves without a reply, and sends in order without being polled.
+      let first = device.vibrate_with_options(0.25, DeviceCommandOptions::fire_and_forget());
+      let second = device.vibrate_with_options(0.5, DeviceCommandOptions::fire_and_forget());
+      for &expected in &[0.25, 0.5] {
+        match receiver.try_recv() {
+          Ok(ButtplugClientRequest::Message(ButtplugCurrentSpecClientMessage::VibrateCmd(msg))) => {
+            assert_eq!(msg.speeds()[0].speed(), expected)
+          }
+          _ => panic!("Expected a VibrateCmd"),
+        }
+      }
+      assert!(first.await.is_ok());
+      assert!(second.await.is_ok());
+      // Connection checks still happen up front.
+      device.set_device_connected(false);
+      assert!(device
+        .vibrate_with_options(0.5, DeviceCommandOptions::fire_and_forget())
+        .await
+        .is_err());
+    });
+  }
+
+  #[test]
+  fn test_device_bad_attributes() {
+    async_manager::block_on(async {
+      let (sender, mut receiver) = broadcast::channel(256);
+      let multiplexer = Arc::new(ButtplugClientRequestMultiplexer::new(sender));
+      multiplexer.open();
+      let mut allowed_messages = ClientDeviceMessageAttributesMap::new();
+      // No feature count, so there's no way to know how many vibrators to
+      // send speeds to.
+      allowed_messages.insert(
+        ButtplugClientDeviceMessageType::VibrateCmd,
+        DeviceMessageAttributes::default(),
+      );
+      let device = ButtplugClientDevice::new(
+        "Test Device",
+        0,
+        allowed_messages,
+        None,
+        multiplexer,
+        Arc::new(ClientClock::default()),
+      );
+      assert!(device.supports_vibrate());
+      assert!(device.vibrate_attributes().is_none());
+      assert!(matches!(
+        device.vibrate(0.5).await,
+        Err(ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+          ButtplugDeviceError::InvalidMessageAttributes(..)
+        )))
+      ));
+      assert!(matches!(
+        device.rotate(RotateCommand::Rotate(0.5, true)).await,
+        Err(ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+          ButtplugDeviceError::MessageNotSupported(..)
+        )))
+      ));
+      assert!(receiver.try_recv().is_err());
+    });
+  }
+}
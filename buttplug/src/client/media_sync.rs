@@ -0,0 +1,208 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Playing tracks in time with a media player.
+//!
+//! A [MediaSyncPlayer] plays [PlaybackTrack]s against the timecode of a video
+//! or audio player instead of a [SharedClock][super::patterns::SharedClock].
+//! The application reports the player's timecode and playback rate through
+//! [MediaSyncPlayer::update] whenever it can (every frame, or on a timer), and
+//! commands are scheduled from the latest report. Jumps in the timecode are
+//! treated as seeks, and a rate of 0 pauses playback and stops the devices.
+
+use super::patterns::{PlaybackTrack, TrackCommand};
+use crate::util::async_manager;
+use futures::FutureExt;
+use futures_timer::Delay;
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// How far a reported timecode can be from where we expected it to be before
+/// it's treated as a seek. Media players only report timecodes so often, so
+/// this needs to allow some slop.
+pub const MEDIA_SYNC_SEEK_THRESHOLD: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+struct MediaClock {
+  timecode: Duration,
+  rate: f64,
+  reported_at: Instant,
+  // Bumped on seeks and pauses, so tracks know to reposition.
+  seek_count: u64,
+}
+
+impl MediaClock {
+  fn paused(&self) -> bool {
+    self.rate <= 0.0
+  }
+
+  /// Where we expect the media to be right now.
+  fn timecode_at(&self, now: Instant) -> Duration {
+    if self.paused() {
+      self.timecode
+    } else {
+      self.timecode + now.saturating_duration_since(self.reported_at).mul_f64(self.rate)
+    }
+  }
+
+  /// Real time until the media reaches `timecode`.
+  fn time_until(&self, timecode: Duration) -> Duration {
+    timecode
+      .checked_sub(self.timecode_at(Instant::now()))
+      .unwrap_or_default()
+      .div_f64(self.rate)
+  }
+}
+
+/// Plays tracks in time with a media player's timecode.
+///
+/// Track times are media timecodes. Playback starts paused at 0; nothing is
+/// sent until the first [update][Self::update] with a non-zero rate. Dropping
+/// the player stops playback, but doesn't stop the devices.
+pub struct MediaSyncPlayer {
+  clock: Mutex<MediaClock>,
+  sender: watch::Sender<MediaClock>,
+}
+
+impl MediaSyncPlayer {
+  pub fn new(tracks: Vec<PlaybackTrack>) -> Self {
+    let clock = MediaClock {
+      timecode: Duration::from_millis(0),
+      rate: 0.0,
+      reported_at: Instant::now(),
+      seek_count: 0,
+    };
+    let (sender, receiver) = watch::channel(clock);
+    for track in tracks {
+      async_manager::spawn(run_track(track, receiver.clone())).unwrap();
+    }
+    Self {
+      clock: Mutex::new(clock),
+      sender,
+    }
+  }
+
+  /// Reports the media player's current timecode and playback rate (1.0 for
+  /// normal speed, 0.0 when paused).
+  ///
+  /// If the timecode isn't within [MEDIA_SYNC_SEEK_THRESHOLD] of where
+  /// playback was expected to be, it's handled as a seek: each device is sent
+  /// the last command before the new timecode, so it's doing what it would be
+  /// if the media had played through.
+  pub fn update(&self, timecode: Duration, rate: f64) {
+    let mut clock = self.clock.lock().unwrap();
+    let now = Instant::now();
+    let expected = clock.timecode_at(now);
+    let drift = expected.max(timecode) - expected.min(timecode);
+    let seeked = drift > MEDIA_SYNC_SEEK_THRESHOLD;
+    if seeked || clock.paused() != (rate <= 0.0) {
+      clock.seek_count += 1;
+    }
+    clock.timecode = timecode;
+    clock.rate = rate;
+    clock.reported_at = now;
+    // Only fails if there are no tracks, in which case no one cares.
+    let _ = self.sender.send(*clock);
+  }
+
+  /// Pauses at the current timecode, stopping all devices.
+  pub fn pause(&self) {
+    let timecode = self.timecode();
+    self.update(timecode, 0.0);
+  }
+
+  /// Jumps to `timecode`, keeping the current rate.
+  pub fn seek(&self, timecode: Duration) {
+    let mut clock = self.clock.lock().unwrap();
+    clock.seek_count += 1;
+    clock.timecode = timecode;
+    clock.reported_at = Instant::now();
+    let _ = self.sender.send(*clock);
+  }
+
+  /// Where playback is expected to be, based on the last update.
+  pub fn timecode(&self) -> Duration {
+    self.clock.lock().unwrap().timecode_at(Instant::now())
+  }
+
+  pub fn paused(&self) -> bool {
+    self.clock.lock().unwrap().paused()
+  }
+}
+
+async fn send_track_command(track: &PlaybackTrack, command: TrackCommand) {
+  // Keep going on errors, the device may come back, and the other tracks
+  // shouldn't care either way.
+  if let Err(err) = track.send(command).await {
+    error!("Media sync command {:?} failed: {}", command, err);
+  }
+}
+
+async fn run_track(track: PlaybackTrack, mut receiver: watch::Receiver<MediaClock>) {
+  let mut seek_count = receiver.borrow().seek_count;
+  let mut index = 0;
+  loop {
+    let clock = *receiver.borrow();
+    if clock.seek_count != seek_count {
+      seek_count = clock.seek_count;
+      let timecode = clock.timecode_at(Instant::now());
+      index = (0..track.commands().len())
+        .find(|i| track.command_time(*i) > timecode)
+        .unwrap_or_else(|| track.commands().len());
+      if clock.paused() {
+        send_track_command(&track, TrackCommand::Stop).await;
+      } else if index > 0 {
+        send_track_command(&track, track.commands()[index - 1].1).await;
+      }
+    }
+    if clock.paused() || index >= track.commands().len() {
+      // Nothing to do until something changes. Seeking backwards can give us
+      // more commands to run.
+      if receiver.changed().await.is_err() {
+        return;
+      }
+      continue;
+    }
+    let wait = clock.time_until(track.command_time(index));
+    select! {
+      _ = Delay::new(wait).fuse() => {
+        send_track_command(&track, track.commands()[index].1).await;
+        index += 1;
+      }
+      changed = receiver.changed().fuse() => {
+        if changed.is_err() {
+          return;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::MediaClock;
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn test_media_clock_timecode() {
+    let reported_at = Instant::now();
+    let mut clock = MediaClock {
+      timecode: Duration::from_secs(10),
+      rate: 2.0,
+      reported_at,
+      seek_count: 0,
+    };
+    let later = reported_at + Duration::from_secs(1);
+    assert_eq!(clock.timecode_at(later), Duration::from_secs(12));
+    clock.rate = 0.0;
+    assert!(clock.paused());
+    assert_eq!(clock.timecode_at(later), Duration::from_secs(10));
+  }
+}
@@ -0,0 +1,74 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hooks for watching and changing messages between a client and its
+//! connector.
+//!
+//! Middleware added with
+//! [ButtplugClient::add_middleware][super::ButtplugClient::add_middleware]
+//! sees every message the client sends and receives, so things like message
+//! logging, metrics or fault injection in tests don't need a whole wrapper
+//! connector. Outgoing messages go through middleware in the order it was
+//! added, and incoming messages go through in reverse, so the first
+//! middleware added is the one closest to the application.
+
+use crate::core::messages::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage};
+use std::sync::{Arc, RwLock};
+
+pub trait ButtplugClientMiddleware: Send + Sync {
+  /// Called with each message before it goes to the connector. Message ids
+  /// are already set at this point, and changing them will leave the request
+  /// waiting on a reply that never comes.
+  fn outgoing(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugCurrentSpecClientMessage {
+    msg
+  }
+
+  /// Called with each message from the connector, before the client handles
+  /// it. Returning None drops the message.
+  fn incoming(
+    &self,
+    msg: ButtplugCurrentSpecServerMessage,
+  ) -> Option<ButtplugCurrentSpecServerMessage> {
+    Some(msg)
+  }
+}
+
+/// Middleware shared between a client and its event loop, so it can be added
+/// while connected.
+#[derive(Default, Clone)]
+pub(super) struct ButtplugClientMiddlewareList {
+  middleware: Arc<RwLock<Vec<Arc<dyn ButtplugClientMiddleware>>>>,
+}
+
+impl ButtplugClientMiddlewareList {
+  pub fn add(&self, middleware: Arc<dyn ButtplugClientMiddleware>) {
+    self.middleware.write().unwrap().push(middleware);
+  }
+
+  pub fn outgoing(
+    &self,
+    msg: ButtplugCurrentSpecClientMessage,
+  ) -> ButtplugCurrentSpecClientMessage {
+    self
+      .middleware
+      .read()
+      .unwrap()
+      .iter()
+      .fold(msg, |msg, middleware| middleware.outgoing(msg))
+  }
+
+  pub fn incoming(
+    &self,
+    msg: ButtplugCurrentSpecServerMessage,
+  ) -> Option<ButtplugCurrentSpecServerMessage> {
+    let mut msg = msg;
+    for middleware in self.middleware.read().unwrap().iter().rev() {
+      msg = middleware.incoming(msg)?;
+    }
+    Some(msg)
+  }
+}
@@ -0,0 +1,207 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Combining vibration speeds from more than one source.
+//!
+//! If two parts of an application both send VibrateCmds to the same device
+//! (say, a background pattern and game events), whichever sent last wins, and
+//! the other source's speed is lost until it sends again. An
+//! [IntensityMixer] gives each part of the application its own
+//! [MixerSource], and sends the device a combination of all of their speeds,
+//! based on a [MixPolicy].
+
+use super::{ButtplugClientDevice, ButtplugClientResultFuture, VibrateCommand};
+use crate::util::async_manager;
+use futures::future;
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+/// How speeds from different sources are combined, per vibrator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MixPolicy {
+  /// Fastest speed any source has set.
+  Max,
+  /// All speeds added together, capped at 1.0.
+  SumClamped,
+  /// Speed from whichever source was set most recently. Once that source is
+  /// cleared, the source set before it takes over again.
+  Override,
+}
+
+#[derive(Default)]
+struct MixerState {
+  // Source id to (update sequence number, speeds).
+  sources: HashMap<u32, (u64, Vec<f64>)>,
+  next_source_id: u32,
+  sequence: u64,
+  last_sent: Option<Vec<f64>>,
+}
+
+struct MixerInner {
+  device: Arc<ButtplugClientDevice>,
+  policy: MixPolicy,
+  vibrator_count: usize,
+  state: Mutex<MixerState>,
+}
+
+impl MixerInner {
+  fn update(&self, source_id: u32, speeds: Option<Vec<f64>>) -> ButtplugClientResultFuture {
+    let mut state = self.state.lock().unwrap();
+    match speeds {
+      Some(speeds) => {
+        state.sequence += 1;
+        let sequence = state.sequence;
+        state.sources.insert(source_id, (sequence, speeds));
+      }
+      None => {
+        state.sources.remove(&source_id);
+      }
+    }
+    let mixed = mix(
+      self.policy,
+      self.vibrator_count,
+      state.sources.values().map(|(seq, speeds)| (*seq, speeds.as_slice())),
+    );
+    if state.last_sent.as_ref() == Some(&mixed) {
+      return Box::pin(future::ready(Ok(())));
+    }
+    state.last_sent = Some(mixed.clone());
+    self.device.vibrate(VibrateCommand::SpeedVec(mixed))
+  }
+}
+
+/// Mixes speeds from all sources, per vibrator. Vibrators a source didn't set
+/// a speed for count as 0 for that source.
+fn mix<'a>(
+  policy: MixPolicy,
+  vibrator_count: usize,
+  sources: impl Iterator<Item = (u64, &'a [f64])>,
+) -> Vec<f64> {
+  let speed = |speeds: &[f64], index: usize| speeds.get(index).copied().unwrap_or(0.0);
+  let mut mixed: Vec<f64> = vec![0.0; vibrator_count];
+  match policy {
+    MixPolicy::Max => {
+      for (_, speeds) in sources {
+        for (index, value) in mixed.iter_mut().enumerate() {
+          *value = value.max(speed(speeds, index));
+        }
+      }
+    }
+    MixPolicy::SumClamped => {
+      for (_, speeds) in sources {
+        for (index, value) in mixed.iter_mut().enumerate() {
+          *value = (*value + speed(speeds, index)).min(1.0);
+        }
+      }
+    }
+    MixPolicy::Override => {
+      if let Some((_, speeds)) = sources.max_by_key(|(sequence, _)| *sequence) {
+        for (index, value) in mixed.iter_mut().enumerate() {
+          *value = speed(speeds, index);
+        }
+      }
+    }
+  }
+  mixed
+}
+
+/// Sends a device the mix of speeds from all of its [MixerSource]s.
+pub struct IntensityMixer {
+  inner: Arc<MixerInner>,
+}
+
+impl IntensityMixer {
+  pub fn new(device: Arc<ButtplugClientDevice>, policy: MixPolicy) -> Self {
+    let vibrator_count = device.vibrate_feature_count().unwrap_or(0) as usize;
+    Self {
+      inner: Arc::new(MixerInner {
+        device,
+        policy,
+        vibrator_count,
+        state: Mutex::new(MixerState::default()),
+      }),
+    }
+  }
+
+  /// Adds a source. Sources start out not contributing anything.
+  pub fn add_source(&self) -> MixerSource {
+    let mut state = self.inner.state.lock().unwrap();
+    let id = state.next_source_id;
+    state.next_source_id += 1;
+    MixerSource {
+      id,
+      mixer: self.inner.clone(),
+    }
+  }
+
+  /// Speeds last sent to the device, or None if nothing has been sent yet.
+  pub fn mixed_speeds(&self) -> Option<Vec<f64>> {
+    self.inner.state.lock().unwrap().last_sent.clone()
+  }
+}
+
+/// One contributor to an [IntensityMixer]. Dropping the source clears it.
+///
+/// Commands only go to the device when the mixed speeds change, so setting a
+/// source may not send anything.
+pub struct MixerSource {
+  id: u32,
+  mixer: Arc<MixerInner>,
+}
+
+impl MixerSource {
+  /// Sets this source's speed for all vibrators.
+  pub fn set(&self, speed: f64) -> ButtplugClientResultFuture {
+    self
+      .mixer
+      .update(self.id, Some(vec![speed; self.mixer.vibrator_count]))
+  }
+
+  /// Sets this source's speeds per vibrator, by position.
+  pub fn set_vec(&self, speeds: Vec<f64>) -> ButtplugClientResultFuture {
+    self.mixer.update(self.id, Some(speeds))
+  }
+
+  /// Removes this source from the mix.
+  pub fn clear(&self) -> ButtplugClientResultFuture {
+    self.mixer.update(self.id, None)
+  }
+}
+
+impl Drop for MixerSource {
+  fn drop(&mut self) {
+    let fut = self.clear();
+    async_manager::spawn(async move {
+      if let Err(err) = fut.await {
+        error!("Cannot update mixed speeds after source was dropped: {}", err);
+      }
+    })
+    .unwrap();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{mix, MixPolicy};
+
+  #[test]
+  fn test_mix_policies() {
+    let base: &[f64] = &[0.5, 0.2];
+    let event: &[f64] = &[0.7];
+    let sources = || vec![(1, base), (2, event)].into_iter();
+    assert_eq!(mix(MixPolicy::Max, 2, sources()), vec![0.7, 0.2]);
+    assert_eq!(mix(MixPolicy::SumClamped, 2, sources()), vec![1.0, 0.2]);
+    assert_eq!(mix(MixPolicy::Override, 2, sources()), vec![0.7, 0.0]);
+    assert_eq!(
+      mix(MixPolicy::Override, 2, vec![(3, base), (2, event)].into_iter()),
+      vec![0.5, 0.2]
+    );
+    assert_eq!(mix(MixPolicy::Max, 2, std::iter::empty()), vec![0.0, 0.0]);
+  }
+}
@@ -6,40 +6,62 @@
 // for full license information.
 
 //! Communications API for accessing Buttplug Servers
-pub mod client_event_loop;
-mod client_message_sorter;
+mod client_builder;
+mod client_event_loop;
+mod client_event_queue;
+mod client_request_multiplexer;
+pub mod clock_sync;
 pub mod device;
-
+pub mod media_sync;
+pub mod middleware;
+pub mod mixer;
+#[cfg(feature = "motion-mapping")]
+pub mod motion;
+pub mod patterns;
+pub mod scene;
+pub mod sensor;
+pub mod settings;
+#[cfg(feature = "client-sync")]
+pub mod sync;
+
+pub use client_builder::ButtplugClientBuilder;
 use client_event_loop::{ButtplugClientEventLoop, ButtplugClientRequest};
+pub use client_event_queue::{ButtplugClientEventQueue, ButtplugClientQueuedEvent};
+use client_request_multiplexer::ButtplugClientRequestMultiplexer;
+use clock_sync::{ClientClock, ClockOffset, CLOCK_SYNC_SAMPLES};
+use middleware::{ButtplugClientMiddleware, ButtplugClientMiddlewareList};
 pub use device::{
-  ButtplugClientDevice, ButtplugClientDeviceEvent, ButtplugClientDeviceMessageType, LinearCommand,
-  RotateCommand, VibrateCommand,
+  ButtplugClientDevice, ButtplugClientDeviceEvent, ButtplugClientDeviceMessageType,
+  ButtplugClientDeviceSelector, ButtplugClientDeviceStopGuard, DeviceCommandOptions,
+  LinearCommand, RotateCommand, RotateToAngleCommand, VibrateCommand,
 };
 
 use crate::{
   connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorFuture},
   core::{
-    errors::{ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     messages::{
       ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage,
-      ButtplugMessageSpecVersion, Ping, RequestDeviceList, RequestServerInfo, StartScanning,
-      StopAllDevices, StopScanning,
+      ButtplugMessageSpecVersion, Ping, RequestDeviceList, RequestServerInfo, RequestServerTime,
+      StartScanning, StopAllDevices, StopScanning,
     },
   },
   util::{
-    async_manager,
-    future::{ButtplugFuture, ButtplugFutureStateShared},
-    stream::convert_broadcast_receiver_to_stream,
+    async_manager, future::ButtplugFuture, stream::convert_broadcast_receiver_to_stream,
   },
 };
 use dashmap::DashMap;
 use futures::{
   future::{self, BoxFuture},
-  Stream,
+  FutureExt, Stream, StreamExt,
 };
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+use futures_timer::Delay;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
 use tokio::sync::{broadcast, mpsc, Mutex};
@@ -58,51 +80,16 @@ type ButtplugClientResultFuture<T = ()> = BoxFuture<'static, ButtplugClientResul
 pub type ButtplugServerMessageResult = ButtplugClientResult<ButtplugCurrentSpecServerMessage>;
 pub type ButtplugServerMessageResultFuture =
   ButtplugClientResultFuture<ButtplugCurrentSpecServerMessage>;
-/// Future state type for returning server responses across futures.
-pub(crate) type ButtplugServerMessageStateShared =
-  ButtplugFutureStateShared<ButtplugServerMessageResult>;
-/// Future type that expects server responses.
-pub(crate) type ButtplugServerMessageFuture = ButtplugFuture<ButtplugServerMessageResult>;
-
-/// Future state for messages sent from the client that expect a server
-/// response.
-///
-/// When a message is sent from the client and expects a response from the
-/// server, we'd like to know when that response arrives, and usually we'll want
-/// to wait for it. We can do so by creating a future that will be resolved when
-/// a response is received from the server.
-///
-/// To do this, we build a [ButtplugFuture], then take its waker and pass it
-/// along with the message we send to the connector, using the
-/// [ButtplugClientMessageFuturePair] type. We can then expect the connector to
-/// get the response from the server, match it with our message (using something
-/// like the
-/// [ClientConnectorMessageSorter][crate::connector::ClientConnectorMessageSorter]),
-/// and set the reply in the waker we've sent along. This will resolve the
-/// future we're waiting on and allow us to continue execution.
-#[derive(Clone)]
-pub struct ButtplugClientMessageFuturePair {
-  pub msg: ButtplugCurrentSpecClientMessage,
-  pub waker: ButtplugServerMessageStateShared,
-}
-
-impl ButtplugClientMessageFuturePair {
-  pub fn new(
-    msg: ButtplugCurrentSpecClientMessage,
-    waker: ButtplugServerMessageStateShared,
-  ) -> Self {
-    Self { msg, waker }
-  }
-}
 
 /// Represents all of the different types of errors a ButtplugClient can return.
 ///
-/// Clients can return two types of errors:
+/// Clients can return three types of errors:
 ///
 /// - [ButtplugConnectorError], which means there was a problem with the
 /// connection between the client and the server, like a network connection
 /// issue.
 /// - [ButtplugError], which is an error specific to the Buttplug Protocol.
+/// - [Timeout](ButtplugClientError::Timeout), when a request's timeout runs out.
 #[derive(Debug, Error)]
 pub enum ButtplugClientError {
   /// Connector error
@@ -111,6 +98,9 @@ pub enum ButtplugClientError {
   /// Protocol error
   #[error(transparent)]
   ButtplugError(#[from] ButtplugError),
+  /// No response within the timeout given with the request.
+  #[error("No response from the server within {0:?}.")]
+  Timeout(Duration),
 }
 
 /// Enum representing different events that can be emitted by a client.
@@ -129,6 +119,10 @@ pub enum ButtplugClientEvent {
   /// Emitted when a device has been removed from the server. Includes a
   /// [ButtplugClientDevice] object representing the device.
   DeviceRemoved(Arc<ButtplugClientDevice>),
+  /// Emitted when the server sends a new name or message attributes for a
+  /// device the client already knows about. The device has already been
+  /// updated by the time this is emitted.
+  DeviceUpdated(Arc<ButtplugClientDevice>),
   /// Emitted when a client has not pinged the server in a sufficient amount of
   /// time.
   PingTimeout,
@@ -166,11 +160,19 @@ pub struct ButtplugClient {
   /// The server name that we're current connected to.
   server_name: Arc<Mutex<Option<String>>>,
   event_stream: broadcast::Sender<ButtplugClientEvent>,
+  // Client and device events in a single queue, for polling consumers.
+  queued_event_sender: broadcast::Sender<ButtplugClientQueuedEvent>,
   // Sender to relay messages to the internal client loop
   message_sender: broadcast::Sender<ButtplugClientRequest>,
+  // Pairs server responses with our requests. Shared with the event loop and
+  // client devices.
+  multiplexer: Arc<ButtplugClientRequestMultiplexer>,
   connected: Arc<AtomicBool>,
   _client_span: Arc<Mutex<Option<Span>>>,
   device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
+  middleware: ButtplugClientMiddlewareList,
+  // Latest estimate of the server's clock, shared with client devices.
+  clock: Arc<ClientClock>,
 }
 
 unsafe impl Send for ButtplugClient {}
@@ -182,17 +184,34 @@ impl ButtplugClient {
   pub fn new(name: &str) -> Self {
     let (message_sender, _) = broadcast::channel(256);
     let (event_stream, _) = broadcast::channel(256);
+    let (queued_event_sender, _) = broadcast::channel(1024);
     Self {
       client_name: name.to_owned(),
       server_name: Arc::new(Mutex::new(None)),
       event_stream,
+      queued_event_sender,
+      multiplexer: Arc::new(ButtplugClientRequestMultiplexer::new(
+        message_sender.clone(),
+      )),
       message_sender,
       _client_span: Arc::new(Mutex::new(None)),
       connected: Arc::new(AtomicBool::new(false)),
       device_map: Arc::new(DashMap::new()),
+      middleware: ButtplugClientMiddlewareList::default(),
+      clock: Arc::new(ClientClock::default()),
     }
   }
 
+  /// Adds middleware that sees every message sent to or received from the
+  /// server. Can be called before or after connecting, and stays in place
+  /// across reconnects. See the [middleware] module for ordering.
+  pub fn add_middleware<T>(&self, middleware: T)
+  where
+    T: ButtplugClientMiddleware + 'static,
+  {
+    self.middleware.add(Arc::new(middleware));
+  }
+
   pub async fn connect<ConnectorType>(
     &self,
     mut connector: ConnectorType,
@@ -220,17 +239,23 @@ impl ButtplugClient {
       ButtplugClientError::from(e)
     })?;
     info!("Connection to server succeeded.");
+    // Whatever we knew about the last server's clock doesn't apply anymore.
+    self.clock.set_offset(None);
     let mut client_event_loop = ButtplugClientEventLoop::new(
       self.connected.clone(),
       connector,
       connector_receiver,
       self.event_stream.clone(),
-      self.message_sender.clone(),
+      self.queued_event_sender.clone(),
+      self.multiplexer.clone(),
       self.device_map.clone(),
+      self.middleware.clone(),
+      self.clock.clone(),
     );
 
     // Start the event loop before we run the handshake.
-    async_manager::spawn(
+    async_manager::spawn_named(
+      "Client Event Loop",
       async move {
         client_event_loop.run().await;
       }
@@ -260,7 +285,8 @@ impl ButtplugClient {
   /// the devices you want, there are a couple of things to check:
   ///
   /// - Are you on a platform that the device communication manager supports?
-  ///   For instance, we only support XInput on windows.
+  ///   For instance, we only support XInput on windows, and evdev force
+  ///   feedback on linux.
   /// - Did the developers add a new Device CommunicationManager type and forget
   ///   to add it to this method? _It's more likely than you think!_ [File a
   ///   bug](https://github.com/buttplugio/buttplug-rs/issues).
@@ -285,51 +311,8 @@ impl ButtplugClient {
       Ok(conn) => conn,
       Err(err) => return Err(ButtplugClientError::ButtplugError(err)),
     };
-    #[cfg(feature = "btleplug-manager")]
-    {
-      use crate::server::comm_managers::btleplug::BtlePlugCommunicationManagerBuilder;
-      connector
-        .server_ref()
-        .add_comm_manager(BtlePlugCommunicationManagerBuilder::default())
-        .unwrap();
-    }
-    #[cfg(feature = "serial-manager")]
-    {
-      use crate::server::comm_managers::serialport::SerialPortCommunicationManagerBuilder;
-      connector
-        .server_ref()
-        .add_comm_manager(SerialPortCommunicationManagerBuilder::default())
-        .unwrap();
-    }
-    #[cfg(feature = "lovense-connect-service-manager")]
-    {
-      use crate::server::comm_managers::lovense_connect_service::LovenseConnectServiceCommunicationManagerBuilder;
-      connector
-        .server_ref()
-        .add_comm_manager(LovenseConnectServiceCommunicationManagerBuilder::default())
-        .unwrap();
-    }
-    #[cfg(feature = "lovense-dongle-manager")]
-    {
-      use crate::server::comm_managers::lovense_dongle::{
-        LovenseHIDDongleCommunicationManagerBuilder, LovenseSerialDongleCommunicationManagerBuilder,
-      };
-      connector
-        .server_ref()
-        .add_comm_manager(LovenseHIDDongleCommunicationManagerBuilder::default())
-        .unwrap();
-      connector
-        .server_ref()
-        .add_comm_manager(LovenseSerialDongleCommunicationManagerBuilder::default())
-        .unwrap();
-    }
-    #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
-    {
-      use crate::server::comm_managers::xinput::XInputDeviceCommunicationManagerBuilder;
-      connector
-        .server_ref()
-        .add_comm_manager(XInputDeviceCommunicationManagerBuilder::default())
-        .unwrap();
+    for comm_manager in crate::server::DefaultCommManager::available() {
+      comm_manager.add_to(connector.server_ref()).unwrap();
     }
     self.connect(connector).await
   }
@@ -361,15 +344,7 @@ impl ButtplugClient {
       // Get currently connected devices. The event loop will
       // handle sending the message and getting the return, and
       // will send the client updates as events.
-      let msg = self
-        .send_message(RequestDeviceList::default().into())
-        .await?;
-      if let ButtplugCurrentSpecServerMessage::DeviceList(m) = msg {
-        self
-          .send_message_to_event_loop(ButtplugClientRequest::HandleDeviceList(m))
-          .await?;
-      }
-      Ok(())
+      self.refresh_device_list().await
     } else {
       self.disconnect().await?;
       Err(ButtplugClientError::ButtplugError(
@@ -378,6 +353,31 @@ impl ButtplugClient {
     }
   }
 
+  /// Requests the device list from the server and reconciles it with the
+  /// client's devices.
+  ///
+  /// [ButtplugClientEvent::DeviceAdded] and
+  /// [ButtplugClientEvent::DeviceRemoved] events are emitted for any devices
+  /// that the client missed events for, which can happen after reconnects or
+  /// with servers that don't reliably deliver events. Returns once all events
+  /// have been emitted.
+  pub async fn refresh_device_list(&self) -> ButtplugClientResult {
+    let msg = self
+      .send_message(RequestDeviceList::default().into())
+      .await?;
+    if let ButtplugCurrentSpecServerMessage::DeviceList(m) = msg {
+      let fut = ButtplugFuture::default();
+      self
+        .send_message_to_event_loop(ButtplugClientRequest::HandleDeviceList(
+          m,
+          fut.get_state_clone(),
+        ))
+        .await?;
+      fut.await;
+    }
+    Ok(())
+  }
+
   /// Returns true if client is currently connected.
   pub fn connected(&self) -> bool {
     self.connected.load(Ordering::SeqCst)
@@ -423,6 +423,113 @@ impl ButtplugClient {
     self.send_message_expect_ok(StopScanning::default().into())
   }
 
+  /// Scans for devices for up to `duration`, then stops scanning.
+  ///
+  /// Scanning also ends early if the server reports that it's finished (i.e.
+  /// all of its comm managers only scan for a set time). Returns the devices
+  /// that were added during the scan and are still connected. Devices the
+  /// client already knew about are not included.
+  pub async fn scan_for(
+    &self,
+    duration: Duration,
+  ) -> ButtplugClientResult<Vec<Arc<ButtplugClientDevice>>> {
+    // Subscribe before starting, so we can't miss anything the scan finds.
+    let mut events = self.event_stream();
+    self.start_scanning().await?;
+    let mut timeout = Delay::new(duration).fuse();
+    let mut found: Vec<Arc<ButtplugClientDevice>> = vec![];
+    let mut finished = false;
+    while !finished {
+      select! {
+        event = events.next().fuse() => match event {
+          Some(ButtplugClientEvent::DeviceAdded(device)) => found.push(device),
+          Some(ButtplugClientEvent::DeviceRemoved(device)) => {
+            found.retain(|existing| existing.index() != device.index())
+          }
+          Some(ButtplugClientEvent::ScanningFinished) => finished = true,
+          Some(ButtplugClientEvent::ServerDisconnect) | None => {
+            return Err(ButtplugConnectorError::ConnectorNotConnected.into())
+          }
+          Some(_) => {}
+        },
+        _ = timeout => break,
+      }
+    }
+    // If the server finished on its own, it's already stopped.
+    if !finished {
+      self.stop_scanning().await?;
+    }
+    Ok(found)
+  }
+
+  /// Waits for a device matching `selector` to show up, for up to `timeout`.
+  ///
+  /// Returns right away if the client already has a matching device.
+  /// Otherwise starts scanning (unless something else already did), and
+  /// stops it again once the device is found or the timeout runs out. Returns
+  /// None if no matching device was added in time.
+  pub async fn await_device<T>(
+    &self,
+    selector: T,
+    timeout: Duration,
+  ) -> ButtplugClientResult<Option<Arc<ButtplugClientDevice>>>
+  where
+    T: Into<ButtplugClientDeviceSelector>,
+  {
+    let selector = selector.into();
+    // Subscribe before checking existing devices, so nothing can get added in
+    // between without us seeing it.
+    let mut events = self.event_stream();
+    if let Some(device) = self
+      .devices()
+      .into_iter()
+      .find(|device| selector.matches(device))
+    {
+      return Ok(Some(device));
+    }
+    let mut scanning = match self.start_scanning().await {
+      Ok(()) => true,
+      Err(ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::DeviceScanningAlreadyStarted,
+      ))) => {
+        debug!("Scanning already started, leaving it running after await_device.");
+        false
+      }
+      Err(err) => return Err(err),
+    };
+    let mut timeout = Delay::new(timeout).fuse();
+    let mut found = None;
+    loop {
+      select! {
+        event = events.next().fuse() => match event {
+          Some(ButtplugClientEvent::DeviceAdded(device)) if selector.matches(&device) => {
+            found = Some(device);
+            break;
+          }
+          // Devices can still connect after the server finishes scanning, so
+          // keep waiting until the timeout.
+          Some(ButtplugClientEvent::ScanningFinished) => scanning = false,
+          Some(ButtplugClientEvent::ServerDisconnect) | None => {
+            return Err(ButtplugConnectorError::ConnectorNotConnected.into())
+          }
+          Some(_) => {}
+        },
+        _ = timeout => break,
+      }
+    }
+    if scanning {
+      // The server may have finished on its own before we heard about it.
+      match self.stop_scanning().await {
+        Ok(())
+        | Err(ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+          ButtplugDeviceError::DeviceScanningAlreadyStopped,
+        ))) => {}
+        Err(err) => return Err(err),
+      }
+    }
+    Ok(found)
+  }
+
   /// Tells server to stop all devices.
   ///
   /// Returns Err([ButtplugClientError]) if request fails due to issues with
@@ -441,6 +548,13 @@ impl ButtplugClient {
     Box::pin(stream)
   }
 
+  /// Creates a queue that receives client events and events for all devices,
+  /// in order, for consumers that would rather poll than deal with streams
+  /// (i.e. FFI layers). See [ButtplugClientEventQueue].
+  pub fn event_queue(&self) -> ButtplugClientEventQueue {
+    ButtplugClientEventQueue::new(self.queued_event_sender.subscribe())
+  }
+
   /// Send message to the internal event loop.
   ///
   /// Mostly for handling boilerplate around possible send errors.
@@ -482,19 +596,7 @@ impl ButtplugClient {
     &self,
     msg: ButtplugCurrentSpecClientMessage,
   ) -> ButtplugServerMessageResultFuture {
-    // Create a future to pair with the message being resolved.
-    let fut = ButtplugServerMessageFuture::default();
-    let internal_msg = ButtplugClientRequest::Message(ButtplugClientMessageFuturePair::new(
-      msg,
-      fut.get_state_clone(),
-    ));
-
-    // Send message to internal loop and wait for return.
-    let send_fut = self.send_message_to_event_loop(internal_msg);
-    Box::pin(async move {
-      send_fut.await?;
-      fut.await
-    })
+    self.multiplexer.send(msg)
   }
 
   /// Sends a ButtplugMessage from client to server. Expects to receive an [Ok]
@@ -521,6 +623,54 @@ impl ButtplugClient {
     Box::pin(async move { ping_fut.await })
   }
 
+  /// Estimates how far the server's clock is from the client's, by timing a
+  /// few round trips to the server. See the [clock_sync] module for how.
+  ///
+  /// The estimate is kept and used to convert
+  /// [DeviceCommandOptions::run_at] times from then on, and can be read back
+  /// with [ButtplugClient::clock_offset]. Clocks drift, so long running
+  /// applications should sync again every so often.
+  pub async fn sync_clock(&self) -> ButtplugClientResult<ClockOffset> {
+    let mut best: Option<ClockOffset> = None;
+    for _ in 0..CLOCK_SYNC_SAMPLES {
+      let sent = SystemTime::now();
+      let started = Instant::now();
+      let msg = self
+        .send_message(RequestServerTime::default().into())
+        .await?;
+      let round_trip = started.elapsed();
+      let server_timestamp = match msg {
+        ButtplugCurrentSpecServerMessage::ServerTime(time) => time.timestamp(),
+        ButtplugCurrentSpecServerMessage::Error(err) => {
+          return Err(ButtplugError::from(err).into())
+        }
+        msg => {
+          return Err(
+            ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+              "{:?}",
+              msg
+            )))
+            .into(),
+          )
+        }
+      };
+      let sample = ClockOffset::from_sample(sent, round_trip, server_timestamp);
+      if best.map_or(true, |best| sample.round_trip < best.round_trip) {
+        best = Some(sample);
+      }
+    }
+    let offset = best.expect("CLOCK_SYNC_SAMPLES is never 0");
+    debug!("Estimated server clock offset: {:?}", offset);
+    self.clock.set_offset(Some(offset));
+    Ok(offset)
+  }
+
+  /// The last estimate from [ButtplugClient::sync_clock], if there's been one
+  /// since connecting.
+  pub fn clock_offset(&self) -> Option<ClockOffset> {
+    self.clock.offset()
+  }
+
   pub fn server_name(&self) -> Option<String> {
     // We'd have to be calling server_name in an extremely tight, asynchronous
     // loop for this to return None, so we'll treat this as lockless.
@@ -0,0 +1,236 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Driving devices from accelerometer motion.
+//!
+//! Covers the "interactive pair" setup, where shaking one thing (a phone, a
+//! toy with an accelerometer, a game controller) makes a partner's device
+//! vibrate. A [MotionMapping] turns accelerometer samples into vibration
+//! speeds:
+//!
+//! - Gravity is taken out, so a device sitting still maps to 0.
+//! - Samples are smoothed, so single jolts don't cause spikes.
+//! - Motion below a dead zone is ignored, and motion at or above the
+//!   saturation point maps to full speed.
+//! - Everything in between goes through a [MappingCurve].
+//!
+//! Getting samples is up to the application, since every device (and phone
+//! API) reports them differently. See
+//! [raw_readings][super::sensor::raw_readings] for reading them off of a
+//! device.
+
+use super::{sensor::MovingAverage, ButtplugClientDevice, ButtplugClientResult, VibrateCommand};
+use futures::{Stream, StreamExt};
+use std::sync::Arc;
+
+/// Speeds closer than this to the last one sent aren't sent, so a stream of
+/// samples doesn't turn into a flood of near identical commands.
+pub const MOTION_SPEED_EPSILON: f64 = 0.01;
+
+/// One accelerometer reading, in g.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AccelerometerSample {
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+}
+
+impl AccelerometerSample {
+  pub fn new(x: f64, y: f64, z: f64) -> Self {
+    Self { x, y, z }
+  }
+
+  pub fn magnitude(&self) -> f64 {
+    (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+  }
+
+  /// How much the sample differs from sitting still, i.e. its magnitude with
+  /// 1g of gravity taken out.
+  pub fn motion(&self) -> f64 {
+    (self.magnitude() - 1.0).abs()
+  }
+}
+
+/// Shape of the mapping from motion to speed. Curves take and return values
+/// from 0.0 to 1.0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingCurve {
+  Linear,
+  /// Raises the input to this power. Above 1.0 keeps speeds low until motion
+  /// gets big, below 1.0 makes small motions count for more.
+  Power(f64),
+  /// Piecewise linear curve through (input, output) points, which should be
+  /// sorted by input. Inputs outside the points get the nearest point's
+  /// output.
+  Points(Vec<(f64, f64)>),
+}
+
+impl MappingCurve {
+  pub fn apply(&self, input: f64) -> f64 {
+    let input = input.clamp(0.0, 1.0);
+    let output = match self {
+      MappingCurve::Linear => input,
+      MappingCurve::Power(exponent) => input.powf(*exponent),
+      MappingCurve::Points(points) => match points.iter().position(|(x, _)| *x >= input) {
+        None => points.last().map_or(input, |(_, y)| *y),
+        Some(0) => points[0].1,
+        Some(index) => {
+          let (x0, y0) = points[index - 1];
+          let (x1, y1) = points[index];
+          if x1 <= x0 {
+            y1
+          } else {
+            y0 + (y1 - y0) * (input - x0) / (x1 - x0)
+          }
+        }
+      },
+    };
+    output.clamp(0.0, 1.0)
+  }
+}
+
+/// Settings for turning motion into speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionMapping {
+  /// Motion (in g) at or below which speed is 0.
+  pub dead_zone: f64,
+  /// Motion (in g) at or above which speed is 1.0.
+  pub saturation: f64,
+  /// Number of samples averaged together before mapping.
+  pub smoothing: usize,
+  pub curve: MappingCurve,
+}
+
+impl Default for MotionMapping {
+  fn default() -> Self {
+    Self {
+      dead_zone: 0.05,
+      saturation: 2.0,
+      smoothing: 5,
+      curve: MappingCurve::Linear,
+    }
+  }
+}
+
+/// Turns accelerometer samples into speeds, using a [MotionMapping].
+pub struct MotionMapper {
+  mapping: MotionMapping,
+  average: MovingAverage,
+}
+
+impl MotionMapper {
+  pub fn new(mapping: MotionMapping) -> Self {
+    Self {
+      average: MovingAverage::new(mapping.smoothing),
+      mapping,
+    }
+  }
+
+  /// Adds a sample, returning the speed it maps to.
+  pub fn push(&mut self, sample: AccelerometerSample) -> f64 {
+    let motion = self.average.push(sample.motion());
+    let range = self.mapping.saturation - self.mapping.dead_zone;
+    if motion <= self.mapping.dead_zone {
+      return 0.0;
+    }
+    if range <= 0.0 {
+      return 1.0;
+    }
+    self
+      .mapping
+      .curve
+      .apply((motion - self.mapping.dead_zone) / range)
+  }
+}
+
+/// Speeds for each sample in `samples`. See [MotionMapper].
+pub fn motion_to_speed<S>(samples: S, mapping: MotionMapping) -> impl Stream<Item = f64>
+where
+  S: Stream<Item = AccelerometerSample>,
+{
+  let mut mapper = MotionMapper::new(mapping);
+  samples.map(move |sample| mapper.push(sample))
+}
+
+/// Vibrates `device` based on `samples` until the stream ends, then stops
+/// the device.
+///
+/// Speeds within [MOTION_SPEED_EPSILON] of the last one sent are skipped.
+/// Returns early with an error if a command fails (i.e. the device
+/// disconnected).
+pub async fn drive_vibration<S>(
+  samples: S,
+  mapping: MotionMapping,
+  device: Arc<ButtplugClientDevice>,
+) -> ButtplugClientResult
+where
+  S: Stream<Item = AccelerometerSample>,
+{
+  let speeds = motion_to_speed(samples, mapping);
+  futures::pin_mut!(speeds);
+  let mut last_speed: Option<f64> = None;
+  while let Some(speed) = speeds.next().await {
+    if matches!(last_speed, Some(last) if (last - speed).abs() < MOTION_SPEED_EPSILON) {
+      continue;
+    }
+    device.vibrate(VibrateCommand::Speed(speed)).await?;
+    last_speed = Some(speed);
+  }
+  device.stop().await
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::util::async_manager;
+  use futures::stream;
+
+  #[test]
+  fn test_mapping_curves() {
+    assert_eq!(MappingCurve::Linear.apply(0.5), 0.5);
+    assert_eq!(MappingCurve::Linear.apply(2.0), 1.0);
+    assert_eq!(MappingCurve::Power(2.0).apply(0.5), 0.25);
+    let points = MappingCurve::Points(vec![(0.2, 0.0), (0.6, 1.0)]);
+    assert_eq!(points.apply(0.1), 0.0);
+    assert!((points.apply(0.4) - 0.5).abs() < 1e-9);
+    assert_eq!(points.apply(0.9), 1.0);
+  }
+
+  #[test]
+  fn test_motion_mapping() {
+    let mut mapper = MotionMapper::new(MotionMapping {
+      dead_zone: 0.1,
+      saturation: 1.1,
+      smoothing: 1,
+      curve: MappingCurve::Linear,
+    });
+    // Sitting still, gravity only.
+    assert_eq!(mapper.push(AccelerometerSample::new(0.0, 0.0, 1.0)), 0.0);
+    assert_eq!(mapper.push(AccelerometerSample::new(0.0, 0.0, 1.05)), 0.0);
+    assert!((mapper.push(AccelerometerSample::new(0.0, 0.0, 1.6)) - 0.5).abs() < 1e-9);
+    assert_eq!(mapper.push(AccelerometerSample::new(0.0, 3.0, 0.0)), 1.0);
+  }
+
+  #[test]
+  fn test_motion_smoothing() {
+    async_manager::block_on(async move {
+      let samples = stream::iter(vec![
+        AccelerometerSample::new(0.0, 0.0, 1.0),
+        AccelerometerSample::new(0.0, 0.0, 3.0),
+        AccelerometerSample::new(0.0, 0.0, 1.0),
+      ]);
+      let mapping = MotionMapping {
+        dead_zone: 0.0,
+        saturation: 2.0,
+        smoothing: 2,
+        curve: MappingCurve::Linear,
+      };
+      let speeds: Vec<f64> = motion_to_speed(samples, mapping).collect().await;
+      assert_eq!(speeds, vec![0.0, 0.5, 0.5]);
+    });
+  }
+}
@@ -0,0 +1,575 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Generated movement patterns for client devices.
+//!
+//! Sending the same LinearCmd over and over gives perfectly regular strokes,
+//! which feel mechanical. [StrokePatternGenerator] produces strokes with
+//! varying depth and speed instead, ramping up from short, slow strokes at the
+//! start. Steps can be sent to a device with [play_stroke_pattern], or used
+//! directly to build [LinearCommand]s.
+//!
+//! To keep several devices in sync, put their commands in [PlaybackTrack]s
+//! and play them together with [play_synchronized]. All tracks are timed off
+//! one [SharedClock], and every command is scheduled against the clock's start
+//! time rather than the previous command, so tracks don't drift apart however
+//! long they run.
+//!
+//! A track normally owns every feature on its device. To run separate
+//! patterns on different vibrators of the same device (i.e. one per motor on
+//! a two motor toy), make the tracks from [DeviceChannels] instead. Each one
+//! only sets its own vibrators, can be started and stopped on its own, and
+//! the speeds from all of them are combined into a single VibrateCmd.
+//!
+//! Some devices can store a vibration pattern and play it back on their own.
+//! [play_vibrate_pattern] uses that when it's there, so nothing has to be
+//! sent while the pattern runs, and falls back to streaming VibrateCmds for
+//! everything else.
+
+use super::{
+  device::{LinearCommand, RotateCommand, VibrateCommand},
+  mixer::{IntensityMixer, MixPolicy, MixerSource},
+  ButtplugClientDevice, ButtplugClientResult,
+};
+use futures::future;
+use futures_timer::Delay;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+/// Settings for [StrokePatternGenerator].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrokePatternOptions {
+  /// Position strokes start from, 0.0-1.0.
+  pub min_position: f64,
+  /// Position of the deepest possible stroke, 0.0-1.0.
+  pub max_position: f64,
+  /// Time for one movement (half of a full stroke) at full speed.
+  pub stroke_duration: Duration,
+  /// How much each stroke's depth can vary, as a fraction of the full range.
+  pub depth_jitter: f64,
+  /// How much each movement's duration can vary, as a fraction of
+  /// `stroke_duration`.
+  pub speed_jitter: f64,
+  /// Number of full strokes taken to ramp up to full depth and speed. 0 starts
+  /// at full depth and speed.
+  pub ramp_strokes: u32,
+  /// Seed for the jitter, so the same options always give the same pattern.
+  pub seed: u64,
+}
+
+impl Default for StrokePatternOptions {
+  fn default() -> Self {
+    Self {
+      min_position: 0.0,
+      max_position: 1.0,
+      stroke_duration: Duration::from_millis(500),
+      depth_jitter: 0.15,
+      speed_jitter: 0.2,
+      ramp_strokes: 5,
+      seed: 0x5eed,
+    }
+  }
+}
+
+/// A single movement, to `position` over `duration` milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrokeStep {
+  pub position: f64,
+  pub duration: u32,
+}
+
+impl From<StrokeStep> for LinearCommand {
+  fn from(step: StrokeStep) -> Self {
+    LinearCommand::Linear(step.duration, step.position)
+  }
+}
+
+// Where ramped strokes start, as a fraction of full depth and speed.
+const RAMP_START: f64 = 0.3;
+
+/// Endless iterator of [StrokeStep]s, alternating between the out and in
+/// parts of each stroke. Use [Iterator::take] to limit it.
+pub struct StrokePatternGenerator {
+  options: StrokePatternOptions,
+  rng_state: u64,
+  strokes: u32,
+  moving_in: bool,
+}
+
+impl StrokePatternGenerator {
+  pub fn new(options: StrokePatternOptions) -> Self {
+    Self {
+      options,
+      // xorshift gets stuck on 0.
+      rng_state: options.seed.max(1),
+      strokes: 0,
+      moving_in: true,
+    }
+  }
+
+  /// Random value between -1.0 and 1.0. Doesn't need to be good, only
+  /// uncorrelated enough that strokes don't look regular.
+  fn next_jitter(&mut self) -> f64 {
+    self.rng_state ^= self.rng_state << 13;
+    self.rng_state ^= self.rng_state >> 7;
+    self.rng_state ^= self.rng_state << 17;
+    (self.rng_state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+  }
+
+  fn ramp(&self) -> f64 {
+    if self.strokes >= self.options.ramp_strokes {
+      1.0
+    } else {
+      RAMP_START + (1.0 - RAMP_START) * self.strokes as f64 / self.options.ramp_strokes as f64
+    }
+  }
+}
+
+impl Iterator for StrokePatternGenerator {
+  type Item = StrokeStep;
+
+  fn next(&mut self) -> Option<StrokeStep> {
+    let ramp = self.ramp();
+    let range = self.options.max_position - self.options.min_position;
+    let position = if self.moving_in {
+      let depth = ramp * (1.0 - self.options.depth_jitter * self.next_jitter().abs());
+      self.options.min_position + range * depth
+    } else {
+      self.options.min_position
+    };
+    // Slower while ramping means longer movements.
+    let duration = self.options.stroke_duration.as_millis() as f64
+      * (1.0 + self.options.speed_jitter * self.next_jitter())
+      / ramp;
+    if !self.moving_in {
+      self.strokes += 1;
+    }
+    self.moving_in = !self.moving_in;
+    Some(StrokeStep {
+      position: position.clamp(0.0, 1.0),
+      duration: duration.max(1.0) as u32,
+    })
+  }
+}
+
+/// Sends each step to the device as a LinearCmd, sending the next one when
+/// the previous movement is due to finish. Returns when the steps run out, or
+/// on the first command error.
+pub async fn play_stroke_pattern(
+  device: &ButtplugClientDevice,
+  steps: impl IntoIterator<Item = StrokeStep>,
+) -> ButtplugClientResult {
+  let clock = SharedClock::new(Duration::from_millis(0));
+  let mut time = Duration::from_millis(0);
+  for step in steps {
+    clock.wait_until(time).await;
+    device.linear(step.into()).await?;
+    time += Duration::from_millis(step.duration as u64);
+  }
+  clock.wait_until(time).await;
+  Ok(())
+}
+
+/// Keyed command that stores a pattern on devices that can play patterns on
+/// their own. The value is `slot:interval_ms:speed,speed,...`.
+pub const STORE_PATTERN_KEY: &str = "StorePattern";
+/// Keyed command that plays the pattern stored in the slot given as its
+/// value. Slot 0 stops the pattern.
+pub const PLAY_PATTERN_KEY: &str = "PlayPattern";
+/// Slot [play_vibrate_pattern] stores patterns in. It's the last one on
+/// Lovense toys, to stay clear of patterns set up in the toy's own app.
+pub const PATTERN_ENGINE_SLOT: u32 = 10;
+
+/// Vibration speeds (0.0-1.0) stepped through at a fixed interval, looping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VibratePattern {
+  pub interval: Duration,
+  pub speeds: Vec<f64>,
+}
+
+impl VibratePattern {
+  pub fn new(interval: Duration, speeds: Vec<f64>) -> Self {
+    Self { interval, speeds }
+  }
+
+  /// Builds a track that streams the pattern to `device`, looping until
+  /// `duration` is up, then stops the device.
+  pub fn to_track(&self, device: Arc<ButtplugClientDevice>, duration: Duration) -> PlaybackTrack {
+    let mut track = PlaybackTrack::new(device);
+    if self.interval > Duration::from_millis(0) {
+      let mut time = Duration::from_millis(0);
+      for speed in self.speeds.iter().cycle() {
+        if time >= duration {
+          break;
+        }
+        track = track.command(time, TrackCommand::Vibrate(*speed));
+        time += self.interval;
+      }
+    }
+    track.command(duration, TrackCommand::Stop)
+  }
+
+  /// Stores the pattern in `slot` on the device, replacing whatever was
+  /// there. Devices limit how long patterns can be and how fast they can
+  /// step; Lovense toys take up to 50 speeds, at least 100ms apart.
+  pub async fn store(&self, device: &ButtplugClientDevice, slot: u32) -> ButtplugClientResult {
+    let speeds: Vec<String> = self.speeds.iter().map(|speed| speed.to_string()).collect();
+    let value = format!(
+      "{}:{}:{}",
+      slot,
+      self.interval.as_millis(),
+      speeds.join(",")
+    );
+    device.keyed_command(STORE_PATTERN_KEY, &value).await
+  }
+}
+
+/// True if `device` can store patterns and play them on its own.
+pub fn supports_stored_patterns(device: &ButtplugClientDevice) -> bool {
+  let keys = device.keyed_command_keys();
+  keys.iter().any(|key| key == STORE_PATTERN_KEY) && keys.iter().any(|key| key == PLAY_PATTERN_KEY)
+}
+
+/// Plays the pattern stored in `slot`, until slot 0 is played or the
+/// device's vibrators are sent anything else (including a stop).
+pub async fn play_stored_pattern(device: &ButtplugClientDevice, slot: u32) -> ButtplugClientResult {
+  device
+    .keyed_command(PLAY_PATTERN_KEY, &slot.to_string())
+    .await
+}
+
+/// Plays `pattern` on `device` for `duration`, then stops the device.
+///
+/// Devices that can store patterns get it stored in [PATTERN_ENGINE_SLOT]
+/// and play it from there. If the device has no pattern storage, or won't
+/// take this pattern (i.e. it's too long), each step is streamed as a
+/// VibrateCmd instead.
+pub async fn play_vibrate_pattern(
+  device: Arc<ButtplugClientDevice>,
+  pattern: &VibratePattern,
+  duration: Duration,
+) -> ButtplugClientResult {
+  if supports_stored_patterns(&device) {
+    match pattern.store(&device, PATTERN_ENGINE_SLOT).await {
+      Ok(()) => {
+        play_stored_pattern(&device, PATTERN_ENGINE_SLOT).await?;
+        Delay::new(duration).await;
+        return device.stop().await;
+      }
+      Err(err) => debug!(
+        "Device {} didn't take pattern, streaming it instead: {}",
+        device.name(),
+        err
+      ),
+    }
+  }
+  let track = pattern.to_track(device, duration);
+  play_synchronized(&[track], SharedClock::new(Duration::from_millis(0))).await
+}
+
+/// Start time shared by everything played against it.
+#[derive(Debug, Clone, Copy)]
+pub struct SharedClock {
+  start: Instant,
+}
+
+impl SharedClock {
+  /// Creates a clock starting `start_delay` from now. A short delay gives all
+  /// tracks time to get going before the first command is due.
+  pub fn new(start_delay: Duration) -> Self {
+    Self {
+      start: Instant::now() + start_delay,
+    }
+  }
+
+  pub fn start(&self) -> Instant {
+    self.start
+  }
+
+  /// Time since the start, or zero if the clock hasn't started yet.
+  pub fn elapsed(&self) -> Duration {
+    Instant::now().saturating_duration_since(self.start)
+  }
+
+  /// Waits until `time` after the start. Returns immediately if that's
+  /// already passed.
+  pub async fn wait_until(&self, time: Duration) {
+    let remaining = (self.start + time).saturating_duration_since(Instant::now());
+    if remaining > Duration::from_millis(0) {
+      Delay::new(remaining).await;
+    }
+  }
+}
+
+/// Command sent as part of a [PlaybackTrack]. Commands apply to all features
+/// of their type on the device, or only to the track's features if it came
+/// from [DeviceChannels].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackCommand {
+  /// Vibration speed, 0.0-1.0.
+  Vibrate(f64),
+  /// Duration in milliseconds and position, 0.0-1.0.
+  Linear(u32, f64),
+  /// Rotation speed, 0.0-1.0, and whether the rotation is clockwise.
+  Rotate(f64, bool),
+  /// Stops the device. For channel tracks, only stops the track's vibrators,
+  /// and leaves other channels running.
+  Stop,
+}
+
+/// Splits a device's vibrators between tracks, so each can run its own
+/// pattern. Speeds from every channel are combined per vibrator (the fastest
+/// wins, if two channels share a vibrator) and sent as one VibrateCmd.
+pub struct DeviceChannels {
+  device: Arc<ButtplugClientDevice>,
+  mixer: IntensityMixer,
+}
+
+impl DeviceChannels {
+  pub fn new(device: Arc<ButtplugClientDevice>) -> Self {
+    Self {
+      mixer: IntensityMixer::new(device.clone(), MixPolicy::Max),
+      device,
+    }
+  }
+
+  /// Creates a track that only drives the features at `features`. Vibrate
+  /// and Stop commands only touch those vibrators, and Linear and Rotate
+  /// commands only go to the linear and rotation features with those
+  /// indexes. Dropping the track stops its vibrators.
+  pub fn track(&self, features: &[u32]) -> PlaybackTrack {
+    let mut track = PlaybackTrack::new(self.device.clone());
+    track.channel = Some(TrackChannel {
+      features: features.to_vec(),
+      source: self.mixer.add_source(),
+    });
+    track
+  }
+
+  /// Speeds last sent to the device, combined from all channels.
+  pub fn mixed_speeds(&self) -> Option<Vec<f64>> {
+    self.mixer.mixed_speeds()
+  }
+}
+
+struct TrackChannel {
+  features: Vec<u32>,
+  source: MixerSource,
+}
+
+impl TrackChannel {
+  fn speeds(&self, speed: f64) -> Vec<f64> {
+    let count = self.features.iter().max().map_or(0, |max| *max as usize + 1);
+    let mut speeds = vec![0.0; count];
+    for feature in &self.features {
+      speeds[*feature as usize] = speed;
+    }
+    speeds
+  }
+
+  fn map<T: Copy>(&self, value: T) -> HashMap<u32, T> {
+    self.features.iter().map(|feature| (*feature, value)).collect()
+  }
+}
+
+/// Commands for one device, each with the time it should be sent at.
+pub struct PlaybackTrack {
+  device: Arc<ButtplugClientDevice>,
+  offset: Duration,
+  commands: Vec<(Duration, TrackCommand)>,
+  channel: Option<TrackChannel>,
+}
+
+impl PlaybackTrack {
+  pub fn new(device: Arc<ButtplugClientDevice>) -> Self {
+    Self {
+      device,
+      offset: Duration::from_millis(0),
+      commands: vec![],
+      channel: None,
+    }
+  }
+
+  /// Builds a linear track from stroke steps, sending each step when the
+  /// previous one is due to finish.
+  pub fn from_stroke_steps(
+    device: Arc<ButtplugClientDevice>,
+    steps: impl IntoIterator<Item = StrokeStep>,
+  ) -> Self {
+    let mut track = Self::new(device);
+    let mut time = Duration::from_millis(0);
+    for step in steps {
+      track = track.command(time, TrackCommand::Linear(step.duration, step.position));
+      time += Duration::from_millis(step.duration as u64);
+    }
+    track
+  }
+
+  /// Shifts every command in the track later by `offset`, i.e. to account for
+  /// a device that responds faster than the others.
+  pub fn offset(mut self, offset: Duration) -> Self {
+    self.offset = offset;
+    self
+  }
+
+  /// Adds a command to be sent `time` after the clock starts (plus the
+  /// track's offset). Commands can be added in any order.
+  pub fn command(mut self, time: Duration, command: TrackCommand) -> Self {
+    let index = self
+      .commands
+      .iter()
+      .position(|(existing, _)| *existing > time)
+      .unwrap_or(self.commands.len());
+    self.commands.insert(index, (time, command));
+    self
+  }
+
+  pub fn commands(&self) -> &[(Duration, TrackCommand)] {
+    &self.commands
+  }
+
+  /// Features the track is limited to, or None if it drives the whole device.
+  pub fn features(&self) -> Option<&[u32]> {
+    self.channel.as_ref().map(|channel| channel.features.as_slice())
+  }
+
+  /// Time the command at `index` is due, including the track offset.
+  pub(super) fn command_time(&self, index: usize) -> Duration {
+    self.offset + self.commands[index].0
+  }
+
+  pub(super) async fn send(&self, command: TrackCommand) -> ButtplugClientResult {
+    if let Some(channel) = &self.channel {
+      return match command {
+        TrackCommand::Vibrate(speed) => channel.source.set_vec(channel.speeds(speed)).await,
+        TrackCommand::Linear(duration, position) => {
+          self
+            .device
+            .linear(LinearCommand::LinearMap(channel.map((duration, position))))
+            .await
+        }
+        TrackCommand::Rotate(speed, clockwise) => {
+          self
+            .device
+            .rotate(RotateCommand::RotateMap(channel.map((speed, clockwise))))
+            .await
+        }
+        TrackCommand::Stop => channel.source.clear().await,
+      };
+    }
+    match command {
+      TrackCommand::Vibrate(speed) => self.device.vibrate(VibrateCommand::Speed(speed)).await,
+      TrackCommand::Linear(duration, position) => {
+        self
+          .device
+          .linear(LinearCommand::Linear(duration, position))
+          .await
+      }
+      TrackCommand::Rotate(speed, clockwise) => {
+        self
+          .device
+          .rotate(RotateCommand::Rotate(speed, clockwise))
+          .await
+      }
+      TrackCommand::Stop => self.device.stop().await,
+    }
+  }
+
+  async fn play(&self, clock: SharedClock) -> ButtplugClientResult {
+    for (index, (_, command)) in self.commands.iter().enumerate() {
+      clock.wait_until(self.command_time(index)).await;
+      self.send(*command).await?;
+    }
+    Ok(())
+  }
+}
+
+/// Plays all tracks against one clock, returning once every track is done.
+///
+/// Commands are sent at their scheduled time whether or not other tracks are
+/// keeping up, so a slow device falls behind on its own instead of dragging
+/// the rest with it. Returns the first error any track hits, after all tracks
+/// have finished.
+pub async fn play_synchronized(
+  tracks: &[PlaybackTrack],
+  clock: SharedClock,
+) -> ButtplugClientResult {
+  let results = future::join_all(tracks.iter().map(|track| track.play(clock))).await;
+  results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{SharedClock, StrokePatternGenerator, StrokePatternOptions, StrokeStep};
+  use crate::util::async_manager;
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn test_stroke_pattern_stays_in_range() {
+    let options = StrokePatternOptions {
+      min_position: 0.2,
+      max_position: 0.8,
+      ..Default::default()
+    };
+    for step in StrokePatternGenerator::new(options).take(200) {
+      assert!(step.position >= 0.2 && step.position <= 0.8, "{:?}", step);
+      assert!(step.duration > 0);
+    }
+  }
+
+  #[test]
+  fn test_stroke_pattern_alternates_and_ramps() {
+    let steps: Vec<StrokeStep> = StrokePatternGenerator::new(StrokePatternOptions::default())
+      .take(20)
+      .collect();
+    for pair in steps.chunks(2) {
+      assert!(pair[0].position > pair[1].position);
+      assert_eq!(pair[1].position, 0.0);
+    }
+    // The first stroke is ramped down, so it's shallower and slower than the
+    // shortest possible full strokes.
+    assert!(steps[0].position < 0.5);
+    assert!(steps[0].duration > 600);
+    assert!(steps[19].duration <= 600);
+  }
+
+  #[test]
+  fn test_stroke_pattern_seeded() {
+    let first: Vec<StrokeStep> = StrokePatternGenerator::new(StrokePatternOptions::default())
+      .take(10)
+      .collect();
+    let second: Vec<StrokeStep> = StrokePatternGenerator::new(StrokePatternOptions::default())
+      .take(10)
+      .collect();
+    assert_eq!(first, second);
+    let other_seed = StrokePatternOptions {
+      seed: 12345,
+      ..Default::default()
+    };
+    let third: Vec<StrokeStep> = StrokePatternGenerator::new(other_seed).take(10).collect();
+    assert_ne!(first, third);
+    // Jitter should actually vary strokes.
+    assert_ne!(first[0].position, first[2].position);
+  }
+
+  #[test]
+  fn test_shared_clock_waits_from_start() {
+    async_manager::block_on(async {
+      let clock = SharedClock::new(Duration::from_millis(20));
+      assert_eq!(clock.elapsed(), Duration::from_millis(0));
+      clock.wait_until(Duration::from_millis(30)).await;
+      assert!(Instant::now() >= clock.start() + Duration::from_millis(30));
+      // Times that have already passed don't wait.
+      let before = Instant::now();
+      clock.wait_until(Duration::from_millis(0)).await;
+      assert!(before.elapsed() < Duration::from_millis(10));
+    });
+  }
+}
@@ -0,0 +1,398 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Declarative scenes, for simple interactive experiences without an app
+//! around them.
+//!
+//! A [Scene] is a set of [SceneStage]s. Each stage is a list of timed
+//! commands (like a [PlaybackTrack]), and transitions to other stages that
+//! happen after some time, or when a [SceneEvent] comes in. Events are
+//! whatever the application wants them to be, usually button presses or
+//! sensor readings (see [sensor][super::sensor]).
+//!
+//! ```no_run
+//! # use buttplug::client::{scene::*, patterns::TrackCommand, ButtplugClientDevice};
+//! # use futures::stream;
+//! # use std::{sync::Arc, time::Duration};
+//! # async fn example(device: Arc<ButtplugClientDevice>) {
+//! let scene = Scene::new()
+//!   .stage(
+//!     SceneStage::new("warmup")
+//!       .command(Duration::from_secs(0), TrackCommand::Vibrate(0.2))
+//!       .command(Duration::from_secs(5), TrackCommand::Vibrate(0.4))
+//!       .on(SceneTrigger::Button("next".to_owned()), SceneTarget::stage("peak"))
+//!       .on(SceneTrigger::After(Duration::from_secs(60)), SceneTarget::stage("peak")),
+//!   )
+//!   .stage(
+//!     SceneStage::new("peak")
+//!       .command(Duration::from_secs(0), TrackCommand::Vibrate(1.0))
+//!       .on(SceneTrigger::After(Duration::from_secs(10)), SceneTarget::End),
+//!   );
+//! scene.play(device, stream::pending()).await.unwrap();
+//! # }
+//! ```
+
+// SceneError wraps ButtplugClientError, which clippy only complains about for
+// the non-async validation functions.
+#![allow(clippy::result_large_err)]
+
+use super::{
+  patterns::{PlaybackTrack, SharedClock, StrokeStep, TrackCommand},
+  ButtplugClientDevice, ButtplugClientError,
+};
+use futures::{future, FutureExt, Stream, StreamExt};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SceneError {
+  #[error("Scene has no stages.")]
+  NoStages,
+  #[error("Scene has more than one stage named {0}.")]
+  DuplicateStage(String),
+  #[error("Stage {0} transitions to stage {1}, which doesn't exist.")]
+  UnknownStage(String, String),
+  #[error(transparent)]
+  Device(#[from] ButtplugClientError),
+}
+
+/// Something that happened outside of the scene, that stages can transition
+/// on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneEvent {
+  /// A named button was pressed.
+  Button(String),
+  /// A named sensor has a new reading.
+  Sensor(String, f64),
+}
+
+/// What makes a stage move on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneTrigger {
+  /// Time since the stage started.
+  After(Duration),
+  /// The named button was pressed.
+  Button(String),
+  /// The named sensor read at or above the value.
+  SensorAbove(String, f64),
+  /// The named sensor read at or below the value.
+  SensorBelow(String, f64),
+}
+
+impl SceneTrigger {
+  fn matches(&self, event: &SceneEvent) -> bool {
+    match (self, event) {
+      (SceneTrigger::Button(name), SceneEvent::Button(pressed)) => name == pressed,
+      (SceneTrigger::SensorAbove(name, threshold), SceneEvent::Sensor(sensor, value)) => {
+        name == sensor && value >= threshold
+      }
+      (SceneTrigger::SensorBelow(name, threshold), SceneEvent::Sensor(sensor, value)) => {
+        name == sensor && value <= threshold
+      }
+      _ => false,
+    }
+  }
+}
+
+/// Where a transition goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneTarget {
+  Stage(String),
+  /// Ends the scene, stopping the device.
+  End,
+}
+
+impl SceneTarget {
+  pub fn stage(name: &str) -> Self {
+    SceneTarget::Stage(name.to_owned())
+  }
+}
+
+/// One part of a [Scene].
+#[derive(Debug, Clone)]
+pub struct SceneStage {
+  name: String,
+  commands: Vec<(Duration, TrackCommand)>,
+  repeat: Option<Duration>,
+  transitions: Vec<(SceneTrigger, SceneTarget)>,
+}
+
+impl SceneStage {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      commands: vec![],
+      repeat: None,
+      transitions: vec![],
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Adds a command sent `time` after the stage starts.
+  pub fn command(mut self, time: Duration, command: TrackCommand) -> Self {
+    self.commands.push((time, command));
+    self
+  }
+
+  /// Adds stroke steps (i.e. from a
+  /// [StrokePatternGenerator][super::patterns::StrokePatternGenerator]),
+  /// starting when the stage starts.
+  pub fn strokes(mut self, steps: impl IntoIterator<Item = StrokeStep>) -> Self {
+    let mut time = Duration::from_millis(0);
+    for step in steps {
+      self
+        .commands
+        .push((time, TrackCommand::Linear(step.duration, step.position)));
+      time += Duration::from_millis(step.duration as u64);
+    }
+    self
+  }
+
+  /// Plays the stage's commands again every `period` until a transition
+  /// happens.
+  pub fn repeat_every(mut self, period: Duration) -> Self {
+    self.repeat = Some(period);
+    self
+  }
+
+  /// Adds a transition. If more than one transition could happen, the first
+  /// one added wins.
+  pub fn on(mut self, trigger: SceneTrigger, target: SceneTarget) -> Self {
+    self.transitions.push((trigger, target));
+    self
+  }
+
+  fn timed_transition(&self) -> Option<(Duration, &SceneTarget)> {
+    let mut earliest: Option<(Duration, &SceneTarget)> = None;
+    for (trigger, target) in &self.transitions {
+      if let SceneTrigger::After(time) = trigger {
+        match earliest {
+          Some((earliest_time, _)) if earliest_time <= *time => (),
+          _ => earliest = Some((*time, target)),
+        }
+      }
+    }
+    earliest
+  }
+
+  fn has_event_transitions(&self) -> bool {
+    self
+      .transitions
+      .iter()
+      .any(|(trigger, _)| !matches!(trigger, SceneTrigger::After(_)))
+  }
+
+  fn event_transition(&self, event: &SceneEvent) -> Option<&SceneTarget> {
+    self
+      .transitions
+      .iter()
+      .find(|(trigger, _)| trigger.matches(event))
+      .map(|(_, target)| target)
+  }
+}
+
+/// What woke up the scene loop.
+enum SceneWake<'a> {
+  Command,
+  Transition(&'a SceneTarget),
+  Event(Option<SceneEvent>),
+}
+
+/// Stages, played starting from the first one added.
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+  stages: Vec<SceneStage>,
+}
+
+impl Scene {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn stage(mut self, stage: SceneStage) -> Self {
+    self.stages.push(stage);
+    self
+  }
+
+  pub fn stages(&self) -> &[SceneStage] {
+    &self.stages
+  }
+
+  /// Checks that the scene has stages, their names are unique, and every
+  /// transition goes somewhere.
+  pub fn validate(&self) -> Result<(), SceneError> {
+    self.stage_indexes().map(|_| ())
+  }
+
+  fn stage_indexes(&self) -> Result<HashMap<&str, usize>, SceneError> {
+    if self.stages.is_empty() {
+      return Err(SceneError::NoStages);
+    }
+    let mut indexes = HashMap::new();
+    for (index, stage) in self.stages.iter().enumerate() {
+      if indexes.insert(stage.name.as_str(), index).is_some() {
+        return Err(SceneError::DuplicateStage(stage.name.clone()));
+      }
+    }
+    for stage in &self.stages {
+      for (_, target) in &stage.transitions {
+        if let SceneTarget::Stage(name) = target {
+          if !indexes.contains_key(name.as_str()) {
+            return Err(SceneError::UnknownStage(stage.name.clone(), name.clone()));
+          }
+        }
+      }
+    }
+    Ok(indexes)
+  }
+
+  /// Plays the scene on `device`, reacting to `events`, until a transition
+  /// ends it or the current stage has nothing left to do (no commands left,
+  /// and no transitions that could still happen). The device is stopped at
+  /// the end. Returns early on the first command error.
+  pub async fn play(
+    &self,
+    device: Arc<ButtplugClientDevice>,
+    mut events: impl Stream<Item = SceneEvent> + Unpin,
+  ) -> Result<(), SceneError> {
+    let indexes = self.stage_indexes()?;
+    let mut stage_index = 0;
+    let mut events_done = false;
+    'stages: loop {
+      let stage = &self.stages[stage_index];
+      debug!("Scene starting stage {}", stage.name);
+      let mut track = PlaybackTrack::new(device.clone());
+      for (time, command) in &stage.commands {
+        track = track.command(*time, *command);
+      }
+      let clock = SharedClock::new(Duration::from_millis(0));
+      let mut next_command = 0;
+      let mut repeat_offset = Duration::from_millis(0);
+      loop {
+        if let Some(period) = stage.repeat {
+          if next_command == track.commands().len() && !track.commands().is_empty() {
+            repeat_offset += period;
+            next_command = 0;
+          }
+        }
+        let command_time = if next_command < track.commands().len() {
+          Some(repeat_offset + track.command_time(next_command))
+        } else {
+          None
+        };
+        // Commands due at the same time as a transition go out first.
+        let timer = match (command_time, stage.timed_transition()) {
+          (Some(time), Some((transition_time, target))) if transition_time < time => {
+            Some((transition_time, Some(target)))
+          }
+          (Some(time), _) => Some((time, None)),
+          (None, Some((transition_time, target))) => Some((transition_time, Some(target))),
+          (None, None) => None,
+        };
+        let wait_for_events = !events_done && stage.has_event_transitions();
+        if timer.is_none() && !wait_for_events {
+          break 'stages;
+        }
+        let timer_fut = async {
+          match timer {
+            Some((time, _)) => clock.wait_until(time).await,
+            None => future::pending().await,
+          }
+        };
+        let event_fut = async {
+          if wait_for_events {
+            events.next().await
+          } else {
+            future::pending().await
+          }
+        };
+        let wake = select! {
+          _ = timer_fut.fuse() => match timer {
+            Some((_, Some(target))) => SceneWake::Transition(target),
+            _ => SceneWake::Command,
+          },
+          event = event_fut.fuse() => SceneWake::Event(event),
+        };
+        let target = match wake {
+          SceneWake::Command => {
+            track.send(track.commands()[next_command].1).await?;
+            next_command += 1;
+            continue;
+          }
+          SceneWake::Transition(target) => target,
+          SceneWake::Event(None) => {
+            events_done = true;
+            continue;
+          }
+          SceneWake::Event(Some(event)) => match stage.event_transition(&event) {
+            Some(target) => target,
+            None => continue,
+          },
+        };
+        match target {
+          SceneTarget::Stage(name) => {
+            stage_index = indexes[name.as_str()];
+            continue 'stages;
+          }
+          SceneTarget::End => break 'stages,
+        }
+      }
+    }
+    debug!("Scene finished, stopping device.");
+    device.stop().await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_scene_validation() {
+    assert!(matches!(Scene::new().validate(), Err(SceneError::NoStages)));
+    let duplicate = Scene::new()
+      .stage(SceneStage::new("a"))
+      .stage(SceneStage::new("a"));
+    assert!(matches!(
+      duplicate.validate(),
+      Err(SceneError::DuplicateStage(name)) if name == "a"
+    ));
+    let unknown = Scene::new().stage(
+      SceneStage::new("a").on(SceneTrigger::Button("next".to_owned()), SceneTarget::stage("b")),
+    );
+    assert!(matches!(
+      unknown.validate(),
+      Err(SceneError::UnknownStage(from, to)) if from == "a" && to == "b"
+    ));
+    let valid = Scene::new()
+      .stage(
+        SceneStage::new("a").on(SceneTrigger::Button("next".to_owned()), SceneTarget::stage("b")),
+      )
+      .stage(
+        SceneStage::new("b").on(SceneTrigger::After(Duration::from_secs(1)), SceneTarget::End),
+      );
+    assert!(valid.validate().is_ok());
+  }
+
+  #[test]
+  fn test_scene_triggers() {
+    let button = SceneTrigger::Button("next".to_owned());
+    assert!(button.matches(&SceneEvent::Button("next".to_owned())));
+    assert!(!button.matches(&SceneEvent::Button("back".to_owned())));
+    let above = SceneTrigger::SensorAbove("pressure".to_owned(), 0.5);
+    assert!(above.matches(&SceneEvent::Sensor("pressure".to_owned(), 0.5)));
+    assert!(!above.matches(&SceneEvent::Sensor("pressure".to_owned(), 0.4)));
+    assert!(!above.matches(&SceneEvent::Sensor("motion".to_owned(), 0.9)));
+    let below = SceneTrigger::SensorBelow("pressure".to_owned(), 0.5);
+    assert!(below.matches(&SceneEvent::Sensor("pressure".to_owned(), 0.1)));
+    assert!(!below.matches(&SceneEvent::Button("pressure".to_owned())));
+  }
+}
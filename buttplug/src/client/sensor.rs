@@ -0,0 +1,280 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Turning raw sensor readings into something an application can react to.
+//!
+//! Pressure sensors, accelerometers and the like send a steady stream of noisy
+//! readings. What applications actually want is usually "the value, minus the
+//! jitter", "tell me when it goes over this", or "tell me when it peaks". The
+//! filters here each work on single readings (for applications that already
+//! have their own loop), and have matching stream functions that work on a
+//! stream of readings.
+//!
+//! Readings come in as raw bytes, so decoding them is up to the application:
+//!
+//! ```no_run
+//! # use buttplug::client::{sensor, ButtplugClientDevice};
+//! # use buttplug::device::Endpoint;
+//! # use futures::StreamExt;
+//! # async fn example(device: &ButtplugClientDevice) {
+//! device.raw_subscribe(Endpoint::RxPressure).await.unwrap();
+//! let pressure = sensor::raw_readings(device, Endpoint::RxPressure)
+//!   .filter_map(|data| async move { data.first().map(|value| *value as f64 / 255.0) });
+//! let mut crossings = Box::pin(sensor::threshold_crossings(
+//!   sensor::moving_average(pressure, 5),
+//!   0.5,
+//!   0.1,
+//! ));
+//! while let Some(crossing) = crossings.next().await {
+//!   println!("{:?}", crossing);
+//! }
+//! # }
+//! ```
+
+use super::{ButtplugClientDevice, ButtplugClientDeviceEvent};
+use crate::{core::messages::ButtplugCurrentSpecServerMessage, device::Endpoint};
+use futures::{future, Stream, StreamExt};
+use std::collections::VecDeque;
+
+/// Data from RawReading events the device sends for `endpoint`. The endpoint
+/// needs to be subscribed to with
+/// [ButtplugClientDevice::raw_subscribe] for readings to show up. Ends when
+/// the device disconnects.
+pub fn raw_readings(
+  device: &ButtplugClientDevice,
+  endpoint: Endpoint,
+) -> impl Stream<Item = Vec<u8>> {
+  device
+    .event_stream()
+    .take_while(|event| {
+      future::ready(!matches!(
+        event,
+        ButtplugClientDeviceEvent::DeviceRemoved | ButtplugClientDeviceEvent::ClientDisconnect
+      ))
+    })
+    .filter_map(move |event| {
+      future::ready(match event {
+        ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::RawReading(
+          reading,
+        )) if reading.endpoint() == endpoint => Some(reading.data().clone()),
+        _ => None,
+      })
+    })
+}
+
+/// Average of the last `window` readings.
+pub struct MovingAverage {
+  window: usize,
+  readings: VecDeque<f64>,
+  sum: f64,
+}
+
+impl MovingAverage {
+  /// `window` is the number of readings averaged. A window of 0 is treated as
+  /// 1.
+  pub fn new(window: usize) -> Self {
+    let window = window.max(1);
+    Self {
+      window,
+      readings: VecDeque::with_capacity(window),
+      sum: 0.0,
+    }
+  }
+
+  /// Adds a reading, returning the new average. Until the window fills, this
+  /// is the average of everything seen so far.
+  pub fn push(&mut self, reading: f64) -> f64 {
+    if self.readings.len() == self.window {
+      if let Some(oldest) = self.readings.pop_front() {
+        self.sum -= oldest;
+      }
+    }
+    self.readings.push_back(reading);
+    self.sum += reading;
+    self.sum / self.readings.len() as f64
+  }
+}
+
+/// Smooths `readings` with a [MovingAverage].
+pub fn moving_average<S>(readings: S, window: usize) -> impl Stream<Item = f64>
+where
+  S: Stream<Item = f64>,
+{
+  let mut average = MovingAverage::new(window);
+  readings.map(move |reading| average.push(reading))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdCrossing {
+  /// Reading went up to or over the threshold. Contains the reading.
+  Rising(f64),
+  /// Reading dropped back below the threshold (minus hysteresis). Contains
+  /// the reading.
+  Falling(f64),
+}
+
+/// Reports when readings cross a threshold.
+///
+/// Readings have to drop `hysteresis` below the threshold before they count
+/// as having fallen back under it, so noise around the threshold doesn't
+/// cause a flood of crossings.
+pub struct ThresholdDetector {
+  threshold: f64,
+  hysteresis: f64,
+  above: bool,
+}
+
+impl ThresholdDetector {
+  pub fn new(threshold: f64, hysteresis: f64) -> Self {
+    Self {
+      threshold,
+      hysteresis: hysteresis.abs(),
+      above: false,
+    }
+  }
+
+  /// Adds a reading, returning a crossing if this reading caused one.
+  pub fn push(&mut self, reading: f64) -> Option<ThresholdCrossing> {
+    if !self.above && reading >= self.threshold {
+      self.above = true;
+      Some(ThresholdCrossing::Rising(reading))
+    } else if self.above && reading < self.threshold - self.hysteresis {
+      self.above = false;
+      Some(ThresholdCrossing::Falling(reading))
+    } else {
+      None
+    }
+  }
+}
+
+/// Crossings of `threshold` in `readings`. See [ThresholdDetector].
+pub fn threshold_crossings<S>(
+  readings: S,
+  threshold: f64,
+  hysteresis: f64,
+) -> impl Stream<Item = ThresholdCrossing>
+where
+  S: Stream<Item = f64>,
+{
+  let mut detector = ThresholdDetector::new(threshold, hysteresis);
+  readings.filter_map(move |reading| future::ready(detector.push(reading)))
+}
+
+/// Finds peaks in readings.
+///
+/// A peak is reported once readings have dropped at least `prominence` below
+/// the highest point since the last trough, so it comes out a little after the
+/// actual peak. Wobbles smaller than `prominence` are ignored.
+pub struct PeakDetector {
+  prominence: f64,
+  // Highest reading since the last trough, while looking for a peak.
+  highest: Option<f64>,
+  // Lowest reading since the last peak, while looking for a trough.
+  lowest: Option<f64>,
+}
+
+impl PeakDetector {
+  pub fn new(prominence: f64) -> Self {
+    Self {
+      prominence: prominence.abs(),
+      highest: None,
+      lowest: None,
+    }
+  }
+
+  /// Adds a reading, returning the peak value if this reading confirmed one.
+  pub fn push(&mut self, reading: f64) -> Option<f64> {
+    match (self.highest, self.lowest) {
+      (Some(highest), _) => {
+        if reading > highest {
+          self.highest = Some(reading);
+        } else if highest - reading >= self.prominence {
+          self.highest = None;
+          self.lowest = Some(reading);
+          return Some(highest);
+        }
+      }
+      (None, Some(lowest)) => {
+        if reading < lowest {
+          self.lowest = Some(reading);
+        } else if reading - lowest >= self.prominence {
+          self.lowest = None;
+          self.highest = Some(reading);
+        }
+      }
+      // First reading, start out looking for a peak.
+      (None, None) => self.highest = Some(reading),
+    }
+    None
+  }
+}
+
+/// Peak values in `readings`. See [PeakDetector].
+pub fn peaks<S>(readings: S, prominence: f64) -> impl Stream<Item = f64>
+where
+  S: Stream<Item = f64>,
+{
+  let mut detector = PeakDetector::new(prominence);
+  readings.filter_map(move |reading| future::ready(detector.push(reading)))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::util::async_manager;
+  use futures::stream;
+
+  #[test]
+  fn test_moving_average() {
+    let mut average = MovingAverage::new(3);
+    assert_eq!(average.push(3.0), 3.0);
+    assert_eq!(average.push(6.0), 4.5);
+    assert_eq!(average.push(9.0), 6.0);
+    assert_eq!(average.push(0.0), 5.0);
+    let mut single = MovingAverage::new(0);
+    assert_eq!(single.push(1.0), 1.0);
+    assert_eq!(single.push(2.0), 2.0);
+  }
+
+  #[test]
+  fn test_threshold_crossings() {
+    async_manager::block_on(async move {
+      let readings = stream::iter(vec![0.1, 0.5, 0.45, 0.55, 0.3, 0.6]);
+      let crossings: Vec<ThresholdCrossing> =
+        threshold_crossings(readings, 0.5, 0.1).collect().await;
+      // 0.45 and 0.55 are within the hysteresis band, so don't count.
+      assert_eq!(
+        crossings,
+        vec![
+          ThresholdCrossing::Rising(0.5),
+          ThresholdCrossing::Falling(0.3),
+          ThresholdCrossing::Rising(0.6),
+        ]
+      );
+    });
+  }
+
+  #[test]
+  fn test_peaks() {
+    async_manager::block_on(async move {
+      let readings = stream::iter(vec![0.0, 0.4, 0.8, 0.75, 0.5, 0.55, 0.2, 0.9, 0.1]);
+      let found: Vec<f64> = peaks(readings, 0.25).collect().await;
+      // The bump to 0.55 isn't prominent enough to count as its own peak.
+      assert_eq!(found, vec![0.8, 0.9]);
+    });
+  }
+
+  #[test]
+  fn test_smoothed_peaks() {
+    async_manager::block_on(async move {
+      // Noisy single sample spike gets smoothed away.
+      let readings = stream::iter(vec![0.0, 0.0, 0.9, 0.0, 0.0, 0.0]);
+      let found: Vec<f64> = peaks(moving_average(readings, 3), 0.5).collect().await;
+      assert!(found.is_empty());
+    });
+  }
+}
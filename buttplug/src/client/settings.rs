@@ -0,0 +1,330 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Client settings saved between runs.
+//!
+//! Most applications end up wanting to remember the same few things about
+//! devices (what the user renamed them to, how strong they should run), and
+//! each one was writing its own config file for it. [ClientSettingsStore]
+//! keeps those in a JSON file, by default in the platform's config directory,
+//! along with anything else an application wants to save next to them:
+//! arbitrary values can be stored both per device and for the whole
+//! application, as long as they serialize with serde.
+//!
+//! Devices are looked up by name, as that's the only thing about a device
+//! that stays the same across connections. Two of the same toy will share
+//! settings.
+
+use super::{ButtplugClientDevice, VibrateCommand};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+  collections::BTreeMap,
+  fs,
+  io,
+  path::{Path, PathBuf},
+  sync::RwLock,
+};
+use thiserror::Error;
+
+/// Name of the settings file within an application's config directory.
+pub const CLIENT_SETTINGS_FILE_NAME: &str = "buttplug-client-settings.json";
+
+#[derive(Debug, Error)]
+pub enum ClientSettingsError {
+  #[error("Cannot read or write settings file: {0}")]
+  Io(#[from] io::Error),
+  #[error("Cannot parse settings: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("Cannot find a config directory for this platform.")]
+  NoConfigDirectory,
+}
+
+/// Settings for one device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientDeviceSettings {
+  /// Name to show instead of the one the server gave the device.
+  #[serde(rename = "display-name", default, skip_serializing_if = "Option::is_none")]
+  pub display_name: Option<String>,
+  /// Multiplier for vibration speeds, 0.0-1.0.
+  #[serde(rename = "intensity-scale", default, skip_serializing_if = "Option::is_none")]
+  pub intensity_scale: Option<f64>,
+  // Values applications have added, by key.
+  #[serde(flatten)]
+  extra: Map<String, Value>,
+}
+
+impl ClientDeviceSettings {
+  /// The display name if one is set, otherwise the device's own name.
+  pub fn display_name_for(&self, device: &ButtplugClientDevice) -> String {
+    self
+      .display_name
+      .clone()
+      .unwrap_or_else(|| device.name())
+  }
+
+  /// Scales the speeds in `command` by the intensity scale, if set.
+  pub fn scale_vibrate(&self, command: VibrateCommand) -> VibrateCommand {
+    let scale = match self.intensity_scale {
+      Some(scale) => scale.clamp(0.0, 1.0),
+      None => return command,
+    };
+    match command {
+      VibrateCommand::Speed(speed) => VibrateCommand::Speed(speed * scale),
+      VibrateCommand::SpeedVec(speeds) => {
+        VibrateCommand::SpeedVec(speeds.into_iter().map(|speed| speed * scale).collect())
+      }
+      VibrateCommand::SpeedMap(speeds) => VibrateCommand::SpeedMap(
+        speeds
+          .into_iter()
+          .map(|(index, speed)| (index, speed * scale))
+          .collect(),
+      ),
+    }
+  }
+
+  /// Application value stored under `key`, or None if it's missing or
+  /// doesn't deserialize as `T`.
+  pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+    get_value(&self.extra, key)
+  }
+
+  pub fn set<T: Serialize>(&mut self, key: &str, value: T) -> Result<(), ClientSettingsError> {
+    self.extra.insert(key.to_owned(), serde_json::to_value(value)?);
+    Ok(())
+  }
+
+  pub fn remove(&mut self, key: &str) {
+    self.extra.remove(key);
+  }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ClientSettings {
+  #[serde(default)]
+  devices: BTreeMap<String, ClientDeviceSettings>,
+  #[serde(default)]
+  application: Map<String, Value>,
+}
+
+fn get_value<T: DeserializeOwned>(values: &Map<String, Value>, key: &str) -> Option<T> {
+  values
+    .get(key)
+    .and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
+/// Config directory for the current user, i.e. `%APPDATA%` on Windows,
+/// `~/Library/Application Support` on macOS, and `$XDG_CONFIG_HOME` or
+/// `~/.config` elsewhere.
+pub fn platform_config_dir() -> Option<PathBuf> {
+  let env_dir = |name: &str| {
+    std::env::var_os(name)
+      .filter(|dir| !dir.is_empty())
+      .map(PathBuf::from)
+  };
+  if cfg!(target_os = "windows") {
+    env_dir("APPDATA")
+  } else if cfg!(target_os = "macos") {
+    env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+  } else {
+    env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".config")))
+  }
+}
+
+/// Settings loaded from, and saved to, a JSON file. Changes are only written
+/// out on [save](Self::save).
+#[derive(Debug)]
+pub struct ClientSettingsStore {
+  path: Option<PathBuf>,
+  settings: RwLock<ClientSettings>,
+}
+
+impl ClientSettingsStore {
+  /// Loads settings from `path`. A missing file gives empty settings, and is
+  /// created on the first save.
+  pub fn open(path: impl Into<PathBuf>) -> Result<Self, ClientSettingsError> {
+    let path = path.into();
+    let settings = match fs::read_to_string(&path) {
+      Ok(contents) => serde_json::from_str(&contents)?,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => ClientSettings::default(),
+      Err(err) => return Err(err.into()),
+    };
+    Ok(Self {
+      path: Some(path),
+      settings: RwLock::new(settings),
+    })
+  }
+
+  /// Loads settings for `application_name` from the platform config
+  /// directory, see [platform_config_dir].
+  pub fn open_default(application_name: &str) -> Result<Self, ClientSettingsError> {
+    let dir = platform_config_dir().ok_or(ClientSettingsError::NoConfigDirectory)?;
+    Self::open(dir.join(application_name).join(CLIENT_SETTINGS_FILE_NAME))
+  }
+
+  /// Settings that are never saved, i.e. for tests or when the config
+  /// directory can't be written to.
+  pub fn in_memory() -> Self {
+    Self {
+      path: None,
+      settings: RwLock::new(ClientSettings::default()),
+    }
+  }
+
+  /// File the settings are saved to, or None for in memory settings.
+  pub fn path(&self) -> Option<&Path> {
+    self.path.as_deref()
+  }
+
+  /// Settings for `device`, or defaults if none have been stored.
+  pub fn device(&self, device: &ButtplugClientDevice) -> ClientDeviceSettings {
+    self.device_by_name(&device.name())
+  }
+
+  pub fn device_by_name(&self, device_name: &str) -> ClientDeviceSettings {
+    self
+      .settings
+      .read()
+      .unwrap()
+      .devices
+      .get(device_name)
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  /// Changes the settings for `device`, starting from the stored settings or
+  /// defaults.
+  pub fn update_device<F>(&self, device: &ButtplugClientDevice, f: F)
+  where
+    F: FnOnce(&mut ClientDeviceSettings),
+  {
+    self.update_device_by_name(&device.name(), f)
+  }
+
+  pub fn update_device_by_name<F>(&self, device_name: &str, f: F)
+  where
+    F: FnOnce(&mut ClientDeviceSettings),
+  {
+    let mut settings = self.settings.write().unwrap();
+    f(settings.devices.entry(device_name.to_owned()).or_default());
+  }
+
+  pub fn remove_device(&self, device_name: &str) {
+    self.settings.write().unwrap().devices.remove(device_name);
+  }
+
+  /// Names of all devices with stored settings.
+  pub fn device_names(&self) -> Vec<String> {
+    self.settings.read().unwrap().devices.keys().cloned().collect()
+  }
+
+  /// Application wide value stored under `key`, or None if it's missing or
+  /// doesn't deserialize as `T`.
+  pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+    get_value(&self.settings.read().unwrap().application, key)
+  }
+
+  pub fn set<T: Serialize>(&self, key: &str, value: T) -> Result<(), ClientSettingsError> {
+    let value = serde_json::to_value(value)?;
+    self
+      .settings
+      .write()
+      .unwrap()
+      .application
+      .insert(key.to_owned(), value);
+    Ok(())
+  }
+
+  pub fn remove(&self, key: &str) {
+    self.settings.write().unwrap().application.remove(key);
+  }
+
+  /// Writes the settings to the file, creating its directory if needed. Does
+  /// nothing for in memory settings.
+  ///
+  /// The file is written to a temporary file first and then moved into
+  /// place, so a crash part way through doesn't lose the old settings.
+  pub fn save(&self) -> Result<(), ClientSettingsError> {
+    let path = match &self.path {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    let contents = serde_json::to_string_pretty(&*self.settings.read().unwrap())?;
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::collections::HashMap;
+
+  #[test]
+  fn test_client_settings_round_trip() {
+    let dir = std::env::temp_dir().join(format!("buttplug-settings-test-{}", std::process::id()));
+    let path = dir.join("nested").join(CLIENT_SETTINGS_FILE_NAME);
+    let store = ClientSettingsStore::open(&path).unwrap();
+    assert_eq!(store.device_by_name("Massage Demo"), ClientDeviceSettings::default());
+    store.update_device_by_name("Massage Demo", |settings| {
+      settings.display_name = Some("Bedside".to_owned());
+      settings.intensity_scale = Some(0.5);
+      settings.set("favorite-pattern", "wave").unwrap();
+    });
+    store.set("last-server", "ws://127.0.0.1:12345").unwrap();
+    store.save().unwrap();
+
+    let store = ClientSettingsStore::open(&path).unwrap();
+    assert_eq!(store.device_names(), vec!["Massage Demo".to_owned()]);
+    let settings = store.device_by_name("Massage Demo");
+    assert_eq!(settings.display_name.as_deref(), Some("Bedside"));
+    assert_eq!(settings.get::<String>("favorite-pattern"), Some("wave".to_owned()));
+    assert_eq!(settings.get::<u32>("favorite-pattern"), None);
+    assert_eq!(
+      store.get::<String>("last-server"),
+      Some("ws://127.0.0.1:12345".to_owned())
+    );
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_client_settings_scale_vibrate() {
+    let mut settings = ClientDeviceSettings::default();
+    assert_eq!(
+      settings.scale_vibrate(VibrateCommand::Speed(0.8)),
+      VibrateCommand::Speed(0.8)
+    );
+    settings.intensity_scale = Some(0.5);
+    assert_eq!(
+      settings.scale_vibrate(VibrateCommand::SpeedVec(vec![1.0, 0.5])),
+      VibrateCommand::SpeedVec(vec![0.5, 0.25])
+    );
+    let mut speeds = HashMap::new();
+    speeds.insert(1, 0.8);
+    let mut scaled = HashMap::new();
+    scaled.insert(1, 0.4);
+    assert_eq!(
+      settings.scale_vibrate(VibrateCommand::SpeedMap(speeds)),
+      VibrateCommand::SpeedMap(scaled)
+    );
+  }
+
+  #[test]
+  fn test_client_settings_in_memory() {
+    let store = ClientSettingsStore::in_memory();
+    assert!(store.path().is_none());
+    store.update_device_by_name("Test", |settings| settings.intensity_scale = Some(0.2));
+    store.save().unwrap();
+    store.remove_device("Test");
+    assert!(store.device_names().is_empty());
+  }
+}
@@ -0,0 +1,275 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Blocking wrappers around [ButtplugClient] and [ButtplugClientDevice].
+//!
+//! Some environments (scripting language bindings, game engine plugins, etc)
+//! can't host an async runtime, or would rather not. [ButtplugClientSync] owns
+//! its own tokio runtime, and every call blocks the calling thread until the
+//! underlying client future resolves. Events are pulled with
+//! [ButtplugClientSync::next_event] instead of being streamed.
+//!
+//! None of the methods here should be called from inside an async context, as
+//! blocking on the runtime from one of its own tasks will panic.
+
+// Errors are the same as the async API, which clippy only complains about
+// here since these aren't futures.
+#![allow(clippy::result_large_err)]
+
+use super::{
+  device::ButtplugClientDevice, ButtplugClient, ButtplugClientError, ButtplugClientEvent,
+  ButtplugClientResult, LinearCommand, RotateCommand, VibrateCommand,
+};
+use crate::{
+  connector::ButtplugConnector,
+  core::messages::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+  device::Endpoint,
+};
+use futures::FutureExt;
+use futures_timer::Delay;
+use std::{
+  io,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{
+  runtime::{Handle, Runtime},
+  sync::broadcast::{self, error::RecvError, error::TryRecvError},
+};
+
+/// Blocking version of [ButtplugClient].
+pub struct ButtplugClientSync {
+  client: ButtplugClient,
+  event_receiver: Mutex<broadcast::Receiver<ButtplugClientEvent>>,
+  // Declared last so the client is dropped before its runtime goes away.
+  runtime: Arc<Runtime>,
+}
+
+impl ButtplugClientSync {
+  /// Creates a new client, along with the runtime it will run on.
+  ///
+  /// Returns an error if the runtime can't be created.
+  pub fn new(name: &str) -> io::Result<Self> {
+    let runtime = Runtime::new()?;
+    let client = ButtplugClient::new(name);
+    // Subscribe now, so no events are missed between connecting and the first
+    // call to next_event().
+    let event_receiver = Mutex::new(client.event_stream.subscribe());
+    Ok(Self {
+      client,
+      event_receiver,
+      runtime: Arc::new(runtime),
+    })
+  }
+
+  /// The async client being wrapped, for anything not exposed here.
+  pub fn client(&self) -> &ButtplugClient {
+    &self.client
+  }
+
+  /// Handle to the client's runtime. Some connectors (like the in-process
+  /// connector) spawn tasks when they're created, so they need to be built
+  /// while this runtime is entered (see [Handle::enter]).
+  pub fn runtime_handle(&self) -> Handle {
+    self.runtime.handle().clone()
+  }
+
+  pub fn connect<ConnectorType>(&self, connector: ConnectorType) -> ButtplugClientResult
+  where
+    ConnectorType: ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>
+      + 'static,
+  {
+    self.runtime.block_on(self.client.connect(connector))
+  }
+
+  /// See [ButtplugClient::connect_in_process].
+  #[cfg(feature = "server")]
+  pub fn connect_in_process(
+    &self,
+    options: &crate::server::ButtplugServerOptions,
+  ) -> ButtplugClientResult {
+    self
+      .runtime
+      .block_on(self.client.connect_in_process(options))
+  }
+
+  pub fn connected(&self) -> bool {
+    self.client.connected()
+  }
+
+  pub fn disconnect(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.disconnect())
+  }
+
+  pub fn start_scanning(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.start_scanning())
+  }
+
+  pub fn stop_scanning(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.stop_scanning())
+  }
+
+  pub fn stop_all_devices(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.stop_all_devices())
+  }
+
+  pub fn refresh_device_list(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.refresh_device_list())
+  }
+
+  pub fn ping(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.client.ping())
+  }
+
+  pub fn server_name(&self) -> Option<String> {
+    self.client.server_name()
+  }
+
+  pub fn devices(&self) -> Vec<ButtplugClientDeviceSync> {
+    self
+      .client
+      .devices()
+      .into_iter()
+      .map(|device| self.wrap_device(device))
+      .collect()
+  }
+
+  /// Wraps a device from a [ButtplugClientEvent] so it can be used with
+  /// blocking calls.
+  pub fn wrap_device(&self, device: Arc<ButtplugClientDevice>) -> ButtplugClientDeviceSync {
+    ButtplugClientDeviceSync {
+      runtime: self.runtime.clone(),
+      device,
+    }
+  }
+
+  /// Blocks until the next client event arrives, or `timeout` passes. With no
+  /// timeout, waits forever.
+  ///
+  /// Returns None on timeout. Events are buffered from the time the client is
+  /// created, but if the buffer fills up, the oldest events are dropped.
+  pub fn next_event(&self, timeout: Option<Duration>) -> Option<ButtplugClientEvent> {
+    let mut receiver = self.event_receiver.lock().unwrap();
+    self.runtime.block_on(async {
+      let recv_fut = async {
+        loop {
+          match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(count)) => {
+              warn!("Sync client lagged behind, {} events dropped.", count);
+            }
+            Err(RecvError::Closed) => return None,
+          }
+        }
+      };
+      match timeout {
+        Some(duration) => {
+          select! {
+            event = recv_fut.fuse() => event,
+            _ = Delay::new(duration).fuse() => None,
+          }
+        }
+        None => recv_fut.await,
+      }
+    })
+  }
+
+  /// Returns the next client event if one is waiting, without blocking.
+  pub fn try_next_event(&self) -> Option<ButtplugClientEvent> {
+    let mut receiver = self.event_receiver.lock().unwrap();
+    loop {
+      match receiver.try_recv() {
+        Ok(event) => return Some(event),
+        Err(TryRecvError::Lagged(count)) => {
+          warn!("Sync client lagged behind, {} events dropped.", count);
+        }
+        Err(_) => return None,
+      }
+    }
+  }
+}
+
+/// Blocking version of [ButtplugClientDevice], created by
+/// [ButtplugClientSync].
+#[derive(Clone)]
+pub struct ButtplugClientDeviceSync {
+  runtime: Arc<Runtime>,
+  device: Arc<ButtplugClientDevice>,
+}
+
+impl ButtplugClientDeviceSync {
+  /// The async device being wrapped, for anything not exposed here.
+  pub fn device(&self) -> &Arc<ButtplugClientDevice> {
+    &self.device
+  }
+
+  pub fn name(&self) -> String {
+    self.device.name()
+  }
+
+  pub fn index(&self) -> u32 {
+    self.device.index()
+  }
+
+  pub fn connected(&self) -> bool {
+    self.device.connected()
+  }
+
+  pub fn vibrate(&self, speed_cmd: impl Into<VibrateCommand>) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.vibrate(speed_cmd))
+  }
+
+  pub fn linear(&self, linear_cmd: LinearCommand) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.linear(linear_cmd))
+  }
+
+  pub fn rotate(&self, rotate_cmd: RotateCommand) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.rotate(rotate_cmd))
+  }
+
+  pub fn battery_level(&self) -> Result<f64, ButtplugClientError> {
+    self.runtime.block_on(self.device.battery_level())
+  }
+
+  pub fn rssi_level(&self) -> Result<i32, ButtplugClientError> {
+    self.runtime.block_on(self.device.rssi_level())
+  }
+
+  pub fn raw_write(
+    &self,
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    write_with_response: bool,
+  ) -> ButtplugClientResult {
+    self
+      .runtime
+      .block_on(self.device.raw_write(endpoint, data, write_with_response))
+  }
+
+  pub fn raw_read(
+    &self,
+    endpoint: Endpoint,
+    expected_length: u32,
+    timeout: u32,
+  ) -> Result<Vec<u8>, ButtplugClientError> {
+    self
+      .runtime
+      .block_on(self.device.raw_read(endpoint, expected_length, timeout))
+  }
+
+  pub fn raw_subscribe(&self, endpoint: Endpoint) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.raw_subscribe(endpoint))
+  }
+
+  pub fn raw_unsubscribe(&self, endpoint: Endpoint) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.raw_unsubscribe(endpoint))
+  }
+
+  pub fn stop(&self) -> ButtplugClientResult {
+    self.runtime.block_on(self.device.stop())
+  }
+}
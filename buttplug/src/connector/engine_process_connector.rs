@@ -0,0 +1,344 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Spawns and supervises an external Intiface engine process, and connects to
+//! it using another connector.
+//!
+//! Desktop applications usually don't embed the server in process, instead
+//! running the Intiface engine (IntifaceCLI) as a child process and talking to
+//! it over websockets. [ButtplugEngineProcess] handles the process lifecycle
+//! (start, restart on crash, kill on drop), and
+//! [ButtplugEngineProcessConnector] wraps whatever connector is used to talk to
+//! the engine, retrying the connection while the engine starts up.
+//!
+//! The process handle is reference counted, so that applications can make a
+//! new connector for the same engine after a disconnect (say, after the engine
+//! crashed and was restarted). The engine is shut down when the last handle is
+//! dropped.
+
+use super::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorResultFuture};
+use crate::{core::messages::ButtplugMessage, util::async_manager};
+use futures::{future::BoxFuture, FutureExt};
+use futures_timer::Delay;
+use std::{
+  io,
+  path::PathBuf,
+  process::Stdio,
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::{
+  process::{Child, Command},
+  sync::{mpsc::Sender, Mutex, Notify},
+};
+
+/// Options for spawning and supervising an engine process.
+#[derive(Debug, Clone)]
+pub struct ButtplugEngineProcessOptions {
+  /// Path to the engine executable.
+  pub engine_path: PathBuf,
+  /// Arguments to pass to the engine. These should set up whatever transport
+  /// the connector is going to use (i.e. websocket port).
+  pub args: Vec<String>,
+  /// Number of times to try connecting to the engine before giving up. The
+  /// engine may take a moment to bring up its transport after starting.
+  pub connect_attempts: u32,
+  /// Time to wait between connection attempts.
+  pub connect_retry_delay: Duration,
+  /// If true, restart the engine if it exits without us asking it to.
+  pub restart_on_crash: bool,
+  /// Maximum number of restarts before we give up on the engine.
+  pub max_restarts: u32,
+}
+
+impl Default for ButtplugEngineProcessOptions {
+  fn default() -> Self {
+    Self {
+      engine_path: PathBuf::from("IntifaceCLI"),
+      args: vec![],
+      connect_attempts: 10,
+      connect_retry_delay: Duration::from_millis(500),
+      restart_on_crash: true,
+      max_restarts: 5,
+    }
+  }
+}
+
+fn spawn_engine(options: &ButtplugEngineProcessOptions) -> io::Result<Child> {
+  info!(
+    "Starting engine process {:?} with args {:?}",
+    options.engine_path, options.args
+  );
+  Command::new(&options.engine_path)
+    .args(&options.args)
+    .stdin(Stdio::null())
+    // If our supervisor task goes away for any reason, still make sure we
+    // don't leave an engine running.
+    .kill_on_drop(true)
+    .spawn()
+}
+
+async fn supervise_engine(
+  options: ButtplugEngineProcessOptions,
+  mut child: Child,
+  running: Arc<AtomicBool>,
+  restart_count: Arc<AtomicU32>,
+  shutdown_notifier: Arc<Notify>,
+) {
+  loop {
+    select! {
+      status = child.wait().fuse() => {
+        running.store(false, Ordering::SeqCst);
+        match status {
+          Ok(status) => error!("Engine process exited unexpectedly: {}", status),
+          Err(err) => error!("Error waiting on engine process: {}", err),
+        }
+        if !options.restart_on_crash {
+          return;
+        }
+        if restart_count.load(Ordering::SeqCst) >= options.max_restarts {
+          error!("Engine process has hit its restart limit, not restarting.");
+          return;
+        }
+        restart_count.fetch_add(1, Ordering::SeqCst);
+        match spawn_engine(&options) {
+          Ok(new_child) => {
+            child = new_child;
+            running.store(true, Ordering::SeqCst);
+          }
+          Err(err) => {
+            error!("Cannot restart engine process: {}", err);
+            return;
+          }
+        }
+      }
+      _ = shutdown_notifier.notified().fuse() => {
+        info!("Shutting down engine process.");
+        if let Err(err) = child.kill().await {
+          error!("Error killing engine process: {}", err);
+        }
+        running.store(false, Ordering::SeqCst);
+        return;
+      }
+    }
+  }
+}
+
+/// Handle to a running engine process.
+///
+/// The process is watched by a supervisor task, which restarts it if it exits
+/// unexpectedly (see [ButtplugEngineProcessOptions::restart_on_crash]). The
+/// process is killed when this handle is dropped.
+pub struct ButtplugEngineProcess {
+  options: ButtplugEngineProcessOptions,
+  running: Arc<AtomicBool>,
+  restart_count: Arc<AtomicU32>,
+  shutdown_notifier: Arc<Notify>,
+}
+
+impl ButtplugEngineProcess {
+  /// Spawns the engine process and starts supervising it.
+  pub fn start(options: ButtplugEngineProcessOptions) -> io::Result<Self> {
+    let child = spawn_engine(&options)?;
+    let running = Arc::new(AtomicBool::new(true));
+    let restart_count = Arc::new(AtomicU32::new(0));
+    let shutdown_notifier = Arc::new(Notify::new());
+    async_manager::spawn(supervise_engine(
+      options.clone(),
+      child,
+      running.clone(),
+      restart_count.clone(),
+      shutdown_notifier.clone(),
+    ))
+    .unwrap();
+    Ok(Self {
+      options,
+      running,
+      restart_count,
+      shutdown_notifier,
+    })
+  }
+
+  pub fn options(&self) -> &ButtplugEngineProcessOptions {
+    &self.options
+  }
+
+  /// True if the engine process is currently alive.
+  pub fn is_running(&self) -> bool {
+    self.running.load(Ordering::SeqCst)
+  }
+
+  /// Number of times the engine has been restarted after crashing.
+  pub fn restart_count(&self) -> u32 {
+    self.restart_count.load(Ordering::SeqCst)
+  }
+
+  /// Kills the engine process. It will not be restarted.
+  pub fn stop(&self) {
+    // notify_one stores a permit if the supervisor isn't currently waiting, so
+    // this can't get lost.
+    self.shutdown_notifier.notify_one();
+  }
+}
+
+impl Drop for ButtplugEngineProcess {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+/// Connector that makes sure an engine process is running, then connects to
+/// it using a connector built by `connector_factory`.
+///
+/// Connectors can only be used for a single connection, so a new one is built
+/// for each connection attempt.
+pub struct ButtplugEngineProcessConnector<ConnectorType> {
+  process: Arc<ButtplugEngineProcess>,
+  connector_factory: Arc<dyn Fn() -> ConnectorType + Send + Sync>,
+  connector: Arc<Mutex<Option<ConnectorType>>>,
+}
+
+impl<ConnectorType> ButtplugEngineProcessConnector<ConnectorType> {
+  pub fn new<F>(process: Arc<ButtplugEngineProcess>, connector_factory: F) -> Self
+  where
+    F: Fn() -> ConnectorType + Send + Sync + 'static,
+  {
+    Self {
+      process,
+      connector_factory: Arc::new(connector_factory),
+      connector: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  pub fn process(&self) -> Arc<ButtplugEngineProcess> {
+    self.process.clone()
+  }
+}
+
+impl<ConnectorType, OutboundMessageType, InboundMessageType>
+  ButtplugConnector<OutboundMessageType, InboundMessageType>
+  for ButtplugEngineProcessConnector<ConnectorType>
+where
+  ConnectorType: ButtplugConnector<OutboundMessageType, InboundMessageType> + 'static,
+  OutboundMessageType: ButtplugMessage + 'static,
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  fn connect(
+    &mut self,
+    message_receiver: Sender<InboundMessageType>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let process = self.process.clone();
+    let connector_factory = self.connector_factory.clone();
+    let connector_holder = self.connector.clone();
+    Box::pin(async move {
+      let mut holder = connector_holder.lock().await;
+      if holder.is_some() {
+        return Err(ButtplugConnectorError::ConnectorAlreadyConnected);
+      }
+      let options = process.options();
+      let mut last_error = ButtplugConnectorError::ConnectorNotConnected;
+      for attempt in 0..options.connect_attempts {
+        if attempt > 0 {
+          Delay::new(options.connect_retry_delay).await;
+        }
+        if !process.is_running() {
+          debug!("Engine process not running, waiting before connection attempt.");
+          continue;
+        }
+        let mut connector = connector_factory();
+        match connector.connect(message_receiver.clone()).await {
+          Ok(()) => {
+            *holder = Some(connector);
+            return Ok(());
+          }
+          Err(err) => {
+            debug!("Engine connection attempt {} failed: {}", attempt + 1, err);
+            last_error = err;
+          }
+        }
+      }
+      Err(last_error)
+    })
+  }
+
+  fn disconnect(&self) -> ButtplugConnectorResultFuture {
+    let connector_holder = self.connector.clone();
+    Box::pin(async move {
+      // Leave the engine running, so another connector can use it.
+      match connector_holder.lock().await.take() {
+        Some(connector) => connector.disconnect().await,
+        None => Err(ButtplugConnectorError::ConnectorNotConnected),
+      }
+    })
+  }
+
+  fn send(&self, msg: OutboundMessageType) -> ButtplugConnectorResultFuture {
+    let connector_holder = self.connector.clone();
+    Box::pin(async move {
+      let send_fut = match connector_holder.lock().await.as_ref() {
+        Some(connector) => connector.send(msg),
+        None => return Err(ButtplugConnectorError::ConnectorNotConnected),
+      };
+      send_fut.await
+    })
+  }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+  use super::*;
+
+  fn options(engine_path: &str, args: &[&str]) -> ButtplugEngineProcessOptions {
+    ButtplugEngineProcessOptions {
+      engine_path: PathBuf::from(engine_path),
+      args: args.iter().map(|arg| arg.to_string()).collect(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_engine_process_stop() {
+    async_manager::block_on(async {
+      let process = ButtplugEngineProcess::start(options("sleep", &["60"])).unwrap();
+      assert!(process.is_running());
+      process.stop();
+      for _ in 0..50u8 {
+        if !process.is_running() {
+          break;
+        }
+        Delay::new(Duration::from_millis(10)).await;
+      }
+      assert!(!process.is_running());
+      assert_eq!(process.restart_count(), 0);
+    });
+  }
+
+  #[test]
+  fn test_engine_process_restart_limit() {
+    async_manager::block_on(async {
+      let mut opts = options("true", &[]);
+      opts.max_restarts = 2;
+      let process = ButtplugEngineProcess::start(opts).unwrap();
+      for _ in 0..100u8 {
+        if process.restart_count() == 2 && !process.is_running() {
+          break;
+        }
+        Delay::new(Duration::from_millis(10)).await;
+      }
+      assert_eq!(process.restart_count(), 2);
+      assert!(!process.is_running());
+    });
+  }
+
+  #[test]
+  fn test_engine_process_bad_path() {
+    assert!(ButtplugEngineProcess::start(options("/this/engine/does/not/exist", &[])).is_err());
+  }
+}
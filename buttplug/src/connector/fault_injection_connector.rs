@@ -0,0 +1,382 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Connector wrapper that simulates a bad connection.
+
+use super::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorResultFuture};
+use crate::{core::messages::ButtplugMessage, util::async_manager};
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+};
+use futures_timer::Delay;
+use std::{
+  collections::VecDeque,
+  marker::PhantomData,
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+use tokio::sync::{
+  mpsc::{channel, Receiver, Sender},
+  watch,
+};
+
+/// Faults for a [ButtplugFaultInjectionConnector] to inject. The default
+/// injects nothing, so options can be built with struct update syntax.
+///
+/// Chances are between 0.0 (never) and 1.0 (every message), and are rolled
+/// per message.
+#[derive(Debug, Clone, Copy)]
+pub struct ButtplugFaultInjectionOptions {
+  /// Seed for all of the random choices. With the same seed and the same
+  /// order of messages, the same faults happen.
+  pub seed: u64,
+  /// Shortest delay added to messages, in both directions.
+  pub min_latency: Duration,
+  /// Longest delay added to messages, in both directions. Messages still
+  /// arrive in the order they were sent, unless reordered.
+  pub max_latency: Duration,
+  /// Chance of an outgoing message being lost. Sending still succeeds, but
+  /// the message never reaches the other side.
+  pub outgoing_drop_chance: f64,
+  /// Chance of an incoming message being lost.
+  pub incoming_drop_chance: f64,
+  /// Chance of an incoming message being delivered after the one behind it,
+  /// if there is one waiting.
+  pub incoming_reorder_chance: f64,
+  /// Chance of the connection dropping instead of passing a message along,
+  /// in either direction.
+  pub disconnect_chance: f64,
+  /// Drops the connection instead of passing along the message after this
+  /// many messages have gone through, counting both directions.
+  pub disconnect_after: Option<u32>,
+}
+
+impl Default for ButtplugFaultInjectionOptions {
+  fn default() -> Self {
+    Self {
+      seed: 0x5eed,
+      min_latency: Duration::from_millis(0),
+      max_latency: Duration::from_millis(0),
+      outgoing_drop_chance: 0.0,
+      incoming_drop_chance: 0.0,
+      incoming_reorder_chance: 0.0,
+      disconnect_chance: 0.0,
+      disconnect_after: None,
+    }
+  }
+}
+
+/// What to do with a message passing through the connector.
+enum FaultAction {
+  Deliver(Instant),
+  Drop,
+  Disconnect,
+}
+
+struct FaultInjectionState {
+  options: ButtplugFaultInjectionOptions,
+  rng_state: Mutex<u64>,
+  message_count: AtomicU32,
+  connected: AtomicBool,
+  disconnect_sender: watch::Sender<bool>,
+}
+
+impl FaultInjectionState {
+  /// Random value between 0.0 and 1.0, via xorshift.
+  fn roll(&self) -> f64 {
+    let mut state = self.rng_state.lock().unwrap();
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  fn latency(&self) -> Duration {
+    let options = &self.options;
+    let spread = options
+      .max_latency
+      .checked_sub(options.min_latency)
+      .unwrap_or_default();
+    options.min_latency + spread.mul_f64(self.roll())
+  }
+
+  fn next_action(&self, drop_chance: f64) -> FaultAction {
+    let count = self.message_count.fetch_add(1, Ordering::SeqCst);
+    if matches!(self.options.disconnect_after, Some(limit) if count >= limit)
+      || self.roll() < self.options.disconnect_chance
+    {
+      FaultAction::Disconnect
+    } else if self.roll() < drop_chance {
+      FaultAction::Drop
+    } else {
+      FaultAction::Deliver(Instant::now() + self.latency())
+    }
+  }
+
+  fn disconnect(&self) {
+    if self.connected.swap(false, Ordering::SeqCst) {
+      // Only fails if both tasks are already gone.
+      let _ = self.disconnect_sender.send(true);
+    }
+  }
+}
+
+/// Waits until the front of the queue is due, or forever if it's empty.
+fn wait_for_front<T>(queue: &VecDeque<(Instant, T)>) -> BoxFuture<'static, ()> {
+  match queue.front() {
+    Some((due, _)) => Delay::new(due.saturating_duration_since(Instant::now())).boxed(),
+    None => future::pending().boxed(),
+  }
+}
+
+/// Wraps another connector, and injects latency, lost and reordered messages,
+/// and disconnects, based on [ButtplugFaultInjectionOptions].
+///
+/// Meant for testing how applications (and libraries built on Buttplug)
+/// handle unreliable connections, without needing an unreliable network to do
+/// it on. An injected disconnect looks the same as the server going away:
+/// the inner connector is disconnected, and the client gets a
+/// [ServerDisconnect][crate::client::ButtplugClientEvent::ServerDisconnect].
+pub struct ButtplugFaultInjectionConnector<ConnectorType, OutboundMessageType, InboundMessageType>
+where
+  ConnectorType: ButtplugConnector<OutboundMessageType, InboundMessageType> + 'static,
+  OutboundMessageType: ButtplugMessage + 'static,
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  inner: Arc<ConnectorType>,
+  state: Arc<FaultInjectionState>,
+  outgoing_sender: Option<Sender<(Instant, OutboundMessageType)>>,
+  _phantom: PhantomData<fn() -> InboundMessageType>,
+}
+
+impl<ConnectorType, OutboundMessageType, InboundMessageType>
+  ButtplugFaultInjectionConnector<ConnectorType, OutboundMessageType, InboundMessageType>
+where
+  ConnectorType: ButtplugConnector<OutboundMessageType, InboundMessageType> + 'static,
+  OutboundMessageType: ButtplugMessage + 'static,
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  pub fn new(connector: ConnectorType, options: ButtplugFaultInjectionOptions) -> Self {
+    let (disconnect_sender, _) = watch::channel(false);
+    Self {
+      inner: Arc::new(connector),
+      state: Arc::new(FaultInjectionState {
+        options,
+        // xorshift gets stuck on 0.
+        rng_state: Mutex::new(options.seed.max(1)),
+        message_count: AtomicU32::new(0),
+        connected: AtomicBool::new(false),
+        disconnect_sender,
+      }),
+      outgoing_sender: None,
+      _phantom: PhantomData,
+    }
+  }
+
+  /// Get a reference to the wrapped connector.
+  pub fn inner(&self) -> &ConnectorType {
+    &self.inner
+  }
+
+  /// Drops the connection right away, as if the network went down.
+  pub fn inject_disconnect(&self) {
+    self.state.disconnect();
+  }
+}
+
+async fn run_outgoing<ConnectorType, OutboundMessageType, InboundMessageType>(
+  inner: Arc<ConnectorType>,
+  mut outgoing_receiver: Receiver<(Instant, OutboundMessageType)>,
+  mut disconnect_receiver: watch::Receiver<bool>,
+  _phantom: PhantomData<fn() -> InboundMessageType>,
+) where
+  ConnectorType: ButtplugConnector<OutboundMessageType, InboundMessageType> + 'static,
+  OutboundMessageType: ButtplugMessage + 'static,
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  loop {
+    select! {
+      outgoing = outgoing_receiver.recv().fuse() => match outgoing {
+        Some((due, msg)) => {
+          Delay::new(due.saturating_duration_since(Instant::now())).await;
+          if let Err(err) = inner.send(msg).await {
+            error!("Fault injection connector could not send message: {}", err);
+          }
+        }
+        // The connector itself went away.
+        None => return,
+      },
+      _ = disconnect_receiver.changed().fuse() => break,
+    }
+  }
+  if let Err(err) = inner.disconnect().await {
+    debug!("Fault injection connector could not disconnect inner connector: {}", err);
+  }
+}
+
+async fn run_incoming<InboundMessageType>(
+  state: Arc<FaultInjectionState>,
+  mut inner_receiver: Receiver<InboundMessageType>,
+  message_sender: Sender<InboundMessageType>,
+  mut disconnect_receiver: watch::Receiver<bool>,
+) where
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  let mut queue = VecDeque::new();
+  loop {
+    select! {
+      incoming = inner_receiver.recv().fuse() => match incoming {
+        Some(msg) => match state.next_action(state.options.incoming_drop_chance) {
+          FaultAction::Deliver(due) => queue.push_back((due, msg)),
+          FaultAction::Drop => info!("Fault injection connector dropping incoming message."),
+          FaultAction::Disconnect => {
+            info!("Fault injection connector disconnecting.");
+            state.disconnect();
+            return;
+          }
+        },
+        None => return,
+      },
+      _ = wait_for_front(&queue).fuse() => {
+        let index = if queue.len() > 1 && state.roll() < state.options.incoming_reorder_chance {
+          info!("Fault injection connector reordering incoming messages.");
+          1
+        } else {
+          0
+        };
+        let (_, msg) = queue.remove(index).unwrap();
+        if message_sender.send(msg).await.is_err() {
+          return;
+        }
+      },
+      // Dropping the message sender is what tells the client we're gone.
+      _ = disconnect_receiver.changed().fuse() => return,
+    }
+  }
+}
+
+impl<ConnectorType, OutboundMessageType, InboundMessageType>
+  ButtplugConnector<OutboundMessageType, InboundMessageType>
+  for ButtplugFaultInjectionConnector<ConnectorType, OutboundMessageType, InboundMessageType>
+where
+  ConnectorType: ButtplugConnector<OutboundMessageType, InboundMessageType> + 'static,
+  OutboundMessageType: ButtplugMessage + 'static,
+  InboundMessageType: ButtplugMessage + 'static,
+{
+  fn connect(
+    &mut self,
+    message_sender: Sender<InboundMessageType>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    // The outgoing task holds a reference to the inner connector once we've
+    // connected, and we can't reconnect without it.
+    let inner = match Arc::get_mut(&mut self.inner) {
+      Some(inner) => inner,
+      None => return ButtplugConnectorError::ConnectorAlreadyConnected.into(),
+    };
+    let (inner_sender, inner_receiver) = channel(256);
+    let connect_fut = inner.connect(inner_sender);
+    let inner = self.inner.clone();
+    let state = self.state.clone();
+    let (outgoing_sender, outgoing_receiver) = channel(256);
+    self.outgoing_sender = Some(outgoing_sender);
+    Box::pin(async move {
+      connect_fut.await?;
+      state.connected.store(true, Ordering::SeqCst);
+      let disconnect_receiver = state.disconnect_sender.subscribe();
+      async_manager::spawn(run_outgoing(
+        inner,
+        outgoing_receiver,
+        disconnect_receiver.clone(),
+        PhantomData,
+      ))
+      .unwrap();
+      async_manager::spawn(run_incoming(
+        state,
+        inner_receiver,
+        message_sender,
+        disconnect_receiver,
+      ))
+      .unwrap();
+      Ok(())
+    })
+  }
+
+  fn disconnect(&self) -> ButtplugConnectorResultFuture {
+    if !self.state.connected.load(Ordering::SeqCst) {
+      return ButtplugConnectorError::ConnectorNotConnected.into();
+    }
+    // The outgoing task disconnects the inner connector on its way out.
+    self.state.disconnect();
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn send(&self, msg: OutboundMessageType) -> ButtplugConnectorResultFuture {
+    let outgoing_sender = match &self.outgoing_sender {
+      Some(sender) if self.state.connected.load(Ordering::SeqCst) => sender.clone(),
+      _ => return ButtplugConnectorError::ConnectorNotConnected.into(),
+    };
+    match self.state.next_action(self.state.options.outgoing_drop_chance) {
+      FaultAction::Deliver(due) => Box::pin(async move {
+        outgoing_sender
+          .send((due, msg))
+          .await
+          .map_err(|_| ButtplugConnectorError::ConnectorNotConnected)
+      }),
+      FaultAction::Drop => {
+        info!("Fault injection connector dropping outgoing message.");
+        Box::pin(future::ready(Ok(())))
+      }
+      FaultAction::Disconnect => {
+        info!("Fault injection connector disconnecting.");
+        self.state.disconnect();
+        ButtplugConnectorError::ConnectorNotConnected.into()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugFaultInjectionOptions, FaultAction, FaultInjectionState};
+  use std::sync::{
+    atomic::{AtomicBool, AtomicU32},
+    Mutex,
+  };
+  use tokio::sync::watch;
+
+  fn state(options: ButtplugFaultInjectionOptions) -> FaultInjectionState {
+    FaultInjectionState {
+      options,
+      rng_state: Mutex::new(options.seed),
+      message_count: AtomicU32::new(0),
+      connected: AtomicBool::new(true),
+      disconnect_sender: watch::channel(false).0,
+    }
+  }
+
+  #[test]
+  fn test_fault_injection_seeded() {
+    let options = ButtplugFaultInjectionOptions {
+      outgoing_drop_chance: 0.5,
+      ..Default::default()
+    };
+    let dropped = |state: FaultInjectionState| -> Vec<bool> {
+      (0..32)
+        .map(|_| matches!(state.next_action(0.5), FaultAction::Drop))
+        .collect()
+    };
+    let first = dropped(state(options));
+    assert_eq!(first, dropped(state(options)));
+    assert!(first.contains(&true) && first.contains(&false));
+    let never = state(ButtplugFaultInjectionOptions::default());
+    assert!((0..32).all(|_| matches!(never.next_action(0.0), FaultAction::Deliver(_))));
+  }
+}
@@ -69,14 +69,19 @@ impl<'a> ButtplugInProcessClientConnector {
   /// Takes the server's name and the ping time it should use, with a ping time
   /// of 0 meaning infinite ping.
   pub fn new_with_options(options: &ButtplugServerOptions) -> Result<Self, ButtplugError> {
-    let server = Arc::new(ButtplugServer::new_with_options(options)?);
+    Ok(Self::new_with_server(ButtplugServer::new_with_options(options)?))
+  }
+
+  /// Creates a new in-process connector around an already built server, i.e.
+  /// from a [ButtplugServerBuilder][crate::server::ButtplugServerBuilder].
+  pub fn new_with_server(server: ButtplugServer) -> Self {
     // Create a dummy channel, will just be overwritten on connect.
     let (server_outbound_sender, _) = channel(256);
-    Ok(Self {
+    Self {
       server_outbound_sender,
-      server,
+      server: Arc::new(server),
       connected: Arc::new(AtomicBool::new(false)),
-    })
+    }
   }
 
   /// Get a reference to the internal server.
@@ -88,6 +93,12 @@ impl<'a> ButtplugInProcessClientConnector {
   pub fn server_ref(&'a self) -> &'a ButtplugServer {
     &self.server
   }
+
+  /// Shared handle to the internal server, for when it's needed after the
+  /// connector has been handed to a client, i.e. to rename devices.
+  pub fn server(&self) -> Arc<ButtplugServer> {
+    self.server.clone()
+  }
 }
 
 #[cfg(feature = "server")]
@@ -74,20 +74,37 @@
 //! work comes in also, but that Windows 7/Android example is where the idea
 //! originally came from.
 
+#[cfg(feature = "engine-process")]
+mod engine_process_connector;
+mod fault_injection_connector;
 #[cfg(all(feature = "server", feature = "client"))]
 mod in_process_connector;
 pub mod remote_connector;
 pub mod transport;
 
+#[cfg(feature = "engine-process")]
+pub use engine_process_connector::{
+  ButtplugEngineProcess, ButtplugEngineProcessConnector, ButtplugEngineProcessOptions,
+};
+pub use fault_injection_connector::{
+  ButtplugFaultInjectionConnector, ButtplugFaultInjectionOptions,
+};
 #[cfg(all(feature = "server", feature = "client"))]
 pub use in_process_connector::ButtplugInProcessClientConnector;
 pub use remote_connector::{
   ButtplugRemoteClientConnector, ButtplugRemoteConnector, ButtplugRemoteServerConnector,
 };
+#[cfg(feature = "e2e-encryption")]
+pub use transport::{ButtplugEncryptedTransport, ButtplugPairingKey};
+pub use transport::{ButtplugMultiplexedTransport, ButtplugTransportMultiplexer};
 #[cfg(feature = "websockets")]
 pub use transport::ButtplugWebsocketClientTransport;
 #[cfg(feature = "websockets")]
 pub use transport::{ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportOptions};
+#[cfg(feature = "websockets")]
+pub use transport::{
+  ButtplugRelayControlMessage, ButtplugRelayRole, ButtplugWebsocketRelayTransport,
+};
 
 use crate::{
   core::messages::{serializer::ButtplugSerializedMessage, ButtplugMessage},
@@ -119,7 +136,7 @@ pub enum ButtplugConnectorError {
   /// Connector error: {0}
   ConnectorGenericError(String),
   /// Specific error for connector type: {0}.
-  TransportSpecificError(transport::ButtplugConnectorTransportSpecificError),
+  TransportSpecificError(#[source] transport::ButtplugConnectorTransportSpecificError),
 }
 
 impl<T> From<ButtplugConnectorError> for BoxFuture<'static, Result<T, ButtplugConnectorError>>
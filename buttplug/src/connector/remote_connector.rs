@@ -105,7 +105,6 @@ async fn remote_connector_event_loop<
                 }
               }
               Err(e) => {
-                // TODO Not sure where to relay this.
                 error!(
                   "{}",
                   format!(
@@ -113,6 +112,15 @@ async fn remote_connector_event_loop<
                     e
                   )
                 );
+                // If the serializer knows how to tell the other side what went
+                // wrong, do that. Otherwise there's not much we can do besides
+                // log it.
+                if let Some(error_msg) = serializer.serialize_error(&e) {
+                  if transport_outgoing_sender.send(error_msg).await.is_err() {
+                    error!("Transport has disconnected, exiting remote connector loop.");
+                    return;
+                  }
+                }
               }
             }
           }
@@ -0,0 +1,457 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! End-to-end encryption for remote connections.
+//!
+//! When a client and server talk through something neither of them trusts
+//! (say, a relay server for remote control sessions), TLS only protects each
+//! hop, and whoever runs the relay can read and inject device commands.
+//! [ButtplugEncryptedTransport] wraps another transport and encrypts
+//! everything the serializers produce, so the relay only ever sees opaque
+//! binary messages.
+//!
+//! Both ends share a [ButtplugPairingKey], handed over out of band (a QR code,
+//! a string pasted into a chat, etc). On connect, each side sends an ephemeral
+//! X25519 public key, and session keys are derived from the resulting shared
+//! secret and the pairing key with HKDF-SHA256. Messages are then sealed with
+//! ChaCha20-Poly1305 using per-direction keys and message counters, so without
+//! the pairing key, nothing can be read, forged, replayed, or reordered, and
+//! recording a session doesn't help decrypt it later.
+
+use crate::{
+  connector::{
+    transport::{
+      ButtplugConnectorTransport, ButtplugConnectorTransportSpecificError,
+      ButtplugTransportIncomingMessage,
+    },
+    ButtplugConnectorError, ButtplugConnectorResultFuture,
+  },
+  core::messages::serializer::ButtplugSerializedMessage,
+  util::async_manager,
+};
+use futures::{future::BoxFuture, FutureExt};
+use futures_timer::Delay;
+use openssl::{
+  derive::Deriver,
+  error::ErrorStack,
+  md::Md,
+  pkey::{Id, PKey},
+  pkey_ctx::PkeyCtx,
+  symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use std::{fmt, time::Duration};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::Instrument;
+
+/// Prefix for pairing strings, versioned in case the scheme ever changes.
+const PAIRING_STRING_PREFIX: &str = "bpe1-";
+/// Marks handshake messages, so connecting to a peer that isn't encrypting
+/// fails clearly.
+const HANDSHAKE_MAGIC: &[u8] = b"BPE1";
+const KEY_LENGTH: usize = 32;
+const TAG_LENGTH: usize = 16;
+/// How long to wait for the other side's handshake before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Plaintext type markers, so text messages come back out as text.
+const TEXT_MESSAGE: u8 = 0;
+const BINARY_MESSAGE: u8 = 1;
+
+fn encryption_error(err: impl fmt::Display) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::EncryptionError(err.to_string()),
+  )
+}
+
+/// Secret shared by both ends of an encrypted connection.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ButtplugPairingKey([u8; KEY_LENGTH]);
+
+impl ButtplugPairingKey {
+  /// Creates a new random key.
+  pub fn generate() -> Self {
+    let mut key = [0u8; KEY_LENGTH];
+    // Only fails if the system random number generator does, at which point
+    // we have bigger problems.
+    openssl::rand::rand_bytes(&mut key).expect("System random number generator failed");
+    Self(key)
+  }
+
+  /// Parses a string made by [to_pairing_string][Self::to_pairing_string],
+  /// returning None if it isn't valid.
+  pub fn from_pairing_string(pairing_string: &str) -> Option<Self> {
+    let hex = pairing_string.trim().strip_prefix(PAIRING_STRING_PREFIX)?;
+    if hex.len() != KEY_LENGTH * 2 || !hex.is_ascii() {
+      return None;
+    }
+    let mut key = [0u8; KEY_LENGTH];
+    for (index, byte) in key.iter_mut().enumerate() {
+      *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(Self(key))
+  }
+
+  /// String to hand to the other side, i.e. by showing it as a QR code.
+  pub fn to_pairing_string(&self) -> String {
+    let hex: String = self.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}{}", PAIRING_STRING_PREFIX, hex)
+  }
+}
+
+// Keep the key out of logs.
+impl fmt::Debug for ButtplugPairingKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("ButtplugPairingKey(..)")
+  }
+}
+
+/// Seals or opens messages in one direction of a session.
+struct SessionCipher {
+  key: Vec<u8>,
+  counter: u64,
+}
+
+impl SessionCipher {
+  fn new(key: &[u8]) -> Self {
+    Self {
+      key: key.to_vec(),
+      counter: 0,
+    }
+  }
+
+  /// Each key is only used in one direction, so the counter alone makes the
+  /// nonce unique. It also means messages have to be opened in order. The
+  /// counter is only advanced once a message has been sealed or opened, so
+  /// both ends stay in step.
+  fn nonce(&self) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+    nonce
+  }
+
+  fn seal(&mut self, msg: ButtplugSerializedMessage) -> Result<Vec<u8>, String> {
+    let plaintext = match msg {
+      ButtplugSerializedMessage::Text(text) => [&[TEXT_MESSAGE], text.as_bytes()].concat(),
+      ButtplugSerializedMessage::Binary(data) => [&[BINARY_MESSAGE], data.as_slice()].concat(),
+    };
+    let nonce = self.nonce();
+    let mut tag = [0u8; TAG_LENGTH];
+    let mut sealed = encrypt_aead(
+      Cipher::chacha20_poly1305(),
+      &self.key,
+      Some(&nonce),
+      &[],
+      &plaintext,
+      &mut tag,
+    )
+    .map_err(|err| err.to_string())?;
+    self.counter += 1;
+    sealed.extend_from_slice(&tag);
+    Ok(sealed)
+  }
+
+  fn open(&mut self, sealed: &[u8]) -> Result<ButtplugSerializedMessage, String> {
+    if sealed.len() < TAG_LENGTH {
+      return Err("Encrypted message too short".to_owned());
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LENGTH);
+    let nonce = self.nonce();
+    let plaintext = decrypt_aead(
+      Cipher::chacha20_poly1305(),
+      &self.key,
+      Some(&nonce),
+      &[],
+      ciphertext,
+      tag,
+    )
+    .map_err(|_| "Message failed authentication".to_owned())?;
+    self.counter += 1;
+    match plaintext.split_first() {
+      Some((&TEXT_MESSAGE, text)) => String::from_utf8(text.to_vec())
+        .map(ButtplugSerializedMessage::Text)
+        .map_err(|err| err.to_string()),
+      Some((&BINARY_MESSAGE, data)) => Ok(ButtplugSerializedMessage::Binary(data.to_vec())),
+      _ => Err("Unknown encrypted message type".to_owned()),
+    }
+  }
+}
+
+/// Derives (sending, receiving) ciphers from the ephemeral key exchange.
+fn derive_session(
+  pairing_key: &ButtplugPairingKey,
+  our_key: &PKey<openssl::pkey::Private>,
+  peer_public: &[u8],
+) -> Result<(SessionCipher, SessionCipher), String> {
+  let our_public = our_key.raw_public_key().map_err(|err| err.to_string())?;
+  // A relay echoing our own handshake back at us.
+  if our_public == peer_public {
+    return Err("Peer sent our own handshake back".to_owned());
+  }
+  let shared_secret =
+    derive_shared_secret(our_key, peer_public).map_err(|err| err.to_string())?;
+
+  // Both sides need to agree on ordering, so sort by public key.
+  let we_are_first = our_public.as_slice() < peer_public;
+  let (first, second) = if we_are_first {
+    (our_public.as_slice(), peer_public)
+  } else {
+    (peer_public, our_public.as_slice())
+  };
+  let info = [HANDSHAKE_MAGIC, first, second].concat();
+  let okm =
+    derive_session_keys(&shared_secret, pairing_key, &info).map_err(|err| err.to_string())?;
+  let (first_key, second_key) = okm.split_at(KEY_LENGTH);
+  let (send_key, receive_key) = if we_are_first {
+    (first_key, second_key)
+  } else {
+    (second_key, first_key)
+  };
+  Ok((SessionCipher::new(send_key), SessionCipher::new(receive_key)))
+}
+
+fn derive_shared_secret(
+  our_key: &PKey<openssl::pkey::Private>,
+  peer_public: &[u8],
+) -> Result<Vec<u8>, ErrorStack> {
+  let peer_key = PKey::public_key_from_raw_bytes(peer_public, Id::X25519)?;
+  let mut deriver = Deriver::new(our_key)?;
+  deriver.set_peer(&peer_key)?;
+  deriver.derive_to_vec()
+}
+
+/// HKDF-SHA256, salted with the pairing key, so only someone holding it ends
+/// up with the same session keys.
+fn derive_session_keys(
+  shared_secret: &[u8],
+  pairing_key: &ButtplugPairingKey,
+  info: &[u8],
+) -> Result<[u8; KEY_LENGTH * 2], ErrorStack> {
+  let mut okm = [0u8; KEY_LENGTH * 2];
+  let mut hkdf = PkeyCtx::new_id(Id::HKDF)?;
+  hkdf.derive_init()?;
+  hkdf.set_hkdf_md(Md::sha256())?;
+  hkdf.set_hkdf_key(shared_secret)?;
+  hkdf.set_hkdf_salt(&pairing_key.0)?;
+  hkdf.add_hkdf_info(info)?;
+  hkdf.derive(Some(&mut okm))?;
+  Ok(okm)
+}
+
+/// Waits for the peer's handshake message, returning its public key.
+async fn receive_handshake(
+  inner_incoming_receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+) -> Result<Vec<u8>, ButtplugConnectorError> {
+  loop {
+    match inner_incoming_receiver.recv().await {
+      Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data)))
+        if data.len() == HANDSHAKE_MAGIC.len() + KEY_LENGTH
+          && data.starts_with(HANDSHAKE_MAGIC) =>
+      {
+        return Ok(data[HANDSHAKE_MAGIC.len()..].to_vec())
+      }
+      Some(ButtplugTransportIncomingMessage::Message(_)) => {
+        return Err(encryption_error(
+          "Peer isn't using end-to-end encryption, or is using a different version",
+        ))
+      }
+      Some(ButtplugTransportIncomingMessage::Close(reason)) => {
+        return Err(encryption_error(format!(
+          "Connection closed during handshake: {}",
+          reason
+        )))
+      }
+      Some(_) => continue,
+      None => return Err(ButtplugConnectorError::ConnectorChannelClosed),
+    }
+  }
+}
+
+/// Wraps another transport, encrypting everything sent over it. See the
+/// [module documentation][self] for details.
+pub struct ButtplugEncryptedTransport<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  inner: TransportType,
+  pairing_key: ButtplugPairingKey,
+}
+
+impl<TransportType> ButtplugEncryptedTransport<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  pub fn new(inner: TransportType, pairing_key: ButtplugPairingKey) -> Self {
+    Self { inner, pairing_key }
+  }
+}
+
+impl<TransportType> ButtplugConnectorTransport for ButtplugEncryptedTransport<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let (inner_outgoing_sender, inner_outgoing_receiver) = channel(256);
+    let (inner_incoming_sender, mut inner_incoming_receiver) = channel(256);
+    let inner_connect = self
+      .inner
+      .connect(inner_outgoing_receiver, inner_incoming_sender);
+    let pairing_key = self.pairing_key.clone();
+    Box::pin(async move {
+      inner_connect.await?;
+      let our_key = PKey::generate_x25519().map_err(encryption_error)?;
+      let our_public = our_key.raw_public_key().map_err(encryption_error)?;
+      inner_outgoing_sender
+        .send(ButtplugSerializedMessage::Binary(
+          [HANDSHAKE_MAGIC, &our_public].concat(),
+        ))
+        .await
+        .map_err(|_| ButtplugConnectorError::ConnectorChannelClosed)?;
+      let peer_public = select! {
+        handshake = receive_handshake(&mut inner_incoming_receiver).fuse() => handshake?,
+        _ = Delay::new(HANDSHAKE_TIMEOUT).fuse() => {
+          return Err(encryption_error("Timed out waiting for handshake"));
+        }
+      };
+      let (mut send_cipher, mut receive_cipher) =
+        derive_session(&pairing_key, &our_key, &peer_public).map_err(encryption_error)?;
+      info!("End-to-end encrypted session established.");
+
+      async_manager::spawn(
+        async move {
+          loop {
+            select! {
+              outgoing = outgoing_receiver.recv().fuse() => match outgoing {
+                Some(msg) => {
+                  let sealed = match send_cipher.seal(msg) {
+                    Ok(sealed) => sealed,
+                    Err(err) => {
+                      // Dropping the message would leave the other end
+                      // waiting on a reply forever, so give up on the
+                      // connection instead.
+                      error!("Cannot encrypt message, closing connection: {}", err);
+                      let _ = incoming_sender
+                        .send(ButtplugTransportIncomingMessage::Close(err))
+                        .await;
+                      return;
+                    }
+                  };
+                  if inner_outgoing_sender
+                    .send(ButtplugSerializedMessage::Binary(sealed))
+                    .await
+                    .is_err()
+                  {
+                    return;
+                  }
+                }
+                // Dropping the inner sender tells the inner transport to close.
+                None => return,
+              },
+              incoming = inner_incoming_receiver.recv().fuse() => {
+                let incoming = match incoming {
+                  Some(ButtplugTransportIncomingMessage::Message(
+                    ButtplugSerializedMessage::Binary(sealed),
+                  )) => match receive_cipher.open(&sealed) {
+                    Ok(msg) => ButtplugTransportIncomingMessage::Message(msg),
+                    Err(err) => {
+                      // Someone is tampering with the connection, or it's
+                      // broken. Either way, we're done with it.
+                      error!("Cannot decrypt message, closing connection: {}", err);
+                      ButtplugTransportIncomingMessage::Close(err)
+                    }
+                  },
+                  Some(ButtplugTransportIncomingMessage::Message(_)) => {
+                    error!("Received unencrypted message, closing connection.");
+                    ButtplugTransportIncomingMessage::Close(
+                      "Received unencrypted message".to_owned(),
+                    )
+                  }
+                  Some(other) => other,
+                  None => return,
+                };
+                let closing = matches!(incoming, ButtplugTransportIncomingMessage::Close(_));
+                if incoming_sender.send(incoming).await.is_err() || closing {
+                  return;
+                }
+              }
+            }
+          }
+        }
+        .instrument(tracing::info_span!("Encrypted Transport Task")),
+      )
+      .unwrap();
+      Ok(())
+    })
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    self.inner.disconnect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{derive_session, ButtplugPairingKey, SessionCipher};
+  use crate::core::messages::serializer::ButtplugSerializedMessage;
+  use openssl::pkey::PKey;
+
+  #[test]
+  fn test_pairing_string_round_trip() {
+    let key = ButtplugPairingKey::generate();
+    let pairing_string = key.to_pairing_string();
+    assert_eq!(
+      ButtplugPairingKey::from_pairing_string(&pairing_string).unwrap(),
+      key
+    );
+    assert!(ButtplugPairingKey::from_pairing_string("bpe1-abcd").is_none());
+    assert!(ButtplugPairingKey::from_pairing_string(&pairing_string[1..]).is_none());
+    assert!(!format!("{:?}", key).contains(&pairing_string[5..]));
+  }
+
+  fn session_pair(
+    first_key: &ButtplugPairingKey,
+    second_key: &ButtplugPairingKey,
+  ) -> ((SessionCipher, SessionCipher), (SessionCipher, SessionCipher)) {
+    let first = PKey::generate_x25519().unwrap();
+    let second = PKey::generate_x25519().unwrap();
+    (
+      derive_session(first_key, &first, &second.raw_public_key().unwrap()).unwrap(),
+      derive_session(second_key, &second, &first.raw_public_key().unwrap()).unwrap(),
+    )
+  }
+
+  #[test]
+  fn test_session_ciphers() {
+    let key = ButtplugPairingKey::generate();
+    let ((mut first_send, mut first_receive), (mut second_send, mut second_receive)) =
+      session_pair(&key, &key);
+    let text = ButtplugSerializedMessage::Text("[{\"Ok\":{\"Id\":1}}]".to_owned());
+    let sealed = first_send.seal(text.clone()).unwrap();
+    assert_eq!(second_receive.open(&sealed).unwrap(), text);
+    // Replaying fails, since the counter has moved on.
+    assert!(second_receive.open(&sealed).is_err());
+    let binary = ButtplugSerializedMessage::Binary(vec![1, 2, 3]);
+    let mut sealed = second_send.seal(binary.clone()).unwrap();
+    // Reflecting a message back at its sender fails, as each direction has its
+    // own key.
+    assert!(second_receive.open(&sealed).is_err());
+    sealed[0] ^= 1;
+    assert!(first_receive.open(&sealed).is_err());
+    // Failures don't use up a nonce.
+    sealed[0] ^= 1;
+    assert_eq!(first_receive.open(&sealed).unwrap(), binary);
+
+    // Without the same pairing key, nothing gets through.
+    let ((mut first_send, _), (_, mut second_receive)) =
+      session_pair(&key, &ButtplugPairingKey::generate());
+    let sealed = first_send.seal(text).unwrap();
+    assert!(second_receive.open(&sealed).is_err());
+  }
+}
@@ -0,0 +1,58 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Carrying serialized messages as bytes.
+//!
+//! Transports that wrap messages in their own binary frames need to keep track
+//! of whether each one was text or binary. They all do it the same way: the
+//! first byte is 0 for text (followed by UTF-8) or 1 for binary (followed by
+//! the data).
+
+use crate::core::messages::serializer::ButtplugSerializedMessage;
+
+const TEXT_FRAME: u8 = 0;
+const BINARY_FRAME: u8 = 1;
+
+pub(super) fn frame_message(msg: ButtplugSerializedMessage) -> Vec<u8> {
+  match msg {
+    ButtplugSerializedMessage::Text(text) => [&[TEXT_FRAME], text.as_bytes()].concat(),
+    ButtplugSerializedMessage::Binary(data) => [&[BINARY_FRAME], data.as_slice()].concat(),
+  }
+}
+
+/// Undoes [frame_message]. Returns None for frames it couldn't have made.
+pub(super) fn unframe_message(frame: &[u8]) -> Option<ButtplugSerializedMessage> {
+  match frame.split_first()? {
+    (&TEXT_FRAME, text) => String::from_utf8(text.to_vec())
+      .ok()
+      .map(ButtplugSerializedMessage::Text),
+    (&BINARY_FRAME, data) => Some(ButtplugSerializedMessage::Binary(data.to_vec())),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{frame_message, unframe_message};
+  use crate::core::messages::serializer::ButtplugSerializedMessage;
+
+  #[test]
+  fn test_framing() {
+    for msg in &[
+      ButtplugSerializedMessage::Text("[{\"Ok\":{\"Id\":1}}]".to_owned()),
+      ButtplugSerializedMessage::Binary(vec![1, 2, 3]),
+    ] {
+      assert_eq!(
+        unframe_message(&frame_message(msg.clone())).as_ref(),
+        Some(msg)
+      );
+    }
+    assert_eq!(unframe_message(&[]), None);
+    assert_eq!(unframe_message(&[7, 1]), None);
+    assert_eq!(unframe_message(&[0, 0xff]), None);
+  }
+}
@@ -1,3 +1,7 @@
+#[cfg(feature = "e2e-encryption")]
+mod encrypted;
+mod framing;
+mod multiplexed;
 #[cfg(feature = "websockets")]
 mod websocket;
 use crate::connector::{
@@ -5,10 +9,17 @@ use crate::connector::{
 };
 use futures::future::BoxFuture;
 use tokio::sync::mpsc::{Receiver, Sender};
+#[cfg(feature = "e2e-encryption")]
+pub use encrypted::{ButtplugEncryptedTransport, ButtplugPairingKey};
+pub use multiplexed::{ButtplugMultiplexedTransport, ButtplugTransportMultiplexer};
 #[cfg(feature = "websockets")]
 pub use websocket::{ButtplugWebsocketClientTransport, TungsteniteError};
 #[cfg(feature = "websockets")]
 pub use websocket::{ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportOptions};
+#[cfg(feature = "websockets")]
+pub use websocket::{
+  ButtplugRelayControlMessage, ButtplugRelayRole, ButtplugWebsocketRelayTransport,
+};
 
 use thiserror::Error;
 
@@ -41,5 +52,10 @@ pub enum ButtplugConnectorTransportSpecificError {
   #[error("Tungstenite specific error: {0}")]
   TungsteniteError(#[from] TungsteniteError),
   #[error("Network error: {0}")]
-  GenericNetworkError(String)
+  GenericNetworkError(String),
+  #[error("IO error: {0}")]
+  IoError(#[from] std::io::Error),
+  #[cfg(feature = "e2e-encryption")]
+  #[error("End-to-end encryption error: {0}")]
+  EncryptionError(String),
 }
@@ -0,0 +1,284 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Carrying several Buttplug connections over one transport.
+//!
+//! Bridges (one network connection, several apps behind it) would otherwise
+//! need a websocket per app. [ButtplugTransportMultiplexer] wraps a single
+//! transport and hands out [ButtplugMultiplexedTransport] channels, each of
+//! which is a transport of its own. Every channel gets its own connector, so
+//! handshakes, message IDs and disconnects are independent, the same as if
+//! each had its own websocket.
+//!
+//! Both ends wrap their side of the connection in a multiplexer and open
+//! channels with the same IDs, agreed on ahead of time (i.e. one per app the
+//! bridge knows about). The inner transport connects when the first channel
+//! does, and is closed once the last channel disconnects.
+//!
+//! # Framing
+//!
+//! Everything goes over the inner transport as binary messages. Each starts
+//! with the channel ID as a big endian u32, then a byte that's 0 for text
+//! messages (followed by UTF-8) or 1 for binary messages, same as the
+//! websocket relay. Messages for channels that aren't open are dropped.
+
+use crate::{
+  connector::{
+    transport::{
+      framing::{frame_message, unframe_message},
+      ButtplugConnectorTransport, ButtplugTransportIncomingMessage,
+    },
+    ButtplugConnectorError, ButtplugConnectorResultFuture,
+  },
+  core::messages::serializer::ButtplugSerializedMessage,
+  util::async_manager,
+};
+use futures::future::BoxFuture;
+use std::{collections::HashMap, convert::TryInto, sync::Arc};
+use tokio::sync::{
+  mpsc::{channel, Receiver, Sender},
+  Mutex,
+};
+use tracing::Instrument;
+
+const CHANNEL_ID_LENGTH: usize = 4;
+
+fn frame_channel_message(
+  channel_id: u32,
+  msg: ButtplugSerializedMessage,
+) -> ButtplugSerializedMessage {
+  ButtplugSerializedMessage::Binary([&channel_id.to_be_bytes()[..], &frame_message(msg)].concat())
+}
+
+fn unframe_channel_message(frame: &[u8]) -> Option<(u32, ButtplugSerializedMessage)> {
+  if frame.len() <= CHANNEL_ID_LENGTH {
+    return None;
+  }
+  let (channel_id, rest) = frame.split_at(CHANNEL_ID_LENGTH);
+  let channel_id = u32::from_be_bytes(channel_id.try_into().ok()?);
+  Some((channel_id, unframe_message(rest)?))
+}
+
+#[derive(Default)]
+struct MultiplexerState {
+  /// Sends to the inner transport. Set while it's connected.
+  inner_outgoing_sender: Option<Sender<ButtplugSerializedMessage>>,
+  /// Open channels, by ID.
+  channels: HashMap<u32, Sender<ButtplugTransportIncomingMessage>>,
+}
+
+impl MultiplexerState {
+  /// Forgets a channel, closing the inner transport if it was the last one.
+  fn close_channel(&mut self, channel_id: u32) {
+    if self.channels.remove(&channel_id).is_some() && self.channels.is_empty() {
+      info!("Last multiplexed channel closed, closing inner transport.");
+      // Dropping the sender tells the inner transport to close.
+      self.inner_outgoing_sender = None;
+    }
+  }
+}
+
+/// Shares one transport between several [ButtplugMultiplexedTransport]
+/// channels. See the [module documentation][self] for details.
+pub struct ButtplugTransportMultiplexer<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  inner: Arc<TransportType>,
+  state: Arc<Mutex<MultiplexerState>>,
+}
+
+impl<TransportType> ButtplugTransportMultiplexer<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  pub fn new(inner: TransportType) -> Self {
+    Self {
+      inner: Arc::new(inner),
+      state: Arc::new(Mutex::new(MultiplexerState::default())),
+    }
+  }
+
+  /// Transport for channel `channel_id`, to hand to a connector. The other
+  /// end needs to open a channel with the same ID.
+  pub fn channel(&self, channel_id: u32) -> ButtplugMultiplexedTransport<TransportType> {
+    ButtplugMultiplexedTransport {
+      channel_id,
+      inner: self.inner.clone(),
+      state: self.state.clone(),
+    }
+  }
+}
+
+/// Routes messages from the inner transport to channels, until it closes.
+async fn route_incoming(
+  mut inner_incoming_receiver: Receiver<ButtplugTransportIncomingMessage>,
+  state: Arc<Mutex<MultiplexerState>>,
+) {
+  while let Some(incoming) = inner_incoming_receiver.recv().await {
+    match incoming {
+      ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(frame)) => {
+        let (channel_id, msg) = match unframe_channel_message(&frame) {
+          Some(unframed) => unframed,
+          None => {
+            error!("Received invalid multiplexed message, ignoring.");
+            continue;
+          }
+        };
+        let sender = state.lock().await.channels.get(&channel_id).cloned();
+        match sender {
+          Some(sender) => {
+            let _ = sender
+              .send(ButtplugTransportIncomingMessage::Message(msg))
+              .await;
+          }
+          None => warn!(
+            "Received message for multiplexed channel {}, which isn't open. Dropping.",
+            channel_id
+          ),
+        }
+      }
+      ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Text(_)) => {
+        error!("Received unframed text message on multiplexed transport, ignoring.");
+      }
+      ButtplugTransportIncomingMessage::Close(reason) => {
+        info!("Multiplexed transport closed: {}", reason);
+        break;
+      }
+      // Connected and errors aren't tied to a channel, so every channel gets
+      // them.
+      other => {
+        let senders: Vec<_> = state.lock().await.channels.values().cloned().collect();
+        for sender in senders {
+          let _ = sender.send(other.clone()).await;
+        }
+      }
+    }
+  }
+  // The inner transport is gone, so every channel is too. The next channel to
+  // connect will connect it again.
+  let mut state = state.lock().await;
+  state.inner_outgoing_sender = None;
+  for (_, sender) in state.channels.drain() {
+    let _ = sender
+      .send(ButtplugTransportIncomingMessage::Close(
+        "Multiplexed transport closed".to_owned(),
+      ))
+      .await;
+  }
+}
+
+/// One channel of a [ButtplugTransportMultiplexer].
+pub struct ButtplugMultiplexedTransport<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  channel_id: u32,
+  inner: Arc<TransportType>,
+  state: Arc<Mutex<MultiplexerState>>,
+}
+
+impl<TransportType> ButtplugConnectorTransport for ButtplugMultiplexedTransport<TransportType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+{
+  /// Connects the inner transport if no other channel has yet.
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let channel_id = self.channel_id;
+    let inner = self.inner.clone();
+    let state = self.state.clone();
+    Box::pin(async move {
+      // Held until the channel is registered, so channels connecting at the
+      // same time don't both try to connect the inner transport.
+      let mut locked_state = state.lock().await;
+      if locked_state.channels.contains_key(&channel_id) {
+        return Err(ButtplugConnectorError::ConnectorAlreadyConnected);
+      }
+      let inner_outgoing_sender = match &locked_state.inner_outgoing_sender {
+        Some(sender) => sender.clone(),
+        None => {
+          let (inner_outgoing_sender, inner_outgoing_receiver) = channel(256);
+          let (inner_incoming_sender, inner_incoming_receiver) = channel(256);
+          inner
+            .connect(inner_outgoing_receiver, inner_incoming_sender)
+            .await?;
+          async_manager::spawn_named(
+            "Multiplexed Transport Router",
+            route_incoming(inner_incoming_receiver, state.clone())
+              .instrument(tracing::info_span!("Multiplexed Transport Router")),
+          )
+          .unwrap();
+          locked_state.inner_outgoing_sender = Some(inner_outgoing_sender.clone());
+          inner_outgoing_sender
+        }
+      };
+      locked_state.channels.insert(channel_id, incoming_sender);
+      drop(locked_state);
+      debug!("Multiplexed channel {} connected.", channel_id);
+
+      let state = state.clone();
+      async_manager::spawn(
+        async move {
+          while let Some(msg) = outgoing_receiver.recv().await {
+            if inner_outgoing_sender
+              .send(frame_channel_message(channel_id, msg))
+              .await
+              .is_err()
+            {
+              break;
+            }
+          }
+          // Our connector is done with us.
+          drop(inner_outgoing_sender);
+          state.lock().await.close_channel(channel_id);
+        }
+        .instrument(tracing::info_span!("Multiplexed Channel Task", channel_id)),
+      )
+      .unwrap();
+      Ok(())
+    })
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let channel_id = self.channel_id;
+    let state = self.state;
+    Box::pin(async move {
+      state.lock().await.close_channel(channel_id);
+      Ok(())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_framing_round_trip() {
+    let text = ButtplugSerializedMessage::Text("[{\"Ok\":{\"Id\":1}}]".to_owned());
+    let framed = frame_channel_message(7, text.clone());
+    match &framed {
+      ButtplugSerializedMessage::Binary(frame) => {
+        assert_eq!(unframe_channel_message(frame), Some((7, text)))
+      }
+      _ => panic!("Framed messages should be binary"),
+    }
+    let binary = ButtplugSerializedMessage::Binary(vec![1, 2, 3]);
+    if let ButtplugSerializedMessage::Binary(frame) =
+      frame_channel_message(u32::MAX, binary.clone())
+    {
+      assert_eq!(unframe_channel_message(&frame), Some((u32::MAX, binary)));
+    }
+    // Too short, or an unknown message type.
+    assert!(unframe_channel_message(&[0, 0, 0, 1]).is_none());
+    assert!(unframe_channel_message(&[0, 0, 0, 1, 2, 0]).is_none());
+  }
+}
@@ -0,0 +1,195 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Compression of large websocket messages.
+//!
+//! Most Buttplug messages are tiny, but a DeviceList for a server with a lot of
+//! devices attached can run to tens of kilobytes of JSON. async-tungstenite
+//! doesn't support permessage-deflate, so compression is done here instead,
+//! and negotiated with the [COMPRESSION_SUBPROTOCOL] websocket subprotocol.
+//! Clients ask for it, and servers that have it turned on echo it back. If
+//! either side doesn't know about it, messages go over the wire as usual.
+//!
+//! Once negotiated, text messages under [COMPRESSION_THRESHOLD] bytes are
+//! still sent as text frames. Everything else is framed the same way as on
+//! other transports that carry messages as bytes (a byte saying whether it's
+//! text or binary, then the message), and sent as a binary frame starting
+//! with 1 if the rest is deflated, or 0 if not. Messages that inflate to more
+//! than [MAX_INFLATED_SIZE] bytes are rejected.
+
+use crate::{
+  connector::transport::framing::{frame_message, unframe_message},
+  core::messages::serializer::ButtplugSerializedMessage,
+};
+use async_tungstenite::tungstenite::protocol::Message;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::io::{Read, Write};
+
+/// Websocket subprotocol used to negotiate compression.
+pub const COMPRESSION_SUBPROTOCOL: &str = "buttplug-deflate";
+/// Messages smaller than this (in bytes) aren't worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+/// Largest message (in bytes) we'll inflate. A few kilobytes of deflated data
+/// can inflate to gigabytes, so this keeps a peer from making us allocate
+/// that.
+pub const MAX_INFLATED_SIZE: usize = 16 * 1024 * 1024;
+
+const STORED: u8 = 0;
+const DEFLATED: u8 = 1;
+
+/// True if a `Sec-WebSocket-Protocol` header value lists our subprotocol.
+pub(super) fn lists_compression_subprotocol(header: &str) -> bool {
+  header
+    .split(',')
+    .any(|protocol| protocol.trim() == COMPRESSION_SUBPROTOCOL)
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+  let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+  encoder
+    .write_all(data)
+    .expect("Writing to memory should never fail");
+  encoder
+    .finish()
+    .expect("Writing to memory should never fail")
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+  let mut inflated = vec![];
+  // Read one byte past the limit, so we can tell if it was hit.
+  DeflateDecoder::new(data)
+    .take(MAX_INFLATED_SIZE as u64 + 1)
+    .read_to_end(&mut inflated)
+    .map_err(|err| format!("Cannot decompress websocket message: {}", err))?;
+  if inflated.len() > MAX_INFLATED_SIZE {
+    return Err(format!(
+      "Decompressed websocket message is larger than {} bytes",
+      MAX_INFLATED_SIZE
+    ));
+  }
+  Ok(inflated)
+}
+
+/// Turns a serialized message into a websocket message, compressing it if
+/// compression was negotiated and it's large enough to bother.
+pub(super) fn encode_message(msg: ButtplugSerializedMessage, compressed: bool) -> Message {
+  if !compressed {
+    return match msg {
+      ButtplugSerializedMessage::Text(text) => Message::Text(text),
+      ButtplugSerializedMessage::Binary(bin) => Message::Binary(bin),
+    };
+  }
+  if let ButtplugSerializedMessage::Text(text) = &msg {
+    if text.len() < COMPRESSION_THRESHOLD {
+      return Message::Text(text.clone());
+    }
+  }
+  let framed = frame_message(msg);
+  if framed.len() < COMPRESSION_THRESHOLD {
+    Message::Binary([&[STORED][..], &framed].concat())
+  } else {
+    Message::Binary([&[DEFLATED][..], &deflate(&framed)].concat())
+  }
+}
+
+/// Turns the contents of a binary websocket message back into the message
+/// that was sent.
+pub(super) fn decode_binary_message(
+  data: Vec<u8>,
+  compressed: bool,
+) -> Result<ButtplugSerializedMessage, String> {
+  if !compressed {
+    return Ok(ButtplugSerializedMessage::Binary(data));
+  }
+  let framed = match data.split_first() {
+    Some((&STORED, framed)) => framed.to_vec(),
+    Some((&DEFLATED, deflated)) => inflate(deflated)?,
+    Some((compression, _)) => {
+      return Err(format!(
+        "Unknown websocket message compression {}",
+        compression
+      ))
+    }
+    None => return Err("Received empty binary message on compressed websocket".to_owned()),
+  };
+  unframe_message(&framed).ok_or_else(|| "Invalid compressed websocket message".to_owned())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn round_trip(msg: ButtplugSerializedMessage) -> Message {
+    let encoded = encode_message(msg.clone(), true);
+    let decoded = match encoded.clone() {
+      Message::Text(text) => ButtplugSerializedMessage::Text(text),
+      Message::Binary(data) => decode_binary_message(data, true).unwrap(),
+      _ => panic!("Encoding should only produce text or binary messages"),
+    };
+    assert_eq!(decoded, msg);
+    encoded
+  }
+
+  #[test]
+  fn test_compression_round_trip() {
+    // Small text stays as-is.
+    let small = ButtplugSerializedMessage::Text("[{\"Ok\":{\"Id\":1}}]".to_owned());
+    assert!(matches!(round_trip(small), Message::Text(_)));
+    // Large text gets deflated, and ends up smaller.
+    let large_text = "[{\"DeviceList\":{\"Id\":1,\"Devices\":[]}}]".repeat(100);
+    match round_trip(ButtplugSerializedMessage::Text(large_text.clone())) {
+      Message::Binary(data) => assert!(data.len() < large_text.len()),
+      _ => panic!("Large text messages should be compressed"),
+    }
+    round_trip(ButtplugSerializedMessage::Binary(vec![1, 2, 3]));
+    let large_binary = vec![7; COMPRESSION_THRESHOLD * 4];
+    round_trip(ButtplugSerializedMessage::Binary(large_binary));
+  }
+
+  #[test]
+  fn test_compression_disabled_passthrough() {
+    let large_text = "a".repeat(COMPRESSION_THRESHOLD * 4);
+    assert_eq!(
+      encode_message(ButtplugSerializedMessage::Text(large_text.clone()), false),
+      Message::Text(large_text)
+    );
+    assert_eq!(
+      decode_binary_message(vec![DEFLATED, 1, 2], false).unwrap(),
+      ButtplugSerializedMessage::Binary(vec![DEFLATED, 1, 2])
+    );
+    assert!(decode_binary_message(vec![], true).is_err());
+    assert!(decode_binary_message(vec![9, 1, 2], true).is_err());
+    assert!(decode_binary_message(vec![STORED, 9, 1], true).is_err());
+  }
+
+  #[test]
+  fn test_decompression_size_limit() {
+    // A binary message that inflates to just over the limit. It's all zeros,
+    // so deflates down to almost nothing.
+    let bomb = deflate(&frame_message(ButtplugSerializedMessage::Binary(vec![
+      0;
+      MAX_INFLATED_SIZE
+    ])));
+    assert!(bomb.len() < 32 * 1024);
+    let err = decode_binary_message([&[DEFLATED][..], &bomb].concat(), true).unwrap_err();
+    assert!(err.contains("larger than"));
+    // Right at the limit is fine.
+    let max = deflate(&frame_message(ButtplugSerializedMessage::Binary(vec![
+      0;
+      MAX_INFLATED_SIZE
+        - 1
+    ])));
+    assert!(decode_binary_message([&[DEFLATED][..], &max].concat(), true).is_ok());
+  }
+
+  #[test]
+  fn test_subprotocol_header_matching() {
+    assert!(lists_compression_subprotocol("buttplug-deflate"));
+    assert!(lists_compression_subprotocol("other, buttplug-deflate"));
+    assert!(!lists_compression_subprotocol("buttplug-deflate-v2"));
+  }
+}
@@ -1,8 +1,13 @@
+pub mod compression;
 pub mod websocket_client;
+pub mod websocket_relay;
 pub mod websocket_server;
 
 pub use async_tungstenite::tungstenite::Error as TungsteniteError;
 pub use websocket_client::ButtplugWebsocketClientTransport;
+pub use websocket_relay::{
+  ButtplugRelayControlMessage, ButtplugRelayRole, ButtplugWebsocketRelayTransport,
+};
 
 pub use websocket_server::{
   ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportOptions,
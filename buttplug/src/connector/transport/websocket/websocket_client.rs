@@ -7,6 +7,9 @@
 
 //! Handling of websockets using async-tungstenite
 
+use super::compression::{
+  decode_binary_message, encode_message, lists_compression_subprotocol, COMPRESSION_SUBPROTOCOL,
+};
 use crate::{
   connector::{
     transport::{
@@ -18,7 +21,14 @@ use crate::{
   core::messages::serializer::ButtplugSerializedMessage,
   util::async_manager,
 };
-use async_tungstenite::{tokio::connect_async_with_tls_connector, tungstenite::protocol::Message};
+use async_tungstenite::{
+  tokio::connect_async_with_tls_connector,
+  tungstenite::{
+    client::IntoClientRequest,
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+    protocol::Message,
+  },
+};
 use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
 use std::sync::Arc;
 use tokio::sync::{
@@ -36,6 +46,9 @@ pub struct ButtplugWebsocketClientTransport {
   /// If true, bypass certificate verification. Should be true for self-signed
   /// certs.
   bypass_cert_verify: bool,
+  /// If true, ask the server to compress large messages. See
+  /// [compression][super::compression].
+  request_compression: bool,
   /// Internally held sender, used for when disconnect is called.
   disconnect_notifier: Arc<Notify>,
 }
@@ -46,6 +59,7 @@ impl ButtplugWebsocketClientTransport {
       should_use_tls,
       address: address.to_owned(),
       bypass_cert_verify,
+      request_compression: false,
       disconnect_notifier: Arc::new(Notify::new()),
     }
   }
@@ -69,6 +83,15 @@ impl ButtplugWebsocketClientTransport {
   pub fn new_secure_connector(address: &str, bypass_cert_verify: bool) -> Self {
     ButtplugWebsocketClientTransport::create(address, true, bypass_cert_verify)
   }
+
+  /// Asks the server to compress large messages (i.e. DeviceLists on servers
+  /// with lots of devices). Servers that don't support compression, or don't
+  /// have it turned on, will just ignore the request, so this is always safe
+  /// to use.
+  pub fn with_compression(mut self) -> Self {
+    self.request_compression = true;
+    self
+  }
 }
 
 impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
@@ -101,10 +124,32 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
       None
     };
     let address = self.address.clone();
+    let request_compression = self.request_compression;
 
     Box::pin(async move {
-      match connect_async_with_tls_connector(&address, tls_connector).await {
-        Ok((stream, _)) => {
+      let mut request = address.into_client_request().map_err(|err| {
+        ButtplugConnectorError::TransportSpecificError(
+          ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+        )
+      })?;
+      if request_compression {
+        request.headers_mut().insert(
+          SEC_WEBSOCKET_PROTOCOL,
+          HeaderValue::from_static(COMPRESSION_SUBPROTOCOL),
+        );
+      }
+      match connect_async_with_tls_connector(request, tls_connector).await {
+        Ok((stream, response)) => {
+          // Only compress if the server agreed to.
+          let compressed = request_compression
+            && response
+              .headers()
+              .get(SEC_WEBSOCKET_PROTOCOL)
+              .and_then(|protocol| protocol.to_str().ok())
+              .map_or(false, lists_compression_subprotocol);
+          if compressed {
+            info!("Websocket server agreed to message compression.");
+          }
           let (mut writer, mut reader) = stream.split();
           async_manager::spawn(
             async move {
@@ -112,10 +157,7 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
                 select! {
                   msg = outgoing_receiver.recv().fuse() => {
                     if let Some(msg) = msg {
-                      let out_msg = match msg {
-                        ButtplugSerializedMessage::Text(text) => Message::Text(text),
-                        ButtplugSerializedMessage::Binary(bin) => Message::Binary(bin),
-                      };
+                      let out_msg = encode_message(msg, compressed);
                       // TODO see what happens when we try to send to a remote that's closed connection.
                       writer.send(out_msg).await.expect("This should never fail?");
                     } else {
@@ -158,10 +200,15 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
                       }
                     }
                     Message::Binary(v) => {
+                      let msg = match decode_binary_message(v, compressed) {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                          error!("{}", err);
+                          continue;
+                        }
+                      };
                       if incoming_sender
-                        .send(ButtplugTransportIncomingMessage::Message(
-                          ButtplugSerializedMessage::Binary(v),
-                        ))
+                        .send(ButtplugTransportIncomingMessage::Message(msg))
                         .await
                         .is_err()
                       {
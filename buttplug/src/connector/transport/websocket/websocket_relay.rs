@@ -0,0 +1,246 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Connecting through a websocket relay server.
+//!
+//! When neither side of a connection can accept incoming connections (both
+//! behind NATs, no port forwarding), both can connect out to a relay server
+//! instead, and meet in a room named by a code they've shared some other way.
+//! The relay pairs up one [ButtplugRelayRole::Client] and one
+//! [ButtplugRelayRole::Server] per room, then passes messages between them.
+//! Relays can read everything passing through them, so wrap this transport in
+//! an encrypted transport (see the `e2e-encryption` feature) if the relay
+//! isn't trusted.
+//!
+//! # Relay Protocol
+//!
+//! Control messages are JSON in websocket text frames:
+//!
+//! - Peer to relay, once connected: `{"Join":{"room":"<code>","role":"Client"}}`
+//!   (or `"Server"`).
+//! - Relay to peer: `"Joined"` once in the room, then
+//!   `{"PeerJoined":{"role":"Server"}}` once the other side is there.
+//!   `"PeerLeft"` if the other side goes away, and
+//!   `{"Error":{"message":"..."}}` if joining fails (room full, etc).
+//!
+//! After `PeerJoined`, Buttplug messages go in binary frames, which the relay
+//! forwards to the other peer untouched. The first byte of each frame is 0 for
+//! text messages (followed by UTF-8) or 1 for binary messages.
+
+use crate::{
+  connector::{
+    transport::{
+      framing::{frame_message, unframe_message},
+      ButtplugConnectorTransport, ButtplugConnectorTransportSpecificError,
+      ButtplugTransportIncomingMessage,
+    },
+    ButtplugConnectorError, ButtplugConnectorResultFuture,
+  },
+  core::messages::serializer::ButtplugSerializedMessage,
+  util::async_manager,
+};
+use async_tungstenite::{tokio::connect_async, tungstenite::protocol::Message};
+use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{
+  mpsc::{Receiver, Sender},
+  Notify,
+};
+use tracing::Instrument;
+
+/// Which side of the Buttplug connection a relay peer is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ButtplugRelayRole {
+  Client,
+  Server,
+}
+
+/// Control messages between relay peers and the relay server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ButtplugRelayControlMessage {
+  Join { room: String, role: ButtplugRelayRole },
+  Joined,
+  PeerJoined { role: ButtplugRelayRole },
+  PeerLeft,
+  Error { message: String },
+}
+
+fn relay_error(message: impl Into<String>) -> ButtplugConnectorError {
+  ButtplugConnectorError::ConnectorGenericError(message.into())
+}
+
+/// Transport that meets its peer in a room on a websocket relay server. See
+/// the [module documentation][self] for details.
+pub struct ButtplugWebsocketRelayTransport {
+  address: String,
+  room: String,
+  role: ButtplugRelayRole,
+  disconnect_notifier: Arc<Notify>,
+}
+
+impl ButtplugWebsocketRelayTransport {
+  /// Creates a transport that joins `room` on the relay at `address` (i.e.
+  /// "wss://relay.example.com"). Client connectors should use
+  /// [ButtplugRelayRole::Client], server connectors
+  /// [ButtplugRelayRole::Server].
+  pub fn new(address: &str, room: &str, role: ButtplugRelayRole) -> Self {
+    Self {
+      address: address.to_owned(),
+      room: room.to_owned(),
+      role,
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
+impl ButtplugConnectorTransport for ButtplugWebsocketRelayTransport {
+  /// Resolves once the other peer has joined the room, which may take a while.
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let disconnect_notifier = self.disconnect_notifier.clone();
+    let address = self.address.clone();
+    let join = ButtplugRelayControlMessage::Join {
+      room: self.room.clone(),
+      role: self.role,
+    };
+    let role = self.role;
+
+    Box::pin(async move {
+      let (stream, _) = connect_async(&address).await.map_err(|err| {
+        ButtplugConnectorError::TransportSpecificError(
+          ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+        )
+      })?;
+      let (mut writer, mut reader) = stream.split();
+      writer
+        .send(Message::Text(serde_json::to_string(&join).unwrap()))
+        .await
+        .map_err(|_| ButtplugConnectorError::ConnectorChannelClosed)?;
+
+      // Wait in the room until our peer shows up.
+      loop {
+        let msg = match reader.next().await {
+          Some(Ok(Message::Text(text))) => text,
+          Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+          Some(Ok(_)) => return Err(relay_error("Unexpected message from relay")),
+          Some(Err(_)) | None => return Err(ButtplugConnectorError::ConnectorChannelClosed),
+        };
+        match serde_json::from_str(&msg) {
+          Ok(ButtplugRelayControlMessage::Joined) => {
+            info!("Joined relay room, waiting for peer.");
+          }
+          Ok(ButtplugRelayControlMessage::PeerJoined { role: peer_role }) => {
+            if peer_role == role {
+              return Err(relay_error(format!(
+                "Relay peer is also a {:?}, cannot connect",
+                role
+              )));
+            }
+            info!("Relay peer joined.");
+            break;
+          }
+          Ok(ButtplugRelayControlMessage::Error { message }) => {
+            return Err(relay_error(format!("Relay error: {}", message)))
+          }
+          _ => return Err(relay_error(format!("Unexpected message from relay: {}", msg))),
+        }
+      }
+
+      async_manager::spawn(
+        async move {
+          loop {
+            select! {
+              msg = outgoing_receiver.recv().fuse() => match msg {
+                Some(msg) => {
+                  if writer.send(Message::Binary(frame_message(msg))).await.is_err() {
+                    error!("Cannot send to relay, considering connection closed.");
+                    let _ = incoming_sender
+                      .send(ButtplugTransportIncomingMessage::Close(
+                        "Relay connection closed".to_owned(),
+                      ))
+                      .await;
+                    return;
+                  }
+                }
+                None => {
+                  info!("Connector holding relay transport dropped, returning");
+                  writer.close().await.unwrap_or_else(|err| error!("{}", err));
+                  return;
+                }
+              },
+              msg = reader.next().fuse() => {
+                let incoming = match msg {
+                  Some(Ok(Message::Binary(frame))) => match unframe_message(&frame) {
+                    Some(msg) => ButtplugTransportIncomingMessage::Message(msg),
+                    None => {
+                      error!("Invalid frame from relay peer, ignoring.");
+                      continue;
+                    }
+                  },
+                  Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ButtplugRelayControlMessage::PeerLeft) => {
+                      ButtplugTransportIncomingMessage::Close("Relay peer left".to_owned())
+                    }
+                    _ => {
+                      error!("Unexpected control message from relay: {}", text);
+                      continue;
+                    }
+                  },
+                  Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                  Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    ButtplugTransportIncomingMessage::Close("Relay connection closed".to_owned())
+                  }
+                };
+                let closing = matches!(incoming, ButtplugTransportIncomingMessage::Close(_));
+                if incoming_sender.send(incoming).await.is_err() || closing {
+                  return;
+                }
+              },
+              _ = disconnect_notifier.notified().fuse() => {
+                info!("Relay transport requested to disconnect.");
+                writer.close().await.unwrap_or_else(|err| error!("{}", err));
+                return;
+              }
+            }
+          }
+        }
+        .instrument(tracing::info_span!("Websocket Relay Task")),
+      )
+      .unwrap();
+      Ok(())
+    })
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    Box::pin(async move {
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugRelayControlMessage, ButtplugRelayRole};
+
+  #[test]
+  fn test_relay_control_messages() {
+    assert_eq!(
+      serde_json::to_string(&ButtplugRelayControlMessage::Join {
+        room: "abc123".to_owned(),
+        role: ButtplugRelayRole::Client
+      })
+      .unwrap(),
+      r#"{"Join":{"room":"abc123","role":"Client"}}"#
+    );
+  }
+}
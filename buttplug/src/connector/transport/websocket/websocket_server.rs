@@ -1,3 +1,6 @@
+use super::compression::{
+  decode_binary_message, encode_message, lists_compression_subprotocol, COMPRESSION_SUBPROTOCOL,
+};
 use crate::{
   connector::{
     transport::{
@@ -9,6 +12,10 @@ use crate::{
   core::messages::serializer::ButtplugSerializedMessage,
   util::async_manager,
 };
+use async_tungstenite::tungstenite::{
+  handshake::server::{ErrorResponse, Request, Response},
+  http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+};
 use futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt};
 use std::sync::Arc;
 use tokio::net::TcpListener;
@@ -24,6 +31,9 @@ pub struct ButtplugWebsocketServerTransportOptions {
   /// Insecure port for listening for websocket connections. Secure ports were
   /// removed, but this name was left as is to minimize code breakage.
   pub ws_insecure_port: u16,
+  /// If true, compress large messages for clients that ask for it. See
+  /// [compression][super::compression].
+  pub compress_messages: bool,
 }
 
 async fn run_connection_loop<S>(
@@ -31,6 +41,7 @@ async fn run_connection_loop<S>(
   mut request_receiver: Receiver<ButtplugSerializedMessage>,
   response_sender: Sender<ButtplugTransportIncomingMessage>,
   disconnect_notifier: Arc<Notify>,
+  compressed: bool,
 ) where
   S: AsyncRead + AsyncWrite + Unpin,
 {
@@ -49,25 +60,12 @@ async fn run_connection_loop<S>(
       },
       serialized_msg = request_receiver.recv().fuse() => {
         if let Some(serialized_msg) = serialized_msg {
-          match serialized_msg {
-            ButtplugSerializedMessage::Text(text_msg) => {
-              if websocket_server_sender
-                .send(async_tungstenite::tungstenite::Message::Text(text_msg))
-                .await
-                .is_err() {
-                error!("Cannot send text value to server, considering connection closed.");
-                return;
-              }
-            }
-            ButtplugSerializedMessage::Binary(binary_msg) => {
-              if websocket_server_sender
-                .send(async_tungstenite::tungstenite::Message::Binary(binary_msg))
-                .await
-                .is_err() {
-                error!("Cannot send binary value to server, considering connection closed.");
-                return;
-              }
-            }
+          if websocket_server_sender
+            .send(encode_message(serialized_msg, compressed))
+            .await
+            .is_err() {
+            error!("Cannot send value to server, considering connection closed.");
+            return;
           }
         } else {
           info!("Websocket server connector owner dropped, disconnecting websocket connection.");
@@ -101,8 +99,16 @@ async fn run_connection_loop<S>(
                   // noop
                   continue;
                 }
-                async_tungstenite::tungstenite::Message::Binary(_) => {
-                  error!("Don't know how to handle binary message types!");
+                async_tungstenite::tungstenite::Message::Binary(binary_msg) => {
+                  match decode_binary_message(binary_msg, compressed) {
+                    Ok(msg) => {
+                      if response_sender.send(ButtplugTransportIncomingMessage::Message(msg)).await.is_err() {
+                        error!("Connector that owns transport no longer available, exiting.");
+                        break;
+                      }
+                    }
+                    Err(err) => error!("{}", err),
+                  }
                 }
               }
             },
@@ -158,27 +164,51 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
     let request_receiver_clone = request_receiver.clone();
     let response_sender_clone = incoming_sender.clone();
     let disconnect_notifier_clone = disconnect_notifier.clone();
+    let compress_messages = self.options.compress_messages;
     let fut = async move {
       // Create the event loop and TCP listener we'll accept connections on.
       let try_socket = TcpListener::bind(&addr).await;
       debug!("Websocket Insecure: Socket bound.");
-      let listener = try_socket.map_err(|e| ButtplugConnectorError::TransportSpecificError(ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", e))))?;
+      let listener = try_socket.map_err(|e| ButtplugConnectorError::TransportSpecificError(ButtplugConnectorTransportSpecificError::IoError(e)))?;
       debug!("Websocket Insecure: Listening on: {}", addr);
       if let Ok((stream, _)) = listener.accept().await {
         info!("Websocket Insecure: Got connection");
-        let ws_fut = async_tungstenite::tokio::accept_async(stream);
+        let mut compressed = false;
+        // Agree to compression if the client asked for it and we're allowed to.
+        // The error type is tungstenite's, so there's no making it smaller.
+        #[allow(clippy::result_large_err)]
+        let negotiate_compression = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+          compressed = compress_messages
+            && request
+              .headers()
+              .get(SEC_WEBSOCKET_PROTOCOL)
+              .and_then(|protocol| protocol.to_str().ok())
+              .map_or(false, lists_compression_subprotocol);
+          if compressed {
+            response.headers_mut().insert(
+              SEC_WEBSOCKET_PROTOCOL,
+              HeaderValue::from_static(COMPRESSION_SUBPROTOCOL),
+            );
+          }
+          Ok(response)
+        };
+        let ws_fut = async_tungstenite::tokio::accept_hdr_async(stream, negotiate_compression);
         let ws_stream = ws_fut.await.map_err(|err| {
           error!("Websocket server accept error: {:?}", err);
           ButtplugConnectorError::TransportSpecificError(
             ButtplugConnectorTransportSpecificError::TungsteniteError(err),
           )
         })?;
+        if compressed {
+          info!("Websocket Insecure: Client asked for message compression, enabling.");
+        }
         async_manager::spawn(async move {
           run_connection_loop(
             ws_stream,
             (*request_receiver_clone.lock().await).take().unwrap(),
             response_sender_clone,
             disconnect_notifier_clone,
+            compressed,
           )
           .await;
         })
@@ -14,11 +14,88 @@ use crate::device::Endpoint;
 use crate::server::comm_managers::ButtplugDeviceSpecificError;
 use displaydoc::Display;
 use futures::future::BoxFuture;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{error, fmt, sync::Arc};
 use thiserror::Error;
 
 pub type ButtplugResult<T = ()> = Result<T, ButtplugError>;
 
+/// Holds an error from outside of Buttplug (io, serde, btleplug, etc...) that
+/// caused one of our errors.
+///
+/// Buttplug errors need to be clonable and serializable, and most library
+/// error types are neither, so we used to just format them into strings. This
+/// keeps the original error around so it can be handed back via
+/// [std::error::Error::source], while still cloning cheaply and serializing as
+/// its message. Errors that come from deserialization only have the message.
+#[derive(Clone)]
+pub struct ButtplugErrorCause {
+  message: String,
+  error: Option<Arc<dyn error::Error + Send + Sync + 'static>>,
+}
+
+impl ButtplugErrorCause {
+  pub fn new<E>(error: E) -> Self
+  where
+    E: error::Error + Send + Sync + 'static,
+  {
+    Self {
+      message: error.to_string(),
+      error: Some(Arc::new(error)),
+    }
+  }
+
+  /// Returns the original error, if we still have it.
+  pub fn error(&self) -> Option<&(dyn error::Error + 'static)> {
+    match &self.error {
+      Some(err) => Some(err.as_ref()),
+      None => None,
+    }
+  }
+}
+
+impl From<String> for ButtplugErrorCause {
+  fn from(message: String) -> Self {
+    Self {
+      message,
+      error: None,
+    }
+  }
+}
+
+impl fmt::Display for ButtplugErrorCause {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+impl fmt::Debug for ButtplugErrorCause {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.error {
+      Some(err) => fmt::Debug::fmt(err, f),
+      None => fmt::Debug::fmt(&self.message, f),
+    }
+  }
+}
+
+impl Serialize for ButtplugErrorCause {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(&self.message)
+  }
+}
+
+impl<'de> Deserialize<'de> for ButtplugErrorCause {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    String::deserialize(deserializer).map(ButtplugErrorCause::from)
+  }
+}
+
 /// Handshake errors occur while a client is connecting to a server. This
 /// usually involves protocol handshake errors. For connector errors (i.e. when
 /// a remote network connection cannot be established), see
@@ -41,6 +118,8 @@ pub enum ButtplugHandshakeError {
   RequestServerInfoExpected,
   /// Handshake already happened, cannot run handshake again.
   HandshakeAlreadyHappened,
+  /// Handshake was rejected, server must be disconnected before trying again.
+  HandshakeRejected,
   /// Server spec version ({0}) must be equal or greater than client version ({1})
   MessageSpecVersionMismatch(ButtplugMessageSpecVersion, ButtplugMessageSpecVersion),
   /// Untyped Deserialized Error: {0}
@@ -71,6 +150,8 @@ pub enum ButtplugMessageError {
   InvalidMessageContents(String),
   /// Unhandled message type: {0}
   UnhandledMessage(String),
+  /// Message type {0} is not permitted for this client.
+  MessageNotPermitted(String),
   /// Message validation error(s): {0}
   ValidationError(String),
   /// Message serialization error
@@ -162,6 +243,22 @@ pub enum ButtplugDeviceError {
   UntypedDeserializedError(String),
   /// Device Configuration File Error: {0}
   DeviceConfigurationFileError(String),
+  /// User device configuration version {0} is newer than the newest supported version ({1})
+  UserDeviceConfigurationVersionUnsupported(u32, u32),
+  /// Write to device {0} did not finish within {1}ms
+  DeviceWriteTimeout(String, u64),
+  /// Waiting for the user to allow access to device {0}
+  DeviceConsentPending(u32),
+  /// User denied access to device {0}
+  DeviceConsentDenied(u32),
+  /// Device {0} is getting more than {1} commands a second, command dropped
+  DeviceCommandRateExceeded(u32, u32),
+  /// Session time limit for device {0} has run out
+  DeviceSessionExpired(u32),
+  /// Device does not support KeyedDeviceCmd key {0}
+  KeyNotSupported(String),
+  /// Device attributes for {0} are invalid: {1}
+  InvalidMessageAttributes(ButtplugDeviceMessageType, String),
 }
 
 /// Unknown errors occur in exceptional circumstances where no other error type
@@ -22,6 +22,11 @@ pub struct DeviceAdded {
   device_name: String,
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
   device_messages: DeviceMessageAttributesMap,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceMetadata", default, skip_serializing_if = "Option::is_none")
+  )]
+  device_metadata: Option<DeviceMetadata>,
 }
 
 impl DeviceAdded {
@@ -35,9 +40,17 @@ impl DeviceAdded {
       device_index,
       device_name: device_name.to_string(),
       device_messages: device_messages.clone(),
+      device_metadata: None,
     }
   }
 
+  /// Attaches what the comm manager knew about the device when it was found.
+  /// Only sent on the current spec version.
+  pub fn with_device_metadata(mut self, device_metadata: DeviceMetadata) -> Self {
+    self.device_metadata = Some(device_metadata);
+    self
+  }
+
   pub fn device_index(&self) -> u32 {
     self.device_index
   }
@@ -49,6 +62,10 @@ impl DeviceAdded {
   pub fn device_messages(&self) -> &DeviceMessageAttributesMap {
     &self.device_messages
   }
+
+  pub fn device_metadata(&self) -> &Option<DeviceMetadata> {
+    &self.device_metadata
+  }
 }
 
 impl ButtplugMessageValidator for DeviceAdded {
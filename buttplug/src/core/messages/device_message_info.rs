@@ -65,6 +65,17 @@ impl From<DeviceAdded> for DeviceMessageInfo {
   }
 }
 
+impl From<DeviceUpdated> for DeviceMessageInfo {
+  fn from(device_updated: DeviceUpdated) -> Self {
+    Self {
+      device_index: device_updated.device_index(),
+      device_name: device_updated.device_name().clone(),
+      device_messages: device_updated.device_messages().clone(),
+      original_device_messages: device_updated.device_messages().clone(),
+    }
+  }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct DeviceMessageInfoV1 {
@@ -102,6 +113,10 @@ impl From<DeviceMessageInfo> for DeviceMessageInfoV1 {
       ButtplugDeviceMessageType::RawUnsubscribeCmd,
       ButtplugDeviceMessageType::BatteryLevelCmd,
       ButtplugDeviceMessageType::RSSILevelCmd,
+      ButtplugDeviceMessageType::KeyedDeviceCmd,
+      ButtplugDeviceMessageType::RotateToAngleCmd,
+      ButtplugDeviceMessageType::TemperatureCmd,
+      ButtplugDeviceMessageType::SuctionCmd,
     ];
     for t in &v2_message_types {
       dmi_v1.device_messages.remove(t);
@@ -0,0 +1,91 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// How the server talks to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum DeviceTransport {
+  BluetoothLE,
+  Serial,
+  HID,
+  XInput,
+  LovenseDongle,
+  LovenseConnect,
+  Test,
+  Unknown,
+}
+
+/// Details about a device that comm managers know at discovery time, sent
+/// along with [DeviceAdded][super::DeviceAdded] so applications can show
+/// something more useful than a name. Anything the transport doesn't know is
+/// left empty.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceMetadata {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Transport"))]
+  transport: DeviceTransport,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "SignalStrength", default, skip_serializing_if = "Option::is_none")
+  )]
+  signal_strength: Option<i16>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "VendorId", default, skip_serializing_if = "Option::is_none")
+  )]
+  vendor_id: Option<u16>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "ProductId", default, skip_serializing_if = "Option::is_none")
+  )]
+  product_id: Option<u16>,
+}
+
+impl DeviceMetadata {
+  pub fn new(transport: DeviceTransport) -> Self {
+    Self {
+      transport,
+      signal_strength: None,
+      vendor_id: None,
+      product_id: None,
+    }
+  }
+
+  /// Sets the signal strength (RSSI, in dBm) the device was found with.
+  pub fn with_signal_strength(mut self, signal_strength: i16) -> Self {
+    self.signal_strength = Some(signal_strength);
+    self
+  }
+
+  /// Sets the USB/HID vendor and product IDs of the device.
+  pub fn with_usb_ids(mut self, vendor_id: u16, product_id: u16) -> Self {
+    self.vendor_id = Some(vendor_id);
+    self.product_id = Some(product_id);
+    self
+  }
+
+  pub fn transport(&self) -> DeviceTransport {
+    self.transport
+  }
+
+  /// Signal strength (RSSI, in dBm) at discovery time, if the transport has
+  /// one.
+  pub fn signal_strength(&self) -> Option<i16> {
+    self.signal_strength
+  }
+
+  pub fn vendor_id(&self) -> Option<u16> {
+    self.vendor_id
+  }
+
+  pub fn product_id(&self) -> Option<u16> {
+    self.product_id
+  }
+}
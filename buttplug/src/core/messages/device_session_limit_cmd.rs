@@ -0,0 +1,54 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Limits how long a device runs before the server winds it down. Handled by
+/// the server itself, so every device supports it. A duration of 0 removes
+/// the limit.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceSessionLimitCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  /// Milliseconds from the first command until the device starts winding
+  /// down.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Duration"))]
+  duration: u32,
+  /// Milliseconds to ramp down to a stop over.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "WindDown"))]
+  wind_down: u32,
+}
+
+impl DeviceSessionLimitCmd {
+  pub fn new(device_index: u32, duration: u32, wind_down: u32) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      duration,
+      wind_down,
+    }
+  }
+
+  pub fn duration(&self) -> u32 {
+    self.duration
+  }
+
+  pub fn wind_down(&self) -> u32 {
+    self.wind_down
+  }
+}
+
+impl ButtplugMessageValidator for DeviceSessionLimitCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
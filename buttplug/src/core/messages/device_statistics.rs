@@ -0,0 +1,79 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// How a device's connection has been behaving, as requested by
+/// [DeviceStatisticsCmd]. Lets applications warn about flaky devices before
+/// they fail outright.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceStatistics {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "WriteCount"))]
+  write_count: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ErrorCount"))]
+  error_count: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "AverageWriteLatency"))]
+  average_write_latency: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "LastError", default, skip_serializing_if = "Option::is_none")
+  )]
+  last_error: Option<String>,
+}
+
+impl DeviceStatistics {
+  pub fn new(
+    device_index: u32,
+    write_count: u32,
+    error_count: u32,
+    average_write_latency: u32,
+    last_error: Option<String>,
+  ) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      write_count,
+      error_count,
+      average_write_latency,
+      last_error,
+    }
+  }
+
+  /// Writes sent to the device since it connected.
+  pub fn write_count(&self) -> u32 {
+    self.write_count
+  }
+
+  /// Reads and writes that have failed since the device connected.
+  pub fn error_count(&self) -> u32 {
+    self.error_count
+  }
+
+  /// Average time taken by recent writes, in milliseconds. 0 if nothing has
+  /// been written yet.
+  pub fn average_write_latency(&self) -> u32 {
+    self.average_write_latency
+  }
+
+  /// Description of the most recent read or write failure, if any.
+  pub fn last_error(&self) -> &Option<String> {
+    &self.last_error
+  }
+}
+
+impl ButtplugMessageValidator for DeviceStatistics {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -0,0 +1,93 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Last position a linear actuator was sent to, as part of [DeviceStatus].
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct LinearPosition {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Position"))]
+  position: f64,
+}
+
+impl LinearPosition {
+  pub fn new(index: u32, position: f64) -> Self {
+    Self { index, position }
+  }
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  pub fn position(&self) -> f64 {
+    self.position
+  }
+}
+
+/// What a device's features were last told to do, as requested by
+/// [DeviceStatusCmd]. Speeds are quantized to the device's steps, so they
+/// match what the hardware actually got.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceStatus {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Speeds"))]
+  speeds: Vec<VibrateSubcommand>,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Rotations"))]
+  rotations: Vec<RotationSubcommand>,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Positions"))]
+  positions: Vec<LinearPosition>,
+}
+
+impl DeviceStatus {
+  pub fn new(
+    device_index: u32,
+    speeds: Vec<VibrateSubcommand>,
+    rotations: Vec<RotationSubcommand>,
+    positions: Vec<LinearPosition>,
+  ) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      speeds,
+      rotations,
+      positions,
+    }
+  }
+
+  /// Speed of each vibrator. Empty if the device doesn't vibrate.
+  pub fn speeds(&self) -> &Vec<VibrateSubcommand> {
+    &self.speeds
+  }
+
+  /// Speed and direction of each rotator. Empty if the device doesn't
+  /// rotate.
+  pub fn rotations(&self) -> &Vec<RotationSubcommand> {
+    &self.rotations
+  }
+
+  /// Last position of each linear actuator. Actuators that haven't been
+  /// moved since the device connected are left out, since there's no way to
+  /// know where they are.
+  pub fn positions(&self) -> &Vec<LinearPosition> {
+    &self.positions
+  }
+}
+
+impl ButtplugMessageValidator for DeviceStatus {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -0,0 +1,60 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Sent when a connected device's name or message attributes change, i.e.
+/// after the user renames it, or once firmware dependent setup has found what
+/// the device can actually do. Carries everything DeviceAdded did, other than
+/// metadata, so clients can replace what they had.
+#[derive(Default, ButtplugMessage, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceUpdated {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceName"))]
+  device_name: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
+  device_messages: DeviceMessageAttributesMap,
+}
+
+impl DeviceUpdated {
+  pub fn new(
+    device_index: u32,
+    device_name: &str,
+    device_messages: &DeviceMessageAttributesMap,
+  ) -> Self {
+    Self {
+      id: 0,
+      device_index,
+      device_name: device_name.to_string(),
+      device_messages: device_messages.clone(),
+    }
+  }
+
+  pub fn device_index(&self) -> u32 {
+    self.device_index
+  }
+
+  pub fn device_name(&self) -> &String {
+    &self.device_name
+  }
+
+  pub fn device_messages(&self) -> &DeviceMessageAttributesMap {
+    &self.device_messages
+  }
+}
+
+impl ButtplugMessageValidator for DeviceUpdated {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_system_id(self.id)
+  }
+}
@@ -0,0 +1,61 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Triggers a feature specific to a device (lights, modes, etc), that
+/// doesn't warrant a generic message of its own.
+///
+/// Devices list the keys they support in the
+/// [keys][crate::core::messages::DeviceMessageAttributes::keys] attribute of
+/// KeyedDeviceCmd. What values are valid depends on the key, and is checked by
+/// the device's protocol.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct KeyedDeviceCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Key"))]
+  key: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Value"))]
+  value: String,
+}
+
+impl KeyedDeviceCmd {
+  pub fn new(device_index: u32, key: &str, value: &str) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      key: key.to_owned(),
+      value: value.to_owned(),
+    }
+  }
+
+  pub fn key(&self) -> &str {
+    &self.key
+  }
+
+  pub fn value(&self) -> &str {
+    &self.value
+  }
+}
+
+impl ButtplugMessageValidator for KeyedDeviceCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if self.key.is_empty() {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "KeyedDeviceCmd key cannot be empty.".to_owned(),
+      ));
+    }
+    Ok(())
+  }
+}
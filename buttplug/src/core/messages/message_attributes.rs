@@ -5,8 +5,10 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use crate::device::Endpoint;
+use super::ButtplugDeviceMessageType;
+use crate::{core::errors::ButtplugDeviceError, device::Endpoint};
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
 // Unlike other message components, MessageAttributes is always turned on for
 // serialization, because it's used by device configuration files also.
@@ -30,6 +32,10 @@ pub struct DeviceMessageAttributes {
   #[serde(rename = "MaxDuration")]
   #[serde(skip_serializing_if = "Option::is_none")]
   pub max_duration: Option<Vec<u32>>,
+  /// Keys accepted by KeyedDeviceCmd.
+  #[serde(rename = "Keys")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub keys: Option<Vec<String>>,
   /*
   // Unimplemented attributes
   #[serde(rename = "Patterns")]
@@ -44,3 +50,121 @@ pub struct DeviceMessageAttributes {
   #[serde(skip)]
   pub feature_order: Option<Vec<u32>>,
 }
+
+/// Feature and step counts for messages that drive a set of features, checked
+/// for consistency.
+fn actuator_counts(
+  message_type: ButtplugDeviceMessageType,
+  attributes: &DeviceMessageAttributes,
+) -> Result<(u32, Vec<u32>), ButtplugDeviceError> {
+  let invalid =
+    |reason: String| ButtplugDeviceError::InvalidMessageAttributes(message_type, reason);
+  let feature_count = match attributes.feature_count {
+    Some(0) | None => return Err(invalid("no FeatureCount given".to_owned())),
+    Some(feature_count) => feature_count,
+  };
+  let step_counts = attributes.step_count.clone().unwrap_or_default();
+  if !step_counts.is_empty() && step_counts.len() != feature_count as usize {
+    return Err(invalid(format!(
+      "{} step counts given for {} features",
+      step_counts.len(),
+      feature_count
+    )));
+  }
+  Ok((feature_count, step_counts))
+}
+
+// Typed views of DeviceMessageAttributes for the generic actuator messages.
+// Unlike the raw attributes, these can only be created with a feature count,
+// and with a step count for every feature (or none at all), so code using them
+// doesn't need to decide what missing values mean.
+macro_rules! actuator_attributes {
+  ($(#[$meta:meta])* $name:ident, $message_type:ident) => {
+    $(#[$meta])*
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct $name {
+      feature_count: u32,
+      step_counts: Vec<u32>,
+    }
+
+    impl $name {
+      /// Number of features, always at least 1.
+      pub fn feature_count(&self) -> u32 {
+        self.feature_count
+      }
+
+      /// Number of steps each feature has, by index. Empty if the server
+      /// didn't send step counts (i.e. servers on spec v1).
+      pub fn step_counts(&self) -> &[u32] {
+        &self.step_counts
+      }
+    }
+
+    impl TryFrom<&DeviceMessageAttributes> for $name {
+      type Error = ButtplugDeviceError;
+
+      fn try_from(attributes: &DeviceMessageAttributes) -> Result<Self, Self::Error> {
+        let (feature_count, step_counts) =
+          actuator_counts(ButtplugDeviceMessageType::$message_type, attributes)?;
+        Ok(Self {
+          feature_count,
+          step_counts,
+        })
+      }
+    }
+  };
+}
+
+actuator_attributes!(
+  /// Attributes for VibrateCmd.
+  VibrateAttributes,
+  VibrateCmd
+);
+actuator_attributes!(
+  /// Attributes for RotateCmd.
+  RotateAttributes,
+  RotateCmd
+);
+actuator_attributes!(
+  /// Attributes for LinearCmd.
+  LinearAttributes,
+  LinearCmd
+);
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_typed_actuator_attributes() {
+    let attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![20, 10]),
+      ..Default::default()
+    };
+    let vibrate = VibrateAttributes::try_from(&attributes).unwrap();
+    assert_eq!(vibrate.feature_count(), 2);
+    assert_eq!(vibrate.step_counts(), &[20, 10]);
+    let no_steps = DeviceMessageAttributes {
+      feature_count: Some(1),
+      ..Default::default()
+    };
+    assert!(RotateAttributes::try_from(&no_steps)
+      .unwrap()
+      .step_counts()
+      .is_empty());
+    assert!(matches!(
+      LinearAttributes::try_from(&DeviceMessageAttributes::default()),
+      Err(ButtplugDeviceError::InvalidMessageAttributes(
+        ButtplugDeviceMessageType::LinearCmd,
+        _
+      ))
+    ));
+    let mismatched = DeviceMessageAttributes {
+      feature_count: Some(3),
+      step_count: Some(vec![20]),
+      ..Default::default()
+    };
+    assert!(VibrateAttributes::try_from(&mismatched).is_err());
+  }
+}
@@ -19,9 +19,17 @@ mod battery_level_reading;
 mod device_added;
 mod device_list;
 mod device_message_info;
+mod device_metadata;
 mod device_removed;
+mod device_session_limit_cmd;
+mod device_statistics;
+mod device_statistics_cmd;
+mod device_status;
+mod device_status_cmd;
+mod device_updated;
 mod error;
 mod fleshlight_launch_fw12_cmd;
+mod keyed_device_cmd;
 mod kiiroo_cmd;
 mod linear_cmd;
 mod log;
@@ -38,17 +46,23 @@ mod raw_write_cmd;
 mod request_device_list;
 mod request_log;
 mod request_server_info;
+mod request_server_time;
 mod rotate_cmd;
+mod rotate_to_angle_cmd;
 mod rssi_level_cmd;
 mod rssi_level_reading;
 mod scanning_finished;
+mod scheduled_cmd;
 pub mod serializer;
 mod server_info;
+mod server_time;
 mod single_motor_vibrate_cmd;
 mod start_scanning;
 mod stop_all_devices;
 mod stop_device_cmd;
 mod stop_scanning;
+mod suction_cmd;
+mod temperature_cmd;
 mod test;
 mod vibrate_cmd;
 mod vorze_a10_cyclone_cmd;
@@ -59,14 +73,24 @@ pub use battery_level_reading::BatteryLevelReading;
 pub use device_added::{DeviceAdded, DeviceAddedV0, DeviceAddedV1};
 pub use device_list::{DeviceList, DeviceListV0, DeviceListV1};
 pub use device_message_info::{DeviceMessageAttributesMap, DeviceMessageInfo};
+pub use device_metadata::{DeviceMetadata, DeviceTransport};
 pub use device_removed::DeviceRemoved;
+pub use device_session_limit_cmd::DeviceSessionLimitCmd;
+pub use device_statistics::DeviceStatistics;
+pub use device_statistics_cmd::DeviceStatisticsCmd;
+pub use device_status::{DeviceStatus, LinearPosition};
+pub use device_status_cmd::DeviceStatusCmd;
+pub use device_updated::DeviceUpdated;
 pub use error::{Error, ErrorCode, ErrorV0};
 pub use fleshlight_launch_fw12_cmd::FleshlightLaunchFW12Cmd;
+pub use keyed_device_cmd::KeyedDeviceCmd;
 pub use kiiroo_cmd::KiirooCmd;
 pub use linear_cmd::{LinearCmd, VectorSubcommand};
 pub use log_level::LogLevel;
 pub use lovense_cmd::LovenseCmd;
-pub use message_attributes::DeviceMessageAttributes;
+pub use message_attributes::{
+  DeviceMessageAttributes, LinearAttributes, RotateAttributes, VibrateAttributes,
+};
 pub use ok::Ok;
 pub use ping::Ping;
 pub use raw_read_cmd::RawReadCmd;
@@ -77,16 +101,22 @@ pub use raw_write_cmd::RawWriteCmd;
 pub use request_device_list::RequestDeviceList;
 pub use request_log::RequestLog;
 pub use request_server_info::RequestServerInfo;
+pub use request_server_time::RequestServerTime;
 pub use rotate_cmd::{RotateCmd, RotationSubcommand};
+pub use rotate_to_angle_cmd::{AngleSubcommand, RotateToAngleCmd};
 pub use rssi_level_cmd::RSSILevelCmd;
 pub use rssi_level_reading::RSSILevelReading;
 pub use scanning_finished::ScanningFinished;
+pub use scheduled_cmd::ScheduledCmd;
 pub use server_info::{ServerInfo, ServerInfoV0};
+pub use server_time::ServerTime;
 pub use single_motor_vibrate_cmd::SingleMotorVibrateCmd;
 pub use start_scanning::StartScanning;
 pub use stop_all_devices::StopAllDevices;
 pub use stop_device_cmd::StopDeviceCmd;
 pub use stop_scanning::StopScanning;
+pub use suction_cmd::{SuctionCmd, SuctionSubcommand};
+pub use temperature_cmd::{TemperatureCmd, TemperatureSubcommand};
 pub use test::Test;
 pub use vibrate_cmd::{VibrateCmd, VibrateSubcommand};
 pub use vorze_a10_cyclone_cmd::VorzeA10CycloneCmd;
@@ -194,6 +224,10 @@ pub enum ButtplugDeviceMessageType {
   RawUnsubscribeCmd,
   BatteryLevelCmd,
   RSSILevelCmd,
+  KeyedDeviceCmd,
+  RotateToAngleCmd,
+  TemperatureCmd,
+  SuctionCmd,
   // Deprecated generic commands
   SingleMotorVibrateCmd,
   // Deprecated device specific commands
@@ -236,6 +270,10 @@ pub enum ButtplugCurrentSpecDeviceMessageType {
   RawUnsubscribeCmd,
   BatteryLevelCmd,
   RSSILevelCmd,
+  KeyedDeviceCmd,
+  RotateToAngleCmd,
+  TemperatureCmd,
+  SuctionCmd,
 }
 
 // Ordering for ButtplugCurrentDeviceMessageType should be lexicographic, for
@@ -278,6 +316,16 @@ impl TryFrom<ButtplugDeviceMessageType> for ButtplugCurrentSpecDeviceMessageType
       ButtplugDeviceMessageType::RSSILevelCmd => {
         Ok(ButtplugCurrentSpecDeviceMessageType::RSSILevelCmd)
       }
+      ButtplugDeviceMessageType::KeyedDeviceCmd => {
+        Ok(ButtplugCurrentSpecDeviceMessageType::KeyedDeviceCmd)
+      }
+      ButtplugDeviceMessageType::RotateToAngleCmd => {
+        Ok(ButtplugCurrentSpecDeviceMessageType::RotateToAngleCmd)
+      }
+      ButtplugDeviceMessageType::TemperatureCmd => {
+        Ok(ButtplugCurrentSpecDeviceMessageType::TemperatureCmd)
+      }
+      ButtplugDeviceMessageType::SuctionCmd => Ok(ButtplugCurrentSpecDeviceMessageType::SuctionCmd),
       _ => Err(ButtplugMessageError::MessageConversionError(
         "Device message deprecated, does not exist in current version of protocol.".to_owned(),
       )),
@@ -306,6 +354,16 @@ impl From<ButtplugCurrentSpecDeviceMessageType> for ButtplugDeviceMessageType {
         ButtplugDeviceMessageType::BatteryLevelCmd
       }
       ButtplugCurrentSpecDeviceMessageType::RSSILevelCmd => ButtplugDeviceMessageType::RSSILevelCmd,
+      ButtplugCurrentSpecDeviceMessageType::KeyedDeviceCmd => {
+        ButtplugDeviceMessageType::KeyedDeviceCmd
+      }
+      ButtplugCurrentSpecDeviceMessageType::RotateToAngleCmd => {
+        ButtplugDeviceMessageType::RotateToAngleCmd
+      }
+      ButtplugCurrentSpecDeviceMessageType::TemperatureCmd => {
+        ButtplugDeviceMessageType::TemperatureCmd
+      }
+      ButtplugCurrentSpecDeviceMessageType::SuctionCmd => ButtplugDeviceMessageType::SuctionCmd,
     }
   }
 }
@@ -327,6 +385,7 @@ pub enum ButtplugClientMessage {
   RequestLog(RequestLog),
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
+  RequestServerTime(RequestServerTime),
   // Device enumeration messages
   StartScanning(StartScanning),
   StopScanning(StopScanning),
@@ -336,6 +395,9 @@ pub enum ButtplugClientMessage {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotateToAngleCmd(RotateToAngleCmd),
+  TemperatureCmd(TemperatureCmd),
+  SuctionCmd(SuctionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -344,6 +406,13 @@ pub enum ButtplugClientMessage {
   // Sensor commands
   BatteryLevelCmd(BatteryLevelCmd),
   RSSILevelCmd(RSSILevelCmd),
+  // Device status commands
+  DeviceStatisticsCmd(DeviceStatisticsCmd),
+  DeviceSessionLimitCmd(DeviceSessionLimitCmd),
+  DeviceStatusCmd(DeviceStatusCmd),
+  ScheduledCmd(ScheduledCmd),
+  // Device specific commands
+  KeyedDeviceCmd(KeyedDeviceCmd),
   // Deprecated generic commands
   SingleMotorVibrateCmd(SingleMotorVibrateCmd),
   // Deprecated device specific commands
@@ -377,16 +446,21 @@ pub enum ButtplugServerMessage {
   Log(Log),
   // Handshake messages
   ServerInfo(ServerInfo),
+  ServerTime(ServerTime),
   // Device enumeration messages
   DeviceList(DeviceList),
   DeviceAdded(DeviceAdded),
   DeviceRemoved(DeviceRemoved),
+  DeviceUpdated(DeviceUpdated),
   ScanningFinished(ScanningFinished),
   // Generic commands
   RawReading(RawReading),
   // Sensor Reading Messages
   BatteryLevelReading(BatteryLevelReading),
   RSSILevelReading(RSSILevelReading),
+  // Device status messages
+  DeviceStatistics(DeviceStatistics),
+  DeviceStatus(DeviceStatus),
 }
 
 /// Type alias for the latest version of client-to-server messages.
@@ -410,6 +484,7 @@ pub enum ButtplugSpecV2ClientMessage {
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
   Ping(Ping),
+  RequestServerTime(RequestServerTime),
   // Device enumeration messages
   StartScanning(StartScanning),
   StopScanning(StopScanning),
@@ -419,6 +494,9 @@ pub enum ButtplugSpecV2ClientMessage {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotateToAngleCmd(RotateToAngleCmd),
+  TemperatureCmd(TemperatureCmd),
+  SuctionCmd(SuctionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -427,6 +505,13 @@ pub enum ButtplugSpecV2ClientMessage {
   // Sensor commands
   BatteryLevelCmd(BatteryLevelCmd),
   RSSILevelCmd(RSSILevelCmd),
+  // Device status commands
+  DeviceStatisticsCmd(DeviceStatisticsCmd),
+  DeviceSessionLimitCmd(DeviceSessionLimitCmd),
+  DeviceStatusCmd(DeviceStatusCmd),
+  ScheduledCmd(ScheduledCmd),
+  // Device specific commands
+  KeyedDeviceCmd(KeyedDeviceCmd),
 }
 
 /// Represents all server-to-client messages in v2 of the Buttplug Spec
@@ -447,16 +532,21 @@ pub enum ButtplugSpecV2ServerMessage {
   Error(Error),
   // Handshake messages
   ServerInfo(ServerInfo),
+  ServerTime(ServerTime),
   // Device enumeration messages
   DeviceList(DeviceList),
   DeviceAdded(DeviceAdded),
   DeviceRemoved(DeviceRemoved),
+  DeviceUpdated(DeviceUpdated),
   ScanningFinished(ScanningFinished),
   // Generic commands
   RawReading(RawReading),
   // Sensor commands
   BatteryLevelReading(BatteryLevelReading),
   RSSILevelReading(RSSILevelReading),
+  // Device status messages
+  DeviceStatistics(DeviceStatistics),
+  DeviceStatus(DeviceStatus),
 }
 
 /// Represents all client-to-server messages in v1 of the Buttplug Spec
@@ -648,6 +738,10 @@ pub enum ButtplugDeviceManagerMessageUnion {
   StopAllDevices(StopAllDevices),
   StartScanning(StartScanning),
   StopScanning(StopScanning),
+  DeviceStatisticsCmd(DeviceStatisticsCmd),
+  DeviceSessionLimitCmd(DeviceSessionLimitCmd),
+  DeviceStatusCmd(DeviceStatusCmd),
+  ScheduledCmd(ScheduledCmd),
 }
 
 /// Represents all possible device command message types.
@@ -661,6 +755,7 @@ pub enum ButtplugDeviceManagerMessageUnion {
   FromSpecificButtplugMessage,
   TryFromButtplugClientMessage,
 )]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub enum ButtplugDeviceCommandMessageUnion {
   FleshlightLaunchFW12Cmd(FleshlightLaunchFW12Cmd),
   SingleMotorVibrateCmd(SingleMotorVibrateCmd),
@@ -670,6 +765,9 @@ pub enum ButtplugDeviceCommandMessageUnion {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotateToAngleCmd(RotateToAngleCmd),
+  TemperatureCmd(TemperatureCmd),
+  SuctionCmd(SuctionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -677,4 +775,5 @@ pub enum ButtplugDeviceCommandMessageUnion {
   RawUnsubscribeCmd(RawUnsubscribeCmd),
   BatteryLevelCmd(BatteryLevelCmd),
   RSSILevelCmd(RSSILevelCmd),
+  KeyedDeviceCmd(KeyedDeviceCmd),
 }
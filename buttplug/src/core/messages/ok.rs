@@ -26,6 +26,8 @@ impl Ok {
   }
 }
 
+/// Creates an Ok with a placeholder id of 1. Ok is always a reply, so the
+/// server replaces this with the id of the message being replied to.
 impl Default for Ok {
   fn default() -> Self {
     Self { id: 1 }
@@ -0,0 +1,89 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct AngleSubcommand {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Duration"))]
+  duration: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Angle"))]
+  angle: f64,
+}
+
+impl AngleSubcommand {
+  pub fn new(index: u32, duration: u32, angle: f64) -> Self {
+    Self {
+      index,
+      duration,
+      angle,
+    }
+  }
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  /// Time to take getting to the angle, in milliseconds.
+  pub fn duration(&self) -> u32 {
+    self.duration
+  }
+
+  /// Where to turn to, from 0.0 at one end of the rotator's range to 1.0 at
+  /// the other.
+  pub fn angle(&self) -> f64 {
+    self.angle
+  }
+}
+
+/// Turns rotators to absolute angles, for devices that can hold a position
+/// instead of only spinning at a speed.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct RotateToAngleCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Angles"))]
+  angles: Vec<AngleSubcommand>,
+}
+
+impl RotateToAngleCmd {
+  pub fn new(device_index: u32, angles: Vec<AngleSubcommand>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      angles,
+    }
+  }
+
+  pub fn angles(&self) -> &Vec<AngleSubcommand> {
+    &self.angles
+  }
+}
+
+impl ButtplugMessageValidator for RotateToAngleCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    for angle in &self.angles {
+      self.is_in_command_range(
+        angle.angle,
+        format!(
+          "Angle {} for RotateToAngleCmd index {} is invalid. Angle should be a value between 0.0 and 1.0",
+          angle.angle, angle.index
+        ),
+      )?;
+    }
+    Ok(())
+  }
+}
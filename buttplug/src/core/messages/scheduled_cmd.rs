@@ -0,0 +1,59 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Runs a device command at a set time instead of as soon as it arrives, so
+/// remote clients can send commands a little ahead and have network jitter
+/// smoothed out. Handled by the server itself, so every device supports it.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ScheduledCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  /// When to run the command, in milliseconds since the Unix epoch by the
+  /// server's clock.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Timestamp"))]
+  timestamp: u64,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Command"))]
+  command: ButtplugDeviceCommandMessageUnion,
+}
+
+impl ScheduledCmd {
+  pub fn new(timestamp: u64, command: ButtplugDeviceCommandMessageUnion) -> Self {
+    Self {
+      id: 1,
+      device_index: command.device_index(),
+      timestamp,
+      command,
+    }
+  }
+
+  pub fn timestamp(&self) -> u64 {
+    self.timestamp
+  }
+
+  pub fn command(&self) -> &ButtplugDeviceCommandMessageUnion {
+    &self.command
+  }
+}
+
+impl ButtplugMessageValidator for ScheduledCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if self.command.device_index() != self.device_index {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "ScheduledCmd Command must be for the same device as the ScheduledCmd".to_owned(),
+      ));
+    }
+    self.command.is_valid()
+  }
+}
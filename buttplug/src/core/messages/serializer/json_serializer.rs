@@ -1,7 +1,7 @@
 use super::{ButtplugMessageSerializer, ButtplugSerializedMessage, ButtplugSerializerError};
 use crate::{
   core::{
-    errors::{ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugError, ButtplugErrorCause, ButtplugHandshakeError, ButtplugMessageError},
     messages::{
       self, ButtplugClientMessage, ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage, ButtplugMessage, ButtplugMessageSpecVersion,
@@ -59,11 +59,11 @@ fn deserialize_to_message<T>(
 where
   T: serde::de::DeserializeOwned + Clone,
 {
-  // We have to pass back a string formatted error, as SerdeJson's error type
-  // isn't clonable.
+  // SerdeJson's error type isn't clonable, so it gets wrapped in a cause to
+  // keep it around as the error source.
   validator.validate(&msg).and_then(|_| {
     serde_json::from_str::<Vec<T>>(&msg)
-      .map_err(|e| ButtplugSerializerError::JsonSerializerError(format!("{:?}", e)))
+      .map_err(|e| ButtplugSerializerError::JsonSerializerError(ButtplugErrorCause::new(e)))
   })
 }
 
@@ -183,7 +183,7 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
       // In the rare event that there is a problem with the
       // RequestServerInfo message (so we can't set up our known spec
       // version), just encode to the latest and return.
-      if let ButtplugServerMessage::Error(_) = &msgs[0] {
+      if let Some(ButtplugServerMessage::Error(_)) = msgs.first() {
         serialize_to_version(ButtplugMessageSpecVersion::Version2, msgs)
       } else {
         // If we don't even have enough info to know which message
@@ -196,6 +196,15 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
       }
     }
   }
+
+  fn serialize_error(&self, err: &ButtplugSerializerError) -> Option<ButtplugSerializedMessage> {
+    // We can't get an Id out of something we couldn't parse, so this goes out
+    // as a system message.
+    let error_msg = messages::Error::from(ButtplugError::from(
+      ButtplugMessageError::MessageSerializationError(err.clone()),
+    ));
+    Some(self.serialize(vec![error_msg.into()]))
+  }
 }
 
 pub struct ButtplugClientJSONSerializer {
@@ -236,7 +245,10 @@ impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::core::messages::{RequestServerInfo, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION};
+  use crate::core::messages::{
+    AngleSubcommand, DeviceTransport, KeyedDeviceCmd, RequestServerInfo, RotateToAngleCmd,
+    ScheduledCmd, VibrateCmd, VibrateSubcommand, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  };
 
   #[test]
   fn test_correct_message_version() {
@@ -308,4 +320,224 @@ mod test {
       }
     }
   }
+
+  #[test]
+  fn test_deserialize_error_source() {
+    use std::error::Error;
+
+    let serializer = ButtplugClientJSONSerializer::default();
+    let err = serializer
+      .deserialize(ButtplugSerializedMessage::Text(
+        "not a json message".to_owned(),
+      ))
+      .unwrap_err();
+    assert!(err
+      .source()
+      .and_then(|source| source.downcast_ref::<serde_json::Error>())
+      .is_some());
+    // The source should survive being wrapped up into a ButtplugError.
+    let buttplug_err = ButtplugError::from(ButtplugMessageError::from(err));
+    assert!(buttplug_err
+      .source()
+      .and_then(|source| source.downcast_ref::<serde_json::Error>())
+      .is_some());
+  }
+
+  #[test]
+  fn test_server_incorrect_messages() {
+    let incorrect_incoming_messages = vec![
+      "not a json message",
+      "",
+      "[]",
+      "[{}]",
+      "[{\"NotAMessage\":{}}]",
+      "[{\"RequestServerInfo\":[]}]",
+      "[{\"RequestServerInfo\":{\"Id\":1,\"ClientName\":\"Test Client\",\"MessageVersion\":-1}}]",
+      "[{\"RequestServerInfo\":{\"Id\":18446744073709551616,\"ClientName\":\"Test Client\",\"MessageVersion\":2}}]",
+    ];
+    let serializer = ButtplugServerJSONSerializer::default();
+    for msg in incorrect_incoming_messages {
+      let err = serializer
+        .deserialize(ButtplugSerializedMessage::Text(msg.to_owned()))
+        .unwrap_err();
+      // Before the handshake, we should still be able to tell the client what
+      // went wrong, as a system Error message.
+      let reply = serializer
+        .serialize_error(&err)
+        .expect("Server serializer should always build an error reply.");
+      let client_serializer = ButtplugClientJSONSerializer::default();
+      let reply_msgs = client_serializer.deserialize(reply).unwrap();
+      assert_eq!(reply_msgs.len(), 1);
+      if let ButtplugCurrentSpecServerMessage::Error(error_msg) = &reply_msgs[0] {
+        assert_eq!(error_msg.id(), 0);
+      } else {
+        panic!("Expected Error message, got {:?}", reply_msgs[0]);
+      }
+    }
+  }
+
+  #[test]
+  fn test_device_added_metadata() {
+    let json = r#"[{
+            "DeviceAdded": {
+                "Id": 0,
+                "DeviceIndex": 0,
+                "DeviceName": "Test Vibrator",
+                "DeviceMessages": { "StopDeviceCmd": {} },
+                "DeviceMetadata": {
+                    "Transport": "Serial",
+                    "VendorId": 4660,
+                    "ProductId": 22136
+                }
+            }
+        }]"#;
+    let serializer = ButtplugClientJSONSerializer::default();
+    let _ = serializer.serialize(vec![RequestServerInfo::new(
+      "test client",
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    )
+    .into()]);
+    let msgs = serializer
+      .deserialize(ButtplugSerializedMessage::Text(json.to_owned()))
+      .unwrap();
+    if let ButtplugCurrentSpecServerMessage::DeviceAdded(device_added) = &msgs[0] {
+      let metadata = device_added.device_metadata().clone().unwrap();
+      assert_eq!(metadata.transport(), DeviceTransport::Serial);
+      assert_eq!(metadata.vendor_id(), Some(0x1234));
+      assert_eq!(metadata.product_id(), Some(0x5678));
+      assert_eq!(metadata.signal_strength(), None);
+    } else {
+      panic!("Expected DeviceAdded message, got {:?}", msgs[0]);
+    }
+    // Transport is required if metadata is sent at all.
+    let json = json.replace(r#""Transport": "Serial","#, "");
+    assert!(serializer
+      .deserialize(ButtplugSerializedMessage::Text(json))
+      .is_err());
+  }
+
+  #[test]
+  fn test_keyed_device_cmd() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer
+      .deserialize(ButtplugSerializedMessage::Text(
+        r#"[{"RequestServerInfo": {"Id": 1, "ClientName": "Test Client", "MessageVersion": 2}}]"#
+          .to_owned(),
+      ))
+      .unwrap();
+    let json = r#"[{
+            "KeyedDeviceCmd": {
+                "Id": 2,
+                "DeviceIndex": 0,
+                "Key": "Light",
+                "Value": "Off"
+            }
+        }]"#;
+    let msgs = serializer
+      .deserialize(ButtplugSerializedMessage::Text(json.to_owned()))
+      .unwrap();
+    assert_eq!(
+      msgs[0],
+      ButtplugClientMessage::KeyedDeviceCmd({
+        let mut msg = KeyedDeviceCmd::new(0, "Light", "Off");
+        msg.set_id(2);
+        msg
+      })
+    );
+    // Keys can't be empty.
+    let json = json.replace(r#""Light""#, r#""""#);
+    assert!(serializer
+      .deserialize(ButtplugSerializedMessage::Text(json))
+      .is_err());
+  }
+
+  #[test]
+  fn test_rotate_to_angle_cmd() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer
+      .deserialize(ButtplugSerializedMessage::Text(
+        r#"[{"RequestServerInfo": {"Id": 1, "ClientName": "Test Client", "MessageVersion": 2}}]"#
+          .to_owned(),
+      ))
+      .unwrap();
+    let json = r#"[{
+            "RotateToAngleCmd": {
+                "Id": 2,
+                "DeviceIndex": 0,
+                "Angles": [{"Index": 0, "Duration": 500, "Angle": 0.25}]
+            }
+        }]"#;
+    let msgs = serializer
+      .deserialize(ButtplugSerializedMessage::Text(json.to_owned()))
+      .unwrap();
+    assert_eq!(
+      msgs[0],
+      ButtplugClientMessage::RotateToAngleCmd({
+        let mut msg = RotateToAngleCmd::new(0, vec![AngleSubcommand::new(0, 500, 0.25)]);
+        msg.set_id(2);
+        msg
+      })
+    );
+    // Angles outside of 0.0-1.0 are rejected.
+    let json = json.replace("0.25", "1.5");
+    assert!(serializer
+      .deserialize(ButtplugSerializedMessage::Text(json))
+      .is_err());
+  }
+
+  #[test]
+  fn test_scheduled_cmd() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer
+      .deserialize(ButtplugSerializedMessage::Text(
+        r#"[{"RequestServerInfo": {"Id": 1, "ClientName": "Test Client", "MessageVersion": 2}}]"#
+          .to_owned(),
+      ))
+      .unwrap();
+    let json = r#"[{
+            "ScheduledCmd": {
+                "Id": 2,
+                "DeviceIndex": 0,
+                "Timestamp": 1623500000120,
+                "Command": {
+                    "VibrateCmd": {
+                        "Id": 1,
+                        "DeviceIndex": 0,
+                        "Speeds": [{"Index": 0, "Speed": 0.5}]
+                    }
+                }
+            }
+        }]"#;
+    let msgs = serializer
+      .deserialize(ButtplugSerializedMessage::Text(json.to_owned()))
+      .unwrap();
+    assert_eq!(
+      msgs[0],
+      ButtplugClientMessage::ScheduledCmd({
+        let command = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]);
+        let mut msg = ScheduledCmd::new(1623500000120, command.into());
+        msg.set_id(2);
+        msg
+      })
+    );
+    // Only commands that set device state can be scheduled.
+    let json = r#"[{
+            "ScheduledCmd": {
+                "Id": 3,
+                "DeviceIndex": 0,
+                "Timestamp": 1623500000120,
+                "Command": {"BatteryLevelCmd": {"Id": 1, "DeviceIndex": 0}}
+            }
+        }]"#;
+    assert!(serializer
+      .deserialize(ButtplugSerializedMessage::Text(json.to_owned()))
+      .is_err());
+  }
+
+  #[test]
+  fn test_server_serialize_empty() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    // Shouldn't panic, even without a message version.
+    let _ = serializer.serialize(vec![]);
+  }
 }
@@ -3,27 +3,38 @@ mod json_serializer;
 #[cfg(feature = "serialize-json")]
 pub use json_serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer};
 
+use crate::core::errors::ButtplugErrorCause;
 use serde::{Deserialize, Serialize};
-use thiserror::Error;
+use std::error::Error;
 pub type ButtplugSerializerResult<T> = Result<T, ButtplugSerializerError>;
 
-#[derive(Debug, Error, Clone, Serialize, Deserialize)]
+#[derive(Debug, displaydoc::Display, Clone, Serialize, Deserialize)]
 pub enum ButtplugSerializerError {
   // Valico hands back a vector of errors that isn't easy to encase, so we just
   // turn it into a big string and pass that back.
-  #[error("JSON Schema Validation Error: {0}")]
+  /// JSON Schema Validation Error: {0}
   JsonValidatorError(String),
-  /// Serialization error.
-  #[error("Cannot serialize to JSON: {0}")]
-  JsonSerializerError(String),
-  #[error("Cannot deserialize binary in a text handler")]
+  /// Cannot serialize to JSON: {0}
+  JsonSerializerError(ButtplugErrorCause),
+  /// Cannot deserialize binary in a text handler
   BinaryDeserializationError,
-  #[error("Cannot deserialize text in a binary handler.")]
+  /// Cannot deserialize text in a binary handler.
   TextDeserializationError,
-  #[error("Message version not received, can't figure out which spec version to de/serialize to.")]
+  /// Message version not received, can't figure out which spec version to de/serialize to.
   MessageSpecVersionNotReceived,
 }
 
+// Implemented by hand instead of via thiserror, so that source() hands back the
+// serde_json error itself, instead of the ButtplugErrorCause holding it.
+impl Error for ButtplugSerializerError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ButtplugSerializerError::JsonSerializerError(cause) => cause.error(),
+      _ => None,
+    }
+  }
+}
+
 #[derive(Debug, Display, Clone, PartialEq)]
 pub enum ButtplugSerializedMessage {
   Text(String),
@@ -50,4 +61,10 @@ pub trait ButtplugMessageSerializer: Default + Sync + Send {
     msg: ButtplugSerializedMessage,
   ) -> ButtplugSerializerResult<Vec<Self::Inbound>>;
   fn serialize(&self, msg: Vec<Self::Outbound>) -> ButtplugSerializedMessage;
+  /// Builds a reply to send back to the remote side when something it sent us
+  /// couldn't be deserialized. Only server side serializers can do anything
+  /// useful here, as clients have no message to tell the server it messed up.
+  fn serialize_error(&self, _err: &ButtplugSerializerError) -> Option<ButtplugSerializedMessage> {
+    None
+  }
 }
@@ -0,0 +1,77 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct SuctionSubcommand {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Level"))]
+  level: f64,
+}
+
+impl SuctionSubcommand {
+  pub fn new(index: u32, level: f64) -> Self {
+    Self { index, level }
+  }
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  /// Suction level, from 0.0 (released) to 1.0 (the strongest pressure step
+  /// the device config allows for the pump).
+  pub fn level(&self) -> f64 {
+    self.level
+  }
+}
+
+/// Sets the suction level of pump devices.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct SuctionCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Levels"))]
+  levels: Vec<SuctionSubcommand>,
+}
+
+impl SuctionCmd {
+  pub fn new(device_index: u32, levels: Vec<SuctionSubcommand>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      levels,
+    }
+  }
+
+  pub fn levels(&self) -> &Vec<SuctionSubcommand> {
+    &self.levels
+  }
+}
+
+impl ButtplugMessageValidator for SuctionCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    for level in &self.levels {
+      self.is_in_command_range(
+        level.level,
+        format!(
+          "Level {} for SuctionCmd index {} is invalid. Level should be a value between 0.0 and 1.0",
+          level.level, level.index
+        ),
+      )?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,77 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct TemperatureSubcommand {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Level"))]
+  level: f64,
+}
+
+impl TemperatureSubcommand {
+  pub fn new(index: u32, level: f64) -> Self {
+    Self { index, level }
+  }
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  /// Heat level, from 0.0 (off) to 1.0 (the hottest step the device config
+  /// allows for the heater).
+  pub fn level(&self) -> f64 {
+    self.level
+  }
+}
+
+/// Sets the heat level of devices with heating elements.
+#[derive(Debug, ButtplugDeviceMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct TemperatureCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Levels"))]
+  levels: Vec<TemperatureSubcommand>,
+}
+
+impl TemperatureCmd {
+  pub fn new(device_index: u32, levels: Vec<TemperatureSubcommand>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      levels,
+    }
+  }
+
+  pub fn levels(&self) -> &Vec<TemperatureSubcommand> {
+    &self.levels
+  }
+}
+
+impl ButtplugMessageValidator for TemperatureCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    for level in &self.levels {
+      self.is_in_command_range(
+        level.level,
+        format!(
+          "Level {} for TemperatureCmd index {} is invalid. Level should be a value between 0.0 and 1.0",
+          level.level, level.index
+        ),
+      )?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,209 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Support for peripherals that expose more than one logical device.
+//!
+//! Some hardware (i.e. controllers with detachable parts) shows up as a single
+//! peripheral, but is really two or more devices that should be controlled
+//! separately. Protocol definitions for these list their parts as
+//! [subdevices][super::configuration_manager::SubdeviceDefinition]. The
+//! peripheral is connected once, then each subdevice gets its own
+//! [DeviceImpl] that only sees the endpoints listed for it, and its own
+//! protocol instance.
+
+use super::{
+  configuration_manager::SubdeviceDefinition, ButtplugDeviceEvent, DeviceImpl, DeviceImplInternal,
+  DeviceReadCmd, DeviceSubscribeCmd, DeviceUnsubscribeCmd, DeviceWriteCmd, Endpoint,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{ButtplugDeviceMessage, RawReading},
+    ButtplugResultFuture,
+  },
+  util::async_manager,
+};
+use futures::future::{self, BoxFuture};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::broadcast;
+
+/// Creates the [DeviceImpl] for one subdevice of a composite peripheral.
+///
+/// Subdevice addresses are the peripheral address with the subdevice index
+/// appended, so they stay the same across reconnections.
+pub(super) fn new_subdevice_impl(
+  peripheral: Arc<DeviceImpl>,
+  index: usize,
+  definition: &SubdeviceDefinition,
+) -> DeviceImpl {
+  let address = format!("{}/{}", peripheral.address(), index);
+  let name = definition
+    .identifier
+    .clone()
+    .unwrap_or_else(|| peripheral.name().to_owned());
+  let endpoints: Vec<Endpoint> = definition.endpoints.keys().copied().collect();
+  let peripheral_address = peripheral.peripheral_address().to_owned();
+  let internal_impl = SubdeviceImplInternal::new(&address, peripheral, &definition.endpoints);
+  let mut device_impl = DeviceImpl::new(&name, &address, &endpoints, Box::new(internal_impl));
+  device_impl.peripheral_address = peripheral_address;
+  device_impl
+}
+
+struct SubdeviceImplInternal {
+  peripheral: Arc<DeviceImpl>,
+  // Maps endpoints the subdevice protocol uses to the peripheral endpoints
+  // they're actually on.
+  endpoints: HashMap<Endpoint, Endpoint>,
+  event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+}
+
+impl SubdeviceImplInternal {
+  fn new(
+    address: &str,
+    peripheral: Arc<DeviceImpl>,
+    endpoints: &HashMap<Endpoint, Endpoint>,
+  ) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    // Subscribe before returning, so we can't miss anything the peripheral
+    // sends once the subdevice exists.
+    let peripheral_events = peripheral.event_stream();
+    async_manager::spawn(forward_peripheral_events(
+      address.to_owned(),
+      endpoints.clone(),
+      peripheral_events,
+      event_sender.clone(),
+    ))
+    .unwrap();
+    Self {
+      peripheral,
+      endpoints: endpoints.clone(),
+      event_sender,
+    }
+  }
+
+  fn peripheral_endpoint(&self, endpoint: Endpoint) -> Result<Endpoint, ButtplugError> {
+    self
+      .endpoints
+      .get(&endpoint)
+      .copied()
+      .ok_or_else(|| ButtplugDeviceError::InvalidEndpoint(endpoint).into())
+  }
+}
+
+/// Rewrites peripheral events so they look like they came from the subdevice,
+/// dropping notifications for endpoints the subdevice doesn't use.
+async fn forward_peripheral_events(
+  address: String,
+  endpoints: HashMap<Endpoint, Endpoint>,
+  mut peripheral_events: broadcast::Receiver<ButtplugDeviceEvent>,
+  event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+) {
+  loop {
+    let event = match peripheral_events.recv().await {
+      Ok(event) => event,
+      Err(broadcast::error::RecvError::Lagged(count)) => {
+        warn!("Subdevice {} lagged, {} events dropped.", address, count);
+        continue;
+      }
+      Err(broadcast::error::RecvError::Closed) => return,
+    };
+    match event {
+      ButtplugDeviceEvent::Notification(_, peripheral_endpoint, data) => {
+        for (endpoint, _) in endpoints
+          .iter()
+          .filter(|(_, mapped)| **mapped == peripheral_endpoint)
+        {
+          // No one listening is fine, it just means no one cares about this
+          // notification.
+          let _ = event_sender.send(ButtplugDeviceEvent::Notification(
+            address.clone(),
+            *endpoint,
+            data.clone(),
+          ));
+        }
+      }
+      ButtplugDeviceEvent::Removed(_) => {
+        let _ = event_sender.send(ButtplugDeviceEvent::Removed(address));
+        return;
+      }
+      ButtplugDeviceEvent::Connected(_) => {}
+    }
+  }
+}
+
+impl DeviceImplInternal for SubdeviceImplInternal {
+  fn connected(&self) -> bool {
+    self.peripheral.connected()
+  }
+
+  /// Disconnects the whole peripheral, since parts can't be disconnected on
+  /// their own. All subdevices will be removed.
+  fn disconnect(&self) -> ButtplugResultFuture {
+    self.peripheral.disconnect()
+  }
+
+  fn event_stream(&self) -> broadcast::Receiver<ButtplugDeviceEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn read_value(
+    &self,
+    msg: DeviceReadCmd,
+  ) -> BoxFuture<'static, Result<RawReading, ButtplugError>> {
+    let endpoint = msg.endpoint;
+    let peripheral_endpoint = match self.peripheral_endpoint(endpoint) {
+      Ok(peripheral_endpoint) => peripheral_endpoint,
+      Err(err) => return Box::pin(future::ready(Err(err))),
+    };
+    let read_fut = self.peripheral.read_value(DeviceReadCmd::new(
+      peripheral_endpoint,
+      msg.length,
+      msg.timeout_ms,
+    ));
+    Box::pin(async move {
+      let reading = read_fut.await?;
+      Ok(RawReading::new(
+        reading.device_index(),
+        endpoint,
+        reading.data().clone(),
+      ))
+    })
+  }
+
+  fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture {
+    match self.peripheral_endpoint(msg.endpoint) {
+      Ok(endpoint) => self.peripheral.write_value(DeviceWriteCmd::new(
+        endpoint,
+        msg.data,
+        msg.write_with_response,
+      )),
+      Err(err) => Box::pin(future::ready(Err(err))),
+    }
+  }
+
+  fn subscribe(&self, msg: DeviceSubscribeCmd) -> ButtplugResultFuture {
+    match self.peripheral_endpoint(msg.endpoint) {
+      Ok(endpoint) => self.peripheral.subscribe(DeviceSubscribeCmd::new(endpoint)),
+      Err(err) => Box::pin(future::ready(Err(err))),
+    }
+  }
+
+  fn unsubscribe(&self, msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture {
+    match self.peripheral_endpoint(msg.endpoint) {
+      Ok(endpoint) => self
+        .peripheral
+        .unsubscribe(DeviceUnsubscribeCmd::new(endpoint)),
+      Err(err) => Box::pin(future::ready(Err(err))),
+    }
+  }
+
+  /// Subdevices share one connection, so one of them going idle can't slow it
+  /// down for the others.
+  fn supports_connection_priority(&self) -> bool {
+    false
+  }
+}
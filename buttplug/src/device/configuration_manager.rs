@@ -17,7 +17,9 @@ use crate::{
 };
 use super::protocol::{ButtplugProtocol, TryCreateProtocolFunc, get_default_protocol_map, add_to_protocol_map};
 use serde::Deserialize;
+use serde_json::Value;
 use std::{
+  cmp::Ordering,
   collections::{HashMap, HashSet},
   mem,
   sync::Arc
@@ -32,6 +34,11 @@ static DEVICE_CONFIGURATION_JSON_SCHEMA: &str =
 static USER_DEVICE_CONFIGURATION_JSON_SCHEMA: &str =
   include_str!("../../buttplug-device-config/buttplug-user-device-config-schema.json");
 
+/// Current version of the user device configuration format. Older user configs
+/// are migrated up to this version when loaded, see
+/// [migrate_user_device_config].
+pub const USER_DEVICE_CONFIGURATION_VERSION: u32 = 2;
+
 // Note: There's a ton of extra structs in here just to deserialize the json
 // file. Just leave them and build extras (for instance,
 // DeviceProtocolConfiguration) if needed elsewhere in the codebase. It's not
@@ -84,6 +91,32 @@ impl BluetoothLESpecifier {
   }
 }
 
+/// Everything a BLE scan needs to look for to find devices we have protocols
+/// for, as returned by [DeviceConfigurationManager::btle_scan_filter].
+///
+/// Filtering happens in software for now. btleplug 0.7 has no way to pass
+/// scan filters to the adapter, so the btleplug comm manager still scans for
+/// everything, and checks advertised names against this before handing
+/// devices to the device manager. Services aren't checked, as most devices
+/// don't advertise them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BluetoothLEScanFilter {
+  /// Advertised names. Entries ending in * match as prefixes.
+  pub names: HashSet<String>,
+  /// Services devices might advertise.
+  pub services: HashSet<Uuid>,
+}
+
+impl BluetoothLEScanFilter {
+  pub fn matches_name(&self, name: &str) -> bool {
+    self.names.contains(name)
+      || self.names.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => false,
+      })
+  }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct LovenseConnectServiceSpecifier {
   exists: bool
@@ -126,6 +159,26 @@ pub struct HIDSpecifier {
   product_id: u16,
 }
 
+impl HIDSpecifier {
+  pub fn new(vendor_id: u16, product_id: u16) -> Self {
+    Self {
+      vendor_id,
+      product_id,
+    }
+  }
+}
+
+/// UUID of the HID over Bluetooth service. Addresses of HID devices connected
+/// over Bluetooth always contain it, so protocols that need to know how a
+/// device is connected (i.e. controllers with different report formats for
+/// USB and Bluetooth) can tell. Windows HID paths already include it, the HID
+/// comm manager adds it everywhere else.
+pub const BLUETOOTH_HID_SERVICE_UUID: &str = "00001124-0000-1000-8000-00805f9b34fb";
+
+pub fn is_bluetooth_hid_address(address: &str) -> bool {
+  address.to_lowercase().contains(BLUETOOTH_HID_SERVICE_UUID)
+}
+
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct SerialSpecifier {
   #[serde(rename = "baud-rate")]
@@ -178,6 +231,172 @@ pub struct ProtocolAttributes {
   messages: Option<DeviceMessageAttributesMap>,
 }
 
+/// A write sent to a device as soon as it's connected, before its protocol is
+/// initialized. Used for devices that just need to be poked to wake up, so they
+/// don't each need a protocol with a custom initialize().
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct InitSequenceStep {
+  pub endpoint: Endpoint,
+  pub data: Vec<u8>,
+  #[serde(rename = "write-with-response", default)]
+  pub write_with_response: bool,
+  /// Time to wait after the write, in milliseconds.
+  #[serde(default)]
+  pub delay: u64,
+}
+
+/// One of the logical devices behind a composite peripheral (see
+/// [crate::device::composite]).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SubdeviceDefinition {
+  /// Protocol that runs this part of the peripheral.
+  pub protocol: String,
+  /// Identifier used to look up attributes in the subdevice protocol's
+  /// configurations. Defaults to the name the peripheral advertised.
+  pub identifier: Option<String>,
+  /// Maps endpoints the subdevice protocol uses to the peripheral endpoints
+  /// they're on. The subdevice can't touch any other endpoints.
+  pub endpoints: HashMap<Endpoint, Endpoint>,
+}
+
+/// A firmware version, read from the first run of dot separated numbers in a
+/// firmware string, so "V2.1.0" and "ANKNI-2.1" are both 2.1.0. Missing
+/// components count as 0 when comparing.
+#[derive(Debug, Clone)]
+pub struct FirmwareVersion {
+  components: Vec<u32>,
+}
+
+impl FirmwareVersion {
+  pub fn parse(firmware: &str) -> Option<Self> {
+    let start = firmware.find(|c: char| c.is_ascii_digit())?;
+    let components = firmware[start..]
+      .split(|c: char| !c.is_ascii_digit() && c != '.')
+      .next()
+      .unwrap()
+      .split('.')
+      .filter_map(|component| component.parse().ok())
+      .collect();
+    Some(Self { components })
+  }
+
+  fn component(&self, index: usize) -> u32 {
+    self.components.get(index).copied().unwrap_or(0)
+  }
+}
+
+impl PartialEq for FirmwareVersion {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for FirmwareVersion {}
+
+impl PartialOrd for FirmwareVersion {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for FirmwareVersion {
+  fn cmp(&self, other: &Self) -> Ordering {
+    let len = self.components.len().max(other.components.len());
+    (0..len)
+      .map(|i| self.component(i).cmp(&other.component(i)))
+      .find(|ordering| *ordering != Ordering::Equal)
+      .unwrap_or(Ordering::Equal)
+  }
+}
+
+/// Range of firmware versions a [QuirkDefinition] applies to.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct FirmwareVersionRange {
+  /// Lowest version in the range, inclusive.
+  pub min: Option<String>,
+  /// First version past the range, exclusive.
+  pub max: Option<String>,
+}
+
+impl FirmwareVersionRange {
+  pub fn contains(&self, version: &FirmwareVersion) -> bool {
+    let in_bound = |bound: &Option<String>, ok: fn(Ordering) -> bool| {
+      match bound.as_deref().and_then(FirmwareVersion::parse) {
+        Some(bound) => ok(version.cmp(&bound)),
+        None => true,
+      }
+    };
+    in_bound(&self.min, |ordering| ordering != Ordering::Less)
+      && in_bound(&self.max, |ordering| ordering == Ordering::Less)
+  }
+}
+
+/// Behavior differences for devices whose firmware or model string matches.
+/// Usually either clones that advertise the same names as the originals but
+/// don't quite speak the same protocol, or firmware updates that changed
+/// packet formats or added features. Protocols read the string at
+/// initialization (see
+/// [ButtplugProtocol::read_firmware][crate::device::protocol::ButtplugProtocol::read_firmware])
+/// and decide what each quirk means.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct QuirkDefinition {
+  /// Firmware strings this applies to. Entries ending in * match as prefixes.
+  /// If empty, any firmware string matches.
+  #[serde(default)]
+  pub firmware: Vec<String>,
+  /// Firmware versions this applies to. Devices whose firmware string has no
+  /// version in it never match a range.
+  #[serde(rename = "firmware-version")]
+  pub firmware_version: Option<FirmwareVersionRange>,
+  /// Quirks to turn on for matching devices.
+  #[serde(default)]
+  pub quirks: Vec<String>,
+  /// Message attributes for matching devices, merged over the ones from the
+  /// device's configuration. Used for features only some firmware has.
+  pub messages: Option<DeviceMessageAttributesMap>,
+}
+
+impl QuirkDefinition {
+  pub fn matches(&self, firmware: &str) -> bool {
+    let firmware_matches = self.firmware.is_empty()
+      || self.firmware.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => firmware.starts_with(prefix),
+        None => firmware == pattern,
+      });
+    let version_matches = match &self.firmware_version {
+      Some(range) => match FirmwareVersion::parse(firmware) {
+        Some(version) => range.contains(&version),
+        None => false,
+      },
+      None => true,
+    };
+    firmware_matches && version_matches
+  }
+}
+
+/// Quirks turned on for a device, as matched from [QuirkDefinition]s.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceQuirks {
+  quirks: HashSet<String>,
+  messages: DeviceMessageAttributesMap,
+}
+
+impl DeviceQuirks {
+  pub fn contains(&self, quirk: &str) -> bool {
+    self.quirks.contains(quirk)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.quirks.is_empty() && self.messages.is_empty()
+  }
+
+  /// Merges message attributes from matching quirk definitions into
+  /// `attributes`.
+  pub fn apply_message_attributes(&self, attributes: &mut DeviceMessageAttributesMap) {
+    attributes.extend(self.messages.clone());
+  }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ProtocolDefinition {
   // Can't get serde flatten specifiers into a String/DeviceSpecifier map, so
@@ -190,6 +409,14 @@ pub struct ProtocolDefinition {
   pub xinput: Option<XInputSpecifier>,
   #[serde(rename = "lovense-connect-service")]
   pub lovense_connect_service: Option<LovenseConnectServiceSpecifier>,
+  #[serde(rename = "init-sequence", default)]
+  pub init_sequence: Vec<InitSequenceStep>,
+  /// If not empty, the peripheral is a composite device, and each of these is
+  /// exposed as its own device instead of using this protocol.
+  #[serde(default)]
+  pub subdevices: Vec<SubdeviceDefinition>,
+  #[serde(default)]
+  pub quirks: Vec<QuirkDefinition>,
   pub defaults: Option<ProtocolAttributes>,
   #[serde(default)]
   pub configurations: Vec<ProtocolAttributes>,
@@ -202,6 +429,29 @@ pub struct UserProtocolDefinition {
   pub serial: Option<Vec<SerialSpecifier>>,
 }
 
+/// User settings for a specific device, keyed by device address in the user
+/// config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserDeviceDefinition {
+  /// Name to show clients instead of the name from the device config.
+  #[serde(rename = "display-name")]
+  pub display_name: Option<String>,
+  /// How long the device can run before the server winds it down.
+  #[serde(rename = "session-limit")]
+  pub session_limit: Option<UserDeviceSessionLimit>,
+}
+
+/// Session time limit for a device, from the user config. Times are in
+/// milliseconds.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserDeviceSessionLimit {
+  /// Time from the first command until the device starts winding down.
+  pub duration: u32,
+  /// Time taken to ramp the device down to a stop.
+  #[serde(rename = "wind-down", default)]
+  pub wind_down: u32,
+}
+
 fn option_some_eq<T>(a: &Option<T>, b: &T) -> bool
 where
   T: PartialEq,
@@ -238,7 +488,10 @@ pub struct ProtocolConfiguration {
 
 #[derive(Deserialize, Debug)]
 pub struct UserProtocolConfiguration {
+  pub version: u32,
   pub protocols: HashMap<String, UserProtocolDefinition>,
+  #[serde(default)]
+  pub devices: HashMap<String, UserDeviceDefinition>,
 }
 
 impl ProtocolConfiguration {
@@ -263,6 +516,7 @@ pub struct DeviceProtocolConfiguration {
   allow_raw_messages: bool,
   defaults: Option<ProtocolAttributes>,
   configurations: Vec<ProtocolAttributes>,
+  quirks: Vec<QuirkDefinition>,
 }
 
 impl DeviceProtocolConfiguration {
@@ -275,7 +529,32 @@ impl DeviceProtocolConfiguration {
       allow_raw_messages,
       defaults,
       configurations,
+      quirks: vec![],
+    }
+  }
+
+  pub fn with_quirks(mut self, quirks: Vec<QuirkDefinition>) -> Self {
+    self.quirks = quirks;
+    self
+  }
+
+  /// True if the protocol has quirks defined, meaning it's worth reading the
+  /// device's firmware string to look for them.
+  pub fn has_quirks(&self) -> bool {
+    !self.quirks.is_empty()
+  }
+
+  /// Quirks for a device with the given firmware string, from every matching
+  /// definition.
+  pub fn quirks_for_firmware(&self, firmware: &str) -> DeviceQuirks {
+    let mut device_quirks = DeviceQuirks::default();
+    for definition in self.quirks.iter().filter(|definition| definition.matches(firmware)) {
+      device_quirks.quirks.extend(definition.quirks.iter().cloned());
+      if let Some(messages) = &definition.messages {
+        device_quirks.messages.extend(messages.clone());
+      }
     }
+    device_quirks
   }
 
   pub fn get_attributes(
@@ -352,10 +631,188 @@ impl DeviceProtocolConfiguration {
   }
 }
 
+/// A device listed in the device configuration, as returned in
+/// [SupportedProtocol::devices].
+#[derive(Debug, Clone)]
+pub struct SupportedDevice {
+  /// Device names, keyed by locale (i.e. "en-us").
+  pub names: HashMap<String, String>,
+  /// Identifiers the protocol uses to tell this device apart from the others
+  /// it handles. Empty for protocols that only have one device entry.
+  pub identifiers: Vec<String>,
+  /// Messages the device accepts, with their attributes. Doesn't include raw
+  /// messages, since those depend on server options.
+  pub message_attributes: DeviceMessageAttributesMap,
+}
+
+/// A protocol from the device configuration, with everything needed to tell
+/// whether some piece of hardware will work with it.
+#[derive(Debug, Clone)]
+pub struct SupportedProtocol {
+  pub name: String,
+  /// Names the protocol matches on BLE advertisements. Names ending in `*`
+  /// match any advertised name that starts with the rest of the name.
+  pub btle_names: Vec<String>,
+  /// USB (vendor id, product id) pairs.
+  pub usb_ids: Vec<(u16, u16)>,
+  /// HID (vendor id, product id) pairs.
+  pub hid_ids: Vec<(u16, u16)>,
+  /// Serial ports, including ones added through the user config.
+  pub serial_ports: Vec<String>,
+  pub xinput: bool,
+  pub lovense_connect_service: bool,
+  pub devices: Vec<SupportedDevice>,
+}
+
+impl SupportedProtocol {
+  fn new(name: &str, definition: &ProtocolDefinition) -> Self {
+    let mut btle_names: Vec<String> = definition
+      .btle
+      .as_ref()
+      .map(|btle| btle.names.iter().cloned().collect())
+      .unwrap_or_default();
+    btle_names.sort();
+    let devices = if definition.configurations.is_empty() {
+      // Without any configurations, the defaults are the only device.
+      definition
+        .defaults
+        .iter()
+        .map(|defaults| SupportedDevice::new(&None, defaults))
+        .collect()
+    } else {
+      definition
+        .configurations
+        .iter()
+        .map(|attrs| SupportedDevice::new(&definition.defaults, attrs))
+        .collect()
+    };
+    Self {
+      name: name.to_owned(),
+      btle_names,
+      usb_ids: definition
+        .usb
+        .iter()
+        .flatten()
+        .map(|usb| (usb.vendor_id, usb.product_id))
+        .collect(),
+      hid_ids: definition
+        .hid
+        .iter()
+        .flatten()
+        .map(|hid| (hid.vendor_id, hid.product_id))
+        .collect(),
+      serial_ports: definition
+        .serial
+        .iter()
+        .flatten()
+        .map(|serial| serial.port.clone())
+        .collect(),
+      xinput: definition.xinput.is_some(),
+      lovense_connect_service: definition.lovense_connect_service.is_some(),
+      devices,
+    }
+  }
+
+  /// Returns true if a BLE device advertising `name` would be handled by this
+  /// protocol.
+  pub fn matches_btle_name(&self, name: &str) -> bool {
+    let specifier = BluetoothLESpecifier {
+      names: self.btle_names.iter().cloned().collect(),
+      services: HashMap::new(),
+    };
+    specifier == BluetoothLESpecifier::new_from_device(name)
+  }
+
+  /// Case insensitive search over the protocol name, device names, and BLE
+  /// names, for filtering supported device lists.
+  pub fn matches(&self, query: &str) -> bool {
+    let query = query.to_lowercase();
+    self.name.to_lowercase().contains(&query)
+      || self
+        .devices
+        .iter()
+        .flat_map(|device| device.names.values())
+        .any(|name| name.to_lowercase().contains(&query))
+      || self
+        .btle_names
+        .iter()
+        .any(|name| name.to_lowercase().contains(query.trim_end_matches('*')))
+  }
+}
+
+impl SupportedDevice {
+  fn new(defaults: &Option<ProtocolAttributes>, attrs: &ProtocolAttributes) -> Self {
+    let mut message_attributes = defaults
+      .as_ref()
+      .and_then(|defaults| defaults.messages.clone())
+      .unwrap_or_default();
+    if let Some(ref msg_attrs) = attrs.messages {
+      message_attributes.extend(msg_attrs.clone());
+    }
+    message_attributes
+      .entry(ButtplugDeviceMessageType::StopDeviceCmd)
+      .or_default();
+    Self {
+      names: attrs.name.clone().unwrap_or_default(),
+      identifiers: attrs.identifier.clone().unwrap_or_default(),
+      message_attributes,
+    }
+  }
+}
+
+fn user_config_error(err: impl std::fmt::Display) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceConfigurationFileError(format!("{}", err))
+}
+
+/// Upgrades a user device configuration to [USER_DEVICE_CONFIGURATION_VERSION],
+/// returning the upgraded JSON.
+///
+/// User configs are loaded through this automatically, but applications can
+/// also use it to rewrite stored files. Configs without a version field are
+/// treated as version 1. Configs from a newer version of the library are
+/// rejected with [ButtplugDeviceError::UserDeviceConfigurationVersionUnsupported]
+/// rather than risking misparsing them.
+pub fn migrate_user_device_config(user_config: &str) -> Result<String, ButtplugDeviceError> {
+  let mut config: Value = serde_json::from_str(user_config).map_err(user_config_error)?;
+  let config_obj = config.as_object_mut().ok_or_else(|| {
+    user_config_error("User device configuration must be a JSON object")
+  })?;
+  let mut version = match config_obj.get("version") {
+    None => 1,
+    Some(version) => version
+      .as_u64()
+      .filter(|v| *v >= 1 && *v <= u32::MAX as u64)
+      .ok_or_else(|| user_config_error("User device configuration version must be a positive integer"))?
+      as u32,
+  };
+  if version > USER_DEVICE_CONFIGURATION_VERSION {
+    return Err(ButtplugDeviceError::UserDeviceConfigurationVersionUnsupported(
+      version,
+      USER_DEVICE_CONFIGURATION_VERSION,
+    ));
+  }
+  while version < USER_DEVICE_CONFIGURATION_VERSION {
+    match version {
+      // Version 1 configs (written before user configs were versioned) have the
+      // same layout as version 2, they just need the version field.
+      1 => {}
+      _ => unreachable!("All versions below the current version have a migration step"),
+    }
+    version += 1;
+    info!("Migrated user device configuration to version {}", version);
+  }
+  config_obj.insert("version".to_owned(), Value::from(version));
+  Ok(config.to_string())
+}
+
 pub struct DeviceConfigurationManager {
   allow_raw_messages: bool,
   pub(self) config: ProtocolConfiguration,
-  protocol_map: Arc<DashMap<String, TryCreateProtocolFunc>>
+  protocol_map: Arc<DashMap<String, TryCreateProtocolFunc>>,
+  // Address to user display name.
+  display_names: Arc<DashMap<String, String>>,
+  // Address to user session limit.
+  session_limits: Arc<DashMap<String, UserDeviceSessionLimit>>,
 }
 
 impl Default for DeviceConfigurationManager {
@@ -402,11 +859,24 @@ impl DeviceConfigurationManager {
       config.version
     );
 
+    let display_names = DashMap::new();
+    let session_limits = DashMap::new();
     if let Some(user_config_str) = user_config {
+      let user_config_str = migrate_user_device_config(user_config_str)?;
       let user_validator = JSONValidator::new(USER_DEVICE_CONFIGURATION_JSON_SCHEMA);
       match user_validator.validate(&user_config_str) {
-        Ok(_) => match serde_json::from_str(&user_config_str) {
-          Ok(user_cfg) => config.merge_user_config(user_cfg),
+        Ok(_) => match serde_json::from_str::<UserProtocolConfiguration>(&user_config_str) {
+          Ok(mut user_cfg) => {
+            for (address, device) in user_cfg.devices.drain() {
+              if let Some(session_limit) = device.session_limit {
+                session_limits.insert(address.clone(), session_limit);
+              }
+              if let Some(display_name) = device.display_name {
+                display_names.insert(address, display_name);
+              }
+            }
+            config.merge_user_config(user_cfg)
+          }
           Err(err) => {
             return Err(ButtplugDeviceError::DeviceConfigurationFileError(format!(
               "{}",
@@ -426,10 +896,53 @@ impl DeviceConfigurationManager {
     Ok(DeviceConfigurationManager {
       allow_raw_messages,
       config,
-      protocol_map: Arc::new(get_default_protocol_map())
+      protocol_map: Arc::new(get_default_protocol_map()),
+      display_names: Arc::new(display_names),
+      session_limits: Arc::new(session_limits),
     })
   }
 
+  /// Returns the user set display name for the device at `address`, if there
+  /// is one.
+  pub fn display_name(&self, address: &str) -> Option<String> {
+    self
+      .display_names
+      .get(address)
+      .map(|name| name.value().clone())
+  }
+
+  /// Sets the display name for the device at `address`. This will be used in
+  /// place of the device config name the next time the device is added or
+  /// listed.
+  pub fn set_display_name(&self, address: &str, display_name: &str) {
+    self
+      .display_names
+      .insert(address.to_owned(), display_name.to_owned());
+  }
+
+  /// Removes the display name for the device at `address`, returning it if one
+  /// was set.
+  pub fn remove_display_name(&self, address: &str) -> Option<String> {
+    self.display_names.remove(address).map(|(_, name)| name)
+  }
+
+  /// All display names, keyed by device address. Applications can store these
+  /// in the `devices` section of the user device config to keep them across
+  /// sessions.
+  pub fn display_names(&self) -> HashMap<String, String> {
+    self
+      .display_names
+      .iter()
+      .map(|entry| (entry.key().clone(), entry.value().clone()))
+      .collect()
+  }
+
+  /// Returns the session limit the user config sets for the device at
+  /// `address`, if there is one.
+  pub fn session_limit(&self, address: &str) -> Option<UserDeviceSessionLimit> {
+    self.session_limits.get(address).map(|limit| *limit.value())
+  }
+
   pub fn add_protocol<T>(&self, protocol_name: &str) where T: ButtplugProtocol {
     add_to_protocol_map::<T>(&self.protocol_map, protocol_name);
   }
@@ -457,6 +970,43 @@ impl DeviceConfigurationManager {
     &self.config.protocols
   }
 
+  /// Scan filter covering every BLE device that would get a protocol if found.
+  /// Protocols that aren't available (and composite devices using them) are
+  /// left out.
+  pub fn btle_scan_filter(&self) -> BluetoothLEScanFilter {
+    let mut filter = BluetoothLEScanFilter::default();
+    for (name, definition) in self.config.protocols.iter() {
+      let available = if definition.subdevices.is_empty() {
+        self.has_protocol(name)
+      } else {
+        definition
+          .subdevices
+          .iter()
+          .all(|subdevice| self.has_protocol(&subdevice.protocol))
+      };
+      if let (true, Some(btle)) = (available, &definition.btle) {
+        filter.names.extend(btle.names.iter().cloned());
+        filter.services.extend(btle.services.keys().cloned());
+      }
+    }
+    filter
+  }
+
+  /// Every protocol in the loaded configuration that has an implementation
+  /// registered, sorted by name. Useful for showing users what hardware is
+  /// supported, based on the config actually in use.
+  pub fn supported_protocols(&self) -> Vec<SupportedProtocol> {
+    let mut protocols: Vec<SupportedProtocol> = self
+      .config
+      .protocols
+      .iter()
+      .filter(|(name, _)| self.has_protocol(name))
+      .map(|(name, definition)| SupportedProtocol::new(name, definition))
+      .collect();
+    protocols.sort_by(|a, b| a.name.cmp(&b.name));
+    protocols
+  }
+
   pub fn find_configuration(
     &self,
     specifier: &DeviceSpecifier,
@@ -481,11 +1031,14 @@ impl DeviceConfigurationManager {
     // but I'm not really sure what it is?
     if let Some(proto) = self.config.protocols.get(name) {
       info!("Found a protocol definition for {}", name);
-      Some(DeviceProtocolConfiguration::new(
-        self.allow_raw_messages,
-        proto.defaults.clone(),
-        proto.configurations.clone(),
-      ))
+      Some(
+        DeviceProtocolConfiguration::new(
+          self.allow_raw_messages,
+          proto.defaults.clone(),
+          proto.configurations.clone(),
+        )
+        .with_quirks(proto.quirks.clone()),
+      )
     } else {
       debug!("No matching protocol definition found.");
       None
@@ -496,9 +1049,17 @@ impl DeviceConfigurationManager {
 #[cfg(test)]
 mod test {
   use super::{
-    BluetoothLESpecifier, DeviceConfigurationManager, DeviceProtocolConfiguration, DeviceSpecifier,
+    is_bluetooth_hid_address, migrate_user_device_config, BluetoothLESpecifier,
+    DeviceConfigurationManager, DeviceProtocolConfiguration, DeviceSpecifier, FirmwareVersion,
+    HIDSpecifier, QuirkDefinition, UserDeviceSessionLimit, USER_DEVICE_CONFIGURATION_VERSION,
+  };
+  use crate::{
+    core::{
+      errors::ButtplugDeviceError,
+      messages::{ButtplugDeviceMessageType, DeviceMessageAttributesMap},
+    },
+    device::Endpoint,
   };
-  use crate::core::messages::ButtplugDeviceMessageType;
 
   #[test]
   fn test_load_config() {
@@ -513,6 +1074,28 @@ mod test {
     assert!(config.find_configuration(&launch).is_some());
   }
 
+  #[test]
+  fn test_hid_config_equals() {
+    let config = DeviceConfigurationManager::default();
+    let dualshock = DeviceSpecifier::HID(HIDSpecifier::new(0x054c, 0x05c4));
+    assert_eq!(
+      config.find_configuration(&dualshock).map(|(_, name, _)| name),
+      Some("dualshock4".to_owned())
+    );
+    let unknown = DeviceSpecifier::HID(HIDSpecifier::new(0x054c, 0xffff));
+    assert!(config.find_configuration(&unknown).is_none());
+  }
+
+  #[test]
+  fn test_is_bluetooth_hid_address() {
+    assert!(is_bluetooth_hid_address(
+      "\\\\?\\hid#{00001124-0000-1000-8000-00805F9B34FB}_vid&0002054c_pid&05c4#9&1234&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}"
+    ));
+    assert!(!is_bluetooth_hid_address(
+      "\\\\?\\hid#vid_054c&pid_05c4#7&1234&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}"
+    ));
+  }
+
   #[test]
   fn test_config_wildcard_equals() {
     let config = DeviceConfigurationManager::default();
@@ -543,6 +1126,79 @@ mod test {
     );
   }
 
+  #[test]
+  fn test_btle_scan_filter() {
+    let config = DeviceConfigurationManager::default();
+    let filter = config.btle_scan_filter();
+    assert!(filter.matches_name("LVS-Whatever"));
+    assert!(filter.matches_name("Picobong Egg"));
+    assert!(!filter.matches_name("Some Headphones"));
+    assert!(!filter.services.is_empty());
+    // Names for protocols that aren't available shouldn't be scanned for.
+    config.remove_protocol("lovense");
+    assert!(!config.btle_scan_filter().matches_name("LVS-Whatever"));
+  }
+
+  #[test]
+  fn test_quirk_matching() {
+    let config = DeviceConfigurationManager::default();
+    let picobong =
+      DeviceSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device("Picobong Egg"));
+    let proto = config.find_configuration(&picobong).unwrap();
+    let proto_config =
+      DeviceProtocolConfiguration::new(false, proto.2.defaults.clone(), proto.2.configurations)
+        .with_quirks(proto.2.quirks);
+    assert!(proto_config.has_quirks());
+    assert!(proto_config
+      .quirks_for_firmware("ANKNI-1.3")
+      .contains("no-command-byte"));
+    assert!(proto_config.quirks_for_firmware("V2.1.0").is_empty());
+    // Wildcards only match as prefixes.
+    assert!(proto_config.quirks_for_firmware("NOT-ANKNI").is_empty());
+  }
+
+  #[test]
+  fn test_firmware_version_parsing() {
+    let version = |firmware| FirmwareVersion::parse(firmware).unwrap();
+    assert_eq!(version("V2.1.0"), version("2.1"));
+    assert_eq!(version("ANKNI-1.3 build 7"), version("1.3"));
+    assert!(version("1.10") > version("1.9"));
+    assert!(version("2.0.1") > version("2"));
+    assert!(FirmwareVersion::parse("ANKNI").is_none());
+  }
+
+  #[test]
+  fn test_firmware_version_quirks() {
+    let quirks: Vec<QuirkDefinition> = serde_json::from_str(
+      r#"[
+        {
+          "firmware-version": { "max": "2.0" },
+          "quirks": ["old-packets"]
+        },
+        {
+          "firmware": ["LVS*"],
+          "firmware-version": { "min": "2.0" },
+          "messages": { "BatteryLevelCmd": {} }
+        }
+      ]"#,
+    )
+    .unwrap();
+    let proto_config = DeviceProtocolConfiguration::new(false, None, vec![]).with_quirks(quirks);
+    assert!(proto_config
+      .quirks_for_firmware("V1.9.3")
+      .contains("old-packets"));
+    // Firmware without a version never matches a range.
+    assert!(proto_config.quirks_for_firmware("unknown").is_empty());
+
+    let new_quirks = proto_config.quirks_for_firmware("LVS 2.0");
+    assert!(!new_quirks.contains("old-packets"));
+    let mut attributes = DeviceMessageAttributesMap::new();
+    new_quirks.apply_message_attributes(&mut attributes);
+    assert!(attributes.contains_key(&ButtplugDeviceMessageType::BatteryLevelCmd));
+    // Both the string and the version have to match.
+    assert!(proto_config.quirks_for_firmware("V2.0").is_empty());
+  }
+
   #[test]
   fn test_raw_device_config_creation() {
     let config = DeviceConfigurationManager::new_with_options(true, &None, &None).unwrap();
@@ -579,6 +1235,30 @@ mod test {
     assert!(!message_map.contains_key(&ButtplugDeviceMessageType::RawUnsubscribeCmd));
   }
 
+  #[test]
+  fn test_hid_input_device_config_creation() {
+    let config = DeviceConfigurationManager::default();
+    let pedal = DeviceSpecifier::HID(HIDSpecifier::new(0x05f3, 0x00ff));
+    let proto = config.find_configuration(&pedal).unwrap();
+    assert_eq!(proto.1, "foot-pedal");
+    // Input-only devices get raw reads and subscriptions on rx, even with raw
+    // messages off, but no raw writes.
+    let proto_config =
+      DeviceProtocolConfiguration::new(false, proto.2.defaults.clone(), proto.2.configurations);
+    let (_, message_map) = proto_config.get_attributes("P", &[Endpoint::Rx]).unwrap();
+    assert!(!message_map.contains_key(&ButtplugDeviceMessageType::RawWriteCmd));
+    for message_type in &[
+      ButtplugDeviceMessageType::RawReadCmd,
+      ButtplugDeviceMessageType::RawSubscribeCmd,
+      ButtplugDeviceMessageType::RawUnsubscribeCmd,
+    ] {
+      assert_eq!(
+        message_map.get(message_type).unwrap().endpoints,
+        Some(vec![Endpoint::Rx])
+      );
+    }
+  }
+
   #[test]
   fn test_user_config_loading() {
     let mut config = DeviceConfigurationManager::default();
@@ -661,6 +1341,134 @@ mod test {
       .any(|x| x.port == "COM1"));
   }
 
+  #[test]
+  fn test_user_config_migration() {
+    // Unversioned configs are treated as version 1 and upgraded.
+    let migrated = migrate_user_device_config(r#"{ "protocols": {} }"#).unwrap();
+    let migrated: serde_json::Value = serde_json::from_str(&migrated).unwrap();
+    assert_eq!(
+      migrated["version"],
+      serde_json::Value::from(USER_DEVICE_CONFIGURATION_VERSION)
+    );
+    assert!(DeviceConfigurationManager::new_with_options(
+      false,
+      &None,
+      &Some(r#"{ "version": 2, "protocols": {} }"#.to_owned())
+    )
+    .is_ok());
+    // Configs from the future are rejected instead of guessed at.
+    assert!(matches!(
+      DeviceConfigurationManager::new_with_options(
+        false,
+        &None,
+        &Some(r#"{ "version": 1000, "protocols": {} }"#.to_owned())
+      ),
+      Err(ButtplugDeviceError::UserDeviceConfigurationVersionUnsupported(
+        1000,
+        USER_DEVICE_CONFIGURATION_VERSION
+      ))
+    ));
+    assert!(matches!(
+      migrate_user_device_config(r#"{ "version": "two", "protocols": {} }"#),
+      Err(ButtplugDeviceError::DeviceConfigurationFileError(_))
+    ));
+  }
+
+  #[test]
+  fn test_user_config_session_limits() {
+    let config = DeviceConfigurationManager::new_with_options(
+      false,
+      &None,
+      &Some(
+        r#"{
+          "version": 2,
+          "protocols": {},
+          "devices": {
+            "limited-address": { "session-limit": { "duration": 60000, "wind-down": 5000 } },
+            "sudden-address": { "session-limit": { "duration": 60000 } }
+          }
+        }"#
+        .to_owned(),
+      ),
+    )
+    .unwrap();
+    assert_eq!(
+      config.session_limit("limited-address"),
+      Some(UserDeviceSessionLimit {
+        duration: 60000,
+        wind_down: 5000
+      })
+    );
+    assert_eq!(
+      config.session_limit("sudden-address").unwrap().wind_down,
+      0
+    );
+    assert_eq!(config.session_limit("other-address"), None);
+    // Zero length sessions aren't limits, they're mistakes.
+    assert!(DeviceConfigurationManager::new_with_options(
+      false,
+      &None,
+      &Some(
+        r#"{
+          "version": 2,
+          "protocols": {},
+          "devices": { "limited-address": { "session-limit": { "duration": 0 } } }
+        }"#
+        .to_owned()
+      )
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_supported_protocols() {
+    let config = DeviceConfigurationManager::default();
+    let protocols = config.supported_protocols();
+    assert!(protocols.windows(2).all(|pair| pair[0].name < pair[1].name));
+    let lovense = protocols.iter().find(|p| p.name == "lovense").unwrap();
+    assert!(lovense.matches_btle_name("LVS-Whatever"));
+    assert!(!lovense.matches_btle_name("Launch"));
+    assert!(lovense.matches("EDGE"));
+    let edge = lovense
+      .devices
+      .iter()
+      .find(|device| device.identifiers.contains(&"P".to_owned()))
+      .unwrap();
+    assert_eq!(edge.names.get("en-us").unwrap(), "Lovense Edge");
+    // Defaults are merged in, so Edge keeps battery support.
+    assert!(edge
+      .message_attributes
+      .contains_key(&ButtplugDeviceMessageType::BatteryLevelCmd));
+    assert_eq!(
+      edge
+        .message_attributes
+        .get(&ButtplugDeviceMessageType::VibrateCmd)
+        .unwrap()
+        .feature_count,
+      Some(2)
+    );
+    assert_eq!(
+      edge
+        .message_attributes
+        .get(&ButtplugDeviceMessageType::KeyedDeviceCmd)
+        .unwrap()
+        .keys,
+      Some(vec![
+        "Light".to_owned(),
+        "StorePattern".to_owned(),
+        "PlayPattern".to_owned()
+      ])
+    );
+
+    // Protocols without an implementation aren't supported, whatever the
+    // config says.
+    config.remove_protocol("lovense");
+    assert!(!config
+      .supported_protocols()
+      .iter()
+      .any(|p| p.name == "lovense"));
+  }
+
   // TODO Test invalid config load (not json)
   // TODO Test invalid user config load (not json)
   // TODO Test device config with repeated ble service
@@ -1,19 +1,28 @@
+pub mod composite;
 pub mod configuration_manager;
+#[cfg(feature = "packet-trace")]
+pub mod packet_trace;
 pub mod protocol;
+pub mod transcript;
 use serde::{
   de::{self, Visitor},
   Deserialize, Deserializer, Serialize, Serializer,
 };
 use std::{
+  collections::{BTreeMap, VecDeque},
   fmt::{self, Debug},
   str::FromStr,
   string::ToString,
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+  },
+  time::{Duration, Instant},
 };
 
 use crate::{
   core::{
-    errors::ButtplugError,
+    errors::{ButtplugDeviceError, ButtplugError},
     messages::{
       self, ButtplugDeviceCommandMessageUnion, ButtplugServerMessage, DeviceMessageAttributesMap,
       RawReadCmd, RawReading, RawSubscribeCmd, RawUnsubscribeCmd, RawWriteCmd,
@@ -22,15 +31,319 @@ use crate::{
   },
   device::{
     configuration_manager::{DeviceConfigurationManager, DeviceSpecifier, ProtocolDefinition},
-    protocol::ButtplugProtocol,
+    protocol::{
+      generic_command_manager::{step_to_speed, GenericCommandManager},
+      ButtplugProtocol,
+    },
+    transcript::{DeviceTranscript, DeviceTranscriptEvent, DeviceTranscriptRecorder},
   },
+  util::{async_manager, logging::redact_address},
 };
 use async_trait::async_trait;
 use configuration_manager::DeviceProtocolConfiguration;
 use core::hash::{Hash, Hasher};
-use futures::future::BoxFuture;
+use dashmap::DashSet;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+};
+use futures_timer::Delay;
 use tokio::sync::broadcast;
 
+/// Default for [DeviceWriteWatchdog::timeout]. Writes usually finish in well
+/// under a second, even with response, so anything this long means the
+/// transport is hung.
+pub const DEFAULT_DEVICE_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Catches device writes that never finish, so a hung transport (i.e. a stuck
+/// BLE stack) fails the write instead of wedging the device's command queue
+/// forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceWriteWatchdog {
+  /// How long a write can take before it's considered stuck.
+  pub timeout: Duration,
+  /// Disconnect the device when a write gets stuck. Otherwise, only the write
+  /// fails.
+  pub disconnect_on_timeout: bool,
+}
+
+impl Default for DeviceWriteWatchdog {
+  fn default() -> Self {
+    Self {
+      timeout: DEFAULT_DEVICE_WRITE_TIMEOUT,
+      disconnect_on_timeout: false,
+    }
+  }
+}
+
+/// Number of recent writes [DeviceStatisticsRecorder::average_write_latency]
+/// averages over.
+pub const DEVICE_WRITE_LATENCY_WINDOW: usize = 50;
+
+/// Rolling record of how well a device's transport has been working, kept
+/// from the time the device connects.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatisticsRecorder {
+  write_count: u32,
+  error_count: u32,
+  recent_write_latencies: VecDeque<Duration>,
+  last_error: Option<String>,
+}
+
+impl DeviceStatisticsRecorder {
+  fn record_write(&mut self, latency: Duration, result: &Result<(), ButtplugError>) {
+    self.write_count = self.write_count.saturating_add(1);
+    if self.recent_write_latencies.len() == DEVICE_WRITE_LATENCY_WINDOW {
+      self.recent_write_latencies.pop_front();
+    }
+    self.recent_write_latencies.push_back(latency);
+    if let Err(err) = result {
+      self.record_error(err);
+    }
+  }
+
+  fn record_error(&mut self, err: &ButtplugError) {
+    self.error_count = self.error_count.saturating_add(1);
+    self.last_error = Some(err.to_string());
+  }
+
+  pub fn write_count(&self) -> u32 {
+    self.write_count
+  }
+
+  /// Failed reads and writes.
+  pub fn error_count(&self) -> u32 {
+    self.error_count
+  }
+
+  /// Average latency over the last [DEVICE_WRITE_LATENCY_WINDOW] writes, or
+  /// zero if there haven't been any.
+  pub fn average_write_latency(&self) -> Duration {
+    if self.recent_write_latencies.is_empty() {
+      return Duration::default();
+    }
+    self.recent_write_latencies.iter().sum::<Duration>()
+      / self.recent_write_latencies.len() as u32
+  }
+
+  pub fn last_error(&self) -> &Option<String> {
+    &self.last_error
+  }
+}
+
+/// What a device's features were last told to do, as returned by
+/// [ButtplugDevice::command_state]. Speeds are quantized to the device's
+/// steps, so they match what the hardware actually got.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceCommandState {
+  /// Speed of each vibrator, 0.0-1.0.
+  pub vibrate_speeds: Vec<f64>,
+  /// Speed and direction (true for clockwise) of each rotator.
+  pub rotations: Vec<(f64, bool)>,
+  /// Last position each linear actuator was sent to, by index. Actuators
+  /// that haven't been moved yet are left out.
+  pub linear_positions: BTreeMap<u32, f64>,
+}
+
+/// Runs successful commands through a [GenericCommandManager] of its own,
+/// separate from the protocol's, to keep track of the device's state.
+struct DeviceCommandStateRecorder {
+  manager: GenericCommandManager,
+  linear_positions: BTreeMap<u32, f64>,
+}
+
+impl DeviceCommandStateRecorder {
+  fn new(attributes: &DeviceMessageAttributesMap) -> Self {
+    Self {
+      manager: GenericCommandManager::new(attributes),
+      linear_positions: BTreeMap::new(),
+    }
+  }
+
+  fn record(&mut self, msg: &ButtplugDeviceCommandMessageUnion) {
+    // The protocol already accepted the command, so any error here would be
+    // something the recorder doesn't care about (i.e. an empty command).
+    match msg {
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        let _ = self.manager.update_vibration(msg, false);
+      }
+      ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => {
+        let speeds = (0..self.manager.vibration_steps().len() as u32)
+          .map(|index| messages::VibrateSubcommand::new(index, msg.speed()))
+          .collect();
+        let _ = self
+          .manager
+          .update_vibration(&messages::VibrateCmd::new(0, speeds), false);
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        let _ = self.manager.update_rotation(msg);
+      }
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => {
+        for vector in msg.vectors() {
+          self
+            .linear_positions
+            .insert(vector.index(), *vector.position());
+        }
+      }
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => {
+        for stop_command in self.manager.get_stop_commands() {
+          self.record(&stop_command);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn state(&self) -> DeviceCommandState {
+    let step_counts = self.manager.vibration_step_counts();
+    let rotation_step_counts = self.manager.rotation_step_counts();
+    DeviceCommandState {
+      vibrate_speeds: self
+        .manager
+        .vibration_steps()
+        .iter()
+        .zip(step_counts)
+        .map(|(step, step_count)| step_to_speed(*step, *step_count))
+        .collect(),
+      rotations: self
+        .manager
+        .rotation_steps()
+        .iter()
+        .zip(rotation_step_counts)
+        .map(|((step, clockwise), step_count)| (step_to_speed(*step, *step_count), *clockwise))
+        .collect(),
+      linear_positions: self.linear_positions.clone(),
+    }
+  }
+}
+
+/// Connection interval tradeoffs a transport can be asked for, mirroring the
+/// connection priorities BLE stacks usually expose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceConnectionPriority {
+  /// Shortest connection interval. Lowest latency, most power use.
+  High,
+  /// Whatever the platform considers normal.
+  Balanced,
+  /// Longest connection interval. Commands take longer to arrive, but the
+  /// device's radio gets to sleep more.
+  LowPower,
+}
+
+/// Default for [DeviceIdlePowerManagement::idle_timeout].
+pub const DEFAULT_DEVICE_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Asks the transport for a lower power connection once a device has gone a
+/// while without being sent anything, and switches back as soon as commands
+/// start again. Saves toy batteries during long sessions where commands are
+/// sparse. Only transports that can change their connection parameters (see
+/// [DeviceImplInternal::supports_connection_priority]) use this, on anything
+/// else it's ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceIdlePowerManagement {
+  /// How long a device can go without reads or writes before it's idle.
+  pub idle_timeout: Duration,
+  /// Priority requested when the device becomes active again.
+  pub active_priority: DeviceConnectionPriority,
+  /// Priority requested once the device is idle.
+  pub idle_priority: DeviceConnectionPriority,
+}
+
+impl Default for DeviceIdlePowerManagement {
+  fn default() -> Self {
+    Self {
+      idle_timeout: DEFAULT_DEVICE_IDLE_TIMEOUT,
+      active_priority: DeviceConnectionPriority::Balanced,
+      idle_priority: DeviceConnectionPriority::LowPower,
+    }
+  }
+}
+
+/// Activity tracking for [DeviceIdlePowerManagement].
+#[derive(Default)]
+struct IdlePowerState {
+  settings: std::sync::RwLock<Option<DeviceIdlePowerManagement>>,
+  last_activity: std::sync::Mutex<Option<Instant>>,
+  idle: AtomicBool,
+  task_running: AtomicBool,
+  // Held while asking the transport for a new priority, so requests reach it
+  // in the order they were decided on.
+  applied_priority: tokio::sync::Mutex<Option<DeviceConnectionPriority>>,
+}
+
+impl IdlePowerState {
+  async fn apply_priority(
+    &self,
+    internal_impl: &dyn DeviceImplInternal,
+    settings: DeviceIdlePowerManagement,
+  ) {
+    let mut applied_priority = self.applied_priority.lock().await;
+    let priority = if self.idle.load(Ordering::SeqCst) {
+      settings.idle_priority
+    } else {
+      settings.active_priority
+    };
+    if *applied_priority == Some(priority) {
+      return;
+    }
+    debug!("Requesting {:?} connection priority.", priority);
+    match internal_impl.set_connection_priority(priority).await {
+      Ok(()) => *applied_priority = Some(priority),
+      Err(err) => error!("Cannot change device connection priority: {}", err),
+    }
+  }
+
+  fn time_until_idle(&self, settings: &DeviceIdlePowerManagement) -> Option<Duration> {
+    let elapsed = self
+      .last_activity
+      .lock()
+      .unwrap()
+      .map(|last_activity| last_activity.elapsed())
+      .unwrap_or_default();
+    settings
+      .idle_timeout
+      .checked_sub(elapsed)
+      .filter(|remaining| *remaining > Duration::default())
+  }
+}
+
+async fn run_idle_power_task(
+  state: Weak<IdlePowerState>,
+  internal_impl: Arc<dyn DeviceImplInternal>,
+) {
+  loop {
+    let wait = {
+      let state = match state.upgrade() {
+        Some(state) => state,
+        None => return,
+      };
+      let settings = {
+        let settings = state.settings.read().unwrap();
+        match *settings {
+          Some(settings) => settings,
+          None => {
+            // Cleared while holding the settings lock, so turning idle
+            // management back on can't miss that this task is stopping.
+            state.task_running.store(false, Ordering::SeqCst);
+            return;
+          }
+        }
+      };
+      match state.time_until_idle(&settings) {
+        Some(remaining) => remaining,
+        None => {
+          if internal_impl.connected() && !state.idle.swap(true, Ordering::SeqCst) {
+            info!("Device idle, requesting lower power connection.");
+            state.apply_priority(&*internal_impl, settings).await;
+          }
+          settings.idle_timeout
+        }
+      }
+    };
+    Delay::new(wait).await;
+  }
+}
+
 // We need this array to be exposed in our WASM FFI, but the only way to do that
 // is to expose it at the declaration level. Therefore, we use the WASM feature
 // to assume we're building for WASM and attach our bindgen. The serde
@@ -150,7 +463,7 @@ impl From<RawReadCmd> for DeviceReadCmd {
   }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct DeviceWriteCmd {
   pub endpoint: Endpoint,
   pub data: Vec<u8>,
@@ -267,6 +580,10 @@ impl From<DeviceUnsubscribeCmd> for DeviceImplCommand {
   }
 }
 
+/// Gets a device's writes instead of its transport, while the device is in
+/// dry run mode. See [DeviceImpl::set_dry_run].
+pub type DeviceDryRunHandler = Arc<dyn Fn(DeviceWriteCmd) + Send + Sync>;
+
 #[derive(Debug)]
 pub struct ButtplugDeviceImplInfo {
   pub endpoints: Vec<Endpoint>,
@@ -300,8 +617,21 @@ pub enum ButtplugDeviceEvent {
 pub struct DeviceImpl {
   name: String,
   address: String,
+  // Address of the physical connection. Only differs from address for
+  // subdevices of composite peripherals.
+  peripheral_address: String,
   endpoints: Vec<Endpoint>,
-  internal_impl: Box<dyn DeviceImplInternal>,
+  internal_impl: Arc<dyn DeviceImplInternal>,
+  transcript_recorder: std::sync::RwLock<Option<Arc<DeviceTranscriptRecorder>>>,
+  write_watchdog: std::sync::RwLock<Option<DeviceWriteWatchdog>>,
+  dry_run_handler: std::sync::RwLock<Option<DeviceDryRunHandler>>,
+  idle_power: Arc<IdlePowerState>,
+  statistics: Arc<std::sync::Mutex<DeviceStatisticsRecorder>>,
+  // Errors that happen outside of any command, i.e. stuck writes from
+  // protocol tasks.
+  error_sender: broadcast::Sender<ButtplugDeviceError>,
+  #[cfg(feature = "packet-trace")]
+  packet_tracer: packet_trace::PacketTracer,
 }
 
 impl DeviceImpl {
@@ -311,11 +641,24 @@ impl DeviceImpl {
     endpoints: &[Endpoint],
     internal_impl: Box<dyn DeviceImplInternal>,
   ) -> Self {
+    #[cfg(feature = "packet-trace")]
+    let packet_tracer = packet_trace::PacketTracer::new(address);
+    #[cfg(feature = "packet-trace")]
+    packet_tracer.trace_notifications(internal_impl.event_stream());
     Self {
       name: name.to_owned(),
       address: address.to_owned(),
+      peripheral_address: address.to_owned(),
       endpoints: endpoints.into(),
-      internal_impl,
+      internal_impl: internal_impl.into(),
+      transcript_recorder: std::sync::RwLock::new(None),
+      write_watchdog: std::sync::RwLock::new(Some(DeviceWriteWatchdog::default())),
+      dry_run_handler: std::sync::RwLock::new(None),
+      idle_power: Arc::new(IdlePowerState::default()),
+      statistics: Arc::new(std::sync::Mutex::new(DeviceStatisticsRecorder::default())),
+      error_sender: broadcast::channel(256).0,
+      #[cfg(feature = "packet-trace")]
+      packet_tracer,
     }
   }
 
@@ -327,6 +670,13 @@ impl DeviceImpl {
     &self.address
   }
 
+  /// Address of the peripheral this device is on. Same as
+  /// [address][Self::address], unless this is one part of a composite
+  /// peripheral (see [composite]).
+  pub fn peripheral_address(&self) -> &str {
+    &self.peripheral_address
+  }
+
   pub fn connected(&self) -> bool {
     self.internal_impl.connected()
   }
@@ -335,6 +685,12 @@ impl DeviceImpl {
     self.internal_impl.event_stream()
   }
 
+  /// Errors that aren't returned to anyone, like writes that got stuck while
+  /// no one was waiting on them.
+  pub fn error_stream(&self) -> broadcast::Receiver<ButtplugDeviceError> {
+    self.error_sender.subscribe()
+  }
+
   pub fn endpoints(&self) -> Vec<Endpoint> {
     self.endpoints.clone()
   }
@@ -343,24 +699,207 @@ impl DeviceImpl {
     self.internal_impl.disconnect()
   }
 
+  /// Sets how stuck writes are handled, or turns the watchdog off if `None`.
+  pub fn set_write_watchdog(&self, watchdog: Option<DeviceWriteWatchdog>) {
+    *self.write_watchdog.write().unwrap() = watchdog;
+  }
+
+  /// Puts the device in dry run mode, where writes are handed to `handler`
+  /// instead of the transport and always succeed, or takes it out of dry run
+  /// mode if `None`. Reads and subscriptions still go to the transport.
+  pub fn set_dry_run(&self, handler: Option<DeviceDryRunHandler>) {
+    *self.dry_run_handler.write().unwrap() = handler;
+  }
+
+  /// Sets how the device's connection is slowed down while idle, or turns
+  /// idle power management off if `None`.
+  pub fn set_idle_power_management(
+    &self,
+    idle_power_management: Option<DeviceIdlePowerManagement>,
+  ) {
+    if idle_power_management.is_some() && !self.internal_impl.supports_connection_priority() {
+      // No point waking up to track idle time if nothing can come of it.
+      debug!("Device transport cannot change connection priority, ignoring idle power management.");
+      return;
+    }
+    let mut settings = self.idle_power.settings.write().unwrap();
+    let old_settings = std::mem::replace(&mut *settings, idle_power_management);
+    *self.idle_power.last_activity.lock().unwrap() = Some(Instant::now());
+    if idle_power_management.is_some() {
+      if !self.idle_power.task_running.swap(true, Ordering::SeqCst) {
+        async_manager::spawn(run_idle_power_task(
+          Arc::downgrade(&self.idle_power),
+          self.internal_impl.clone(),
+        ))
+        .unwrap();
+      }
+    } else if let Some(old_settings) = old_settings {
+      // Don't leave the device stuck on a slow connection.
+      if self.idle_power.idle.swap(false, Ordering::SeqCst) {
+        let state = self.idle_power.clone();
+        let internal_impl = self.internal_impl.clone();
+        async_manager::spawn(async move {
+          state.apply_priority(&*internal_impl, old_settings).await;
+        })
+        .unwrap();
+      }
+    }
+  }
+
+  fn record_activity(&self) {
+    let settings = match *self.idle_power.settings.read().unwrap() {
+      Some(settings) => settings,
+      None => return,
+    };
+    *self.idle_power.last_activity.lock().unwrap() = Some(Instant::now());
+    if self.idle_power.idle.swap(false, Ordering::SeqCst) {
+      info!("Idle device active again, requesting faster connection.");
+      let state = self.idle_power.clone();
+      let internal_impl = self.internal_impl.clone();
+      async_manager::spawn(async move {
+        state.apply_priority(&*internal_impl, settings).await;
+      })
+      .unwrap();
+    }
+  }
+
   pub fn read_value(
     &self,
     msg: DeviceReadCmd,
   ) -> BoxFuture<'static, Result<RawReading, ButtplugError>> {
-    self.internal_impl.read_value(msg)
+    self.record_activity();
+    let read_fut = self.internal_impl.read_value(msg);
+    let recorder = self.transcript_recorder();
+    let statistics = self.statistics.clone();
+    #[cfg(feature = "packet-trace")]
+    let packet_tracer = self.packet_tracer.clone();
+    Box::pin(async move {
+      let reading = match read_fut.await {
+        Ok(reading) => reading,
+        Err(err) => {
+          statistics.lock().unwrap().record_error(&err);
+          return Err(err);
+        }
+      };
+      #[cfg(feature = "packet-trace")]
+      packet_tracer.trace(
+        packet_trace::PacketDirection::In,
+        reading.endpoint(),
+        reading.data(),
+      );
+      if let Some(recorder) = recorder {
+        recorder.record(DeviceTranscriptEvent::Read {
+          endpoint: reading.endpoint(),
+          data: reading.data().clone(),
+        });
+      }
+      Ok(reading)
+    })
   }
 
   pub fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture {
-    self.internal_impl.write_value(msg)
+    self.record_transcript_event(|| DeviceTranscriptEvent::Write {
+      endpoint: msg.endpoint,
+      data: msg.data.clone(),
+      write_with_response: msg.write_with_response,
+    });
+    #[cfg(feature = "packet-trace")]
+    self
+      .packet_tracer
+      .trace(packet_trace::PacketDirection::Out, msg.endpoint, &msg.data);
+    if let Some(handler) = self.dry_run_handler.read().unwrap().clone() {
+      handler(msg);
+      return Box::pin(future::ready(Ok(())));
+    }
+    self.record_activity();
+    let write_fut = self.watch_write(self.internal_impl.write_value(msg));
+    let statistics = self.statistics.clone();
+    let start = Instant::now();
+    Box::pin(async move {
+      let result = write_fut.await;
+      statistics
+        .lock()
+        .unwrap()
+        .record_write(start.elapsed(), &result);
+      result
+    })
+  }
+
+  /// Statistics on reads and writes since the device connected.
+  pub fn statistics(&self) -> DeviceStatisticsRecorder {
+    self.statistics.lock().unwrap().clone()
+  }
+
+  fn watch_write(&self, write_fut: ButtplugResultFuture) -> ButtplugResultFuture {
+    let watchdog = match *self.write_watchdog.read().unwrap() {
+      Some(watchdog) => watchdog,
+      None => return write_fut,
+    };
+    let internal_impl = self.internal_impl.clone();
+    let error_sender = self.error_sender.clone();
+    let address = self.address.clone();
+    Box::pin(async move {
+      select! {
+        result = write_fut.fuse() => result,
+        _ = Delay::new(watchdog.timeout).fuse() => {
+          let err =
+            ButtplugDeviceError::DeviceWriteTimeout(address, watchdog.timeout.as_millis() as u64);
+          error!("{}", err);
+          // No one listening is fine, the write still fails.
+          let _ = error_sender.send(err.clone());
+          if watchdog.disconnect_on_timeout {
+            if let Err(disconnect_err) = internal_impl.disconnect().await {
+              error!("Error disconnecting device with stuck write: {}", disconnect_err);
+            }
+          }
+          Err(err.into())
+        }
+      }
+    })
   }
 
   pub fn subscribe(&self, msg: DeviceSubscribeCmd) -> ButtplugResultFuture {
+    self.record_transcript_event(|| DeviceTranscriptEvent::Subscribe {
+      endpoint: msg.endpoint,
+    });
     self.internal_impl.subscribe(msg)
   }
 
   pub fn unsubscribe(&self, msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture {
+    self.record_transcript_event(|| DeviceTranscriptEvent::Unsubscribe {
+      endpoint: msg.endpoint,
+    });
     self.internal_impl.unsubscribe(msg)
   }
+
+  /// Starts recording everything sent to and received from the device,
+  /// replacing any recording already in progress.
+  pub fn start_transcript(&self) -> Arc<DeviceTranscriptRecorder> {
+    let recorder = DeviceTranscriptRecorder::new(&self.name, self.event_stream());
+    *self.transcript_recorder.write().unwrap() = Some(recorder.clone());
+    recorder
+  }
+
+  /// Stops recording, returning the transcript if a recording was in
+  /// progress.
+  pub fn stop_transcript(&self) -> Option<DeviceTranscript> {
+    self
+      .transcript_recorder
+      .write()
+      .unwrap()
+      .take()
+      .map(|recorder| recorder.transcript())
+  }
+
+  fn transcript_recorder(&self) -> Option<Arc<DeviceTranscriptRecorder>> {
+    self.transcript_recorder.read().unwrap().clone()
+  }
+
+  fn record_transcript_event(&self, event: impl FnOnce() -> DeviceTranscriptEvent) {
+    if let Some(recorder) = self.transcript_recorder.read().unwrap().as_ref() {
+      recorder.record(event());
+    }
+  }
 }
 
 pub trait DeviceImplInternal: Sync + Send {
@@ -374,6 +913,23 @@ pub trait DeviceImplInternal: Sync + Send {
   fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture;
   fn subscribe(&self, msg: DeviceSubscribeCmd) -> ButtplugResultFuture;
   fn unsubscribe(&self, msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture;
+  /// Whether [set_connection_priority][Self::set_connection_priority] does
+  /// anything. Idle power management is skipped for transports that return
+  /// false.
+  fn supports_connection_priority(&self) -> bool {
+    false
+  }
+  /// Asks the transport to change its connection interval. Only called if
+  /// [supports_connection_priority][Self::supports_connection_priority]
+  /// returns true.
+  fn set_connection_priority(&self, _priority: DeviceConnectionPriority) -> ButtplugResultFuture {
+    Box::pin(future::ready(Err(
+      ButtplugDeviceError::UnhandledCommand(
+        "Transport cannot change connection priority".to_owned(),
+      )
+      .into(),
+    )))
+  }
 }
 
 #[async_trait]
@@ -388,6 +944,8 @@ pub trait ButtplugDeviceImplCreator: Sync + Send + Debug {
 pub struct ButtplugDevice {
   protocol: Box<dyn ButtplugProtocol>,
   device: Arc<DeviceImpl>,
+  command_state: Arc<std::sync::Mutex<DeviceCommandStateRecorder>>,
+  raw_subscriptions: Arc<DashSet<Endpoint>>,
 }
 
 impl Debug for ButtplugDevice {
@@ -415,70 +973,174 @@ impl PartialEq for ButtplugDevice {
 
 impl ButtplugDevice {
   pub fn new(protocol: Box<dyn ButtplugProtocol>, device: Arc<DeviceImpl>) -> Self {
-    Self { protocol, device }
+    let command_state = DeviceCommandStateRecorder::new(&protocol.message_attributes());
+    Self {
+      protocol,
+      device,
+      command_state: Arc::new(std::sync::Mutex::new(command_state)),
+      raw_subscriptions: Arc::new(DashSet::new()),
+    }
   }
 
   pub fn address(&self) -> &str {
     self.device.address()
   }
 
+  pub fn peripheral_address(&self) -> &str {
+    self.device.peripheral_address()
+  }
+
+  pub fn error_stream(&self) -> broadcast::Receiver<ButtplugDeviceError> {
+    self.device.error_stream()
+  }
+
+  pub fn set_write_watchdog(&self, watchdog: Option<DeviceWriteWatchdog>) {
+    self.device.set_write_watchdog(watchdog);
+  }
+
+  pub fn set_dry_run(&self, handler: Option<DeviceDryRunHandler>) {
+    self.device.set_dry_run(handler);
+  }
+
+  pub fn set_idle_power_management(
+    &self,
+    idle_power_management: Option<DeviceIdlePowerManagement>,
+  ) {
+    self.device.set_idle_power_management(idle_power_management);
+  }
+
+  pub fn statistics(&self) -> DeviceStatisticsRecorder {
+    self.device.statistics()
+  }
+
+  /// What the device's features were last successfully told to do. Starts
+  /// out with everything stopped.
+  pub fn command_state(&self) -> DeviceCommandState {
+    self.command_state.lock().unwrap().state()
+  }
+
+  /// Whether the underlying transport still thinks the device is connected.
+  pub fn connected(&self) -> bool {
+    self.device.connected()
+  }
+
+  /// Creates a device from `device_creator`, if there's a protocol for it.
+  ///
+  /// Composite peripherals only return their first subdevice here, use
+  /// [try_create_devices][Self::try_create_devices] to get all of them.
   pub async fn try_create_device(
     device_config_mgr: Arc<DeviceConfigurationManager>,
-    mut device_creator: Box<dyn ButtplugDeviceImplCreator>,
+    device_creator: Box<dyn ButtplugDeviceImplCreator>,
   ) -> Result<Option<ButtplugDevice>, ButtplugError> {
+    Ok(
+      Self::try_create_devices(device_config_mgr, device_creator)
+        .await?
+        .into_iter()
+        .next(),
+    )
+  }
+
+  /// Creates all of the devices on the peripheral from `device_creator`. This
+  /// will be a single device unless the peripheral is a composite device, in
+  /// which case there will be one for each subdevice. Returns an empty vector
+  /// if there's no protocol for the peripheral.
+  pub async fn try_create_devices(
+    device_config_mgr: Arc<DeviceConfigurationManager>,
+    mut device_creator: Box<dyn ButtplugDeviceImplCreator>,
+  ) -> Result<Vec<ButtplugDevice>, ButtplugError> {
     // First off, we need to see if we even have a configuration available
-    // for the device we're trying to create. If we don't, return Ok(None),
-    // because this isn't actually an error. However, if we *do* have a
-    // configuration but something goes wrong after this, then it's an
+    // for the device we're trying to create. If we don't, return an empty
+    // vector, because this isn't actually an error. However, if we *do* have
+    // a configuration but something goes wrong after this, then it's an
     // error.
-
-    match device_config_mgr.find_configuration(&device_creator.get_specifier()) {
-      Some((allow_raw_messages, config_name, config)) => {
-        // Now that we have both a possible device implementation and a
-        // configuration for that device, try to initialize the implementation.
-        // This usually means trying to connect to whatever the device is,
-        // finding endpoints, etc.
-        let device_protocol_config = DeviceProtocolConfiguration::new(
-          allow_raw_messages,
-          config.defaults.clone(),
-          config.configurations.clone(),
-        );
-        // TODO Should we even return a config from the device_config_mgr if the
-        // protocol isn't there?
-        if device_config_mgr.has_protocol(&*config_name) {
-          match device_creator.try_create_device_impl(config).await {
-            Ok(device_impl) => {
-              info!(
-                address = tracing::field::display(device_impl.address()),
-                "Found Buttplug Device {}",
-                device_impl.name()
-              );
-              // If we've made it this far, we now have a connected device
-              // implementation with endpoints set up. We now need to run whatever
-              // protocol initialization might need to happen. We'll fetch a protocol
-              // creator, pass the device implementation to it, then let it do
-              // whatever it needs. For most protocols, this is a no-op. However, for
-              // devices like Lovense, some Kiiroo, etc, this can get fairly
-              // complicated.
-              let sharable_device_impl = Arc::new(device_impl);
-              match device_config_mgr.get_protocol_creator(&*config_name)(sharable_device_impl.clone(), device_protocol_config).await
-              {
-                Ok(protocol_impl) => Ok(Some(ButtplugDevice::new(
-                  protocol_impl,
-                  sharable_device_impl,
-                ))),
-                Err(e) => Err(e),
-              }
-            }
-            Err(e) => Err(e),
-          }
-        } else {
-          info!("Protocol {} not available", config_name);
-          Ok(None)
-        }
+    let (allow_raw_messages, config_name, config) =
+      match device_config_mgr.find_configuration(&device_creator.get_specifier()) {
+        Some(found) => found,
+        None => return Ok(vec![]),
+      };
+    let subdevices = config.subdevices.clone();
+    // TODO Should we even return a config from the device_config_mgr if the
+    // protocol isn't there?
+    let required_protocols: Vec<&String> = if subdevices.is_empty() {
+      vec![&config_name]
+    } else {
+      subdevices.iter().map(|subdevice| &subdevice.protocol).collect()
+    };
+    if let Some(protocol) = required_protocols
+      .into_iter()
+      .find(|protocol| !device_config_mgr.has_protocol(protocol))
+    {
+      info!("Protocol {} not available", protocol);
+      return Ok(vec![]);
+    }
+    // Now that we have both a possible device implementation and a
+    // configuration for that device, try to initialize the implementation.
+    // This usually means trying to connect to whatever the device is,
+    // finding endpoints, etc.
+    let device_protocol_config = DeviceProtocolConfiguration::new(
+      allow_raw_messages,
+      config.defaults.clone(),
+      config.configurations.clone(),
+    )
+    .with_quirks(config.quirks.clone());
+    let init_sequence = config.init_sequence.clone();
+    let device_impl = device_creator.try_create_device_impl(config).await?;
+    info!(
+      address = tracing::field::display(redact_address(device_impl.address())),
+      "Found Buttplug Device {}",
+      device_impl.name()
+    );
+    // Some devices need a few writes to wake them up before anything
+    // else happens. These are listed in the device config, so run
+    // them before the protocol gets its chance at initialization.
+    for step in init_sequence {
+      device_impl
+        .write_value(DeviceWriteCmd::new(
+          step.endpoint,
+          step.data,
+          step.write_with_response,
+        ))
+        .await?;
+      if step.delay > 0 {
+        Delay::new(Duration::from_millis(step.delay)).await;
       }
-      None => Ok(None),
     }
+    // If we've made it this far, we now have a connected device
+    // implementation with endpoints set up. We now need to run whatever
+    // protocol initialization might need to happen. We'll fetch a protocol
+    // creator, pass the device implementation to it, then let it do
+    // whatever it needs. For most protocols, this is a no-op. However, for
+    // devices like Lovense, some Kiiroo, etc, this can get fairly
+    // complicated.
+    let sharable_device_impl = Arc::new(device_impl);
+    if subdevices.is_empty() {
+      let protocol_impl = device_config_mgr.get_protocol_creator(&config_name)(
+        sharable_device_impl.clone(),
+        device_protocol_config,
+      )
+      .await?;
+      return Ok(vec![ButtplugDevice::new(protocol_impl, sharable_device_impl)]);
+    }
+    // Composite peripherals get a device for each part, each with its own
+    // protocol and its own view of the peripheral's endpoints.
+    let mut devices = vec![];
+    for (index, subdevice) in subdevices.iter().enumerate() {
+      let subdevice_protocol_config = device_config_mgr
+        .get_protocol_config(&subdevice.protocol)
+        .unwrap_or_else(|| DeviceProtocolConfiguration::new(allow_raw_messages, None, vec![]));
+      let subdevice_impl = Arc::new(composite::new_subdevice_impl(
+        sharable_device_impl.clone(),
+        index,
+        subdevice,
+      ));
+      let protocol_impl = device_config_mgr.get_protocol_creator(&subdevice.protocol)(
+        subdevice_impl.clone(),
+        subdevice_protocol_config,
+      )
+      .await?;
+      devices.push(ButtplugDevice::new(protocol_impl, subdevice_impl));
+    }
+    Ok(devices)
   }
 
   pub fn name(&self) -> String {
@@ -513,12 +1175,376 @@ impl ButtplugDevice {
     &self,
     message: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugDeviceResultFuture {
-    self.protocol.handle_command(self.device.clone(), message)
+    self
+      .device
+      .record_transcript_event(|| DeviceTranscriptEvent::Message(message.clone()));
+    let raw_subscription = match &message {
+      ButtplugDeviceCommandMessageUnion::RawSubscribeCmd(msg) => Some((msg.endpoint(), true)),
+      ButtplugDeviceCommandMessageUnion::RawUnsubscribeCmd(msg) => Some((msg.endpoint(), false)),
+      _ => None,
+    };
+    if let Some((endpoint, subscribed)) = raw_subscription {
+      let raw_subscriptions = self.raw_subscriptions.clone();
+      let command_fut = self.protocol.handle_command(self.device.clone(), message);
+      return Box::pin(async move {
+        let result = command_fut.await;
+        if result.is_ok() {
+          if subscribed {
+            raw_subscriptions.insert(endpoint);
+          } else {
+            raw_subscriptions.remove(&endpoint);
+          }
+        }
+        result
+      });
+    }
+    let recorded_message = match message {
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(_)
+      | ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(_)
+      | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+      | ButtplugDeviceCommandMessageUnion::LinearCmd(_)
+      | ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => message.clone(),
+      _ => return self.protocol.handle_command(self.device.clone(), message),
+    };
+    let command_state = self.command_state.clone();
+    let command_fut = self.protocol.handle_command(self.device.clone(), message);
+    Box::pin(async move {
+      let result = command_fut.await;
+      if result.is_ok() {
+        command_state.lock().unwrap().record(&recorded_message);
+      }
+      result
+    })
+  }
+
+  /// See [DeviceImpl::start_transcript]. Messages passed to
+  /// [parse_message][Self::parse_message] are recorded along with the
+  /// hardware traffic.
+  pub fn start_transcript(&self) -> Arc<DeviceTranscriptRecorder> {
+    self.device.start_transcript()
+  }
+
+  pub fn stop_transcript(&self) -> Option<DeviceTranscript> {
+    self.device.stop_transcript()
   }
 
   pub fn event_stream(&self) -> broadcast::Receiver<ButtplugDeviceEvent> {
     self.device.event_stream()
   }
 
-  // TODO Handle raw messages here.
+  /// True if a client has subscribed to `endpoint` with RawSubscribeCmd, so
+  /// notifications from it should be sent on as RawReadings.
+  pub fn raw_subscribed(&self, endpoint: &Endpoint) -> bool {
+    self.raw_subscriptions.contains(endpoint)
+  }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use super::{
+    configuration_manager::{BluetoothLESpecifier, DeviceConfigurationManager, DeviceSpecifier},
+    ButtplugDevice, ButtplugDeviceEvent, DeviceConnectionPriority, DeviceIdlePowerManagement,
+    DeviceImplCommand, DeviceWriteCmd, DeviceWriteWatchdog, Endpoint,
+  };
+  use crate::{
+    core::{
+      errors::{ButtplugDeviceError, ButtplugError},
+      messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
+    },
+    test::{
+      check_test_recv_empty, check_test_recv_value, new_bluetoothle_test_device,
+      new_bluetoothle_test_device_with_cfg, TestDeviceImplCreator, TestDeviceInternal,
+    },
+    util::async_manager,
+  };
+  use futures_timer::Delay;
+  use std::{sync::Arc, time::Duration};
+
+  const INIT_SEQUENCE_CONFIG: &str = r#"
+  {
+    "version": 1,
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": ["Init Test"],
+          "services": {
+            "0000ffe0-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ffe1-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "init-sequence": [
+          { "endpoint": "tx", "data": [0], "delay": 10 },
+          { "endpoint": "tx", "data": [1, 2], "write-with-response": true }
+        ],
+        "defaults": {
+          "name": { "en-us": "Init Test Device" },
+          "messages": {
+            "VibrateCmd": { "FeatureCount": 1, "StepCount": [127] }
+          }
+        }
+      }
+    }
+  }
+  "#;
+
+  #[test]
+  fn test_device_init_sequence() {
+    async_manager::block_on(async move {
+      let config = DeviceConfigurationManager::new_with_options(
+        false,
+        &Some(INIT_SEQUENCE_CONFIG.to_owned()),
+        &None,
+      )
+      .unwrap();
+      let (_device, test_device) =
+        new_bluetoothle_test_device_with_cfg("Init Test", Some(Arc::new(config)))
+          .await
+          .unwrap();
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0], false)),
+      );
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![1, 2], true)),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+    });
+  }
+
+  const COMPOSITE_CONFIG: &str = r#"
+  {
+    "version": 1,
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": ["Not A Composite"],
+          "services": {
+            "0000ffe0-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ffe1-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": { "en-us": "Composite Part" },
+          "messages": {
+            "VibrateCmd": { "FeatureCount": 1, "StepCount": [127] }
+          }
+        }
+      },
+      "composite-test": {
+        "btle": {
+          "names": ["Composite Test"],
+          "services": {
+            "0000ffe0-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ffe1-0000-1000-8000-00805f9b34fb",
+              "txvibrate": "0000ffe2-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "subdevices": [
+          { "protocol": "aneros", "endpoints": { "tx": "tx" } },
+          { "protocol": "aneros", "endpoints": { "tx": "txvibrate" } }
+        ]
+      }
+    }
+  }
+  "#;
+
+  #[test]
+  fn test_composite_device() {
+    async_manager::block_on(async move {
+      let config = DeviceConfigurationManager::new_with_options(
+        false,
+        &Some(COMPOSITE_CONFIG.to_owned()),
+        &None,
+      )
+      .unwrap();
+      let test_device = Arc::new(TestDeviceInternal::new("Composite Test", "composite"));
+      let creator = TestDeviceImplCreator::new(
+        DeviceSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device("Composite Test")),
+        test_device.clone(),
+      );
+      let devices = ButtplugDevice::try_create_devices(Arc::new(config), Box::new(creator))
+        .await
+        .unwrap();
+      assert_eq!(devices.len(), 2);
+      assert_eq!(devices[0].address(), "composite/0");
+      assert_eq!(devices[1].address(), "composite/1");
+      assert!(devices
+        .iter()
+        .all(|device| device.peripheral_address() == "composite"));
+
+      // Each part only writes to its own endpoint.
+      let tx_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      let txvibrate_receiver = test_device
+        .get_endpoint_receiver(&Endpoint::TxVibrate)
+        .unwrap();
+      devices[1]
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &txvibrate_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::TxVibrate, vec![0xF1, 64], false)),
+      );
+      assert!(check_test_recv_empty(&tx_receiver));
+      devices[0]
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.0)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &tx_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 127], false)),
+      );
+
+      // Losing the peripheral removes every part.
+      let mut event_streams: Vec<_> = devices.iter().map(|device| device.event_stream()).collect();
+      test_device.disconnect().await.unwrap();
+      for (device, event_stream) in devices.iter().zip(event_streams.iter_mut()) {
+        match event_stream.recv().await.unwrap() {
+          ButtplugDeviceEvent::Removed(address) => assert_eq!(address, device.address()),
+          event => panic!("Unexpected event {:?}", event),
+        }
+      }
+    });
+  }
+
+  #[test]
+  fn test_write_watchdog() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      device.set_write_watchdog(Some(DeviceWriteWatchdog {
+        timeout: Duration::from_millis(50),
+        disconnect_on_timeout: true,
+      }));
+      let mut error_stream = device.error_stream();
+      let mut event_stream = device.event_stream();
+      test_device.set_hang_writes(true);
+      let err = device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap_err();
+      assert!(matches!(
+        err,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceWriteTimeout(_, 50))
+      ));
+      assert!(matches!(
+        error_stream.recv().await.unwrap(),
+        ButtplugDeviceError::DeviceWriteTimeout(_, 50)
+      ));
+      assert!(matches!(
+        event_stream.recv().await.unwrap(),
+        ButtplugDeviceEvent::Removed(_)
+      ));
+    });
+  }
+
+  #[test]
+  fn test_idle_power_management() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      device.set_idle_power_management(Some(DeviceIdlePowerManagement {
+        idle_timeout: Duration::from_millis(100),
+        ..Default::default()
+      }));
+      // Commands keep the device from going idle.
+      for &speed in &[0.25, 0.5, 0.75] {
+        Delay::new(Duration::from_millis(50)).await;
+        device
+          .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, speed)]).into())
+          .await
+          .unwrap();
+      }
+      assert!(test_device.connection_priorities().is_empty());
+      Delay::new(Duration::from_millis(200)).await;
+      assert_eq!(
+        test_device.connection_priorities(),
+        vec![DeviceConnectionPriority::LowPower]
+      );
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.0)]).into())
+        .await
+        .unwrap();
+      Delay::new(Duration::from_millis(20)).await;
+      assert_eq!(
+        test_device.connection_priorities(),
+        vec![
+          DeviceConnectionPriority::LowPower,
+          DeviceConnectionPriority::Balanced
+        ]
+      );
+    });
+  }
+
+  #[test]
+  fn test_idle_power_management_unsupported() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      test_device.set_supports_connection_priority(false);
+      device.set_idle_power_management(Some(DeviceIdlePowerManagement {
+        idle_timeout: Duration::from_millis(50),
+        ..Default::default()
+      }));
+      Delay::new(Duration::from_millis(100)).await;
+      assert!(test_device.connection_priorities().is_empty());
+    });
+  }
+
+  #[test]
+  fn test_device_statistics() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      device.set_write_watchdog(Some(DeviceWriteWatchdog {
+        timeout: Duration::from_millis(50),
+        disconnect_on_timeout: false,
+      }));
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      let statistics = device.statistics();
+      assert_eq!(statistics.write_count(), 1);
+      assert_eq!(statistics.error_count(), 0);
+      assert!(statistics.last_error().is_none());
+      test_device.set_hang_writes(true);
+      assert!(device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.0)]).into())
+        .await
+        .is_err());
+      let statistics = device.statistics();
+      assert_eq!(statistics.write_count(), 2);
+      assert_eq!(statistics.error_count(), 1);
+      assert!(statistics.last_error().is_some());
+      assert!(statistics.average_write_latency() >= Duration::from_millis(25));
+    });
+  }
+
+  #[test]
+  fn test_device_command_state() {
+    async_manager::block_on(async move {
+      let (device, _) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      assert_eq!(device.command_state().vibrate_speeds, vec![0.0, 0.0]);
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.5)]).into())
+        .await
+        .unwrap();
+      // Reported after quantizing to the device's 127 steps.
+      assert_eq!(device.command_state().vibrate_speeds, vec![0.0, 64.0 / 127.0]);
+      assert!(device.command_state().rotations.is_empty());
+      // Commands the device rejects don't change anything.
+      assert!(device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(5, 1.0)]).into())
+        .await
+        .is_err());
+      assert_eq!(device.command_state().vibrate_speeds, vec![0.0, 64.0 / 127.0]);
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      assert_eq!(device.command_state().vibrate_speeds, vec![0.0, 0.0]);
+    });
+  }
 }
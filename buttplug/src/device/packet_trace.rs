@@ -0,0 +1,109 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Packet level logging for protocol development.
+//!
+//! With the `packet-trace` feature on, every write, read and notification
+//! going through a [DeviceImpl][super::DeviceImpl] is logged at trace level
+//! under the `buttplug::packet` target, with the device address, endpoint,
+//! direction, time since the device was created, and the data as hex. Turn it
+//! on with something like `RUST_LOG=buttplug::packet=trace`.
+
+use super::{ButtplugDeviceEvent, Endpoint};
+use crate::util::async_manager;
+use std::{fmt, time::Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+pub const PACKET_TRACE_TARGET: &str = "buttplug::packet";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+  /// Written to the device.
+  Out,
+  /// Read from, or sent by, the device.
+  In,
+}
+
+impl fmt::Display for PacketDirection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PacketDirection::Out => write!(f, "->"),
+      PacketDirection::In => write!(f, "<-"),
+    }
+  }
+}
+
+/// Formats bytes as space separated hex, i.e. `f1 40`.
+pub fn hex_dump(data: &[u8]) -> String {
+  data
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Logs packets for one device.
+#[derive(Clone)]
+pub struct PacketTracer {
+  address: String,
+  start: Instant,
+}
+
+impl PacketTracer {
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      start: Instant::now(),
+    }
+  }
+
+  pub fn trace(&self, direction: PacketDirection, endpoint: Endpoint, data: &[u8]) {
+    let elapsed = self.start.elapsed();
+    trace!(
+      target: PACKET_TRACE_TARGET,
+      address = %self.address,
+      %endpoint,
+      %direction,
+      elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+      len = data.len(),
+      "[{:>10.3}ms] {} {} {} {}",
+      elapsed.as_secs_f64() * 1000.0,
+      self.address,
+      direction,
+      endpoint,
+      hex_dump(data)
+    );
+  }
+
+  /// Logs notifications from `receiver` until the device goes away.
+  pub fn trace_notifications(&self, mut receiver: broadcast::Receiver<ButtplugDeviceEvent>) {
+    let tracer = self.clone();
+    async_manager::spawn(async move {
+      loop {
+        match receiver.recv().await {
+          Ok(ButtplugDeviceEvent::Notification(_, endpoint, data)) => {
+            tracer.trace(PacketDirection::In, endpoint, &data)
+          }
+          Ok(_) | Err(RecvError::Lagged(_)) => continue,
+          Err(RecvError::Closed) => break,
+        }
+      }
+    })
+    .unwrap();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_hex_dump() {
+    assert_eq!(hex_dump(&[]), "");
+    assert_eq!(hex_dump(&[0xf1, 0x40, 0x0a]), "f1 40 0a");
+  }
+}
@@ -1,15 +1,22 @@
 use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
 use crate::{
-  core::messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  core::messages::{
+    self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributes,
+    DeviceMessageAttributesMap,
+  },
   device::{
-    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    protocol::{
+      generic_command_manager::GenericCommandManager, ButtplugProtocolCapabilities,
+      ButtplugProtocolProperties,
+    },
     DeviceImpl, DeviceWriteCmd, Endpoint,
   },
 };
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-#[derive(ButtplugProtocolProperties)]
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(127, 127)))]
 pub struct Aneros {
   name: String,
   message_attributes: DeviceMessageAttributesMap,
@@ -25,6 +32,7 @@ impl ButtplugProtocol for Aneros {
   where
     Self: Sized,
   {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
     let manager = GenericCommandManager::new(&message_attributes);
 
     Box::new(Self {
@@ -70,13 +78,50 @@ impl ButtplugProtocolCommandHandler for Aneros {
 
 #[cfg(all(test, feature = "server"))]
 mod test {
+  use super::Aneros;
   use crate::{
-    core::messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
-    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    core::messages::{
+      ButtplugDeviceMessageType, DeviceMessageAttributes, DeviceMessageAttributesMap,
+      StopDeviceCmd, VibrateCmd, VibrateSubcommand,
+    },
+    device::{
+      protocol::ButtplugProtocolCapabilities, DeviceImplCommand, DeviceWriteCmd, Endpoint,
+    },
     test::{check_test_recv_empty, check_test_recv_value, new_bluetoothle_test_device},
     util::async_manager,
   };
 
+  #[test]
+  pub fn test_aneros_declared_capabilities() {
+    async_manager::block_on(async move {
+      let declared = Aneros::declared_message_attributes();
+      let vibrate = &declared[&ButtplugDeviceMessageType::VibrateCmd];
+      assert_eq!(vibrate.feature_count, Some(2));
+      assert_eq!(vibrate.step_count, Some(vec![127, 127]));
+      assert_eq!(Aneros::declared_stop_commands().len(), 1);
+      // The shipped device config should agree with the protocol.
+      let (device, _) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      let attrs = device.message_attributes();
+      assert_eq!(&attrs[&ButtplugDeviceMessageType::VibrateCmd], vibrate);
+      // Config that has drifted gets the declared counts, keeping the rest.
+      let mut drifted = DeviceMessageAttributesMap::new();
+      drifted.insert(
+        ButtplugDeviceMessageType::VibrateCmd,
+        DeviceMessageAttributes {
+          feature_count: Some(1),
+          step_count: Some(vec![20]),
+          feature_order: Some(vec![1, 0]),
+          ..Default::default()
+        },
+      );
+      let merged = Aneros::with_declared_capabilities("Aneros Vivi", drifted);
+      let vibrate = &merged[&ButtplugDeviceMessageType::VibrateCmd];
+      assert_eq!(vibrate.feature_count, Some(2));
+      assert_eq!(vibrate.step_count, Some(vec![127, 127]));
+      assert_eq!(vibrate.feature_order, Some(vec![1, 0]));
+    });
+  }
+
   #[test]
   pub fn test_aneros_protocol() {
     async_manager::block_on(async move {
@@ -0,0 +1,136 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::messages::{
+    self, ButtplugDeviceCommandMessageUnion, ButtplugMessage, DeviceMessageAttributesMap,
+  },
+  device::{
+    protocol::{
+      generic_command_manager::{stop_commands, suction_steps},
+      ButtplugProtocolProperties,
+    },
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use futures::future;
+use std::sync::Arc;
+
+/// Sets the pump pressure step, 0 turning the pump off.
+fn pressure_command(step: u8) -> DeviceWriteCmd {
+  DeviceWriteCmd::new(Endpoint::Tx, vec![0xA0, 0x01, step], false)
+}
+
+/// Opens the release valve, letting go of any held pressure at once.
+fn release_command() -> DeviceWriteCmd {
+  DeviceWriteCmd::new(Endpoint::Tx, vec![0xA0, 0x02, 0x01], false)
+}
+
+/// BLE auto-pumps. The pump holds whatever pressure it was last set to, so
+/// stopping the device also opens the release valve, as turning the pump off
+/// alone would leave it holding suction.
+#[derive(ButtplugProtocolProperties)]
+pub struct AutoPump {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+}
+
+impl ButtplugProtocol for AutoPump {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    Box::new(Self {
+      name: name.to_owned(),
+      stop_commands: stop_commands(&message_attributes),
+      message_attributes,
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for AutoPump {
+  fn handle_suction_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    msg: messages::SuctionCmd,
+  ) -> ButtplugDeviceResultFuture {
+    // Auto-pumps only have a single pump.
+    let step = match suction_steps(&msg, &self.message_attributes) {
+      Ok(steps) => steps[0].1 as u8,
+      Err(err) => return Box::pin(future::ready(Err(err))),
+    };
+    let fut = device.write_value(pressure_command(step));
+    Box::pin(async {
+      fut.await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
+
+  fn handle_stop_device_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    msg: messages::StopDeviceCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let ok_return = messages::Ok::new(msg.id());
+    Box::pin(async move {
+      // Release even if turning the pump off failed, since that's the write
+      // that actually gets rid of the pressure.
+      let pump_off = device.write_value(pressure_command(0)).await;
+      device.write_value(release_command()).await?;
+      pump_off?;
+      Ok(ok_return.into())
+    })
+  }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use crate::{
+    core::messages::{StopDeviceCmd, SuctionCmd, SuctionSubcommand},
+    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    test::{check_test_recv_empty, check_test_recv_value, new_bluetoothle_test_device},
+    util::async_manager,
+  };
+
+  #[test]
+  pub fn test_auto_pump_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("AUTO-PUMP").await.unwrap();
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      device
+        .parse_message(SuctionCmd::new(0, vec![SuctionSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0xA0, 0x01, 0x05],
+          false,
+        )),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+      // Stopping turns the pump off and releases the pressure.
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0xA0, 0x01, 0x00],
+          false,
+        )),
+      );
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0xA0, 0x02, 0x01],
+          false,
+        )),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+    });
+  }
+}
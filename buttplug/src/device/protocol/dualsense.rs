@@ -0,0 +1,183 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributes,
+      DeviceMessageAttributesMap,
+    },
+  },
+  device::{
+    configuration_manager::is_bluetooth_hid_address,
+    protocol::{
+      generic_command_manager::GenericCommandManager,
+      sony_controller_helper::{self, SonyControllerOutputs, LIGHTBAR_KEY},
+      ButtplugProtocolCapabilities, ButtplugProtocolProperties,
+    },
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use std::sync::{
+  atomic::{AtomicU8, Ordering},
+  Arc,
+};
+use tokio::sync::Mutex;
+
+const USB_REPORT_SIZE: usize = 63;
+const BLUETOOTH_REPORT_SIZE: usize = 78;
+// Rumble emulation ("compatible vibration") plus haptics select, otherwise the
+// DualSense ignores the motor values.
+const OUTPUT_FLAG0_RUMBLE: u8 = 0x03;
+const OUTPUT_FLAG1_LIGHTBAR: u8 = 0x04;
+const BLUETOOTH_OUTPUT_TAG: u8 = 0x10;
+
+/// Builds a DualSense output report. `sequence` is only used for Bluetooth
+/// reports.
+fn output_report(bluetooth: bool, sequence: u8, outputs: &SonyControllerOutputs) -> Vec<u8> {
+  // Both report types share the same block of output settings, Bluetooth just
+  // adds a sequence number and tag in front and a CRC at the end.
+  let (mut report, offset) = if bluetooth {
+    let mut report = vec![0u8; BLUETOOTH_REPORT_SIZE];
+    report[0] = 0x31;
+    report[1] = (sequence & 0x0f) << 4;
+    report[2] = BLUETOOTH_OUTPUT_TAG;
+    (report, 3)
+  } else {
+    let mut report = vec![0u8; USB_REPORT_SIZE];
+    report[0] = 0x02;
+    (report, 1)
+  };
+  report[offset] = OUTPUT_FLAG0_RUMBLE;
+  report[offset + 2] = outputs.small_motor;
+  report[offset + 3] = outputs.large_motor;
+  if let Some(level) = outputs.lightbar {
+    report[offset + 1] = OUTPUT_FLAG1_LIGHTBAR;
+    report[offset + 44..offset + 47].copy_from_slice(&[level, level, level]);
+  }
+  if bluetooth {
+    sony_controller_helper::set_bluetooth_report_crc(&mut report);
+  }
+  report
+}
+
+async fn write_outputs(
+  device: &DeviceImpl,
+  sequence: &AtomicU8,
+  outputs: &SonyControllerOutputs,
+) -> Result<(), ButtplugError> {
+  let report = output_report(
+    is_bluetooth_hid_address(device.address()),
+    sequence.fetch_add(1, Ordering::SeqCst),
+    outputs,
+  );
+  device
+    .write_value(DeviceWriteCmd::new(Endpoint::Tx, report, false))
+    .await
+}
+
+/// DualSense rumble (via its rumble emulation mode), over USB or Bluetooth.
+/// Motors are ordered the same as the DualShock 4, and lightbar brightness is
+/// set with the Lightbar KeyedDeviceCmd key.
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(255, 255)))]
+pub struct DualSense {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  outputs: Arc<Mutex<SonyControllerOutputs>>,
+  sequence: Arc<AtomicU8>,
+}
+
+impl ButtplugProtocol for DualSense {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+      outputs: Arc::new(Mutex::new(SonyControllerOutputs::default())),
+      sequence: Arc::new(AtomicU8::new(0)),
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for DualSense {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let outputs = self.outputs.clone();
+    let sequence = self.sequence.clone();
+    Box::pin(async move {
+      // Every report sets both motors, so always ask for all values.
+      let result = manager.lock().await.update_vibration(&message, true)?;
+      if let Some(cmds) = result {
+        let value = |index: usize| cmds.get(index).copied().flatten().unwrap_or(0) as u8;
+        let mut outputs = outputs.lock().await;
+        outputs.large_motor = value(0);
+        outputs.small_motor = value(1);
+        write_outputs(&device, &sequence, &outputs).await?;
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+
+  fn handle_keyed_device_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::KeyedDeviceCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let outputs = self.outputs.clone();
+    let sequence = self.sequence.clone();
+    Box::pin(async move {
+      if message.key() != LIGHTBAR_KEY {
+        return Err(ButtplugDeviceError::KeyNotSupported(message.key().to_owned()).into());
+      }
+      let level = sony_controller_helper::parse_lightbar_value(message.value())?;
+      let mut outputs = outputs.lock().await;
+      outputs.lightbar = Some(level);
+      write_outputs(&device, &sequence, &outputs).await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_dualsense_usb_report() {
+    let outputs = SonyControllerOutputs {
+      large_motor: 0x80,
+      small_motor: 0x40,
+      lightbar: Some(0x10),
+    };
+    let report = output_report(false, 0, &outputs);
+    assert_eq!(report.len(), USB_REPORT_SIZE);
+    assert_eq!(report[..5], [0x02, 0x03, 0x04, 0x40, 0x80]);
+    assert_eq!(report[45..48], [0x10, 0x10, 0x10]);
+  }
+
+  #[test]
+  fn test_dualsense_bluetooth_report() {
+    let outputs = SonyControllerOutputs {
+      large_motor: 0x80,
+      small_motor: 0x40,
+      lightbar: None,
+    };
+    let report = output_report(true, 17, &outputs);
+    assert_eq!(report.len(), BLUETOOTH_REPORT_SIZE);
+    assert_eq!(report[..7], [0x31, 0x10, 0x10, 0x03, 0x00, 0x40, 0x80]);
+    assert_ne!(report[74..], [0, 0, 0, 0]);
+  }
+}
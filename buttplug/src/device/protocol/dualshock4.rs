@@ -0,0 +1,176 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributes,
+      DeviceMessageAttributesMap,
+    },
+  },
+  device::{
+    configuration_manager::is_bluetooth_hid_address,
+    protocol::{
+      generic_command_manager::GenericCommandManager,
+      sony_controller_helper::{self, SonyControllerOutputs, LIGHTBAR_KEY},
+      ButtplugProtocolCapabilities, ButtplugProtocolProperties,
+    },
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const USB_REPORT_SIZE: usize = 32;
+const BLUETOOTH_REPORT_SIZE: usize = 78;
+const OUTPUT_FLAG_RUMBLE: u8 = 0x01;
+const OUTPUT_FLAG_LIGHTBAR: u8 = 0x02;
+
+/// Builds a DualShock 4 output report.
+fn output_report(bluetooth: bool, outputs: &SonyControllerOutputs) -> Vec<u8> {
+  // Bluetooth reports have a couple of extra header bytes, and a CRC at the
+  // end, but are otherwise the same as USB.
+  let (mut report, flags_offset, data_offset) = if bluetooth {
+    let mut report = vec![0u8; BLUETOOTH_REPORT_SIZE];
+    report[0] = 0x11;
+    // Enable HID output and CRC.
+    report[1] = 0xc0;
+    (report, 3, 6)
+  } else {
+    let mut report = vec![0u8; USB_REPORT_SIZE];
+    report[0] = 0x05;
+    (report, 1, 4)
+  };
+  report[flags_offset] = OUTPUT_FLAG_RUMBLE;
+  report[data_offset] = outputs.small_motor;
+  report[data_offset + 1] = outputs.large_motor;
+  if let Some(level) = outputs.lightbar {
+    report[flags_offset] |= OUTPUT_FLAG_LIGHTBAR;
+    report[data_offset + 2..data_offset + 5].copy_from_slice(&[level, level, level]);
+  }
+  if bluetooth {
+    sony_controller_helper::set_bluetooth_report_crc(&mut report);
+  }
+  report
+}
+
+async fn write_outputs(
+  device: &DeviceImpl,
+  outputs: &SonyControllerOutputs,
+) -> Result<(), ButtplugError> {
+  let report = output_report(is_bluetooth_hid_address(device.address()), outputs);
+  device
+    .write_value(DeviceWriteCmd::new(Endpoint::Tx, report, false))
+    .await
+}
+
+/// DualShock 4 rumble, over USB or Bluetooth. Feature 0 is the large motor,
+/// feature 1 the small one. Lightbar brightness is set with the Lightbar
+/// KeyedDeviceCmd key.
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(255, 255)))]
+pub struct DualShock4 {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  outputs: Arc<Mutex<SonyControllerOutputs>>,
+}
+
+impl ButtplugProtocol for DualShock4 {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+      outputs: Arc::new(Mutex::new(SonyControllerOutputs::default())),
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for DualShock4 {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let outputs = self.outputs.clone();
+    Box::pin(async move {
+      // Every report sets both motors, so always ask for all values.
+      let result = manager.lock().await.update_vibration(&message, true)?;
+      if let Some(cmds) = result {
+        let value = |index: usize| cmds.get(index).copied().flatten().unwrap_or(0) as u8;
+        let mut outputs = outputs.lock().await;
+        outputs.large_motor = value(0);
+        outputs.small_motor = value(1);
+        write_outputs(&device, &outputs).await?;
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+
+  fn handle_keyed_device_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::KeyedDeviceCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let outputs = self.outputs.clone();
+    Box::pin(async move {
+      if message.key() != LIGHTBAR_KEY {
+        return Err(ButtplugDeviceError::KeyNotSupported(message.key().to_owned()).into());
+      }
+      let level = sony_controller_helper::parse_lightbar_value(message.value())?;
+      let mut outputs = outputs.lock().await;
+      outputs.lightbar = Some(level);
+      write_outputs(&device, &outputs).await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_dualshock4_usb_report() {
+    let mut outputs = SonyControllerOutputs {
+      large_motor: 0x80,
+      small_motor: 0x40,
+      lightbar: None,
+    };
+    let report = output_report(false, &outputs);
+    assert_eq!(report.len(), USB_REPORT_SIZE);
+    assert_eq!(report[..6], [0x05, 0x01, 0x00, 0x00, 0x40, 0x80]);
+    outputs.lightbar = Some(0x10);
+    let report = output_report(false, &outputs);
+    assert_eq!(
+      report[..9],
+      [0x05, 0x03, 0x00, 0x00, 0x40, 0x80, 0x10, 0x10, 0x10]
+    );
+  }
+
+  #[test]
+  fn test_dualshock4_bluetooth_report() {
+    let outputs = SonyControllerOutputs {
+      large_motor: 0x80,
+      small_motor: 0x40,
+      lightbar: None,
+    };
+    let report = output_report(true, &outputs);
+    assert_eq!(report.len(), BLUETOOTH_REPORT_SIZE);
+    assert_eq!(
+      report[..8],
+      [0x11, 0xc0, 0x00, 0x01, 0x00, 0x00, 0x40, 0x80]
+    );
+    // Make sure we actually wrote a checksum.
+    assert_ne!(report[74..], [0, 0, 0, 0]);
+  }
+}
@@ -0,0 +1,336 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! ErosTek ET-312B e-stim box, over its serial link.
+//!
+//! The box talks a register read/write protocol, where everything the host
+//! sends after connecting is XORed with a key agreed on during the handshake.
+//! The key stays set until the box is power cycled, so connecting a second
+//! time without restarting the box fails the handshake.
+//!
+//! Since this drives current through people, the protocol is more careful than
+//! most:
+//!
+//! - The front panel level knobs are disconnected and both channel levels are
+//!   set to 0 as part of connecting, before the device is exposed to clients.
+//!   If that fails, the device doesn't connect.
+//! - Levels are clamped to the configured step count before being scaled to
+//!   register values, so nothing out of range is ever written.
+//! - Every write waits for the box to acknowledge it, so a level that didn't
+//!   take is an error instead of being silently dropped.
+//!
+//! Register addresses and packet formats are from the
+//! [buttshock](https://github.com/buttshock/buttshock-protocol-docs) protocol
+//! docs.
+
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType,
+      DeviceMessageAttributesMap,
+    },
+  },
+  device::{
+    configuration_manager::DeviceProtocolConfiguration,
+    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    ButtplugDeviceEvent, DeviceImpl, DeviceSubscribeCmd, DeviceWriteCmd, Endpoint,
+  },
+};
+use futures::{future::BoxFuture, FutureExt};
+use futures_timer::Delay;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+const ET312_PROTOCOL_NAME: &str = "ET312";
+const ET312_REPLY_TIMEOUT_MS: u64 = 500;
+// The box needs up to 11 sync bytes to get back to the start of a command.
+const ET312_SYNC_RETRY: usize = 11;
+
+const ET312_SYNC: u8 = 0x00;
+const ET312_SYNC_REPLY: u8 = 0x07;
+const ET312_KEY_EXCHANGE: u8 = 0x2f;
+const ET312_KEY_EXCHANGE_REPLY: u8 = 0x21;
+const ET312_READ: u8 = 0x3c;
+const ET312_READ_REPLY: u8 = 0x22;
+const ET312_WRITE_ACK: u8 = 0x06;
+const ET312_KEY_MAGIC: u8 = 0x55;
+// Key we offer the box. Any value works, the box mixes in its own.
+const ET312_HOST_KEY: u8 = 0x00;
+
+// Bit 0 turns off the ADC, which stops the level knobs overwriting the level
+// registers.
+const ET312_SYSTEM_FLAGS_ADDRESS: u16 = 0x400f;
+const ET312_ADC_DISABLE: u8 = 0x01;
+const ET312_LEVEL_ADDRESSES: [u16; 2] = [0x4064, 0x4065];
+const ET312_LEVEL_MAX: u32 = 0xff;
+
+fn et312_error(message: &str) -> ButtplugError {
+  ButtplugDeviceError::ProtocolSpecificError(ET312_PROTOCOL_NAME.to_owned(), message.to_owned())
+    .into()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+  data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Appends the checksum to a command.
+fn with_checksum(mut data: Vec<u8>) -> Vec<u8> {
+  data.push(checksum(&data));
+  data
+}
+
+fn write_packet(address: u16, value: u8) -> Vec<u8> {
+  // The high nibble is the packet length, including the checksum.
+  let [high, low] = address.to_be_bytes();
+  with_checksum(vec![0x4d, high, low, value])
+}
+
+fn read_packet(address: u16) -> Vec<u8> {
+  let [high, low] = address.to_be_bytes();
+  with_checksum(vec![ET312_READ, high, low])
+}
+
+/// Scales a step from the generic command manager to a level register value,
+/// clamping it to `step_count` first.
+fn level_for_step(step: u32, step_count: u32) -> u8 {
+  if step_count == 0 {
+    return 0;
+  }
+  (step.min(step_count) * ET312_LEVEL_MAX / step_count) as u8
+}
+
+/// Serial link to the box, with the key from the handshake. Commands and their
+/// replies have to go one at a time, so this lives behind a mutex.
+struct Et312Link {
+  key: u8,
+  events: broadcast::Receiver<ButtplugDeviceEvent>,
+  buffer: Vec<u8>,
+}
+
+impl Et312Link {
+  fn new(events: broadcast::Receiver<ButtplugDeviceEvent>) -> Self {
+    Self {
+      key: 0,
+      events,
+      buffer: vec![],
+    }
+  }
+
+  async fn send(&mut self, device: &DeviceImpl, data: &[u8]) -> Result<(), ButtplugError> {
+    // Anything left over belongs to a command we've given up on.
+    self.buffer.clear();
+    let data = data.iter().map(|byte| byte ^ self.key).collect();
+    device
+      .write_value(DeviceWriteCmd::new(Endpoint::Tx, data, false))
+      .await
+  }
+
+  /// Waits for `count` bytes from the box.
+  async fn receive(&mut self, count: usize) -> Result<Vec<u8>, ButtplugError> {
+    while self.buffer.len() < count {
+      select! {
+        event = self.events.recv().fuse() => match event {
+          Ok(ButtplugDeviceEvent::Notification(_, _, data)) => self.buffer.extend(data),
+          Ok(ButtplugDeviceEvent::Removed(_)) | Err(broadcast::error::RecvError::Closed) => {
+            return Err(et312_error("Device disconnected while waiting for a reply."))
+          }
+          Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+        },
+        _ = Delay::new(Duration::from_millis(ET312_REPLY_TIMEOUT_MS)).fuse() => {
+          return Err(et312_error("Timed out waiting for a reply."));
+        }
+      }
+    }
+    Ok(self.buffer.drain(..count).collect())
+  }
+
+  async fn handshake(&mut self, device: &DeviceImpl) -> Result<(), ButtplugError> {
+    let mut synced = false;
+    for _ in 0..ET312_SYNC_RETRY {
+      self.send(device, &[ET312_SYNC]).await?;
+      if let Ok(reply) = self.receive(1).await {
+        if reply[0] == ET312_SYNC_REPLY {
+          synced = true;
+          break;
+        }
+      }
+    }
+    if !synced {
+      return Err(et312_error("Box did not respond to sync."));
+    }
+    self
+      .send(device, &with_checksum(vec![ET312_KEY_EXCHANGE, ET312_HOST_KEY]))
+      .await?;
+    let reply = self.receive(3).await?;
+    if reply[0] != ET312_KEY_EXCHANGE_REPLY || reply[2] != checksum(&reply[..2]) {
+      return Err(et312_error(
+        "Key exchange failed. The box keeps its key until it's turned off, so power cycle it and \
+         try again.",
+      ));
+    }
+    self.key = reply[1] ^ ET312_HOST_KEY ^ ET312_KEY_MAGIC;
+    Ok(())
+  }
+
+  async fn read_register(
+    &mut self,
+    device: &DeviceImpl,
+    address: u16,
+  ) -> Result<u8, ButtplugError> {
+    self.send(device, &read_packet(address)).await?;
+    let reply = self.receive(3).await?;
+    if reply[0] != ET312_READ_REPLY || reply[2] != checksum(&reply[..2]) {
+      return Err(et312_error(&format!("Bad reply reading register {:#06x}.", address)));
+    }
+    Ok(reply[1])
+  }
+
+  async fn write_register(
+    &mut self,
+    device: &DeviceImpl,
+    address: u16,
+    value: u8,
+  ) -> Result<(), ButtplugError> {
+    self.send(device, &write_packet(address, value)).await?;
+    let reply = self.receive(1).await?;
+    if reply[0] != ET312_WRITE_ACK {
+      return Err(et312_error(&format!("Write to register {:#06x} was not acknowledged.", address)));
+    }
+    Ok(())
+  }
+
+  /// Takes the levels away from the front panel knobs and zeroes them.
+  async fn take_level_control(&mut self, device: &DeviceImpl) -> Result<(), ButtplugError> {
+    let flags = self.read_register(device, ET312_SYSTEM_FLAGS_ADDRESS).await?;
+    self
+      .write_register(device, ET312_SYSTEM_FLAGS_ADDRESS, flags | ET312_ADC_DISABLE)
+      .await?;
+    for address in ET312_LEVEL_ADDRESSES {
+      self.write_register(device, address, 0).await?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(ButtplugProtocolProperties)]
+pub struct Et312 {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  // Only set when created through try_create(), which does the handshake.
+  link: Arc<Mutex<Option<Et312Link>>>,
+}
+
+impl Et312 {
+  fn new_with_link(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+    link: Option<Et312Link>,
+  ) -> Self {
+    let manager = GenericCommandManager::new(&message_attributes);
+    Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+      link: Arc::new(Mutex::new(link)),
+    }
+  }
+}
+
+impl ButtplugProtocol for Et312 {
+  fn try_create(
+    device_impl: Arc<DeviceImpl>,
+    config: DeviceProtocolConfiguration,
+  ) -> BoxFuture<'static, Result<Box<dyn ButtplugProtocol>, ButtplugError>> {
+    Box::pin(async move {
+      let mut link = Et312Link::new(device_impl.event_stream());
+      device_impl
+        .subscribe(DeviceSubscribeCmd::new(Endpoint::Rx))
+        .await?;
+      link.handshake(&device_impl).await?;
+      link.take_level_control(&device_impl).await?;
+      info!("ET312 connected, levels zeroed.");
+      let (names, attrs) = config.get_attributes(device_impl.name(), &device_impl.endpoints())?;
+      let name = names.get("en-us").unwrap().clone();
+      Ok(Box::new(Self::new_with_link(&name, attrs, Some(link))) as Box<dyn ButtplugProtocol>)
+    })
+  }
+
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol>
+  where
+    Self: Sized,
+  {
+    Box::new(Self::new_with_link(name, message_attributes, None))
+  }
+}
+
+impl ButtplugProtocolCommandHandler for Et312 {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let link = self.link.clone();
+    let step_counts = self
+      .message_attributes
+      .get(&ButtplugDeviceMessageType::VibrateCmd)
+      .and_then(|attributes| attributes.step_count.clone())
+      .unwrap_or_default();
+    Box::pin(async move {
+      let result = manager.lock().await.update_vibration(&message, false)?;
+      let mut link = link.lock().await;
+      let link = link
+        .as_mut()
+        .ok_or_else(|| et312_error("Box has not completed the handshake."))?;
+      if let Some(cmds) = result {
+        for (index, cmd) in cmds.iter().enumerate() {
+          if let (Some(step), Some(address), Some(step_count)) = (
+            cmd,
+            ET312_LEVEL_ADDRESSES.get(index),
+            step_counts.get(index),
+          ) {
+            let level = level_for_step(*step, *step_count);
+            link.write_register(&device, *address, level).await?;
+          }
+        }
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_et312_packets() {
+    assert_eq!(write_packet(0x4064, 0x10), vec![0x4d, 0x40, 0x64, 0x10, 0x01]);
+    assert_eq!(read_packet(0x400f), vec![0x3c, 0x40, 0x0f, 0x8b]);
+    assert_eq!(
+      with_checksum(vec![ET312_KEY_EXCHANGE, ET312_HOST_KEY]),
+      vec![0x2f, 0x00, 0x2f]
+    );
+  }
+
+  #[test]
+  fn test_et312_level_clamping() {
+    assert_eq!(level_for_step(0, 99), 0);
+    assert_eq!(level_for_step(99, 99), 0xff);
+    assert_eq!(level_for_step(200, 99), 0xff);
+    assert_eq!(level_for_step(50, 100), 127);
+    assert_eq!(level_for_step(10, 0), 0);
+  }
+}
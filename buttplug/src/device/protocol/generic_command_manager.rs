@@ -1,11 +1,150 @@
+//! Turns generic device commands into the step values devices actually use.
+//!
+//! Most protocols hold a [GenericCommandManager] and run every VibrateCmd or
+//! RotateCmd through it. It converts 0.0-1.0 speeds into steps, using the
+//! StepCount attribute from the device config, and keeps track of what was
+//! last sent so unchanged features can be skipped. Protocols added at runtime
+//! via [ButtplugServer::add_protocol][crate::server::ButtplugServer::add_protocol]
+//! can (and should) use it too, along with [speed_to_step],
+//! [step_to_speed], [temperature_steps], [suction_steps] and
+//! [stop_commands], so they behave the same way as the built in protocols.
+
 use crate::core::{
   errors::{ButtplugDeviceError, ButtplugError},
   messages::{
     ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributesMap,
-    LinearCmd, RotateCmd, RotationSubcommand, VibrateCmd, VibrateSubcommand,
+    LinearCmd, RotateCmd, RotationSubcommand, SuctionCmd, SuctionSubcommand, TemperatureCmd,
+    TemperatureSubcommand, VibrateCmd, VibrateSubcommand,
   },
 };
 
+/// Converts a generic 0.0-1.0 command value into the discrete step range the
+/// device feature actually supports, as listed in the StepCount attribute.
+///
+/// Steps are rounded up. This follows how we calculated things in
+/// buttplug-js and buttplug-csharp, so it's more for history than anything,
+/// but it's what users will expect.
+pub fn speed_to_step(value: f64, step_count: u32) -> u32 {
+  (value * step_count as f64).ceil() as u32
+}
+
+/// Converts a step back into a 0.0-1.0 value, for hardware that takes
+/// something other than the raw step (i.e. a duty cycle or amplitude).
+/// Returns 0.0 if `step_count` is 0.
+pub fn step_to_speed(step: u32, step_count: u32) -> f64 {
+  if step_count == 0 {
+    0.0
+  } else {
+    step as f64 / step_count as f64
+  }
+}
+
+/// Converts (feature index, 0.0-1.0 level) pairs into (feature index, step)
+/// pairs for actuators where going past the device config is unsafe.
+///
+/// Unlike [speed_to_step], steps are rounded down, and never go past the
+/// feature's StepCount, so the device config is always the upper bound on
+/// what the device does, no matter what was asked for.
+fn bounded_steps(
+  message_type: ButtplugDeviceMessageType,
+  levels: Vec<(u32, f64)>,
+  attributes: &DeviceMessageAttributesMap,
+) -> Result<Vec<(u32, u32)>, ButtplugError> {
+  if levels.is_empty() {
+    return Err(
+      ButtplugDeviceError::ProtocolRequirementError(format!(
+        "{:?} has 0 commands, will not do anything.",
+        message_type
+      ))
+      .into(),
+    );
+  }
+  let attrs = attributes
+    .get(&message_type)
+    .ok_or(ButtplugDeviceError::MessageNotSupported(message_type))?;
+  let feature_count = attrs.feature_count.unwrap_or(0);
+  let step_counts = attrs.step_count.clone().unwrap_or_default();
+  let mut steps = Vec::with_capacity(levels.len());
+  for (index, level) in levels {
+    if index >= feature_count {
+      return Err(ButtplugDeviceError::DeviceFeatureIndexError(feature_count, index).into());
+    }
+    let step_count = *step_counts.get(index as usize).ok_or_else(|| {
+      ButtplugDeviceError::ProtocolRequirementError(format!(
+        "{:?} feature {} has no step count, will not run without a limit.",
+        message_type, index
+      ))
+    })?;
+    let step = (level.clamp(0.0, 1.0) * step_count as f64).floor() as u32;
+    steps.push((index, step.min(step_count)));
+  }
+  Ok(steps)
+}
+
+/// Converts the levels in a [TemperatureCmd] into (heater index, step) pairs,
+/// never going past the heater's StepCount. Protocols with heaters should
+/// always get their steps from here.
+pub fn temperature_steps(
+  msg: &TemperatureCmd,
+  attributes: &DeviceMessageAttributesMap,
+) -> Result<Vec<(u32, u32)>, ButtplugError> {
+  let levels = msg.levels().iter().map(|cmd| (cmd.index(), cmd.level())).collect();
+  bounded_steps(ButtplugDeviceMessageType::TemperatureCmd, levels, attributes)
+}
+
+/// Converts the levels in a [SuctionCmd] into (pump index, step) pairs, never
+/// going past the pump's StepCount, which is its maximum safe pressure.
+/// Protocols with pumps should always get their steps from here.
+pub fn suction_steps(
+  msg: &SuctionCmd,
+  attributes: &DeviceMessageAttributesMap,
+) -> Result<Vec<(u32, u32)>, ButtplugError> {
+  let levels = msg.levels().iter().map(|cmd| (cmd.index(), cmd.level())).collect();
+  bounded_steps(ButtplugDeviceMessageType::SuctionCmd, levels, attributes)
+}
+
+/// Commands that stop every vibrator, rotator, heater and pump described in
+/// `attributes`. This is what [GenericCommandManager::get_stop_commands]
+/// returns, for protocols that don't use a command manager. The device index
+/// of the commands is always 0, since the device sets it when stopping.
+pub fn stop_commands(
+  attributes: &DeviceMessageAttributesMap,
+) -> Vec<ButtplugDeviceCommandMessageUnion> {
+  let mut stop_commands = vec![];
+  if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::VibrateCmd) {
+    let subcommands = (0..attr.feature_count.unwrap_or(0))
+      .map(|index| VibrateSubcommand::new(index, 0.0))
+      .collect();
+    stop_commands.push(VibrateCmd::new(0, subcommands).into());
+  }
+  if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::RotateCmd) {
+    // TODO Can we assume clockwise is false here? We might send extra
+    // messages on Lovense since it'll require both a speed and change
+    // direction command, but is that really a big deal? We can just
+    // have it ignore the direction difference on a 0.0 speed?
+    let subcommands = (0..attr.feature_count.unwrap_or(0))
+      .map(|index| RotationSubcommand::new(index, 0.0, false))
+      .collect();
+    stop_commands.push(RotateCmd::new(0, subcommands).into());
+  }
+  // Heaters are turned off along with everything else.
+  if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::TemperatureCmd) {
+    let subcommands = (0..attr.feature_count.unwrap_or(0))
+      .map(|index| TemperatureSubcommand::new(index, 0.0))
+      .collect();
+    stop_commands.push(TemperatureCmd::new(0, subcommands).into());
+  }
+  if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::SuctionCmd) {
+    let subcommands = (0..attr.feature_count.unwrap_or(0))
+      .map(|index| SuctionSubcommand::new(index, 0.0))
+      .collect();
+    stop_commands.push(SuctionCmd::new(0, subcommands).into());
+  }
+  stop_commands
+}
+
+/// Keeps the last step values sent to each feature of a device, so protocols
+/// only send what changed.
 pub struct GenericCommandManager {
   sent_vibration: bool,
   sent_rotation: bool,
@@ -17,6 +156,7 @@ pub struct GenericCommandManager {
   _linears: Vec<(u32, u32)>,
   _linear_step_counts: Vec<u32>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  always_resend: bool,
 }
 
 impl GenericCommandManager {
@@ -28,8 +168,6 @@ impl GenericCommandManager {
     let mut linears: Vec<(u32, u32)> = vec![];
     let mut linear_step_counts: Vec<u32> = vec![];
 
-    let mut stop_commands = vec![];
-
     // TODO We should probably panic here if we don't have feature and step counts?
     if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::VibrateCmd) {
       if let Some(count) = attr.feature_count {
@@ -38,12 +176,13 @@ impl GenericCommandManager {
       if let Some(step_counts) = &attr.step_count {
         vibration_step_counts = step_counts.clone();
       }
-
-      let mut subcommands = vec![];
-      for i in 0..vibrations.len() {
-        subcommands.push(VibrateSubcommand::new(i as u32, 0.0));
+      if vibration_step_counts.len() != vibrations.len() {
+        error!(
+          "VibrateCmd has {} features but {} step counts, commands to features without step counts will fail.",
+          vibrations.len(),
+          vibration_step_counts.len()
+        );
       }
-      stop_commands.push(VibrateCmd::new(0, subcommands).into());
     }
     if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::RotateCmd) {
       if let Some(count) = attr.feature_count {
@@ -52,16 +191,13 @@ impl GenericCommandManager {
       if let Some(step_counts) = &attr.step_count {
         rotation_step_counts = step_counts.clone();
       }
-
-      // TODO Can we assume clockwise is false here? We might send extra
-      // messages on Lovense since it'll require both a speed and change
-      // direction command, but is that really a big deal? We can just
-      // have it ignore the direction difference on a 0.0 speed?
-      let mut subcommands = vec![];
-      for i in 0..rotations.len() {
-        subcommands.push(RotationSubcommand::new(i as u32, 0.0, false));
+      if rotation_step_counts.len() != rotations.len() {
+        error!(
+          "RotateCmd has {} features but {} step counts, commands to features without step counts will fail.",
+          rotations.len(),
+          rotation_step_counts.len()
+        );
       }
-      stop_commands.push(RotateCmd::new(0, subcommands).into());
     }
     if let Some(attr) = attributes.get(&ButtplugDeviceMessageType::LinearCmd) {
       if let Some(count) = attr.feature_count {
@@ -82,10 +218,53 @@ impl GenericCommandManager {
       vibration_step_counts,
       rotation_step_counts,
       _linear_step_counts: linear_step_counts,
-      stop_commands,
+      stop_commands: stop_commands(attributes),
+      always_resend: false,
     }
   }
 
+  /// For devices that stop if they don't keep getting commands. When set,
+  /// updates always return values for every feature, even if nothing changed,
+  /// so the protocol can resend them (usually via a
+  /// [RepeatingCommandWriter][super::repeating_command_writer::RepeatingCommandWriter]).
+  pub fn set_always_resend(&mut self, always_resend: bool) {
+    self.always_resend = always_resend;
+  }
+
+  /// Forgets what was sent, so the next update returns every value it's
+  /// given. Useful when the device may have lost its state, i.e. after
+  /// reconnecting.
+  pub fn reset(&mut self) {
+    self.sent_vibration = false;
+    self.sent_rotation = false;
+    self._sent_linear = false;
+  }
+
+  /// Last step sent to each vibrator. All 0 until the first update.
+  pub fn vibration_steps(&self) -> &[u32] {
+    &self.vibrations
+  }
+
+  /// StepCount of each vibrator, from the device config.
+  pub fn vibration_step_counts(&self) -> &[u32] {
+    &self.vibration_step_counts
+  }
+
+  /// Last step and direction (true for clockwise) sent to each rotator.
+  pub fn rotation_steps(&self) -> &[(u32, bool)] {
+    &self.rotations
+  }
+
+  /// StepCount of each rotator, from the device config.
+  pub fn rotation_step_counts(&self) -> &[u32] {
+    &self.rotation_step_counts
+  }
+
+  /// Converts `msg` to steps and records them. Returns `None` if nothing
+  /// changed since the last update, otherwise a step for each vibrator, with
+  /// `None` for vibrators that don't need to be sent. If `match_all` is set
+  /// and anything changed, every vibrator gets a step, for hardware that
+  /// sets all of them with one command.
   pub fn update_vibration(
     &mut self,
     msg: &VibrateCmd,
@@ -108,7 +287,8 @@ impl GenericCommandManager {
     // If we've already sent commands before, we should check against our
     // old values. Otherwise, we should always send whatever command we're
     // going to send.
-    let mut changed_value = false;
+    let match_all = match_all || self.always_resend;
+    let mut changed_value = self.always_resend;
     let mut result: Vec<Option<u32>> = vec![None; self.vibrations.len()];
     // If we're in a match all situation, set up the array with all prior
     // values before switching them out.
@@ -132,10 +312,13 @@ impl GenericCommandManager {
         );
       }
 
-      // When calculating speeds, round up. This follows how we calculated
-      // things in buttplug-js and buttplug-csharp, so it's more for history
-      // than anything, but it's what users will expect.
-      let speed = (speed_command.speed() * self.vibration_step_counts[index] as f64).ceil() as u32;
+      let step_count = self.vibration_step_counts.get(index).ok_or_else(|| {
+        ButtplugDeviceError::ProtocolRequirementError(format!(
+          "VibrateCmd feature {} has no StepCount attribute, cannot convert speed.",
+          index
+        ))
+      })?;
+      let speed = speed_to_step(speed_command.speed(), *step_count);
 
       // If we've already sent commands, we don't want to send them again,
       // because some of our communication busses are REALLY slow. Make sure
@@ -166,6 +349,9 @@ impl GenericCommandManager {
     }
   }
 
+  /// Converts `msg` to steps and records them. Returns a step and direction
+  /// for each rotator, with `None` for rotators that haven't changed since
+  /// the last update.
   pub fn update_rotation(
     &mut self,
     msg: &RotateCmd,
@@ -187,7 +373,11 @@ impl GenericCommandManager {
     // If we've already sent commands before, we should check against our
     // old values. Otherwise, we should always send whatever command we're
     // going to send.
-    let mut result: Vec<Option<(u32, bool)>> = vec![None; self.rotations.len()];
+    let mut result: Vec<Option<(u32, bool)>> = if self.always_resend {
+      self.rotations.iter().copied().map(Some).collect()
+    } else {
+      vec![None; self.rotations.len()]
+    };
     for rotate_command in &msg.rotations {
       let index = rotate_command.index() as usize;
       // Since we're going to iterate here anyways, we do our index check
@@ -203,10 +393,13 @@ impl GenericCommandManager {
         );
       }
 
-      // When calculating speeds, round up. This follows how we calculated
-      // things in buttplug-js and buttplug-csharp, so it's more for history
-      // than anything, but it's what users will expect.
-      let speed = (rotate_command.speed() * self.rotation_step_counts[index] as f64).ceil() as u32;
+      let step_count = self.rotation_step_counts.get(index).ok_or_else(|| {
+        ButtplugDeviceError::ProtocolRequirementError(format!(
+          "RotateCmd feature {} has no StepCount attribute, cannot convert speed.",
+          index
+        ))
+      })?;
+      let speed = speed_to_step(rotate_command.speed(), *step_count);
       let clockwise = rotate_command.clockwise();
       // If we've already sent commands, we don't want to send them again,
       // because some of our communication busses are REALLY slow. Make sure
@@ -214,6 +407,7 @@ impl GenericCommandManager {
       if !self.sent_rotation
         || speed != self.rotations[index].0
         || clockwise != self.rotations[index].1
+        || self.always_resend
       {
         self.rotations[index] = (speed, clockwise);
         result[index] = Some((speed, clockwise));
@@ -248,6 +442,8 @@ impl GenericCommandManager {
     Ok(None)
   }
 
+  /// Commands that stop every feature the manager knows about. See
+  /// [stop_commands].
   pub fn get_stop_commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
     self.stop_commands.clone()
   }
@@ -256,10 +452,14 @@ impl GenericCommandManager {
 #[cfg(test)]
 mod test {
 
-  use super::GenericCommandManager;
+  use super::{
+    speed_to_step, step_to_speed, stop_commands, suction_steps, temperature_steps,
+    GenericCommandManager,
+  };
   use crate::core::messages::{
     ButtplugDeviceMessageType, DeviceMessageAttributes, DeviceMessageAttributesMap, RotateCmd,
-    RotationSubcommand, VibrateCmd, VibrateSubcommand,
+    RotationSubcommand, SuctionCmd, SuctionSubcommand, TemperatureCmd, TemperatureSubcommand,
+    VibrateCmd, VibrateSubcommand,
   };
   #[test]
   pub fn test_command_generator_vibration() {
@@ -337,5 +537,196 @@ mod test {
     assert!(mgr.update_rotation(&rotate_msg_invalid).is_err());
   }
 
+  #[test]
+  pub fn test_command_generator_vibration_step_quantization() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+
+    let vibrate_attributes = DeviceMessageAttributes {
+      feature_count: Some(1),
+      step_count: Some(vec![20]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::VibrateCmd, vibrate_attributes);
+    let mut mgr = GenericCommandManager::new(&attributes_map);
+    assert_eq!(
+      mgr
+        .update_vibration(&VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.51)]), false)
+        .unwrap(),
+      Some(vec![Some(11)])
+    );
+    // 0.54 still lands on step 11 of 20, so there's nothing new to send.
+    assert_eq!(
+      mgr
+        .update_vibration(&VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.54)]), false)
+        .unwrap(),
+      None
+    );
+    assert_eq!(
+      mgr
+        .update_vibration(&VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.56)]), false)
+        .unwrap(),
+      Some(vec![Some(12)])
+    );
+  }
+
+  #[test]
+  pub fn test_command_generator_missing_step_count() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+
+    let vibrate_attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![20]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::VibrateCmd, vibrate_attributes);
+    let mut mgr = GenericCommandManager::new(&attributes_map);
+    assert!(mgr
+      .update_vibration(&VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.5)]), false)
+      .is_err());
+  }
+
+  #[test]
+  pub fn test_temperature_steps() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+    let temperature_attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![3]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::TemperatureCmd, temperature_attributes);
+    let steps = |levels: Vec<TemperatureSubcommand>| {
+      temperature_steps(&TemperatureCmd::new(0, levels), &attributes_map)
+    };
+    // Rounded down, so heaters never end up hotter than asked.
+    assert_eq!(steps(vec![TemperatureSubcommand::new(0, 0.9)]).unwrap(), vec![(0, 2)]);
+    assert_eq!(steps(vec![TemperatureSubcommand::new(0, 1.0)]).unwrap(), vec![(0, 3)]);
+    // Out of range levels stay within the step count.
+    assert_eq!(steps(vec![TemperatureSubcommand::new(0, 5.0)]).unwrap(), vec![(0, 3)]);
+    // Heaters without a step count, or that don't exist, are refused.
+    assert!(steps(vec![TemperatureSubcommand::new(1, 0.5)]).is_err());
+    assert!(steps(vec![TemperatureSubcommand::new(2, 0.5)]).is_err());
+    assert!(steps(vec![]).is_err());
+  }
+
+  #[test]
+  pub fn test_suction_steps_and_stop() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+    let suction_attributes = DeviceMessageAttributes {
+      feature_count: Some(1),
+      step_count: Some(vec![10]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::SuctionCmd, suction_attributes);
+    let msg = SuctionCmd::new(0, vec![SuctionSubcommand::new(0, 0.99)]);
+    assert_eq!(suction_steps(&msg, &attributes_map).unwrap(), vec![(0, 9)]);
+    assert_eq!(
+      stop_commands(&attributes_map),
+      vec![SuctionCmd::new(0, vec![SuctionSubcommand::new(0, 0.0)]).into()]
+    );
+  }
+
+  #[test]
+  pub fn test_command_generator_always_resend() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+
+    let vibrate_attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![20, 20]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::VibrateCmd, vibrate_attributes);
+    let rotate_attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![20, 20]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::RotateCmd, rotate_attributes);
+    let mut mgr = GenericCommandManager::new(&attributes_map);
+    mgr.set_always_resend(true);
+    let vibrate_msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]);
+    assert_eq!(
+      mgr.update_vibration(&vibrate_msg, false).unwrap(),
+      Some(vec![Some(10), Some(0)])
+    );
+    // Same command again still gets us every value back.
+    assert_eq!(
+      mgr.update_vibration(&vibrate_msg, false).unwrap(),
+      Some(vec![Some(10), Some(0)])
+    );
+    let rotate_msg = RotateCmd::new(0, vec![RotationSubcommand::new(1, 0.5, true)]);
+    assert_eq!(
+      mgr.update_rotation(&rotate_msg).unwrap(),
+      vec![Some((0, true)), Some((10, true))]
+    );
+    assert_eq!(
+      mgr.update_rotation(&rotate_msg).unwrap(),
+      vec![Some((0, true)), Some((10, true))]
+    );
+  }
+
   // TODO Write test for vibration stop generator
+
+  #[test]
+  pub fn test_step_conversion() {
+    assert_eq!(speed_to_step(0.5, 20), 10);
+    // Rounds up, so any speed above 0 moves the device.
+    assert_eq!(speed_to_step(0.01, 20), 1);
+    assert_eq!(speed_to_step(1.0, 20), 20);
+    assert_eq!(step_to_speed(10, 20), 0.5);
+    assert_eq!(step_to_speed(10, 0), 0.0);
+    assert_eq!(step_to_speed(speed_to_step(0.75, 20), 20), 0.75);
+  }
+
+  #[test]
+  pub fn test_command_generator_stop_commands_and_reset() {
+    let mut attributes_map = DeviceMessageAttributesMap::new();
+    let vibrate_attributes = DeviceMessageAttributes {
+      feature_count: Some(2),
+      step_count: Some(vec![20, 20]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::VibrateCmd, vibrate_attributes);
+    let rotate_attributes = DeviceMessageAttributes {
+      feature_count: Some(1),
+      step_count: Some(vec![10]),
+      ..Default::default()
+    };
+    attributes_map.insert(ButtplugDeviceMessageType::RotateCmd, rotate_attributes);
+    let mut mgr = GenericCommandManager::new(&attributes_map);
+    assert_eq!(
+      stop_commands(&attributes_map),
+      vec![
+        VibrateCmd::new(
+          0,
+          vec![
+            VibrateSubcommand::new(0, 0.0),
+            VibrateSubcommand::new(1, 0.0)
+          ]
+        )
+        .into(),
+        RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.0, false)]).into(),
+      ]
+    );
+    assert_eq!(mgr.get_stop_commands(), stop_commands(&attributes_map));
+    assert_eq!(mgr.vibration_step_counts(), [20, 20]);
+    assert_eq!(mgr.rotation_step_counts(), [10]);
+    let vibrate_msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.5)]);
+    assert_eq!(
+      mgr.update_vibration(&vibrate_msg, false).unwrap(),
+      Some(vec![None, Some(10)])
+    );
+    assert_eq!(mgr.vibration_steps(), [0, 10]);
+    assert_eq!(mgr.update_vibration(&vibrate_msg, false).unwrap(), None);
+    let rotate_msg = RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]);
+    assert_eq!(mgr.update_rotation(&rotate_msg).unwrap(), vec![Some((5, true))]);
+    assert_eq!(mgr.rotation_steps(), [(5, true)]);
+    assert_eq!(mgr.update_rotation(&rotate_msg).unwrap(), vec![None]);
+    // After a reset, the same values go out again.
+    mgr.reset();
+    assert_eq!(
+      mgr.update_vibration(&vibrate_msg, false).unwrap(),
+      Some(vec![None, Some(10)])
+    );
+    assert_eq!(mgr.update_rotation(&rotate_msg).unwrap(), vec![Some((5, true))]);
+  }
 }
@@ -0,0 +1,146 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  device::{
+    protocol::{
+      generic_command_manager::GenericCommandManager,
+      repeating_command_writer::RepeatingCommandWriter, ButtplugProtocolProperties,
+    },
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+// Hismith controllers shut the machine off if they go about a second without
+// a command, so we keep resending well inside that.
+const HISMITH_KEEPALIVE_MS: u64 = 500;
+
+// Controllers take speeds from 0-100, but the motor stalls under load below
+// around 10. Nonzero speeds are scaled into the range where the machine
+// actually moves, so the lowest speed a client asks for still does something.
+const HISMITH_MIN_SPEED: u32 = 10;
+const HISMITH_MAX_SPEED: u32 = 100;
+
+fn hismith_speed(speed: u32) -> u8 {
+  if speed == 0 {
+    return 0;
+  }
+  let speed = speed.min(HISMITH_MAX_SPEED);
+  (HISMITH_MIN_SPEED + speed * (HISMITH_MAX_SPEED - HISMITH_MIN_SPEED) / HISMITH_MAX_SPEED) as u8
+}
+
+fn hismith_command(speed: u8) -> DeviceWriteCmd {
+  // Last byte is a checksum, which is the sum of the command and speed bytes.
+  DeviceWriteCmd::new(
+    Endpoint::Tx,
+    vec![0xAA, 0x04, speed, speed.wrapping_add(0x04)],
+    false,
+  )
+}
+
+#[derive(ButtplugProtocolProperties)]
+pub struct Hismith {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  writer: Arc<RepeatingCommandWriter>,
+}
+
+impl ButtplugProtocol for Hismith {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let mut manager = GenericCommandManager::new(&message_attributes);
+    manager.set_always_resend(true);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+      writer: Arc::new(RepeatingCommandWriter::new(Duration::from_millis(
+        HISMITH_KEEPALIVE_MS,
+      ))),
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for Hismith {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let writer = self.writer.clone();
+    Box::pin(async move {
+      // Machine speed is exposed as a single vibrator.
+      if let Some(result) = manager.lock().await.update_vibration(&message, false)? {
+        let speed = hismith_speed(result[0].unwrap_or(0));
+        writer.update(device, vec![hismith_command(speed)]).await;
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use super::hismith_speed;
+  use crate::{
+    core::messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
+    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    test::{check_test_recv_value, new_bluetoothle_test_device},
+    util::{async_manager, stream::recv_now},
+  };
+  use futures_timer::Delay;
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_hismith_speed_scaling() {
+    assert_eq!(hismith_speed(0), 0);
+    assert_eq!(hismith_speed(1), 10);
+    assert_eq!(hismith_speed(50), 55);
+    assert_eq!(hismith_speed(100), 100);
+    assert_eq!(hismith_speed(150), 100);
+  }
+
+  #[test]
+  pub fn test_hismith_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("HISMITH").await.unwrap();
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      let expected = DeviceWriteCmd::new(Endpoint::Tx, vec![0xAA, 0x04, 55, 59], false);
+      // The command goes out right away, then keeps going as a keepalive.
+      Delay::new(Duration::from_millis(700)).await;
+      check_test_recv_value(&command_receiver, DeviceImplCommand::Write(expected.clone()));
+      check_test_recv_value(&command_receiver, DeviceImplCommand::Write(expected));
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      let expected = DeviceImplCommand::Write(DeviceWriteCmd::new(
+        Endpoint::Tx,
+        vec![0xAA, 0x04, 0, 4],
+        false,
+      ));
+      // Skip anything that was repeated before the stop landed.
+      Delay::new(Duration::from_millis(700)).await;
+      let mut found = false;
+      while let Some(Some(command)) = recv_now(&mut command_receiver.lock().unwrap()) {
+        if command == expected {
+          found = true;
+          break;
+        }
+      }
+      assert!(found);
+    });
+  }
+}
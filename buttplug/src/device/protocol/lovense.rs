@@ -11,7 +11,10 @@ use crate::{
     },
   },
   device::{
-    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    protocol::{
+      generic_command_manager::{speed_to_step, GenericCommandManager},
+      ButtplugProtocolProperties,
+    },
     DeviceImpl, DeviceWriteCmd, Endpoint,
   },
 };
@@ -34,6 +37,77 @@ use tokio::sync::Mutex;
 const LOVENSE_COMMAND_TIMEOUT_MS: u64 = 500;
 const LOVENSE_COMMAND_RETRY: u64 = 5;
 
+// Lovense toys can store vibration patterns and play them on their own, the
+// same way they play the patterns on their button. Patterns are lists of 0-20
+// levels stepped through at a fixed interval, and loop until the toy gets
+// another command. Stored patterns are written with SetPattern and played
+// with Preset. Preset:0 stops whatever's playing.
+const LOVENSE_PATTERN_SLOTS: u32 = 10;
+const LOVENSE_PATTERN_MAX_LEVELS: usize = 50;
+const LOVENSE_PATTERN_MIN_INTERVAL_MS: u32 = 100;
+const LOVENSE_PATTERN_STEP_COUNT: u32 = 20;
+
+fn lovense_error(message: String) -> ButtplugError {
+  ButtplugDeviceError::ProtocolSpecificError("Lovense".to_owned(), message).into()
+}
+
+fn parse_pattern_slot(slot: &str) -> Result<u32, ButtplugError> {
+  match slot.parse::<u32>() {
+    Ok(slot) if slot <= LOVENSE_PATTERN_SLOTS => Ok(slot),
+    _ => Err(lovense_error(format!(
+      "Invalid pattern slot {}, expected 0-{}.",
+      slot, LOVENSE_PATTERN_SLOTS
+    ))),
+  }
+}
+
+/// Turns a StorePattern value (`slot:interval_ms:speed,speed,...`, speeds
+/// 0.0-1.0) into the command that stores it.
+fn store_pattern_command(value: &str) -> Result<Vec<u8>, ButtplugError> {
+  let parts: Vec<&str> = value.split(':').collect();
+  if parts.len() != 3 {
+    return Err(lovense_error(format!(
+      "Invalid pattern {}, expected slot:interval:speeds.",
+      value
+    )));
+  }
+  let slot = parse_pattern_slot(parts[0])?;
+  if slot == 0 {
+    return Err(lovense_error(
+      "Patterns cannot be stored in slot 0.".to_owned(),
+    ));
+  }
+  let interval = match parts[1].parse::<u32>() {
+    Ok(interval) if interval >= LOVENSE_PATTERN_MIN_INTERVAL_MS => interval,
+    _ => {
+      return Err(lovense_error(format!(
+        "Invalid pattern interval {}, must be at least {}ms.",
+        parts[1], LOVENSE_PATTERN_MIN_INTERVAL_MS
+      )))
+    }
+  };
+  let levels = parts[2]
+    .split(',')
+    .map(|speed| match speed.parse::<f64>() {
+      Ok(speed) if (0.0..=1.0).contains(&speed) => {
+        Ok(speed_to_step(speed, LOVENSE_PATTERN_STEP_COUNT).to_string())
+      }
+      _ => Err(lovense_error(format!(
+        "Invalid pattern speed {}, expected 0.0-1.0.",
+        speed
+      ))),
+    })
+    .collect::<Result<Vec<String>, ButtplugError>>()?;
+  if levels.len() > LOVENSE_PATTERN_MAX_LEVELS {
+    return Err(lovense_error(format!(
+      "Pattern has {} speeds, at most {} can be stored.",
+      levels.len(),
+      LOVENSE_PATTERN_MAX_LEVELS
+    )));
+  }
+  Ok(format!("SetPattern:{}:{}:{};", slot, interval, levels.join(",")).into_bytes())
+}
+
 #[derive(ButtplugProtocolProperties)]
 pub struct Lovense {
   name: String,
@@ -41,6 +115,9 @@ pub struct Lovense {
   manager: Arc<Mutex<GenericCommandManager>>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
   rotation_direction: Arc<AtomicBool>,
+  // True while the toy is playing a stored pattern, which has to be stopped
+  // before anything else can drive the vibrators.
+  pattern_playing: Arc<AtomicBool>,
 }
 
 impl ButtplugProtocol for Lovense {
@@ -57,6 +134,7 @@ impl ButtplugProtocol for Lovense {
       stop_commands: manager.get_stop_commands(),
       manager: Arc::new(Mutex::new(manager)),
       rotation_direction: Arc::new(AtomicBool::new(false)),
+      pattern_playing: Arc::new(AtomicBool::new(false)),
     })
   }
 
@@ -117,7 +195,17 @@ impl ButtplugProtocolCommandHandler for Lovense {
     msg: messages::VibrateCmd,
   ) -> ButtplugDeviceResultFuture {
     let manager = self.manager.clone();
+    let pattern_playing = self.pattern_playing.clone();
     Box::pin(async move {
+      if pattern_playing.swap(false, Ordering::SeqCst) {
+        device
+          .write_value(DeviceWriteCmd::new(
+            Endpoint::Tx,
+            b"Preset:0;".to_vec(),
+            false,
+          ))
+          .await?;
+      }
       // Store off result before the match, so we drop the lock ASAP.
       let result = manager.lock().await.update_vibration(&msg, false)?;
       // Lovense is the same situation as the Lovehoney Desire, where commands
@@ -181,6 +269,43 @@ impl ButtplugProtocolCommandHandler for Lovense {
     })
   }
 
+  fn handle_keyed_device_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    msg: messages::KeyedDeviceCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let pattern_playing = self.pattern_playing.clone();
+    Box::pin(async move {
+      // Keys are checked against the device config before we get here, so
+      // all we need to look at is the value.
+      let lovense_cmd = match (msg.key(), msg.value()) {
+        ("Light", "On") => b"Light:on;".to_vec(),
+        ("Light", "Off") => b"Light:off;".to_vec(),
+        ("Light", value) => {
+          return Err(lovense_error(format!(
+            "Invalid value {} for key Light, expected On or Off.",
+            value
+          )))
+        }
+        ("StorePattern", value) => store_pattern_command(value)?,
+        ("PlayPattern", value) => {
+          let slot = parse_pattern_slot(value)?;
+          // Whatever we last sent is out of date once a pattern takes over,
+          // so make sure the next vibrate goes out even if it's the same.
+          manager.lock().await.reset();
+          pattern_playing.store(slot != 0, Ordering::SeqCst);
+          format!("Preset:{};", slot).into_bytes()
+        }
+        (key, _) => return Err(ButtplugDeviceError::KeyNotSupported(key.to_owned()).into()),
+      };
+      device
+        .write_value(DeviceWriteCmd::new(Endpoint::Tx, lovense_cmd, false))
+        .await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
+
   fn handle_battery_level_cmd(
     &self,
     device: Arc<DeviceImpl>,
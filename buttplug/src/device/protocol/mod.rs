@@ -1,8 +1,13 @@
 // Since users can pick and choose protocols, we need all of these to be public.
 pub mod aneros;
+pub mod auto_pump;
 pub mod cachito;
+pub mod dualsense;
+pub mod dualshock4;
+pub mod et312;
 pub mod fleshlight_launch_helper;
 pub mod generic_command_manager;
+pub mod hismith;
 pub mod jejoue;
 pub mod kiiroo_v2;
 pub mod kiiroo_v21;
@@ -22,15 +27,21 @@ pub mod magic_motion_v3;
 pub mod maxpro;
 pub mod motorbunny;
 pub mod mysteryvibe;
+pub mod nintendo_switch_pro;
 pub mod nobra;
+pub mod ohmibod;
 pub mod patoo;
 pub mod picobong;
 pub mod prettylove;
 pub mod raw_protocol;
 pub mod realov;
+pub mod repeating_command_writer;
+pub mod sony_controller_helper;
+pub mod steam_controller;
 pub mod svakom;
 pub mod tcode_v03;
 pub mod thehandy;
+pub mod vibease;
 pub mod vibratissimo;
 pub mod vorze_sa;
 pub mod wevibe;
@@ -49,8 +60,8 @@ use crate::{
     },
   },
   device::{
-    configuration_manager::DeviceProtocolConfiguration, ButtplugDeviceResultFuture, DeviceReadCmd,
-    Endpoint,
+    configuration_manager::{DeviceProtocolConfiguration, DeviceQuirks},
+    ButtplugDeviceResultFuture, DeviceReadCmd, Endpoint,
   },
 };
 use futures::future::{self, BoxFuture};
@@ -66,7 +77,16 @@ pub fn add_to_protocol_map<T>(map: &DashMap<String, TryCreateProtocolFunc>, prot
 pub fn get_default_protocol_map() -> DashMap<String, TryCreateProtocolFunc> {
   let map = DashMap::new();
   add_to_protocol_map::<aneros::Aneros>(&map, "aneros");
+  add_to_protocol_map::<auto_pump::AutoPump>(&map, "auto-pump");
   add_to_protocol_map::<cachito::Cachito>(&map, "cachito");
+  add_to_protocol_map::<dualsense::DualSense>(&map, "dualsense");
+  add_to_protocol_map::<dualshock4::DualShock4>(&map, "dualshock4");
+  add_to_protocol_map::<et312::Et312>(&map, "erostek-et312");
+  // Input-only HID devices. There's nothing on them to control, so clients
+  // read them with raw messages.
+  add_to_protocol_map::<raw_protocol::RawProtocol>(&map, "elgato-stream-deck");
+  add_to_protocol_map::<raw_protocol::RawProtocol>(&map, "foot-pedal");
+  add_to_protocol_map::<hismith::Hismith>(&map, "hismith");
   add_to_protocol_map::<jejoue::JeJoue>(&map, "jejoue");
   add_to_protocol_map::<kiiroo_v2::KiirooV2>(&map, "kiiroo-v2");
   add_to_protocol_map::<kiiroo_v2_vibrator::KiirooV2Vibrator>(&map, "kiiroo-v2-vibrator");
@@ -86,15 +106,21 @@ pub fn get_default_protocol_map() -> DashMap<String, TryCreateProtocolFunc> {
   add_to_protocol_map::<maxpro::Maxpro>(&map, "maxpro");
   add_to_protocol_map::<motorbunny::Motorbunny>(&map, "motorbunny");
   add_to_protocol_map::<mysteryvibe::MysteryVibe>(&map, "mysteryvibe");
+  add_to_protocol_map::<nintendo_switch_pro::NintendoSwitchPro>(&map, "nintendo-switch-pro");
   add_to_protocol_map::<nobra::Nobra>(&map, "nobra");
+  add_to_protocol_map::<ohmibod::OhMiBod>(&map, "ohmibod");
   add_to_protocol_map::<patoo::Patoo>(&map, "patoo");
   add_to_protocol_map::<picobong::Picobong>(&map, "picobong");
   add_to_protocol_map::<prettylove::PrettyLove>(&map, "prettylove");
   add_to_protocol_map::<raw_protocol::RawProtocol>(&map, "raw");
   add_to_protocol_map::<realov::Realov>(&map, "realov");
+  add_to_protocol_map::<steam_controller::SteamController>(&map, "steam-controller");
+  add_to_protocol_map::<steam_controller::SteamDeck>(&map, "steam-deck");
   add_to_protocol_map::<svakom::Svakom>(&map, "svakom");
   add_to_protocol_map::<tcode_v03::TCodeV03>(&map, "tcode-v03");
+  add_to_protocol_map::<tcode_v03::TCodeV03>(&map, "tcode-v03-twist");
   add_to_protocol_map::<thehandy::TheHandy>(&map, "thehandy");
+  add_to_protocol_map::<vibease::Vibease>(&map, "vibease");
   add_to_protocol_map::<vibratissimo::Vibratissimo>(&map, "vibratissimo");
   add_to_protocol_map::<vorze_sa::VorzeSA>(&map, "vorze-sa");
   add_to_protocol_map::<wevibe::WeVibe>(&map, "wevibe");
@@ -115,15 +141,36 @@ pub trait ButtplugProtocol: ButtplugProtocolCommandHandler + Sync {
   {
     let endpoints = device_impl.endpoints();
     let name = device_impl.name().to_owned();
-    let init_fut = Self::initialize(device_impl);
+    let init_fut = Self::initialize(device_impl.clone());
     Box::pin(async move {
       let device_identifier = match init_fut.await {
         Ok(maybe_ident) => maybe_ident.unwrap_or(name),
         Err(err) => return Err(err),
       };
-      let (names, attrs) = config.get_attributes(&device_identifier, &endpoints)?;
+      // Only bother the device for its firmware if there's something to match
+      // it against.
+      let quirks = if config.has_quirks() {
+        match Self::read_firmware(device_impl).await {
+          Ok(Some(firmware)) => {
+            let quirks = config.quirks_for_firmware(&firmware);
+            if !quirks.is_empty() {
+              info!("Device firmware {} has quirks: {:?}", firmware, quirks);
+            }
+            quirks
+          }
+          Ok(None) => DeviceQuirks::default(),
+          Err(err) => {
+            warn!("Cannot read device firmware, assuming no quirks: {}", err);
+            DeviceQuirks::default()
+          }
+        }
+      } else {
+        DeviceQuirks::default()
+      };
+      let (names, mut attrs) = config.get_attributes(&device_identifier, &endpoints)?;
+      quirks.apply_message_attributes(&mut attrs);
       let name = names.get("en-us").unwrap().clone();
-      Ok(Self::new_protocol(&name, attrs))
+      Ok(Self::new_protocol_with_quirks(&name, attrs, quirks))
     })
   }
 
@@ -136,9 +183,86 @@ pub trait ButtplugProtocol: ButtplugProtocolCommandHandler + Sync {
     Box::pin(future::ready(Ok(None)))
   }
 
+  /// Reads the firmware or model string that quirks in the device config are
+  /// matched against. Only called after initialize(), and only if the
+  /// protocol has quirks defined.
+  fn read_firmware(
+    _device_impl: Arc<DeviceImpl>,
+  ) -> BoxFuture<'static, Result<Option<String>, ButtplugError>>
+  where
+    Self: Sized,
+  {
+    Box::pin(future::ready(Ok(None)))
+  }
+
   fn new_protocol(name: &str, attrs: DeviceMessageAttributesMap) -> Box<dyn ButtplugProtocol>
   where
     Self: Sized;
+
+  /// Creates the protocol for a device with quirks matched from its firmware.
+  /// Protocols with quirks override this, everything else can ignore it.
+  fn new_protocol_with_quirks(
+    name: &str,
+    attrs: DeviceMessageAttributesMap,
+    _quirks: DeviceQuirks,
+  ) -> Box<dyn ButtplugProtocol>
+  where
+    Self: Sized,
+  {
+    Self::new_protocol(name, attrs)
+  }
+}
+
+/// Features a protocol declares in code, via
+/// `#[derive(ButtplugProtocolCapabilities)]`, for protocols whose capabilities
+/// don't change between devices. Protocols call
+/// [with_declared_capabilities](Self::with_declared_capabilities) on the
+/// attributes they're created with, so the code is the source of truth for
+/// feature and step counts, and the device config can't drift from it.
+///
+/// This matters most for protocols that build a fixed report for all their
+/// features at once (the game controllers, for instance), where a config with
+/// the wrong feature count would break every command. Protocols whose
+/// features differ by device keep getting them from the device config.
+pub trait ButtplugProtocolCapabilities {
+  /// Attributes for the messages listed in the `buttplug_capabilities`
+  /// attribute on the protocol.
+  fn declared_message_attributes() -> DeviceMessageAttributesMap;
+
+  /// Lays the declared attributes over `attrs` from the device config.
+  /// Declared feature and step counts win, anything else in the config (raw
+  /// messages, feature order, ...) is kept. Config entries that disagree with
+  /// the declaration are logged, so they can be fixed.
+  fn with_declared_capabilities(
+    name: &str,
+    mut attrs: DeviceMessageAttributesMap,
+  ) -> DeviceMessageAttributesMap {
+    for (message_type, declared) in Self::declared_message_attributes() {
+      let config = attrs.entry(message_type).or_default();
+      if config.feature_count.is_some()
+        && (config.feature_count != declared.feature_count
+          || config.step_count != declared.step_count)
+      {
+        warn!(
+          "Device config for {} has {:?} with {:?} features and {:?} steps, but the protocol declares {:?} and {:?}. Using the protocol's.",
+          name,
+          message_type,
+          config.feature_count,
+          config.step_count,
+          declared.feature_count,
+          declared.step_count
+        );
+      }
+      config.feature_count = declared.feature_count;
+      config.step_count = declared.step_count;
+    }
+    attrs
+  }
+
+  /// Commands that stop every declared feature.
+  fn declared_stop_commands() -> Vec<ButtplugDeviceCommandMessageUnion> {
+    generic_command_manager::stop_commands(&Self::declared_message_attributes())
+  }
 }
 
 fn check_message_support(
@@ -152,6 +276,21 @@ fn check_message_support(
   }
 }
 
+fn check_keyed_device_cmd_support(
+  message: &messages::KeyedDeviceCmd,
+  message_attributes: &DeviceMessageAttributesMap,
+) -> Result<(), ButtplugError> {
+  let attributes = message_attributes
+    .get(&ButtplugDeviceMessageType::KeyedDeviceCmd)
+    .ok_or(ButtplugDeviceError::MessageNotSupported(
+      ButtplugDeviceMessageType::KeyedDeviceCmd,
+    ))?;
+  match &attributes.keys {
+    Some(keys) if keys.iter().any(|key| key == message.key()) => Ok(()),
+    _ => Err(ButtplugDeviceError::KeyNotSupported(message.key().to_owned()).into()),
+  }
+}
+
 pub trait ButtplugProtocolProperties {
   fn name(&self) -> &str;
   fn message_attributes(&self) -> DeviceMessageAttributesMap;
@@ -171,6 +310,9 @@ pub trait ButtplugProtocolProperties {
         &ButtplugDeviceMessageType::FleshlightLaunchFW12Cmd,
         &self.message_attributes(),
       ),
+      ButtplugDeviceCommandMessageUnion::KeyedDeviceCmd(msg) => {
+        check_keyed_device_cmd_support(msg, &self.message_attributes())
+      }
       ButtplugDeviceCommandMessageUnion::KiirooCmd(_) => check_message_support(
         &ButtplugDeviceMessageType::KiirooCmd,
         &self.message_attributes(),
@@ -199,6 +341,18 @@ pub trait ButtplugProtocolProperties {
         &ButtplugDeviceMessageType::RotateCmd,
         &self.message_attributes(),
       ),
+      ButtplugDeviceCommandMessageUnion::RotateToAngleCmd(_) => check_message_support(
+        &ButtplugDeviceMessageType::RotateToAngleCmd,
+        &self.message_attributes(),
+      ),
+      ButtplugDeviceCommandMessageUnion::TemperatureCmd(_) => check_message_support(
+        &ButtplugDeviceMessageType::TemperatureCmd,
+        &self.message_attributes(),
+      ),
+      ButtplugDeviceCommandMessageUnion::SuctionCmd(_) => check_message_support(
+        &ButtplugDeviceMessageType::SuctionCmd,
+        &self.message_attributes(),
+      ),
       ButtplugDeviceCommandMessageUnion::RSSILevelCmd(_) => check_message_support(
         &ButtplugDeviceMessageType::RSSILevelCmd,
         &self.message_attributes(),
@@ -249,6 +403,13 @@ pub trait ButtplugProtocolCommandHandler: Send + ButtplugProtocolProperties {
       ButtplugDeviceCommandMessageUnion::RawReadCmd(msg) => self.handle_raw_read_cmd(device, msg),
       ButtplugDeviceCommandMessageUnion::RawWriteCmd(msg) => self.handle_raw_write_cmd(device, msg),
       ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => self.handle_rotate_cmd(device, msg),
+      ButtplugDeviceCommandMessageUnion::RotateToAngleCmd(msg) => {
+        self.handle_rotate_to_angle_cmd(device, msg)
+      }
+      ButtplugDeviceCommandMessageUnion::TemperatureCmd(msg) => {
+        self.handle_temperature_cmd(device, msg)
+      }
+      ButtplugDeviceCommandMessageUnion::SuctionCmd(msg) => self.handle_suction_cmd(device, msg),
       ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => {
         self.handle_single_motor_vibrate_cmd(device, msg)
       }
@@ -271,6 +432,9 @@ pub trait ButtplugProtocolCommandHandler: Send + ButtplugProtocolProperties {
       ButtplugDeviceCommandMessageUnion::RSSILevelCmd(msg) => {
         self.handle_rssi_level_cmd(device, msg)
       }
+      ButtplugDeviceCommandMessageUnion::KeyedDeviceCmd(msg) => {
+        self.handle_keyed_device_cmd(device, msg)
+      }
     }
   }
 
@@ -435,6 +599,30 @@ pub trait ButtplugProtocolCommandHandler: Send + ButtplugProtocolProperties {
     self.command_unimplemented(print_type_of(&message))
   }
 
+  fn handle_rotate_to_angle_cmd(
+    &self,
+    _device: Arc<DeviceImpl>,
+    message: messages::RotateToAngleCmd,
+  ) -> ButtplugDeviceResultFuture {
+    self.command_unimplemented(print_type_of(&message))
+  }
+
+  fn handle_temperature_cmd(
+    &self,
+    _device: Arc<DeviceImpl>,
+    message: messages::TemperatureCmd,
+  ) -> ButtplugDeviceResultFuture {
+    self.command_unimplemented(print_type_of(&message))
+  }
+
+  fn handle_suction_cmd(
+    &self,
+    _device: Arc<DeviceImpl>,
+    message: messages::SuctionCmd,
+  ) -> ButtplugDeviceResultFuture {
+    self.command_unimplemented(print_type_of(&message))
+  }
+
   fn handle_linear_cmd(
     &self,
     _device: Arc<DeviceImpl>,
@@ -474,4 +662,14 @@ pub trait ButtplugProtocolCommandHandler: Send + ButtplugProtocolProperties {
   ) -> ButtplugDeviceResultFuture {
     self.command_unimplemented(print_type_of(&message))
   }
+
+  /// Only called with keys listed in the protocol's KeyedDeviceCmd
+  /// attributes. Values are up to the protocol to check.
+  fn handle_keyed_device_cmd(
+    &self,
+    _device: Arc<DeviceImpl>,
+    message: messages::KeyedDeviceCmd,
+  ) -> ButtplugDeviceResultFuture {
+    self.command_unimplemented(print_type_of(&message))
+  }
 }
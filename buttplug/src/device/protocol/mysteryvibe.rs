@@ -5,21 +5,16 @@ use crate::{
     messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
   },
   device::{
-    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    protocol::{
+      generic_command_manager::GenericCommandManager,
+      repeating_command_writer::RepeatingCommandWriter, ButtplugProtocolProperties,
+    },
     DeviceImpl, DeviceWriteCmd, Endpoint,
   },
-  util::async_manager,
 };
 use futures::future::BoxFuture;
-use futures_timer::Delay;
-use std::{
-  sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-  },
-  time::Duration,
-};
-use tokio::sync::{Mutex, RwLock};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
 
 // Time between Mysteryvibe update commands, in milliseconds. This is basically
 // a best guess derived from watching packet timing a few years ago.
@@ -34,8 +29,9 @@ pub struct MysteryVibe {
   message_attributes: DeviceMessageAttributesMap,
   manager: Arc<Mutex<GenericCommandManager>>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
-  current_command: Arc<RwLock<Vec<u8>>>,
-  updater_running: Arc<AtomicBool>,
+  // Mysteryvibes stop if they don't keep hearing from us, so commands are
+  // repeated on an interval.
+  writer: Arc<RepeatingCommandWriter>,
 }
 
 impl ButtplugProtocol for MysteryVibe {
@@ -43,15 +39,17 @@ impl ButtplugProtocol for MysteryVibe {
     name: &str,
     message_attributes: DeviceMessageAttributesMap,
   ) -> Box<dyn ButtplugProtocol> {
-    let manager = GenericCommandManager::new(&message_attributes);
+    let mut manager = GenericCommandManager::new(&message_attributes);
+    manager.set_always_resend(true);
 
     Box::new(Self {
       name: name.to_owned(),
       message_attributes,
       stop_commands: manager.get_stop_commands(),
       manager: Arc::new(Mutex::new(manager)),
-      updater_running: Arc::new(AtomicBool::new(false)),
-      current_command: Arc::new(RwLock::new(vec![0u8, 0, 0, 0, 0, 0])),
+      writer: Arc::new(RepeatingCommandWriter::new(Duration::from_millis(
+        MYSTERYVIBE_COMMAND_DELAY_MS,
+      ))),
     })
   }
 
@@ -67,25 +65,6 @@ impl ButtplugProtocol for MysteryVibe {
   }
 }
 
-async fn vibration_update_handler(device: Arc<DeviceImpl>, command_holder: Arc<RwLock<Vec<u8>>>) {
-  info!("Entering Mysteryvibe Control Loop");
-  let mut current_command = command_holder.read().await.clone();
-  while device
-    .write_value(DeviceWriteCmd::new(
-      Endpoint::TxVibrate,
-      current_command,
-      false,
-    ))
-    .await
-    .is_ok()
-  {
-    Delay::new(Duration::from_millis(MYSTERYVIBE_COMMAND_DELAY_MS)).await;
-    current_command = command_holder.read().await.clone();
-    info!("MV Command: {:?}", current_command);
-  }
-  info!("Mysteryvibe control loop exiting, most likely due to device disconnection.");
-}
-
 impl ButtplugProtocolCommandHandler for MysteryVibe {
   fn handle_vibrate_cmd(
     &self,
@@ -93,35 +72,73 @@ impl ButtplugProtocolCommandHandler for MysteryVibe {
     message: messages::VibrateCmd,
   ) -> ButtplugDeviceResultFuture {
     let manager = self.manager.clone();
-    let current_command = self.current_command.clone();
-    let update_running = self.updater_running.clone();
+    let writer = self.writer.clone();
     Box::pin(async move {
-      let result = manager.lock().await.update_vibration(&message, true)?;
-      info!("MV Result: {:?}", result);
-      if result.is_none() {
-        return Ok(messages::Ok::default().into());
-      }
-      let write_mutex = current_command.clone();
-      let mut command_writer = write_mutex.write().await;
-      let command: Vec<u8> = result
-        .unwrap()
-        .into_iter()
-        .map(|x| x.unwrap() as u8)
-        .collect();
-      *command_writer = command;
-      if !update_running.load(Ordering::SeqCst) {
-        async_manager::spawn(
-          async move { vibration_update_handler(device, current_command).await },
-        )
-        .unwrap();
-        update_running.store(true, Ordering::SeqCst);
+      // With always_resend set, we'll get a value for every motor.
+      if let Some(result) = manager.lock().await.update_vibration(&message, true)? {
+        let command: Vec<u8> = result
+          .into_iter()
+          .map(|x| x.unwrap_or(0) as u8)
+          .collect();
+        writer
+          .update(
+            device,
+            vec![DeviceWriteCmd::new(Endpoint::TxVibrate, command, false)],
+          )
+          .await;
       }
       Ok(messages::Ok::default().into())
     })
   }
 }
 
-// TODO Write some tests!
-//
-// At least, once I figure out how to do that with the weird timing on this
-// thing.
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use crate::{
+    core::messages::{VibrateCmd, VibrateSubcommand},
+    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    test::{check_test_recv_value, new_bluetoothle_test_device},
+    util::{async_manager, stream::recv_now},
+  };
+  use futures_timer::Delay;
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_mysteryvibe_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("MV Crescendo").await.unwrap();
+      let command_receiver = test_device
+        .get_endpoint_receiver(&Endpoint::TxVibrate)
+        .unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      let expected = DeviceWriteCmd::new(Endpoint::TxVibrate, vec![28, 0, 0, 0, 0, 0], false);
+      // The command goes out right away, then keeps repeating without any
+      // more messages from us.
+      Delay::new(Duration::from_millis(150)).await;
+      check_test_recv_value(&command_receiver, DeviceImplCommand::Write(expected.clone()));
+      check_test_recv_value(&command_receiver, DeviceImplCommand::Write(expected));
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.5)]).into())
+        .await
+        .unwrap();
+      let expected = DeviceImplCommand::Write(DeviceWriteCmd::new(
+        Endpoint::TxVibrate,
+        vec![28, 28, 0, 0, 0, 0],
+        false,
+      ));
+      // Skip anything that was repeated before the update landed.
+      Delay::new(Duration::from_millis(150)).await;
+      let mut found = false;
+      while let Some(Some(command)) = recv_now(&mut command_receiver.lock().unwrap()) {
+        if command == expected {
+          found = true;
+          break;
+        }
+      }
+      assert!(found);
+    });
+  }
+}
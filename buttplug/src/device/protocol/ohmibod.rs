@@ -0,0 +1,169 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::{
+    errors::ButtplugError,
+    messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  },
+  device::{
+    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// OhMiBod Esca and Club Vibe toys have a mode byte that decides who's driving
+// them: their own mic (music mode), a remote, or an app. Intensity commands
+// are ignored unless they're in app mode, and they come out of the box in
+// music mode, so we switch modes on connect.
+const OHMIBOD_MODE_PACKET: u8 = 0x4d;
+const OHMIBOD_APP_MODE: u8 = 0x03;
+const OHMIBOD_INTENSITY_PACKET: u8 = 0x56;
+// Club Vibes also have an LED "show" that runs independently of the motor,
+// exposed as a second vibrate feature. 0 turns it off, anything else picks a
+// pattern.
+const OHMIBOD_LED_SHOW_PACKET: u8 = 0x4c;
+
+#[derive(ButtplugProtocolProperties)]
+pub struct OhMiBod {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+}
+
+impl ButtplugProtocol for OhMiBod {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+    })
+  }
+
+  fn initialize(
+    device_impl: Arc<DeviceImpl>,
+  ) -> BoxFuture<'static, Result<Option<String>, ButtplugError>> {
+    let mode_fut = device_impl.write_value(DeviceWriteCmd::new(
+      Endpoint::Tx,
+      vec![OHMIBOD_MODE_PACKET, OHMIBOD_APP_MODE],
+      true,
+    ));
+    Box::pin(async move {
+      mode_fut.await?;
+      Ok(None)
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for OhMiBod {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    Box::pin(async move {
+      // Store off result before the match, so we drop the lock ASAP.
+      let result = manager.lock().await.update_vibration(&message, false)?;
+      if let Some(cmds) = result {
+        let packets = [OHMIBOD_INTENSITY_PACKET, OHMIBOD_LED_SHOW_PACKET];
+        for (packet, cmd) in packets.iter().zip(cmds) {
+          if let Some(value) = cmd {
+            device
+              .write_value(DeviceWriteCmd::new(
+                Endpoint::Tx,
+                vec![*packet, value as u8],
+                false,
+              ))
+              .await?;
+          }
+        }
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use crate::{
+    core::messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
+    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    test::{check_test_recv_empty, check_test_recv_value, new_bluetoothle_test_device},
+    util::async_manager,
+  };
+
+  #[test]
+  pub fn test_ohmibod_esca_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("OhMiBod ESCA").await.unwrap();
+      assert_eq!(device.name(), "OhMiBod Esca 2");
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x4d, 0x03], true)),
+      );
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x56, 50], false)),
+      );
+      assert!(device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.5)]).into())
+        .await
+        .is_err());
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x56, 0], false)),
+      );
+    });
+  }
+
+  #[test]
+  pub fn test_ohmibod_club_vibe_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("OhMiBod CLUB").await.unwrap();
+      assert_eq!(device.name(), "OhMiBod Club Vibe 3");
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x4d, 0x03], true)),
+      );
+      // LED show changes don't touch the motor.
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 1.0)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x4c, 3], false)),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      // The motor was never started, so only the LED show needs stopping.
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x4c, 0], false)),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+    });
+  }
+}
@@ -1,24 +1,43 @@
 use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
 use crate::{
-  core::messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  core::{
+    errors::ButtplugError,
+    messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  },
   device::{
+    configuration_manager::DeviceQuirks,
     protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
-    DeviceImpl, DeviceWriteCmd, Endpoint,
+    DeviceImpl, DeviceReadCmd, DeviceWriteCmd, Endpoint,
   },
 };
+use futures::future::BoxFuture;
 use std::sync::Arc;
 
+// Clones (Ankni, among others) advertise Picobong names, but drop the leading
+// command byte from vibration packets. They're told apart by their firmware
+// revision string, see the quirks in the device config.
+const PICOBONG_QUIRK_NO_COMMAND_BYTE: &str = "no-command-byte";
+
 #[derive(ButtplugProtocolProperties)]
 pub struct Picobong {
   name: String,
   message_attributes: DeviceMessageAttributesMap,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  quirks: DeviceQuirks,
 }
 
 impl ButtplugProtocol for Picobong {
   fn new_protocol(
     name: &str,
     message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    Self::new_protocol_with_quirks(name, message_attributes, DeviceQuirks::default())
+  }
+
+  fn new_protocol_with_quirks(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+    quirks: DeviceQuirks,
   ) -> Box<dyn ButtplugProtocol> {
     let manager = GenericCommandManager::new(&message_attributes);
 
@@ -26,6 +45,22 @@ impl ButtplugProtocol for Picobong {
       name: name.to_owned(),
       message_attributes,
       stop_commands: manager.get_stop_commands(),
+      quirks,
+    })
+  }
+
+  fn read_firmware(
+    device_impl: Arc<DeviceImpl>,
+  ) -> BoxFuture<'static, Result<Option<String>, ButtplugError>> {
+    Box::pin(async move {
+      if !device_impl.endpoints().contains(&Endpoint::Firmware) {
+        return Ok(None);
+      }
+      let reading = device_impl
+        .read_value(DeviceReadCmd::new(Endpoint::Firmware, 32, 500))
+        .await?;
+      let firmware = String::from_utf8_lossy(reading.data());
+      Ok(Some(firmware.trim_end_matches(char::from(0)).trim().to_owned()))
     })
   }
 }
@@ -39,7 +74,12 @@ impl ButtplugProtocolCommandHandler for Picobong {
     // TODO Convert to using generic command manager
     let speed = (msg.speeds()[0].speed() * 10.0) as u8;
     let mode: u8 = if speed == 0 { 0xff } else { 0x01 };
-    let msg = DeviceWriteCmd::new(Endpoint::Tx, [0x01, mode, speed].to_vec(), false);
+    let data = if self.quirks.contains(PICOBONG_QUIRK_NO_COMMAND_BYTE) {
+      vec![mode, speed]
+    } else {
+      vec![0x01, mode, speed]
+    };
+    let msg = DeviceWriteCmd::new(Endpoint::Tx, data, false);
     let fut = device.write_value(msg);
     Box::pin(async {
       fut.await?;
@@ -48,4 +88,53 @@ impl ButtplugProtocolCommandHandler for Picobong {
   }
 }
 
-// TODO Write tests for protocol
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use crate::{
+    core::messages::{VibrateCmd, VibrateSubcommand},
+    device::{
+      configuration_manager::DeviceConfigurationManager, ButtplugDevice, DeviceImplCommand,
+      DeviceWriteCmd, Endpoint,
+    },
+    test::new_uninitialized_ble_test_device,
+    util::{async_manager, stream::recv_now},
+  };
+  use std::sync::Arc;
+
+  fn picobong_vibrate_writes(firmware: &[u8]) -> DeviceImplCommand {
+    async_manager::block_on(async move {
+      let (test_device, creator) = new_uninitialized_ble_test_device("Picobong Egg", None);
+      test_device.add_read_response(Endpoint::Firmware, firmware.to_vec());
+      let device = ButtplugDevice::try_create_device(
+        Arc::new(DeviceConfigurationManager::default()),
+        Box::new(creator),
+      )
+      .await
+      .unwrap()
+      .unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      let command = recv_now(&mut command_receiver.lock().unwrap());
+      command.unwrap().unwrap()
+    })
+  }
+
+  #[test]
+  pub fn test_picobong_protocol() {
+    assert_eq!(
+      picobong_vibrate_writes(b"V2.1.0\0\0"),
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x01, 0x01, 5], false))
+    );
+  }
+
+  #[test]
+  pub fn test_picobong_clone_quirk() {
+    assert_eq!(
+      picobong_vibrate_writes(b"ANKNI-1.3"),
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0x01, 5], false))
+    );
+  }
+}
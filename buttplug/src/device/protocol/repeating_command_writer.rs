@@ -0,0 +1,90 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Resends device commands on an interval.
+//!
+//! Some devices stop whatever they're doing if they don't keep receiving
+//! commands, so the usual "only send what changed" behavior of the
+//! [GenericCommandManager][super::generic_command_manager::GenericCommandManager]
+//! will stop them. Protocols for those devices should turn on
+//! [always_resend][super::generic_command_manager::GenericCommandManager::set_always_resend]
+//! on their command manager, and hand the resulting writes to a
+//! [RepeatingCommandWriter] instead of writing them directly.
+
+use crate::{
+  device::{DeviceImpl, DeviceWriteCmd},
+  util::async_manager,
+};
+use futures_timer::Delay;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+  },
+  time::Duration,
+};
+use tokio::sync::RwLock;
+
+/// Keeps writing the most recent set of commands to a device until the device
+/// disconnects or the writer is dropped.
+pub struct RepeatingCommandWriter {
+  interval: Duration,
+  commands: Arc<RwLock<Vec<DeviceWriteCmd>>>,
+  running: Arc<AtomicBool>,
+}
+
+impl RepeatingCommandWriter {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      commands: Arc::new(RwLock::new(vec![])),
+      running: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Replaces the commands being repeated. The first call starts the repeat
+  /// loop, which writes immediately. After that, new commands go out on the
+  /// next tick of the interval.
+  pub async fn update(&self, device: Arc<DeviceImpl>, commands: Vec<DeviceWriteCmd>) {
+    *self.commands.write().await = commands;
+    if !self.running.swap(true, Ordering::SeqCst) {
+      let commands = Arc::downgrade(&self.commands);
+      let running = self.running.clone();
+      let interval = self.interval;
+      async_manager::spawn(async move {
+        repeat_commands(device, commands, interval).await;
+        running.store(false, Ordering::SeqCst);
+      })
+      .unwrap();
+    }
+  }
+}
+
+async fn repeat_commands(
+  device: Arc<DeviceImpl>,
+  commands: Weak<RwLock<Vec<DeviceWriteCmd>>>,
+  interval: Duration,
+) {
+  debug!("Starting command repeat loop for {}", device.name());
+  // Stop once the writer (and therefore the protocol) goes away.
+  while let Some(commands) = commands.upgrade() {
+    let current_commands = commands.read().await.clone();
+    // Don't hold on to the writer while we're waiting.
+    drop(commands);
+    for command in current_commands {
+      if let Err(err) = device.write_value(command).await {
+        info!(
+          "Command repeat loop exiting, most likely due to device disconnection: {}",
+          err
+        );
+        return;
+      }
+    }
+    Delay::new(interval).await;
+  }
+  debug!("Command repeat loop exiting, writer dropped.");
+}
@@ -0,0 +1,84 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Shared bits for the Sony (DualShock 4/DualSense) controller protocols.
+
+use crate::core::errors::{ButtplugDeviceError, ButtplugError};
+
+/// KeyedDeviceCmd key for the lightbar. Values are a brightness from 0 (off)
+/// to 255, applied to all three colors.
+pub const LIGHTBAR_KEY: &str = "Lightbar";
+
+/// Everything an output report sets. Reports always carry every output, so
+/// the protocols keep the last values around to fill in whatever a command
+/// isn't changing. The lightbar is left alone until it's been set.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SonyControllerOutputs {
+  pub large_motor: u8,
+  pub small_motor: u8,
+  pub lightbar: Option<u8>,
+}
+
+pub fn parse_lightbar_value(value: &str) -> Result<u8, ButtplugError> {
+  value.parse::<u8>().map_err(|_| {
+    ButtplugDeviceError::ProtocolSpecificError(
+      "Sony Controller".to_owned(),
+      format!(
+        "Invalid value {} for key {}, expected 0-255.",
+        value, LIGHTBAR_KEY
+      ),
+    )
+    .into()
+  })
+}
+
+// Bluetooth output reports are checksummed, with the checksum seeded by this
+// byte (the HID "output report" transaction header).
+const BLUETOOTH_OUTPUT_REPORT_SEED: u8 = 0xa2;
+
+fn crc32(seed: u32, data: &[u8]) -> u32 {
+  let mut crc = seed;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ 0xedb8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  crc
+}
+
+/// Fills the last 4 bytes of a Bluetooth output report with the CRC32 of the
+/// rest of the report.
+pub fn set_bluetooth_report_crc(report: &mut [u8]) {
+  let crc_offset = report.len() - 4;
+  let crc = !crc32(
+    crc32(0xffff_ffff, &[BLUETOOTH_OUTPUT_REPORT_SEED]),
+    &report[..crc_offset],
+  );
+  report[crc_offset..].copy_from_slice(&crc.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(!crc32(0xffff_ffff, b"123456789"), 0xcbf4_3926);
+  }
+
+  #[test]
+  fn test_parse_lightbar_value() {
+    assert_eq!(parse_lightbar_value("255").unwrap(), 255);
+    assert!(parse_lightbar_value("256").is_err());
+    assert!(parse_lightbar_value("On").is_err());
+  }
+}
@@ -0,0 +1,276 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::messages::{
+    self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributes,
+    DeviceMessageAttributesMap,
+  },
+  device::{
+    protocol::{
+      generic_command_manager::{step_to_speed, GenericCommandManager},
+      ButtplugProtocolCapabilities, ButtplugProtocolProperties,
+    },
+    DeviceImpl, DeviceWriteCmd, Endpoint,
+  },
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Valve controllers take commands as 64 byte feature reports, with report id
+// 0, which the HID comm manager sends for writes to the Command endpoint.
+const REPORT_SIZE: usize = 65;
+const STEAM_CONTROLLER_HAPTIC_PULSE: u8 = 0x8f;
+const STEAM_DECK_RUMBLE: u8 = 0xeb;
+
+// The Steam Controller's actuators only take pulse trains, so intensity is
+// the fraction of each period the actuator is on for. Pulses repeat for about
+// two minutes, or until the next command replaces them.
+const HAPTIC_PULSE_PERIOD_US: u32 = 2000;
+const HAPTIC_PULSE_REPEAT: u16 = 0xffff;
+const STEAM_CONTROLLER_RIGHT_PAD: u8 = 0;
+const STEAM_CONTROLLER_LEFT_PAD: u8 = 1;
+
+fn feature_report(command: u8, data: &[u8]) -> Vec<u8> {
+  let mut report = vec![0u8; REPORT_SIZE];
+  report[1] = command;
+  report[2] = data.len() as u8;
+  report[3..3 + data.len()].copy_from_slice(data);
+  report
+}
+
+/// Turns a step into a fraction of its step count, for protocols that need
+/// something other than the raw step.
+fn step_fraction(step: Option<u32>, step_count: Option<&u32>) -> f64 {
+  match (step, step_count) {
+    (Some(step), Some(step_count)) => step_to_speed(step, *step_count),
+    _ => 0.0,
+  }
+}
+
+fn step_counts(message_attributes: &DeviceMessageAttributesMap) -> Vec<u32> {
+  message_attributes
+    .get(&ButtplugDeviceMessageType::VibrateCmd)
+    .and_then(|attributes| attributes.step_count.clone())
+    .unwrap_or_default()
+}
+
+fn haptic_pulse_report(pad: u8, intensity: f64) -> Vec<u8> {
+  let on_us = (intensity.clamp(0.0, 1.0) * HAPTIC_PULSE_PERIOD_US as f64) as u16;
+  let off_us = HAPTIC_PULSE_PERIOD_US as u16 - on_us;
+  let repeat = if on_us == 0 { 0 } else { HAPTIC_PULSE_REPEAT };
+  let mut data = vec![pad];
+  data.extend_from_slice(&on_us.to_le_bytes());
+  data.extend_from_slice(&off_us.to_le_bytes());
+  data.extend_from_slice(&repeat.to_le_bytes());
+  feature_report(STEAM_CONTROLLER_HAPTIC_PULSE, &data)
+}
+
+fn deck_rumble_report(left: f64, right: f64) -> Vec<u8> {
+  let speed = |intensity: f64| ((intensity.clamp(0.0, 1.0) * u16::MAX as f64) as u16).to_le_bytes();
+  // Rumble type and intensity are always 0, the gains are what Steam uses.
+  let mut data = vec![0u8, 0, 0];
+  data.extend_from_slice(&speed(left));
+  data.extend_from_slice(&speed(right));
+  data.extend_from_slice(&[2, 0]);
+  feature_report(STEAM_DECK_RUMBLE, &data)
+}
+
+/// Steam Controller trackpad haptics, wired or through the wireless dongle.
+/// Feature 0 is the left pad, feature 1 the right.
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(100, 100)))]
+pub struct SteamController {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+}
+
+impl ButtplugProtocol for SteamController {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for SteamController {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let step_counts = step_counts(&self.message_attributes);
+    Box::pin(async move {
+      let result = manager.lock().await.update_vibration(&message, false)?;
+      if let Some(cmds) = result {
+        for (index, (cmd, &pad)) in cmds
+          .iter()
+          .zip(&[STEAM_CONTROLLER_LEFT_PAD, STEAM_CONTROLLER_RIGHT_PAD])
+          .enumerate()
+        {
+          if cmd.is_some() {
+            let report = haptic_pulse_report(pad, step_fraction(*cmd, step_counts.get(index)));
+            device
+              .write_value(DeviceWriteCmd::new(Endpoint::Command, report, false))
+              .await?;
+          }
+        }
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+/// Steam Deck rumble motors. Feature 0 is the left motor, feature 1 the
+/// right.
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(100, 100)))]
+pub struct SteamDeck {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+}
+
+impl ButtplugProtocol for SteamDeck {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for SteamDeck {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    let step_counts = step_counts(&self.message_attributes);
+    Box::pin(async move {
+      // Every report sets both motors, so always ask for all values.
+      let result = manager.lock().await.update_vibration(&message, true)?;
+      if let Some(cmds) = result {
+        let intensity = |index: usize| {
+          step_fraction(cmds.get(index).copied().flatten(), step_counts.get(index))
+        };
+        let report = deck_rumble_report(intensity(0), intensity(1));
+        device
+          .write_value(DeviceWriteCmd::new(Endpoint::Command, report, false))
+          .await?;
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    core::messages::{VibrateCmd, VibrateSubcommand},
+    device::DeviceImplCommand,
+    test::{check_test_recv_empty, check_test_recv_value, new_hid_test_device},
+    util::async_manager,
+  };
+
+  fn write(data: Vec<u8>) -> DeviceImplCommand {
+    DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Command, data, false))
+  }
+
+  #[test]
+  fn test_steam_controller_haptic_report() {
+    let report = haptic_pulse_report(STEAM_CONTROLLER_LEFT_PAD, 0.25);
+    assert_eq!(report.len(), REPORT_SIZE);
+    assert_eq!(
+      report[..10],
+      [0x00, 0x8f, 0x07, 0x01, 0xf4, 0x01, 0xdc, 0x05, 0xff, 0xff]
+    );
+    // Stopping sends no pulses at all.
+    let report = haptic_pulse_report(STEAM_CONTROLLER_RIGHT_PAD, 0.0);
+    assert_eq!(
+      report[..10],
+      [0x00, 0x8f, 0x07, 0x00, 0x00, 0x00, 0xd0, 0x07, 0x00, 0x00]
+    );
+  }
+
+  #[test]
+  fn test_steam_deck_rumble_report() {
+    let report = deck_rumble_report(1.0, 0.0);
+    assert_eq!(report.len(), REPORT_SIZE);
+    assert_eq!(
+      report[..12],
+      [0x00, 0xeb, 0x09, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x02, 0x00]
+    );
+  }
+
+  #[test]
+  fn test_step_fraction() {
+    assert_eq!(step_fraction(Some(50), Some(&100)), 0.5);
+    assert_eq!(step_fraction(None, Some(&100)), 0.0);
+    assert_eq!(step_fraction(Some(50), None), 0.0);
+    assert_eq!(step_fraction(Some(50), Some(&0)), 0.0);
+  }
+
+  #[test]
+  fn test_steam_controller_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) =
+        new_hid_test_device("Steam Controller", 0x28de, 0x1102, "/dev/hidraw0")
+          .await
+          .unwrap();
+      let command_receiver = test_device
+        .get_endpoint_receiver(&Endpoint::Command)
+        .unwrap();
+      let output_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(1, 0.25)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        write(haptic_pulse_report(STEAM_CONTROLLER_RIGHT_PAD, 0.25)),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+      assert!(check_test_recv_empty(&output_receiver));
+    });
+  }
+
+  #[test]
+  fn test_steam_deck_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_hid_test_device("Steam Deck", 0x28de, 0x1205, "/dev/hidraw0")
+        .await
+        .unwrap();
+      let command_receiver = test_device
+        .get_endpoint_receiver(&Endpoint::Command)
+        .unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.0)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(&command_receiver, write(deck_rumble_report(1.0, 0.0)));
+      assert!(check_test_recv_empty(&command_receiver));
+    });
+  }
+}
@@ -2,10 +2,14 @@ use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolComman
 use crate::{
   core::messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
   device::{
-    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    protocol::{
+      generic_command_manager::{temperature_steps, GenericCommandManager},
+      ButtplugProtocolProperties,
+    },
     DeviceImpl, DeviceWriteCmd, Endpoint,
   },
 };
+use futures::future;
 use std::sync::Arc;
 
 #[derive(ButtplugProtocolProperties)]
@@ -50,6 +54,93 @@ impl ButtplugProtocolCommandHandler for Svakom {
       Ok(messages::Ok::default().into())
     })
   }
+
+  fn handle_temperature_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    msg: messages::TemperatureCmd,
+  ) -> ButtplugDeviceResultFuture {
+    // Svakom devices only have a single heater.
+    let step = match temperature_steps(&msg, &self.message_attributes) {
+      Ok(steps) => steps[0].1 as u8,
+      Err(err) => return Box::pin(future::ready(Err(err))),
+    };
+    let enabled: u8 = if step == 0x00 { 0x00 } else { 0x01 };
+    let fut = device.write_value(DeviceWriteCmd::new(
+      Endpoint::Tx,
+      [0x55, 0x05, 0x00, 0x00, enabled, step].to_vec(),
+      false,
+    ));
+    Box::pin(async {
+      fut.await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
 }
 
-// TODO Write Tests
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use crate::{
+    core::messages::{
+      StopDeviceCmd, TemperatureCmd, TemperatureSubcommand, VibrateCmd, VibrateSubcommand,
+    },
+    device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+    test::{check_test_recv_empty, check_test_recv_value, new_bluetoothle_test_device},
+    util::async_manager,
+  };
+
+  #[test]
+  pub fn test_svakom_heating_protocol() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Emma NEO").await.unwrap();
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0x55, 0x04, 0x03, 0x00, 0x01, 0x09],
+          false,
+        )),
+      );
+      device
+        .parse_message(TemperatureCmd::new(0, vec![TemperatureSubcommand::new(0, 0.7)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0x55, 0x05, 0x00, 0x00, 0x01, 0x02],
+          false,
+        )),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+      // Stopping the device turns the heater off too.
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0x55, 0x04, 0x03, 0x00, 0x00, 0x00],
+          false,
+        )),
+      );
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(
+          Endpoint::Tx,
+          vec![0x55, 0x05, 0x00, 0x00, 0x00, 0x00],
+          false,
+        )),
+      );
+      assert!(check_test_recv_empty(&command_receiver));
+    });
+  }
+}
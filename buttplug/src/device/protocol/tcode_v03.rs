@@ -1,6 +1,12 @@
 use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
 use crate::{
-  core::messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  core::{
+    errors::ButtplugDeviceError,
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType,
+      DeviceMessageAttributesMap,
+    },
+  },
   device::{
     protocol::ButtplugProtocolProperties,
     DeviceImpl, DeviceWriteCmd, Endpoint,
@@ -8,6 +14,17 @@ use crate::{
 };
 use std::sync::Arc;
 
+/// Rotation axis command, i.e. `R09999I500` to turn the twist axis (R0) to the
+/// end of its range over half a second. Axes take 4 digits, as that's what twist
+/// servos can actually resolve.
+fn rotation_axis_command(axis: u32, angle: f64, duration: u32) -> String {
+  let position = (angle.clamp(0.0, 1.0) * 9999f64) as u32;
+  format!("R{}{:04}I{}\n", axis, position, duration)
+}
+
+/// T-Code v0.3 serial devices. Linear commands go to the stroke axis (L0), and
+/// angle commands to the rotation axes (R0 for twist, then R1 roll and R2
+/// pitch), on devices configured with them.
 #[derive(ButtplugProtocolProperties)]
 pub struct TCodeV03 {
   name: String,
@@ -53,4 +70,48 @@ impl ButtplugProtocolCommandHandler for TCodeV03 {
       Ok(messages::Ok::default().into())
     })
   }
-}
\ No newline at end of file
+
+  fn handle_rotate_to_angle_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    msg: messages::RotateToAngleCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let axis_count = self
+      .message_attributes
+      .get(&ButtplugDeviceMessageType::RotateToAngleCmd)
+      .and_then(|attributes| attributes.feature_count)
+      .unwrap_or(0);
+    let mut command = String::new();
+    for angle in msg.angles() {
+      let index = angle.index();
+      if index >= axis_count {
+        return Box::pin(async move {
+          Err(ButtplugDeviceError::DeviceFeatureIndexError(axis_count, index).into())
+        });
+      }
+      command += &rotation_axis_command(index, angle.angle(), angle.duration());
+    }
+    let fut = device.write_value(DeviceWriteCmd::new(
+      Endpoint::Tx,
+      command.into_bytes(),
+      false,
+    ));
+
+    Box::pin(async move {
+      fut.await?;
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_tcode_rotation_axis_command() {
+    assert_eq!(rotation_axis_command(0, 0.5, 500), "R04999I500\n");
+    assert_eq!(rotation_axis_command(1, 1.0, 0), "R19999I0\n");
+    assert_eq!(rotation_axis_command(0, 0.0, 100), "R00000I100\n");
+  }
+}
@@ -0,0 +1,307 @@
+use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolCommandHandler};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+  },
+  device::{
+    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    ButtplugDeviceEvent, DeviceImpl, DeviceSubscribeCmd, DeviceWriteCmd, Endpoint,
+  },
+};
+use futures::{future::BoxFuture, FutureExt};
+use futures_timer::Delay;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+// Vibease toys talk ASCII over a UART style service, with every command and
+// response framed as "*<body>#". They're chatty: commands get acked with
+// "*OK#", battery updates show up whenever the toy feels like it, and
+// responses can be split across (or bundled into) notifications. Nothing
+// works until the pairing handshake is done:
+//
+// - We send "*HS#", the toy answers with "*HS:<challenge>#".
+// - We echo the challenge back as "*PAIR:<challenge>#", the toy answers with
+//   "*PAIRED#".
+const VIBEASE_HANDSHAKE_TIMEOUT_MS: u64 = 500;
+const VIBEASE_HANDSHAKE_RETRY: u64 = 5;
+
+fn vibease_command(body: &str) -> Vec<u8> {
+  format!("*{}#", body).into_bytes()
+}
+
+fn vibease_error(message: &str) -> ButtplugError {
+  ButtplugDeviceError::ProtocolSpecificError("Vibease".to_owned(), message.to_owned()).into()
+}
+
+/// Reassembles framed responses out of notifications, which may split or
+/// bundle them.
+#[derive(Default)]
+struct VibeaseResponseBuffer {
+  buffer: String,
+}
+
+impl VibeaseResponseBuffer {
+  fn push(&mut self, data: &[u8]) -> Vec<String> {
+    self.buffer.push_str(&String::from_utf8_lossy(data));
+    let mut responses = vec![];
+    while let Some(end) = self.buffer.find('#') {
+      let frame: String = self.buffer.drain(..=end).collect();
+      // Anything before the start marker is line noise from a dropped packet.
+      match frame.rfind('*') {
+        Some(start) => responses.push(frame[start + 1..frame.len() - 1].to_owned()),
+        None => warn!("Vibease response missing start marker: {}", frame),
+      }
+    }
+    responses
+  }
+}
+
+/// Waits for a response accepted by `matcher`, skipping unrelated chatter.
+async fn wait_for_response<T>(
+  receiver: &mut broadcast::Receiver<ButtplugDeviceEvent>,
+  buffer: &mut VibeaseResponseBuffer,
+  matcher: impl Fn(&str) -> Option<T>,
+) -> Result<T, ButtplugError> {
+  loop {
+    match receiver.recv().await {
+      Ok(ButtplugDeviceEvent::Notification(_, Endpoint::Rx, data)) => {
+        for response in buffer.push(&data) {
+          match matcher(&response) {
+            Some(result) => return Ok(result),
+            None => debug!("Ignoring Vibease response during handshake: {}", response),
+          }
+        }
+      }
+      Ok(ButtplugDeviceEvent::Removed(_)) | Err(broadcast::error::RecvError::Closed) => {
+        return Err(vibease_error(
+          "Vibease device disconnected during pairing handshake.",
+        ))
+      }
+      Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+    }
+  }
+}
+
+async fn pairing_handshake(device_impl: Arc<DeviceImpl>) -> Result<(), ButtplugError> {
+  let mut receiver = device_impl.event_stream();
+  let mut buffer = VibeaseResponseBuffer::default();
+  device_impl
+    .subscribe(DeviceSubscribeCmd::new(Endpoint::Rx))
+    .await?;
+  let mut count = 0;
+  let challenge = loop {
+    device_impl
+      .write_value(DeviceWriteCmd::new(
+        Endpoint::Tx,
+        vibease_command("HS"),
+        false,
+      ))
+      .await?;
+    select! {
+      challenge = wait_for_response(&mut receiver, &mut buffer, |response| {
+        response.strip_prefix("HS:").map(|challenge| challenge.to_owned())
+      }).fuse() => break challenge?,
+      _ = Delay::new(Duration::from_millis(VIBEASE_HANDSHAKE_TIMEOUT_MS)).fuse() => {
+        count += 1;
+        if count > VIBEASE_HANDSHAKE_RETRY {
+          return Err(vibease_error(&format!(
+            "Vibease device timed out waiting for handshake. ({} retries)",
+            VIBEASE_HANDSHAKE_RETRY
+          )));
+        }
+      }
+    }
+  };
+  debug!("Vibease handshake challenge: {}", challenge);
+  device_impl
+    .write_value(DeviceWriteCmd::new(
+      Endpoint::Tx,
+      vibease_command(&format!("PAIR:{}", challenge)),
+      false,
+    ))
+    .await?;
+  select! {
+    paired = wait_for_response(&mut receiver, &mut buffer, |response| match response {
+      "PAIRED" => Some(Ok(())),
+      "FAIL" => Some(Err(vibease_error("Vibease device rejected pairing."))),
+      _ => None,
+    }).fuse() => paired?,
+    _ = Delay::new(Duration::from_millis(VIBEASE_HANDSHAKE_TIMEOUT_MS)).fuse() => {
+      Err(vibease_error("Vibease device timed out waiting for pairing."))
+    }
+  }
+}
+
+#[derive(ButtplugProtocolProperties)]
+pub struct Vibease {
+  name: String,
+  message_attributes: DeviceMessageAttributesMap,
+  manager: Arc<Mutex<GenericCommandManager>>,
+  stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+}
+
+impl ButtplugProtocol for Vibease {
+  fn new_protocol(
+    name: &str,
+    message_attributes: DeviceMessageAttributesMap,
+  ) -> Box<dyn ButtplugProtocol> {
+    let manager = GenericCommandManager::new(&message_attributes);
+
+    Box::new(Self {
+      name: name.to_owned(),
+      message_attributes,
+      stop_commands: manager.get_stop_commands(),
+      manager: Arc::new(Mutex::new(manager)),
+    })
+  }
+
+  fn initialize(
+    device_impl: Arc<DeviceImpl>,
+  ) -> BoxFuture<'static, Result<Option<String>, ButtplugError>> {
+    Box::pin(async move {
+      pairing_handshake(device_impl).await?;
+      Ok(None)
+    })
+  }
+}
+
+impl ButtplugProtocolCommandHandler for Vibease {
+  fn handle_vibrate_cmd(
+    &self,
+    device: Arc<DeviceImpl>,
+    message: messages::VibrateCmd,
+  ) -> ButtplugDeviceResultFuture {
+    let manager = self.manager.clone();
+    Box::pin(async move {
+      // Store off result before the match, so we drop the lock ASAP.
+      let result = manager.lock().await.update_vibration(&message, false)?;
+      if let Some(cmds) = result {
+        if let Some(speed) = cmds[0] {
+          device
+            .write_value(DeviceWriteCmd::new(
+              Endpoint::Tx,
+              vibease_command(&format!("V:{}", speed)),
+              false,
+            ))
+            .await?;
+        }
+      }
+      Ok(messages::Ok::default().into())
+    })
+  }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+  use super::VibeaseResponseBuffer;
+  use crate::{
+    core::messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
+    device::{
+      configuration_manager::DeviceConfigurationManager, ButtplugDevice, ButtplugDeviceEvent,
+      DeviceImplCommand, DeviceWriteCmd, Endpoint,
+    },
+    test::{
+      check_test_recv_empty, check_test_recv_value, new_uninitialized_ble_test_device,
+      TestDeviceInternal,
+    },
+    util::{async_manager, stream::recv_now},
+  };
+  use futures_timer::Delay;
+  use std::{sync::Arc, time::Duration};
+
+  async fn next_write(test_device: &TestDeviceInternal) -> Vec<u8> {
+    loop {
+      if let Some(receiver) = test_device.get_endpoint_receiver(&Endpoint::Tx) {
+        if let Some(Some(DeviceImplCommand::Write(cmd))) = recv_now(&mut receiver.lock().unwrap())
+        {
+          return cmd.data;
+        }
+      }
+      Delay::new(Duration::from_millis(10)).await;
+    }
+  }
+
+  fn notify(test_device: &TestDeviceInternal, data: &[u8]) {
+    test_device
+      .send_event(ButtplugDeviceEvent::Notification(
+        test_device.address(),
+        Endpoint::Rx,
+        data.to_vec(),
+      ));
+  }
+
+  #[test]
+  pub fn test_vibease_response_buffer() {
+    let mut buffer = VibeaseResponseBuffer::default();
+    assert!(buffer.push(b"*HS:").is_empty());
+    assert_eq!(buffer.push(b"1a2b#*OK#*BA"), vec!["HS:1a2b", "OK"]);
+    assert_eq!(buffer.push(b"T:80#garbage#"), vec!["BAT:80"]);
+    assert_eq!(buffer.push(b"xx*OK#"), vec!["OK"]);
+  }
+
+  #[test]
+  pub fn test_vibease_protocol() {
+    async_manager::block_on(async move {
+      let (test_device, creator) = new_uninitialized_ble_test_device("VIBEASE", None);
+      let responder_device = test_device.clone();
+      async_manager::spawn(async move {
+        assert_eq!(next_write(&responder_device).await, b"*HS#".to_vec());
+        // Toys send battery updates whenever, including mid-handshake, and
+        // split responses across notifications.
+        notify(&responder_device, b"*BAT:80#*HS:");
+        notify(&responder_device, b"1a2b#");
+        assert_eq!(next_write(&responder_device).await, b"*PAIR:1a2b#".to_vec());
+        notify(&responder_device, b"*PAIRED#");
+      })
+      .unwrap();
+      let device = ButtplugDevice::try_create_device(
+        Arc::new(DeviceConfigurationManager::default()),
+        Box::new(creator),
+      )
+      .await
+      .unwrap()
+      .unwrap();
+      assert_eq!(device.name(), "Vibease Vibrator");
+      let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      assert!(check_test_recv_empty(&command_receiver));
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, b"*V:10#".to_vec(), false)),
+      );
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, b"*V:0#".to_vec(), false)),
+      );
+    });
+  }
+
+  #[test]
+  pub fn test_vibease_rejected_pairing() {
+    async_manager::block_on(async move {
+      let (test_device, creator) = new_uninitialized_ble_test_device("VIBEASE", None);
+      let responder_device = test_device.clone();
+      async_manager::spawn(async move {
+        next_write(&responder_device).await;
+        notify(&responder_device, b"*HS:1a2b#");
+        next_write(&responder_device).await;
+        notify(&responder_device, b"*FAIL#");
+      })
+      .unwrap();
+      assert!(ButtplugDevice::try_create_device(
+        Arc::new(DeviceConfigurationManager::default()),
+        Box::new(creator),
+      )
+      .await
+      .is_err());
+    });
+  }
+}
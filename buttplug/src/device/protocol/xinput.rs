@@ -2,10 +2,16 @@ use super::{ButtplugDeviceResultFuture, ButtplugProtocol, ButtplugProtocolComman
 use crate::{
   core::{
     errors::{ButtplugError, ButtplugMessageError},
-    messages::{self, ButtplugDeviceCommandMessageUnion, DeviceMessageAttributesMap},
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessageType, DeviceMessageAttributes,
+      DeviceMessageAttributesMap,
+    },
   },
   device::{
-    protocol::{generic_command_manager::GenericCommandManager, ButtplugProtocolProperties},
+    protocol::{
+      generic_command_manager::GenericCommandManager, ButtplugProtocolCapabilities,
+      ButtplugProtocolProperties,
+    },
     DeviceImpl, DeviceWriteCmd, Endpoint,
   },
 };
@@ -14,7 +20,8 @@ use futures::future::{self, BoxFuture};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-#[derive(ButtplugProtocolProperties)]
+#[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+#[buttplug_capabilities(VibrateCmd(steps(65535, 65535)))]
 pub struct XInput {
   name: String,
   message_attributes: DeviceMessageAttributesMap,
@@ -27,6 +34,7 @@ impl ButtplugProtocol for XInput {
     name: &str,
     message_attributes: DeviceMessageAttributesMap,
   ) -> Box<dyn ButtplugProtocol> {
+    let message_attributes = Self::with_declared_capabilities(name, message_attributes);
     let manager = GenericCommandManager::new(&message_attributes);
 
     Box::new(Self {
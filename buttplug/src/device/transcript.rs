@@ -0,0 +1,233 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Recordings of everything that goes between a protocol and its hardware.
+//!
+//! A [DeviceTranscript] holds the Buttplug messages sent to a device, along
+//! with the writes, reads, subscriptions and notifications they caused, in
+//! the order they happened. Transcripts are recorded from real hardware using
+//! [DeviceImpl::start_transcript][super::DeviceImpl::start_transcript], saved
+//! as JSON fixtures, then replayed against protocol implementations in tests
+//! (see [crate::test::replay_device_transcript]), so that changes to protocol
+//! code that alter packet formats show up as test failures.
+
+use super::{ButtplugDeviceEvent, Endpoint};
+use crate::{core::messages::ButtplugDeviceCommandMessageUnion, util::async_manager};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+use std::{
+  sync::{Arc, Mutex, Weak},
+  time::Instant,
+};
+use tokio::sync::broadcast;
+
+/// Something that happened during a transcript.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum DeviceTranscriptEvent {
+  /// A message sent to the device's protocol.
+  Message(ButtplugDeviceCommandMessageUnion),
+  Write {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    write_with_response: bool,
+  },
+  /// A read, along with the data the hardware returned.
+  Read {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  Subscribe {
+    endpoint: Endpoint,
+  },
+  Unsubscribe {
+    endpoint: Endpoint,
+  },
+  /// Data sent by the hardware on a subscribed endpoint.
+  Notification {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceTranscriptEntry {
+  /// Milliseconds since the recording started.
+  pub time: u64,
+  pub event: DeviceTranscriptEvent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceTranscript {
+  /// Name the device advertised, used to pick its protocol on replay.
+  pub name: String,
+  pub entries: Vec<DeviceTranscriptEntry>,
+}
+
+impl DeviceTranscript {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      entries: vec![],
+    }
+  }
+
+  #[cfg(feature = "serialize-json")]
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+
+  #[cfg(feature = "serialize-json")]
+  pub fn to_json(&self) -> String {
+    // Everything in here is plain data, so this can't fail.
+    serde_json::to_string_pretty(self).unwrap()
+  }
+}
+
+/// Collects transcript entries as they happen. Created by
+/// [DeviceImpl::start_transcript][super::DeviceImpl::start_transcript].
+pub struct DeviceTranscriptRecorder {
+  start: Instant,
+  transcript: Mutex<DeviceTranscript>,
+}
+
+impl DeviceTranscriptRecorder {
+  pub(super) fn new(
+    name: &str,
+    event_stream: broadcast::Receiver<ButtplugDeviceEvent>,
+  ) -> Arc<Self> {
+    let recorder = Arc::new(Self {
+      start: Instant::now(),
+      transcript: Mutex::new(DeviceTranscript::new(name)),
+    });
+    async_manager::spawn(record_notifications(
+      Arc::downgrade(&recorder),
+      event_stream,
+    ))
+    .unwrap();
+    recorder
+  }
+
+  pub fn record(&self, event: DeviceTranscriptEvent) {
+    let time = self.start.elapsed().as_millis() as u64;
+    self
+      .transcript
+      .lock()
+      .unwrap()
+      .entries
+      .push(DeviceTranscriptEntry { time, event });
+  }
+
+  /// Copy of everything recorded so far.
+  pub fn transcript(&self) -> DeviceTranscript {
+    self.transcript.lock().unwrap().clone()
+  }
+}
+
+async fn record_notifications(
+  recorder: Weak<DeviceTranscriptRecorder>,
+  mut event_stream: broadcast::Receiver<ButtplugDeviceEvent>,
+) {
+  loop {
+    match event_stream.recv().await {
+      Ok(ButtplugDeviceEvent::Notification(_, endpoint, data)) => match recorder.upgrade() {
+        Some(recorder) => recorder.record(DeviceTranscriptEvent::Notification { endpoint, data }),
+        None => return,
+      },
+      Ok(ButtplugDeviceEvent::Removed(_)) => return,
+      Ok(_) => {}
+      Err(broadcast::error::RecvError::Lagged(count)) => {
+        warn!("Transcript recorder lagged, {} notifications lost.", count);
+      }
+      Err(broadcast::error::RecvError::Closed) => return,
+    }
+    if recorder.strong_count() == 0 {
+      return;
+    }
+  }
+}
+
+#[cfg(all(test, feature = "server", feature = "serialize-json"))]
+mod test {
+  use super::{DeviceTranscript, DeviceTranscriptEvent};
+  use crate::{
+    core::messages::{StopDeviceCmd, VibrateCmd, VibrateSubcommand},
+    device::{ButtplugDeviceEvent, Endpoint},
+    test::{new_bluetoothle_test_device, replay_device_transcript},
+    util::async_manager,
+  };
+  use futures_timer::Delay;
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_transcript_record_and_replay() {
+    async_manager::block_on(async move {
+      let (device, test_device) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      device.start_transcript();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      test_device.send_event(ButtplugDeviceEvent::Notification(
+        test_device.address(),
+        Endpoint::Rx,
+        vec![1, 2, 3],
+      ));
+      device
+        .parse_message(StopDeviceCmd::new(0).into())
+        .await
+        .unwrap();
+      // Notifications are recorded from a separate task.
+      Delay::new(Duration::from_millis(50)).await;
+      let transcript = device.stop_transcript().unwrap();
+      let events: Vec<DeviceTranscriptEvent> = transcript
+        .entries
+        .iter()
+        .map(|entry| entry.event.clone())
+        .collect();
+      assert_eq!(transcript.name, "Massage Demo");
+      // Stop only writes the motor that was running.
+      assert_eq!(events.len(), 5);
+      assert_eq!(
+        events[1],
+        DeviceTranscriptEvent::Write {
+          endpoint: Endpoint::Tx,
+          data: vec![0xF1, 64],
+          write_with_response: false
+        }
+      );
+      assert!(events.contains(&DeviceTranscriptEvent::Notification {
+        endpoint: Endpoint::Rx,
+        data: vec![1, 2, 3]
+      }));
+      assert!(device.stop_transcript().is_none());
+
+      let transcript = DeviceTranscript::from_json(&transcript.to_json()).unwrap();
+      replay_device_transcript(&transcript).await;
+    });
+  }
+
+  #[test]
+  #[should_panic]
+  pub fn test_transcript_replay_mismatch() {
+    async_manager::block_on(async move {
+      let (device, _) = new_bluetoothle_test_device("Massage Demo").await.unwrap();
+      device.start_transcript();
+      device
+        .parse_message(VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into())
+        .await
+        .unwrap();
+      let mut transcript = device.stop_transcript().unwrap();
+      if let DeviceTranscriptEvent::Write { data, .. } = &mut transcript.entries[1].event {
+        data[1] = 63;
+      }
+      replay_device_transcript(&transcript).await;
+    });
+  }
+}
@@ -31,6 +31,7 @@ pub mod client;
 pub mod connector;
 pub mod core;
 pub mod device;
+pub mod prelude;
 #[cfg(feature = "server")]
 pub mod server;
 pub mod util;
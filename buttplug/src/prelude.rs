@@ -0,0 +1,35 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Commonly used types, re-exported in one place.
+//!
+//! Most client applications only need a handful of types out of the library:
+//! the client, its devices and events, the command conveniences, a connector
+//! or two, and the error types. Instead of pulling each of those out of their
+//! own module, applications can glob import the prelude.
+//!
+//! ```
+//! use buttplug::prelude::*;
+//! ```
+
+#[cfg(feature = "client")]
+pub use crate::client::{
+  ButtplugClient, ButtplugClientBuilder, ButtplugClientDevice, ButtplugClientDeviceEvent,
+  ButtplugClientDeviceMessageType, ButtplugClientError, ButtplugClientEvent, LinearCommand,
+  RotateCommand, VibrateCommand,
+};
+#[cfg(all(feature = "server", feature = "client"))]
+pub use crate::connector::ButtplugInProcessClientConnector;
+#[cfg(feature = "websockets")]
+pub use crate::connector::ButtplugWebsocketClientTransport;
+#[cfg(any(feature = "client", feature = "server"))]
+pub use crate::connector::{ButtplugConnectorError, ButtplugRemoteClientConnector};
+#[cfg(feature = "serialize-json")]
+pub use crate::core::messages::serializer::ButtplugClientJSONSerializer;
+pub use crate::core::errors::{ButtplugDeviceError, ButtplugError};
+#[cfg(feature = "server")]
+pub use crate::server::{ButtplugClientPermissions, ButtplugServerOptions};
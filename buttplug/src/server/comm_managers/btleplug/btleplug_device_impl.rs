@@ -13,7 +13,7 @@ use crate::{
     DeviceImpl, DeviceImplInternal, DeviceReadCmd, DeviceSubscribeCmd, DeviceUnsubscribeCmd,
     DeviceWriteCmd,
   },
-  util::async_manager,
+  util::{async_manager, logging::redact_address},
 };
 use async_trait::async_trait;
 use btleplug::api::{CentralEvent, Peripheral};
@@ -82,7 +82,8 @@ impl<T: Peripheral> ButtplugDeviceImplCreator for BtlePlugDeviceImplCreator<T> {
       let name = device.properties().local_name.unwrap();
       let address = device.properties().address.to_string();
       let (device_event_sender, _) = broadcast::channel(256);
-      // rumble calls, so this will block whatever thread it's spawned to.
+      // btleplug 0.7 calls are synchronous, so this will block whatever
+      // thread it's spawned to.
       let mut event_loop = BtlePlugInternalEventLoop::new(
         self.broadcaster.subscribe(),
         device,
@@ -94,7 +95,7 @@ impl<T: Peripheral> ButtplugDeviceImplCreator for BtlePlugDeviceImplCreator<T> {
         async move { event_loop.run().await }.instrument(tracing::info_span!(
           "btleplug Event Loop",
           device = tracing::field::display(&name),
-          address = tracing::field::display(&address)
+          address = tracing::field::display(redact_address(&address))
         )),
       )
       .unwrap();
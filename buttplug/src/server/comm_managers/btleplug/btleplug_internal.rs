@@ -1,5 +1,8 @@
 use crate::{
-  core::{errors::ButtplugDeviceError, messages, ButtplugResult},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugErrorCause},
+    messages, ButtplugResult,
+  },
   device::{
     configuration_manager::BluetoothLESpecifier, ButtplugDeviceCommand, ButtplugDeviceEvent,
     ButtplugDeviceImplInfo, ButtplugDeviceReturn, DeviceImplCommand, DeviceReadCmd,
@@ -9,6 +12,7 @@ use crate::{
   util::{
     async_manager,
     future::{ButtplugFuture, ButtplugFutureStateShared},
+    logging::redact_address,
   },
 };
 use btleplug::api::{CentralEvent, Characteristic, Peripheral, ValueNotification, WriteType};
@@ -53,10 +57,10 @@ impl<T: Peripheral> BtlePlugInternalEventLoop<T> {
         match event {
           CentralEvent::DeviceConnected(ev) => {
             if ev != device_address {
-              debug!("Device {} connect event received, but instance device address is {}, ignoring", ev, device_address);
+              debug!("Device {} connect event received, but instance device address is {}, ignoring", redact_address(&ev), redact_address(&device_address));
               continue;
             } else {
-              debug!("Device {} connect event received, matches instance device address, notifying event loop", ev);
+              debug!("Device {} connect event received, matches instance device address, notifying event loop", redact_address(&ev));
             }
             let s = event_sender.clone();
             let e = event;
@@ -67,10 +71,10 @@ impl<T: Peripheral> BtlePlugInternalEventLoop<T> {
           }
           CentralEvent::DeviceDisconnected(ev) => {
             if ev != device_address {
-              debug!("Device {} disconnect event received, but instance device address is {}, ignoring", ev, device_address);
+              debug!("Device {} disconnect event received, but instance device address is {}, ignoring", redact_address(&ev), redact_address(&device_address));
               continue;
             } else {
-              debug!("Device {} disconnect event received, matches instance device address, exiting", ev);
+              debug!("Device {} disconnect event received, matches instance device address, exiting", redact_address(&ev));
             }
             let s = event_sender.clone();
             let e = event;
@@ -100,7 +104,7 @@ impl<T: Peripheral> BtlePlugInternalEventLoop<T> {
     info!("Connecting to BTLEPlug device");
     if let Err(err) = self.device.connect() {
       let return_err = ButtplugDeviceError::DeviceSpecificError(
-        ButtplugDeviceSpecificError::BtleplugError(format!("{:?}", err)),
+        ButtplugDeviceSpecificError::BtleplugError(ButtplugErrorCause::new(err)),
       );
       state.set_reply(ButtplugDeviceReturn::Error(return_err.clone().into()));
       return Err(return_err.into());
@@ -237,7 +241,7 @@ impl<T: Peripheral> BtlePlugInternalEventLoop<T> {
           error!("BTLEPlug device read error: {:?}", err);
           state.set_reply(ButtplugDeviceReturn::Error(
             ButtplugDeviceError::DeviceSpecificError(ButtplugDeviceSpecificError::BtleplugError(
-              format!("{:?}", err),
+              ButtplugErrorCause::new(err),
             ))
             .into(),
           ));
@@ -2,18 +2,27 @@ mod btleplug_device_impl;
 mod btleplug_internal;
 
 use crate::{
-  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  core::{
+    errors::ButtplugDeviceError,
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
+  device::configuration_manager::DeviceConfigurationManager,
   server::comm_managers::{
+    preflight::{DevicePermission, PermissionCheck},
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
   },
-  util::async_manager,
+  util::{async_manager, logging::redact_address},
 };
+use futures_timer::Delay;
 use std::{
+  collections::HashMap,
   sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
   },
   thread,
+  time::{Duration, Instant},
 };
 use tokio::sync::{broadcast, mpsc::Sender, Notify};
 
@@ -30,7 +39,8 @@ use tokio::runtime::Handle;
 
 #[derive(Default)]
 pub struct BtlePlugCommunicationManagerBuilder {
-  sender: Option<tokio::sync::mpsc::Sender<DeviceCommunicationEvent>>
+  sender: Option<tokio::sync::mpsc::Sender<DeviceCommunicationEvent>>,
+  device_config: Option<Arc<DeviceConfigurationManager>>,
 }
 
 impl DeviceCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder {
@@ -38,29 +48,180 @@ impl DeviceCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder {
     self.sender = Some(sender)
   }
 
+  fn set_device_configuration(&mut self, config: Arc<DeviceConfigurationManager>) {
+    self.device_config = Some(config)
+  }
+
   fn finish(mut self) -> Box<dyn DeviceCommunicationManager> {
-    Box::new(BtlePlugCommunicationManager::new(self.sender.take().unwrap()))
+    Box::new(BtlePlugCommunicationManager::new(
+      self.sender.take().unwrap(),
+      self.device_config.take(),
+    ))
+  }
+}
+
+/// Logs comm manager errors and passes them along to the device manager.
+fn report_error(device_sender: &Sender<DeviceCommunicationEvent>, err: ButtplugDeviceError) {
+  error!("{}", err);
+  if device_sender
+    .try_send(DeviceCommunicationEvent::Error(err))
+    .is_err()
+  {
+    error!("Device manager receiver unavailable, cannot send error.");
+  }
+}
+
+type SharedAdapter = Arc<Mutex<Option<Adapter>>>;
+
+// How often to check whether the adapter is still powered. Powering off
+// doesn't close the adapter event channel, so we have to ask.
+#[cfg(target_os = "linux")]
+const ADAPTER_POWER_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// How long to wait before trying an address that didn't turn into a device
+// again. Devices we don't support are skipped before connecting, so retries
+// are cheap, but this needs to be longer than a connection attempt takes so
+// we don't try the same device twice at once.
+const TRIED_ADDRESS_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// In busy RF environments, the same device can advertise many times a
+// second. Repeats within this long are only logged at trace level.
+const ADVERTISEMENT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Minimum time between passes over the adapter's peripherals while scanning,
+// so a flood of advertisements gets handled in one pass instead of one pass
+// each.
+const SCAN_PASS_MIN_INTERVAL: Duration = Duration::from_millis(250);
+
+fn recently_tried(tried_addresses: &DashMap<BDAddr, Instant>, address: BDAddr) -> bool {
+  match tried_addresses.get(&address) {
+    Some(tried_at) => tried_at.elapsed() < TRIED_ADDRESS_RETRY_DELAY,
+    None => false,
+  }
+}
+
+/// Checks whether a device at `address` should be passed to the device
+/// manager, marking it as tried if so. Addresses are tried again once they
+/// age out, so a device that failed to connect or initialize (out of range,
+/// busy, etc) can still show up without restarting the scan.
+fn try_address(tried_addresses: &DashMap<BDAddr, Instant>, address: BDAddr) -> bool {
+  if recently_tried(tried_addresses, address) {
+    return false;
+  }
+  tried_addresses.insert(address, Instant::now());
+  true
+}
+
+/// Lets through the first advertisement from each address per interval.
+struct AdvertisementDebouncer {
+  interval: Duration,
+  last_seen: HashMap<BDAddr, Instant>,
+}
+
+impl AdvertisementDebouncer {
+  fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      last_seen: HashMap::new(),
+    }
+  }
+
+  fn should_handle(&mut self, address: BDAddr) -> bool {
+    let now = Instant::now();
+    match self.last_seen.get(&address) {
+      Some(seen) if now.duration_since(*seen) < self.interval => false,
+      _ => {
+        self.last_seen.insert(address, now);
+        true
+      }
+    }
+  }
+
+  fn forget(&mut self, address: &BDAddr) {
+    self.last_seen.remove(address);
+  }
+}
+
+/// Everything that needs resetting when the adapter goes away, shared with
+/// the threads watching the adapter.
+#[derive(Clone)]
+struct AdapterLossHandler {
+  adapter: SharedAdapter,
+  tried_addresses: Arc<DashMap<BDAddr, Instant>>,
+  connected_addresses: Arc<DashMap<BDAddr, ()>>,
+  device_sender: Sender<DeviceCommunicationEvent>,
+  scanning_notifier: Arc<Notify>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl AdapterLossHandler {
+  /// Drops the adapter and tells the device manager everything we found
+  /// through it is gone. `alive` is set up per adapter, so only the first
+  /// watcher to notice the loss reports it.
+  fn adapter_lost(&self, alive: &AtomicBool, reason: &str) {
+    if !alive.swap(false, Ordering::SeqCst) {
+      return;
+    }
+    // Clear the adapter so we can look for it again the next time scanning
+    // starts.
+    *self.adapter.lock().unwrap() = None;
+    self.connected_addresses.clear();
+    self.tried_addresses.clear();
+    // Wake the scanning task so it finishes up.
+    self.is_scanning.store(false, Ordering::SeqCst);
+    self.scanning_notifier.notify_one();
+    let err = ButtplugDeviceError::DeviceCommunicationError(format!(
+      "Bluetooth adapter lost: {}",
+      reason
+    ));
+    error!("{}", err);
+    if self
+      .device_sender
+      .try_send(DeviceCommunicationEvent::AdapterRemoved(err))
+      .is_err()
+    {
+      error!("Device manager receiver unavailable, cannot send adapter removal.");
+    }
   }
 }
 
 pub struct BtlePlugCommunicationManager {
   // BtlePlug says to only have one manager at a time, so we'll have the comm
   // manager hold it.
-  manager: Manager,
-  adapter: Option<Adapter>,
+  manager: Option<Manager>,
+  // Cleared if the adapter goes away, so we can try to find one again the
+  // next time scanning starts.
+  adapter: SharedAdapter,
   adapter_event_sender: broadcast::Sender<CentralEvent>,
-  tried_addresses: Arc<DashMap<BDAddr, ()>>,
+  tried_addresses: Arc<DashMap<BDAddr, Instant>>,
   connected_addresses: Arc<DashMap<BDAddr, ()>>,
   device_sender: Sender<DeviceCommunicationEvent>,
   scanning_notifier: Arc<Notify>,
   is_scanning: Arc<AtomicBool>,
+  // Used to build scan filters. Without it, every named device is passed on.
+  device_config: Option<Arc<DeviceConfigurationManager>>,
 }
 
 impl BtlePlugCommunicationManager {
-  fn new(device_sender: Sender<DeviceCommunicationEvent>) -> Self {
+  fn new(
+    device_sender: Sender<DeviceCommunicationEvent>,
+    device_config: Option<Arc<DeviceConfigurationManager>>,
+  ) -> Self {
     // At this point, no one will be subscribed, so just drop the receiver.
     let (adapter_event_sender, _) = broadcast::channel(256);
-    let manager = Manager::new().unwrap();
+    let manager = match Manager::new() {
+      Ok(manager) => Some(manager),
+      Err(err) => {
+        report_error(
+          &device_sender,
+          ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Cannot create bluetooth manager: {}",
+            err
+          )),
+        );
+        None
+      }
+    };
     let tried_addresses = Arc::new(DashMap::new());
     let tried_addresses_clone = tried_addresses.clone();
     let mut adapter_event_handler = adapter_event_sender.subscribe();
@@ -69,19 +230,31 @@ impl BtlePlugCommunicationManager {
     let connected_addresses_clone = connected_addresses.clone();
     let scanning_notifier = Arc::new(Notify::new());
     let scanning_notifier_clone = scanning_notifier.clone();
-    async_manager::spawn(async move {
+    async_manager::spawn_named("BTLEPlug Adapter Event Handler", async move {
+      let mut log_debouncer = AdvertisementDebouncer::new(ADVERTISEMENT_LOG_INTERVAL);
       while let Ok(event) = adapter_event_handler.recv().await {
         match event {
-          CentralEvent::DeviceDiscovered(_) => {
-            debug!("BTLEPlug Device discovered: {:?}", event);
-            scanning_notifier_clone.notify_waiters();
-          }
-          CentralEvent::DeviceUpdated(_) => {
-            // We will get a LOT of these messages due to RSSI updates, but
-            // they'll also happen if we got RSSI first then got an
-            // advertisement packet with a name update.
-            trace!("BTLEPlug Device updated: {:?}", event);
-            scanning_notifier_clone.notify_waiters();
+          // We will get a LOT of updates due to RSSI changes, but they'll also
+          // happen if we got RSSI first then got an advertisement packet with
+          // a name update.
+          CentralEvent::DeviceDiscovered(addr) | CentralEvent::DeviceUpdated(addr) => {
+            // Devices we're connected to or already tried won't be looked at
+            // by the scanning task, so don't wake it up for them.
+            if connected_addresses_clone.contains_key(&addr)
+              || recently_tried(&tried_addresses_clone, addr)
+            {
+              continue;
+            }
+            if matches!(event, CentralEvent::DeviceDiscovered(_))
+              && log_debouncer.should_handle(addr)
+            {
+              debug!("BTLEPlug Device discovered: {}", redact_address(addr));
+            } else {
+              trace!("BTLEPlug Device updated: {}", redact_address(addr));
+            }
+            // Stores a permit if the scanning task is busy, so bursts of
+            // advertisements only cause one more pass.
+            scanning_notifier_clone.notify_one();
           }
           CentralEvent::DeviceConnected(addr) => {
             info!("BTLEPlug Device connected: {:?}", addr);
@@ -91,6 +264,7 @@ impl BtlePlugCommunicationManager {
             debug!("BTLEPlug Device disconnected: {:?}", event);
             connected_addresses_clone.remove(&addr);
             tried_addresses_clone.remove(&addr);
+            log_debouncer.forget(&addr);
           }
           _ => {}
         }
@@ -98,39 +272,92 @@ impl BtlePlugCommunicationManager {
     })
     .unwrap();
 
-    let mut comm_mgr = Self {
+    let comm_mgr = Self {
       manager,
-      adapter: None,
+      adapter: Arc::new(Mutex::new(None)),
       adapter_event_sender,
       connected_addresses,
       tried_addresses,
       device_sender,
       scanning_notifier,
       is_scanning: Arc::new(AtomicBool::new(false)),
+      device_config,
     };
     comm_mgr.setup_adapter();
     comm_mgr
   }
 
-  fn get_central(&self) -> Option<Adapter> {
-    let adapters = self.manager.adapters().unwrap();
-    if adapters.is_empty() {
-      return None;
+  fn loss_handler(&self) -> AdapterLossHandler {
+    AdapterLossHandler {
+      adapter: self.adapter.clone(),
+      tried_addresses: self.tried_addresses.clone(),
+      connected_addresses: self.connected_addresses.clone(),
+      device_sender: self.device_sender.clone(),
+      scanning_notifier: self.scanning_notifier.clone(),
+      is_scanning: self.is_scanning.clone(),
     }
+  }
 
-    let adapter = adapters.into_iter().next().unwrap();
-
-    return Some(adapter);
+  fn get_central(&self) -> Option<Adapter> {
+    let adapters = match self.manager.as_ref()?.adapters() {
+      Ok(adapters) => adapters,
+      Err(err) => {
+        report_error(
+          &self.device_sender,
+          ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Cannot list bluetooth adapters: {}",
+            err
+          )),
+        );
+        return None;
+      }
+    };
+    adapters.into_iter().next()
   }
 
-  fn setup_adapter(&mut self) {
-    let maybe_adapter = self.get_central();
-    if maybe_adapter.is_none() {
-      return;
+  /// Finds an adapter and starts relaying its events, if we don't have one
+  /// already. Returns false if no usable adapter is available.
+  fn setup_adapter(&self) -> bool {
+    let mut adapter_holder = self.adapter.lock().unwrap();
+    if adapter_holder.is_some() {
+      return true;
+    }
+    let adapter = match self.get_central() {
+      Some(adapter) => adapter,
+      None => return false,
+    };
+    let receiver = match adapter.event_receiver() {
+      Some(receiver) => receiver,
+      None => {
+        report_error(
+          &self.device_sender,
+          ButtplugDeviceError::DeviceCommunicationError(
+            "Cannot get event receiver for bluetooth adapter".to_owned(),
+          ),
+        );
+        return false;
+      }
+    };
+    *adapter_holder = Some(adapter.clone());
+    let alive = Arc::new(AtomicBool::new(true));
+    let loss_handler = self.loss_handler();
+    #[cfg(target_os = "linux")]
+    {
+      let alive = alive.clone();
+      let loss_handler = loss_handler.clone();
+      thread::spawn(move || {
+        while alive.load(Ordering::SeqCst) {
+          thread::sleep(ADAPTER_POWER_CHECK_INTERVAL);
+          match adapter.is_powered() {
+            Ok(true) => {}
+            Ok(false) => loss_handler.adapter_lost(&alive, "adapter powered off"),
+            Err(err) => loss_handler.adapter_lost(&alive, &err.to_string()),
+          }
+        }
+      });
     }
-    let adapter = maybe_adapter.unwrap();
-    let receiver = adapter.event_receiver().unwrap();
-    self.adapter = Some(adapter);
+    #[cfg(not(target_os = "linux"))]
+    drop(adapter);
     let event_sender = self.adapter_event_sender.clone();
     let handle = Handle::current();
     thread::spawn(move || {
@@ -145,7 +372,11 @@ impl BtlePlugCommunicationManager {
           });
         }
       }
+      // The adapter stopped sending events, which means it's gone (unplugged,
+      // bluetooth turned off, etc).
+      loss_handler.adapter_lost(&alive, "adapter stopped responding");
     });
+    true
   }
 }
 
@@ -154,11 +385,45 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
     "BtlePlugCommunicationManager"
   }
 
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let remedy = "Allow Bluetooth access for this app in System Preferences > Security & Privacy > Privacy > Bluetooth, then restart it.";
+    #[cfg(target_os = "linux")]
+    let remedy = "Make sure bluetoothd is running and your user can talk to BlueZ over D-Bus (usually by being in the bluetooth group).";
+    #[cfg(target_os = "windows")]
+    let remedy = "Make sure Bluetooth is turned on, and that apps are allowed to use it in Settings > Privacy.";
+    let manager = match &self.manager {
+      Some(manager) => manager,
+      None => {
+        return vec![PermissionCheck::denied(
+          DevicePermission::Bluetooth,
+          "cannot create bluetooth manager",
+          remedy,
+        )]
+      }
+    };
+    if self.adapter.lock().unwrap().is_some() {
+      return vec![PermissionCheck::granted(DevicePermission::Bluetooth)];
+    }
+    let check = match manager.adapters() {
+      Ok(adapters) if adapters.is_empty() => {
+        PermissionCheck::unknown(DevicePermission::Bluetooth, "no bluetooth adapters found")
+      }
+      Ok(_) => PermissionCheck::granted(DevicePermission::Bluetooth),
+      Err(err) => PermissionCheck::denied(
+        DevicePermission::Bluetooth,
+        &format!("cannot list bluetooth adapters: {}", err),
+        remedy,
+      ),
+    };
+    vec![check]
+  }
+
   fn start_scanning(&self) -> ButtplugResultFuture {
     // get the first bluetooth adapter
     debug!("Bringing up adapter.");
-    // TODO What happens if we don't have a radio?
-    if self.adapter.is_none() {
+    // If we lost the adapter (or never had one), see if one is available now.
+    if !self.setup_adapter() {
       warn!("No adapter, can't scan.");
       return ButtplugDeviceError::UnhandledCommand(
         "Cannot scan, no bluetooth adapters found".to_owned(),
@@ -169,46 +434,73 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
     let scanning_notifier = self.scanning_notifier.clone();
     let is_scanning = self.is_scanning.clone();
 
-    let central = self.adapter.clone().unwrap();
+    // setup_adapter() just made sure this exists.
+    let central = self.adapter.lock().unwrap().clone().unwrap();
+    let adapter_holder = self.adapter.clone();
     let adapter_event_sender_clone = self.adapter_event_sender.clone();
     let tried_addresses_handler = self.tried_addresses.clone();
     let connected_addresses_handler = self.connected_addresses.clone();
+    // Built when scanning starts, so protocols added or removed since the
+    // last scan are accounted for.
+    let scan_filter = self
+      .device_config
+      .as_ref()
+      .map(|config| config.btle_scan_filter());
     Box::pin(async move {
       info!("Starting scan.");
+      // btleplug 0.7's start_scan() doesn't take a filter, so the adapter
+      // reports everything, and scan_filter is only applied to names below.
       if let Err(err) = central.start_scan() {
         // TODO Explain the setcap issue on linux here.
         return Err(ButtplugDeviceError::DevicePermissionError(format!("BTLEPlug cannot start scanning. This may be a permissions error (on linux) or an issue with finding the radio. Reason: {}", err)).into());
       }
       is_scanning.store(true, Ordering::SeqCst);
-      async_manager::spawn(async move {
+      async_manager::spawn_named("BTLEPlug Scanning Loop", async move {
         // When stop_scanning is called, this will get false and stop the
         // task.
         while is_scanning.load(Ordering::SeqCst) {
           for p in central.peripherals() {
+            // Skip property lookups for anything we already know about.
+            let address = p.address();
+            if connected_addresses_handler.contains_key(&address)
+              || recently_tried(&tried_addresses_handler, address)
+            {
+              continue;
+            }
+            // Properties are a snapshot, so only fetch them once per pass.
+            let properties = p.properties();
             // If a device has no discernable name, we can't do anything
             // with it, just ignore it.
-            if let Some(name) = p.properties().local_name {
+            if let Some(name) = properties.local_name {
+              let address = properties.address;
               let span = info_span!(
                 "btleplug enumeration",
-                address = tracing::field::display(p.properties().address),
+                address = tracing::field::display(redact_address(address)),
                 name = tracing::field::display(&name)
               );
               let _enter = span.enter();
-              //debug!("Found device {}", name);
               // Names are the only way we really have to test devices
               // at the moment. Most devices don't send services on
               // advertisement.
-              if !name.is_empty()
-                && !tried_addresses_handler.contains_key(&p.properties().address)
-                && !connected_addresses_handler.contains_key(&p.properties().address)
+              let filtered = !name.is_empty()
+                && matches!(&scan_filter, Some(filter) if !filter.matches_name(&name));
+              if filtered {
+                // Marking it as tried keeps later passes from looking up its
+                // properties again until it ages out. Empty names aren't
+                // filtered, since the real name may show up in a later
+                // advertisement.
+                if try_address(&tried_addresses_handler, address) {
+                  trace!("Device {} not in scan filter, ignoring.", name);
+                }
+              } else if !name.is_empty()
+                && !connected_addresses_handler.contains_key(&address)
+                && try_address(&tried_addresses_handler, address)
               {
-                let name = p
-                  .properties()
-                  .local_name
-                  .unwrap_or_else(|| "[NAME UNKNOWN]".to_owned());
-                let address = p.properties().address;
-                debug!("Found new bluetooth device: {} {}", name, address);
-                tried_addresses_handler.insert(address, ());
+                debug!(
+                  "Found new bluetooth device: {} {}",
+                  name,
+                  redact_address(address)
+                );
 
                 let device_creator = Box::new(BtlePlugDeviceImplCreator::new(
                   p,
@@ -220,6 +512,9 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
                     name,
                     address: address.to_string(),
                     creator: device_creator,
+                    // TODO btleplug 0.7 doesn't give us RSSI from
+                    // advertisements, add signal strength once it does.
+                    metadata: DeviceMetadata::new(DeviceTransport::BluetoothLE),
                   })
                   .await
                   .is_err()
@@ -231,13 +526,28 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
             } else {
               trace!(
                 "Device {} found, no advertised name, ignoring.",
-                p.properties().address
+                properties.address
               );
             }
           }
+          Delay::new(SCAN_PASS_MIN_INTERVAL).await;
           scanning_notifier.notified().await;
         }
-        central.stop_scan().unwrap();
+        // If the adapter went away, there's nothing left to stop.
+        let adapter_lost = adapter_holder.lock().unwrap().is_none();
+        if adapter_lost {
+          debug!("Adapter lost while scanning, not stopping scan.");
+        } else if let Err(err) = central.stop_scan() {
+          // Still finish up, since we're not looking at anything the adapter
+          // finds anymore.
+          report_error(
+            &device_sender,
+            ButtplugDeviceError::DeviceCommunicationError(format!(
+              "BTLEPlug cannot stop scanning: {}",
+              err
+            )),
+          );
+        }
         debug!("BTLEPlug scanning finished.");
         if device_sender
           .send(DeviceCommunicationEvent::ScanningFinished)
@@ -260,7 +570,9 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
     Box::pin(async move {
       if is_scanning.load(Ordering::SeqCst) {
         is_scanning.store(false, Ordering::SeqCst);
-        scanning_notifier.notify_waiters();
+        // The scanning task is the only waiter. This leaves a permit if it's
+        // in the middle of a pass, so it can't miss the stop.
+        scanning_notifier.notify_one();
         Ok(())
       } else {
         Err(ButtplugDeviceError::DeviceScanningAlreadyStopped.into())
@@ -276,8 +588,8 @@ impl DeviceCommunicationManager for BtlePlugCommunicationManager {
 impl Drop for BtlePlugCommunicationManager {
   fn drop(&mut self) {
     info!("Dropping btleplug comm manager.");
-    if self.adapter.is_some() {
-      if let Err(e) = self.adapter.as_ref().unwrap().stop_scan() {
+    if let Some(adapter) = self.adapter.lock().unwrap().as_ref() {
+      if let Err(e) = adapter.stop_scan() {
         info!("Error on scanning shutdown for bluetooth: {:?}", e);
       }
     }
@@ -286,22 +598,40 @@ impl Drop for BtlePlugCommunicationManager {
 
 #[cfg(test)]
 mod test {
-  use super::BtlePlugCommunicationManager;
+  use super::{AdvertisementDebouncer, BtlePlugCommunicationManager};
   use crate::{
     server::comm_managers::{
       DeviceCommunicationEvent, DeviceCommunicationManager,
     },
     util::async_manager,
   };
+  use btleplug::api::BDAddr;
+  use std::time::Duration;
   use tokio::sync::mpsc::channel;
 
+  #[test]
+  pub fn test_advertisement_debouncer() {
+    let mut debouncer = AdvertisementDebouncer::new(Duration::from_secs(60));
+    let first = BDAddr {
+      address: [1, 2, 3, 4, 5, 6],
+    };
+    let second = BDAddr {
+      address: [6, 5, 4, 3, 2, 1],
+    };
+    assert!(debouncer.should_handle(first));
+    assert!(!debouncer.should_handle(first));
+    assert!(debouncer.should_handle(second));
+    debouncer.forget(&first);
+    assert!(debouncer.should_handle(first));
+  }
+
   // Ignored because it requires a device. Should probably just be a manual integration test.
   #[test]
   #[ignore]
   pub fn test_btleplug() {
     async_manager::block_on(async move {
       let (sender, mut receiver) = channel(256);
-      let mgr = BtlePlugCommunicationManager::new(sender);
+      let mgr = BtlePlugCommunicationManager::new(sender, None);
       mgr.start_scanning().await.unwrap();
       loop {
         match receiver.recv().await.unwrap() {
@@ -309,6 +639,7 @@ mod test {
             name: _,
             address: _,
             creator: _device,
+            metadata: _,
           } => {
             info!("Got device!");
             info!("Sending message!");
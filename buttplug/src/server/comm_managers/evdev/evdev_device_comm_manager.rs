@@ -0,0 +1,145 @@
+use super::evdev_device_impl::EvdevDeviceImplCreator;
+use crate::{
+  core::{
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
+  server::comm_managers::{
+    preflight::{self, DevicePermission, PermissionCheck},
+    DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
+  },
+  util::async_manager,
+};
+use dashmap::DashSet;
+use evdev::FFEffectType;
+use futures::{future, FutureExt};
+use futures_timer::Delay;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Notify};
+
+/// Set of device paths that we've already emitted, shared with device impls so
+/// they can remove themselves when the device goes away.
+pub(super) type EvdevConnectionTracker = Arc<DashSet<PathBuf>>;
+
+#[derive(Default)]
+pub struct EvdevDeviceCommunicationManagerBuilder {
+  sender: Option<tokio::sync::mpsc::Sender<DeviceCommunicationEvent>>,
+}
+
+impl DeviceCommunicationManagerBuilder for EvdevDeviceCommunicationManagerBuilder {
+  fn set_event_sender(&mut self, sender: mpsc::Sender<DeviceCommunicationEvent>) {
+    self.sender = Some(sender)
+  }
+
+  fn finish(mut self) -> Box<dyn DeviceCommunicationManager> {
+    Box::new(EvdevDeviceCommunicationManager::new(
+      self.sender.take().unwrap(),
+    ))
+  }
+}
+
+/// Finds gamepads with rumble support via the Linux force feedback API. This
+/// is the Linux counterpart to the XInput manager, and hands its devices to
+/// the same protocol.
+pub struct EvdevDeviceCommunicationManager {
+  sender: mpsc::Sender<DeviceCommunicationEvent>,
+  scanning_notifier: Arc<Notify>,
+  connected_devices: EvdevConnectionTracker,
+}
+
+impl EvdevDeviceCommunicationManager {
+  fn new(sender: mpsc::Sender<DeviceCommunicationEvent>) -> Self {
+    Self {
+      sender,
+      scanning_notifier: Arc::new(Notify::new()),
+      connected_devices: Arc::new(DashSet::new()),
+    }
+  }
+}
+
+impl DeviceCommunicationManager for EvdevDeviceCommunicationManager {
+  fn name(&self) -> &'static str {
+    "EvdevDeviceCommunicationManager"
+  }
+
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    vec![preflight::check_device_nodes(
+      DevicePermission::Input,
+      &preflight::device_nodes("/dev/input", &["event"]),
+      "Add your user to the input group, or add a udev rule giving it access to the gamepad's event node.",
+    )]
+  }
+
+  fn start_scanning(&self) -> ButtplugResultFuture {
+    debug!("Evdev manager scanning for devices");
+    let sender = self.sender.clone();
+    let scanning_notifier = self.scanning_notifier.clone();
+    let connected_devices = self.connected_devices.clone();
+    async_manager::spawn_named("Evdev Scanning Loop", async move {
+      let mut stop = false;
+      while !stop {
+        // Like XInput, we don't get plug n' play events here (that'd require
+        // udev), so just poll the device nodes while scanning.
+        for (path, device) in evdev::enumerate() {
+          if !matches!(device.supported_ff(), Some(ff) if ff.contains(FFEffectType::FF_RUMBLE)) {
+            continue;
+          }
+          if connected_devices.contains(&path) {
+            trace!("Evdev device {:?} already found, ignoring.", path);
+            continue;
+          }
+          let name = device.name().unwrap_or("Unknown Gamepad").to_owned();
+          let input_id = device.input_id();
+          let metadata = DeviceMetadata::new(DeviceTransport::HID)
+            .with_usb_ids(input_id.vendor(), input_id.product());
+          info!("Evdev manager found device {} at {:?}", name, path);
+          connected_devices.insert(path.clone());
+          let device_creator = Box::new(EvdevDeviceImplCreator::new(
+            &name,
+            path.clone(),
+            connected_devices.clone(),
+          ));
+          if sender
+            .send(DeviceCommunicationEvent::DeviceFound {
+              name,
+              address: path.to_string_lossy().into_owned(),
+              creator: device_creator,
+              metadata,
+            })
+            .await
+            .is_err()
+          {
+            error!("Error sending device found message from Evdev.");
+            break;
+          }
+        }
+        // Wait for either one second, or until our notifier has been notified.
+        select! {
+          _ = Delay::new(Duration::from_secs(1)).fuse() => {},
+          _ = scanning_notifier.notified().fuse() => {
+            debug!("Evdev stop scanning notifier notified, ending scanning loop");
+            stop = true;
+          }
+        }
+      }
+    })
+    .unwrap();
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn stop_scanning(&self) -> ButtplugResultFuture {
+    debug!("Evdev device comm manager received Stop Scanning request");
+    self.scanning_notifier.notify_waiters();
+    let sender = self.sender.clone();
+    Box::pin(async move {
+      if sender
+        .send(DeviceCommunicationEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished from Evdev.");
+      }
+      Ok(())
+    })
+  }
+}
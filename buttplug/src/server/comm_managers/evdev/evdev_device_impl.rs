@@ -0,0 +1,262 @@
+use super::evdev_device_comm_manager::EvdevConnectionTracker;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugErrorCause},
+    messages::RawReading,
+    ButtplugResultFuture,
+  },
+  device::{
+    configuration_manager::{DeviceSpecifier, ProtocolDefinition, XInputSpecifier},
+    ButtplugDeviceEvent, ButtplugDeviceImplCreator, DeviceImpl, DeviceImplInternal, DeviceReadCmd,
+    DeviceSubscribeCmd, DeviceUnsubscribeCmd, DeviceWriteCmd, Endpoint,
+  },
+  server::comm_managers::ButtplugDeviceSpecificError,
+  util::logging::redact_address,
+};
+use async_trait::async_trait;
+use byteorder::{LittleEndian, ReadBytesExt};
+use evdev::{Device, FFEffect, FFEffectData, FFEffectKind, FFReplay, FFTrigger};
+use futures::future::{self, BoxFuture};
+use std::{
+  fmt::{self, Debug},
+  io::{self, Cursor},
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+};
+use tokio::sync::broadcast;
+
+fn evdev_error(err: io::Error) -> ButtplugError {
+  ButtplugDeviceError::from(ButtplugDeviceSpecificError::EvdevError(
+    ButtplugErrorCause::new(err),
+  ))
+  .into()
+}
+
+/// Reads the strong and weak motor speeds out of a write command, using the
+/// same packet layout as the XInput device impl.
+fn parse_rumble_packet(data: &[u8]) -> Option<(u16, u16)> {
+  let mut cursor = Cursor::new(data);
+  let strong = cursor.read_u16::<LittleEndian>().ok()?;
+  let weak = cursor.read_u16::<LittleEndian>().ok()?;
+  Some((strong, weak))
+}
+
+fn rumble_effect(strong_magnitude: u16, weak_magnitude: u16) -> FFEffectData {
+  FFEffectData {
+    direction: 0,
+    trigger: FFTrigger::default(),
+    // A zero length effect plays until it's stopped or replaced.
+    replay: FFReplay {
+      length: 0,
+      delay: 0,
+    },
+    kind: FFEffectKind::Rumble {
+      strong_magnitude,
+      weak_magnitude,
+    },
+  }
+}
+
+pub struct EvdevDeviceImplCreator {
+  name: String,
+  path: PathBuf,
+  connected_devices: EvdevConnectionTracker,
+}
+
+impl EvdevDeviceImplCreator {
+  pub fn new(name: &str, path: PathBuf, connected_devices: EvdevConnectionTracker) -> Self {
+    debug!("Emitting a new evdev device impl creator!");
+    Self {
+      name: name.to_owned(),
+      path,
+      connected_devices,
+    }
+  }
+}
+
+impl Debug for EvdevDeviceImplCreator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("EvdevDeviceImplCreator")
+      .field("name", &self.name)
+      .field("path", &self.path)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl ButtplugDeviceImplCreator for EvdevDeviceImplCreator {
+  fn get_specifier(&self) -> DeviceSpecifier {
+    // Evdev rumble has the same two motor layout as XInput, so reuse the XInput
+    // protocol and configuration instead of duplicating them.
+    DeviceSpecifier::XInput(XInputSpecifier::default())
+  }
+
+  async fn try_create_device_impl(
+    &mut self,
+    _protocol: ProtocolDefinition,
+  ) -> Result<DeviceImpl, ButtplugError> {
+    debug!("Emitting a new evdev device impl.");
+    let device = match Device::open(&self.path) {
+      Ok(device) => device,
+      Err(err) => {
+        self.connected_devices.remove(&self.path);
+        return Err(evdev_error(err));
+      }
+    };
+    let address = self.path.to_string_lossy().into_owned();
+    let device_impl_internal =
+      EvdevDeviceImpl::new(device, self.path.clone(), self.connected_devices.clone());
+    let device_impl = DeviceImpl::new(
+      &self.name,
+      &address,
+      &[Endpoint::Tx],
+      Box::new(device_impl_internal),
+    );
+    Ok(device_impl)
+  }
+}
+
+struct EvdevDeviceState {
+  device: Device,
+  effect: Option<FFEffect>,
+}
+
+pub struct EvdevDeviceImpl {
+  state: Arc<Mutex<EvdevDeviceState>>,
+  path: PathBuf,
+  connected: Arc<AtomicBool>,
+  connected_devices: EvdevConnectionTracker,
+  event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+}
+
+impl EvdevDeviceImpl {
+  fn new(device: Device, path: PathBuf, connected_devices: EvdevConnectionTracker) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      state: Arc::new(Mutex::new(EvdevDeviceState {
+        device,
+        effect: None,
+      })),
+      path,
+      connected: Arc::new(AtomicBool::new(true)),
+      connected_devices,
+      event_sender,
+    }
+  }
+}
+
+impl Drop for EvdevDeviceImpl {
+  fn drop(&mut self) {
+    // Let the device be found again on the next scan.
+    self.connected_devices.remove(&self.path);
+  }
+}
+
+impl DeviceImplInternal for EvdevDeviceImpl {
+  fn event_stream(&self) -> broadcast::Receiver<ButtplugDeviceEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn connected(&self) -> bool {
+    self.connected.load(Ordering::SeqCst)
+  }
+
+  fn disconnect(&self) -> ButtplugResultFuture {
+    // Dropping the effect removes it from the device, which stops it.
+    if let Ok(mut state) = self.state.lock() {
+      state.effect = None;
+    }
+    self.connected.store(false, Ordering::SeqCst);
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn read_value(
+    &self,
+    _msg: DeviceReadCmd,
+  ) -> BoxFuture<'static, Result<RawReading, ButtplugError>> {
+    Box::pin(future::ready(Err(
+      ButtplugDeviceError::UnhandledCommand("Evdev devices do not support reading.".to_owned())
+        .into(),
+    )))
+  }
+
+  fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture {
+    let state = self.state.clone();
+    let connected = self.connected.clone();
+    let event_sender = self.event_sender.clone();
+    let address = self.path.to_string_lossy().into_owned();
+    Box::pin(async move {
+      let (strong, weak) = parse_rumble_packet(&msg.data).ok_or_else(|| {
+        ButtplugError::from(ButtplugDeviceError::ProtocolSpecificError(
+          "evdev".to_owned(),
+          format!("Invalid rumble packet: {:?}", msg.data),
+        ))
+      })?;
+      let result = {
+        let mut guard = state.lock().expect("Evdev device lock poisoned");
+        let EvdevDeviceState { device, effect } = &mut *guard;
+        if strong == 0 && weak == 0 {
+          effect.as_mut().map_or(Ok(()), |effect| effect.stop())
+        } else {
+          let data = rumble_effect(strong, weak);
+          let upload = match effect {
+            Some(effect) => effect.update(data),
+            None => device
+              .upload_ff_effect(data)
+              .map(|new_effect| *effect = Some(new_effect)),
+          };
+          upload.and_then(|_| match effect {
+            Some(effect) => effect.play(1),
+            None => Ok(()),
+          })
+        }
+      };
+      if let Err(err) = result {
+        // Most likely the controller was unplugged.
+        error!(
+          "Evdev device {} write failed: {}",
+          redact_address(&address),
+          err
+        );
+        if connected.swap(false, Ordering::SeqCst) {
+          let _ = event_sender.send(ButtplugDeviceEvent::Removed(address));
+        }
+        return Err(evdev_error(err));
+      }
+      Ok(())
+    })
+  }
+
+  fn subscribe(&self, _msg: DeviceSubscribeCmd) -> ButtplugResultFuture {
+    Box::pin(future::ready(Err(
+      ButtplugDeviceError::UnhandledCommand("Evdev devices do not support subscribing.".to_owned())
+        .into(),
+    )))
+  }
+
+  fn unsubscribe(&self, _msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture {
+    Box::pin(future::ready(Err(
+      ButtplugDeviceError::UnhandledCommand(
+        "Evdev devices do not support unsubscribing.".to_owned(),
+      )
+      .into(),
+    )))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::parse_rumble_packet;
+
+  #[test]
+  fn test_parse_rumble_packet() {
+    assert_eq!(
+      parse_rumble_packet(&[0x34, 0x12, 0xff, 0x00]),
+      Some((0x1234, 0x00ff))
+    );
+    assert_eq!(parse_rumble_packet(&[0x34, 0x12, 0xff]), None);
+  }
+}
@@ -0,0 +1,6 @@
+mod evdev_device_comm_manager;
+mod evdev_device_impl;
+
+pub use evdev_device_comm_manager::{
+  EvdevDeviceCommunicationManager, EvdevDeviceCommunicationManagerBuilder,
+};
@@ -0,0 +1,283 @@
+use super::hid_device_impl::HidDeviceImplCreator;
+use crate::{
+  core::{
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
+  device::configuration_manager::{
+    is_bluetooth_hid_address, DeviceConfigurationManager, DeviceSpecifier, HIDSpecifier,
+    BLUETOOTH_HID_SERVICE_UUID,
+  },
+  server::comm_managers::{
+    hidapi_context::with_hid_api,
+    preflight::{self, PermissionCheck},
+    DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
+  },
+  util::async_manager,
+};
+use dashmap::DashSet;
+use futures::{future, FutureExt};
+use futures_timer::Delay;
+use hidapi::DeviceInfo;
+use std::{collections::HashSet, ffi::CString, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Notify};
+
+/// Set of device addresses that we've already emitted, shared with device
+/// impls so they can remove themselves when the device goes away.
+pub(super) type HidConnectionTracker = Arc<DashSet<String>>;
+
+// Usage pages from here up are vendor defined.
+const VENDOR_USAGE_PAGE: u16 = 0xff00;
+
+/// What we need to know about a HID interface to decide whether to use it,
+/// copied out so the hidapi context isn't held while we send events.
+struct HidInterface {
+  path: CString,
+  address: String,
+  vendor_id: u16,
+  product_id: u16,
+  name: String,
+  usage_page: u16,
+}
+
+impl HidInterface {
+  fn new(info: &DeviceInfo) -> Self {
+    Self {
+      path: info.path().to_owned(),
+      address: hid_address(&info.path().to_string_lossy(), info.interface_number()),
+      vendor_id: info.vendor_id(),
+      product_id: info.product_id(),
+      name: info
+        .product_string()
+        .unwrap_or("Unknown HID Device")
+        .to_owned(),
+      usage_page: info.usage_page(),
+    }
+  }
+}
+
+/// Device address for a HID interface. This is the hidapi path, plus the HID
+/// over Bluetooth service UUID for Bluetooth connections on platforms where
+/// the path doesn't say (see
+/// [is_bluetooth_hid_address][crate::device::configuration_manager::is_bluetooth_hid_address]).
+/// hidapi only knows interface numbers for USB devices, so anything without
+/// one is taken to be Bluetooth.
+fn hid_address(path: &str, interface_number: i32) -> String {
+  if interface_number < 0 && !is_bluetooth_hid_address(path) {
+    format!("{}#{{{}}}", path, BLUETOOTH_HID_SERVICE_UUID)
+  } else {
+    path.to_owned()
+  }
+}
+
+/// Picks out the interfaces to emit. Controllers with more than one HID
+/// interface (the Steam Controller also shows up as a keyboard and a mouse)
+/// take commands on their vendor defined one, so if a vendor/product pair has
+/// any of those, the rest are skipped.
+fn command_interfaces(interfaces: Vec<HidInterface>) -> Vec<HidInterface> {
+  let has_vendor_interface: HashSet<(u16, u16)> = interfaces
+    .iter()
+    .filter(|interface| interface.usage_page >= VENDOR_USAGE_PAGE)
+    .map(|interface| (interface.vendor_id, interface.product_id))
+    .collect();
+  interfaces
+    .into_iter()
+    .filter(|interface| {
+      interface.usage_page >= VENDOR_USAGE_PAGE
+        || !has_vendor_interface.contains(&(interface.vendor_id, interface.product_id))
+    })
+    .collect()
+}
+
+#[derive(Default)]
+pub struct HidDeviceCommunicationManagerBuilder {
+  sender: Option<tokio::sync::mpsc::Sender<DeviceCommunicationEvent>>,
+  device_config: Option<Arc<DeviceConfigurationManager>>,
+}
+
+impl DeviceCommunicationManagerBuilder for HidDeviceCommunicationManagerBuilder {
+  fn set_event_sender(&mut self, sender: mpsc::Sender<DeviceCommunicationEvent>) {
+    self.sender = Some(sender)
+  }
+
+  fn set_device_configuration(&mut self, config: Arc<DeviceConfigurationManager>) {
+    self.device_config = Some(config)
+  }
+
+  fn finish(mut self) -> Box<dyn DeviceCommunicationManager> {
+    Box::new(HidDeviceCommunicationManager::new(
+      self.sender.take().unwrap(),
+      self.device_config.take(),
+    ))
+  }
+}
+
+/// Finds devices that are configured by HID vendor/product id (game
+/// controllers, mostly), over USB or Bluetooth.
+pub struct HidDeviceCommunicationManager {
+  sender: mpsc::Sender<DeviceCommunicationEvent>,
+  device_config: Option<Arc<DeviceConfigurationManager>>,
+  scanning_notifier: Arc<Notify>,
+  connected_devices: HidConnectionTracker,
+}
+
+impl HidDeviceCommunicationManager {
+  fn new(
+    sender: mpsc::Sender<DeviceCommunicationEvent>,
+    device_config: Option<Arc<DeviceConfigurationManager>>,
+  ) -> Self {
+    Self {
+      sender,
+      device_config,
+      scanning_notifier: Arc::new(Notify::new()),
+      connected_devices: Arc::new(DashSet::new()),
+    }
+  }
+}
+
+impl DeviceCommunicationManager for HidDeviceCommunicationManager {
+  fn name(&self) -> &'static str {
+    "HidDeviceCommunicationManager"
+  }
+
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    preflight::hid_checks()
+  }
+
+  fn start_scanning(&self) -> ButtplugResultFuture {
+    debug!("HID manager scanning for devices");
+    let sender = self.sender.clone();
+    let device_config = self.device_config.clone();
+    let scanning_notifier = self.scanning_notifier.clone();
+    let connected_devices = self.connected_devices.clone();
+    async_manager::spawn_named("HID Scanning Loop", async move {
+      let mut stop = false;
+      while !stop {
+        // hidapi doesn't do plug n' play events either, so poll while
+        // scanning, same as XInput and evdev.
+        let interfaces = match with_hid_api(|api| {
+          api
+            .device_list()
+            .map(HidInterface::new)
+            .collect::<Vec<HidInterface>>()
+        }) {
+          Ok(interfaces) => interfaces,
+          Err(err) => {
+            error!("Cannot enumerate HID devices: {}", err);
+            vec![]
+          }
+        };
+        for interface in command_interfaces(interfaces) {
+          if connected_devices.contains(&interface.address) {
+            trace!("HID device {} already found, ignoring.", interface.address);
+            continue;
+          }
+          // Every keyboard and mouse is a HID device too, so only bother the
+          // device manager with the ones we have a configuration for.
+          let specifier =
+            DeviceSpecifier::HID(HIDSpecifier::new(interface.vendor_id, interface.product_id));
+          if let Some(config) = &device_config {
+            if config.find_configuration(&specifier).is_none() {
+              continue;
+            }
+          }
+          info!(
+            "HID manager found device {} ({:04x}:{:04x})",
+            interface.name, interface.vendor_id, interface.product_id
+          );
+          connected_devices.insert(interface.address.clone());
+          let metadata = DeviceMetadata::new(DeviceTransport::HID)
+            .with_usb_ids(interface.vendor_id, interface.product_id);
+          let device_creator = Box::new(HidDeviceImplCreator::new(
+            &interface.name,
+            &interface.address,
+            interface.path,
+            specifier,
+            connected_devices.clone(),
+          ));
+          if sender
+            .send(DeviceCommunicationEvent::DeviceFound {
+              name: interface.name,
+              address: interface.address,
+              creator: device_creator,
+              metadata,
+            })
+            .await
+            .is_err()
+          {
+            error!("Error sending device found message from HID manager.");
+            break;
+          }
+        }
+        // Wait for either one second, or until our notifier has been notified.
+        select! {
+          _ = Delay::new(Duration::from_secs(1)).fuse() => {},
+          _ = scanning_notifier.notified().fuse() => {
+            debug!("HID stop scanning notifier notified, ending scanning loop");
+            stop = true;
+          }
+        }
+      }
+    })
+    .unwrap();
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn stop_scanning(&self) -> ButtplugResultFuture {
+    debug!("HID device comm manager received Stop Scanning request");
+    // The scanning loop spends most of its time enumerating, not waiting on
+    // the notifier, so leave a permit instead of only waking current waiters.
+    self.scanning_notifier.notify_one();
+    let sender = self.sender.clone();
+    Box::pin(async move {
+      if sender
+        .send(DeviceCommunicationEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished from HID manager.");
+      }
+      Ok(())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn interface(product_id: u16, usage_page: u16) -> HidInterface {
+    HidInterface {
+      path: CString::new(format!("/dev/hidraw{}", usage_page)).unwrap(),
+      address: format!("/dev/hidraw{}", usage_page),
+      vendor_id: 0x28de,
+      product_id,
+      name: "Test".to_owned(),
+      usage_page,
+    }
+  }
+
+  #[test]
+  fn test_hid_address() {
+    assert_eq!(hid_address("/dev/hidraw3", 0), "/dev/hidraw3");
+    let bluetooth = hid_address("/dev/hidraw3", -1);
+    assert!(is_bluetooth_hid_address(&bluetooth));
+    // Windows paths already say, so are left alone.
+    let windows_path = "\\\\?\\hid#{00001124-0000-1000-8000-00805f9b34fb}_vid&0002054c";
+    assert_eq!(hid_address(windows_path, -1), windows_path);
+  }
+
+  #[test]
+  fn test_command_interfaces() {
+    let interfaces = command_interfaces(vec![
+      interface(0x1102, 0x0001),
+      interface(0x1102, 0xff00),
+      interface(0x1205, 0x0001),
+    ]);
+    let found: Vec<(u16, u16)> = interfaces
+      .iter()
+      .map(|interface| (interface.product_id, interface.usage_page))
+      .collect();
+    assert_eq!(found, vec![(0x1102, 0xff00), (0x1205, 0x0001)]);
+  }
+}
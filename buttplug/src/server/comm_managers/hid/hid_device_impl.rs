@@ -0,0 +1,376 @@
+use super::hid_device_comm_manager::HidConnectionTracker;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugErrorCause},
+    messages::RawReading,
+    ButtplugResultFuture,
+  },
+  device::{
+    configuration_manager::{DeviceSpecifier, ProtocolDefinition},
+    ButtplugDeviceEvent, ButtplugDeviceImplCreator, DeviceImpl, DeviceImplInternal, DeviceReadCmd,
+    DeviceSubscribeCmd, DeviceUnsubscribeCmd, DeviceWriteCmd, Endpoint,
+  },
+  server::comm_managers::{hidapi_context::with_hid_api, ButtplugDeviceSpecificError},
+  util::logging::redact_address,
+};
+use async_trait::async_trait;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+};
+use hidapi::{HidDevice, HidError, HidResult};
+use std::{
+  ffi::CString,
+  fmt::{self, Debug},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread,
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+// Big enough for any input report we know of (Bluetooth controller reports
+// are the largest, at 78 bytes).
+const MAX_REPORT_SIZE: usize = 256;
+// How long the I/O thread waits for an input report before checking for
+// requests again, while subscribed.
+const SUBSCRIBED_READ_TIMEOUT_MS: i32 = 10;
+
+fn hid_error(err: HidError) -> ButtplugError {
+  ButtplugDeviceError::from(ButtplugDeviceSpecificError::HidError(
+    ButtplugErrorCause::new(err),
+  ))
+  .into()
+}
+
+fn io_thread_gone() -> ButtplugError {
+  ButtplugDeviceError::DeviceNotConnected("HID device I/O thread has exited.".to_owned()).into()
+}
+
+pub struct HidDeviceImplCreator {
+  name: String,
+  address: String,
+  path: CString,
+  specifier: DeviceSpecifier,
+  connected_devices: HidConnectionTracker,
+}
+
+impl HidDeviceImplCreator {
+  pub fn new(
+    name: &str,
+    address: &str,
+    path: CString,
+    specifier: DeviceSpecifier,
+    connected_devices: HidConnectionTracker,
+  ) -> Self {
+    debug!("Emitting a new HID device impl creator!");
+    Self {
+      name: name.to_owned(),
+      address: address.to_owned(),
+      path,
+      specifier,
+      connected_devices,
+    }
+  }
+}
+
+impl Debug for HidDeviceImplCreator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("HidDeviceImplCreator")
+      .field("name", &self.name)
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl ButtplugDeviceImplCreator for HidDeviceImplCreator {
+  fn get_specifier(&self) -> DeviceSpecifier {
+    self.specifier.clone()
+  }
+
+  async fn try_create_device_impl(
+    &mut self,
+    _protocol: ProtocolDefinition,
+  ) -> Result<DeviceImpl, ButtplugError> {
+    debug!("Emitting a new HID device impl.");
+    let device = match with_hid_api(|api| api.open_path(&self.path)).and_then(|device| device) {
+      Ok(device) => device,
+      Err(err) => {
+        self.connected_devices.remove(&self.address);
+        return Err(hid_error(err));
+      }
+    };
+    let device_impl_internal =
+      HidDeviceImpl::new(device, &self.address, self.connected_devices.clone());
+    let device_impl = DeviceImpl::new(
+      &self.name,
+      &self.address,
+      &[Endpoint::Tx, Endpoint::Rx, Endpoint::Command],
+      Box::new(device_impl_internal),
+    );
+    Ok(device_impl)
+  }
+}
+
+/// Work for a device's I/O thread.
+enum HidIoRequest {
+  Write {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    reply: oneshot::Sender<HidResult<()>>,
+  },
+  Read {
+    timeout_ms: i32,
+    reply: oneshot::Sender<HidResult<Vec<u8>>>,
+  },
+  SetSubscribed(bool),
+}
+
+/// Handles a request on the I/O thread, replying with the result. Returns
+/// false if the device failed.
+fn handle_io_request(
+  device: &HidDevice,
+  request: HidIoRequest,
+  subscribed: &mut bool,
+  report: &mut [u8],
+) -> bool {
+  match request {
+    HidIoRequest::Write {
+      endpoint,
+      data,
+      reply,
+    } => {
+      let result = match endpoint {
+        Endpoint::Command => device.send_feature_report(&data),
+        _ => device.write(&data).map(|_| ()),
+      };
+      let ok = result.is_ok();
+      let _ = reply.send(result);
+      ok
+    }
+    HidIoRequest::Read { timeout_ms, reply } => {
+      let result = device
+        .read_timeout(report, timeout_ms)
+        .map(|len| report[..len].to_vec());
+      let ok = result.is_ok();
+      let _ = reply.send(result);
+      ok
+    }
+    HidIoRequest::SetSubscribed(value) => {
+      *subscribed = value;
+      true
+    }
+  }
+}
+
+/// Owns the hidapi handle for a device. hidapi calls block, so they're all
+/// made here instead of on the async runtime. Input reports are only read
+/// while subscribed, and are sent out as notifications on Rx. Exits when the
+/// device impl is dropped, or the device fails (most likely it was unplugged
+/// or turned off).
+fn hid_io_thread(
+  device: HidDevice,
+  address: String,
+  mut requests: mpsc::Receiver<HidIoRequest>,
+  event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+  connected: Arc<AtomicBool>,
+) {
+  let mut subscribed = false;
+  let mut report = [0u8; MAX_REPORT_SIZE];
+  loop {
+    // While subscribed, check for requests between reads instead of waiting
+    // on them.
+    let request = if subscribed {
+      match requests.recv().now_or_never() {
+        Some(Some(request)) => Some(request),
+        Some(None) => return,
+        None => None,
+      }
+    } else {
+      match requests.blocking_recv() {
+        Some(request) => Some(request),
+        None => return,
+      }
+    };
+    if let Some(request) = request {
+      if !handle_io_request(&device, request, &mut subscribed, &mut report) {
+        break;
+      }
+    }
+    if subscribed {
+      match device.read_timeout(&mut report, SUBSCRIBED_READ_TIMEOUT_MS) {
+        Ok(0) => {}
+        Ok(len) => {
+          // Nobody listening isn't an error, the device may not have been
+          // added yet.
+          let _ = event_sender.send(ButtplugDeviceEvent::Notification(
+            address.clone(),
+            Endpoint::Rx,
+            report[..len].to_vec(),
+          ));
+        }
+        Err(err) => {
+          error!(
+            "HID device {} read failed: {}",
+            redact_address(&address),
+            err
+          );
+          break;
+        }
+      }
+    }
+  }
+  if connected.swap(false, Ordering::SeqCst) {
+    let _ = event_sender.send(ButtplugDeviceEvent::Removed(address));
+  }
+}
+
+/// A HID device, opened through hidapi. Writes to Tx are sent as output
+/// reports, and writes to Command as feature reports. Either way, the first
+/// byte is the report id (0 for devices that don't number their reports).
+/// Input reports are read from Rx, either one at a time or, once subscribed,
+/// as notifications.
+pub struct HidDeviceImpl {
+  address: String,
+  requests: mpsc::Sender<HidIoRequest>,
+  connected: Arc<AtomicBool>,
+  connected_devices: HidConnectionTracker,
+  event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+}
+
+impl HidDeviceImpl {
+  fn new(device: HidDevice, address: &str, connected_devices: HidConnectionTracker) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let (requests, request_receiver) = mpsc::channel(256);
+    let connected = Arc::new(AtomicBool::new(true));
+    let thread_address = address.to_owned();
+    let thread_event_sender = event_sender.clone();
+    let thread_connected = connected.clone();
+    thread::Builder::new()
+      .name("HID Device I/O Thread".to_string())
+      .spawn(move || {
+        hid_io_thread(
+          device,
+          thread_address,
+          request_receiver,
+          thread_event_sender,
+          thread_connected,
+        )
+      })
+      .unwrap();
+    Self {
+      address: address.to_owned(),
+      requests,
+      connected,
+      connected_devices,
+      event_sender,
+    }
+  }
+
+  fn set_subscribed(&self, endpoint: Endpoint, subscribed: bool) -> ButtplugResultFuture {
+    if endpoint != Endpoint::Rx {
+      return Box::pin(future::ready(Err(
+        ButtplugDeviceError::InvalidEndpoint(endpoint).into(),
+      )));
+    }
+    let requests = self.requests.clone();
+    Box::pin(async move {
+      requests
+        .send(HidIoRequest::SetSubscribed(subscribed))
+        .await
+        .map_err(|_| io_thread_gone())
+    })
+  }
+}
+
+impl Drop for HidDeviceImpl {
+  fn drop(&mut self) {
+    // Let the device be found again on the next scan. The I/O thread exits,
+    // closing the device, once the request sender is dropped.
+    self.connected_devices.remove(&self.address);
+  }
+}
+
+impl DeviceImplInternal for HidDeviceImpl {
+  fn event_stream(&self) -> broadcast::Receiver<ButtplugDeviceEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn connected(&self) -> bool {
+    self.connected.load(Ordering::SeqCst)
+  }
+
+  fn disconnect(&self) -> ButtplugResultFuture {
+    // The device is closed when the impl is dropped.
+    self.connected.store(false, Ordering::SeqCst);
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn read_value(
+    &self,
+    msg: DeviceReadCmd,
+  ) -> BoxFuture<'static, Result<RawReading, ButtplugError>> {
+    if msg.endpoint != Endpoint::Rx {
+      return Box::pin(future::ready(Err(
+        ButtplugDeviceError::InvalidEndpoint(msg.endpoint).into(),
+      )));
+    }
+    let requests = self.requests.clone();
+    Box::pin(async move {
+      let (reply, reply_receiver) = oneshot::channel();
+      // hidapi takes -1 to mean no timeout, which is what 0 means here.
+      let timeout_ms = if msg.timeout_ms == 0 {
+        -1
+      } else {
+        msg.timeout_ms as i32
+      };
+      requests
+        .send(HidIoRequest::Read { timeout_ms, reply })
+        .await
+        .map_err(|_| io_thread_gone())?;
+      let data = reply_receiver
+        .await
+        .map_err(|_| io_thread_gone())?
+        .map_err(hid_error)?;
+      Ok(RawReading::new(0, Endpoint::Rx, data))
+    })
+  }
+
+  fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture {
+    if msg.endpoint != Endpoint::Tx && msg.endpoint != Endpoint::Command {
+      return Box::pin(future::ready(Err(
+        ButtplugDeviceError::InvalidEndpoint(msg.endpoint).into(),
+      )));
+    }
+    let requests = self.requests.clone();
+    let address = self.address.clone();
+    Box::pin(async move {
+      let (reply, reply_receiver) = oneshot::channel();
+      requests
+        .send(HidIoRequest::Write {
+          endpoint: msg.endpoint,
+          data: msg.data,
+          reply,
+        })
+        .await
+        .map_err(|_| io_thread_gone())?;
+      reply_receiver
+        .await
+        .map_err(|_| io_thread_gone())?
+        .map_err(|err| {
+          error!("HID device {} write failed: {}", redact_address(&address), err);
+          hid_error(err)
+        })
+    })
+  }
+
+  fn subscribe(&self, msg: DeviceSubscribeCmd) -> ButtplugResultFuture {
+    self.set_subscribed(msg.endpoint, true)
+  }
+
+  fn unsubscribe(&self, msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture {
+    self.set_subscribed(msg.endpoint, false)
+  }
+}
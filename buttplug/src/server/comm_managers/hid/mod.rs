@@ -0,0 +1,6 @@
+mod hid_device_comm_manager;
+mod hid_device_impl;
+
+pub use hid_device_comm_manager::{
+  HidDeviceCommunicationManager, HidDeviceCommunicationManagerBuilder,
+};
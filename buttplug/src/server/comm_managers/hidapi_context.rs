@@ -0,0 +1,31 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The process wide hidapi context.
+//!
+//! hidapi only allows one context at a time, and every device opened through
+//! it keeps it alive, so comm managers can't each make their own (the second
+//! one fails to initialize). Anything that talks to HID devices goes through
+//! [with_hid_api] instead.
+
+use hidapi::{HidApi, HidResult};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+static HID_API: Lazy<Mutex<Option<HidApi>>> = Lazy::new(|| Mutex::new(None));
+
+/// Runs `f` with the hidapi context, creating it if this is the first time
+/// it's needed. The context's device list is refreshed first, so `f` sees
+/// whatever's plugged in right now.
+pub fn with_hid_api<T>(f: impl FnOnce(&HidApi) -> T) -> HidResult<T> {
+  let mut api = HID_API.lock().expect("hidapi context lock poisoned");
+  match api.as_mut() {
+    Some(api) => api.refresh_devices()?,
+    None => *api = Some(HidApi::new()?),
+  }
+  Ok(f(api.as_ref().expect("hidapi context was just created")))
+}
@@ -1,6 +1,9 @@
 use super::lovense_connect_service_device_impl::LovenseServiceDeviceImplCreator;
 use crate::{
-  core::ButtplugResultFuture,
+  core::{
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
   server::comm_managers::{
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
   },
@@ -124,6 +127,7 @@ async fn lovense_local_service_check(
                 name: toy.name.clone(),
                 address: toy.id.clone(),
                 creator: device_creator,
+                metadata: DeviceMetadata::new(DeviceTransport::LovenseConnect),
               })
               .await
               .is_err()
@@ -192,7 +196,7 @@ impl DeviceCommunicationManager for LovenseConnectServiceCommunicationManager {
     let is_scanning = self.is_scanning.clone();
     let known_hosts = self.known_hosts.clone();
     let has_known_hosts = self.has_known_hosts.clone();
-    async_manager::spawn(async move {
+    async_manager::spawn_named("Lovense Connect Scanning Loop", async move {
       debug!("Starting scanning");
       while is_scanning.load(Ordering::SeqCst) {
         match reqwest::get("https://api.lovense.com/api/lan/getToys").await {
@@ -23,7 +23,10 @@ use super::lovense_connect_service_comm_manager::LovenseServiceToyInfo;
 use async_trait::async_trait;
 use futures::future::{self, BoxFuture};
 use std::{
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
   fmt::{self, Debug},
   time::Duration
 };
@@ -82,6 +85,7 @@ pub struct LovenseServiceDeviceImpl {
   toy_info: Arc<RwLock<LovenseServiceToyInfo>>,
   toy_name: String,
   toy_id: String,
+  connected: Arc<AtomicBool>,
 }
 
 impl LovenseServiceDeviceImpl {
@@ -90,10 +94,13 @@ impl LovenseServiceDeviceImpl {
     let toy_info_clone = toy_info.clone();
     let sender_clone = device_event_sender.clone();
     let toy_id_clone = toy_id.to_owned().clone();
+    let connected = Arc::new(AtomicBool::new(true));
+    let connected_clone = connected.clone();
     async_manager::spawn(async move {
       while toy_info_clone.read().await.connected {
         Delay::new(Duration::from_secs(1)).await;
       }
+      connected_clone.store(false, Ordering::SeqCst);
       let _ = sender_clone.send(ButtplugDeviceEvent::Removed(toy_id_clone));
       info!("Exiting lovense service device connection check loop.");
     }).unwrap();
@@ -103,6 +110,7 @@ impl LovenseServiceDeviceImpl {
       toy_info,
       toy_name: toy_name.to_owned(),
       toy_id: toy_id.to_owned(),
+      connected,
     }
   }
 }
@@ -113,7 +121,7 @@ impl DeviceImplInternal for LovenseServiceDeviceImpl {
   }
 
   fn connected(&self) -> bool {
-    true
+    self.connected.load(Ordering::SeqCst)
   }
 
   fn disconnect(&self) -> ButtplugResultFuture {
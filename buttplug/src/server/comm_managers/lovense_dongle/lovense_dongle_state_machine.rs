@@ -1,5 +1,8 @@
 use super::{lovense_dongle_device_impl::*, lovense_dongle_messages::*};
-use crate::server::comm_managers::DeviceCommunicationEvent;
+use crate::{
+  core::messages::{DeviceMetadata, DeviceTransport},
+  server::comm_managers::DeviceCommunicationEvent,
+};
 use async_trait::async_trait;
 use futures::{select, FutureExt};
 use std::sync::{
@@ -615,6 +618,7 @@ impl LovenseDongleState for LovenseDongleDeviceLoop {
           device_write_sender,
           device_read_receiver,
         )),
+        metadata: DeviceMetadata::new(DeviceTransport::LovenseDongle),
       })
       .await;
     loop {
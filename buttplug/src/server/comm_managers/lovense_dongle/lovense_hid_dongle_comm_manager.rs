@@ -7,12 +7,14 @@ use super::{
 use crate::{
   core::{errors::ButtplugDeviceError, ButtplugResultFuture},
   server::comm_managers::{
+    hidapi_context::with_hid_api,
+    preflight::{self, PermissionCheck},
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
   },
   util::async_manager,
 };
 use futures::FutureExt;
-use hidapi::{HidApi, HidDevice};
+use hidapi::HidDevice;
 use serde_json::Deserializer;
 use std::{
   sync::{
@@ -163,7 +165,8 @@ impl LovenseHIDDongleCommunicationManager {
       thread_cancellation_token: CancellationToken::new(),
     };
     let dongle_fut = mgr.find_dongle();
-    async_manager::spawn(
+    async_manager::spawn_named(
+      "Lovense HID Dongle Finder",
       async move {
         let _ = dongle_fut.await;
       }
@@ -172,7 +175,8 @@ impl LovenseHIDDongleCommunicationManager {
     .unwrap();
     let mut machine =
       create_lovense_dongle_machine(event_sender, machine_receiver, mgr.is_scanning.clone());
-    async_manager::spawn(
+    async_manager::spawn_named(
+      "Lovense HID Dongle State Machine",
       async move {
         while let Some(next) = machine.transition().await {
           machine = next;
@@ -197,19 +201,23 @@ impl LovenseHIDDongleCommunicationManager {
     Box::pin(async move {
       let (writer_sender, writer_receiver) = channel(256);
       let (reader_sender, reader_receiver) = channel(256);
-      let api = HidApi::new().map_err(|_| {
-        // This may happen if we create a new server in the same process?
-        error!("Failed to create HIDAPI instance. Was one already created?");
+      // The dongle gets opened twice, once for the reader and once for the
+      // writer.
+      let (dongle1, dongle2) = with_hid_api(|api| {
+        let open_dongle = || {
+          api.open(0x1915, 0x520a).map_err(|_| {
+            warn!("Cannot find lovense HID dongle.");
+            ButtplugDeviceError::DeviceConnectionError(
+              "Cannot find lovense HID Dongle.".to_owned(),
+            )
+          })
+        };
+        Ok::<_, ButtplugDeviceError>((open_dongle()?, open_dongle()?))
+      })
+      .map_err(|_| {
+        error!("Failed to create HIDAPI instance.");
         ButtplugDeviceError::DeviceConnectionError("Cannot create HIDAPI.".to_owned())
-      })?;
-      let dongle1 = api.open(0x1915, 0x520a).map_err(|_| {
-        warn!("Cannot find lovense HID dongle.");
-        ButtplugDeviceError::DeviceConnectionError("Cannot find lovense HID Dongle.".to_owned())
-      })?;
-      let dongle2 = api.open(0x1915, 0x520a).map_err(|_| {
-        warn!("Cannot find lovense HID dongle.");
-        ButtplugDeviceError::DeviceConnectionError("Cannot find lovense HID Dongle.".to_owned())
-      })?;
+      })??;
 
       let read_thread = thread::Builder::new()
         .name("Lovense Dongle HID Reader Thread".to_string())
@@ -249,6 +257,10 @@ impl DeviceCommunicationManager for LovenseHIDDongleCommunicationManager {
     "LovenseHIDDongleCommunicationManager"
   }
 
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    preflight::hid_checks()
+  }
+
   fn start_scanning(&self) -> ButtplugResultFuture {
     debug!("Lovense Dongle Manager scanning for devices");
     let sender = self.machine_sender.clone();
@@ -7,6 +7,7 @@ use super::{
 use crate::{
   core::ButtplugResultFuture,
   server::comm_managers::{
+    preflight::{self, PermissionCheck},
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
   },
   util::async_manager,
@@ -148,7 +149,7 @@ impl LovenseSerialDongleCommunicationManager {
     };
     let dongle_fut = mgr.find_dongle();
     // TODO If we don't find a dongle before scanning, what happens?
-    async_manager::spawn(async move {
+    async_manager::spawn_named("Lovense Serial Dongle Finder", async move {
       if let Err(err) = dongle_fut.await {
         error!("Error finding serial dongle: {:?}", err);
       }
@@ -156,7 +157,8 @@ impl LovenseSerialDongleCommunicationManager {
     .unwrap();
     let mut machine =
       create_lovense_dongle_machine(event_sender, machine_receiver, mgr.is_scanning.clone());
-    async_manager::spawn(
+    async_manager::spawn_named(
+      "Lovense Serial Dongle State Machine",
       async move {
         while let Some(next) = machine.transition().await {
           machine = next;
@@ -254,6 +256,10 @@ impl DeviceCommunicationManager for LovenseSerialDongleCommunicationManager {
     "LovenseSerialDongleCommunicationManager"
   }
 
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    preflight::serial_port_checks()
+  }
+
   fn start_scanning(&self) -> ButtplugResultFuture {
     debug!("Lovense Dongle Manager scanning for devices.");
     let sender = self.machine_sender.clone();
@@ -1,18 +1,39 @@
 #[cfg(feature = "btleplug-manager")]
 pub mod btleplug;
+#[cfg(feature = "hid-manager")]
+pub mod hid;
+#[cfg(any(feature = "hid-manager", feature = "lovense-dongle-manager"))]
+mod hidapi_context;
 #[cfg(feature = "lovense-dongle-manager")]
 pub mod lovense_dongle;
 #[cfg(feature = "serial-manager")]
 pub mod serialport;
 #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
 pub mod xinput;
+#[cfg(all(feature = "evdev-manager", target_os = "linux"))]
+pub mod evdev;
 #[cfg(feature = "lovense-connect-service-manager")]
 pub mod lovense_connect_service;
+pub mod preflight;
 
-use crate::{core::ButtplugResultFuture, device::ButtplugDeviceImplCreator};
+#[cfg(any(
+  feature = "btleplug-manager",
+  feature = "serial-manager",
+  feature = "hid-manager",
+  all(feature = "evdev-manager", target_os = "linux")
+))]
+use crate::core::errors::ButtplugErrorCause;
+use crate::{
+  core::{errors::ButtplugDeviceError, messages::DeviceMetadata, ButtplugResultFuture},
+  device::{configuration_manager::DeviceConfigurationManager, ButtplugDeviceImplCreator},
+};
+use preflight::PermissionCheck;
 use serde::{Deserialize, Serialize};
-use std::sync::{atomic::AtomicBool, Arc};
-use thiserror::Error;
+use std::{
+  error::Error,
+  fmt,
+  sync::{atomic::AtomicBool, Arc},
+};
 use tokio::sync::mpsc::Sender;
 
 #[derive(Debug)]
@@ -23,14 +44,28 @@ pub enum DeviceCommunicationEvent {
     name: String,
     address: String,
     creator: Box<dyn ButtplugDeviceImplCreator>,
+    /// Whatever the comm manager knows about the device without connecting
+    /// to it. Passed along to clients in DeviceAdded.
+    metadata: DeviceMetadata,
   },
-  DeviceManagerAdded(Arc<AtomicBool>),
-  ScanningStarted,
   ScanningFinished,
+  /// Something went wrong in the comm manager that isn't tied to a device
+  /// request (i.e. a bluetooth adapter went away). The comm manager keeps
+  /// running, and will try to recover where it can.
+  Error(ButtplugDeviceError),
+  /// The adapter/dongle the comm manager uses is gone (unplugged, powered
+  /// off, etc). Every device the comm manager found is treated as
+  /// disconnected.
+  AdapterRemoved(ButtplugDeviceError),
 }
 
 pub trait DeviceCommunicationManagerBuilder: Send {
   fn set_event_sender(&mut self, sender: Sender<DeviceCommunicationEvent>);
+  /// Gives the comm manager the device configuration the server uses, for
+  /// comm managers that can narrow down what they look for (i.e. BLE scan
+  /// filters, or HID, where every keyboard and mouse would show up otherwise).
+  /// Called before finish().
+  fn set_device_configuration(&mut self, _config: Arc<DeviceConfigurationManager>) {}
   fn finish(self) -> Box<dyn DeviceCommunicationManager>;
 }
 
@@ -41,20 +76,67 @@ pub trait DeviceCommunicationManager: Send + Sync {
   fn scanning_status(&self) -> Arc<AtomicBool> {
     Arc::new(AtomicBool::new(false))
   }
+  /// Checks the OS permissions the comm manager needs to find devices. See
+  /// [preflight]. Comm managers that don't need any return nothing.
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    vec![]
+  }
   // Events happen via channel senders passed to the comm manager.
 }
 
-#[derive(Error, Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ButtplugDeviceSpecificError {
   // XInput library doesn't derive error on its error enum. :(
   #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
-  #[error("XInput usage error: {0}")]
   XInputError(String),
-  // Btleplug library uses Failure, not Error, on its error enum. :(
+  // Btleplug, serialport, hidapi and evdev errors can't be serialized, so they're carried as
+  // causes to keep them available as the error source.
   #[cfg(feature = "btleplug-manager")]
-  #[error("Btleplug error: {0}")]
-  BtleplugError(String),
+  BtleplugError(ButtplugErrorCause),
   #[cfg(feature = "serial-manager")]
-  #[error("Serial error: {0}")]
-  SerialError(String),
+  SerialError(ButtplugErrorCause),
+  #[cfg(feature = "hid-manager")]
+  HidError(ButtplugErrorCause),
+  #[cfg(all(feature = "evdev-manager", target_os = "linux"))]
+  EvdevError(ButtplugErrorCause),
+}
+
+// Display and Error are implemented by hand so that source() hands back the
+// library error itself, instead of the ButtplugErrorCause holding it. Every
+// variant is feature gated, so the matches here need to stay in step with the
+// enum.
+impl fmt::Display for ButtplugDeviceSpecificError {
+  fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
+      ButtplugDeviceSpecificError::XInputError(ref err) => {
+        write!(_f, "XInput usage error: {}", err)
+      }
+      #[cfg(feature = "btleplug-manager")]
+      ButtplugDeviceSpecificError::BtleplugError(ref err) => write!(_f, "Btleplug error: {}", err),
+      #[cfg(feature = "serial-manager")]
+      ButtplugDeviceSpecificError::SerialError(ref err) => write!(_f, "Serial error: {}", err),
+      #[cfg(feature = "hid-manager")]
+      ButtplugDeviceSpecificError::HidError(ref err) => write!(_f, "HID error: {}", err),
+      #[cfg(all(feature = "evdev-manager", target_os = "linux"))]
+      ButtplugDeviceSpecificError::EvdevError(ref err) => write!(_f, "Evdev error: {}", err),
+    }
+  }
+}
+
+impl Error for ButtplugDeviceSpecificError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match *self {
+      #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
+      ButtplugDeviceSpecificError::XInputError(_) => None,
+      #[cfg(feature = "btleplug-manager")]
+      ButtplugDeviceSpecificError::BtleplugError(ref err) => err.error(),
+      #[cfg(feature = "serial-manager")]
+      ButtplugDeviceSpecificError::SerialError(ref err) => err.error(),
+      #[cfg(feature = "hid-manager")]
+      ButtplugDeviceSpecificError::HidError(ref err) => err.error(),
+      #[cfg(all(feature = "evdev-manager", target_os = "linux"))]
+      ButtplugDeviceSpecificError::EvdevError(ref err) => err.error(),
+    }
+  }
 }
@@ -0,0 +1,283 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Checks for the OS permissions comm managers need to find devices.
+//!
+//! When a comm manager can't see devices because the OS won't let it (no
+//! Bluetooth permission, no access to device nodes, etc), scanning usually
+//! just finds nothing. Checks from
+//! [ButtplugServer::permission_preflight][crate::server::ButtplugServer::permission_preflight]
+//! say what's missing, and what the user can do about it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// OS level permission a comm manager needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DevicePermission {
+  /// Access to the Bluetooth adapter (the Bluetooth privacy permission on
+  /// macOS, BlueZ on Linux).
+  Bluetooth,
+  /// Location permission, which Android requires for BLE scanning. Reported
+  /// by comm managers embedders add on Android.
+  Location,
+  /// Read/write access to HID device nodes (udev rules on Linux).
+  Hid,
+  /// Read/write access to serial ports (dialout group on most Linux
+  /// distributions).
+  Serial,
+  /// Read/write access to input device nodes (input group on Linux).
+  Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PermissionStatus {
+  Granted,
+  Denied,
+  /// Couldn't tell, i.e. there's no device to check against yet.
+  Unknown,
+}
+
+/// Result of checking one permission for a comm manager.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PermissionCheck {
+  /// Name of the comm manager that needs the permission. Filled in by the
+  /// server.
+  pub comm_manager: String,
+  pub permission: DevicePermission,
+  pub status: PermissionStatus,
+  /// What is wrong, when status isn't [PermissionStatus::Granted].
+  pub detail: Option<String>,
+  /// What the user can do to fix it, when status isn't
+  /// [PermissionStatus::Granted].
+  pub remedy: Option<String>,
+}
+
+impl PermissionCheck {
+  pub fn granted(permission: DevicePermission) -> Self {
+    Self {
+      comm_manager: String::new(),
+      permission,
+      status: PermissionStatus::Granted,
+      detail: None,
+      remedy: None,
+    }
+  }
+
+  pub fn denied(permission: DevicePermission, detail: &str, remedy: &str) -> Self {
+    Self {
+      comm_manager: String::new(),
+      permission,
+      status: PermissionStatus::Denied,
+      detail: Some(detail.to_owned()),
+      remedy: Some(remedy.to_owned()),
+    }
+  }
+
+  pub fn unknown(permission: DevicePermission, detail: &str) -> Self {
+    Self {
+      comm_manager: String::new(),
+      permission,
+      status: PermissionStatus::Unknown,
+      detail: Some(detail.to_owned()),
+      remedy: None,
+    }
+  }
+}
+
+impl fmt::Display for PermissionCheck {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} {:?} permission: {:?}",
+      self.comm_manager, self.permission, self.status
+    )?;
+    if let Some(detail) = &self.detail {
+      write!(f, " ({})", detail)?;
+    }
+    if let Some(remedy) = &self.remedy {
+      write!(f, ". {}", remedy)?;
+    }
+    Ok(())
+  }
+}
+
+/// Checks access to serial ports, for comm managers that talk to serial
+/// devices. Only Linux has anything to check.
+pub fn serial_port_checks() -> Vec<PermissionCheck> {
+  #[cfg(target_os = "linux")]
+  return vec![check_device_nodes(
+    DevicePermission::Serial,
+    &device_nodes("/dev", &["ttyUSB", "ttyACM"]),
+    "Add your user to the group that owns the serial ports (usually dialout or uucp), then log out and back in.",
+  )];
+  #[cfg(not(target_os = "linux"))]
+  vec![]
+}
+
+/// Checks access to HID devices, for comm managers that talk to HID devices.
+/// Only Linux has anything to check.
+pub fn hid_checks() -> Vec<PermissionCheck> {
+  #[cfg(target_os = "linux")]
+  return vec![check_device_nodes(
+    DevicePermission::Hid,
+    &device_nodes("/dev", &["hidraw"]),
+    "Add a udev rule giving your user access to the device's hidraw node (i.e. TAG+=\"uaccess\"), then replug the device.",
+  )];
+  #[cfg(not(target_os = "linux"))]
+  vec![]
+}
+
+/// Checks whether the user running the server can read and write each of
+/// `nodes` (i.e. `/dev/hidraw0`), going by file ownership and mode. Reports
+/// [PermissionStatus::Unknown] if there are no nodes to check, and
+/// [PermissionStatus::Denied] with `remedy` if any node is off limits.
+#[cfg(target_os = "linux")]
+pub fn check_device_nodes(
+  permission: DevicePermission,
+  nodes: &[std::path::PathBuf],
+  remedy: &str,
+) -> PermissionCheck {
+  use std::os::unix::fs::MetadataExt;
+  if nodes.is_empty() {
+    return PermissionCheck::unknown(permission, "no devices plugged in to check");
+  }
+  let credentials = match std::fs::read_to_string("/proc/self/status")
+    .ok()
+    .and_then(|status| parse_proc_status_credentials(&status))
+  {
+    Some(credentials) => credentials,
+    None => return PermissionCheck::unknown(permission, "cannot read process credentials"),
+  };
+  let denied: Vec<String> = nodes
+    .iter()
+    .filter(|node| match std::fs::metadata(node) {
+      Ok(metadata) => !mode_allows_read_write(
+        metadata.mode(),
+        metadata.uid(),
+        metadata.gid(),
+        &credentials,
+      ),
+      // Gone since we listed it, so nothing to worry about.
+      Err(_) => false,
+    })
+    .map(|node| node.display().to_string())
+    .collect();
+  if denied.is_empty() {
+    PermissionCheck::granted(permission)
+  } else {
+    PermissionCheck::denied(
+      permission,
+      &format!("cannot read and write {}", denied.join(", ")),
+      remedy,
+    )
+  }
+}
+
+/// Device nodes in `dir` whose names start with any of `prefixes`, i.e.
+/// `device_nodes("/dev", &["hidraw"])`.
+#[cfg(target_os = "linux")]
+pub fn device_nodes(dir: &str, prefixes: &[&str]) -> Vec<std::path::PathBuf> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return vec![],
+  };
+  entries
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| {
+      let name = entry.file_name();
+      let name = name.to_string_lossy();
+      prefixes.iter().any(|prefix| name.starts_with(prefix))
+    })
+    .map(|entry| entry.path())
+    .collect()
+}
+
+/// Effective user id and all group ids of a process, from its
+/// `/proc/<pid>/status`.
+#[cfg(target_os = "linux")]
+struct ProcessCredentials {
+  uid: u32,
+  groups: Vec<u32>,
+}
+
+#[cfg(target_os = "linux")]
+fn parse_proc_status_credentials(status: &str) -> Option<ProcessCredentials> {
+  // Uid and Gid lines are real, effective, saved and filesystem ids, in
+  // that order.
+  let ids = |key: &str| -> Option<Vec<u32>> {
+    let line = status.lines().find(|line| line.starts_with(key))?;
+    line[key.len()..]
+      .split_whitespace()
+      .map(|id| id.parse().ok())
+      .collect()
+  };
+  let uid = *ids("Uid:")?.get(1)?;
+  let mut groups = ids("Groups:").unwrap_or_default();
+  groups.push(*ids("Gid:")?.get(1)?);
+  Some(ProcessCredentials { uid, groups })
+}
+
+#[cfg(target_os = "linux")]
+fn mode_allows_read_write(
+  mode: u32,
+  file_uid: u32,
+  file_gid: u32,
+  credentials: &ProcessCredentials,
+) -> bool {
+  if credentials.uid == 0 {
+    return true;
+  }
+  let read_write = if credentials.uid == file_uid {
+    0o600
+  } else if credentials.groups.contains(&file_gid) {
+    0o060
+  } else {
+    0o006
+  };
+  mode & read_write == read_write
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+  use super::*;
+
+  const STATUS: &str = "Name:\tbuttplug\nUid:\t1000\t1000\t1000\t1000\nGid:\t1000\t1000\t1000\t1000\nGroups:\t4 20 1000\n";
+
+  #[test]
+  fn test_parse_proc_status_credentials() {
+    let credentials = parse_proc_status_credentials(STATUS).unwrap();
+    assert_eq!(credentials.uid, 1000);
+    assert!(credentials.groups.contains(&20));
+    assert!(credentials.groups.contains(&1000));
+    assert!(parse_proc_status_credentials("Name:\tbuttplug\n").is_none());
+  }
+
+  #[test]
+  fn test_mode_allows_read_write() {
+    let credentials = parse_proc_status_credentials(STATUS).unwrap();
+    // Root owned hidraw node, no udev rule.
+    assert!(!mode_allows_read_write(0o600, 0, 0, &credentials));
+    // Serial port owned by the dialout group (20), which the user is in.
+    assert!(mode_allows_read_write(0o660, 0, 20, &credentials));
+    // Input node in a group the user isn't in.
+    assert!(!mode_allows_read_write(0o660, 0, 104, &credentials));
+    // udev rule making the node world writable.
+    assert!(mode_allows_read_write(0o666, 0, 0, &credentials));
+    let root = ProcessCredentials {
+      uid: 0,
+      groups: vec![0],
+    };
+    assert!(mode_allows_read_write(0o600, 1000, 1000, &root));
+  }
+
+  #[test]
+  fn test_check_device_nodes_without_nodes() {
+    let check = check_device_nodes(DevicePermission::Hid, &[], "Add a udev rule.");
+    assert_eq!(check.status, PermissionStatus::Unknown);
+  }
+}
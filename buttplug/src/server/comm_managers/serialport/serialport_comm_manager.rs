@@ -1,12 +1,16 @@
 use super::SerialPortDeviceImplCreator;
 use crate::{
-  core::ButtplugResultFuture,
+  core::{
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
   server::comm_managers::{
+    preflight::{self, PermissionCheck},
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
   },
 };
 use futures::future;
-use serialport::available_ports;
+use serialport::{available_ports, SerialPortInfo, SerialPortType};
 use tokio::sync::mpsc::Sender;
 use tracing_futures::Instrument;
 
@@ -25,6 +29,14 @@ impl DeviceCommunicationManagerBuilder for SerialPortCommunicationManagerBuilder
   }
 }
 
+fn serial_port_metadata(port: &SerialPortInfo) -> DeviceMetadata {
+  let metadata = DeviceMetadata::new(DeviceTransport::Serial);
+  match &port.port_type {
+    SerialPortType::UsbPort(usb_info) => metadata.with_usb_ids(usb_info.vid, usb_info.pid),
+    _ => metadata,
+  }
+}
+
 pub struct SerialPortCommunicationManager {
   sender: Sender<DeviceCommunicationEvent>,
 }
@@ -41,6 +53,10 @@ impl DeviceCommunicationManager for SerialPortCommunicationManager {
     "SerialPortCommunicationManager"
   }
 
+  fn permission_checks(&self) -> Vec<PermissionCheck> {
+    preflight::serial_port_checks()
+  }
+
   fn start_scanning(&self) -> ButtplugResultFuture {
     debug!("Serial port manager scanning for devices.");
     // TODO Does this block? Should it run in one of our threads?
@@ -60,6 +76,7 @@ impl DeviceCommunicationManager for SerialPortCommunicationManager {
                   name: format!("Serial Port Device {}", p.port_name),
                   address: p.port_name.clone(),
                   creator: Box::new(SerialPortDeviceImplCreator::new(&p)),
+                  metadata: serial_port_metadata(&p),
                 })
                 .await
                 .is_err()
@@ -1,6 +1,6 @@
 use crate::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugErrorCause},
     messages::RawReading,
     ButtplugResultFuture,
   },
@@ -163,7 +163,7 @@ impl SerialPortDeviceImpl {
 
     let port = port_receiver.recv().await.unwrap().map_err(|e| {
       ButtplugError::from(ButtplugDeviceError::DeviceSpecificError(
-        ButtplugDeviceSpecificError::SerialError(e.to_string()),
+        ButtplugDeviceSpecificError::SerialError(ButtplugErrorCause::new(e)),
       ))
     })?;
     debug!("Serial port received from thread.");
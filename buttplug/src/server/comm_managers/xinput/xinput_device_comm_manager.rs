@@ -1,6 +1,9 @@
 use super::xinput_device_impl::XInputDeviceImplCreator;
 use crate::{
-  core::ButtplugResultFuture,
+  core::{
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
   device::ButtplugDeviceEvent,
   server::comm_managers::{
     DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
@@ -97,7 +100,7 @@ impl XInputConnectionTracker {
     if should_start {
       let connected_gamepads = self.connected_gamepads.clone();
       let check_running = self.check_running.clone();
-      async_manager::spawn(async move {
+      async_manager::spawn_named("XInput Connectivity Check", async move {
         check_gamepad_connectivity(connected_gamepads, check_running, None).await;
       })
       .unwrap();
@@ -170,7 +173,7 @@ impl DeviceCommunicationManager for XInputDeviceCommunicationManager {
     let sender = self.sender.clone();
     let scanning_notifier = self.scanning_notifier.clone();
     let connected_gamepads = self.connected_gamepads.clone();
-    async_manager::spawn(async move {
+    async_manager::spawn_named("XInput Scanning Loop", async move {
       let handle = rusty_xinput::XInputHandle::load_default().unwrap();
       let mut stop = false;
       while !stop {
@@ -195,6 +198,7 @@ impl DeviceCommunicationManager for XInputDeviceCommunicationManager {
                   name: i.to_string(),
                   address: i.to_string(),
                   creator: device_creator,
+                  metadata: DeviceMetadata::new(DeviceTransport::XInput),
                 })
                 .await
                 .is_err()
@@ -0,0 +1,176 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Protecting devices from clients that send commands too fast.
+//!
+//! A buggy client can end up sending hundreds of commands a second to a
+//! device, i.e. by updating it on every mouse move. Most devices can't keep
+//! up, and some BLE stacks fall over trying. With
+//! [ButtplugServerOptions::device_command_storm_guard][super::ButtplugServerOptions]
+//! set, the server counts commands to each device over one second windows.
+//! Commands past the limit fail with
+//! [DeviceCommandRateExceeded][crate::core::errors::ButtplugDeviceError::DeviceCommandRateExceeded]
+//! instead of going to the device, and a [DeviceCommandStorm] is sent out on
+//! [ButtplugServer::device_command_storms][super::ButtplugServer::device_command_storms]
+//! when a device first goes over.
+//!
+//! StopDeviceCmd is never throttled, and doesn't count against the limit.
+
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// Default for [DeviceCommandStormGuard::max_commands_per_second].
+pub const DEFAULT_MAX_DEVICE_COMMANDS_PER_SECOND: u32 = 200;
+
+const COMMAND_WINDOW: Duration = Duration::from_secs(1);
+
+/// Settings for throttling commands to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceCommandStormGuard {
+  /// Commands a device will take within a second before the rest are
+  /// rejected.
+  pub max_commands_per_second: u32,
+}
+
+impl Default for DeviceCommandStormGuard {
+  fn default() -> Self {
+    Self {
+      max_commands_per_second: DEFAULT_MAX_DEVICE_COMMANDS_PER_SECOND,
+    }
+  }
+}
+
+/// Sent when a client starts sending commands to a device faster than the
+/// [DeviceCommandStormGuard] allows. Only sent once per storm, another one
+/// goes out if the client calms down for a second and then starts again.
+#[derive(Debug, Clone)]
+pub struct DeviceCommandStorm {
+  /// Name the client gave in its handshake.
+  pub client_name: String,
+  pub device_index: u32,
+  pub device_name: String,
+  /// The limit that was hit.
+  pub max_commands_per_second: u32,
+}
+
+/// What to do with a command, according to [DeviceCommandRates::record].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DeviceCommandRate {
+  Allowed,
+  /// The command should be rejected. `storm_started` is true for the first
+  /// command rejected in a storm, meaning a [DeviceCommandStorm] needs to go
+  /// out.
+  Throttled { storm_started: bool },
+}
+
+struct CommandWindow {
+  started: Instant,
+  count: u32,
+  storming: bool,
+}
+
+/// Command counts for each device.
+pub(super) struct DeviceCommandRates {
+  guard: DeviceCommandStormGuard,
+  windows: DashMap<u32, CommandWindow>,
+}
+
+impl DeviceCommandRates {
+  pub fn new(guard: DeviceCommandStormGuard) -> Self {
+    Self {
+      guard,
+      windows: DashMap::new(),
+    }
+  }
+
+  pub fn max_commands_per_second(&self) -> u32 {
+    self.guard.max_commands_per_second
+  }
+
+  /// Counts a command sent to `device_index` at `now`.
+  pub fn record(&self, device_index: u32, now: Instant) -> DeviceCommandRate {
+    let mut window = self.windows.entry(device_index).or_insert_with(|| CommandWindow {
+      started: now,
+      count: 0,
+      storming: false,
+    });
+    if now.saturating_duration_since(window.started) >= COMMAND_WINDOW {
+      // A storm only ends once a whole window stays under the limit, so a
+      // steady flood doesn't send out a new warning every second.
+      if window.count <= self.guard.max_commands_per_second {
+        window.storming = false;
+      }
+      window.started = now;
+      window.count = 0;
+    }
+    window.count = window.count.saturating_add(1);
+    if window.count <= self.guard.max_commands_per_second {
+      return DeviceCommandRate::Allowed;
+    }
+    let storm_started = !window.storming;
+    window.storming = true;
+    DeviceCommandRate::Throttled { storm_started }
+  }
+
+  pub fn clear(&self) {
+    self.windows.clear();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_device_command_rates() {
+    let rates = DeviceCommandRates::new(DeviceCommandStormGuard {
+      max_commands_per_second: 3,
+    });
+    let start = Instant::now();
+    for _ in 0..3 {
+      assert_eq!(rates.record(1, start), DeviceCommandRate::Allowed);
+    }
+    assert_eq!(
+      rates.record(1, start),
+      DeviceCommandRate::Throttled {
+        storm_started: true
+      }
+    );
+    assert_eq!(
+      rates.record(1, start + Duration::from_millis(500)),
+      DeviceCommandRate::Throttled {
+        storm_started: false
+      }
+    );
+    // Other devices have their own limits.
+    assert_eq!(rates.record(2, start), DeviceCommandRate::Allowed);
+    // Flooding through the next window is still the same storm.
+    let next_window = start + Duration::from_secs(1);
+    for _ in 0..3 {
+      assert_eq!(rates.record(1, next_window), DeviceCommandRate::Allowed);
+    }
+    assert_eq!(
+      rates.record(1, next_window),
+      DeviceCommandRate::Throttled {
+        storm_started: false
+      }
+    );
+    // A quiet window ends it.
+    let quiet_window = start + Duration::from_secs(2);
+    assert_eq!(rates.record(1, quiet_window), DeviceCommandRate::Allowed);
+    let last_window = start + Duration::from_secs(3);
+    for _ in 0..3 {
+      assert_eq!(rates.record(1, last_window), DeviceCommandRate::Allowed);
+    }
+    assert_eq!(
+      rates.record(1, last_window),
+      DeviceCommandRate::Throttled {
+        storm_started: true
+      }
+    );
+  }
+}
@@ -0,0 +1,76 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Tracks where the server is in the client connection lifecycle, so we know
+//! which messages we're allowed to handle.
+
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Connection lifecycle states for a [ButtplugServer][super::ButtplugServer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[repr(u8)]
+pub enum ButtplugServerConnectionState {
+  /// No client has connected. Only RequestServerInfo will be accepted.
+  AwaitingHandshake,
+  /// RequestServerInfo has been received and is being processed.
+  Handshaking,
+  /// Handshake finished successfully, all messages will be accepted.
+  Connected,
+  /// Handshake failed. Nothing is accepted until the server is disconnected.
+  HandshakeRejected,
+}
+
+impl From<u8> for ButtplugServerConnectionState {
+  fn from(state: u8) -> Self {
+    match state {
+      0 => ButtplugServerConnectionState::AwaitingHandshake,
+      1 => ButtplugServerConnectionState::Handshaking,
+      2 => ButtplugServerConnectionState::Connected,
+      _ => ButtplugServerConnectionState::HandshakeRejected,
+    }
+  }
+}
+
+/// Atomic storage for [ButtplugServerConnectionState], so it can be shared
+/// with the futures the server hands back.
+pub(super) struct ConnectionState {
+  state: AtomicU8,
+}
+
+impl Default for ConnectionState {
+  fn default() -> Self {
+    Self {
+      state: AtomicU8::new(ButtplugServerConnectionState::AwaitingHandshake as u8),
+    }
+  }
+}
+
+impl ConnectionState {
+  pub fn get(&self) -> ButtplugServerConnectionState {
+    self.state.load(Ordering::SeqCst).into()
+  }
+
+  pub fn set(&self, state: ButtplugServerConnectionState) {
+    self.state.store(state as u8, Ordering::SeqCst);
+  }
+
+  /// Moves from `from` to `to`, returning false (and leaving the state alone)
+  /// if we weren't in `from` when this was called.
+  pub fn transition(
+    &self,
+    from: ButtplugServerConnectionState,
+    to: ButtplugServerConnectionState,
+  ) -> bool {
+    self
+      .state
+      .compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst)
+      .is_ok()
+  }
+}
@@ -0,0 +1,129 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hooks for changing device commands before they reach protocols.
+//!
+//! Transforms registered via
+//! [ButtplugServer::add_device_command_transform][super::ButtplugServer::add_device_command_transform]
+//! see every device command message the server routes to a device, in the
+//! order they were added, and can rewrite or reject it. This is the place for
+//! things like speed limits, response curves, logging, or remapping features,
+//! without having to touch every protocol.
+
+use crate::core::{
+  errors::ButtplugError,
+  messages::{
+    ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessage, ButtplugDeviceMessageType,
+    ButtplugMessage, DeviceMessageAttributesMap, SuctionCmd, SuctionSubcommand, TemperatureCmd,
+    TemperatureSubcommand, VibrateCmd, VibrateSubcommand,
+  },
+};
+
+/// Information about the device a command is headed to.
+pub struct DeviceCommandContext<'a> {
+  pub device_index: u32,
+  pub device_name: &'a str,
+  pub message_attributes: &'a DeviceMessageAttributesMap,
+}
+
+/// Rewrites device commands before they're sent to the device's protocol.
+pub trait DeviceCommandTransform: Send + Sync {
+  /// Returns the message to pass along, or an error to send back to the
+  /// client instead. Messages always go to the device in `context`, even if
+  /// the returned message has a different device index.
+  fn transform(
+    &self,
+    context: &DeviceCommandContext,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugError>;
+}
+
+/// Adds subcommands for any vibrators a VibrateCmd leaves out, using the
+/// speed of the last subcommand given. Registered when
+/// [ButtplugServerOptions::fill_missing_vibrate_subcommands][super::ButtplugServerOptions]
+/// is set.
+pub(super) struct FillMissingVibrateSubcommands;
+
+impl DeviceCommandTransform for FillMissingVibrateSubcommands {
+  fn transform(
+    &self,
+    context: &DeviceCommandContext,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugError> {
+    let msg = match message {
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => msg,
+      message => return Ok(message),
+    };
+    let vibrator_count = context
+      .message_attributes
+      .get(&ButtplugDeviceMessageType::VibrateCmd)
+      .and_then(|attrs| attrs.feature_count)
+      .unwrap_or(0);
+    let last_speed = match msg.speeds().last() {
+      Some(subcommand) => subcommand.speed(),
+      // Nothing to copy, so let the protocol complain about it.
+      None => return Ok(msg.into()),
+    };
+    if msg.speeds().len() >= vibrator_count as usize {
+      return Ok(msg.into());
+    }
+    let mut speeds = msg.speeds().clone();
+    for index in 0..vibrator_count {
+      if !speeds.iter().any(|subcommand| subcommand.index() == index) {
+        speeds.push(VibrateSubcommand::new(index, last_speed));
+      }
+    }
+    let mut filled = VibrateCmd::new(msg.device_index(), speeds);
+    filled.set_id(msg.id());
+    Ok(filled.into())
+  }
+}
+
+/// Lowers TemperatureCmd and SuctionCmd levels above the server's maximums
+/// to them. Registered when
+/// [ButtplugServerOptions::max_temperature_level][super::ButtplugServerOptions]
+/// or
+/// [ButtplugServerOptions::max_suction_level][super::ButtplugServerOptions]
+/// is below 1.0.
+pub(super) struct CapActuatorLevels {
+  pub max_temperature_level: f64,
+  pub max_suction_level: f64,
+}
+
+impl DeviceCommandTransform for CapActuatorLevels {
+  fn transform(
+    &self,
+    _context: &DeviceCommandContext,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugError> {
+    match message {
+      ButtplugDeviceCommandMessageUnion::TemperatureCmd(msg) => {
+        let levels = msg
+          .levels()
+          .iter()
+          .map(|cmd| {
+            TemperatureSubcommand::new(cmd.index(), cmd.level().min(self.max_temperature_level))
+          })
+          .collect();
+        let mut capped = TemperatureCmd::new(msg.device_index(), levels);
+        capped.set_id(msg.id());
+        Ok(capped.into())
+      }
+      ButtplugDeviceCommandMessageUnion::SuctionCmd(msg) => {
+        let levels = msg
+          .levels()
+          .iter()
+          .map(|cmd| SuctionSubcommand::new(cmd.index(), cmd.level().min(self.max_suction_level)))
+          .collect();
+        let mut capped = SuctionCmd::new(msg.device_index(), levels);
+        capped.set_id(msg.id());
+        Ok(capped.into())
+      }
+      message => Ok(message),
+    }
+  }
+}
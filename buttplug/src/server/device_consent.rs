@@ -0,0 +1,76 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Asking the local user before a client gets to control a device.
+//!
+//! With
+//! [ButtplugServerOptions::require_device_consent][super::ButtplugServerOptions]
+//! set, the first command a client sends to a device puts that device in
+//! [DeviceConsentState::Pending] and sends a [DeviceConsentRequest] out on
+//! [ButtplugServer::device_consent_requests][super::ButtplugServer::device_consent_requests].
+//! Until the embedder answers it with
+//! [ButtplugServer::set_device_consent][super::ButtplugServer::set_device_consent],
+//! commands to the device fail with
+//! [DeviceConsentPending][crate::core::errors::ButtplugDeviceError::DeviceConsentPending].
+//! After that they either go through, or fail with
+//! [DeviceConsentDenied][crate::core::errors::ButtplugDeviceError::DeviceConsentDenied].
+//!
+//! Consent is given to the connected client, and forgotten when it
+//! disconnects. StopDeviceCmd always goes through, since nobody needs to be
+//! asked before a device stops.
+
+use dashmap::DashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceConsentState {
+  /// Waiting on the embedder to answer a [DeviceConsentRequest].
+  Pending,
+  Allowed,
+  Denied,
+}
+
+/// Sent when a client first tries to use a device it hasn't been given
+/// consent for.
+#[derive(Debug, Clone)]
+pub struct DeviceConsentRequest {
+  /// Name the client gave in its handshake.
+  pub client_name: String,
+  pub device_index: u32,
+  pub device_name: String,
+}
+
+/// Consent states for the connected client, by device index.
+#[derive(Default)]
+pub(super) struct DeviceConsentStates {
+  states: DashMap<u32, DeviceConsentState>,
+}
+
+impl DeviceConsentStates {
+  pub fn get(&self, device_index: u32) -> Option<DeviceConsentState> {
+    self.states.get(&device_index).map(|state| *state)
+  }
+
+  pub fn set(&self, device_index: u32, state: DeviceConsentState) {
+    self.states.insert(device_index, state);
+  }
+
+  /// Returns the state for `device_index`, marking it as pending if nothing's
+  /// been decided yet. The bool is true if it was just marked, meaning a
+  /// request needs to go out.
+  pub fn get_or_mark_pending(&self, device_index: u32) -> (DeviceConsentState, bool) {
+    let mut marked = false;
+    let state = *self.states.entry(device_index).or_insert_with(|| {
+      marked = true;
+      DeviceConsentState::Pending
+    });
+    (state, marked)
+  }
+
+  pub fn clear(&self) {
+    self.states.clear();
+  }
+}
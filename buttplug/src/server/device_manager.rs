@@ -9,42 +9,77 @@
 //! specific) Managers
 
 use super::{
+  command_storm_guard::{
+    DeviceCommandRate, DeviceCommandRates, DeviceCommandStorm, DeviceCommandStormGuard,
+  },
   comm_managers::{
-    DeviceCommunicationEvent, DeviceCommunicationManager, DeviceCommunicationManagerBuilder,
+    preflight::PermissionCheck, DeviceCommunicationEvent, DeviceCommunicationManager,
+    DeviceCommunicationManagerBuilder,
+  },
+  diagnostics::{CommManagerDiagnostics, DeviceDiagnostics, DiagnosticsRecorder},
+  device_consent::{DeviceConsentRequest, DeviceConsentState, DeviceConsentStates},
+  device_command_transform::{
+    CapActuatorLevels, DeviceCommandContext, DeviceCommandTransform,
+    FillMissingVibrateSubcommands,
   },
-  device_manager_event_loop::DeviceManagerEventLoop,
+  device_manager_event_loop::{DeviceManagerEvent, DeviceManagerEventLoop},
+  device_session::{DeviceSessionLimit, DeviceSessions},
+  event_bus::{DeviceCommandAudit, DeviceLifecycleEvent, ServerEventBus},
+  kill_switch::{spawn_kill_switch_listener, KillSwitchSource, KillSwitchTrigger},
   ping_timer::PingTimer,
+  scheduled_commands::{self, ScheduledCommands},
   ButtplugServerError,
 };
 use crate::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugMessageError, ButtplugUnknownError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError, ButtplugUnknownError},
     messages::{
       self, ButtplugClientMessage, ButtplugDeviceCommandMessageUnion,
       ButtplugDeviceManagerMessageUnion, ButtplugDeviceMessage, ButtplugMessage,
-      ButtplugServerMessage, DeviceList, DeviceMessageInfo,
+      DeviceList, DeviceMessageInfo, DeviceTransport, DeviceUpdated,
     },
   },
-  device::{configuration_manager::DeviceConfigurationManager, ButtplugDevice, protocol::ButtplugProtocol},
+  device::{
+    configuration_manager::{DeviceConfigurationManager, SupportedProtocol},
+    protocol::ButtplugProtocol,
+    ButtplugDevice, DeviceIdlePowerManagement, DeviceWriteWatchdog,
+  },
   server::ButtplugServerResultFuture,
   test::{TestDeviceCommunicationManager, TestDeviceCommunicationManagerHelper},
-  util::async_manager,
+  util::{async_manager, logging::redact_address},
 };
 use dashmap::DashMap;
 use futures::future;
 use std::{
+  collections::HashMap,
   convert::TryFrom,
-  sync::{atomic::Ordering, Arc},
+  sync::{atomic::Ordering, Arc, RwLock},
+  time::{Duration, Instant},
 };
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
+
+/// How many comm manager events can wait on the device manager before comm
+/// managers have to wait to send more.
+pub const DEVICE_MANAGER_EVENT_QUEUE_SIZE: usize = 256;
 
 pub struct DeviceManager {
   // This uses a map to make sure we don't have 2 comm managers of the same type
   // register. Also means we can do lockless access since it's a Dashmap.
   comm_managers: Arc<DashMap<String, Box<dyn DeviceCommunicationManager>>>,
   devices: Arc<DashMap<u32, Arc<ButtplugDevice>>>,
-  device_event_sender: mpsc::Sender<DeviceCommunicationEvent>,
-  config: Arc<DeviceConfigurationManager>
+  device_event_sender: mpsc::Sender<DeviceManagerEvent>,
+  config: Arc<DeviceConfigurationManager>,
+  command_transforms: RwLock<Vec<Arc<dyn DeviceCommandTransform>>>,
+  event_bus: ServerEventBus,
+  // Name of the connected client, for attributing device commands.
+  client_name: RwLock<Option<String>>,
+  require_device_consent: bool,
+  device_consent: DeviceConsentStates,
+  command_rates: Option<DeviceCommandRates>,
+  device_sessions: DeviceSessions,
+  kill_switch_sender: mpsc::UnboundedSender<String>,
+  diagnostics: DiagnosticsRecorder,
+  scheduled_commands: ScheduledCommands,
 }
 
 unsafe impl Send for DeviceManager {}
@@ -52,12 +87,22 @@ unsafe impl Send for DeviceManager {}
 unsafe impl Sync for DeviceManager {}
 
 impl DeviceManager {
+  #[allow(clippy::too_many_arguments)]
   pub fn try_new(
-    output_sender: broadcast::Sender<ButtplugServerMessage>,
+    event_bus: ServerEventBus,
     ping_timer: Arc<PingTimer>,
     allow_raw_messages: bool,
     device_config_json: &Option<String>,
     user_device_config_json: &Option<String>,
+    device_write_watchdog: Option<DeviceWriteWatchdog>,
+    device_idle_power_management: Option<DeviceIdlePowerManagement>,
+    fill_missing_vibrate_subcommands: bool,
+    max_temperature_level: f64,
+    max_suction_level: f64,
+    require_device_consent: bool,
+    dry_run: bool,
+    device_transport_priority: Vec<DeviceTransport>,
+    device_command_storm_guard: Option<DeviceCommandStormGuard>,
   ) -> Result<Self, ButtplugDeviceError> {
     let config = Arc::new(DeviceConfigurationManager::new_with_options(
       allow_raw_messages,
@@ -65,23 +110,53 @@ impl DeviceManager {
       user_device_config_json,
     )?);
     let devices = Arc::new(DashMap::new());
-    let (device_event_sender, device_event_receiver) = mpsc::channel(256);
+    let mut command_transforms: Vec<Arc<dyn DeviceCommandTransform>> = vec![];
+    if fill_missing_vibrate_subcommands {
+      command_transforms.push(Arc::new(FillMissingVibrateSubcommands));
+    }
+    if max_temperature_level < 1.0 || max_suction_level < 1.0 {
+      command_transforms.push(Arc::new(CapActuatorLevels {
+        max_temperature_level: max_temperature_level.max(0.0),
+        max_suction_level: max_suction_level.max(0.0),
+      }));
+    }
+    let (device_event_sender, device_event_receiver) =
+      mpsc::channel(DEVICE_MANAGER_EVENT_QUEUE_SIZE);
+    let diagnostics = DiagnosticsRecorder::default();
     let mut event_loop = DeviceManagerEventLoop::new(
       config.clone(),
-      output_sender,
+      event_bus.clone(),
       devices.clone(),
       ping_timer,
       device_event_receiver,
+      device_write_watchdog,
+      device_idle_power_management,
+      dry_run,
+      device_transport_priority,
+      diagnostics.clone(),
     );
-    async_manager::spawn(async move {
+    async_manager::spawn_named("Device Manager Event Loop", async move {
       event_loop.run().await;
     })
     .unwrap();
+    let scheduled_commands = ScheduledCommands::default();
+    let kill_switch_sender =
+      spawn_kill_switch_listener(Arc::downgrade(&devices), scheduled_commands.clone());
     Ok(Self {
       device_event_sender,
       devices,
       comm_managers: Arc::new(DashMap::new()),
-      config
+      config,
+      command_transforms: RwLock::new(command_transforms),
+      event_bus,
+      client_name: RwLock::new(None),
+      require_device_consent,
+      device_consent: DeviceConsentStates::default(),
+      command_rates: device_command_storm_guard.map(DeviceCommandRates::new),
+      device_sessions: DeviceSessions::default(),
+      kill_switch_sender,
+      diagnostics,
+      scheduled_commands,
     })
   }
 
@@ -91,42 +166,55 @@ impl DeviceManager {
     } else {
       let mgrs = self.comm_managers.clone();
       let sender = self.device_event_sender.clone();
+      let diagnostics = self.diagnostics.clone();
       Box::pin(async move {
         for mgr in mgrs.iter() {
           if mgr.value().scanning_status().load(Ordering::SeqCst) {
             return Err(ButtplugDeviceError::DeviceScanningAlreadyStarted.into());
           }
         }
-        let fut_vec: Vec<_> = mgrs
-          .iter()
-          .map(|guard| guard.value().start_scanning())
-          .collect();
-        // TODO If start_scanning fails anywhere, this will ignore it. We should maybe at least log?
-        future::join_all(fut_vec).await;
-        debug!("All managers started, sending ScanningStarted (and invoking ScanningFinished hack) signal to event loop.");
-        // HACK: In case everything somehow exited between the time all of our
-        // futures resolved and when we updated the event loop, act like we're a
-        // device comm manager and send a ScanningFinished message. This will
-        // cause the finish check to run just in case, so we don't get stuck.
-        //
-        // Ideally, this should be some sort of state machine, but for now, we
-        // can deal with this.
+        let names: Vec<String> = mgrs.iter().map(|guard| guard.key().clone()).collect();
+        // Let the event loop know which managers it needs to hear
+        // ScanningFinished from before anything starts, so no manager can finish
+        // before the event loop knows it's scanning.
         //
         // At this point, it doesn't really matter what we return, only way that
         // event loop could shut down is if the whole system is shutting down.
         // So complain if our sends error out, but don't worry about returning
         // an error.
         if sender
-          .send(DeviceCommunicationEvent::ScanningStarted)
+          .send(DeviceManagerEvent::ScanningStarted(names.clone()))
           .await
           .is_err()
-          || sender
-            .send(DeviceCommunicationEvent::ScanningFinished)
-            .await
-            .is_err()
         {
           debug!("Device manager event loop shut down, cannot send ScanningStarted");
         }
+        let fut_vec: Vec<_> = mgrs
+          .iter()
+          .map(|guard| guard.value().start_scanning())
+          .collect();
+        let results = future::join_all(fut_vec).await;
+        debug!("All managers started.");
+        for (name, result) in names.into_iter().zip(results) {
+          match result {
+            Ok(_) => diagnostics.adapter_available(&name),
+            Err(err) => {
+              // A manager that failed to start isn't going to finish either,
+              // so don't wait on it.
+              error!("Comm manager {} failed to start scanning: {}", name, err);
+              if sender
+                .send(DeviceManagerEvent::CommManager(
+                  name,
+                  DeviceCommunicationEvent::ScanningFinished,
+                ))
+                .await
+                .is_err()
+              {
+                debug!("Device manager event loop shut down, cannot send ScanningFinished");
+              }
+            }
+          }
+        }
         Ok(messages::Ok::default().into())
       })
     }
@@ -137,6 +225,7 @@ impl DeviceManager {
       ButtplugUnknownError::NoDeviceCommManagers.into()
     } else {
       let mgrs = self.comm_managers.clone();
+      let sender = self.device_event_sender.clone();
       Box::pin(async move {
         let mut scanning_stopped = true;
         for mgr in mgrs.iter() {
@@ -156,12 +245,20 @@ impl DeviceManager {
           .collect();
         // TODO If stop_scanning fails anywhere, this will ignore it. We should maybe at least log?
         future::join_all(fut_vec).await;
+        if sender
+          .send(DeviceManagerEvent::ScanningStopRequested)
+          .await
+          .is_err()
+        {
+          debug!("Device manager event loop shut down, cannot send ScanningStopRequested");
+        }
         Ok(messages::Ok::default().into())
       })
     }
   }
 
   fn stop_all_devices(&self) -> ButtplugServerResultFuture {
+    self.scheduled_commands.cancel_all();
     let device_map = self.devices.clone();
     // TODO This could use some error reporting.
     Box::pin(async move {
@@ -181,13 +278,121 @@ impl DeviceManager {
     &self,
     device_msg: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugServerResultFuture {
-    match self.devices.get(&device_msg.device_index()) {
-      Some(device) => {
-        let fut = device.parse_message(device_msg);
-        // Create a future to run the message through the device, then handle adding the id to the result.
-        Box::pin(async move { fut.await })
+    if let ButtplugDeviceCommandMessageUnion::StopDeviceCmd(msg) = &device_msg {
+      self.scheduled_commands.cancel(msg.device_index());
+    }
+    match self.prepare_device_message(device_msg) {
+      Ok((device, device_msg)) => device.parse_message(device_msg),
+      Err(err) => Box::pin(future::ready(Err(err))),
+    }
+  }
+
+  /// Runs a device command through consent, rate limiting, transforms and
+  /// sessions, returning the device and the command to send it.
+  fn prepare_device_message(
+    &self,
+    device_msg: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<(Arc<ButtplugDevice>, ButtplugDeviceCommandMessageUnion), ButtplugError> {
+    let device = match self.devices.get(&device_msg.device_index()) {
+      Some(device) => device.value().clone(),
+      None => return Err(ButtplugDeviceError::DeviceNotAvailable(device_msg.device_index()).into()),
+    };
+    let device_name = device.name();
+    let message_attributes = device.message_attributes();
+    let context = DeviceCommandContext {
+      device_index: device_msg.device_index(),
+      device_name: &device_name,
+      message_attributes: &message_attributes,
+    };
+    self.check_device_consent(&device_msg, &device_name)?;
+    self.check_command_rate(&device_msg, &device_name)?;
+    let mut device_msg = device_msg;
+    for transform in self.command_transforms.read().unwrap().iter() {
+      device_msg = transform.transform(&context, device_msg)?;
+    }
+    let session_limit = self
+      .config
+      .session_limit(device.address())
+      .map(DeviceSessionLimit::from);
+    self
+      .device_sessions
+      .handle_command(&device, session_limit, &device_msg)?;
+    self.event_bus.publish(DeviceCommandAudit {
+      client_name: self.client_name.read().unwrap().clone().unwrap_or_default(),
+      device_index: context.device_index,
+      device_name: device_name.clone(),
+      message: device_msg.clone(),
+    });
+    Ok((device, device_msg))
+  }
+
+  fn check_device_consent(
+    &self,
+    device_msg: &ButtplugDeviceCommandMessageUnion,
+    device_name: &str,
+  ) -> Result<(), ButtplugDeviceError> {
+    if !self.require_device_consent
+      || matches!(device_msg, ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_))
+    {
+      return Ok(());
+    }
+    let device_index = device_msg.device_index();
+    match self.device_consent.get_or_mark_pending(device_index) {
+      (DeviceConsentState::Allowed, _) => Ok(()),
+      (DeviceConsentState::Denied, _) => {
+        Err(ButtplugDeviceError::DeviceConsentDenied(device_index))
+      }
+      (DeviceConsentState::Pending, marked) => {
+        if marked {
+          info!("Asking for consent to use device {}", device_index);
+          if !self.event_bus.publish(DeviceConsentRequest {
+            client_name: self.client_name.read().unwrap().clone().unwrap_or_default(),
+            device_index,
+            device_name: device_name.to_owned(),
+          }) {
+            warn!("Nothing is listening for device consent requests.");
+          }
+        }
+        Err(ButtplugDeviceError::DeviceConsentPending(device_index))
+      }
+    }
+  }
+
+  fn check_command_rate(
+    &self,
+    device_msg: &ButtplugDeviceCommandMessageUnion,
+    device_name: &str,
+  ) -> Result<(), ButtplugDeviceError> {
+    let command_rates = match &self.command_rates {
+      Some(command_rates) => command_rates,
+      None => return Ok(()),
+    };
+    if matches!(device_msg, ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_)) {
+      return Ok(());
+    }
+    let device_index = device_msg.device_index();
+    let max_commands_per_second = command_rates.max_commands_per_second();
+    match command_rates.record(device_index, Instant::now()) {
+      DeviceCommandRate::Allowed => Ok(()),
+      DeviceCommandRate::Throttled { storm_started } => {
+        if storm_started {
+          let client_name = self.client_name.read().unwrap().clone().unwrap_or_default();
+          warn!(
+            "Client {} is sending more than {} commands a second to device {} ({}), throttling.",
+            client_name, max_commands_per_second, device_index, device_name
+          );
+          self.event_bus.publish(DeviceCommandStorm {
+            client_name,
+            device_index,
+            device_name: device_name.to_owned(),
+            max_commands_per_second,
+          });
+        }
+        Err(ButtplugDeviceError::DeviceCommandRateExceeded(
+          device_index,
+          max_commands_per_second,
+        ))
       }
-      None => ButtplugDeviceError::DeviceNotAvailable(device_msg.device_index()).into(),
     }
   }
 
@@ -202,7 +407,11 @@ impl DeviceManager {
           .iter()
           .map(|device| {
             let dev = device.value();
-            DeviceMessageInfo::new(*device.key(), &dev.name(), dev.message_attributes())
+            let name = self
+              .config
+              .display_name(dev.address())
+              .unwrap_or_else(|| dev.name());
+            DeviceMessageInfo::new(*device.key(), &name, dev.message_attributes())
           })
           .collect();
         let mut device_list = DeviceList::new(devices);
@@ -212,6 +421,88 @@ impl DeviceManager {
       ButtplugDeviceManagerMessageUnion::StopAllDevices(_) => self.stop_all_devices(),
       ButtplugDeviceManagerMessageUnion::StartScanning(_) => self.start_scanning(),
       ButtplugDeviceManagerMessageUnion::StopScanning(_) => self.stop_scanning(),
+      ButtplugDeviceManagerMessageUnion::DeviceStatisticsCmd(msg) => {
+        let device_index = msg.device_index();
+        let result = match self.devices.get(&device_index) {
+          Some(device) => {
+            let statistics = device.statistics();
+            Ok(
+              messages::DeviceStatistics::new(
+                device_index,
+                statistics.write_count(),
+                statistics.error_count(),
+                statistics.average_write_latency().as_millis() as u32,
+                statistics.last_error().clone(),
+              )
+              .into(),
+            )
+          }
+          None => Err(ButtplugDeviceError::DeviceNotAvailable(device_index).into()),
+        };
+        Box::pin(future::ready(result))
+      }
+      ButtplugDeviceManagerMessageUnion::DeviceStatusCmd(msg) => {
+        let device_index = msg.device_index();
+        let result = match self.devices.get(&device_index) {
+          Some(device) => {
+            let state = device.command_state();
+            let speeds = state
+              .vibrate_speeds
+              .iter()
+              .enumerate()
+              .map(|(index, speed)| messages::VibrateSubcommand::new(index as u32, *speed))
+              .collect();
+            let rotations = state
+              .rotations
+              .iter()
+              .enumerate()
+              .map(|(index, (speed, clockwise))| {
+                messages::RotationSubcommand::new(index as u32, *speed, *clockwise)
+              })
+              .collect();
+            let positions = state
+              .linear_positions
+              .iter()
+              .map(|(index, position)| messages::LinearPosition::new(*index, *position))
+              .collect();
+            Ok(messages::DeviceStatus::new(device_index, speeds, rotations, positions).into())
+          }
+          None => Err(ButtplugDeviceError::DeviceNotAvailable(device_index).into()),
+        };
+        Box::pin(future::ready(result))
+      }
+      ButtplugDeviceManagerMessageUnion::DeviceSessionLimitCmd(msg) => {
+        let device_index = msg.device_index();
+        if !self.devices.contains_key(&device_index) {
+          return ButtplugDeviceError::DeviceNotAvailable(device_index).into();
+        }
+        let limit = if msg.duration() == 0 {
+          None
+        } else {
+          Some(DeviceSessionLimit {
+            duration: Duration::from_millis(msg.duration().into()),
+            wind_down: Duration::from_millis(msg.wind_down().into()),
+          })
+        };
+        self.device_sessions.set_client_limit(device_index, limit);
+        Box::pin(future::ready(Ok(messages::Ok::default().into())))
+      }
+      ButtplugDeviceManagerMessageUnion::ScheduledCmd(msg) => {
+        let command = msg.command().clone();
+        let delay = match scheduled_commands::check_schedulable(&command)
+          .and_then(|_| scheduled_commands::delay_until(msg.timestamp()))
+        {
+          Ok(delay) => delay,
+          Err(err) => return ButtplugError::from(err).into(),
+        };
+        match self.prepare_device_message(command) {
+          Ok((device, command)) => {
+            self.scheduled_commands.schedule(device, command, delay);
+            Box::pin(future::ready(Ok(messages::Ok::default().into())))
+          }
+          Err(err) => Box::pin(future::ready(Err(err))),
+        }
+      }
     }
   }
 
@@ -227,49 +518,101 @@ impl DeviceManager {
     }
   }
 
+  /// Relays events from a comm manager to the event loop, tagged with the
+  /// manager's name so the event loop knows who they came from.
+  fn forward_comm_manager_events(
+    &self,
+    name: &str,
+    mut receiver: mpsc::Receiver<DeviceCommunicationEvent>,
+  ) {
+    let sender = self.device_event_sender.clone();
+    let name = name.to_owned();
+    async_manager::spawn_named("Comm Manager Event Forwarder", async move {
+      while let Some(event) = receiver.recv().await {
+        if sender
+          .send(DeviceManagerEvent::CommManager(name.clone(), event))
+          .await
+          .is_err()
+        {
+          break;
+        }
+      }
+    })
+    .unwrap();
+  }
+
+  /// Sets the name device commands are attributed to, or clears it when the
+  /// client disconnects. Consent given to the last client is forgotten, along
+  /// with how fast it was sending commands and any device sessions it had.
+  pub fn set_client_name(&self, client_name: Option<String>) {
+    *self.client_name.write().unwrap() = client_name;
+    self.device_consent.clear();
+    self.device_sessions.clear();
+    if let Some(command_rates) = &self.command_rates {
+      command_rates.clear();
+    }
+  }
+
+  pub fn device_consent(&self, device_index: u32) -> Option<DeviceConsentState> {
+    self.device_consent.get(device_index)
+  }
+
+  pub fn set_device_consent(&self, device_index: u32, allowed: bool) {
+    let state = if allowed {
+      DeviceConsentState::Allowed
+    } else {
+      DeviceConsentState::Denied
+    };
+    self.device_consent.set(device_index, state);
+  }
+
+  pub fn add_device_command_transform<T>(&self, transform: T)
+  where
+    T: DeviceCommandTransform + 'static,
+  {
+    self
+      .command_transforms
+      .write()
+      .unwrap()
+      .push(Arc::new(transform));
+  }
+
   pub fn add_comm_manager<T>(&self, mut builder: T) -> Result<(), ButtplugServerError> where T: DeviceCommunicationManagerBuilder {
-    builder.set_event_sender(self.device_event_sender.clone());
+    let (sender, receiver) = mpsc::channel(256);
+    builder.set_event_sender(sender);
+    builder.set_device_configuration(self.config.clone());
     let mgr = builder.finish();
     if self.comm_managers.contains_key(mgr.name()) {
       return Err(ButtplugServerError::DeviceManagerTypeAlreadyAdded(
         mgr.name().to_owned(),
       ));
     }
-    let status = mgr.scanning_status();
-    let sender = self.device_event_sender.clone();
-    // TODO This could run out of order and possibly cause weird scanning finished bugs?
-    async_manager::spawn(async move {
-      sender
-        .send(DeviceCommunicationEvent::DeviceManagerAdded(status))
-        .await
-        .unwrap();
-    })
-    .unwrap();
+    self.forward_comm_manager_events(mgr.name(), receiver);
     self
       .comm_managers
       .insert(mgr.name().to_owned(), mgr);
     Ok(())
   }
 
+  pub fn add_kill_switch(
+    &self,
+    source: Box<dyn KillSwitchSource>,
+  ) -> Result<(), ButtplugServerError> {
+    let trigger = KillSwitchTrigger::new(source.name(), self.kill_switch_sender.clone());
+    source.start(trigger)
+  }
+
   pub fn add_test_comm_manager(
     &self,
   ) -> Result<TestDeviceCommunicationManagerHelper, ButtplugServerError> {
-    let mgr = TestDeviceCommunicationManager::new(self.device_event_sender.clone());
+    let (sender, receiver) = mpsc::channel(256);
+    let mgr = TestDeviceCommunicationManager::new(sender);
     if self.comm_managers.contains_key(mgr.name()) {
       return Err(ButtplugServerError::DeviceManagerTypeAlreadyAdded(
         mgr.name().to_owned(),
       ));
     }
-    let status = mgr.scanning_status();
-    let sender = self.device_event_sender.clone();
-    // TODO This could run out of order and possibly cause weird scanning finished bugs?
-    async_manager::spawn(async move {
-      sender
-        .send(DeviceCommunicationEvent::DeviceManagerAdded(status))
-        .await
-        .unwrap();
-    })
-    .unwrap();
+    self.forward_comm_manager_events(mgr.name(), receiver);
     let helper = mgr.helper();
     self
       .comm_managers
@@ -298,6 +641,128 @@ impl DeviceManager {
   pub fn remove_all_protocols(&self) {
     self.config.remove_all_protocols();
   }
+
+  /// Sets a user display name for the device at `address`, sent to clients
+  /// instead of the device config name. If the device is connected, clients
+  /// are sent a DeviceUpdated with the new name.
+  pub fn set_device_display_name(&self, address: &str, display_name: &str) {
+    self.config.set_display_name(address, display_name);
+    self.refresh_devices_at_address(address);
+  }
+
+  pub fn remove_device_display_name(&self, address: &str) -> Option<String> {
+    let display_name = self.config.remove_display_name(address);
+    if display_name.is_some() {
+      self.refresh_devices_at_address(address);
+    }
+    display_name
+  }
+
+  fn refresh_devices_at_address(&self, address: &str) {
+    let device_indexes: Vec<u32> = self
+      .devices
+      .iter()
+      .filter(|device| device.value().address() == address)
+      .map(|device| *device.key())
+      .collect();
+    for device_index in device_indexes {
+      // Can only fail if the device went away in the meantime.
+      let _ = self.refresh_device(device_index);
+    }
+  }
+
+  /// Sends clients a DeviceUpdated with the device's current name and message
+  /// attributes, for when either has changed since the device was added.
+  pub fn refresh_device(&self, device_index: u32) -> Result<(), ButtplugDeviceError> {
+    let device = self
+      .devices
+      .get(&device_index)
+      .map(|device| device.value().clone())
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(device_index))?;
+    let name = self
+      .config
+      .display_name(device.address())
+      .unwrap_or_else(|| device.name());
+    let msg = DeviceUpdated::new(device_index, &name, &device.message_attributes());
+    if !self.event_bus.publish(DeviceLifecycleEvent::Updated(msg)) {
+      debug!("Server not currently available, dropping Device Updated event.");
+    }
+    Ok(())
+  }
+
+  pub fn device_display_names(&self) -> HashMap<String, String> {
+    self.config.display_names()
+  }
+
+  pub fn supported_protocols(&self) -> Vec<SupportedProtocol> {
+    self.config.supported_protocols()
+  }
+
+  pub fn comm_manager_diagnostics(&self) -> Vec<CommManagerDiagnostics> {
+    let mut comm_managers: Vec<CommManagerDiagnostics> = self
+      .comm_managers
+      .iter()
+      .map(|mgr| {
+        let health = self.diagnostics.comm_manager_health(mgr.key());
+        CommManagerDiagnostics {
+          name: mgr.key().clone(),
+          scanning: mgr.value().scanning_status().load(Ordering::SeqCst),
+          adapter_state: health.adapter_state,
+          last_error: health.last_error,
+        }
+      })
+      .collect();
+    comm_managers.sort_by(|a, b| a.name.cmp(&b.name));
+    comm_managers
+  }
+
+  pub fn device_diagnostics(&self) -> Vec<DeviceDiagnostics> {
+    let mut devices: Vec<DeviceDiagnostics> = self
+      .devices
+      .iter()
+      .map(|device| {
+        let dev = device.value();
+        let origin = self.diagnostics.device_origin(dev.peripheral_address());
+        let statistics = dev.statistics();
+        DeviceDiagnostics {
+          device_index: *device.key(),
+          name: dev.name(),
+          address: redact_address(dev.address()).to_string(),
+          transport: origin.as_ref().map(|(_, transport)| *transport),
+          comm_manager: origin.map(|(comm_manager, _)| comm_manager),
+          connected: dev.connected(),
+          write_count: statistics.write_count(),
+          error_count: statistics.error_count(),
+          average_write_latency_ms: statistics.average_write_latency().as_millis() as u32,
+          last_error: statistics.last_error().clone(),
+        }
+      })
+      .collect();
+    devices.sort_by_key(|device| device.device_index);
+    devices
+  }
+
+  pub fn event_queue_depth(&self) -> usize {
+    DEVICE_MANAGER_EVENT_QUEUE_SIZE - self.device_event_sender.capacity()
+  }
+
+  pub fn client_name(&self) -> Option<String> {
+    self.client_name.read().unwrap().clone()
+  }
+
+  pub fn permission_preflight(&self) -> Vec<PermissionCheck> {
+    self
+      .comm_managers
+      .iter()
+      .flat_map(|mgr| {
+        let name = mgr.key().clone();
+        mgr.value().permission_checks().into_iter().map(move |mut check| {
+          check.comm_manager = name.clone();
+          check
+        })
+      })
+      .collect()
+  }
 }
 
 impl Drop for DeviceManager {
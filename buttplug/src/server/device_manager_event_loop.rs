@@ -1,235 +1,629 @@
-use super::{comm_managers::DeviceCommunicationEvent, ping_timer::PingTimer};
+use super::{
+  comm_managers::DeviceCommunicationEvent,
+  diagnostics::DiagnosticsRecorder,
+  event_bus::{
+    DeviceDryRunWrite, DeviceLifecycleEvent, ScanningEvent, ServerErrorEvent, ServerEventBus,
+  },
+  ping_timer::PingTimer,
+};
 use crate::{
-  core::messages::{
-    ButtplugServerMessage, DeviceAdded, DeviceRemoved, ScanningFinished, StopDeviceCmd,
+  core::{
+    errors::ButtplugDeviceError,
+    messages::{
+      ButtplugMessage, DeviceAdded, DeviceMetadata, DeviceTransport, RawReading, StopDeviceCmd,
+    },
   },
   device::{
     configuration_manager::DeviceConfigurationManager, ButtplugDevice, ButtplugDeviceEvent,
-    ButtplugDeviceImplCreator,
+    ButtplugDeviceImplCreator, DeviceIdlePowerManagement, DeviceWriteWatchdog,
   },
-  util::async_manager,
+  util::{async_manager, logging::redact_address},
 };
 use dashmap::DashMap;
-use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+use futures::{future, stream::FuturesUnordered, FutureExt, StreamExt};
+use futures_timer::Delay;
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{Arc, Weak},
+  time::Duration,
 };
 use tokio::sync::{broadcast, mpsc};
 use tracing;
 use tracing_futures::Instrument;
 
+/// How long to wait for comm managers to report they've finished scanning
+/// after StopScanning (or for found devices to finish connecting once they
+/// have), before we give up on them and emit ScanningFinished anyways.
+const SCANNING_FINISHED_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check whether devices still think they're connected, to catch
+/// devices that went away without sending a removal event.
+const DEVICE_CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Events sent to the event loop by the
+/// [DeviceManager][super::device_manager::DeviceManager].
+pub(super) enum DeviceManagerEvent {
+  /// Event from a comm manager, tagged with the name of the manager it came
+  /// from.
+  CommManager(String, DeviceCommunicationEvent),
+  /// Scanning is starting on the named comm managers.
+  ScanningStarted(Vec<String>),
+  /// StopScanning was requested, so all scanning comm managers should be
+  /// finishing up.
+  ScanningStopRequested,
+}
+
+/// Events the event loop gets from devices.
+enum DeviceLoopEvent {
+  /// A device finished connecting and needs to be registered, along with
+  /// what its comm manager told us about it when it was found.
+  Connected(Arc<ButtplugDevice>, DeviceMetadata),
+  /// A connection attempt started from a DeviceFound event is done, whether
+  /// or not it produced any devices. Sent after any Connected events from the
+  /// attempt.
+  ConnectionAttemptFinished,
+  /// Event from a registered device, tagged with its index and the device it
+  /// came from. A reconnecting device replaces the old one at the same index,
+  /// so this lets us ignore anything the old one sends afterwards.
+  Device(u32, Weak<ButtplugDevice>, ButtplugDeviceEvent),
+  /// Error from a registered device that wasn't returned to anyone, i.e. a
+  /// write from a protocol task that got stuck.
+  DeviceError(u32, ButtplugDeviceError),
+}
+
+/// Id for the physical device at `address`, for spotting the same device
+/// showing up through more than one comm manager (i.e. over BLE, and through
+/// a dongle that reports the device's bluetooth address). Only addresses that
+/// are hardware addresses (6 bytes of hex, with or without separators) have
+/// one, since anything else can't be matched up across transports.
+fn physical_device_id(address: &str) -> Option<String> {
+  let mut id = String::new();
+  for c in address.chars() {
+    match c {
+      ':' | '-' | '_' => continue,
+      c if c.is_ascii_hexdigit() => id.push(c.to_ascii_lowercase()),
+      _ => return None,
+    }
+  }
+  if id.len() == 12 {
+    Some(id)
+  } else {
+    None
+  }
+}
+
+async fn wait_for_timeout(timeout: &mut Option<Delay>) {
+  match timeout {
+    Some(delay) => delay.await,
+    None => future::pending().await,
+  }
+}
+
+/// Device index policy: indexes come from a counter that only goes up, and an
+/// index is never handed to a different device within a server session. A
+/// device that reconnects (same address) gets its old index back. Each device
+/// removal results in exactly one DeviceRemoved message, no matter how many
+/// places notice the device is gone.
 pub struct DeviceManagerEventLoop {
   device_config_manager: Arc<DeviceConfigurationManager>,
   device_index_generator: u32,
   device_map: Arc<DashMap<u32, Arc<ButtplugDevice>>>,
   ping_timer: Arc<PingTimer>,
-  /// Maps device addresses to indexes, so they can be reused on reconnect.
+  /// Maps device addresses to indexes, so devices get the same index back on
+  /// reconnect.
   device_index_map: Arc<DashMap<String, u32>>,
-  /// Broadcaster that relays device events in the form of Buttplug Messages to
-  /// whoever owns the Buttplug Server.
-  server_sender: broadcast::Sender<ButtplugServerMessage>,
+  /// Maps peripheral addresses to the comm manager that found them, so we
+  /// know which devices go away if a comm manager loses its adapter.
+  device_comm_managers: HashMap<String, String>,
+  /// Maps peripheral addresses to the transport they were found on, for
+  /// picking between connections to the same physical device.
+  device_transports: HashMap<String, DeviceTransport>,
+  /// Transports to prefer when a device is found on more than one, best
+  /// first. Transports that aren't listed come after those that are.
+  device_transport_priority: Vec<DeviceTransport>,
+  /// Bus that device lifecycle, scanning and error events are published on,
+  /// for the server to relay to whoever owns it.
+  event_bus: ServerEventBus,
   /// As the device manager owns the Device Communication Managers, it will have
   /// a receiver that the comm managers all send thru.
-  device_comm_receiver: mpsc::Receiver<DeviceCommunicationEvent>,
+  device_comm_receiver: mpsc::Receiver<DeviceManagerEvent>,
   /// Sender for device events, passed to new devices when they are created.
-  device_event_sender: mpsc::Sender<ButtplugDeviceEvent>,
+  device_event_sender: mpsc::Sender<DeviceLoopEvent>,
   /// Receiver for device events, which the event loops to handle events.
-  device_event_receiver: mpsc::Receiver<ButtplugDeviceEvent>,
+  device_event_receiver: mpsc::Receiver<DeviceLoopEvent>,
   /// True if StartScanning has been called but no ScanningFinished has been
   /// emitted yet.
   scanning_in_progress: bool,
-  /// Holds the status of comm manager scanning states (scanning/not scanning).
-  comm_manager_scanning_statuses: Vec<Arc<AtomicBool>>,
+  /// Comm managers that were told to start scanning, and haven't reported
+  /// that they've finished yet. ScanningFinished goes out once this empties.
+  scanning_comm_managers: HashSet<String>,
+  /// Set once StopScanning is requested, in case a comm manager never reports
+  /// that it has finished.
+  scanning_finished_timeout: Option<Delay>,
+  /// Number of devices found that are still being connected. ScanningFinished
+  /// waits on these, so everything found during a scan is added before
+  /// clients are told the scan is over.
+  pending_connections: usize,
+  /// Fires when it's time to check device connection states.
+  connection_check_timer: Delay,
+  /// Watchdog settings given to each device as it's registered.
+  device_write_watchdog: Option<DeviceWriteWatchdog>,
+  /// Idle power settings given to each device as it's registered.
+  device_idle_power_management: Option<DeviceIdlePowerManagement>,
+  /// If true, devices are put in dry run mode as they're registered, with
+  /// their writes published on the event bus.
+  dry_run: bool,
+  /// Comm manager health and device origins, for diagnostics snapshots.
+  diagnostics: DiagnosticsRecorder,
 }
 
 impl DeviceManagerEventLoop {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     device_config_manager: Arc<DeviceConfigurationManager>,
-    server_sender: broadcast::Sender<ButtplugServerMessage>,
+    event_bus: ServerEventBus,
     device_map: Arc<DashMap<u32, Arc<ButtplugDevice>>>,
     ping_timer: Arc<PingTimer>,
-    device_comm_receiver: mpsc::Receiver<DeviceCommunicationEvent>,
+    device_comm_receiver: mpsc::Receiver<DeviceManagerEvent>,
+    device_write_watchdog: Option<DeviceWriteWatchdog>,
+    device_idle_power_management: Option<DeviceIdlePowerManagement>,
+    dry_run: bool,
+    device_transport_priority: Vec<DeviceTransport>,
+    diagnostics: DiagnosticsRecorder,
   ) -> Self {
     let (device_event_sender, device_event_receiver) = mpsc::channel(256);
     Self {
       device_config_manager,
-      server_sender,
+      event_bus,
       device_map,
       ping_timer,
       device_comm_receiver,
       device_index_generator: 0,
       device_index_map: Arc::new(DashMap::new()),
+      device_comm_managers: HashMap::new(),
+      device_transports: HashMap::new(),
+      device_transport_priority,
       device_event_sender,
       device_event_receiver,
       scanning_in_progress: false,
-      comm_manager_scanning_statuses: vec![],
+      scanning_comm_managers: HashSet::new(),
+      scanning_finished_timeout: None,
+      pending_connections: 0,
+      connection_check_timer: Delay::new(DEVICE_CONNECTION_CHECK_INTERVAL),
+      device_write_watchdog,
+      device_idle_power_management,
+      dry_run,
+      diagnostics,
     }
   }
 
-  fn try_create_new_device(&mut self, device_creator: Box<dyn ButtplugDeviceImplCreator>) {
+  /// Position of `transport` in the priority list. Lower is better.
+  fn transport_rank(&self, transport: DeviceTransport) -> usize {
+    self
+      .device_transport_priority
+      .iter()
+      .position(|preferred| *preferred == transport)
+      .unwrap_or(self.device_transport_priority.len())
+  }
+
+  /// Finds a registered device that's the same physical device as the one at
+  /// `address`, but connected at a different address (so through another
+  /// comm manager). Returns its index and transport rank.
+  fn find_physical_duplicate(&self, address: &str) -> Option<(u32, usize)> {
+    let id = physical_device_id(address)?;
+    self
+      .device_map
+      .iter()
+      .find(|device| {
+        let other_address = device.value().peripheral_address();
+        other_address != address && physical_device_id(other_address).as_ref() == Some(&id)
+      })
+      .map(|device| {
+        let transport = self
+          .device_transports
+          .get(device.value().peripheral_address())
+          .copied()
+          .unwrap_or(DeviceTransport::Unknown);
+        (*device.key(), self.transport_rank(transport))
+      })
+  }
+
+  fn try_create_new_device(
+    &mut self,
+    device_creator: Box<dyn ButtplugDeviceImplCreator>,
+    metadata: DeviceMetadata,
+  ) {
     let device_event_sender_clone = self.device_event_sender.clone();
     let create_device_future =
-      ButtplugDevice::try_create_device(self.device_config_manager.clone(), device_creator);
+      ButtplugDevice::try_create_devices(self.device_config_manager.clone(), device_creator);
+    self.pending_connections += 1;
     async_manager::spawn(async move {
       match create_device_future.await {
-        Ok(option_dev) => match option_dev {
-          Some(device) => {
+        Ok(devices) => {
+          if devices.is_empty() {
+            debug!("Device could not be matched to a protocol.");
+          }
+          // Composite peripherals come back as multiple devices, which are
+          // registered separately.
+          for device in devices {
             if device_event_sender_clone
-              .send(ButtplugDeviceEvent::Connected(Arc::new(device)))
+              .send(DeviceLoopEvent::Connected(Arc::new(device), metadata.clone()))
               .await
               .is_err() {
               error!("Device manager disappeared before connection established, device will be dropped.");
+              return;
             }
           }
-          None => debug!("Device could not be matched to a protocol."),
-        },
+        }
         Err(e) => error!("Device errored while trying to connect: {}", e),
       }
+      // Only fails if the event loop is gone, in which case no one cares.
+      let _ = device_event_sender_clone
+        .send(DeviceLoopEvent::ConnectionAttemptFinished)
+        .await;
     }.instrument(tracing::Span::current()))
     .unwrap();
   }
 
-  async fn handle_device_communication(&mut self, event: DeviceCommunicationEvent) {
+  /// Emits ScanningFinished once all comm managers are done, unless devices
+  /// are still connecting, in which case it goes out once they're done.
+  fn try_finish_scanning(&mut self) {
+    if self.pending_connections > 0 {
+      debug!(
+        "{} devices still connecting, holding ScanningFinished.",
+        self.pending_connections
+      );
+      // Don't let a connection that never finishes hold things up forever.
+      if self.scanning_finished_timeout.is_none() {
+        self.scanning_finished_timeout = Some(Delay::new(SCANNING_FINISHED_TIMEOUT));
+      }
+      return;
+    }
+    self.finish_scanning();
+  }
+
+  fn finish_scanning(&mut self) {
+    debug!("All managers finished, emitting ScanningFinished");
+    self.scanning_in_progress = false;
+    self.scanning_comm_managers.clear();
+    self.scanning_finished_timeout = None;
+    if !self.event_bus.publish(ScanningEvent::Finished) {
+      info!("Server not currently available, dropping ScanningFinished event.");
+    }
+  }
+
+  fn handle_device_manager_event(&mut self, event: DeviceManagerEvent) {
     match event {
-      DeviceCommunicationEvent::ScanningStarted => {
+      DeviceManagerEvent::ScanningStarted(comm_managers) => {
         self.scanning_in_progress = true;
+        self.scanning_finished_timeout = None;
+        self.scanning_comm_managers = comm_managers.into_iter().collect();
+        if self.scanning_comm_managers.is_empty() {
+          self.try_finish_scanning();
+        }
+      }
+      DeviceManagerEvent::ScanningStopRequested => {
+        if self.scanning_in_progress && self.scanning_finished_timeout.is_none() {
+          self.scanning_finished_timeout = Some(Delay::new(SCANNING_FINISHED_TIMEOUT));
+        }
       }
+      DeviceManagerEvent::CommManager(name, event) => {
+        self.handle_device_communication(&name, event);
+      }
+    }
+  }
+
+  fn handle_scanning_finished_timeout(&mut self) {
+    warn!(
+      "Comm managers {:?} did not report scanning finished in time, emitting ScanningFinished anyways.",
+      self.scanning_comm_managers
+    );
+    self.finish_scanning();
+  }
+
+  fn handle_device_communication(&mut self, comm_manager: &str, event: DeviceCommunicationEvent) {
+    match event {
       DeviceCommunicationEvent::ScanningFinished => {
         debug!(
-          "System signaled that scanning was finished, check to see if all managers are finished."
+          "{} signaled that scanning was finished, check to see if all managers are finished.",
+          comm_manager
         );
-        if !self.scanning_in_progress {
-          debug!("Manager finished before scanning was fully started, continuing event loop.");
+        if !self.scanning_in_progress || !self.scanning_comm_managers.remove(comm_manager) {
+          debug!("Manager was not scanning, ignoring.");
           return;
         }
-        if self
-          .comm_manager_scanning_statuses
-          .iter()
-          .any(|x| x.load(Ordering::SeqCst))
-        {
-          debug!("At least one manager still scanning, continuing event loop.");
-          return;
-        }
-        debug!("All managers finished, emitting ScanningFinished");
-        self.scanning_in_progress = false;
-        if self
-          .server_sender
-          .send(ScanningFinished::default().into())
-          .is_err()
-        {
-          info!("Server disappeared, exiting loop.");
+        if !self.scanning_comm_managers.is_empty() {
+          debug!(
+            "Managers {:?} still scanning, continuing event loop.",
+            self.scanning_comm_managers
+          );
           return;
         }
+        self.try_finish_scanning();
       }
       DeviceCommunicationEvent::DeviceFound {
         name,
         address,
         creator,
+        metadata,
       } => {
         let span = info_span!(
           "device creation",
           name = tracing::field::display(name),
-          address = tracing::field::display(address.clone())
+          address = tracing::field::display(redact_address(&address))
         );
         let _enter = span.enter();
         // Check to make sure the device isn't already connected. If it is, drop it.
         for device_entry in self.device_map.iter() {
-          if device_entry.value().address() == address {
-            debug!("Device {} already connected, ignoring new device emission", address);
+          if device_entry.value().peripheral_address() == address {
+            debug!(
+              "Device {} already connected, ignoring new device emission",
+              redact_address(&address)
+            );
+            return;
+          }
+        }
+        // Same goes if it's connected through another comm manager, unless
+        // this one has a transport we'd rather use.
+        if let Some((device_index, rank)) = self.find_physical_duplicate(&address) {
+          if self.transport_rank(metadata.transport()) >= rank {
+            debug!(
+              "Device {} already connected as device {}, ignoring new device emission",
+              redact_address(&address),
+              device_index
+            );
             return;
           }
         }
-        self.try_create_new_device(creator);
+        self
+          .device_comm_managers
+          .insert(address.clone(), comm_manager.to_owned());
+        self
+          .diagnostics
+          .device_found(&address, comm_manager, metadata.transport());
+        self.device_transports.insert(address, metadata.transport());
+        self.try_create_new_device(creator, metadata);
+      }
+      DeviceCommunicationEvent::AdapterRemoved(err) => {
+        self.handle_adapter_removed(comm_manager, err);
+      }
+      DeviceCommunicationEvent::Error(err) => {
+        error!("{} reported an error: {}", comm_manager, err);
+        self.diagnostics.comm_manager_error(comm_manager, err.to_string());
+        // Let clients know, since this usually means devices on this comm
+        // manager won't show up until something is fixed on the user's end.
+        if !self.event_bus.publish(ServerErrorEvent::CommManager(err)) {
+          debug!("Server not currently available, dropping comm manager error.");
+        }
       }
-      DeviceCommunicationEvent::DeviceManagerAdded(status) => {
-        self.comm_manager_scanning_statuses.push(status);
+    }
+  }
+
+  /// Removes every device found by `comm_manager` right away, instead of
+  /// waiting for each of them to fail on their own, then lets clients know
+  /// why.
+  fn handle_adapter_removed(&mut self, comm_manager: &str, err: ButtplugDeviceError) {
+    error!("{} lost its adapter: {}", comm_manager, err);
+    self.diagnostics.adapter_removed(comm_manager, err.to_string());
+    let lost_devices: Vec<u32> = self
+      .device_map
+      .iter()
+      .filter(|device| {
+        self
+          .device_comm_managers
+          .get(device.value().peripheral_address())
+          .map_or(false, |name| name == comm_manager)
+      })
+      .map(|device| *device.key())
+      .collect();
+    for device_index in lost_devices {
+      info!("Removing device {} after adapter loss.", device_index);
+      if self.device_map.remove(&device_index).is_some() {
+        self.send_device_removed(device_index);
       }
     }
+    if !self.event_bus.publish(ServerErrorEvent::AdapterRemoved(err)) {
+      debug!("Server not currently available, dropping adapter removal error.");
+    }
+  }
+
+  fn send_device_removed(&self, device_index: u32) {
+    if !self
+      .event_bus
+      .publish(DeviceLifecycleEvent::Removed(device_index))
+    {
+      debug!("Server not currently available, dropping Device Removed event.");
+    }
   }
 
-  async fn handle_device_event(&mut self, device_event: ButtplugDeviceEvent) {
-    trace!("Got device event: {:?}", device_event);
+  async fn handle_device_event(&mut self, device_event: DeviceLoopEvent) {
     match device_event {
-      ButtplugDeviceEvent::Connected(device) => {
+      DeviceLoopEvent::Connected(device, metadata) => {
         let span = info_span!(
           "device registration",
           name = tracing::field::display(device.name()),
-          address = tracing::field::display(device.address())
+          address = tracing::field::display(redact_address(device.address()))
         );
         let _enter = span.enter();
-        let generated_device_index = self.device_index_generator;
-        self.device_index_generator += 1;
-        // See if we have a reusable device index here.
-        let device_index = if let Some(id) = self.device_index_map.get(device.address()) {
-          *id.value()
-        } else {
+        trace!("Got device connection: {}", device.name());
+        // Connections to the same physical device through different comm
+        // managers can race each other. Keep whichever has the better
+        // transport, with the one that got here first winning ties. The
+        // replacement takes over the old device's index, so it's removed
+        // like any other index collision below.
+        if let Some((existing_index, rank)) =
+          self.find_physical_duplicate(device.peripheral_address())
+        {
+          if self.transport_rank(metadata.transport()) >= rank {
+            info!(
+              "Device already connected as device {}, dropping duplicate connection.",
+              existing_index
+            );
+            if let Err(err) = device.disconnect().await {
+              error!("Error disconnecting duplicate device: {:?}", err);
+            }
+            return;
+          }
+          info!(
+            "Device {} reconnected over a preferred transport, replacing.",
+            existing_index
+          );
           self
             .device_index_map
-            .insert(device.address().to_owned(), generated_device_index);
-          generated_device_index
+            .insert(device.address().to_owned(), existing_index);
+        }
+        // See if this device has had an index before.
+        let existing_index = self.device_index_map.get(device.address()).map(|id| *id.value());
+        let device_index = match existing_index {
+          Some(device_index) => device_index,
+          None => {
+            let device_index = self.device_index_generator;
+            self.device_index_generator += 1;
+            self
+              .device_index_map
+              .insert(device.address().to_owned(), device_index);
+            device_index
+          }
         };
-        // Since we can now reuse device indexes, this means we might possibly
-        // stomp on devices already in the map if they don't register a
-        // disconnect before we try to insert the new device. If we have a
-        // device already in the map with the same index (and therefore same
-        // address), consider it disconnected and eject it from the map. This
-        // should also trigger a disconnect event before our new DeviceAdded
-        // message goes out, so timing matters here.
-        if self.device_map.contains_key(&device_index) {
-          info!("Device map contains key {}.", device_index);
-          // We just checked that the key exists, so we can unwrap
-          // here.
-          let (_, old_device) = self.device_map.remove(&device_index).unwrap();
-          // After removing the device from the array, manually disconnect it to
-          // make sure the event is thrown.
+        // Since devices get their indexes back on reconnect, we might stomp on
+        // the old connection if it didn't register a disconnect before the new
+        // one showed up. If so, consider the old one disconnected and eject
+        // it. Clients need to see DeviceRemoved before the new DeviceAdded, so
+        // send it here, and let the old device's own removal event be ignored
+        // when it shows up.
+        if let Some((_, old_device)) = self.device_map.remove(&device_index) {
+          info!("Device map contains key {}, replacing.", device_index);
+          self.send_device_removed(device_index);
           if let Err(err) = old_device.disconnect().await {
             // If we throw an error during the disconnect, we can't really do
             // anything with it, but should at least log it.
             error!("Error during index collision disconnect: {:?}", err);
           }
-        } else {
-          info!("Device map does not contain key {}.", device_index);
+        }
+
+        device.set_write_watchdog(self.device_write_watchdog);
+        device.set_idle_power_management(self.device_idle_power_management);
+        let device_name = self
+          .device_config_manager
+          .display_name(device.address())
+          .unwrap_or_else(|| device.name());
+        if self.dry_run {
+          let event_bus = self.event_bus.clone();
+          let device_name = device_name.clone();
+          device.set_dry_run(Some(Arc::new(move |command| {
+            event_bus.publish(DeviceDryRunWrite {
+              device_index,
+              device_name: device_name.clone(),
+              command,
+            });
+          })));
         }
 
         // Create event loop for forwarding device events into our selector.
         let mut event_listener = device.event_stream();
+        let mut error_listener = device.error_stream();
         let event_sender = self.device_event_sender.clone();
+        let weak_device = Arc::downgrade(&device);
         async_manager::spawn(async move {
-          while let Ok(event) = event_listener.recv().await {
-            event_sender.send(event).await.unwrap();
+          loop {
+            let loop_event = select! {
+              event = event_listener.recv().fuse() => match event {
+                Ok(event) => DeviceLoopEvent::Device(device_index, weak_device.clone(), event),
+                Err(_) => break,
+              },
+              err = error_listener.recv().fuse() => match err {
+                Ok(err) => DeviceLoopEvent::DeviceError(device_index, err),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+              },
+            };
+            event_sender.send(loop_event).await.unwrap();
           }
         })
         .unwrap();
 
         info!("Assigning index {} to {}", device_index, device.name());
         let device_added_message =
-          DeviceAdded::new(device_index, &device.name(), &device.message_attributes());
+          DeviceAdded::new(device_index, &device_name, &device.message_attributes())
+            .with_device_metadata(metadata);
         self.device_map.insert(device_index, device);
         // After that, we can send out to the server's event listeners to let
         // them know a device has been added.
-        if self
-          .server_sender
-          .send(device_added_message.into())
-          .is_err()
+        if !self
+          .event_bus
+          .publish(DeviceLifecycleEvent::Added(device_added_message))
         {
           debug!("Server not currently available, dropping Device Added event.");
         }
       }
-      ButtplugDeviceEvent::Removed(address) => {
-        let device_index = *self.device_index_map.get(&address).unwrap().value();
-        self.device_map.remove(&device_index).unwrap();
-        if self
-          .server_sender
-          .send(DeviceRemoved::new(device_index).into())
-          .is_err()
+      DeviceLoopEvent::ConnectionAttemptFinished => {
+        self.pending_connections -= 1;
+        if self.pending_connections == 0
+          && self.scanning_in_progress
+          && self.scanning_comm_managers.is_empty()
         {
-          debug!("Server not currently available, dropping Device Removed event.");
+          self.finish_scanning();
         }
       }
-      ButtplugDeviceEvent::Notification(_address, _endpoint, _data) => {
-        // TODO At some point here we need to fill this in for RawSubscribe and
-        // other sensor subscriptions.
+      DeviceLoopEvent::Device(device_index, source, event) => {
+        trace!("Got device event: {:?}", event);
+        match event {
+          ButtplugDeviceEvent::Removed(_) => {
+            // The device may already be gone (i.e. its comm manager lost its
+            // adapter), or have been replaced by a reconnection. Either way,
+            // DeviceRemoved was already sent for it.
+            if self
+              .device_map
+              .remove_if(&device_index, |_, current| {
+                Arc::as_ptr(current) == source.as_ptr()
+              })
+              .is_none()
+            {
+              debug!("Device {} already removed, ignoring.", device_index);
+              return;
+            }
+            self.send_device_removed(device_index);
+          }
+          ButtplugDeviceEvent::Notification(_address, endpoint, data) => {
+            // Protocols subscribe to endpoints for their own reasons too, so
+            // only pass on notifications clients asked for.
+            match self.device_map.get(&device_index) {
+              Some(device) if device.raw_subscribed(&endpoint) => {}
+              _ => return,
+            }
+            let mut reading = RawReading::new(device_index, endpoint, data);
+            reading.set_id(0);
+            if !self.event_bus.publish(reading) {
+              debug!("Server not currently available, dropping RawReading.");
+            }
+          }
+          ButtplugDeviceEvent::Connected(_) => {}
+        }
+      }
+      DeviceLoopEvent::DeviceError(device_index, err) => {
+        error!("Device {} reported an error: {}", device_index, err);
+        if !self.event_bus.publish(ServerErrorEvent::Device(err)) {
+          debug!("Server not currently available, dropping device error.");
+        }
+      }
+    }
+  }
+
+  /// Removes devices whose transport says they aren't connected anymore.
+  /// Devices should send a removal event when that happens, but not all
+  /// transports can tell us right away.
+  fn handle_connection_check(&mut self) {
+    self.connection_check_timer = Delay::new(DEVICE_CONNECTION_CHECK_INTERVAL);
+    let disconnected_devices: Vec<u32> = self
+      .device_map
+      .iter()
+      .filter(|device| !device.value().connected())
+      .map(|device| *device.key())
+      .collect();
+    for device_index in disconnected_devices {
+      info!("Device {} no longer connected, removing.", device_index);
+      if self.device_map.remove(&device_index).is_some() {
+        self.send_device_removed(device_index);
       }
     }
   }
@@ -262,11 +656,17 @@ impl DeviceManagerEventLoop {
         },
         device_comm_msg = self.device_comm_receiver.recv().fuse() => {
           if let Some(msg) = device_comm_msg {
-            self.handle_device_communication(msg).await;
+            self.handle_device_manager_event(msg);
           } else {
             break;
           }
         }
+        _ = wait_for_timeout(&mut self.scanning_finished_timeout).fuse() => {
+          self.handle_scanning_finished_timeout();
+        }
+        _ = (&mut self.connection_check_timer).fuse() => {
+          self.handle_connection_check();
+        }
         device_event_msg = self.device_event_receiver.recv().fuse() => {
           if let Some(msg) = device_event_msg {
             self.handle_device_event(msg).await;
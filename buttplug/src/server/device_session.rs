@@ -0,0 +1,313 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Time limits on how long a device runs.
+//!
+//! A session starts with the first command a client sends to a device that
+//! has a limit. Once the limit's duration runs out, the server ramps the
+//! device's vibration and rotation speeds down to nothing over the limit's
+//! wind down time, then stops it, so patterns left running unattended don't
+//! run forever. Commands sent after that fail with
+//! [DeviceSessionExpired][crate::core::errors::ButtplugDeviceError::DeviceSessionExpired],
+//! until the client sets a new limit or reconnects. StopDeviceCmd always goes
+//! through.
+//!
+//! Limits come from the `session-limit` setting for the device in the user
+//! device config, or from the client via DeviceSessionLimitCmd. If both are
+//! set, the shorter one is used, so clients can't get around limits the user
+//! set.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    messages::{
+      ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessage, RotateCmd, RotationSubcommand,
+      StopDeviceCmd, VibrateCmd, VibrateSubcommand,
+    },
+  },
+  device::{configuration_manager::UserDeviceSessionLimit, ButtplugDevice},
+  util::async_manager,
+};
+use dashmap::DashMap;
+use futures_timer::Delay;
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Weak,
+  },
+  time::Duration,
+};
+
+/// How often speeds are lowered while winding down.
+pub const DEVICE_SESSION_WIND_DOWN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceSessionLimit {
+  /// Time from the first command until the device starts winding down.
+  pub duration: Duration,
+  /// Time taken to ramp the device down to a stop.
+  pub wind_down: Duration,
+}
+
+impl From<UserDeviceSessionLimit> for DeviceSessionLimit {
+  fn from(limit: UserDeviceSessionLimit) -> Self {
+    Self {
+      duration: Duration::from_millis(limit.duration.into()),
+      wind_down: Duration::from_millis(limit.wind_down.into()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeviceSessionPhase {
+  Idle,
+  Running,
+  WindingDown,
+  Expired,
+}
+
+// Deriving this needs `#[default]`, which is newer than our MSRV.
+#[allow(clippy::derivable_impls)]
+impl Default for DeviceSessionPhase {
+  fn default() -> Self {
+    DeviceSessionPhase::Idle
+  }
+}
+
+#[derive(Default)]
+struct DeviceSession {
+  client_limit: Option<DeviceSessionLimit>,
+  phase: DeviceSessionPhase,
+  // Unique to each session. Timers give up once this changes, so resetting a
+  // session doesn't leave an old timer around to wind down the new one.
+  generation: u32,
+  // Last speeds sent, by feature, to ramp down from.
+  vibrate_speeds: BTreeMap<u32, f64>,
+  rotations: BTreeMap<u32, (f64, bool)>,
+}
+
+impl DeviceSession {
+  fn record(&mut self, msg: &ButtplugDeviceCommandMessageUnion) {
+    match msg {
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        for subcommand in msg.speeds() {
+          self
+            .vibrate_speeds
+            .insert(subcommand.index(), subcommand.speed());
+        }
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        for subcommand in &msg.rotations {
+          self
+            .rotations
+            .insert(subcommand.index(), (subcommand.speed(), subcommand.clockwise()));
+        }
+      }
+      _ => {}
+    }
+  }
+
+  /// The last speeds sent, multiplied by `scale`.
+  fn scaled_commands(
+    &self,
+    device_index: u32,
+    scale: f64,
+  ) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    let mut commands = vec![];
+    if !self.vibrate_speeds.is_empty() {
+      let speeds = self
+        .vibrate_speeds
+        .iter()
+        .map(|(index, speed)| VibrateSubcommand::new(*index, speed * scale))
+        .collect();
+      commands.push(VibrateCmd::new(device_index, speeds).into());
+    }
+    if !self.rotations.is_empty() {
+      let rotations = self
+        .rotations
+        .iter()
+        .map(|(index, (speed, clockwise))| {
+          RotationSubcommand::new(*index, speed * scale, *clockwise)
+        })
+        .collect();
+      commands.push(RotateCmd::new(device_index, rotations).into());
+    }
+    commands
+  }
+}
+
+/// Session state for the connected client, by device index.
+#[derive(Default)]
+pub(super) struct DeviceSessions {
+  sessions: Arc<DashMap<u32, DeviceSession>>,
+  next_generation: AtomicU32,
+}
+
+impl DeviceSessions {
+  fn next_generation(&self) -> u32 {
+    self.next_generation.fetch_add(1, Ordering::SeqCst)
+  }
+
+  /// Sets the limit the client asked for, or removes it if `None`. Starts the
+  /// device's session over.
+  pub fn set_client_limit(&self, device_index: u32, limit: Option<DeviceSessionLimit>) {
+    let generation = self.next_generation();
+    let mut session = self.sessions.entry(device_index).or_default();
+    *session = DeviceSession {
+      client_limit: limit,
+      generation,
+      ..Default::default()
+    };
+  }
+
+  /// Checks that `device` can still be sent `msg`, starting its session if
+  /// this is the first command. `user_limit` is the limit from the user
+  /// config, if any.
+  pub fn handle_command(
+    &self,
+    device: &Arc<ButtplugDevice>,
+    user_limit: Option<DeviceSessionLimit>,
+    msg: &ButtplugDeviceCommandMessageUnion,
+  ) -> Result<(), ButtplugDeviceError> {
+    let device_index = msg.device_index();
+    if matches!(msg, ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_)) {
+      // Nothing left to wind down.
+      if let Some(mut session) = self.sessions.get_mut(&device_index) {
+        session.vibrate_speeds.clear();
+        session.rotations.clear();
+      }
+      return Ok(());
+    }
+    let mut session = self
+      .sessions
+      .entry(device_index)
+      .or_insert_with(|| DeviceSession {
+        generation: self.next_generation(),
+        ..Default::default()
+      });
+    let limit = match (session.client_limit, user_limit) {
+      (Some(client_limit), Some(user_limit)) if user_limit.duration < client_limit.duration => {
+        user_limit
+      }
+      (Some(limit), _) | (None, Some(limit)) => limit,
+      (None, None) => return Ok(()),
+    };
+    match session.phase {
+      DeviceSessionPhase::WindingDown | DeviceSessionPhase::Expired => {
+        return Err(ButtplugDeviceError::DeviceSessionExpired(device_index))
+      }
+      DeviceSessionPhase::Idle => {
+        debug!("Starting {:?} session for device {}", limit.duration, device_index);
+        session.phase = DeviceSessionPhase::Running;
+        async_manager::spawn(run_session_timer(
+          Arc::downgrade(&self.sessions),
+          Arc::downgrade(device),
+          device_index,
+          session.generation,
+          limit,
+        ))
+        .unwrap();
+      }
+      DeviceSessionPhase::Running => {}
+    }
+    session.record(msg);
+    Ok(())
+  }
+
+  pub fn clear(&self) {
+    self.sessions.clear();
+  }
+}
+
+async fn send_to_device(device: &Weak<ButtplugDevice>, msg: ButtplugDeviceCommandMessageUnion) {
+  let device_index = msg.device_index();
+  let fut = match device.upgrade() {
+    Some(device) => device.parse_message(msg),
+    None => return,
+  };
+  if let Err(err) = fut.await {
+    error!("Error winding down device {}: {}", device_index, err);
+  }
+}
+
+async fn run_session_timer(
+  sessions: Weak<DashMap<u32, DeviceSession>>,
+  device: Weak<ButtplugDevice>,
+  device_index: u32,
+  generation: u32,
+  limit: DeviceSessionLimit,
+) {
+  // Runs `f` on the session, if it's still the one this timer is for.
+  let with_session = |f: &mut dyn FnMut(&mut DeviceSession)| -> bool {
+    let sessions = match sessions.upgrade() {
+      Some(sessions) => sessions,
+      None => return false,
+    };
+    let mut session = match sessions.get_mut(&device_index) {
+      Some(session) => session,
+      None => return false,
+    };
+    if session.generation != generation {
+      return false;
+    }
+    f(&mut session);
+    true
+  };
+  Delay::new(limit.duration).await;
+  if !with_session(&mut |session| session.phase = DeviceSessionPhase::WindingDown) {
+    return;
+  }
+  info!("Session limit for device {} reached, winding down.", device_index);
+  let steps = (limit.wind_down.as_millis() / DEVICE_SESSION_WIND_DOWN_INTERVAL.as_millis())
+    .max(1) as u32;
+  let interval = limit.wind_down / steps;
+  for step in 1..steps {
+    Delay::new(interval).await;
+    let scale = 1.0 - step as f64 / steps as f64;
+    let mut commands = vec![];
+    if !with_session(&mut |session| commands = session.scaled_commands(device_index, scale)) {
+      return;
+    }
+    for msg in commands {
+      send_to_device(&device, msg).await;
+    }
+  }
+  Delay::new(interval).await;
+  if with_session(&mut |session| session.phase = DeviceSessionPhase::Expired) {
+    send_to_device(&device, StopDeviceCmd::new(device_index).into()).await;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_device_session_scaled_commands() {
+    let mut session = DeviceSession::default();
+    assert!(session.scaled_commands(1, 0.5).is_empty());
+    session.record(&VibrateCmd::new(1, vec![VibrateSubcommand::new(0, 1.0)]).into());
+    // Vibrators left out of later commands keep their last speed.
+    session.record(&VibrateCmd::new(1, vec![VibrateSubcommand::new(1, 0.5)]).into());
+    session.record(&RotateCmd::new(1, vec![RotationSubcommand::new(0, 0.8, true)]).into());
+    assert_eq!(
+      session.scaled_commands(1, 0.5),
+      vec![
+        VibrateCmd::new(
+          1,
+          vec![
+            VibrateSubcommand::new(0, 0.5),
+            VibrateSubcommand::new(1, 0.25)
+          ]
+        )
+        .into(),
+        RotateCmd::new(1, vec![RotationSubcommand::new(0, 0.4, true)]).into(),
+      ]
+    );
+  }
+}
@@ -0,0 +1,170 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Snapshot of what the server is up to, for attaching to bug reports.
+//!
+//! [ButtplugServer::diagnostics][super::ButtplugServer::diagnostics] gathers
+//! comm manager, adapter and device state into a [ServerDiagnostics], which
+//! front-ends can serialize and ask users to include when they file issues.
+//! Device addresses go through
+//! [redact_address][crate::util::logging::redact_address], so they're
+//! treated the same way as they are in logs.
+
+use super::ButtplugServerConnectionState;
+use crate::core::messages::{ButtplugMessageSpecVersion, DeviceTransport};
+use dashmap::DashMap;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ServerDiagnostics {
+  pub server_name: String,
+  pub connection_state: ButtplugServerConnectionState,
+  /// Name the connected client gave in its handshake.
+  pub client_name: Option<String>,
+  pub client_message_version: Option<ButtplugMessageSpecVersion>,
+  pub comm_managers: Vec<CommManagerDiagnostics>,
+  pub devices: Vec<DeviceDiagnostics>,
+  /// Comm manager events waiting on the device manager. If this stays near
+  /// [DEVICE_MANAGER_EVENT_QUEUE_SIZE][super::device_manager::DEVICE_MANAGER_EVENT_QUEUE_SIZE],
+  /// the device manager is falling behind.
+  pub device_manager_queue_depth: usize,
+}
+
+/// Whether a comm manager's adapter (bluetooth radio, dongle, etc) is usable,
+/// as far as the server knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum AdapterState {
+  /// Nothing has gone wrong with it yet.
+  Available,
+  /// The comm manager reported its adapter gone, and scanning hasn't
+  /// started successfully since.
+  Removed,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct CommManagerDiagnostics {
+  pub name: String,
+  pub scanning: bool,
+  pub adapter_state: AdapterState,
+  /// Last error the comm manager reported, including adapter removal.
+  pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceDiagnostics {
+  pub device_index: u32,
+  pub name: String,
+  /// Device address, redacted according to the log redaction mode.
+  pub address: String,
+  /// Transport the device was found on, if a comm manager found it.
+  pub transport: Option<DeviceTransport>,
+  /// Comm manager that found the device.
+  pub comm_manager: Option<String>,
+  pub connected: bool,
+  pub write_count: u32,
+  pub error_count: u32,
+  pub average_write_latency_ms: u32,
+  pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct CommManagerHealth {
+  pub adapter_state: AdapterState,
+  pub last_error: Option<String>,
+}
+
+impl Default for CommManagerHealth {
+  fn default() -> Self {
+    Self {
+      adapter_state: AdapterState::Available,
+      last_error: None,
+    }
+  }
+}
+
+/// State that only the device manager event loop sees, shared with the
+/// device manager so it can be put in snapshots.
+#[derive(Default, Clone)]
+pub(super) struct DiagnosticsRecorder {
+  comm_managers: Arc<DashMap<String, CommManagerHealth>>,
+  // Comm manager name and transport, by peripheral address.
+  device_origins: Arc<DashMap<String, (String, DeviceTransport)>>,
+}
+
+impl DiagnosticsRecorder {
+  pub fn comm_manager_error(&self, comm_manager: &str, error: String) {
+    self
+      .comm_managers
+      .entry(comm_manager.to_owned())
+      .or_default()
+      .last_error = Some(error);
+  }
+
+  pub fn adapter_removed(&self, comm_manager: &str, error: String) {
+    let mut health = self
+      .comm_managers
+      .entry(comm_manager.to_owned())
+      .or_default();
+    health.adapter_state = AdapterState::Removed;
+    health.last_error = Some(error);
+  }
+
+  /// Called once a comm manager starts scanning, which means it found an
+  /// adapter to scan with.
+  pub fn adapter_available(&self, comm_manager: &str) {
+    if let Some(mut health) = self.comm_managers.get_mut(comm_manager) {
+      health.adapter_state = AdapterState::Available;
+    }
+  }
+
+  pub fn comm_manager_health(&self, comm_manager: &str) -> CommManagerHealth {
+    self
+      .comm_managers
+      .get(comm_manager)
+      .map(|health| health.clone())
+      .unwrap_or_default()
+  }
+
+  pub fn device_found(&self, address: &str, comm_manager: &str, transport: DeviceTransport) {
+    self
+      .device_origins
+      .insert(address.to_owned(), (comm_manager.to_owned(), transport));
+  }
+
+  pub fn device_origin(&self, address: &str) -> Option<(String, DeviceTransport)> {
+    self.device_origins.get(address).map(|origin| origin.clone())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_adapter_state_tracking() {
+    let recorder = DiagnosticsRecorder::default();
+    assert_eq!(
+      recorder.comm_manager_health("TestCommManager").adapter_state,
+      AdapterState::Available
+    );
+    recorder.adapter_removed("TestCommManager", "adapter powered off".to_owned());
+    let health = recorder.comm_manager_health("TestCommManager");
+    assert_eq!(health.adapter_state, AdapterState::Removed);
+    assert_eq!(health.last_error, Some("adapter powered off".to_owned()));
+    recorder.adapter_available("TestCommManager");
+    let health = recorder.comm_manager_health("TestCommManager");
+    assert_eq!(health.adapter_state, AdapterState::Available);
+    // The error sticks around, since it's still what went wrong last.
+    assert_eq!(health.last_error, Some("adapter powered off".to_owned()));
+  }
+}
@@ -0,0 +1,336 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Internal event bus for the server.
+//!
+//! Everything the server needs to tell the outside world about (devices coming
+//! and going, scanning, errors nobody asked for) is published here as a typed
+//! event, grouped by topic. The server turns these into
+//! [ButtplugServerMessage]s for its event stream, but anything inside the
+//! server can subscribe and react to them too.
+//!
+//! RawReadings from endpoints clients have subscribed to with RawSubscribeCmd
+//! go out here as well.
+//!
+//! Device commands are published too, after they've passed permission checks
+//! and command transforms, so embedders can audit (or build consent UIs on
+//! top of) what clients are doing. These are never sent to clients.
+//!
+//! In dry run mode, device writes that would've gone to the hardware are
+//! published here instead.
+//!
+//! Device consent requests are published here as well, for the embedder to
+//! answer. See [device_consent][super::device_consent].
+//!
+//! Clients flooding devices with commands are reported here too. See
+//! [command_storm_guard][super::command_storm_guard].
+//!
+//! All topics share one broadcast channel, so subscribers see events in the
+//! order they were published, even across topics. This matters for things
+//! like clients expecting every DeviceAdded from a scan to arrive before
+//! ScanningFinished.
+//!
+//! Requests going *into* the device manager event loop (comm manager events,
+//! scanning state changes) stay on mpsc channels, since they only have one
+//! consumer and carry device creators, which can't be cloned.
+
+use super::{command_storm_guard::DeviceCommandStorm, device_consent::DeviceConsentRequest};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugPingError},
+    messages::{
+      self, ButtplugDeviceCommandMessageUnion, ButtplugServerMessage, DeviceAdded, DeviceRemoved,
+      DeviceUpdated, RawReading, ScanningFinished,
+    },
+  },
+  device::DeviceWriteCmd,
+  util::stream::convert_broadcast_receiver_to_stream,
+};
+use futures::{future, Stream, StreamExt};
+use tokio::sync::broadcast;
+
+/// Capacity of the bus. Subscribers that fall further behind than this will
+/// start missing events.
+const SERVER_EVENT_BUS_CAPACITY: usize = 256;
+
+/// Devices being added to or removed from the server, or changing while
+/// connected.
+#[derive(Debug, Clone)]
+pub enum DeviceLifecycleEvent {
+  Added(DeviceAdded),
+  /// A connected device's name or message attributes changed.
+  Updated(DeviceUpdated),
+  Removed(u32),
+}
+
+/// Device scanning state changes.
+#[derive(Debug, Clone)]
+pub enum ScanningEvent {
+  /// All comm managers have finished scanning, and all devices found while
+  /// scanning have finished connecting.
+  Finished,
+}
+
+/// Errors that didn't come from handling a client message, so need to be sent
+/// out on their own.
+#[derive(Debug, Clone)]
+pub enum ServerErrorEvent {
+  /// The client missed a ping.
+  PingedOut,
+  /// A comm manager reported an error.
+  CommManager(ButtplugDeviceError),
+  /// A comm manager lost its adapter.
+  AdapterRemoved(ButtplugDeviceError),
+  /// A device reported an error outside of a command, i.e. a write from a
+  /// protocol task that got stuck.
+  Device(ButtplugDeviceError),
+}
+
+impl From<ServerErrorEvent> for ButtplugError {
+  fn from(event: ServerErrorEvent) -> Self {
+    match event {
+      ServerErrorEvent::PingedOut => ButtplugPingError::PingedOut.into(),
+      ServerErrorEvent::CommManager(err)
+      | ServerErrorEvent::AdapterRemoved(err)
+      | ServerErrorEvent::Device(err) => err.into(),
+    }
+  }
+}
+
+/// A device command a client sent, on its way to the device.
+#[derive(Debug, Clone)]
+pub struct DeviceCommandAudit {
+  /// Name the client gave in its handshake.
+  pub client_name: String,
+  pub device_index: u32,
+  pub device_name: String,
+  /// The command as it will be sent to the device, i.e. after any command
+  /// transforms have run.
+  pub message: ButtplugDeviceCommandMessageUnion,
+}
+
+/// A write that would have been sent to a device, if the server wasn't in dry
+/// run mode.
+#[derive(Debug, Clone)]
+pub struct DeviceDryRunWrite {
+  pub device_index: u32,
+  pub device_name: String,
+  pub command: DeviceWriteCmd,
+}
+
+/// Events published on the [ServerEventBus], tagged by topic.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+  DeviceLifecycle(DeviceLifecycleEvent),
+  Scanning(ScanningEvent),
+  Error(ServerErrorEvent),
+  RawReading(RawReading),
+  DeviceCommand(DeviceCommandAudit),
+  DeviceConsent(DeviceConsentRequest),
+  DryRunWrite(DeviceDryRunWrite),
+  DeviceCommandStorm(DeviceCommandStorm),
+}
+
+impl From<DeviceLifecycleEvent> for ServerEvent {
+  fn from(event: DeviceLifecycleEvent) -> Self {
+    ServerEvent::DeviceLifecycle(event)
+  }
+}
+
+impl From<ScanningEvent> for ServerEvent {
+  fn from(event: ScanningEvent) -> Self {
+    ServerEvent::Scanning(event)
+  }
+}
+
+impl From<ServerErrorEvent> for ServerEvent {
+  fn from(event: ServerErrorEvent) -> Self {
+    ServerEvent::Error(event)
+  }
+}
+
+impl From<RawReading> for ServerEvent {
+  fn from(event: RawReading) -> Self {
+    ServerEvent::RawReading(event)
+  }
+}
+
+impl From<DeviceCommandAudit> for ServerEvent {
+  fn from(event: DeviceCommandAudit) -> Self {
+    ServerEvent::DeviceCommand(event)
+  }
+}
+
+impl From<DeviceConsentRequest> for ServerEvent {
+  fn from(event: DeviceConsentRequest) -> Self {
+    ServerEvent::DeviceConsent(event)
+  }
+}
+
+impl From<DeviceDryRunWrite> for ServerEvent {
+  fn from(event: DeviceDryRunWrite) -> Self {
+    ServerEvent::DryRunWrite(event)
+  }
+}
+
+impl From<DeviceCommandStorm> for ServerEvent {
+  fn from(event: DeviceCommandStorm) -> Self {
+    ServerEvent::DeviceCommandStorm(event)
+  }
+}
+
+impl ServerEvent {
+  /// The message to send to the client for this event, if it's one clients
+  /// get told about.
+  pub fn into_server_message(self) -> Option<ButtplugServerMessage> {
+    match self {
+      ServerEvent::DeviceLifecycle(DeviceLifecycleEvent::Added(msg)) => Some(msg.into()),
+      ServerEvent::DeviceLifecycle(DeviceLifecycleEvent::Updated(msg)) => Some(msg.into()),
+      ServerEvent::DeviceLifecycle(DeviceLifecycleEvent::Removed(index)) => {
+        Some(DeviceRemoved::new(index).into())
+      }
+      ServerEvent::Scanning(ScanningEvent::Finished) => Some(ScanningFinished::default().into()),
+      ServerEvent::Error(err) => Some(messages::Error::from(ButtplugError::from(err)).into()),
+      ServerEvent::RawReading(msg) => Some(msg.into()),
+      ServerEvent::DeviceCommand(_)
+      | ServerEvent::DeviceConsent(_)
+      | ServerEvent::DryRunWrite(_)
+      | ServerEvent::DeviceCommandStorm(_) => None,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct ServerEventBus {
+  sender: broadcast::Sender<ServerEvent>,
+}
+
+impl Default for ServerEventBus {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(SERVER_EVENT_BUS_CAPACITY);
+    Self { sender }
+  }
+}
+
+impl ServerEventBus {
+  /// Publishes an event to all current subscribers. Returns false if there
+  /// was no one around to get it.
+  pub fn publish<T>(&self, event: T) -> bool
+  where
+    T: Into<ServerEvent>,
+  {
+    self.sender.send(event.into()).is_ok()
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+    self.sender.subscribe()
+  }
+
+  /// Stream of everything published on the bus that clients get told about,
+  /// as the messages sent to them. Ends if the subscriber falls too far
+  /// behind.
+  pub fn server_message_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+    convert_broadcast_receiver_to_stream(self.subscribe())
+      .filter_map(|event| future::ready(event.into_server_message()))
+  }
+
+  /// Stream of device commands published on the bus. Ends if the subscriber
+  /// falls too far behind.
+  pub fn device_command_stream(&self) -> impl Stream<Item = DeviceCommandAudit> {
+    convert_broadcast_receiver_to_stream(self.subscribe()).filter_map(|event| {
+      future::ready(match event {
+        ServerEvent::DeviceCommand(audit) => Some(audit),
+        _ => None,
+      })
+    })
+  }
+
+  /// Stream of device consent requests published on the bus. Ends if the
+  /// subscriber falls too far behind.
+  pub fn device_consent_request_stream(&self) -> impl Stream<Item = DeviceConsentRequest> {
+    convert_broadcast_receiver_to_stream(self.subscribe()).filter_map(|event| {
+      future::ready(match event {
+        ServerEvent::DeviceConsent(request) => Some(request),
+        _ => None,
+      })
+    })
+  }
+
+  /// Stream of dry run writes published on the bus. Ends if the subscriber
+  /// falls too far behind.
+  pub fn dry_run_write_stream(&self) -> impl Stream<Item = DeviceDryRunWrite> {
+    convert_broadcast_receiver_to_stream(self.subscribe()).filter_map(|event| {
+      future::ready(match event {
+        ServerEvent::DryRunWrite(write) => Some(write),
+        _ => None,
+      })
+    })
+  }
+
+  /// Stream of device command storms published on the bus. Ends if the
+  /// subscriber falls too far behind.
+  pub fn device_command_storm_stream(&self) -> impl Stream<Item = DeviceCommandStorm> {
+    convert_broadcast_receiver_to_stream(self.subscribe()).filter_map(|event| {
+      future::ready(match event {
+        ServerEvent::DeviceCommandStorm(storm) => Some(storm),
+        _ => None,
+      })
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{core::messages::StopDeviceCmd, util::async_manager};
+
+  #[test]
+  fn test_event_bus_keeps_order_across_topics() {
+    async_manager::block_on(async move {
+      let bus = ServerEventBus::default();
+      // Nothing listening yet.
+      assert!(!bus.publish(ScanningEvent::Finished));
+      let mut receiver = bus.subscribe();
+      let stream = bus.server_message_stream();
+      assert!(bus.publish(DeviceLifecycleEvent::Removed(1)));
+      assert!(bus.publish(ScanningEvent::Finished));
+      assert!(bus.publish(ServerErrorEvent::PingedOut));
+      assert!(bus.publish(DeviceCommandAudit {
+        client_name: "Test Client".to_owned(),
+        device_index: 1,
+        device_name: "Test Device".to_owned(),
+        message: StopDeviceCmd::new(1).into(),
+      }));
+      assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ServerEvent::DeviceLifecycle(DeviceLifecycleEvent::Removed(1))
+      ));
+      assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ServerEvent::Scanning(ScanningEvent::Finished)
+      ));
+      assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ServerEvent::Error(ServerErrorEvent::PingedOut)
+      ));
+      assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ServerEvent::DeviceCommand(DeviceCommandAudit {
+          device_index: 1,
+          ..
+        })
+      ));
+      drop(bus);
+      // Device commands don't go out to clients.
+      let messages: Vec<ButtplugServerMessage> = stream.collect().await;
+      assert_eq!(messages.len(), 3);
+      assert!(matches!(messages[0], ButtplugServerMessage::DeviceRemoved(_)));
+      assert!(matches!(messages[1], ButtplugServerMessage::ScanningFinished(_)));
+      assert!(matches!(messages[2], ButtplugServerMessage::Error(_)));
+    });
+  }
+}
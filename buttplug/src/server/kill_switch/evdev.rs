@@ -0,0 +1,118 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{KillSwitchSource, KillSwitchTrigger};
+use crate::server::ButtplugServerError;
+use evdev::{Device, InputEventKind, Key};
+use std::{collections::HashSet, path::PathBuf, thread};
+
+/// Kill switch for Linux input devices, read through evdev. Watches every input
+/// device that has all of the switch's keys, so it works for keyboard
+/// shortcuts as well as dedicated buttons. Reading input devices needs access
+/// to /dev/input, which usually means being in the `input` group.
+///
+/// Input isn't grabbed, so other programs still see the keys.
+pub struct EvdevKillSwitch {
+  keys: Vec<Key>,
+  device_name: Option<String>,
+}
+
+impl EvdevKillSwitch {
+  /// Triggers when all of `keys` are held down at once. Holding them down
+  /// only triggers once, they have to be released before it'll trigger again.
+  pub fn new(keys: Vec<Key>) -> Self {
+    Self {
+      keys,
+      device_name: None,
+    }
+  }
+
+  /// Only watches devices with this name, i.e. a dedicated USB button, so the
+  /// same key on a keyboard doesn't trigger it.
+  pub fn with_device_name(mut self, device_name: &str) -> Self {
+    self.device_name = Some(device_name.to_owned());
+    self
+  }
+
+  fn device_matches(&self, device: &Device) -> bool {
+    if let Some(name) = &self.device_name {
+      if device.name() != Some(name.as_str()) {
+        return false;
+      }
+    }
+    match device.supported_keys() {
+      Some(supported) => self.keys.iter().all(|key| supported.contains(*key)),
+      None => false,
+    }
+  }
+}
+
+fn watch_device(path: PathBuf, mut device: Device, keys: Vec<Key>, trigger: KillSwitchTrigger) {
+  let mut held = HashSet::new();
+  let mut triggered = false;
+  loop {
+    let events = match device.fetch_events() {
+      Ok(events) => events,
+      Err(err) => {
+        warn!("Kill switch stopped reading {:?}: {}", path, err);
+        return;
+      }
+    };
+    for event in events {
+      if let InputEventKind::Key(key) = event.kind() {
+        if !keys.contains(&key) {
+          continue;
+        }
+        // 0 is a release, 1 a press, and 2 a key repeat.
+        if event.value() == 0 {
+          held.remove(&key);
+          triggered = false;
+        } else {
+          held.insert(key);
+        }
+      }
+    }
+    if !triggered && held.len() == keys.len() {
+      triggered = true;
+      if !trigger.trigger() {
+        return;
+      }
+    }
+  }
+}
+
+impl KillSwitchSource for EvdevKillSwitch {
+  fn name(&self) -> &str {
+    "EvdevKillSwitch"
+  }
+
+  fn start(self: Box<Self>, trigger: KillSwitchTrigger) -> Result<(), ButtplugServerError> {
+    if self.keys.is_empty() {
+      return Err(ButtplugServerError::KillSwitchStartFailed(
+        self.name().to_owned(),
+        "No keys given.".to_owned(),
+      ));
+    }
+    let devices: Vec<_> = evdev::enumerate()
+      .filter(|(_, device)| self.device_matches(device))
+      .collect();
+    if devices.is_empty() {
+      return Err(ButtplugServerError::KillSwitchStartFailed(
+        self.name().to_owned(),
+        "No input devices with the switch's keys found.".to_owned(),
+      ));
+    }
+    for (path, device) in devices {
+      info!("Kill switch watching {:?} ({:?})", path, device.name());
+      let keys = self.keys.clone();
+      let trigger = trigger.clone();
+      // evdev reads block, so each device gets its own thread.
+      thread::spawn(move || watch_device(path, device, keys, trigger));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,93 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hardware panic buttons.
+//!
+//! A [KillSwitchSource] watches some input outside of the client connection (a
+//! keyboard shortcut, a dedicated USB button, etc), and when it's hit, every
+//! device on the server is stopped right away, whatever the client is doing.
+//! Sources are added with
+//! [ButtplugServer::add_kill_switch][super::ButtplugServer::add_kill_switch].
+//!
+//! With the `evdev-kill-switch` feature, [EvdevKillSwitch] watches Linux input
+//! devices. Anything else can implement [KillSwitchSource] and call
+//! [KillSwitchTrigger::trigger] itself.
+
+#[cfg(all(feature = "evdev-kill-switch", target_os = "linux"))]
+mod evdev;
+#[cfg(all(feature = "evdev-kill-switch", target_os = "linux"))]
+pub use evdev::EvdevKillSwitch;
+
+use super::{scheduled_commands::ScheduledCommands, ButtplugServerError};
+use crate::{core::messages::StopDeviceCmd, device::ButtplugDevice, util::async_manager};
+use dashmap::DashMap;
+use futures::future;
+use std::sync::{Arc, Weak};
+use tokio::sync::mpsc;
+
+/// Something that can stop every device on a server.
+pub trait KillSwitchSource: Send {
+  /// Name used in logs when the switch is hit.
+  fn name(&self) -> &str;
+  /// Starts watching for the switch, calling [KillSwitchTrigger::trigger] each
+  /// time it's hit. Sources should keep running until trigger() returns false.
+  fn start(self: Box<Self>, trigger: KillSwitchTrigger) -> Result<(), ButtplugServerError>;
+}
+
+/// Stops every device on the server it came from. Can be used from any thread,
+/// without an async runtime, so sources can call it from blocking input loops.
+#[derive(Clone)]
+pub struct KillSwitchTrigger {
+  source_name: String,
+  sender: mpsc::UnboundedSender<String>,
+}
+
+impl KillSwitchTrigger {
+  pub(super) fn new(source_name: &str, sender: mpsc::UnboundedSender<String>) -> Self {
+    Self {
+      source_name: source_name.to_owned(),
+      sender,
+    }
+  }
+
+  /// Stops every device. Returns false if the server is gone, in which case
+  /// the source can stop watching.
+  pub fn trigger(&self) -> bool {
+    self.sender.send(self.source_name.clone()).is_ok()
+  }
+}
+
+/// Spawns the task that stops devices whenever a trigger is hit, returning the
+/// sender that triggers use to reach it. The task ends once the device map is
+/// gone.
+pub(super) fn spawn_kill_switch_listener(
+  devices: Weak<DashMap<u32, Arc<ButtplugDevice>>>,
+  scheduled_commands: ScheduledCommands,
+) -> mpsc::UnboundedSender<String> {
+  let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+  async_manager::spawn_named("Kill Switch Listener", async move {
+    while let Some(source_name) = receiver.recv().await {
+      let devices = match devices.upgrade() {
+        Some(devices) => devices,
+        None => break,
+      };
+      warn!("Kill switch {} hit, stopping all devices.", source_name);
+      scheduled_commands.cancel_all();
+      let stop_futures: Vec<_> = devices
+        .iter()
+        .map(|device| device.value().parse_message(StopDeviceCmd::new(1).into()))
+        .collect();
+      for result in future::join_all(stop_futures).await {
+        if let Err(err) = result {
+          error!("Error stopping device from kill switch: {}", err);
+        }
+      }
+    }
+  })
+  .unwrap();
+  sender
+}
@@ -7,43 +7,64 @@
 
 //! Handles client sessions, as well as discovery and communication with hardware.
 
+pub mod command_storm_guard;
 pub mod comm_managers;
+mod connection_state;
+pub mod device_command_transform;
+pub mod device_consent;
 pub mod device_manager;
+pub mod device_session;
+pub mod diagnostics;
 mod device_manager_event_loop;
+mod event_bus;
+pub mod kill_switch;
 mod ping_timer;
 pub mod remote_server;
+pub mod scheduled_commands;
+pub mod server_builder;
 
+pub use connection_state::ButtplugServerConnectionState;
+pub use event_bus::{DeviceCommandAudit, DeviceDryRunWrite};
 pub use remote_server::ButtplugRemoteServer;
+pub use server_builder::{ButtplugServerBuilder, DefaultCommManager};
 
 use crate::{
   core::{
     errors::*,
     messages::{
       self, ButtplugClientMessage, ButtplugDeviceCommandMessageUnion,
-      ButtplugDeviceManagerMessageUnion, ButtplugMessage, ButtplugServerMessage, StopAllDevices,
-      StopScanning, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+      ButtplugDeviceManagerMessageUnion, ButtplugMessage, ButtplugMessageValidator,
+      ButtplugMessageSpecVersion, ButtplugServerMessage, StopAllDevices, StopScanning,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
   },
-  device::protocol::ButtplugProtocol,
+  device::{
+    configuration_manager::SupportedProtocol, protocol::ButtplugProtocol, DeviceIdlePowerManagement,
+    DeviceWriteWatchdog,
+  },
   test::TestDeviceCommunicationManagerHelper,
-  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+  util::async_manager,
 };
-use comm_managers::DeviceCommunicationManagerBuilder;
+use comm_managers::{preflight::PermissionCheck, DeviceCommunicationManagerBuilder};
+use command_storm_guard::{DeviceCommandStorm, DeviceCommandStormGuard};
+use kill_switch::KillSwitchSource;
+use connection_state::ConnectionState;
+use device_command_transform::DeviceCommandTransform;
+use device_consent::{DeviceConsentRequest, DeviceConsentState};
 use device_manager::DeviceManager;
+use diagnostics::ServerDiagnostics;
+use event_bus::{ServerErrorEvent, ServerEventBus};
 use futures::{
   future::{self, BoxFuture},
-  Stream,
+  Stream, StreamExt,
 };
 use ping_timer::PingTimer;
 use std::{
+  collections::HashMap,
   convert::{TryFrom, TryInto},
-  sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-  },
+  sync::{Arc, RwLock},
 };
 use thiserror::Error;
-use tokio::sync::broadcast;
 use tracing_futures::Instrument;
 
 pub type ButtplugServerResult = Result<ButtplugServerMessage, ButtplugError>;
@@ -57,6 +78,54 @@ pub enum ButtplugServerError {
   ProtocolAlreadyAdded(String),
   #[error("Buttplug Protocol of type {0} does not exist in the system and cannot be removed.")]
   ProtocolDoesNotExist(String),
+  #[error("Kill switch {0} could not be started: {1}")]
+  KillSwitchStartFailed(String, String),
+  #[error("Server could not be created: {0}")]
+  ServerCreationFailed(ButtplugError),
+}
+
+/// Message access granted to a connected client.
+///
+/// These are set by whatever is embedding the server, and are checked on top
+/// of the server wide options. For instance, if the server was created without
+/// `allow_raw_messages`, setting `allow_raw_messages` here will not enable raw
+/// messages. Permissions are reset to the server defaults on disconnect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtplugClientPermissions {
+  /// Client can send StartScanning/StopScanning.
+  pub allow_scanning: bool,
+  /// Client can send RawWriteCmd/RawReadCmd/RawSubscribeCmd/RawUnsubscribeCmd.
+  pub allow_raw_messages: bool,
+}
+
+impl Default for ButtplugClientPermissions {
+  fn default() -> Self {
+    Self {
+      allow_scanning: true,
+      allow_raw_messages: true,
+    }
+  }
+}
+
+impl ButtplugClientPermissions {
+  fn check_message(&self, msg: &ButtplugClientMessage) -> Result<(), ButtplugError> {
+    let (permitted, msg_type) = match msg {
+      ButtplugClientMessage::StartScanning(_) => (self.allow_scanning, "StartScanning"),
+      ButtplugClientMessage::StopScanning(_) => (self.allow_scanning, "StopScanning"),
+      ButtplugClientMessage::RawWriteCmd(_) => (self.allow_raw_messages, "RawWriteCmd"),
+      ButtplugClientMessage::RawReadCmd(_) => (self.allow_raw_messages, "RawReadCmd"),
+      ButtplugClientMessage::RawSubscribeCmd(_) => (self.allow_raw_messages, "RawSubscribeCmd"),
+      ButtplugClientMessage::RawUnsubscribeCmd(_) => {
+        (self.allow_raw_messages, "RawUnsubscribeCmd")
+      }
+      _ => return Ok(()),
+    };
+    if permitted {
+      Ok(())
+    } else {
+      Err(ButtplugMessageError::MessageNotPermitted(msg_type.to_owned()).into())
+    }
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -66,6 +135,47 @@ pub struct ButtplugServerOptions {
   pub allow_raw_messages: bool,
   pub device_configuration_json: Option<String>,
   pub user_device_configuration_json: Option<String>,
+  /// Permissions given to each client on connection, unless overridden via
+  /// [ButtplugServer::set_client_permissions].
+  pub client_permissions: ButtplugClientPermissions,
+  /// How device writes that never finish are handled. `None` turns the
+  /// watchdog off, meaning a hung transport can block a device forever.
+  pub device_write_watchdog: Option<DeviceWriteWatchdog>,
+  /// Slows down device connections after they've gone unused for a while, to
+  /// save battery. `None` (the default) keeps connections as they are.
+  pub device_idle_power_management: Option<DeviceIdlePowerManagement>,
+  /// If true, VibrateCmd messages with fewer subcommands than the device has
+  /// vibrators have the last given speed applied to the vibrators that were
+  /// left out. Helps with apps that assume every device has a single motor.
+  pub fill_missing_vibrate_subcommands: bool,
+  /// Highest heat level (0.0-1.0 of each heater's range in the device config)
+  /// the server will send to heating devices, whatever clients ask for.
+  /// Higher levels are lowered to this. Defaults to 1.0, which leaves the
+  /// device config step counts as the only limit.
+  pub max_temperature_level: f64,
+  /// Highest suction level (0.0-1.0 of each pump's pressure range in the
+  /// device config) the server will send to pump devices, whatever clients
+  /// ask for. Works the same way as `max_temperature_level`.
+  pub max_suction_level: f64,
+  /// If true, clients can't send commands to a device until the embedder has
+  /// allowed it. See [device_consent].
+  pub require_device_consent: bool,
+  /// If true, commands are handled as usual (validated, run through
+  /// transforms and protocols), but the bytes that would be written to
+  /// devices are sent out on [ButtplugServer::dry_run_writes] instead. Useful
+  /// for previewing what commands will do, or testing protocols without
+  /// hardware. Writes devices need while connecting still go through.
+  pub dry_run: bool,
+  /// Transports to prefer, best first, when the same device is found through
+  /// more than one comm manager (i.e. over BLE and through a dongle). The
+  /// device is only added once either way, this just picks which connection
+  /// is kept. Devices are matched up by hardware address, so this only works
+  /// for transports that report one.
+  pub device_transport_priority: Vec<messages::DeviceTransport>,
+  /// Rejects commands sent to a device faster than the guard allows, and
+  /// reports the client on [ButtplugServer::device_command_storms]. `None`
+  /// lets clients send as fast as they like. See [command_storm_guard].
+  pub device_command_storm_guard: Option<DeviceCommandStormGuard>,
 }
 
 impl Default for ButtplugServerOptions {
@@ -76,6 +186,16 @@ impl Default for ButtplugServerOptions {
       allow_raw_messages: false,
       device_configuration_json: None,
       user_device_configuration_json: None,
+      client_permissions: ButtplugClientPermissions::default(),
+      device_write_watchdog: Some(DeviceWriteWatchdog::default()),
+      device_idle_power_management: None,
+      fill_missing_vibrate_subcommands: false,
+      max_temperature_level: 1.0,
+      max_suction_level: 1.0,
+      require_device_consent: false,
+      dry_run: false,
+      device_transport_priority: vec![],
+      device_command_storm_guard: Some(DeviceCommandStormGuard::default()),
     }
   }
 }
@@ -86,8 +206,12 @@ pub struct ButtplugServer {
   max_ping_time: u64,
   device_manager: DeviceManager,
   ping_timer: Arc<PingTimer>,
-  connected: Arc<AtomicBool>,
-  output_sender: broadcast::Sender<ButtplugServerMessage>,
+  connection_state: Arc<ConnectionState>,
+  default_client_permissions: ButtplugClientPermissions,
+  client_permissions: Arc<RwLock<ButtplugClientPermissions>>,
+  // Spec version the connected client asked for in its handshake.
+  client_message_version: Arc<RwLock<Option<ButtplugMessageSpecVersion>>>,
+  event_bus: ServerEventBus,
 }
 
 impl Default for ButtplugServer {
@@ -100,23 +224,21 @@ impl Default for ButtplugServer {
 impl ButtplugServer {
   pub fn new_with_options(options: &ButtplugServerOptions) -> Result<Self, ButtplugError> {
     debug!("Creating server '{}'", options.name);
-    let (send, _) = broadcast::channel(256);
-    let output_sender_clone = send.clone();
-    let connected = Arc::new(AtomicBool::new(false));
+    let event_bus = ServerEventBus::default();
+    let event_bus_clone = event_bus.clone();
+    let connection_state = Arc::new(ConnectionState::default());
     let ping_timer = Arc::new(PingTimer::new(options.max_ping_time));
     let ping_timeout_notifier = ping_timer.ping_timeout_waiter();
-    let connected_clone = connected.clone();
-    async_manager::spawn(
+    let connection_state_clone = connection_state.clone();
+    async_manager::spawn_named(
+      "Server Ping Timeout Task",
       async move {
         // This will only exit if we've pinged out.
         ping_timeout_notifier.await;
         error!("Ping out signal received, stopping server");
-        connected_clone.store(false, Ordering::SeqCst);
+        connection_state_clone.set(ButtplugServerConnectionState::AwaitingHandshake);
         // TODO Should the event sender return a result instead of an error message?
-        if output_sender_clone
-          .send(messages::Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into())
-          .is_err()
-        {
+        if !event_bus_clone.publish(ServerErrorEvent::PingedOut) {
           error!("Server disappeared, cannot update about ping out.");
         };
       }
@@ -124,26 +246,89 @@ impl ButtplugServer {
     )
     .unwrap();
     let device_manager = DeviceManager::try_new(
-      send.clone(),
+      event_bus.clone(),
       ping_timer.clone(),
       options.allow_raw_messages,
       &options.device_configuration_json,
       &options.user_device_configuration_json,
+      options.device_write_watchdog,
+      options.device_idle_power_management,
+      options.fill_missing_vibrate_subcommands,
+      options.max_temperature_level,
+      options.max_suction_level,
+      options.require_device_consent,
+      options.dry_run,
+      options.device_transport_priority.clone(),
+      options.device_command_storm_guard,
     )?;
     Ok(Self {
       server_name: options.name.clone(),
       max_ping_time: options.max_ping_time,
       device_manager,
       ping_timer,
-      connected,
-      output_sender: send,
+      connection_state,
+      default_client_permissions: options.client_permissions,
+      client_permissions: Arc::new(RwLock::new(options.client_permissions)),
+      client_message_version: Arc::new(RwLock::new(None)),
+      event_bus,
     })
   }
 
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
     // Unlike the client API, we can expect anyone using the server to pin this
     // themselves.
-    convert_broadcast_receiver_to_stream(self.output_sender.subscribe())
+    let client_message_version = self.client_message_version.clone();
+    self.event_bus.server_message_stream().filter(move |msg| {
+      // Older spec versions have no way to say a device changed, so those
+      // clients only see it in their next device list.
+      let supported = !matches!(msg, ButtplugServerMessage::DeviceUpdated(_))
+        || *client_message_version.read().unwrap() >= Some(ButtplugMessageSpecVersion::Version2);
+      future::ready(supported)
+    })
+  }
+
+  /// Stream of device commands sent by the connected client, after they've
+  /// passed permission checks and any command transforms, right before they
+  /// go to the device. Commands the server sends on its own (i.e. stopping
+  /// devices on disconnect) don't show up here.
+  ///
+  /// Meant for embedders that want to log or show what clients are doing.
+  /// Commands that need to be held back until a user allows them should be
+  /// handled with a [DeviceCommandTransform] instead.
+  pub fn device_command_audit_stream(&self) -> impl Stream<Item = DeviceCommandAudit> {
+    self.event_bus.device_command_stream()
+  }
+
+  /// Stream of writes devices would have gotten, if the server was created
+  /// with `dry_run` set.
+  pub fn dry_run_writes(&self) -> impl Stream<Item = DeviceDryRunWrite> {
+    self.event_bus.dry_run_write_stream()
+  }
+
+  /// Stream of requests for consent to use devices, to be answered with
+  /// [ButtplugServer::set_device_consent]. Only used if the server was created
+  /// with `require_device_consent` set.
+  pub fn device_consent_requests(&self) -> impl Stream<Item = DeviceConsentRequest> {
+    self.event_bus.device_consent_request_stream()
+  }
+
+  /// Stream of clients caught sending commands to a device faster than the
+  /// server's [DeviceCommandStormGuard] allows.
+  pub fn device_command_storms(&self) -> impl Stream<Item = DeviceCommandStorm> {
+    self.event_bus.device_command_storm_stream()
+  }
+
+  /// Whether the connected client can use the device at `device_index`.
+  /// `None` means it hasn't tried yet, and nothing's been decided.
+  pub fn device_consent(&self, device_index: u32) -> Option<DeviceConsentState> {
+    self.device_manager.device_consent(device_index)
+  }
+
+  /// Allows or denies the connected client use of the device at
+  /// `device_index`. Can be called before the client asks, or to change a
+  /// previous answer.
+  pub fn set_device_consent(&self, device_index: u32, allowed: bool) {
+    self.device_manager.set_device_consent(device_index, allowed)
   }
 
   pub fn add_comm_manager<T>(&self, builder: T) -> Result<(), ButtplugServerError> where T: DeviceCommunicationManagerBuilder
@@ -157,6 +342,25 @@ impl ButtplugServer {
     self.device_manager.add_test_comm_manager()
   }
 
+  /// Adds a transform that every device command goes through before reaching
+  /// the device. Transforms run in the order they're added. See
+  /// [device_command_transform].
+  pub fn add_device_command_transform<T>(&self, transform: T)
+  where
+    T: DeviceCommandTransform + 'static,
+  {
+    self.device_manager.add_device_command_transform(transform)
+  }
+
+  /// Starts a kill switch, which stops every device whenever it's hit. See
+  /// [kill_switch].
+  pub fn add_kill_switch<T>(&self, source: T) -> Result<(), ButtplugServerError>
+  where
+    T: KillSwitchSource + 'static,
+  {
+    self.device_manager.add_kill_switch(Box::new(source))
+  }
+
   pub fn add_protocol<T>(&self, protocol_name: &str) -> Result<(), ButtplugServerError> where T: ButtplugProtocol {
     self.device_manager.add_protocol::<T>(protocol_name)
   }
@@ -169,21 +373,100 @@ impl ButtplugServer {
     self.device_manager.remove_all_protocols();
   }
 
+  /// Sets a user display name for the device at `address`, which will be sent
+  /// to clients in DeviceAdded and DeviceList messages instead of the device
+  /// config name. If the device is already connected, the client is sent a
+  /// DeviceUpdated.
+  pub fn set_device_display_name(&self, address: &str, display_name: &str) {
+    self
+      .device_manager
+      .set_device_display_name(address, display_name);
+  }
+
+  pub fn remove_device_display_name(&self, address: &str) -> Option<String> {
+    self.device_manager.remove_device_display_name(address)
+  }
+
+  /// Sends the client a DeviceUpdated with the device's current name and
+  /// message attributes. Display name changes do this on their own, this is
+  /// for anything else that changes what a connected device can do.
+  pub fn refresh_device(&self, device_index: u32) -> Result<(), ButtplugDeviceError> {
+    self.device_manager.refresh_device(device_index)
+  }
+
+  /// All user display names, keyed by device address, so they can be saved to
+  /// the user device config.
+  pub fn device_display_names(&self) -> HashMap<String, String> {
+    self.device_manager.device_display_names()
+  }
+
+  /// Protocols and devices this server can handle, based on its device
+  /// configuration and registered protocols.
+  pub fn supported_protocols(&self) -> Vec<SupportedProtocol> {
+    self.device_manager.supported_protocols()
+  }
+
+  /// Snapshot of comm manager, adapter and device state, for front-ends to
+  /// attach to bug reports. See [diagnostics].
+  pub fn diagnostics(&self) -> ServerDiagnostics {
+    ServerDiagnostics {
+      server_name: self.server_name.clone(),
+      connection_state: self.connection_state(),
+      client_name: self.device_manager.client_name(),
+      client_message_version: *self.client_message_version.read().unwrap(),
+      comm_managers: self.device_manager.comm_manager_diagnostics(),
+      devices: self.device_manager.device_diagnostics(),
+      device_manager_queue_depth: self.device_manager.event_queue_depth(),
+    }
+  }
+
+  /// Checks the OS permissions each comm manager needs to find devices, so
+  /// front-ends can tell the user why scanning won't find anything and how to
+  /// fix it. See [comm_managers::preflight].
+  pub fn permission_preflight(&self) -> Vec<PermissionCheck> {
+    self.device_manager.permission_preflight()
+  }
+
   pub fn connected(&self) -> bool {
-    self.connected.load(Ordering::SeqCst)
+    self.connection_state() == ButtplugServerConnectionState::Connected
+  }
+
+  pub fn connection_state(&self) -> ButtplugServerConnectionState {
+    self.connection_state.get()
+  }
+
+  pub fn client_permissions(&self) -> ButtplugClientPermissions {
+    *self.client_permissions.read().unwrap()
+  }
+
+  /// Sets message permissions for the current (or next) client connection.
+  /// These will be reset to the defaults from [ButtplugServerOptions] when the
+  /// server disconnects.
+  pub fn set_client_permissions(&self, permissions: ButtplugClientPermissions) {
+    *self.client_permissions.write().unwrap() = permissions;
   }
 
   pub fn disconnect(&self) -> BoxFuture<Result<(), messages::Error>> {
     debug!("Buttplug Server {} disconnect requested", self.server_name);
     let ping_timer = self.ping_timer.clone();
-    let stop_scanning_fut =
-      self.parse_message(ButtplugClientMessage::StopScanning(StopScanning::default()));
-    let stop_fut = self.parse_message(ButtplugClientMessage::StopAllDevices(
-      StopAllDevices::default(),
-    ));
-    let connected = self.connected.clone();
+    // Go straight to the device manager here, as we may be disconnecting
+    // without ever having finished a handshake.
+    let stop_scanning_fut = self
+      .device_manager
+      .parse_message(ButtplugClientMessage::StopScanning(StopScanning::default()));
+    let stop_fut = self
+      .device_manager
+      .parse_message(ButtplugClientMessage::StopAllDevices(
+        StopAllDevices::default(),
+      ));
+    self.device_manager.set_client_name(None);
+    *self.client_message_version.write().unwrap() = None;
+    let connection_state = self.connection_state.clone();
+    let client_permissions = self.client_permissions.clone();
+    let default_client_permissions = self.default_client_permissions;
     Box::pin(async move {
-      connected.store(false, Ordering::SeqCst);
+      connection_state.set(ButtplugServerConnectionState::AwaitingHandshake);
+      *client_permissions.write().unwrap() = default_client_permissions;
       ping_timer.stop_ping_timer().await;
       // Ignore returns here, we just want to stop.
       info!("Server disconnected, stopping device scanning if it was started...");
@@ -206,27 +489,50 @@ impl ButtplugServer {
       msg
     );
     let id = msg.id();
-    if !self.connected() {
+    // Catches client messages using the system message id (0), along with
+    // anything else malformed. The error goes back with whatever id the
+    // message had, since that's all we have to match it up with.
+    if let Err(err) = msg.is_valid() {
+      error!("Message not valid: {:?} - Error: {}", msg, err);
+      let mut error = messages::Error::from(ButtplugError::from(err));
+      error.set_id(id);
+      return Box::pin(future::ready(Err(error)));
+    }
+    let state = self.connection_state();
+    if state != ButtplugServerConnectionState::Connected {
       // Check for ping timeout first! There's no way we should've pinged out if
       // we haven't received RequestServerInfo first, but we do want to know if
       // we pinged out.
       let error = if self.ping_timer.pinged_out() {
-        Some(messages::Error::from(ButtplugError::from(
-          ButtplugPingError::PingedOut,
-        )))
-      } else if !matches!(msg, ButtplugClientMessage::RequestServerInfo(_)) {
-        Some(messages::Error::from(ButtplugError::from(
-          ButtplugHandshakeError::RequestServerInfoExpected,
-        )))
+        Some(ButtplugError::from(ButtplugPingError::PingedOut))
       } else {
-        None
-      };
+        match (state, &msg) {
+          (
+            ButtplugServerConnectionState::AwaitingHandshake,
+            ButtplugClientMessage::RequestServerInfo(_),
+          ) => None,
+          (
+            ButtplugServerConnectionState::Handshaking,
+            ButtplugClientMessage::RequestServerInfo(_),
+          ) => Some(ButtplugHandshakeError::HandshakeAlreadyHappened.into()),
+          (ButtplugServerConnectionState::HandshakeRejected, _) => {
+            Some(ButtplugHandshakeError::HandshakeRejected.into())
+          }
+          _ => Some(ButtplugHandshakeError::RequestServerInfoExpected.into()),
+        }
+      }
+      .map(messages::Error::from);
       if let Some(mut return_error) = error {
         return_error.set_id(msg.id());
         return Box::pin(future::ready(Err(return_error)));
       }
       // If we haven't pinged out and we got an RSI message, fall thru.
     }
+    if let Err(err) = self.client_permissions().check_message(&msg) {
+      let mut error = messages::Error::from(err);
+      error.set_id(id);
+      return Box::pin(future::ready(Err(error)));
+    }
     // Produce whatever future is needed to reply to the message, this may be a
     // device command future, or something the server handles. All futures will
     // return Result<ButtplugServerMessage, ButtplugError>, and we'll handle
@@ -240,6 +546,7 @@ impl ButtplugServer {
       match msg {
         ButtplugClientMessage::RequestServerInfo(rsi_msg) => self.perform_handshake(rsi_msg),
         ButtplugClientMessage::Ping(p) => self.handle_ping(p),
+        ButtplugClientMessage::RequestServerTime(_) => self.handle_request_server_time(),
         _ => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
       }
     };
@@ -264,7 +571,12 @@ impl ButtplugServer {
   }
 
   fn perform_handshake(&self, msg: messages::RequestServerInfo) -> ButtplugServerResultFuture {
-    if self.connected() {
+    // Claim the handshake up front, so that a second RequestServerInfo that
+    // shows up while we're still working on this one gets turned away.
+    if !self.connection_state.transition(
+      ButtplugServerConnectionState::AwaitingHandshake,
+      ButtplugServerConnectionState::Handshaking,
+    ) {
       return ButtplugHandshakeError::HandshakeAlreadyHappened.into();
     }
     info!(
@@ -273,12 +585,19 @@ impl ButtplugServer {
       msg.message_version()
     );
     if BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION < msg.message_version() {
+      self
+        .connection_state
+        .set(ButtplugServerConnectionState::HandshakeRejected);
       return ButtplugHandshakeError::MessageSpecVersionMismatch(
         BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
         msg.message_version(),
       )
       .into();
     }
+    self
+      .device_manager
+      .set_client_name(Some(msg.client_name().clone()));
+    *self.client_message_version.write().unwrap() = Some(msg.message_version());
     // Only start the ping timer after we've received the handshake.
     let ping_timer = self.ping_timer.clone();
     let out_msg = messages::ServerInfo::new(
@@ -286,10 +605,10 @@ impl ButtplugServer {
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
       self.max_ping_time.try_into().unwrap(),
     );
-    let connected = self.connected.clone();
+    let connection_state = self.connection_state.clone();
     Box::pin(async move {
       ping_timer.start_ping_timer().await;
-      connected.store(true, Ordering::SeqCst);
+      connection_state.set(ButtplugServerConnectionState::Connected);
       debug!("Server handshake check successful.");
       Result::Ok(out_msg.into())
     })
@@ -305,6 +624,15 @@ impl ButtplugServer {
       Result::Ok(messages::Ok::new(msg.id()).into())
     })
   }
+
+  fn handle_request_server_time(&self) -> ButtplugServerResultFuture {
+    // Read the clock now rather than when the future is polled, so the
+    // timestamp is as close to when the request arrived as we can get it.
+    let timestamp = scheduled_commands::server_timestamp();
+    Box::pin(future::ready(Result::Ok(
+      messages::ServerTime::new(timestamp).into(),
+    )))
+  }
 }
 
 #[cfg(test)]
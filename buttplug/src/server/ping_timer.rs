@@ -85,7 +85,7 @@ impl PingTimer {
         ping_timeout_notifier.clone(),
         pinged_out.clone(),
       );
-      async_manager::spawn(async move { fut.await }).unwrap();
+      async_manager::spawn_named("Ping Timer", async move { fut.await }).unwrap();
     }
     Self {
       max_ping_time,
@@ -1,13 +1,14 @@
-use super::{ButtplugServer, ButtplugServerOptions, ButtplugServerError};
+use super::{
+  device_command_transform::DeviceCommandTransform, ButtplugClientPermissions, ButtplugServer,
+  ButtplugServerError, ButtplugServerOptions,
+};
 use crate::{
   connector::ButtplugConnector,
   core::{
     errors::ButtplugError,
-    messages::{
-      self, ButtplugClientMessage, ButtplugMessage, ButtplugMessageValidator, ButtplugServerMessage,
-    },
+    messages::{ButtplugClientMessage, ButtplugServerMessage},
   },
-  device::protocol::ButtplugProtocol,
+  device::{configuration_manager::SupportedProtocol, protocol::ButtplugProtocol},
   server::DeviceCommunicationManagerBuilder,
   test::TestDeviceCommunicationManagerHelper,
   util::{async_manager, stream::convert_broadcast_receiver_to_stream},
@@ -68,13 +69,6 @@ async fn run_server<ConnectorType>(
           let connector_clone = shared_connector.clone();
           let remote_event_sender_clone = remote_event_sender.clone();
           async_manager::spawn(async move {
-            if let Err(e) = client_message.is_valid() {
-              error!("Message not valid: {:?} - Error: {}", client_message, e);
-              let mut err_msg = messages::Error::from(ButtplugError::from(e));
-              err_msg.set_id(client_message.id());
-              connector_clone.send(err_msg.into());
-              return;
-            }
             match server_clone.parse_message(client_message.clone()).await {
               Ok(ret_msg) => {
                 if let ButtplugClientMessage::RequestServerInfo(rsi) = client_message {
@@ -140,13 +134,18 @@ impl Default for ButtplugRemoteServer {
 
 impl ButtplugRemoteServer {
   pub fn new_with_options(options: &ButtplugServerOptions) -> Result<Self, ButtplugError> {
-    let server = ButtplugServer::new_with_options(options)?;
+    Ok(Self::new_with_server(ButtplugServer::new_with_options(options)?))
+  }
+
+  /// Wraps an already built server, i.e. from a
+  /// [ButtplugServerBuilder][super::ButtplugServerBuilder].
+  pub fn new_with_server(server: ButtplugServer) -> Self {
     let (event_sender, _) = broadcast::channel(256);
-    Ok(Self {
+    Self {
       event_sender,
       server: Arc::new(server),
       disconnect_notifier: Arc::new(Notify::new()),
-    })
+    }
   }
 
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugRemoteServerEvent> {
@@ -181,6 +180,20 @@ impl ButtplugRemoteServer {
     }
   }
 
+  /// Same as [ButtplugRemoteServer::start], but with message permissions for
+  /// this connection that differ from the server defaults.
+  pub fn start_with_permissions<ConnectorType>(
+    &self,
+    connector: ConnectorType,
+    permissions: ButtplugClientPermissions,
+  ) -> impl Future<Output = Result<(), ButtplugServerConnectorError>>
+  where
+    ConnectorType: ButtplugConnector<ButtplugServerMessage, ButtplugClientMessage> + 'static,
+  {
+    self.server.set_client_permissions(permissions);
+    self.start(connector)
+  }
+
   pub async fn disconnect(&self) -> Result<(), ButtplugError> {
     self.disconnect_notifier.notify_waiters();
     Ok(())
@@ -197,6 +210,13 @@ impl ButtplugRemoteServer {
     self.server.add_test_comm_manager()
   }
 
+  pub fn add_device_command_transform<T>(&self, transform: T)
+  where
+    T: DeviceCommandTransform + 'static,
+  {
+    self.server.add_device_command_transform(transform)
+  }
+
   pub fn add_protocol<T>(&self, protocol_name: &str) -> Result<(), ButtplugServerError> where T: ButtplugProtocol {
     self.server.add_protocol::<T>(protocol_name)
   }
@@ -208,6 +228,10 @@ impl ButtplugRemoteServer {
   pub fn remove_all_protocols(&self) {
     self.server.remove_all_protocols();
   }
+
+  pub fn supported_protocols(&self) -> Vec<SupportedProtocol> {
+    self.server.supported_protocols()
+  }
 }
 
 impl Drop for ButtplugRemoteServer {
@@ -0,0 +1,166 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Running device commands at a set time.
+//!
+//! Remote clients see commands arrive whenever the network gets them there,
+//! which makes anything rhythmic (music sync, video sync, another person's
+//! hand on a slider) stutter. With
+//! [ScheduledCmd][crate::core::messages::ScheduledCmd], a client sends a
+//! command a little ahead of when it should run, along with a timestamp by
+//! the server's clock, and the server holds on to it until then. As long as
+//! the lead is longer than the jitter, commands come out evenly spaced.
+//!
+//! Scheduled commands go through consent, rate limiting, transforms and
+//! device sessions when they arrive, same as any other command, and are only
+//! sent to the device once they're due. Commands due in the past run right
+//! away. ScheduledCmd is answered as soon as the command is accepted, so
+//! clients (and connectors that handle one message at a time) aren't held up
+//! waiting for it. Errors from running the command later on can only be
+//! logged.
+//!
+//! Stopping a device (StopDeviceCmd, StopAllDevices or a kill switch) cancels
+//! whatever's still waiting for it, so a stop can't be undone by a command
+//! that was scheduled before it.
+
+use crate::{
+  core::{
+    errors::ButtplugMessageError,
+    messages::{ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessage},
+  },
+  device::ButtplugDevice,
+  util::async_manager,
+};
+use dashmap::DashMap;
+use futures_timer::Delay;
+use std::{
+  convert::TryFrom,
+  sync::Arc,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Furthest ahead a command can be scheduled. Anything further out is more
+/// likely a clock problem than a plan.
+pub const MAX_SCHEDULE_AHEAD: Duration = Duration::from_secs(60);
+
+/// The server's clock, in milliseconds since the Unix epoch. This is the time
+/// base for ScheduledCmd timestamps.
+pub fn server_timestamp() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|since_epoch| u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX))
+    .unwrap_or(0)
+}
+
+/// How long to wait before running a command scheduled for `timestamp`.
+pub(super) fn delay_until(timestamp: u64) -> Result<Duration, ButtplugMessageError> {
+  let delay = Duration::from_millis(timestamp.saturating_sub(server_timestamp()));
+  if delay > MAX_SCHEDULE_AHEAD {
+    return Err(ButtplugMessageError::InvalidMessageContents(format!(
+      "ScheduledCmd timestamp is more than {} seconds away",
+      MAX_SCHEDULE_AHEAD.as_secs()
+    )));
+  }
+  Ok(delay)
+}
+
+/// Only commands that set what a device is doing can be scheduled. Reads and
+/// subscriptions would have to hold their reply until they ran, which isn't
+/// useful to anyone.
+pub(super) fn check_schedulable(
+  command: &ButtplugDeviceCommandMessageUnion,
+) -> Result<(), ButtplugMessageError> {
+  match command {
+    ButtplugDeviceCommandMessageUnion::RawWriteCmd(_)
+    | ButtplugDeviceCommandMessageUnion::RawReadCmd(_)
+    | ButtplugDeviceCommandMessageUnion::RawSubscribeCmd(_)
+    | ButtplugDeviceCommandMessageUnion::RawUnsubscribeCmd(_)
+    | ButtplugDeviceCommandMessageUnion::BatteryLevelCmd(_)
+    | ButtplugDeviceCommandMessageUnion::RSSILevelCmd(_) => Err(
+      ButtplugMessageError::InvalidMessageContents(format!("{:?} cannot be scheduled", command)),
+    ),
+    _ => Ok(()),
+  }
+}
+
+/// Commands waiting to run, tracked well enough to cancel them.
+#[derive(Default, Clone)]
+pub(super) struct ScheduledCommands {
+  // Bumped for a device whenever its waiting commands are cancelled. Commands
+  // remember the generation they were scheduled in, and only run if it's
+  // still current.
+  generations: Arc<DashMap<u32, u64>>,
+}
+
+impl ScheduledCommands {
+  /// Waits `delay`, then sends `command` to `device`, unless the device is
+  /// stopped in the meantime.
+  pub fn schedule(
+    &self,
+    device: Arc<ButtplugDevice>,
+    command: ButtplugDeviceCommandMessageUnion,
+    delay: Duration,
+  ) {
+    let device_index = command.device_index();
+    let generation = *self.generations.entry(device_index).or_insert(0);
+    let generations = self.generations.clone();
+    async_manager::spawn(async move {
+      Delay::new(delay).await;
+      if generations.get(&device_index).map(|current| *current) != Some(generation) {
+        debug!(
+          "Device {} was stopped, dropping scheduled {:?}",
+          device_index, command
+        );
+        return;
+      }
+      if let Err(err) = device.parse_message(command).await {
+        error!(
+          "Scheduled command for device {} failed: {}",
+          device_index, err
+        );
+      }
+    })
+    .unwrap();
+  }
+
+  pub fn cancel(&self, device_index: u32) {
+    if let Some(mut generation) = self.generations.get_mut(&device_index) {
+      *generation += 1;
+    }
+  }
+
+  pub fn cancel_all(&self) {
+    for mut generation in self.generations.iter_mut() {
+      *generation += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::messages::{BatteryLevelCmd, VibrateCmd, VibrateSubcommand};
+
+  #[test]
+  fn test_delay_until() {
+    let now = server_timestamp();
+    assert_eq!(
+      delay_until(now.saturating_sub(500)).unwrap(),
+      Duration::from_secs(0)
+    );
+    assert!(delay_until(now + 1000).unwrap() <= Duration::from_millis(1000));
+    let too_far = now + MAX_SCHEDULE_AHEAD.as_millis() as u64 + 5000;
+    assert!(delay_until(too_far).is_err());
+  }
+
+  #[test]
+  fn test_check_schedulable() {
+    let vibrate = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]);
+    assert!(check_schedulable(&vibrate.into()).is_ok());
+    assert!(check_schedulable(&BatteryLevelCmd::new(0).into()).is_err());
+  }
+}
@@ -0,0 +1,244 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Builder for setting up a [ButtplugServer] along with its comm managers.
+//!
+//! Which comm managers make sense depends on what features the library was
+//! built with, and what it's running on (XInput only exists on Windows, evdev
+//! only on Linux, etc). [DefaultCommManager::available] knows all of that, so
+//! [ButtplugServerBuilder::with_default_device_managers] can add everything
+//! that will work, and applications can turn off what they don't want.
+
+use super::{
+  comm_managers::DeviceCommunicationManagerBuilder, ButtplugServer, ButtplugServerError,
+  ButtplugServerOptions,
+};
+use std::fmt;
+
+/// Comm managers that come with the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefaultCommManager {
+  /// Bluetooth LE, through btleplug.
+  Btleplug,
+  /// Serial ports, for devices configured in the user device config.
+  Serial,
+  /// HID devices (game controllers, mostly), over USB or Bluetooth.
+  Hid,
+  /// Lovense USB dongles, both the HID and the serial versions.
+  LovenseDongle,
+  /// The Lovense Connect app, over the local network.
+  LovenseConnectService,
+  /// XInput gamepads, on Windows.
+  XInput,
+  /// Force feedback input devices, on Linux.
+  Evdev,
+}
+
+impl DefaultCommManager {
+  /// Every comm manager that can work in this build, given the features it
+  /// was compiled with and the target OS.
+  pub fn available() -> Vec<Self> {
+    [
+      (cfg!(feature = "btleplug-manager"), DefaultCommManager::Btleplug),
+      (cfg!(feature = "serial-manager"), DefaultCommManager::Serial),
+      (cfg!(feature = "hid-manager"), DefaultCommManager::Hid),
+      (cfg!(feature = "lovense-dongle-manager"), DefaultCommManager::LovenseDongle),
+      (
+        cfg!(feature = "lovense-connect-service-manager"),
+        DefaultCommManager::LovenseConnectService,
+      ),
+      (
+        cfg!(all(feature = "xinput-manager", target_os = "windows")),
+        DefaultCommManager::XInput,
+      ),
+      (
+        cfg!(all(feature = "evdev-manager", target_os = "linux")),
+        DefaultCommManager::Evdev,
+      ),
+    ]
+    .iter()
+    .filter(|(available, _)| *available)
+    .map(|(_, manager)| *manager)
+    .collect()
+  }
+
+  /// Adds the comm manager to `server`. Comm managers that aren't
+  /// [available](Self::available) in this build are skipped.
+  pub fn add_to(self, server: &ButtplugServer) -> Result<(), ButtplugServerError> {
+    match self {
+      #[cfg(feature = "btleplug-manager")]
+      DefaultCommManager::Btleplug => server.add_comm_manager(
+        super::comm_managers::btleplug::BtlePlugCommunicationManagerBuilder::default(),
+      ),
+      #[cfg(feature = "serial-manager")]
+      DefaultCommManager::Serial => server.add_comm_manager(
+        super::comm_managers::serialport::SerialPortCommunicationManagerBuilder::default(),
+      ),
+      #[cfg(feature = "hid-manager")]
+      DefaultCommManager::Hid => server.add_comm_manager(
+        super::comm_managers::hid::HidDeviceCommunicationManagerBuilder::default(),
+      ),
+      #[cfg(feature = "lovense-dongle-manager")]
+      DefaultCommManager::LovenseDongle => {
+        use super::comm_managers::lovense_dongle::{
+          LovenseHIDDongleCommunicationManagerBuilder, LovenseSerialDongleCommunicationManagerBuilder,
+        };
+        server.add_comm_manager(LovenseHIDDongleCommunicationManagerBuilder::default())?;
+        server.add_comm_manager(LovenseSerialDongleCommunicationManagerBuilder::default())
+      }
+      #[cfg(feature = "lovense-connect-service-manager")]
+      DefaultCommManager::LovenseConnectService => {
+        use super::comm_managers::lovense_connect_service::{
+          LovenseConnectServiceCommunicationManagerBuilder,
+        };
+        server.add_comm_manager(LovenseConnectServiceCommunicationManagerBuilder::default())
+      }
+      #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
+      DefaultCommManager::XInput => server.add_comm_manager(
+        super::comm_managers::xinput::XInputDeviceCommunicationManagerBuilder::default(),
+      ),
+      #[cfg(all(feature = "evdev-manager", target_os = "linux"))]
+      DefaultCommManager::Evdev => server.add_comm_manager(
+        super::comm_managers::evdev::EvdevDeviceCommunicationManagerBuilder::default(),
+      ),
+      #[allow(unreachable_patterns)]
+      _ => {
+        // With no comm manager features on, nothing else uses the server.
+        let _ = server;
+        debug!("Comm manager {:?} not available in this build, skipping.", self);
+        Ok(())
+      }
+    }
+  }
+}
+
+type CommManagerAdder = Box<dyn FnOnce(&ButtplugServer) -> Result<(), ButtplugServerError> + Send>;
+
+/// Sets up a [ButtplugServer] with its options and comm managers.
+///
+/// ```no_run
+/// # use buttplug::server::{ButtplugServerBuilder, DefaultCommManager};
+/// let server = ButtplugServerBuilder::default()
+///   .with_default_device_managers()
+///   .without_comm_manager(DefaultCommManager::Serial)
+///   .finish()
+///   .unwrap();
+/// ```
+#[derive(Default)]
+pub struct ButtplugServerBuilder {
+  options: ButtplugServerOptions,
+  default_comm_managers: Vec<DefaultCommManager>,
+  comm_managers: Vec<CommManagerAdder>,
+}
+
+impl fmt::Debug for ButtplugServerBuilder {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ButtplugServerBuilder")
+      .field("options", &self.options)
+      .field("default_comm_managers", &self.default_comm_managers)
+      .field("comm_managers", &self.comm_managers.len())
+      .finish()
+  }
+}
+
+impl ButtplugServerBuilder {
+  pub fn new(options: ButtplugServerOptions) -> Self {
+    Self {
+      options,
+      ..Default::default()
+    }
+  }
+
+  pub fn options(mut self, options: ButtplugServerOptions) -> Self {
+    self.options = options;
+    self
+  }
+
+  /// Adds every comm manager that's [available](DefaultCommManager::available)
+  /// in this build.
+  pub fn with_default_device_managers(mut self) -> Self {
+    for manager in DefaultCommManager::available() {
+      self = self.with_comm_manager(manager);
+    }
+    self
+  }
+
+  /// Adds a single library comm manager. Adding one twice does nothing.
+  pub fn with_comm_manager(mut self, manager: DefaultCommManager) -> Self {
+    if !self.default_comm_managers.contains(&manager) {
+      self.default_comm_managers.push(manager);
+    }
+    self
+  }
+
+  /// Removes a library comm manager, i.e. after
+  /// [with_default_device_managers](Self::with_default_device_managers).
+  pub fn without_comm_manager(mut self, manager: DefaultCommManager) -> Self {
+    self.default_comm_managers.retain(|m| *m != manager);
+    self
+  }
+
+  /// Adds a comm manager from outside of the library.
+  pub fn comm_manager<T>(mut self, builder: T) -> Self
+  where
+    T: DeviceCommunicationManagerBuilder + 'static,
+  {
+    self
+      .comm_managers
+      .push(Box::new(move |server| server.add_comm_manager(builder)));
+    self
+  }
+
+  /// Library comm managers that will be added, in order.
+  pub fn default_comm_managers(&self) -> &[DefaultCommManager] {
+    &self.default_comm_managers
+  }
+
+  /// Creates the server, adding library comm managers, then the others in
+  /// the order they were given.
+  pub fn finish(self) -> Result<ButtplugServer, ButtplugServerError> {
+    let server = ButtplugServer::new_with_options(&self.options)
+      .map_err(ButtplugServerError::ServerCreationFailed)?;
+    for manager in self.default_comm_managers {
+      manager.add_to(&server)?;
+    }
+    for add_comm_manager in self.comm_managers {
+      add_comm_manager(&server)?;
+    }
+    Ok(server)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_server_builder_comm_manager_toggles() {
+    let builder = ButtplugServerBuilder::default().with_default_device_managers();
+    assert_eq!(builder.default_comm_managers(), DefaultCommManager::available());
+    #[cfg(feature = "serial-manager")]
+    assert!(DefaultCommManager::available().contains(&DefaultCommManager::Serial));
+    #[cfg(not(target_os = "windows"))]
+    assert!(!DefaultCommManager::available().contains(&DefaultCommManager::XInput));
+    let builder = builder
+      .without_comm_manager(DefaultCommManager::Serial)
+      .with_comm_manager(DefaultCommManager::Evdev)
+      .with_comm_manager(DefaultCommManager::Evdev);
+    assert!(!builder
+      .default_comm_managers()
+      .contains(&DefaultCommManager::Serial));
+    assert_eq!(
+      builder
+        .default_comm_managers()
+        .iter()
+        .filter(|m| **m == DefaultCommManager::Evdev)
+        .count(),
+      1
+    );
+  }
+}
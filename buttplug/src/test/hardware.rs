@@ -0,0 +1,346 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2021 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Validation runs against real, physically attached hardware.
+//!
+//! Simulated devices can't catch regressions in comm managers, platform BLE
+//! stacks, or protocols whose hardware behaves differently than documented.
+//! This runs a scripted pass over a list of known devices (connect, vibrate
+//! sweep, stop, then disconnect) using a normal [ButtplugClient], and produces
+//! a report that can be serialized to JSON and compared between runs. See the
+//! `hardware-validation` example for a runnable harness.
+//!
+//! Configs are JSON, and list the devices expected to show up:
+//!
+//! ```json
+//! {
+//!   "scan-timeout": 30000,
+//!   "step-delay": 500,
+//!   "vibrate-steps": 4,
+//!   "devices": [{ "name": "Lovense Hush" }, { "name": "Aneros Vivi" }]
+//! }
+//! ```
+
+use crate::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientError, ButtplugClientEvent},
+  connector::ButtplugConnectorError,
+};
+use futures::{FutureExt, StreamExt};
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+use std::{
+  future::Future,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+fn default_scan_timeout() -> u64 {
+  30000
+}
+
+fn default_step_delay() -> u64 {
+  500
+}
+
+fn default_vibrate_steps() -> u32 {
+  4
+}
+
+/// Device expected to be attached during a hardware test run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareTestDevice {
+  /// Name the device shows up to clients with.
+  pub name: String,
+}
+
+/// What to test during a hardware run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareTestConfig {
+  /// How long to scan for devices, in milliseconds, before failing the ones
+  /// that haven't shown up.
+  #[serde(default = "default_scan_timeout")]
+  pub scan_timeout: u64,
+  /// How long to leave each command running before the next, in
+  /// milliseconds.
+  #[serde(default = "default_step_delay")]
+  pub step_delay: u64,
+  /// Number of speeds in the vibrate sweep, evenly spaced up to full speed.
+  #[serde(default = "default_vibrate_steps")]
+  pub vibrate_steps: u32,
+  pub devices: Vec<HardwareTestDevice>,
+}
+
+impl HardwareTestConfig {
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+}
+
+/// Part of a hardware test run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "step")]
+pub enum HardwareTestStep {
+  /// Device showed up while scanning.
+  Connect,
+  /// All vibrators set to `speed`.
+  Vibrate { speed: f64 },
+  /// Device stopped.
+  Stop,
+  /// Client disconnected from the server.
+  Disconnect,
+}
+
+/// Outcome of one [HardwareTestStep].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareTestStepResult {
+  #[serde(flatten)]
+  pub step: HardwareTestStep,
+  pub passed: bool,
+  /// Time the step took, in milliseconds.
+  pub duration: u64,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+impl HardwareTestStepResult {
+  async fn run<F>(step: HardwareTestStep, fut: F) -> Self
+  where
+    F: Future<Output = Result<(), ButtplugClientError>>,
+  {
+    let start = Instant::now();
+    let result = fut.await;
+    Self {
+      step,
+      passed: result.is_ok(),
+      duration: start.elapsed().as_millis() as u64,
+      error: result.err().map(|err| err.to_string()),
+    }
+  }
+}
+
+/// Results for one configured device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareDeviceReport {
+  pub name: String,
+  pub steps: Vec<HardwareTestStepResult>,
+}
+
+impl HardwareDeviceReport {
+  pub fn passed(&self) -> bool {
+    self.steps.iter().all(|step| step.passed)
+  }
+}
+
+/// Results of a whole hardware test run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareTestReport {
+  pub passed: bool,
+  /// Time the run took, in milliseconds.
+  pub duration: u64,
+  pub devices: Vec<HardwareDeviceReport>,
+  pub disconnect: HardwareTestStepResult,
+}
+
+impl HardwareTestReport {
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).expect("Report only contains serializable types")
+  }
+}
+
+/// Scans until every device in `config` has shown up, or the scan times out.
+/// Returns each device (if found) and how long it took to show up.
+async fn find_devices(
+  client: &ButtplugClient,
+  config: &HardwareTestConfig,
+) -> Result<Vec<(Option<Arc<ButtplugClientDevice>>, Duration)>, ButtplugClientError> {
+  let start = Instant::now();
+  let mut found: Vec<Option<(Arc<ButtplugClientDevice>, Duration)>> =
+    vec![None; config.devices.len()];
+  let mut events = client.event_stream();
+  client.start_scanning().await?;
+  let mut timeout = Delay::new(Duration::from_millis(config.scan_timeout)).fuse();
+  let mut finished = false;
+  loop {
+    for device in client.devices() {
+      if found
+        .iter()
+        .any(|found| matches!(found, Some((found, _)) if found.index() == device.index()))
+      {
+        continue;
+      }
+      // Devices with the same name are matched in the order they show up.
+      let slot = config
+        .devices
+        .iter()
+        .zip(found.iter_mut())
+        .find(|(expected, slot)| slot.is_none() && expected.name == device.name());
+      if let Some((_, slot)) = slot {
+        *slot = Some((device.clone(), start.elapsed()));
+      }
+    }
+    if finished || found.iter().all(Option::is_some) {
+      break;
+    }
+    select! {
+      event = events.next().fuse() => match event {
+        Some(ButtplugClientEvent::ScanningFinished) => finished = true,
+        Some(ButtplugClientEvent::ServerDisconnect) | None => {
+          return Err(ButtplugConnectorError::ConnectorNotConnected.into())
+        }
+        Some(_) => {}
+      },
+      _ = timeout => break,
+    }
+  }
+  // If the server finished on its own, it's already stopped.
+  if !finished {
+    client.stop_scanning().await?;
+  }
+  Ok(
+    found
+      .into_iter()
+      .map(|found| match found {
+        Some((device, elapsed)) => (Some(device), elapsed),
+        None => (None, start.elapsed()),
+      })
+      .collect(),
+  )
+}
+
+async fn test_device(
+  device: &ButtplugClientDevice,
+  config: &HardwareTestConfig,
+) -> Vec<HardwareTestStepResult> {
+  let step_delay = Duration::from_millis(config.step_delay);
+  let mut steps = vec![];
+  if device.supports_vibrate() {
+    for step in 1..=config.vibrate_steps {
+      let speed = step as f64 / config.vibrate_steps as f64;
+      steps.push(
+        HardwareTestStepResult::run(HardwareTestStep::Vibrate { speed }, device.vibrate(speed))
+          .await,
+      );
+      Delay::new(step_delay).await;
+    }
+  }
+  steps.push(HardwareTestStepResult::run(HardwareTestStep::Stop, device.stop()).await);
+  steps
+}
+
+/// Runs the hardware test pass described by `config` through `client`, which
+/// should already be connected to a server with the comm managers the
+/// devices need. Devices are tested one at a time, and the client is
+/// disconnected at the end.
+pub async fn run_hardware_tests(
+  client: &ButtplugClient,
+  config: &HardwareTestConfig,
+) -> HardwareTestReport {
+  let start = Instant::now();
+  let mut devices: Vec<HardwareDeviceReport> = vec![];
+  match find_devices(client, config).await {
+    Ok(found) => {
+      for (expected, (device, elapsed)) in config.devices.iter().zip(found) {
+        let mut steps = vec![HardwareTestStepResult {
+          step: HardwareTestStep::Connect,
+          passed: device.is_some(),
+          duration: elapsed.as_millis() as u64,
+          error: match device {
+            Some(_) => None,
+            None => Some("Device not found while scanning".to_owned()),
+          },
+        }];
+        if let Some(device) = device {
+          info!("Testing hardware device {}", expected.name);
+          steps.extend(test_device(&device, config).await);
+        } else {
+          error!("Hardware device {} not found", expected.name);
+        }
+        devices.push(HardwareDeviceReport {
+          name: expected.name.clone(),
+          steps,
+        });
+      }
+    }
+    Err(err) => {
+      // Can't test anything without scanning, so every device fails to
+      // connect.
+      error!("Cannot scan for hardware devices: {}", err);
+      for expected in &config.devices {
+        devices.push(HardwareDeviceReport {
+          name: expected.name.clone(),
+          steps: vec![HardwareTestStepResult {
+            step: HardwareTestStep::Connect,
+            passed: false,
+            duration: 0,
+            error: Some(err.to_string()),
+          }],
+        });
+      }
+    }
+  }
+  let disconnect =
+    HardwareTestStepResult::run(HardwareTestStep::Disconnect, client.disconnect()).await;
+  HardwareTestReport {
+    passed: disconnect.passed && devices.iter().all(HardwareDeviceReport::passed),
+    duration: start.elapsed().as_millis() as u64,
+    devices,
+    disconnect,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{run_hardware_tests, HardwareTestConfig, HardwareTestStep};
+  use crate::{
+    client::ButtplugClient, connector::ButtplugInProcessClientConnector, util::async_manager,
+  };
+
+  #[test]
+  fn test_hardware_test_run() {
+    async_manager::block_on(async move {
+      let config = HardwareTestConfig::from_json(
+        r#"{
+          "scan-timeout": 500,
+          "step-delay": 0,
+          "vibrate-steps": 2,
+          "devices": [{ "name": "Aneros Vivi" }, { "name": "Missing Device" }]
+        }"#,
+      )
+      .unwrap();
+      let connector = ButtplugInProcessClientConnector::default();
+      let helper = connector.server_ref().add_test_comm_manager().unwrap();
+      let _ = helper.add_ble_device("Massage Demo").await;
+      let client = ButtplugClient::new("Test Client");
+      client.connect(connector).await.unwrap();
+      let report = run_hardware_tests(&client, &config).await;
+      assert!(!report.passed);
+      assert!(report.disconnect.passed);
+      let found = &report.devices[0];
+      assert!(found.passed(), "{}", report.to_json());
+      let steps: Vec<_> = found.steps.iter().map(|result| result.step.clone()).collect();
+      assert_eq!(
+        steps,
+        vec![
+          HardwareTestStep::Connect,
+          HardwareTestStep::Vibrate { speed: 0.5 },
+          HardwareTestStep::Vibrate { speed: 1.0 },
+          HardwareTestStep::Stop
+        ]
+      );
+      let missing = &report.devices[1];
+      assert_eq!(missing.steps.len(), 1);
+      assert!(!missing.passed());
+      assert!(missing.steps[0].error.is_some());
+      assert!(report.to_json().contains(r#""step": "vibrate""#));
+    });
+  }
+}
@@ -1,6 +1,10 @@
+#[cfg(feature = "hardware-tests")]
+mod hardware;
 mod test_device;
 #[cfg(feature = "server")]
 mod test_device_comm_manager;
+#[cfg(feature = "server")]
+mod transcript;
 
 use crate::{
   device::DeviceImplCommand,
@@ -12,8 +16,19 @@ pub use test_device::{
 };
 #[cfg(feature = "server")]
 pub use test_device_comm_manager::{
-  new_bluetoothle_test_device, TestDeviceCommunicationManager, TestDeviceCommunicationManagerHelper,
+  new_bluetoothle_test_device, new_bluetoothle_test_device_with_cfg, new_hid_test_device,
+  new_uninitialized_ble_test_device, new_uninitialized_hid_test_device,
+  TestDeviceCommunicationManager, TestDeviceCommunicationManagerHelper,
+};
+#[cfg(feature = "hardware-tests")]
+pub use hardware::{
+  run_hardware_tests, HardwareDeviceReport, HardwareTestConfig, HardwareTestDevice,
+  HardwareTestReport, HardwareTestStep, HardwareTestStepResult,
 };
+#[cfg(feature = "server")]
+pub use transcript::replay_device_transcript;
+#[cfg(all(feature = "server", feature = "serialize-json"))]
+pub use transcript::replay_device_transcript_file;
 use tokio::sync::mpsc::Receiver;
 
 #[allow(dead_code)]
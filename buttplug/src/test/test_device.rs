@@ -6,17 +6,21 @@ use crate::{
   },
   device::{
     configuration_manager::{DeviceSpecifier, ProtocolDefinition},
-    ButtplugDeviceEvent, ButtplugDeviceImplCreator, DeviceImpl, DeviceImplCommand,
-    DeviceImplInternal, DeviceReadCmd, DeviceSubscribeCmd, DeviceUnsubscribeCmd, DeviceWriteCmd,
-    Endpoint,
+    ButtplugDeviceEvent, ButtplugDeviceImplCreator, DeviceConnectionPriority, DeviceImpl,
+    DeviceImplCommand, DeviceImplInternal, DeviceReadCmd, DeviceSubscribeCmd,
+    DeviceUnsubscribeCmd, DeviceWriteCmd, Endpoint,
   },
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
 use futures::future::{self, BoxFuture};
 use std::{
+  collections::VecDeque,
   fmt::{self, Debug},
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
 };
 use tokio::sync::{broadcast, mpsc};
 
@@ -65,6 +69,13 @@ impl ButtplugDeviceImplCreator for TestDeviceImplCreator {
         }
       }
     }
+    // HID devices always have the endpoints the HID comm manager gives
+    // them.
+    if protocol.hid.is_some() {
+      for endpoint in &[Endpoint::Tx, Endpoint::Rx, Endpoint::Command] {
+        device.add_endpoint(endpoint).await;
+      }
+    }
     let endpoints: Vec<Endpoint> = device
       .endpoint_channels
       .iter()
@@ -100,11 +111,18 @@ impl TestDeviceEndpointChannel {
   }
 }
 
+type ReadResponses = Arc<DashMap<Endpoint, std::sync::Mutex<VecDeque<Vec<u8>>>>>;
+
 pub struct TestDeviceInternal {
   name: String,
   address: String,
   endpoint_channels: Arc<DashMap<Endpoint, TestDeviceEndpointChannel>>,
+  read_responses: ReadResponses,
   event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+  connected: Arc<AtomicBool>,
+  hang_writes: Arc<AtomicBool>,
+  connection_priorities: Arc<std::sync::Mutex<Vec<DeviceConnectionPriority>>>,
+  supports_connection_priority: Arc<AtomicBool>,
 }
 
 impl TestDeviceInternal {
@@ -114,7 +132,12 @@ impl TestDeviceInternal {
       name: name.to_owned(),
       address: address.to_owned(),
       endpoint_channels: Arc::new(DashMap::new()),
+      read_responses: Arc::new(DashMap::new()),
       event_sender,
+      connected: Arc::new(AtomicBool::new(true)),
+      hang_writes: Arc::new(AtomicBool::new(false)),
+      connection_priorities: Arc::new(std::sync::Mutex::new(vec![])),
+      supports_connection_priority: Arc::new(AtomicBool::new(true)),
     }
   }
 
@@ -134,6 +157,50 @@ impl TestDeviceInternal {
     self.address.clone()
   }
 
+  /// Changes what the device reports for its connection state, without
+  /// sending any events, like hardware that goes away without telling anyone.
+  pub fn set_connected(&self, connected: bool) {
+    self.connected.store(connected, Ordering::SeqCst);
+  }
+
+  /// Makes writes never finish, like a hung transport.
+  pub fn set_hang_writes(&self, hang_writes: bool) {
+    self.hang_writes.store(hang_writes, Ordering::SeqCst);
+  }
+
+  /// Makes the device act like a transport that can't change its connection
+  /// priority.
+  pub fn set_supports_connection_priority(&self, supported: bool) {
+    self
+      .supports_connection_priority
+      .store(supported, Ordering::SeqCst);
+  }
+
+  /// Every connection priority the device has been asked for, in order.
+  pub fn connection_priorities(&self) -> Vec<DeviceConnectionPriority> {
+    self.connection_priorities.lock().unwrap().clone()
+  }
+
+  pub fn endpoints(&self) -> Vec<Endpoint> {
+    self
+      .endpoint_channels
+      .iter()
+      .map(|el| *el.key())
+      .collect()
+  }
+
+  /// Queues data to be returned by the next read on `endpoint`. Reads with
+  /// nothing queued return no data.
+  pub fn add_read_response(&self, endpoint: Endpoint, data: Vec<u8>) {
+    self
+      .read_responses
+      .entry(endpoint)
+      .or_default()
+      .lock()
+      .unwrap()
+      .push_back(data);
+  }
+
   pub fn get_endpoint_receiver(
     &self,
     endpoint: &Endpoint,
@@ -154,10 +221,12 @@ impl TestDeviceInternal {
   }
 
   pub fn disconnect(&self) -> ButtplugResultFuture {
+    self.connected.store(false, Ordering::SeqCst);
     let sender = self.event_sender.clone();
     let address = self.address.clone();
     Box::pin(async move {
-      sender.send(ButtplugDeviceEvent::Removed(address)).unwrap();
+      // No one listening is fine, the device may already be gone.
+      let _ = sender.send(ButtplugDeviceEvent::Removed(address));
       Ok(())
     })
   }
@@ -170,7 +239,12 @@ pub struct TestDevice {
   // for creation in ButtplugDevice, so initialization and cloning order
   // matters here.
   pub endpoint_channels: Arc<DashMap<Endpoint, TestDeviceEndpointChannel>>,
+  read_responses: ReadResponses,
   event_sender: broadcast::Sender<ButtplugDeviceEvent>,
+  connected: Arc<AtomicBool>,
+  hang_writes: Arc<AtomicBool>,
+  connection_priorities: Arc<std::sync::Mutex<Vec<DeviceConnectionPriority>>>,
+  supports_connection_priority: Arc<AtomicBool>,
 }
 
 impl TestDevice {
@@ -179,7 +253,12 @@ impl TestDevice {
     Self {
       address: internal_device.address(),
       endpoint_channels: internal_device.endpoint_channels.clone(),
+      read_responses: internal_device.read_responses.clone(),
       event_sender: internal_device.sender(),
+      connected: internal_device.connected.clone(),
+      hang_writes: internal_device.hang_writes.clone(),
+      connection_priorities: internal_device.connection_priorities.clone(),
+      supports_connection_priority: internal_device.supports_connection_priority.clone(),
     }
   }
 }
@@ -190,14 +269,16 @@ impl DeviceImplInternal for TestDevice {
   }
 
   fn connected(&self) -> bool {
-    true
+    self.connected.load(Ordering::SeqCst)
   }
 
   fn disconnect(&self) -> ButtplugResultFuture {
+    self.connected.store(false, Ordering::SeqCst);
     let sender = self.event_sender.clone();
     let address = self.address.clone();
     Box::pin(async move {
-      sender.send(ButtplugDeviceEvent::Removed(address)).unwrap();
+      // No one listening is fine, the device may already be gone.
+      let _ = sender.send(ButtplugDeviceEvent::Removed(address));
       Ok(())
     })
   }
@@ -206,10 +287,18 @@ impl DeviceImplInternal for TestDevice {
     &self,
     msg: DeviceReadCmd,
   ) -> BoxFuture<'static, Result<RawReading, ButtplugError>> {
-    Box::pin(future::ready(Ok(RawReading::new(0, msg.endpoint, vec![]))))
+    let data = self
+      .read_responses
+      .get(&msg.endpoint)
+      .and_then(|responses| responses.lock().unwrap().pop_front())
+      .unwrap_or_default();
+    Box::pin(future::ready(Ok(RawReading::new(0, msg.endpoint, data))))
   }
 
   fn write_value(&self, msg: DeviceWriteCmd) -> ButtplugResultFuture {
+    if self.hang_writes.load(Ordering::SeqCst) {
+      return Box::pin(future::pending());
+    }
     let channels = self.endpoint_channels.clone();
     Box::pin(async move {
       // Since we're only accessing a channel, we can use a read lock here.
@@ -231,4 +320,13 @@ impl DeviceImplInternal for TestDevice {
   fn unsubscribe(&self, _msg: DeviceUnsubscribeCmd) -> ButtplugResultFuture {
     Box::pin(future::ready(Ok(())))
   }
+
+  fn supports_connection_priority(&self) -> bool {
+    self.supports_connection_priority.load(Ordering::SeqCst)
+  }
+
+  fn set_connection_priority(&self, priority: DeviceConnectionPriority) -> ButtplugResultFuture {
+    self.connection_priorities.lock().unwrap().push(priority);
+    Box::pin(future::ready(Ok(())))
+  }
 }
@@ -1,8 +1,14 @@
 use super::{TestDeviceImplCreator, TestDeviceInternal};
 use crate::{
-  core::{errors::ButtplugError, ButtplugResultFuture},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{DeviceMetadata, DeviceTransport},
+    ButtplugResultFuture,
+  },
   device::{
-    configuration_manager::{BluetoothLESpecifier, DeviceConfigurationManager, DeviceSpecifier},
+    configuration_manager::{
+      BluetoothLESpecifier, DeviceConfigurationManager, DeviceSpecifier, HIDSpecifier,
+    },
     ButtplugDevice,
   },
   server::comm_managers::{
@@ -16,10 +22,11 @@ use std::{
 };
 use tokio::sync::{mpsc::Sender, Mutex};
 
-type WaitingDeviceList = Arc<Mutex<Vec<TestDeviceImplCreator>>>;
+type WaitingDeviceList = Arc<Mutex<Vec<(TestDeviceImplCreator, DeviceTransport)>>>;
 
-#[allow(dead_code)]
-fn new_uninitialized_ble_test_device(
+/// Creates a test device without initializing it, for tests that need to
+/// respond to a protocol's initialization (i.e. handshakes) while it happens.
+pub fn new_uninitialized_ble_test_device(
   name: &str,
   address: Option<String>,
 ) -> (Arc<TestDeviceInternal>, TestDeviceImplCreator) {
@@ -39,7 +46,23 @@ fn new_uninitialized_ble_test_device(
   (device_impl_clone, device_impl_creator)
 }
 
-async fn new_bluetoothle_test_device_with_cfg(
+/// Creates an uninitialized test device that looks like it was found by the
+/// HID comm manager. Use an address containing the HID over Bluetooth service
+/// UUID to look like a Bluetooth connection, anything else for USB.
+pub fn new_uninitialized_hid_test_device(
+  name: &str,
+  vendor_id: u16,
+  product_id: u16,
+  address: &str,
+) -> (Arc<TestDeviceInternal>, TestDeviceImplCreator) {
+  let specifier = DeviceSpecifier::HID(HIDSpecifier::new(vendor_id, product_id));
+  let device_impl = Arc::new(TestDeviceInternal::new(name, address));
+  let device_impl_clone = device_impl.clone();
+  let device_impl_creator = TestDeviceImplCreator::new(specifier, device_impl);
+  (device_impl_clone, device_impl_creator)
+}
+
+pub async fn new_bluetoothle_test_device_with_cfg(
   name: &str,
   device_config_mgr: Option<Arc<DeviceConfigurationManager>>,
 ) -> Result<(ButtplugDevice, Arc<TestDeviceInternal>), ButtplugError> {
@@ -55,6 +78,23 @@ async fn new_bluetoothle_test_device_with_cfg(
   Ok((device, device_impl_clone))
 }
 
+pub async fn new_hid_test_device(
+  name: &str,
+  vendor_id: u16,
+  product_id: u16,
+  address: &str,
+) -> Result<(ButtplugDevice, Arc<TestDeviceInternal>), ButtplugError> {
+  let config_mgr = Arc::new(DeviceConfigurationManager::default());
+  let (device_impl, device_impl_creator) =
+    new_uninitialized_hid_test_device(name, vendor_id, product_id, address);
+  let device: ButtplugDevice =
+    ButtplugDevice::try_create_device(config_mgr, Box::new(device_impl_creator))
+      .await
+      .unwrap()
+      .unwrap();
+  Ok((device, device_impl))
+}
+
 pub async fn new_bluetoothle_test_device(
   name: &str,
 ) -> Result<(ButtplugDevice, Arc<TestDeviceInternal>), ButtplugError> {
@@ -63,18 +103,43 @@ pub async fn new_bluetoothle_test_device(
 
 pub struct TestDeviceCommunicationManagerHelper {
   devices: WaitingDeviceList,
+  device_sender: Sender<DeviceCommunicationEvent>,
 }
 
 impl TestDeviceCommunicationManagerHelper {
-  pub(super) fn new(device_list: WaitingDeviceList) -> Self {
+  pub(super) fn new(
+    device_list: WaitingDeviceList,
+    device_sender: Sender<DeviceCommunicationEvent>,
+  ) -> Self {
     Self {
       devices: device_list,
+      device_sender,
     }
   }
 
+  /// Sends an error event, as a real comm manager would if its hardware had
+  /// problems.
+  pub async fn send_error(&self, err: ButtplugDeviceError) {
+    self
+      .device_sender
+      .send(DeviceCommunicationEvent::Error(err))
+      .await
+      .unwrap();
+  }
+
+  /// Acts like the comm manager's adapter was unplugged, which removes every
+  /// device the comm manager found.
+  pub async fn remove_adapter(&self, err: ButtplugDeviceError) {
+    self
+      .device_sender
+      .send(DeviceCommunicationEvent::AdapterRemoved(err))
+      .await
+      .unwrap();
+  }
+
   pub async fn add_ble_device(&self, name: &str) -> Arc<TestDeviceInternal> {
     let (device, creator) = new_uninitialized_ble_test_device(name, None);
-    self.devices.lock().await.push(creator);
+    self.devices.lock().await.push((creator, DeviceTransport::Test));
     device
   }
 
@@ -82,9 +147,23 @@ impl TestDeviceCommunicationManagerHelper {
     &self,
     name: &str,
     address: &str,
+  ) -> Arc<TestDeviceInternal> {
+    self
+      .add_ble_device_with_transport(name, address, DeviceTransport::Test)
+      .await
+  }
+
+  /// Adds a device that the comm manager reports as found over `transport`,
+  /// for testing how the server handles devices from different comm
+  /// managers.
+  pub async fn add_ble_device_with_transport(
+    &self,
+    name: &str,
+    address: &str,
+    transport: DeviceTransport,
   ) -> Arc<TestDeviceInternal> {
     let (device, creator) = new_uninitialized_ble_test_device(name, Some(address.to_owned()));
-    self.devices.lock().await.push(creator);
+    self.devices.lock().await.push((creator, transport));
     device
   }
 }
@@ -111,7 +190,7 @@ pub struct TestDeviceCommunicationManager {
 
 impl TestDeviceCommunicationManager {
   pub fn helper(&self) -> TestDeviceCommunicationManagerHelper {
-    TestDeviceCommunicationManagerHelper::new(self.devices.clone())
+    TestDeviceCommunicationManagerHelper::new(self.devices.clone(), self.device_sender.clone())
   }
 
   pub fn new(device_sender: Sender<DeviceCommunicationEvent>) -> Self {
@@ -135,7 +214,7 @@ impl DeviceCommunicationManager for TestDeviceCommunicationManager {
       if devices.is_empty() {
         panic!("No devices for test device comm manager to emit!");
       }
-      while let Some(d) = devices.pop() {
+      while let Some((d, transport)) = devices.pop() {
         if device_sender
           .send(DeviceCommunicationEvent::DeviceFound {
             name: d
@@ -147,6 +226,7 @@ impl DeviceCommunicationManager for TestDeviceCommunicationManager {
               .as_ref()
               .map_or("Test device address".to_owned(), |x| x.address().clone()),
             creator: Box::new(d),
+            metadata: DeviceMetadata::new(transport),
           })
           .await
           .is_err()
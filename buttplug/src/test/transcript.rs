@@ -0,0 +1,114 @@
+use super::new_bluetoothle_test_device;
+use crate::{
+  device::{
+    transcript::{DeviceTranscript, DeviceTranscriptEvent},
+    ButtplugDeviceEvent, DeviceImplCommand, DeviceWriteCmd,
+  },
+  util::stream::recv_now,
+};
+use futures_timer::Delay;
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::mpsc::Receiver;
+
+// Some protocols write from their own tasks (i.e. repeating commands), so give
+// writes a moment to show up.
+const REPLAY_WRITE_TIMEOUT_MS: u64 = 1000;
+const REPLAY_WRITE_POLL_MS: u64 = 10;
+
+async fn next_command(
+  receiver: &Arc<Mutex<Receiver<DeviceImplCommand>>>,
+) -> Option<DeviceImplCommand> {
+  for _ in 0..(REPLAY_WRITE_TIMEOUT_MS / REPLAY_WRITE_POLL_MS) {
+    if let Some(Some(command)) = recv_now(&mut receiver.lock().unwrap()) {
+      return Some(command);
+    }
+    Delay::new(Duration::from_millis(REPLAY_WRITE_POLL_MS)).await;
+  }
+  None
+}
+
+/// Replays a transcript against a test device using the protocol for the
+/// transcript's device name, panicking if the protocol's writes don't match
+/// the ones recorded.
+///
+/// Messages are sent in order, and notifications are emitted by the test
+/// device when they come up. Recorded reads are queued up as responses for
+/// the test device's reads. Subscriptions aren't tracked by test devices, so
+/// those entries are skipped. Recorded timing is not reproduced; writes are
+/// only required to show up in the right order.
+pub async fn replay_device_transcript(transcript: &DeviceTranscript) {
+  let (device, test_device) = new_bluetoothle_test_device(&transcript.name)
+    .await
+    .unwrap_or_else(|err| panic!("Cannot create device {}: {}", transcript.name, err));
+  // Initialization happens before recording starts, so get rid of anything it
+  // wrote.
+  for endpoint in test_device.endpoints() {
+    let receiver = test_device.get_endpoint_receiver(&endpoint).unwrap();
+    while let Some(Some(_)) = recv_now(&mut receiver.lock().unwrap()) {}
+  }
+  for entry in &transcript.entries {
+    if let DeviceTranscriptEvent::Read { endpoint, data } = &entry.event {
+      test_device.add_read_response(*endpoint, data.clone());
+    }
+  }
+  for (index, entry) in transcript.entries.iter().enumerate() {
+    match &entry.event {
+      DeviceTranscriptEvent::Message(message) => {
+        if let Err(err) = device.parse_message(message.clone()).await {
+          panic!(
+            "Transcript entry {} ({:?}) returned error: {}",
+            index, message, err
+          );
+        }
+      }
+      DeviceTranscriptEvent::Write {
+        endpoint,
+        data,
+        write_with_response,
+      } => {
+        let receiver = test_device
+          .get_endpoint_receiver(endpoint)
+          .unwrap_or_else(|| panic!("Transcript entry {}: no endpoint {}", index, endpoint));
+        let expected = DeviceImplCommand::Write(DeviceWriteCmd::new(
+          *endpoint,
+          data.clone(),
+          *write_with_response,
+        ));
+        assert_eq!(
+          next_command(&receiver).await,
+          Some(expected),
+          "Transcript entry {} (at {}ms) does not match",
+          index,
+          entry.time
+        );
+      }
+      DeviceTranscriptEvent::Notification { endpoint, data } => {
+        // Protocols that don't care about notifications won't be listening,
+        // so don't fail if nothing receives this.
+        let _ = test_device.sender().send(ButtplugDeviceEvent::Notification(
+          test_device.address(),
+          *endpoint,
+          data.clone(),
+        ));
+      }
+      DeviceTranscriptEvent::Read { .. }
+      | DeviceTranscriptEvent::Subscribe { .. }
+      | DeviceTranscriptEvent::Unsubscribe { .. } => {}
+    }
+  }
+}
+
+/// Loads a JSON transcript fixture and replays it with
+/// [replay_device_transcript].
+#[cfg(feature = "serialize-json")]
+pub async fn replay_device_transcript_file(path: impl AsRef<std::path::Path>) {
+  let path = path.as_ref();
+  let json = std::fs::read_to_string(path)
+    .unwrap_or_else(|err| panic!("Cannot read transcript {}: {}", path.display(), err));
+  let transcript = DeviceTranscript::from_json(&json)
+    .unwrap_or_else(|err| panic!("Cannot parse transcript {}: {}", path.display(), err));
+  replay_device_transcript(&transcript).await;
+}
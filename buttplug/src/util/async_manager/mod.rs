@@ -13,3 +13,6 @@ cfg_if::cfg_if! {
     std::compile_error!("Please choose a runtime feature: tokio-runtime, wasm-bindgen-runtime, dummy-runtime");
   }
 }
+
+mod task_registry;
+pub use task_registry::{running_tasks, shutdown_and_join, spawn_named};
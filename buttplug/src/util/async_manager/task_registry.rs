@@ -0,0 +1,105 @@
+//! Named task tracking, so embedders can check that nothing a session spawned
+//! outlives it.
+//!
+//! Long running tasks (event loops, comm manager scanning loops, connector
+//! pumps) are spawned through [spawn_named], which keeps track of them until
+//! they finish. [shutdown_and_join] aborts whatever's still running and waits
+//! for it to go away, handing back the names of anything that didn't.
+
+use super::spawn;
+use dashmap::DashMap;
+use futures::{
+  future::{self, AbortHandle, Abortable, Either, Future},
+  pin_mut,
+  task::SpawnError,
+};
+use futures_timer::Delay;
+use once_cell::sync::Lazy;
+use std::{
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct TaskRegistry {
+  next_id: AtomicU64,
+  tasks: DashMap<u64, (String, AbortHandle)>,
+  task_finished: Notify,
+}
+
+static TASK_REGISTRY: Lazy<TaskRegistry> = Lazy::new(TaskRegistry::default);
+
+/// Takes a task out of the registry when dropped, which happens when the task
+/// finishes, is aborted, or is dropped by the runtime without running.
+struct RegisteredTask(u64);
+
+impl Drop for RegisteredTask {
+  fn drop(&mut self) {
+    TASK_REGISTRY.tasks.remove(&self.0);
+    TASK_REGISTRY.task_finished.notify_waiters();
+  }
+}
+
+/// Same as [spawn], but the task shows up in [running_tasks] under `name`
+/// until it finishes, and can be stopped by [shutdown_and_join].
+pub fn spawn_named<Fut>(name: &str, future: Fut) -> Result<(), SpawnError>
+where
+  Fut: Future<Output = ()> + Send + 'static,
+{
+  let (abort_handle, abort_registration) = AbortHandle::new_pair();
+  let id = TASK_REGISTRY.next_id.fetch_add(1, Ordering::SeqCst);
+  TASK_REGISTRY
+    .tasks
+    .insert(id, (name.to_owned(), abort_handle));
+  let registered_task = RegisteredTask(id);
+  spawn(async move {
+    let _registered_task = registered_task;
+    // Aborted tasks just stop, there's nothing to report.
+    let _ = Abortable::new(future, abort_registration).await;
+  })
+}
+
+/// Names of tasks spawned with [spawn_named] that haven't finished yet, sorted
+/// so they're easy to compare. Names aren't unique, so a name shows up once
+/// for every task running under it.
+pub fn running_tasks() -> Vec<String> {
+  let mut names: Vec<String> = TASK_REGISTRY
+    .tasks
+    .iter()
+    .map(|task| task.value().0.clone())
+    .collect();
+  names.sort();
+  names
+}
+
+/// Aborts every task spawned with [spawn_named], then waits up to `timeout`
+/// for them to be dropped. Returns the names of tasks still around after the
+/// timeout, so an empty list means nothing leaked.
+///
+/// Tasks only notice they've been aborted the next time they're polled, so a
+/// task stuck in a blocking call will hold things up until it returns.
+pub async fn shutdown_and_join(timeout: Duration) -> Vec<String> {
+  for task in TASK_REGISTRY.tasks.iter() {
+    debug!("Aborting task {}", task.value().0);
+    task.value().1.abort();
+  }
+  let deadline = Delay::new(timeout);
+  pin_mut!(deadline);
+  loop {
+    // Has to exist before we check the registry, otherwise a task finishing
+    // in between would be missed.
+    let task_finished = TASK_REGISTRY.task_finished.notified();
+    if TASK_REGISTRY.tasks.is_empty() {
+      return vec![];
+    }
+    pin_mut!(task_finished);
+    if let Either::Right(_) = future::select(task_finished, deadline.as_mut()).await {
+      let remaining = running_tasks();
+      if !remaining.is_empty() {
+        warn!("Tasks still running after shutdown: {:?}", remaining);
+      }
+      return remaining;
+    }
+  }
+}
@@ -10,7 +10,7 @@
 //! buttplug message de/serializers in both the client and server. Uses the
 //! Valico library.
 
-use crate::core::messages::serializer::ButtplugSerializerError;
+use crate::core::{errors::ButtplugErrorCause, messages::serializer::ButtplugSerializerError};
 use serde_json::Value;
 use valico::json_schema;
 
@@ -43,7 +43,7 @@ impl JSONValidator {
   pub fn validate(&self, json_str: &str) -> Result<(), ButtplugSerializerError> {
     let schema = self.scope.resolve(&self.id).unwrap();
     let check_value = serde_json::from_str(json_str)
-      .map_err(|err| ButtplugSerializerError::JsonSerializerError(format!("{:?}", err)))?;
+      .map_err(|err| ButtplugSerializerError::JsonSerializerError(ButtplugErrorCause::new(err)))?;
     let state = schema.validate(&check_value);
     if state.is_valid() {
       Ok(())
@@ -1,4 +1,10 @@
 use crate::util::async_manager;
+use std::{
+  collections::hash_map::DefaultHasher,
+  fmt,
+  hash::{Hash, Hasher},
+  sync::atomic::{AtomicU8, Ordering},
+};
 use tokio::sync::mpsc::Sender;
 
 use tracing_subscriber::fmt::MakeWriter;
@@ -45,3 +51,93 @@ impl MakeWriter for ChannelWriter {
     ChannelWriter::new(self.log_sender.clone())
   }
 }
+
+/// How device addresses (bluetooth MACs, serial numbers, etc) show up in log
+/// output.
+///
+/// Users tend to post logs publicly when filing bugs, and an address next to a
+/// toy name is pretty identifying, so applications may want to turn this on
+/// before setting up their log outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LogAddressRedaction {
+  /// Log addresses as is.
+  None,
+  /// Only log the last 4 characters of addresses.
+  Truncate,
+  /// Replace addresses with a short hash. The hash is stable across runs, so
+  /// the same device can still be followed through multiple logs, but it's
+  /// not salted, so this is obfuscation, not anonymization.
+  Hash,
+}
+
+impl From<u8> for LogAddressRedaction {
+  fn from(mode: u8) -> Self {
+    match mode {
+      0 => LogAddressRedaction::None,
+      1 => LogAddressRedaction::Truncate,
+      _ => LogAddressRedaction::Hash,
+    }
+  }
+}
+
+static LOG_ADDRESS_REDACTION: AtomicU8 = AtomicU8::new(LogAddressRedaction::None as u8);
+
+/// Sets how device addresses are logged, for the whole process.
+pub fn set_log_address_redaction(mode: LogAddressRedaction) {
+  LOG_ADDRESS_REDACTION.store(mode as u8, Ordering::SeqCst);
+}
+
+/// Returns the current address redaction mode.
+pub fn log_address_redaction() -> LogAddressRedaction {
+  LOG_ADDRESS_REDACTION.load(Ordering::SeqCst).into()
+}
+
+/// Display wrapper that formats an address using the current
+/// [LogAddressRedaction] mode. Use [redact_address] to build one.
+pub struct RedactedAddress<T>(T);
+
+/// Wraps an address for use in log messages and tracing fields.
+pub fn redact_address<T: fmt::Display>(address: T) -> RedactedAddress<T> {
+  RedactedAddress(address)
+}
+
+impl<T: fmt::Display> fmt::Display for RedactedAddress<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match log_address_redaction() {
+      LogAddressRedaction::None => self.0.fmt(f),
+      LogAddressRedaction::Truncate => {
+        let address = self.0.to_string();
+        let chars: Vec<char> = address.chars().collect();
+        let tail: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+        write!(f, "...{}", tail)
+      }
+      LogAddressRedaction::Hash => {
+        let mut hasher = DefaultHasher::new();
+        self.0.to_string().hash(&mut hasher);
+        write!(f, "addr-{:08x}", hasher.finish() as u32)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_redact_address() {
+    let address = "AA:BB:CC:DD:EE:FF";
+    set_log_address_redaction(LogAddressRedaction::None);
+    assert_eq!(redact_address(address).to_string(), address);
+    set_log_address_redaction(LogAddressRedaction::Truncate);
+    assert_eq!(redact_address(address).to_string(), "...E:FF");
+    assert_eq!(redact_address("FF").to_string(), "...FF");
+    set_log_address_redaction(LogAddressRedaction::Hash);
+    let hashed = redact_address(address).to_string();
+    assert!(hashed.starts_with("addr-"));
+    assert!(!hashed.contains("FF"));
+    assert_eq!(hashed, redact_address(address).to_string());
+    set_log_address_redaction(LogAddressRedaction::None);
+  }
+}
@@ -2,25 +2,42 @@ mod util;
 extern crate buttplug;
 
 use buttplug::{
-  client::{ButtplugClient, ButtplugClientError, ButtplugClientEvent, VibrateCommand},
+  client::{
+    middleware::ButtplugClientMiddleware, ButtplugClient, ButtplugClientBuilder,
+    ButtplugClientError, ButtplugClientEvent, VibrateCommand,
+  },
   connector::{
-    ButtplugConnector, ButtplugConnectorError, ButtplugConnectorResultFuture,
-    ButtplugInProcessClientConnector,
+    transport::ButtplugTransportIncomingMessage, ButtplugConnector, ButtplugConnectorError,
+    ButtplugConnectorResultFuture, ButtplugFaultInjectionConnector,
+    ButtplugFaultInjectionOptions, ButtplugInProcessClientConnector,
   },
   core::{
-    errors::{ButtplugDeviceError, ButtplugError},
-    messages::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
+    messages::{
+      self, ButtplugClientMessage, ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage, ButtplugDeviceMessage, ButtplugMessage,
+    },
   },
   device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
   server::ButtplugServerOptions,
   test::check_test_recv_value,
   util::async_manager,
 };
-use futures::{future::BoxFuture, StreamExt};
+use futures::{
+  future::{self, BoxFuture},
+  select, FutureExt, StreamExt,
+};
 use futures_timer::Delay;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
-use util::DelayDeviceCommunicationManagerBuilder;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{self, channel, Sender};
+use util::{ChannelClientTestHelper, DelayDeviceCommunicationManagerBuilder};
 
 #[derive(Default)]
 struct ButtplugFailingConnector {}
@@ -113,6 +130,31 @@ fn test_client_connected_status() {
   });
 }
 
+#[cfg(feature = "server")]
+#[test]
+fn test_client_sync_clock() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    client
+      .connect(ButtplugInProcessClientConnector::default())
+      .await
+      .unwrap();
+    assert!(client.clock_offset().is_none());
+    let offset = client.sync_clock().await.unwrap();
+    // Same machine, same clock, so the estimate should be within its own
+    // uncertainty of zero.
+    assert!(i128::from(offset.offset_ms).abs() <= offset.uncertainty.as_millis() as i128);
+    assert_eq!(client.clock_offset(), Some(offset));
+    // Reconnecting throws the estimate out.
+    client.disconnect().await.unwrap();
+    client
+      .connect(ButtplugInProcessClientConnector::default())
+      .await
+      .unwrap();
+    assert!(client.clock_offset().is_none());
+  });
+}
+
 #[cfg(feature = "server")]
 #[test]
 fn test_start_scanning() {
@@ -167,6 +209,126 @@ fn test_start_scanning_when_already_scanning() {
   });
 }
 
+#[cfg(feature = "server")]
+#[test]
+fn test_client_scan_for_timeout() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    connector
+      .server_ref()
+      .add_comm_manager(DelayDeviceCommunicationManagerBuilder::default())
+      .unwrap();
+    let client = ButtplugClient::new("Test Client");
+    client.connect(connector).await.unwrap();
+    let devices = client.scan_for(Duration::from_millis(100)).await.unwrap();
+    assert!(devices.is_empty());
+    // Scanning was stopped when the time ran out.
+    assert!(client.start_scanning().await.is_ok());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_scan_for_devices() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    let client = ButtplugClient::new("Test Client");
+    client.connect(connector).await.unwrap();
+    // The test comm manager finishes scanning on its own, so this shouldn't
+    // wait anywhere near the full time.
+    let devices = select! {
+      devices = client.scan_for(Duration::from_secs(30)).fuse() => devices.unwrap(),
+      _ = Delay::new(Duration::from_secs(5)).fuse() => panic!("scan_for didn't finish early."),
+    };
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name(), "Aneros Vivi");
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_await_device() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    let client = ButtplugClient::new("Test Client");
+    client.connect(connector).await.unwrap();
+    let device = select! {
+      device = client.await_device("aneros vivi", Duration::from_secs(30)).fuse() => {
+        device.unwrap().unwrap()
+      }
+      _ = Delay::new(Duration::from_secs(5)).fuse() => panic!("await_device didn't find device."),
+    };
+    assert_eq!(device.name(), "Aneros Vivi");
+    // Devices the client already has come back without scanning.
+    let existing = client
+      .await_device(device.index(), Duration::from_millis(100))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(existing.index(), device.index());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_await_device_timeout() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    connector
+      .server_ref()
+      .add_comm_manager(DelayDeviceCommunicationManagerBuilder::default())
+      .unwrap();
+    let client = ButtplugClient::new("Test Client");
+    client.connect(connector).await.unwrap();
+    assert!(client
+      .await_device("Not A Device", Duration::from_millis(100))
+      .await
+      .unwrap()
+      .is_none());
+    // Scanning was stopped when the time ran out.
+    assert!(client.start_scanning().await.is_ok());
+    // Scanning someone else started is left alone.
+    assert!(client
+      .await_device("Not A Device", Duration::from_millis(100))
+      .await
+      .unwrap()
+      .is_none());
+    assert!(client.stop_scanning().await.is_ok());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_builder_event_handlers() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let added_sender = sender.clone();
+    let client = ButtplugClientBuilder::new("Test Client")
+      .on_device_added(move |device| added_sender.send(device.name()).unwrap())
+      .on_scanning_finished(move || sender.send("ScanningFinished".to_owned()).unwrap())
+      .connect(connector)
+      .await
+      .unwrap();
+    assert!(client.connected());
+    client.start_scanning().await.unwrap();
+    let mut events = vec![];
+    while events.len() < 2 {
+      select! {
+        event = receiver.recv().fuse() => events.push(event.unwrap()),
+        _ = Delay::new(Duration::from_secs(5)).fuse() => panic!("Handlers never called."),
+      }
+    }
+    assert_eq!(events, vec!["Aneros Vivi", "ScanningFinished"]);
+  });
+}
+
 #[cfg(feature = "server")]
 #[test]
 fn test_client_scanning_finished() {
@@ -257,11 +419,326 @@ fn test_stop_all_devices_and_device_command_range() {
   });
 }
 
+/// Wraps the in process connector, but drops DeviceAdded/DeviceRemoved events,
+/// to act like a server with unreliable event delivery.
+struct ButtplugDeviceEventDroppingConnector {
+  inner: ButtplugInProcessClientConnector,
+}
+
+impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>
+  for ButtplugDeviceEventDroppingConnector
+{
+  fn connect(
+    &mut self,
+    message_sender: Sender<ButtplugCurrentSpecServerMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let (inner_sender, mut inner_receiver) = channel(256);
+    async_manager::spawn(async move {
+      while let Some(msg) = inner_receiver.recv().await {
+        if matches!(
+          msg,
+          ButtplugCurrentSpecServerMessage::DeviceAdded(_)
+            | ButtplugCurrentSpecServerMessage::DeviceRemoved(_)
+        ) {
+          continue;
+        }
+        if message_sender.send(msg).await.is_err() {
+          break;
+        }
+      }
+    })
+    .unwrap();
+    self.inner.connect(inner_sender)
+  }
+
+  fn disconnect(&self) -> ButtplugConnectorResultFuture {
+    self.inner.disconnect()
+  }
+
+  fn send(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugConnectorResultFuture {
+    self.inner.send(msg)
+  }
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_refresh_device_list() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    client
+      .connect(ButtplugDeviceEventDroppingConnector { inner: connector })
+      .await
+      .unwrap();
+    client.start_scanning().await.unwrap();
+    while let Some(event) = event_stream.next().await {
+      match event {
+        ButtplugClientEvent::ScanningFinished => break,
+        ButtplugClientEvent::DeviceAdded(_) => panic!("DeviceAdded should have been dropped."),
+        _ => {}
+      }
+    }
+    assert!(client.devices().is_empty());
+
+    // ScanningFinished can beat the device being added on the server side, so
+    // give it a few tries.
+    for _ in 0..10 {
+      client.refresh_device_list().await.unwrap();
+      if !client.devices().is_empty() {
+        break;
+      }
+      Delay::new(Duration::from_millis(50)).await;
+    }
+    assert_eq!(client.devices().len(), 1);
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::DeviceAdded(_))
+    ));
+
+    device.disconnect().await.unwrap();
+    // Give the server a moment to handle the removal.
+    Delay::new(Duration::from_millis(100)).await;
+    client.refresh_device_list().await.unwrap();
+    assert!(client.devices().is_empty());
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::DeviceRemoved(_))
+    ));
+  });
+}
+
 // TODO Test calling connect twice
 // TODO Test calling disconnect twice w/o connection
 // TODO Test invalid return on RequestServerInfo
 // TODO Test invalid return on DeviceList
-// TODO Test receiving unmatched Ok (should emit error)
 // TODO Test receiving unmatched DeviceRemoved
 // TODO Test receiving Error when expecting Ok (i.e. StartScanning returns an error)
 // TODO Test receiving wrong message expecting Ok (i.e. StartScanning returns DeviceList)
+
+#[test]
+fn test_client_pending_requests_fail_on_connector_close() {
+  async_manager::block_on(async {
+    let helper = ChannelClientTestHelper::new();
+    helper.simulate_successful_connect().await;
+    let start_fut = helper.client().start_scanning();
+    let stop_fut = helper.client().stop_scanning();
+    assert!(matches!(
+      helper.get_next_client_message().await,
+      ButtplugClientMessage::StartScanning(..)
+    ));
+    assert!(matches!(
+      helper.get_next_client_message().await,
+      ButtplugClientMessage::StopScanning(..)
+    ));
+    // The server never answers, the connection just goes away.
+    helper
+      .send_incoming(ButtplugTransportIncomingMessage::Close(
+        "Test close".to_owned(),
+      ))
+      .await;
+    select! {
+      (start, stop) = future::join(start_fut, stop_fut).fuse() => {
+        for result in &[start, stop] {
+          assert!(matches!(
+            result.as_ref().unwrap_err(),
+            ButtplugClientError::ButtplugConnectorError(
+              ButtplugConnectorError::ConnectorNotConnected
+            )
+          ));
+        }
+      },
+      _ = Delay::new(Duration::from_secs(5)).fuse() => panic!("Pending requests never resolved."),
+    };
+  });
+}
+
+#[test]
+fn test_client_unmatched_reply_emits_error() {
+  async_manager::block_on(async {
+    let helper = ChannelClientTestHelper::new();
+    helper.simulate_successful_connect().await;
+    let mut event_stream = helper.client().event_stream();
+    // Nothing was sent with this id, and since it isn't 0 it can't be an
+    // event either.
+    let mut ok = messages::Ok::default();
+    ok.set_id(100);
+    helper.send_client_incoming(ok.into()).await;
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::Error(ButtplugError::ButtplugMessageError(
+        ButtplugMessageError::InvalidMessageContents(_)
+      )))
+    ));
+  });
+}
+
+#[derive(Default)]
+struct MessageCountingMiddleware {
+  outgoing: Arc<AtomicUsize>,
+  incoming: Arc<AtomicUsize>,
+}
+
+impl ButtplugClientMiddleware for MessageCountingMiddleware {
+  fn outgoing(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugCurrentSpecClientMessage {
+    self.outgoing.fetch_add(1, Ordering::SeqCst);
+    msg
+  }
+
+  fn incoming(
+    &self,
+    msg: ButtplugCurrentSpecServerMessage,
+  ) -> Option<ButtplugCurrentSpecServerMessage> {
+    self.incoming.fetch_add(1, Ordering::SeqCst);
+    Some(msg)
+  }
+}
+
+/// Halves all vibration speeds on the way out.
+struct HalfSpeedMiddleware;
+
+impl ButtplugClientMiddleware for HalfSpeedMiddleware {
+  fn outgoing(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugCurrentSpecClientMessage {
+    match msg {
+      ButtplugCurrentSpecClientMessage::VibrateCmd(cmd) => {
+        let speeds = cmd
+          .speeds()
+          .iter()
+          .map(|subcommand| {
+            messages::VibrateSubcommand::new(subcommand.index(), subcommand.speed() / 2.0)
+          })
+          .collect();
+        let mut halved = messages::VibrateCmd::new(cmd.device_index(), speeds);
+        halved.set_id(cmd.id());
+        halved.into()
+      }
+      msg => msg,
+    }
+  }
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_middleware_outgoing() {
+  async_manager::block_on(async {
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    let client = ButtplugClient::new("Test Client");
+    let counter = MessageCountingMiddleware::default();
+    let (outgoing, incoming) = (counter.outgoing.clone(), counter.incoming.clone());
+    client.add_middleware(counter);
+    let mut event_stream = client.event_stream();
+    client.connect(connector).await.unwrap();
+    // RequestServerInfo and RequestDeviceList, and their replies.
+    assert_eq!(outgoing.load(Ordering::SeqCst), 2);
+    assert_eq!(incoming.load(Ordering::SeqCst), 2);
+
+    client.add_middleware(HalfSpeedMiddleware);
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        client_device = Some(device);
+        break;
+      }
+    }
+    client_device
+      .unwrap()
+      .vibrate(VibrateCommand::Speed(1.0))
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    for data in &[[0xF1, 64], [0xF2, 64]] {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+      );
+    }
+    // StartScanning and VibrateCmd went through the counter too.
+    assert_eq!(outgoing.load(Ordering::SeqCst), 4);
+  });
+}
+
+/// Drops DeviceAdded events.
+struct DeviceAddedDroppingMiddleware;
+
+impl ButtplugClientMiddleware for DeviceAddedDroppingMiddleware {
+  fn incoming(
+    &self,
+    msg: ButtplugCurrentSpecServerMessage,
+  ) -> Option<ButtplugCurrentSpecServerMessage> {
+    match msg {
+      ButtplugCurrentSpecServerMessage::DeviceAdded(_) => None,
+      msg => Some(msg),
+    }
+  }
+}
+
+#[test]
+fn test_client_middleware_drops_incoming() {
+  async_manager::block_on(async {
+    let helper = ChannelClientTestHelper::new();
+    helper.simulate_successful_connect().await;
+    helper.client().add_middleware(DeviceAddedDroppingMiddleware);
+    let mut event_stream = helper.client().event_stream();
+    let device_added = messages::DeviceAdded::new(1, "Test Device", &HashMap::new());
+    helper.send_client_incoming(device_added.into()).await;
+    helper
+      .send_client_incoming(messages::ScanningFinished::default().into())
+      .await;
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::ScanningFinished)
+    ));
+    assert!(helper.client().devices().is_empty());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_fault_injection_latency() {
+  async_manager::block_on(async {
+    let options = ButtplugFaultInjectionOptions {
+      min_latency: Duration::from_millis(100),
+      max_latency: Duration::from_millis(100),
+      ..Default::default()
+    };
+    let connector = ButtplugFaultInjectionConnector::new(
+      ButtplugInProcessClientConnector::default(),
+      options,
+    );
+    let client = ButtplugClient::new("Test Client");
+    client.connect(connector).await.unwrap();
+    // Latency applies both ways.
+    let start = Instant::now();
+    client.stop_all_devices().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_fault_injection_disconnect() {
+  async_manager::block_on(async {
+    // Let the handshake through, then drop on the next message.
+    let options = ButtplugFaultInjectionOptions {
+      disconnect_after: Some(4),
+      ..Default::default()
+    };
+    let connector = ButtplugFaultInjectionConnector::new(
+      ButtplugInProcessClientConnector::default(),
+      options,
+    );
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    client.connect(connector).await.unwrap();
+    assert!(client.start_scanning().await.is_err());
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::ServerDisconnect)
+    ));
+  });
+}
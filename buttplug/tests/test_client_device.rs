@@ -1,19 +1,35 @@
 mod util;
 use buttplug::{
   client::{
+    media_sync::MediaSyncPlayer,
+    mixer::{IntensityMixer, MixPolicy},
+    patterns::{
+      play_synchronized, play_vibrate_pattern, supports_stored_patterns, DeviceChannels,
+      PlaybackTrack, SharedClock, TrackCommand, VibratePattern,
+    },
+    scene::{Scene, SceneEvent, SceneStage, SceneTarget, SceneTrigger},
     ButtplugClient, ButtplugClientDeviceEvent, ButtplugClientError, ButtplugClientEvent,
-    VibrateCommand,
+    ButtplugClientQueuedEvent, DeviceCommandOptions, RotateToAngleCommand, VibrateCommand,
   },
   connector::ButtplugInProcessClientConnector,
   core::{
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
-    messages::{self, ButtplugClientMessage},
+    messages::{self, ButtplugClientMessage, DeviceTransport},
   },
+  device::{ButtplugDeviceEvent, DeviceImplCommand, DeviceWriteCmd, Endpoint},
+  test::{check_test_recv_empty, check_test_recv_value},
   util::async_manager,
 };
-use futures::{pin_mut, StreamExt};
+use futures::{channel::mpsc, pin_mut, StreamExt};
 use futures_timer::Delay;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, SystemTime},
+};
 
 #[cfg(feature = "server")]
 #[test]
@@ -40,6 +56,10 @@ fn test_client_device_connected_status() {
     let test_device = client_device.unwrap();
     let mut device_event_stream = test_device.event_stream();
     assert!(test_device.connected());
+    assert_eq!(
+      test_device.metadata().map(|metadata| metadata.transport()),
+      Some(DeviceTransport::Test)
+    );
     device.disconnect().await.unwrap();
     while let Some(msg) = device_event_stream.next().await {
       if let ButtplugClientDeviceEvent::DeviceRemoved = msg {
@@ -52,6 +72,41 @@ fn test_client_device_connected_status() {
   });
 }
 
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_clones_share_state() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    let clone = (*test_device).clone();
+    assert_eq!(clone, *test_device);
+    // Events reach subscribers made through any clone.
+    let mut clone_event_stream = clone.event_stream();
+    device.disconnect().await.unwrap();
+    while let Some(msg) = clone_event_stream.next().await {
+      if let ButtplugClientDeviceEvent::DeviceRemoved = msg {
+        break;
+      }
+    }
+    assert!(!test_device.connected());
+    assert!(!clone.connected());
+    assert!(clone.vibrate(VibrateCommand::Speed(0.5)).await.is_err());
+  });
+}
+
 #[cfg(feature = "server")]
 #[test]
 fn test_client_device_client_disconnected_status() {
@@ -167,6 +222,307 @@ fn test_client_device_invalid_command() {
   });
 }
 
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_vibrate_primitive_conversions() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    test_device.vibrate(0.5).await.unwrap();
+    test_device.vibrate(vec![0.25]).await.unwrap();
+    test_device.vibrate(&[0.75][..]).await.unwrap();
+    let mut speed_map = HashMap::new();
+    speed_map.insert(0, 1.0);
+    test_device.vibrate(speed_map).await.unwrap();
+    assert!(matches!(
+      test_device.vibrate(2.0).await.unwrap_err(),
+      ButtplugClientError::ButtplugError(ButtplugError::ButtplugMessageError(
+        ButtplugMessageError::InvalidMessageContents(..)
+      ))
+    ));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_capabilities() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    assert!(test_device.supports_vibrate());
+    assert_eq!(test_device.vibrate_feature_count(), Some(2));
+    assert!(!test_device.supports_linear());
+    assert_eq!(test_device.linear_feature_count(), None);
+    assert!(!test_device.supports_rotate());
+    assert_eq!(test_device.rotate_feature_count(), None);
+    // Out of range indexes are errors, not panics.
+    let mut speed_map = HashMap::new();
+    speed_map.insert(2, 1.0);
+    assert!(matches!(
+      test_device.vibrate(speed_map).await.unwrap_err(),
+      ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::DeviceFeatureIndexError(2, 2)
+      ))
+    ));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_synchronized_playback() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let first = helper.add_ble_device("Massage Demo").await;
+    let second = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_devices = vec![];
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_devices.push(da);
+        if client_devices.len() == 2 {
+          break;
+        }
+      }
+    }
+    let tracks: Vec<PlaybackTrack> = client_devices
+      .iter()
+      .map(|device| {
+        PlaybackTrack::new(device.clone())
+          .command(Duration::from_millis(50), TrackCommand::Stop)
+          .command(Duration::from_millis(0), TrackCommand::Vibrate(0.5))
+      })
+      .collect();
+    let clock = SharedClock::new(Duration::from_millis(10));
+    play_synchronized(&tracks, clock).await.unwrap();
+    assert!(clock.elapsed() >= Duration::from_millis(50));
+    for device in &[first, second] {
+      let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+      for data in &[[0xF1, 64], [0xF2, 64], [0xF1, 0], [0xF2, 0]] {
+        check_test_recv_value(
+          &command_receiver,
+          DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+        );
+      }
+    }
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_scene_playback() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let scene = Scene::new()
+      .stage(
+        SceneStage::new("warmup")
+          .command(Duration::from_millis(0), TrackCommand::Vibrate(0.25))
+          .on(SceneTrigger::Button("next".to_owned()), SceneTarget::stage("peak")),
+      )
+      .stage(
+        SceneStage::new("peak")
+          .command(Duration::from_millis(0), TrackCommand::Vibrate(1.0))
+          .on(SceneTrigger::After(Duration::from_millis(50)), SceneTarget::End),
+      );
+    let (event_sender, event_receiver) = mpsc::unbounded();
+    async_manager::spawn(async move {
+      Delay::new(Duration::from_millis(50)).await;
+      // Buttons the stage doesn't know about are ignored.
+      event_sender
+        .unbounded_send(SceneEvent::Button("back".to_owned()))
+        .unwrap();
+      event_sender
+        .unbounded_send(SceneEvent::Button("next".to_owned()))
+        .unwrap();
+    })
+    .unwrap();
+    scene
+      .play(client_device.unwrap(), event_receiver)
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    for data in &[[0xF1, 32], [0xF2, 32], [0xF1, 127], [0xF2, 127], [0xF1, 0], [0xF2, 0]] {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+      );
+    }
+    check_test_recv_empty(&command_receiver);
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_media_sync_playback() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let track = PlaybackTrack::new(client_device.unwrap())
+      .command(Duration::from_millis(50), TrackCommand::Vibrate(0.5))
+      .command(Duration::from_secs(10), TrackCommand::Vibrate(1.0));
+    let player = MediaSyncPlayer::new(vec![track]);
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    let check_writes = |speed| {
+      for data in &[[0xF1, speed], [0xF2, speed]] {
+        check_test_recv_value(
+          &command_receiver,
+          DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+        );
+      }
+    };
+    player.update(Duration::from_millis(0), 1.0);
+    Delay::new(Duration::from_millis(150)).await;
+    check_writes(64);
+    // Seeking past the second command sends it right away.
+    player.seek(Duration::from_secs(20));
+    Delay::new(Duration::from_millis(50)).await;
+    check_writes(127);
+    player.pause();
+    assert!(player.paused());
+    Delay::new(Duration::from_millis(50)).await;
+    check_writes(0);
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_intensity_mixer() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let mixer = IntensityMixer::new(client_device.unwrap(), MixPolicy::Max);
+    let base = mixer.add_source();
+    let event = mixer.add_source();
+    base.set(0.5).await.unwrap();
+    event.set_vec(vec![1.0]).await.unwrap();
+    assert_eq!(mixer.mixed_speeds(), Some(vec![1.0, 0.5]));
+    // Lower than the event on the first vibrator, so only the second changes.
+    base.set(0.75).await.unwrap();
+    event.clear().await.unwrap();
+    assert_eq!(mixer.mixed_speeds(), Some(vec![0.75, 0.75]));
+    // Same mix as before, so nothing is sent.
+    event.set(0.25).await.unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    for data in [[0xF1, 64], [0xF2, 64], [0xF1, 127], [0xF2, 96], [0xF1, 96]] {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+      );
+    }
+    assert!(check_test_recv_empty(&command_receiver));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_channel_tracks() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let channels = DeviceChannels::new(client_device.unwrap());
+    let left = channels
+      .track(&[0])
+      .command(Duration::from_millis(0), TrackCommand::Vibrate(1.0))
+      .command(Duration::from_millis(100), TrackCommand::Stop);
+    let right = channels
+      .track(&[1])
+      .command(Duration::from_millis(50), TrackCommand::Vibrate(0.5));
+    assert_eq!(left.features(), Some(&[0][..]));
+    let tracks = [left, right];
+    play_synchronized(&tracks, SharedClock::new(Duration::from_millis(10)))
+      .await
+      .unwrap();
+    // Stopping the left channel left the right one running.
+    assert_eq!(channels.mixed_speeds(), Some(vec![0.0, 0.5]));
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    for data in [[0xF1, 127], [0xF2, 0], [0xF2, 64], [0xF1, 0]] {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data.to_vec(), false)),
+      );
+    }
+    assert!(check_test_recv_empty(&command_receiver));
+  });
+}
+
 #[cfg(feature = "server")]
 #[test]
 fn test_client_repeated_deviceadded_message() {
@@ -251,3 +607,501 @@ fn test_client_repeated_deviceremoved_message() {
 // TODO Test DeviceList being sent followed by repeat DeviceAdded
 // TODO Test DeviceList being sent multiple times
 // TODO Test sending device return for device that doesn't exist (in client)
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_stop_guard() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let test_device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let client_device = client_device.unwrap();
+    {
+      let guard = client_device.stop_guard();
+      guard.vibrate(0.5).await.unwrap();
+    }
+    let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+    );
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF2, 64], false)),
+    );
+    // The stop isn't awaited, so give it a moment to make it to the device.
+    Delay::new(Duration::from_millis(100)).await;
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false)),
+    );
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF2, 0], false)),
+    );
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_event_queue_ordering() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut queue = client.event_queue();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let timeout = Some(Duration::from_secs(5));
+    let mut device_index = None;
+    while let Some(event) = queue.next_event_async(timeout).await {
+      if let ButtplugClientQueuedEvent::Client(ButtplugClientEvent::DeviceAdded(da)) = event {
+        device_index = Some(da.index());
+        break;
+      }
+    }
+    let device_index = device_index.expect("Should have received DeviceAdded");
+    device.disconnect().await.unwrap();
+    // The device hears about its removal before the client does.
+    loop {
+      match queue.next_event_async(timeout).await {
+        Some(ButtplugClientQueuedEvent::Device {
+          device_index: index,
+          event: ButtplugClientDeviceEvent::DeviceRemoved,
+        }) => {
+          assert_eq!(index, device_index);
+          break;
+        }
+        Some(ButtplugClientQueuedEvent::Client(ButtplugClientEvent::DeviceRemoved(_))) => {
+          panic!("Client DeviceRemoved received before device DeviceRemoved")
+        }
+        Some(_) => {}
+        None => panic!("Event queue timed out"),
+      }
+    }
+    assert!(matches!(
+      queue.next_event_async(timeout).await,
+      Some(ButtplugClientQueuedEvent::Client(
+        ButtplugClientEvent::DeviceRemoved(_)
+      ))
+    ));
+    assert!(queue.try_next_event().is_none());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_statistics() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    let statistics = test_device.statistics().await.unwrap();
+    assert_eq!(statistics.write_count(), 0);
+    test_device.vibrate(0.5).await.unwrap();
+    let statistics = test_device.statistics().await.unwrap();
+    assert_eq!(statistics.write_count(), 2);
+    assert_eq!(statistics.error_count(), 0);
+    assert_eq!(statistics.last_error(), &None);
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_session_limit() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    let check_write = |data: Vec<u8>| {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data, false)),
+      );
+    };
+    test_device
+      .set_session_limit(Duration::from_millis(50), Duration::from_millis(200))
+      .await
+      .unwrap();
+    test_device.vibrate(1.0).await.unwrap();
+    check_write(vec![0xF1, 127]);
+    check_write(vec![0xF2, 127]);
+    Delay::new(Duration::from_millis(600)).await;
+    // Halfway through winding down, then stopped.
+    check_write(vec![0xF1, 64]);
+    check_write(vec![0xF2, 64]);
+    check_write(vec![0xF1, 0]);
+    check_write(vec![0xF2, 0]);
+    assert!(check_test_recv_empty(&command_receiver));
+    assert!(matches!(
+      test_device.vibrate(1.0).await.unwrap_err(),
+      ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::DeviceSessionExpired(_)
+      ))
+    ));
+    test_device.stop().await.unwrap();
+    // Without a limit, the device runs as long as it's told to.
+    test_device.clear_session_limit().await.unwrap();
+    test_device.vibrate(0.5).await.unwrap();
+    check_write(vec![0xF1, 64]);
+    check_write(vec![0xF2, 64]);
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_scheduled_commands() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    let check_write = |data: Vec<u8>| {
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, data, false)),
+      );
+    };
+    // Accepted right away, but nothing goes to the device until it's due.
+    test_device
+      .vibrate_with_options(
+        1.0,
+        DeviceCommandOptions::run_at(SystemTime::now() + Duration::from_millis(200)),
+      )
+      .await
+      .unwrap();
+    assert!(check_test_recv_empty(&command_receiver));
+    Delay::new(Duration::from_millis(400)).await;
+    check_write(vec![0xF1, 127]);
+    check_write(vec![0xF2, 127]);
+    // Stopping the device drops anything still waiting for it.
+    test_device
+      .vibrate_with_options(
+        0.5,
+        DeviceCommandOptions::run_at(SystemTime::now() + Duration::from_millis(200)),
+      )
+      .await
+      .unwrap();
+    test_device.stop().await.unwrap();
+    check_write(vec![0xF1, 0]);
+    check_write(vec![0xF2, 0]);
+    Delay::new(Duration::from_millis(400)).await;
+    assert!(check_test_recv_empty(&command_receiver));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_lovense_stored_pattern() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("LVS-Test").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    // Lovense toys identify themselves in reply to DeviceType. Keep answering
+    // until the device shows up, since we don't know when it'll ask.
+    let identified = Arc::new(AtomicBool::new(false));
+    let identified_clone = identified.clone();
+    let device_clone = device.clone();
+    async_manager::spawn(async move {
+      while !identified_clone.load(Ordering::SeqCst) {
+        device_clone.send_event(ButtplugDeviceEvent::Notification(
+          device_clone.address(),
+          Endpoint::Rx,
+          b"P:37:0082059AD3BD;".to_vec(),
+        ));
+        Delay::new(Duration::from_millis(50)).await;
+      }
+    })
+    .unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    identified.store(true, Ordering::SeqCst);
+    let test_device = client_device.unwrap();
+    assert!(supports_stored_patterns(&test_device));
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    let take_writes = || {
+      let mut writes = vec![];
+      while let Ok(DeviceImplCommand::Write(cmd)) = command_receiver.lock().unwrap().try_recv() {
+        writes.push(String::from_utf8(cmd.data).unwrap());
+      }
+      writes
+    };
+    // Throw out the DeviceType queries.
+    take_writes();
+    // Stored and played on the device, then stopped.
+    let pattern = VibratePattern::new(Duration::from_millis(100), vec![0.5, 1.0]);
+    play_vibrate_pattern(test_device.clone(), &pattern, Duration::from_millis(50))
+      .await
+      .unwrap();
+    assert_eq!(
+      take_writes(),
+      vec![
+        "SetPattern:10:100:10,20;",
+        "Preset:10;",
+        "Preset:0;",
+        "Vibrate:0;"
+      ]
+    );
+    // Too fast for the device to store, so it gets streamed instead.
+    let pattern = VibratePattern::new(Duration::from_millis(20), vec![0.5, 1.0]);
+    play_vibrate_pattern(test_device.clone(), &pattern, Duration::from_millis(30))
+      .await
+      .unwrap();
+    assert_eq!(
+      take_writes(),
+      vec!["Vibrate:10;", "Vibrate:20;", "Vibrate:0;"]
+    );
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_keyed_command_unsupported() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    assert!(test_device.keyed_command_keys().is_empty());
+    assert!(matches!(
+      test_device.keyed_command("Light", "Off").await.unwrap_err(),
+      ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::MessageNotSupported(
+          messages::ButtplugDeviceMessageType::KeyedDeviceCmd
+        )
+      ))
+    ));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_rotate_to_angle_unsupported() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    assert!(!test_device.supports_rotate_to_angle());
+    assert!(matches!(
+      test_device
+        .rotate_to_angle(RotateToAngleCommand::Angle(500, 0.5))
+        .await
+        .unwrap_err(),
+      ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::MessageNotSupported(
+          messages::ButtplugDeviceMessageType::RotateToAngleCmd
+        )
+      ))
+    ));
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_set_temperature() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Emma NEO").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    assert!(test_device.supports_temperature());
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    test_device.set_temperature(1.0).await.unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(
+        Endpoint::Tx,
+        vec![0x55, 0x05, 0x00, 0x00, 0x01, 0x03],
+        false,
+      )),
+    );
+    assert!(test_device.set_temperature(1.5).await.is_err());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_updated() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let server = connector.server();
+    let helper = server.add_test_comm_manager().unwrap();
+    let _ = helper
+      .add_ble_device_with_address("Massage Demo", "device-updated-test-address")
+      .await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    let device_clone = (*test_device).clone();
+    let mut device_events = device_clone.event_stream();
+    let allowed_messages = test_device.allowed_messages();
+    server.set_device_display_name("device-updated-test-address", "Bedside");
+    loop {
+      match event_stream.next().await.unwrap() {
+        ButtplugClientEvent::DeviceUpdated(device) => {
+          assert_eq!(device.index(), test_device.index());
+          break;
+        }
+        ButtplugClientEvent::ScanningFinished => continue,
+        event => panic!("Expected DeviceUpdated, got {:?}", event),
+      }
+    }
+    assert!(matches!(
+      device_events.next().await,
+      Some(ButtplugClientDeviceEvent::DeviceUpdated)
+    ));
+    // Every copy of the device sees the update, and keeps working.
+    assert_eq!(test_device.name(), "Bedside");
+    assert_eq!(device_clone.name(), "Bedside");
+    assert_eq!(device_clone.allowed_messages(), allowed_messages);
+    assert_eq!(client.devices()[0].name(), "Bedside");
+    device_clone.vibrate(0.5).await.unwrap();
+    // Removing the name sends the config name back out.
+    server.remove_device_display_name("device-updated-test-address");
+    loop {
+      if let ButtplugClientEvent::DeviceUpdated(_) = event_stream.next().await.unwrap() {
+        break;
+      }
+    }
+    assert_eq!(test_device.name(), "Aneros Vivi");
+    assert!(server.refresh_device(test_device.index()).is_ok());
+    assert!(server.refresh_device(100).is_err());
+  });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_device_status() {
+  async_manager::block_on(async {
+    let client = ButtplugClient::new("Test Client");
+    let mut event_stream = client.event_stream();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    let _ = helper.add_ble_device("Massage Demo").await;
+    client.connect(connector).await.unwrap();
+    client.start_scanning().await.unwrap();
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.unwrap();
+    test_device.vibrate(1.0).await.unwrap();
+    let status = test_device.status().await.unwrap();
+    assert_eq!(
+      status.speeds(),
+      &vec![
+        messages::VibrateSubcommand::new(0, 1.0),
+        messages::VibrateSubcommand::new(1, 1.0)
+      ]
+    );
+    assert!(status.rotations().is_empty());
+    assert!(status.positions().is_empty());
+    test_device.stop().await.unwrap();
+    let status = test_device.status().await.unwrap();
+    assert_eq!(status.speeds()[0].speed(), 0.0);
+  });
+}
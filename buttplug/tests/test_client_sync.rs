@@ -0,0 +1,58 @@
+#![cfg(all(feature = "client-sync", feature = "server"))]
+
+use buttplug::{
+  client::{sync::ButtplugClientSync, ButtplugClientEvent},
+  connector::ButtplugInProcessClientConnector,
+  device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
+  test::check_test_recv_value,
+};
+use std::time::Duration;
+
+#[test]
+fn test_sync_client_device_control() {
+  let client = ButtplugClientSync::new("Test Client").unwrap();
+  let handle = client.runtime_handle();
+  // The in process connector starts up its server on creation, so it has to be
+  // set up inside the client runtime.
+  let (connector, helper) = {
+    let _guard = handle.enter();
+    let connector = ButtplugInProcessClientConnector::default();
+    let helper = connector.server_ref().add_test_comm_manager().unwrap();
+    (connector, helper)
+  };
+  let test_device = handle.block_on(helper.add_ble_device("Massage Demo"));
+  client.connect(connector).unwrap();
+  assert!(client.connected());
+  client.start_scanning().unwrap();
+  let mut device = None;
+  while let Some(event) = client.next_event(Some(Duration::from_secs(5))) {
+    if let ButtplugClientEvent::DeviceAdded(added) = event {
+      device = Some(client.wrap_device(added));
+      break;
+    }
+  }
+  let device = device.expect("Should have received DeviceAdded");
+  assert_eq!(device.name(), "Aneros Vivi");
+  assert_eq!(client.devices().len(), 1);
+  device.vibrate(0.5).unwrap();
+  let command_receiver = test_device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+  check_test_recv_value(
+    &command_receiver,
+    DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+  );
+  check_test_recv_value(
+    &command_receiver,
+    DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF2, 64], false)),
+  );
+  client.disconnect().unwrap();
+  assert!(!client.connected());
+}
+
+#[test]
+fn test_sync_client_event_timeout() {
+  let client = ButtplugClientSync::new("Test Client").unwrap();
+  assert!(client
+    .next_event(Some(Duration::from_millis(50)))
+    .is_none());
+  assert!(client.try_next_event().is_none());
+}
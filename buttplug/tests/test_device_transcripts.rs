@@ -0,0 +1,21 @@
+use buttplug::{test::replay_device_transcript_file, util::async_manager};
+use std::{fs, path::PathBuf};
+
+// Replays every transcript fixture in tests/transcripts against the current
+// protocol implementations. To add a fixture, record a transcript with
+// ButtplugDevice::start_transcript()/stop_transcript() and save the output of
+// DeviceTranscript::to_json() there.
+#[test]
+fn test_device_transcripts() {
+  let fixture_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/transcripts");
+  let mut fixtures: Vec<PathBuf> = fs::read_dir(&fixture_dir)
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension() == Some("json".as_ref()))
+    .collect();
+  fixtures.sort();
+  assert!(!fixtures.is_empty());
+  for fixture in fixtures {
+    async_manager::block_on(replay_device_transcript_file(&fixture));
+  }
+}
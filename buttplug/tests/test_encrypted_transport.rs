@@ -0,0 +1,142 @@
+#[cfg(feature = "e2e-encryption")]
+mod encrypted_transport_tests {
+  use buttplug::{
+    client::ButtplugClient,
+    connector::{
+      transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+      ButtplugConnectorError, ButtplugEncryptedTransport, ButtplugPairingKey,
+      ButtplugRemoteClientConnector, ButtplugRemoteServerConnector,
+    },
+    core::messages::serializer::{
+      ButtplugClientJSONSerializer, ButtplugSerializedMessage, ButtplugServerJSONSerializer,
+    },
+    server::ButtplugRemoteServer,
+    util::async_manager,
+  };
+  use futures::future::{self, BoxFuture};
+  use std::sync::{Arc, Mutex};
+  use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+  /// One end of an in-memory link, standing in for a relay. Keeps a copy of
+  /// everything it sends, so tests can check what the relay could see.
+  struct RelayTransport {
+    to_peer: Sender<ButtplugSerializedMessage>,
+    from_peer: Mutex<Option<Receiver<ButtplugSerializedMessage>>>,
+    seen: Arc<Mutex<Vec<ButtplugSerializedMessage>>>,
+  }
+
+  impl ButtplugConnectorTransport for RelayTransport {
+    fn connect(
+      &self,
+      mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+      incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+    ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+      let to_peer = self.to_peer.clone();
+      let seen = self.seen.clone();
+      async_manager::spawn(async move {
+        while let Some(msg) = outgoing_receiver.recv().await {
+          seen.lock().unwrap().push(msg.clone());
+          if to_peer.send(msg).await.is_err() {
+            return;
+          }
+        }
+      })
+      .unwrap();
+      let mut from_peer = self.from_peer.lock().unwrap().take().unwrap();
+      async_manager::spawn(async move {
+        while let Some(msg) = from_peer.recv().await {
+          if incoming_sender
+            .send(ButtplugTransportIncomingMessage::Message(msg))
+            .await
+            .is_err()
+          {
+            return;
+          }
+        }
+      })
+      .unwrap();
+      Box::pin(future::ready(Ok(())))
+    }
+
+    fn disconnect(self) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+      Box::pin(future::ready(Ok(())))
+    }
+  }
+
+  fn relay_pair(
+    seen: &Arc<Mutex<Vec<ButtplugSerializedMessage>>>,
+  ) -> (RelayTransport, RelayTransport) {
+    let (first_sender, first_receiver) = channel(256);
+    let (second_sender, second_receiver) = channel(256);
+    (
+      RelayTransport {
+        to_peer: second_sender,
+        from_peer: Mutex::new(Some(first_receiver)),
+        seen: seen.clone(),
+      },
+      RelayTransport {
+        to_peer: first_sender,
+        from_peer: Mutex::new(Some(second_receiver)),
+        seen: seen.clone(),
+      },
+    )
+  }
+
+  async fn connect_pair(
+    client_key: ButtplugPairingKey,
+    server_key: ButtplugPairingKey,
+    seen: &Arc<Mutex<Vec<ButtplugSerializedMessage>>>,
+  ) -> (ButtplugClient, bool) {
+    let (client_relay, server_relay) = relay_pair(seen);
+    let server = Arc::new(ButtplugRemoteServer::default());
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<_, ButtplugServerJSONSerializer>::new(
+        ButtplugEncryptedTransport::new(server_relay, server_key),
+      );
+      let _ = server.start(connector).await;
+    })
+    .unwrap();
+    let connector = ButtplugRemoteClientConnector::<_, ButtplugClientJSONSerializer>::new(
+      ButtplugEncryptedTransport::new(client_relay, client_key),
+    );
+    let client = ButtplugClient::new("Test Client");
+    let connected = client.connect(connector).await.is_ok();
+    (client, connected)
+  }
+
+  #[test]
+  fn test_encrypted_transport_connection() {
+    async_manager::block_on(async move {
+      let seen = Arc::new(Mutex::new(vec![]));
+      let key = ButtplugPairingKey::generate();
+      let (client, connected) = connect_pair(key.clone(), key, &seen).await;
+      assert!(connected);
+      client.stop_all_devices().await.unwrap();
+      // The relay only ever saw binary, none of which was readable JSON.
+      let seen = seen.lock().unwrap();
+      assert!(!seen.is_empty());
+      for msg in seen.iter() {
+        match msg {
+          ButtplugSerializedMessage::Binary(data) => {
+            assert!(!String::from_utf8_lossy(data).contains("StopAllDevices"))
+          }
+          ButtplugSerializedMessage::Text(text) => panic!("Relay saw plaintext: {}", text),
+        }
+      }
+    });
+  }
+
+  #[test]
+  fn test_encrypted_transport_wrong_key() {
+    async_manager::block_on(async move {
+      let seen = Arc::new(Mutex::new(vec![]));
+      let (_client, connected) = connect_pair(
+        ButtplugPairingKey::generate(),
+        ButtplugPairingKey::generate(),
+        &seen,
+      )
+      .await;
+      assert!(!connected);
+    });
+  }
+}
@@ -0,0 +1,151 @@
+use buttplug::{
+  client::ButtplugClient,
+  connector::{
+    transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+    ButtplugConnectorError, ButtplugRemoteClientConnector, ButtplugRemoteServerConnector,
+    ButtplugTransportMultiplexer,
+  },
+  core::messages::serializer::{
+    ButtplugClientJSONSerializer, ButtplugSerializedMessage, ButtplugServerJSONSerializer,
+  },
+  server::ButtplugRemoteServer,
+  util::async_manager,
+};
+use futures::future::{self, BoxFuture};
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  Arc, Mutex,
+};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// One end of an in-memory link, standing in for a websocket. Counts how many
+/// times it's been connected, so tests can check channels share it.
+struct LinkTransport {
+  to_peer: Sender<ButtplugSerializedMessage>,
+  from_peer: Mutex<Option<Receiver<ButtplugSerializedMessage>>>,
+  connections: Arc<AtomicUsize>,
+}
+
+impl ButtplugConnectorTransport for LinkTransport {
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    self.connections.fetch_add(1, Ordering::SeqCst);
+    let to_peer = self.to_peer.clone();
+    async_manager::spawn(async move {
+      while let Some(msg) = outgoing_receiver.recv().await {
+        if to_peer.send(msg).await.is_err() {
+          return;
+        }
+      }
+    })
+    .unwrap();
+    let mut from_peer = match self.from_peer.lock().unwrap().take() {
+      Some(from_peer) => from_peer,
+      None => {
+        return Box::pin(future::ready(Err(
+          ButtplugConnectorError::ConnectorAlreadyConnected,
+        )))
+      }
+    };
+    async_manager::spawn(async move {
+      while let Some(msg) = from_peer.recv().await {
+        if incoming_sender
+          .send(ButtplugTransportIncomingMessage::Message(msg))
+          .await
+          .is_err()
+        {
+          return;
+        }
+      }
+    })
+    .unwrap();
+    Box::pin(future::ready(Ok(())))
+  }
+
+  fn disconnect(self) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    Box::pin(future::ready(Ok(())))
+  }
+}
+
+fn link_pair(connections: &Arc<AtomicUsize>) -> (LinkTransport, LinkTransport) {
+  let (first_sender, first_receiver) = channel(256);
+  let (second_sender, second_receiver) = channel(256);
+  (
+    LinkTransport {
+      to_peer: second_sender,
+      from_peer: Mutex::new(Some(first_receiver)),
+      connections: connections.clone(),
+    },
+    LinkTransport {
+      to_peer: first_sender,
+      from_peer: Mutex::new(Some(second_receiver)),
+      connections: connections.clone(),
+    },
+  )
+}
+
+#[test]
+fn test_multiplexed_transport_clients() {
+  async_manager::block_on(async move {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let (client_link, server_link) = link_pair(&connections);
+    let client_multiplexer = ButtplugTransportMultiplexer::new(client_link);
+    let server_multiplexer = ButtplugTransportMultiplexer::new(server_link);
+    let mut clients = vec![];
+    for channel_id in 1..=2 {
+      // Each channel is its own session, with its own server.
+      let server = Arc::new(ButtplugRemoteServer::default());
+      let server_transport = server_multiplexer.channel(channel_id);
+      async_manager::spawn(async move {
+        let connector = ButtplugRemoteServerConnector::<_, ButtplugServerJSONSerializer>::new(
+          server_transport,
+        );
+        let _ = server.start(connector).await;
+      })
+      .unwrap();
+      let connector = ButtplugRemoteClientConnector::<_, ButtplugClientJSONSerializer>::new(
+        client_multiplexer.channel(channel_id),
+      );
+      let client = ButtplugClient::new(&format!("Test Client {}", channel_id));
+      client.connect(connector).await.unwrap();
+      clients.push(client);
+    }
+    // Both handshakes went over the one link.
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    for client in &clients {
+      assert!(client.connected());
+      client.stop_all_devices().await.unwrap();
+    }
+    // Disconnecting one client leaves the other alone.
+    clients[0].disconnect().await.unwrap();
+    clients[1].stop_all_devices().await.unwrap();
+  });
+}
+
+#[test]
+fn test_multiplexed_transport_channel_already_connected() {
+  async_manager::block_on(async move {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let (client_link, _server_link) = link_pair(&connections);
+    let multiplexer = ButtplugTransportMultiplexer::new(client_link);
+    let (_outgoing_sender, outgoing_receiver) = channel(256);
+    let (incoming_sender, _incoming_receiver) = channel(256);
+    multiplexer
+      .channel(1)
+      .connect(outgoing_receiver, incoming_sender)
+      .await
+      .unwrap();
+    let (_outgoing_sender, outgoing_receiver) = channel(256);
+    let (incoming_sender, _incoming_receiver) = channel(256);
+    assert!(matches!(
+      multiplexer
+        .channel(1)
+        .connect(outgoing_receiver, incoming_sender)
+        .await,
+      Err(ButtplugConnectorError::ConnectorAlreadyConnected)
+    ));
+  });
+}
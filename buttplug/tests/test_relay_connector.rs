@@ -0,0 +1,138 @@
+#[cfg(feature = "websockets")]
+mod relay_connector_tests {
+  use async_tungstenite::{tokio::accept_async, tungstenite::protocol::Message};
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent},
+    connector::{
+      ButtplugRelayControlMessage, ButtplugRelayRole, ButtplugRemoteClientConnector,
+      ButtplugRemoteServerConnector, ButtplugWebsocketRelayTransport,
+    },
+    core::messages::serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer},
+    server::ButtplugRemoteServer,
+    util::async_manager,
+  };
+  use futures::{SinkExt, StreamExt};
+  use std::sync::Arc;
+  use tokio::net::TcpListener;
+
+  fn control(msg: &ButtplugRelayControlMessage) -> Message {
+    Message::Text(serde_json::to_string(msg).unwrap())
+  }
+
+  /// Bare bones relay, good for exactly one room with two peers.
+  async fn run_test_relay(listener: TcpListener) {
+    let mut peers = vec![];
+    while peers.len() < 2 {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut ws = accept_async(stream).await.unwrap();
+      let role = match ws.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text).unwrap() {
+          ButtplugRelayControlMessage::Join { role, .. } => role,
+          msg => panic!("Expected join, got {:?}", msg),
+        },
+        msg => panic!("Expected join, got {:?}", msg),
+      };
+      ws.send(control(&ButtplugRelayControlMessage::Joined))
+        .await
+        .unwrap();
+      peers.push((ws, role));
+    }
+    let (mut second, second_role) = peers.pop().unwrap();
+    let (mut first, first_role) = peers.pop().unwrap();
+    first
+      .send(control(&ButtplugRelayControlMessage::PeerJoined { role: second_role }))
+      .await
+      .unwrap();
+    second
+      .send(control(&ButtplugRelayControlMessage::PeerJoined { role: first_role }))
+      .await
+      .unwrap();
+    let (mut first_writer, mut first_reader) = first.split();
+    let (mut second_writer, mut second_reader) = second.split();
+    async_manager::spawn(async move {
+      while let Some(Ok(Message::Binary(frame))) = first_reader.next().await {
+        second_writer.send(Message::Binary(frame)).await.unwrap();
+      }
+      let _ = second_writer
+        .send(control(&ButtplugRelayControlMessage::PeerLeft))
+        .await;
+    })
+    .unwrap();
+    async_manager::spawn(async move {
+      while let Some(Ok(Message::Binary(frame))) = second_reader.next().await {
+        first_writer.send(Message::Binary(frame)).await.unwrap();
+      }
+      let _ = first_writer
+        .send(control(&ButtplugRelayControlMessage::PeerLeft))
+        .await;
+    })
+    .unwrap();
+  }
+
+  #[test]
+  fn test_client_server_through_relay() {
+    async_manager::block_on(async move {
+      let listener = TcpListener::bind("127.0.0.1:12350").await.unwrap();
+      async_manager::spawn(run_test_relay(listener)).unwrap();
+
+      let server = Arc::new(ButtplugRemoteServer::default());
+      let server_clone = server.clone();
+      async_manager::spawn(async move {
+        let connector = ButtplugRemoteServerConnector::<_, ButtplugServerJSONSerializer>::new(
+          ButtplugWebsocketRelayTransport::new(
+            "ws://127.0.0.1:12350",
+            "test-room",
+            ButtplugRelayRole::Server,
+          ),
+        );
+        server_clone.start(connector).await.unwrap();
+      })
+      .unwrap();
+
+      let connector = ButtplugRemoteClientConnector::<_, ButtplugClientJSONSerializer>::new(
+        ButtplugWebsocketRelayTransport::new(
+          "ws://127.0.0.1:12350",
+          "test-room",
+          ButtplugRelayRole::Client,
+        ),
+      );
+      let client = ButtplugClient::new("Test Client");
+      let mut event_stream = client.event_stream();
+      client.connect(connector).await.unwrap();
+      assert!(client.connected());
+      client.stop_all_devices().await.unwrap();
+
+      // The server leaving the room should disconnect the client.
+      server.disconnect().await.unwrap();
+      assert!(matches!(
+        event_stream.next().await,
+        Some(ButtplugClientEvent::ServerDisconnect)
+      ));
+    });
+  }
+
+  #[test]
+  fn test_relay_rejects_same_role() {
+    async_manager::block_on(async move {
+      let listener = TcpListener::bind("127.0.0.1:12351").await.unwrap();
+      async_manager::spawn(run_test_relay(listener)).unwrap();
+      let first = ButtplugClient::new("First Client");
+      let second = ButtplugClient::new("Second Client");
+      let connector = || {
+        ButtplugRemoteClientConnector::<_, ButtplugClientJSONSerializer>::new(
+          ButtplugWebsocketRelayTransport::new(
+            "ws://127.0.0.1:12351",
+            "test-room",
+            ButtplugRelayRole::Client,
+          ),
+        )
+      };
+      let (first_result, second_result) = futures::join!(
+        first.connect(connector()),
+        second.connect(connector())
+      );
+      assert!(first_result.is_err());
+      assert!(second_result.is_err());
+    });
+  }
+}
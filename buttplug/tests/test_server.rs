@@ -2,20 +2,33 @@ mod util;
 
 use buttplug::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     messages::{
-      self, ButtplugMessageSpecVersion, ButtplugServerMessage,
+      self, ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessage, ButtplugMessage,
+      ButtplugMessageSpecVersion, ButtplugServerMessage,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
   },
   device::{DeviceImplCommand, DeviceWriteCmd, Endpoint},
-  server::{ButtplugServer, ButtplugServerOptions},
-  test::check_test_recv_value,
+  server::{
+    command_storm_guard::DeviceCommandStormGuard,
+    device_command_transform::{DeviceCommandContext, DeviceCommandTransform},
+    device_consent::DeviceConsentState,
+    diagnostics::AdapterState,
+    kill_switch::{KillSwitchSource, KillSwitchTrigger},
+    ButtplugClientPermissions, ButtplugServer, ButtplugServerBuilder, ButtplugServerConnectionState,
+    ButtplugServerError, ButtplugServerOptions,
+  },
+  test::{check_test_recv_empty, check_test_recv_value},
   util::async_manager,
 };
-use futures::{pin_mut, Stream, StreamExt};
+use futures::{pin_mut, FutureExt, Stream, StreamExt};
 use futures_timer::Delay;
-use std::time::Duration;
+use std::{
+  sync::{Arc, Mutex},
+  thread,
+  time::Duration,
+};
 
 async fn setup_test_server(
   msg_union: messages::ButtplugClientMessage,
@@ -59,6 +72,94 @@ fn test_server_handshake_not_done_first() {
   });
 }
 
+#[test]
+fn test_server_device_command_before_handshake() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let msgs: [messages::ButtplugClientMessage; 3] = [
+      messages::StartScanning::default().into(),
+      messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.5)]).into(),
+      messages::StopAllDevices::default().into(),
+    ];
+    for msg in msgs {
+      let err = server.parse_message(msg).await.unwrap_err();
+      assert_eq!(err.error_code, messages::ErrorCode::ErrorHandshake);
+      assert!(matches!(
+        err.original_error(),
+        ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::RequestServerInfoExpected)
+      ));
+    }
+    assert_eq!(
+      server.connection_state(),
+      ButtplugServerConnectionState::AwaitingHandshake
+    );
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    assert!(server.parse_message(msg.into()).await.is_ok());
+    assert_eq!(
+      server.connection_state(),
+      ButtplugServerConnectionState::Connected
+    );
+    assert!(server.disconnect().await.is_ok());
+    assert_eq!(
+      server.connection_state(),
+      ButtplugServerConnectionState::AwaitingHandshake
+    );
+  });
+}
+
+#[test]
+fn test_server_client_permissions() {
+  async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      allow_raw_messages: true,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    server.set_client_permissions(ButtplugClientPermissions {
+      allow_scanning: false,
+      allow_raw_messages: false,
+    });
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    assert!(server.parse_message(msg.clone().into()).await.is_ok());
+    let msgs: [messages::ButtplugClientMessage; 3] = [
+      messages::StartScanning::default().into(),
+      messages::StopScanning::default().into(),
+      messages::RawWriteCmd::new(0, Endpoint::Tx, vec![0x0], false).into(),
+    ];
+    for msg in msgs {
+      let err = server.parse_message(msg).await.unwrap_err();
+      assert!(matches!(
+        err.original_error(),
+        ButtplugError::ButtplugMessageError(ButtplugMessageError::MessageNotPermitted(_))
+      ));
+    }
+    // Device control should still go through to the device manager.
+    let err = server
+      .parse_message(messages::VibrateCmd::new(10, vec![]).into())
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(_))
+    ));
+    // Permissions go back to the server defaults on disconnect.
+    assert!(server.disconnect().await.is_ok());
+    assert_eq!(
+      server.client_permissions(),
+      ButtplugClientPermissions::default()
+    );
+    assert!(server.parse_message(msg.into()).await.is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+  });
+}
+
 #[test]
 fn test_server_version_lt() {
   let msg =
@@ -187,131 +288,1160 @@ fn test_device_stop_on_ping_timeout() {
 }
 
 #[test]
-fn test_repeated_handshake() {
-  let msg = messages::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version2);
+fn test_server_fill_missing_vibrate_subcommands() {
   async_manager::block_on(async {
-    let (server, _recv) = setup_test_server((msg.clone()).into()).await;
-    assert!(server.connected());
-    let err = server.parse_message(msg.into()).await.unwrap_err();
-    assert!(matches!(
-      err.original_error(),
-      ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::HandshakeAlreadyHappened)
-    ));
+    let options = ButtplugServerOptions {
+      fill_missing_vibrate_subcommands: true,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    // Two vibrators, with a single command to set both to the same speed.
+    let device = helper.add_ble_device("PROSTATE VIBE").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    server
+      .parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 0.5)])
+          .into(),
+      )
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF3, 0, 64], false)),
+    );
   });
 }
 
 #[test]
-fn test_invalid_device_index() {
+fn test_server_max_temperature_level() {
   async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      max_temperature_level: 0.4,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    // One heater, with 3 heat steps.
+    let device = helper.add_ble_device("Emma NEO").await;
     let msg =
       messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
-    let (server, _) = setup_test_server(msg.into()).await;
-    let reply = server
-      .parse_message(messages::VibrateCmd::new(10, vec![]).into())
-      .await;
-    assert!(reply.is_err());
-    assert!(matches!(
-      reply.unwrap_err().original_error(),
-      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(_))
-    ));
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    // Asking for full heat only gets the capped level.
+    server
+      .parse_message(
+        messages::TemperatureCmd::new(
+          device_index,
+          vec![messages::TemperatureSubcommand::new(0, 1.0)],
+        )
+        .into(),
+      )
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(
+        Endpoint::Tx,
+        vec![0x55, 0x05, 0x00, 0x00, 0x01, 0x01],
+        false,
+      )),
+    );
   });
 }
 
 #[test]
-fn test_device_index_generation() {
+fn test_server_max_suction_level() {
   async_manager::block_on(async {
-    let server = ButtplugServer::default();
+    let options = ButtplugServerOptions {
+      max_suction_level: 0.5,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
     let recv = server.event_stream();
     pin_mut!(recv);
     let helper = server.add_test_comm_manager().unwrap();
-    helper.add_ble_device("Massage Demo").await;
-    helper.add_ble_device("Massage Demo").await;
-    assert!(server
-      .parse_message(
-        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
-          .into()
-      )
-      .await
-      .is_ok());
-    assert!(server
+    // One pump, with 10 pressure steps.
+    let device = helper.add_ble_device("AUTO-PUMP").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
       .parse_message(messages::StartScanning::default().into())
       .await
-      .is_ok());
-    // Check that we got an event back about a new device.
-    let mut index = 0u32;
-    while let Some(msg) = recv.next().await {
-      if let ButtplugServerMessage::ScanningFinished(_) = msg {
-        continue;
-      } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
-        assert_eq!(da.device_name(), "Aneros Vivi");
-        // Devices aren't guaranteed to be added in any specific order, the
-        // scheduler will do whatever it wants. So check boundaries instead of
-        // exact.
-        assert!(da.device_index() < 2);
-        index += 1;
-        // Found both devices we're looking for, finish test.
-        if index == 2 {
-          break;
-        }
-      } else {
-        panic!(
-          "Returned message was not a DeviceAdded message or timed out: {:?}",
-          msg
-        );
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
       }
+    };
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    for &(level, step) in &[(1.0, 5), (0.2, 2)] {
+      server
+        .parse_message(
+          messages::SuctionCmd::new(device_index, vec![messages::SuctionSubcommand::new(0, level)])
+            .into(),
+        )
+        .await
+        .unwrap();
+      check_test_recv_value(
+        &command_receiver,
+        DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xA0, 0x01, step], false)),
+      );
     }
   });
 }
 
+struct VibrateSpeedLimit(f64);
+
+impl DeviceCommandTransform for VibrateSpeedLimit {
+  fn transform(
+    &self,
+    _: &DeviceCommandContext,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugError> {
+    if let ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) = &message {
+      let speeds = msg
+        .speeds()
+        .iter()
+        .map(|cmd| messages::VibrateSubcommand::new(cmd.index(), cmd.speed().min(self.0)))
+        .collect();
+      let mut limited = messages::VibrateCmd::new(msg.device_index(), speeds);
+      limited.set_id(msg.id());
+      return Ok(limited.into());
+    }
+    Ok(message)
+  }
+}
+
+struct RejectStop;
+
+impl DeviceCommandTransform for RejectStop {
+  fn transform(
+    &self,
+    context: &DeviceCommandContext,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugError> {
+    if let ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) = message {
+      return Err(
+        ButtplugDeviceError::ProtocolRequirementError(format!(
+          "{} can't be stopped.",
+          context.device_name
+        ))
+        .into(),
+      );
+    }
+    Ok(message)
+  }
+}
+
 #[test]
-fn test_server_scanning_finished() {
+fn test_server_device_command_transforms() {
   async_manager::block_on(async {
     let server = ButtplugServer::default();
+    server.add_device_command_transform(VibrateSpeedLimit(0.5));
+    server.add_device_command_transform(RejectStop);
     let recv = server.event_stream();
     pin_mut!(recv);
     let helper = server.add_test_comm_manager().unwrap();
-    helper.add_ble_device("Massage Demo").await;
-    helper.add_ble_device("Massage Demo").await;
-    assert!(server
+    let device = helper.add_ble_device("Massage Demo").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    server
       .parse_message(
-        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
-          .into()
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 1.0)])
+          .into(),
       )
       .await
-      .is_ok());
-    assert!(server
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+    );
+    let err = server
+      .parse_message(messages::StopDeviceCmd::new(device_index).into())
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::ProtocolRequirementError(_))
+    ));
+  });
+}
+
+#[test]
+fn test_server_device_command_audit() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    server.add_device_command_transform(VibrateSpeedLimit(0.5));
+    server.add_device_command_transform(RejectStop);
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let audit = server.device_command_audit_stream();
+    pin_mut!(audit);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
       .parse_message(messages::StartScanning::default().into())
       .await
-      .is_ok());
-    // Check that we got an event back about a new device.
-    let mut count = 0u32;
-    let mut finish_received = false;
-    // We should get 3 messages: 2 DeviceAdded, 1 ScanningFinished.
-    while let Some(msg) = recv.next().await {
-      if matches!(msg, ButtplugServerMessage::ScanningFinished(_)) {
-        finish_received = true;
-        break;
-      }
-      count += 1;
-      if count == 3 {
-        break;
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
       }
-    }
-    assert!(finish_received);
+    };
     server
-      .add_comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+      .parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 1.0)])
+          .into(),
+      )
+      .await
       .unwrap();
-    helper.add_ble_device("Massage Demo").await;
+    let entry = audit.next().await.unwrap();
+    assert_eq!(entry.client_name, "Test Client");
+    assert_eq!(entry.device_index, device_index);
+    assert_eq!(entry.device_name, "Aneros Vivi");
+    // Commands show up as they were sent to the device, after transforms.
+    match entry.message {
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        assert_eq!(msg.speeds()[0].speed(), 0.5)
+      }
+      msg => panic!("Expected VibrateCmd, got {:?}", msg),
+    }
+    // Rejected commands never reach the device, so don't show up.
     assert!(server
+      .parse_message(messages::StopDeviceCmd::new(device_index).into())
+      .await
+      .is_err());
+    assert!(audit.next().now_or_never().is_none());
+  });
+}
+
+#[test]
+fn test_server_diagnostics() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    let diagnostics = server.diagnostics();
+    assert_eq!(
+      diagnostics.connection_state,
+      ButtplugServerConnectionState::AwaitingHandshake
+    );
+    assert!(diagnostics.devices.is_empty());
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
       .parse_message(messages::StartScanning::default().into())
       .await
-      .is_ok());
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    let diagnostics = server.diagnostics();
+    assert_eq!(diagnostics.client_name, Some("Test Client".to_owned()));
+    assert_eq!(diagnostics.comm_managers.len(), 1);
+    let comm_manager = &diagnostics.comm_managers[0];
+    assert_eq!(comm_manager.name, "TestDeviceCommunicationManager");
+    assert_eq!(comm_manager.adapter_state, AdapterState::Available);
+    assert!(comm_manager.last_error.is_none());
+    assert_eq!(diagnostics.devices.len(), 1);
+    let device = &diagnostics.devices[0];
+    assert_eq!(device.device_index, device_index);
+    assert_eq!(device.name, "Aneros Vivi");
+    assert_eq!(device.transport, Some(messages::DeviceTransport::Test));
+    assert_eq!(
+      device.comm_manager,
+      Some("TestDeviceCommunicationManager".to_owned())
+    );
+    assert!(device.connected);
   });
 }
 
-// TODO Test sending system message (Id 0)
-// TODO Test sending system message (Ok but Id > 0)
-// TODO Test repeated handshake
-// TODO Test scan with no comm managers
-// TODO Test message with no RequestServerInfo first
-// TODO Test sending device command for device that doesn't exist (in server)
+#[test]
+fn test_server_device_consent() {
+  async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      require_device_consent: true,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let requests = server.device_consent_requests();
+    pin_mut!(requests);
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    assert_eq!(server.device_consent(device_index), None);
+    let vibrate = || {
+      server.parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 0.5)])
+          .into(),
+      )
+    };
+    let check_error = |err: messages::Error, expected: ButtplugDeviceError| {
+      assert_eq!(
+        format!("{:?}", err.original_error()),
+        format!("{:?}", ButtplugError::from(expected))
+      );
+    };
+    // Nothing goes through until the request is answered, and only one
+    // request goes out.
+    check_error(
+      vibrate().await.unwrap_err(),
+      ButtplugDeviceError::DeviceConsentPending(device_index),
+    );
+    check_error(
+      vibrate().await.unwrap_err(),
+      ButtplugDeviceError::DeviceConsentPending(device_index),
+    );
+    let request = requests.next().await.unwrap();
+    assert_eq!(request.client_name, "Test Client");
+    assert_eq!(request.device_index, device_index);
+    assert!(requests.next().now_or_never().is_none());
+    assert_eq!(
+      server.device_consent(device_index),
+      Some(DeviceConsentState::Pending)
+    );
+    // Stopping never needs consent.
+    server
+      .parse_message(messages::StopDeviceCmd::new(device_index).into())
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false)),
+    );
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF2, 0], false)),
+    );
+    server.set_device_consent(device_index, false);
+    check_error(
+      vibrate().await.unwrap_err(),
+      ButtplugDeviceError::DeviceConsentDenied(device_index),
+    );
+    server.set_device_consent(device_index, true);
+    vibrate().await.unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+    );
+    // Consent doesn't carry over to the next client.
+    server.disconnect().await.unwrap();
+    assert_eq!(server.device_consent(device_index), None);
+  });
+}
+
+#[test]
+fn test_server_device_command_storm_guard() {
+  async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      device_command_storm_guard: Some(DeviceCommandStormGuard {
+        max_commands_per_second: 2,
+      }),
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let storms = server.device_command_storms();
+    pin_mut!(storms);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    let vibrate = |speed| {
+      server.parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, speed)])
+          .into(),
+      )
+    };
+    vibrate(0.25).await.unwrap();
+    vibrate(0.5).await.unwrap();
+    for _ in 0..2 {
+      assert_eq!(
+        format!("{:?}", vibrate(0.75).await.unwrap_err().original_error()),
+        format!(
+          "{:?}",
+          ButtplugError::from(ButtplugDeviceError::DeviceCommandRateExceeded(device_index, 2))
+        )
+      );
+    }
+    // One warning per storm.
+    let storm = storms.next().await.unwrap();
+    assert_eq!(storm.client_name, "Test Client");
+    assert_eq!(storm.device_index, device_index);
+    assert_eq!(storm.device_name, "Aneros Vivi");
+    assert_eq!(storm.max_commands_per_second, 2);
+    assert!(storms.next().now_or_never().is_none());
+    // Stopping is never throttled.
+    server
+      .parse_message(messages::StopDeviceCmd::new(device_index).into())
+      .await
+      .unwrap();
+    // The next client starts with a clean slate.
+    server.disconnect().await.unwrap();
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    vibrate(0.25).await.unwrap();
+  });
+}
+
+#[test]
+fn test_server_dry_run() {
+  async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      dry_run: true,
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let writes = server.dry_run_writes();
+    pin_mut!(writes);
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    server.parse_message(msg.into()).await.unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    server
+      .parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 0.5)])
+          .into(),
+      )
+      .await
+      .unwrap();
+    let write = writes.next().await.unwrap();
+    assert_eq!(write.device_index, device_index);
+    assert_eq!(write.device_name, "Aneros Vivi");
+    assert_eq!(write.command, DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false));
+    // Nothing made it to the device itself.
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    assert!(check_test_recv_empty(&command_receiver));
+  });
+}
+
+async fn physical_duplicate_device_added(
+  transport_priority: Vec<messages::DeviceTransport>,
+) -> Vec<messages::DeviceAdded> {
+  let options = ButtplugServerOptions {
+    device_transport_priority: transport_priority,
+    ..Default::default()
+  };
+  let server = ButtplugServer::new_with_options(&options).unwrap();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  let helper = server.add_test_comm_manager().unwrap();
+  // The same device, found over BLE and through a dongle reporting its
+  // address differently.
+  helper
+    .add_ble_device_with_transport(
+      "Massage Demo",
+      "a1b2c3d4e5f6",
+      messages::DeviceTransport::LovenseDongle,
+    )
+    .await;
+  helper
+    .add_ble_device_with_transport(
+      "Massage Demo",
+      "A1:B2:C3:D4:E5:F6",
+      messages::DeviceTransport::BluetoothLE,
+    )
+    .await;
+  let msg = messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  server.parse_message(msg.into()).await.unwrap();
+  server
+    .parse_message(messages::StartScanning::default().into())
+    .await
+    .unwrap();
+  let mut added = vec![];
+  loop {
+    match recv.next().await.unwrap() {
+      ButtplugServerMessage::DeviceAdded(da) => added.push(da),
+      ButtplugServerMessage::DeviceRemoved(dr) => {
+        added.retain(|da| da.device_index() != dr.device_index())
+      }
+      ButtplugServerMessage::ScanningFinished(_) => break,
+      _ => {}
+    }
+  }
+  added
+}
+
+#[test]
+fn test_server_physical_device_dedup() {
+  async_manager::block_on(async {
+    // Without a priority, whichever connection finished first is kept. Both
+    // connect at the same time, so either could win.
+    let added = physical_duplicate_device_added(vec![]).await;
+    assert_eq!(added.len(), 1);
+    let added = physical_duplicate_device_added(vec![
+      messages::DeviceTransport::LovenseDongle,
+      messages::DeviceTransport::BluetoothLE,
+    ])
+    .await;
+    assert_eq!(added.len(), 1);
+    assert_eq!(
+      added[0].device_metadata().as_ref().unwrap().transport(),
+      messages::DeviceTransport::LovenseDongle
+    );
+  });
+}
+
+/// Kill switch that hands its trigger back to the test.
+struct TestKillSwitch {
+  trigger: Arc<Mutex<Option<KillSwitchTrigger>>>,
+}
+
+impl KillSwitchSource for TestKillSwitch {
+  fn name(&self) -> &str {
+    "TestKillSwitch"
+  }
+
+  fn start(self: Box<Self>, trigger: KillSwitchTrigger) -> Result<(), ButtplugServerError> {
+    *self.trigger.lock().unwrap() = Some(trigger);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_server_kill_switch() {
+  async_manager::block_on(async {
+    let (server, recv) = setup_test_server(
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await;
+    pin_mut!(recv);
+    let trigger = Arc::new(Mutex::new(None));
+    server
+      .add_kill_switch(TestKillSwitch {
+        trigger: trigger.clone(),
+      })
+      .unwrap();
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      if let Some(ButtplugServerMessage::DeviceAdded(da)) = recv.next().await {
+        break da.device_index();
+      }
+    };
+    server
+      .parse_message(
+        messages::VibrateCmd::new(device_index, vec![messages::VibrateSubcommand::new(0, 0.5)])
+          .into(),
+      )
+      .await
+      .unwrap();
+    let command_receiver = device.get_endpoint_receiver(&Endpoint::Tx).unwrap();
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+    );
+    // Triggers work from threads outside of the async runtime.
+    let trigger = trigger.lock().unwrap().take().unwrap();
+    assert!(thread::spawn(move || trigger.trigger()).join().unwrap());
+    Delay::new(Duration::from_millis(100)).await;
+    check_test_recv_value(
+      &command_receiver,
+      DeviceImplCommand::Write(DeviceWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false)),
+    );
+    // The second vibrator was never on, so there's nothing to stop there.
+    assert!(check_test_recv_empty(&command_receiver));
+  });
+}
+
+#[test]
+fn test_repeated_handshake() {
+  let msg = messages::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version2);
+  async_manager::block_on(async {
+    let (server, _recv) = setup_test_server((msg.clone()).into()).await;
+    assert!(server.connected());
+    let err = server.parse_message(msg.into()).await.unwrap_err();
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::HandshakeAlreadyHappened)
+    ));
+  });
+}
+
+#[test]
+fn test_server_rejects_system_id_from_client() {
+  async_manager::block_on(async {
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    let (server, _) = setup_test_server(msg.into()).await;
+    // Id 0 is reserved for messages the server sends on its own.
+    let mut ping = messages::Ping::default();
+    ping.set_id(0);
+    let err = server.parse_message(ping.into()).await.unwrap_err();
+    assert_eq!(err.id(), 0);
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugMessageError(ButtplugMessageError::InvalidMessageContents(_))
+    ));
+  });
+}
+
+#[test]
+fn test_invalid_device_index() {
+  async_manager::block_on(async {
+    let msg =
+      messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    let (server, _) = setup_test_server(msg.into()).await;
+    let reply = server
+      .parse_message(messages::VibrateCmd::new(10, vec![]).into())
+      .await;
+    assert!(reply.is_err());
+    assert!(matches!(
+      reply.unwrap_err().original_error(),
+      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(_))
+    ));
+  });
+}
+
+#[test]
+fn test_device_index_generation() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    helper.add_ble_device("Massage Demo").await;
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    // Check that we got an event back about a new device.
+    let mut index = 0u32;
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::ScanningFinished(_) = msg {
+        continue;
+      } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+        assert_eq!(da.device_name(), "Aneros Vivi");
+        // Devices aren't guaranteed to be added in any specific order, the
+        // scheduler will do whatever it wants. So check boundaries instead of
+        // exact.
+        assert!(da.device_index() < 2);
+        index += 1;
+        // Found both devices we're looking for, finish test.
+        if index == 2 {
+          break;
+        }
+      } else {
+        panic!(
+          "Returned message was not a DeviceAdded message or timed out: {:?}",
+          msg
+        );
+      }
+    }
+  });
+}
+
+#[test]
+fn test_server_scanning_finished() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    helper.add_ble_device("Massage Demo").await;
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    // Check that we got an event back about a new device.
+    let mut count = 0u32;
+    let mut finish_received = false;
+    // We should get 3 messages: 2 DeviceAdded, 1 ScanningFinished.
+    while let Some(msg) = recv.next().await {
+      if matches!(msg, ButtplugServerMessage::ScanningFinished(_)) {
+        finish_received = true;
+        break;
+      }
+      count += 1;
+      if count == 3 {
+        break;
+      }
+    }
+    assert!(finish_received);
+    server
+      .add_comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+      .unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+  });
+}
+
+// TODO Test sending system message (Id 0)
+// TODO Test sending system message (Ok but Id > 0)
+// TODO Test repeated handshake
+// TODO Test scan with no comm managers
+// TODO Test message with no RequestServerInfo first
+// TODO Test sending device command for device that doesn't exist (in server)
+
+#[test]
+fn test_server_builder_comm_managers() {
+  async_manager::block_on(async {
+    let server = ButtplugServerBuilder::default()
+      .comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+      .finish()
+      .unwrap();
+    assert!(matches!(
+      server.add_comm_manager(util::DelayDeviceCommunicationManagerBuilder::default()),
+      Err(ButtplugServerError::DeviceManagerTypeAlreadyAdded(_))
+    ));
+    assert!(matches!(
+      ButtplugServerBuilder::default()
+        .comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+        .comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+        .finish(),
+      Err(ButtplugServerError::DeviceManagerTypeAlreadyAdded(_))
+    ));
+  });
+}
+
+#[test]
+fn test_server_scanning_finished_waits_for_all_managers() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    server
+      .add_comm_manager(util::DelayDeviceCommunicationManagerBuilder::default())
+      .unwrap();
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    // The test manager finishes right away, but the delay manager keeps
+    // scanning until stopped, so we should only see the device.
+    let mut device_added = false;
+    loop {
+      let next = futures::select! {
+        msg = recv.next().fuse() => msg,
+        _ = Delay::new(Duration::from_millis(200)).fuse() => None,
+      };
+      match next {
+        Some(ButtplugServerMessage::DeviceAdded(_)) => device_added = true,
+        Some(ButtplugServerMessage::ScanningFinished(_)) => {
+          panic!("ScanningFinished sent while a manager was still scanning.")
+        }
+        Some(_) => {}
+        None => break,
+      }
+    }
+    assert!(device_added);
+    assert!(server
+      .parse_message(messages::StopScanning::default().into())
+      .await
+      .is_ok());
+    let mut finished_count = 0u32;
+    loop {
+      let next = futures::select! {
+        msg = recv.next().fuse() => msg,
+        _ = Delay::new(Duration::from_millis(200)).fuse() => None,
+      };
+      match next {
+        Some(ButtplugServerMessage::ScanningFinished(_)) => finished_count += 1,
+        Some(_) => {}
+        None => break,
+      }
+    }
+    assert_eq!(finished_count, 1);
+  });
+}
+
+#[test]
+fn test_server_device_display_names() {
+  async_manager::block_on(async {
+    let options = ButtplugServerOptions {
+      user_device_configuration_json: Some(
+        r#"{
+        "protocols": {},
+        "devices": {
+          "display-name-test-address": {
+            "display-name": "Left toy"
+          }
+        }
+      }"#
+          .to_owned(),
+      ),
+      ..Default::default()
+    };
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper
+      .add_ble_device_with_address("Massage Demo", "display-name-test-address")
+      .await;
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::DeviceAdded(da) = msg {
+        assert_eq!(da.device_name(), "Left toy");
+        break;
+      }
+    }
+    server.set_device_display_name("display-name-test-address", "Partner's ring");
+    loop {
+      match recv.next().await.unwrap() {
+        ButtplugServerMessage::DeviceUpdated(du) => {
+          assert_eq!(du.device_name(), "Partner's ring");
+          break;
+        }
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        msg => panic!("Expected DeviceUpdated, got {:?}", msg),
+      }
+    }
+    assert_eq!(
+      server
+        .device_display_names()
+        .get("display-name-test-address")
+        .map(String::as_str),
+      Some("Partner's ring")
+    );
+    match server
+      .parse_message(messages::RequestDeviceList::default().into())
+      .await
+      .unwrap()
+    {
+      ButtplugServerMessage::DeviceList(list) => {
+        assert_eq!(list.devices()[0].device_name, "Partner's ring")
+      }
+      msg => panic!("Expected DeviceList, got {:?}", msg),
+    }
+    assert_eq!(
+      server.remove_device_display_name("display-name-test-address"),
+      Some("Partner's ring".to_owned())
+    );
+  });
+}
+
+#[test]
+fn test_server_device_updated_spec_version() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper
+      .add_ble_device_with_address("Massage Demo", "device-updated-v1-address")
+      .await;
+    // Spec v1 has no DeviceUpdated, so those clients don't get it.
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version1)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    let mut device_index = None;
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::DeviceAdded(da) = msg {
+        device_index = Some(da.device_index());
+        break;
+      }
+    }
+    server.set_device_display_name("device-updated-v1-address", "Bedside");
+    assert!(server.refresh_device(device_index.unwrap()).is_ok());
+    while let Some(Some(msg)) = recv.next().now_or_never() {
+      assert!(!matches!(msg, ButtplugServerMessage::DeviceUpdated(_)));
+    }
+  });
+}
+
+#[test]
+fn test_server_comm_manager_error_event() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper
+      .send_error(ButtplugDeviceError::DeviceCommunicationError(
+        "Adapter went away".to_owned(),
+      ))
+      .await;
+    match recv.next().await.unwrap() {
+      ButtplugServerMessage::Error(err) => {
+        assert_eq!(err.error_code, messages::ErrorCode::ErrorDevice);
+        assert!(matches!(
+          err.original_error(),
+          ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceCommunicationError(_))
+        ));
+      }
+      msg => panic!("Expected error message, got {:?}", msg),
+    }
+  });
+}
+
+#[test]
+fn test_server_adapter_removed() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    helper.add_ble_device("Massage Demo").await;
+    server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let mut device_index = 100;
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceAdded(da) => {
+          device_index = da.device_index();
+          break;
+        }
+        msg => panic!("Expected DeviceAdded, got {:?}", msg),
+      }
+    }
+    helper
+      .remove_adapter(ButtplugDeviceError::DeviceCommunicationError(
+        "Adapter unplugged".to_owned(),
+      ))
+      .await;
+    let mut removed = false;
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceRemoved(dr) => {
+          assert_eq!(dr.device_index(), device_index);
+          removed = true;
+        }
+        ButtplugServerMessage::Error(err) => {
+          assert_eq!(err.error_code, messages::ErrorCode::ErrorDevice);
+          break;
+        }
+        msg => panic!("Expected DeviceRemoved or Error, got {:?}", msg),
+      }
+    }
+    assert!(removed);
+    assert!(server
+      .parse_message(messages::StopDeviceCmd::new(device_index).into())
+      .await
+      .is_err());
+  });
+}
+
+#[test]
+fn test_server_device_removed_sent_once() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceAdded(_) => break,
+        msg => panic!("Expected DeviceAdded, got {:?}", msg),
+      }
+    }
+    // Both the comm manager and the device itself notice the device is gone.
+    helper
+      .remove_adapter(ButtplugDeviceError::DeviceCommunicationError(
+        "Adapter unplugged".to_owned(),
+      ))
+      .await;
+    device.disconnect().await.unwrap();
+    Delay::new(Duration::from_millis(100)).await;
+    let mut removed_count = 0;
+    while let Some(Some(msg)) = recv.next().now_or_never() {
+      if let ButtplugServerMessage::DeviceRemoved(_) = msg {
+        removed_count += 1;
+      }
+    }
+    assert_eq!(removed_count, 1);
+  });
+}
+
+#[test]
+fn test_server_removes_silently_disconnected_device() {
+  async_manager::block_on(async {
+    let server = ButtplugServer::default();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let mut device_index = 100;
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceAdded(da) => {
+          device_index = da.device_index();
+          break;
+        }
+        msg => panic!("Expected DeviceAdded, got {:?}", msg),
+      }
+    }
+    // No removal event, the server has to notice on its own.
+    device.set_connected(false);
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceRemoved(dr) => {
+          assert_eq!(dr.device_index(), device_index);
+          break;
+        }
+        msg => panic!("Expected DeviceRemoved, got {:?}", msg),
+      }
+    }
+  });
+}
@@ -3,10 +3,11 @@ use buttplug::{
   core::{
     errors::{ButtplugDeviceError, ButtplugError},
     messages::{
-      self, ButtplugDeviceMessageType, ButtplugServerMessage, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+      self, ButtplugDeviceMessage, ButtplugDeviceMessageType, ButtplugMessage,
+      ButtplugServerMessage, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
   },
-  device::Endpoint,
+  device::{ButtplugDeviceEvent, Endpoint},
   server::{ButtplugServer, ButtplugServerOptions},
   util::async_manager,
 };
@@ -224,6 +225,58 @@ fn test_reject_on_no_raw_message() {
   });
 }
 
+#[test]
+fn test_raw_subscribe_notifications() {
+  async_manager::block_on(async {
+    let mut options = ButtplugServerOptions::default();
+    options.allow_raw_messages = true;
+    let server = ButtplugServer::new_with_options(&options).unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    let helper = server.add_test_comm_manager().unwrap();
+    let device = helper.add_ble_device("Massage Demo").await;
+    server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .unwrap();
+    server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .unwrap();
+    let device_index = loop {
+      match recv.next().await.unwrap() {
+        ButtplugServerMessage::DeviceAdded(da) => break da.device_index(),
+        _ => continue,
+      }
+    };
+    server
+      .parse_message(messages::RawSubscribeCmd::new(device_index, Endpoint::Tx).into())
+      .await
+      .unwrap();
+    device.send_event(ButtplugDeviceEvent::Notification(
+      device.address(),
+      Endpoint::Tx,
+      vec![1, 2, 3],
+    ));
+    loop {
+      if let ButtplugServerMessage::RawReading(reading) = recv.next().await.unwrap() {
+        assert_eq!(reading.id(), 0);
+        assert_eq!(reading.device_index(), device_index);
+        assert_eq!(reading.endpoint(), Endpoint::Tx);
+        assert_eq!(reading.data(), &vec![1, 2, 3]);
+        break;
+      }
+    }
+    server
+      .parse_message(messages::RawUnsubscribeCmd::new(device_index, Endpoint::Tx).into())
+      .await
+      .unwrap();
+  });
+}
+
 #[test]
 fn test_repeated_address_additions() {
   async_manager::block_on(async {
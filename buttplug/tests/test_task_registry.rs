@@ -0,0 +1,20 @@
+use buttplug::util::async_manager::{self, running_tasks, shutdown_and_join, spawn_named};
+use futures::future;
+use futures_timer::Delay;
+use std::time::Duration;
+
+// The task registry is global, so this file only has the one test, to keep
+// other tests' tasks out of it.
+#[test]
+fn test_shutdown_and_join() {
+  async_manager::block_on(async {
+    spawn_named("Finished Task", async {}).unwrap();
+    spawn_named("Pending Task", future::pending()).unwrap();
+    spawn_named("Pending Task", future::pending()).unwrap();
+    // Let the finished task run.
+    Delay::new(Duration::from_millis(50)).await;
+    assert_eq!(running_tasks(), vec!["Pending Task", "Pending Task"]);
+    assert!(shutdown_and_join(Duration::from_secs(1)).await.is_empty());
+    assert!(running_tasks().is_empty());
+  });
+}
@@ -31,6 +31,7 @@ mod websocket_connector_tests {
           ButtplugWebsocketServerTransportOptions {
             ws_listen_on_all_interfaces: false,
             ws_insecure_port: 12345u16,
+            compress_messages: false,
           },
         ));
         server_clone.start(connector).await.unwrap();
@@ -83,6 +84,7 @@ mod websocket_connector_tests {
           ButtplugWebsocketServerTransportOptions {
             ws_listen_on_all_interfaces: false,
             ws_insecure_port: 12347u16,
+            compress_messages: false,
           },
         ));
 
@@ -97,6 +99,50 @@ mod websocket_connector_tests {
       server.disconnect().await.unwrap();
     });
   }
+
+  #[test]
+  fn test_client_ws_client_server_ws_server_compressed() {
+    async_manager::block_on(async move {
+      let test_server = ButtplugRemoteServer::default();
+      let server = Arc::new(test_server);
+      let server_clone = server.clone();
+      async_manager::spawn(async move {
+        let connector = ButtplugRemoteServerConnector::<
+          ButtplugWebsocketServerTransport,
+          ButtplugServerJSONSerializer,
+        >::new(ButtplugWebsocketServerTransport::new(
+          ButtplugWebsocketServerTransportOptions {
+            ws_listen_on_all_interfaces: false,
+            ws_insecure_port: 12348u16,
+            compress_messages: true,
+          },
+        ));
+        server_clone.start(connector).await.unwrap();
+      })
+      .unwrap();
+      let mut client = None;
+      for _ in 0..10u8 {
+        let connector = ButtplugRemoteClientConnector::<
+          ButtplugWebsocketClientTransport,
+          ButtplugClientJSONSerializer,
+        >::new(
+          ButtplugWebsocketClientTransport::new_insecure_connector("ws://127.0.0.1:12348")
+            .with_compression(),
+        );
+
+        let test_client = ButtplugClient::new("Test Client");
+        if test_client.connect(connector).await.is_ok() {
+          client = Some(test_client);
+          break;
+        }
+        Delay::new(Duration::from_secs(1)).await;
+      }
+      // Messages still make it across once compression is on.
+      let client = client.expect("Client should connect");
+      client.stop_all_devices().await.unwrap();
+      server.disconnect().await.unwrap();
+    });
+  }
 }
 
 // TODO Test disconnection event from server side
@@ -305,3 +305,110 @@ fn impl_buttplug_protocol_properties_macro(ast: &syn::DeriveInput) -> TokenStrea
   };
   gen.into()
 }
+
+/// Declares a protocol's features in code, for protocols whose capabilities
+/// are the same for every device they handle. Features are listed in a
+/// `buttplug_capabilities` attribute, by message type, with the step count of
+/// each feature:
+///
+/// ```ignore
+/// #[derive(ButtplugProtocolProperties, ButtplugProtocolCapabilities)]
+/// #[buttplug_capabilities(VibrateCmd(steps(20, 20)), RotateCmd(steps(10)), BatteryLevelCmd)]
+/// pub struct Example { ... }
+/// ```
+///
+/// Messages without features (i.e. BatteryLevelCmd) are listed on their own.
+/// Like the other protocol derives, the generated code expects
+/// ButtplugProtocolCapabilities, ButtplugDeviceMessageType,
+/// DeviceMessageAttributes and DeviceMessageAttributesMap to be in scope.
+#[proc_macro_derive(ButtplugProtocolCapabilities, attributes(buttplug_capabilities))]
+pub fn buttplug_protocol_capabilities_derive(input: TokenStream) -> TokenStream {
+  // Construct a representation of Rust code as a syntax tree
+  // that we can manipulate
+  let ast = syn::parse(input).unwrap();
+
+  // Build the trait implementation
+  impl_buttplug_protocol_capabilities_macro(&ast)
+}
+
+fn parse_capability_steps(meta: &syn::Meta) -> Vec<u32> {
+  let list = match meta {
+    syn::Meta::List(list) if list.path.is_ident("steps") => list,
+    _ => panic!("Capabilities only take a steps(...) list"),
+  };
+  list
+    .nested
+    .iter()
+    .map(|step| match step {
+      syn::NestedMeta::Lit(syn::Lit::Int(int)) => int
+        .base10_parse::<u32>()
+        .expect("Step counts must fit in a u32"),
+      _ => panic!("Step counts must be integers"),
+    })
+    .collect()
+}
+
+fn impl_buttplug_protocol_capabilities_macro(ast: &syn::DeriveInput) -> TokenStream {
+  let name = &ast.ident;
+  let mut message_types = vec![];
+  let mut feature_counts = vec![];
+  let mut step_counts = vec![];
+  for attr in ast
+    .attrs
+    .iter()
+    .filter(|attr| attr.path.is_ident("buttplug_capabilities"))
+  {
+    let list = match attr.parse_meta() {
+      Ok(syn::Meta::List(list)) => list,
+      _ => panic!("buttplug_capabilities must be a list of message types"),
+    };
+    for capability in list.nested.iter() {
+      let (message_type, steps) = match capability {
+        syn::NestedMeta::Meta(syn::Meta::Path(path)) => (path.clone(), None),
+        syn::NestedMeta::Meta(syn::Meta::List(list)) => {
+          let steps: Vec<u32> = list
+            .nested
+            .iter()
+            .flat_map(|nested| match nested {
+              syn::NestedMeta::Meta(meta) => parse_capability_steps(meta),
+              _ => panic!("Capabilities only take a steps(...) list"),
+            })
+            .collect();
+          (list.path.clone(), Some(steps))
+        }
+        _ => panic!("buttplug_capabilities must be a list of message types"),
+      };
+      message_types.push(message_type);
+      match steps {
+        Some(steps) => {
+          let feature_count = steps.len() as u32;
+          feature_counts.push(quote! { Some(#feature_count) });
+          step_counts.push(quote! { Some(vec![#(#steps),*]) });
+        }
+        None => {
+          feature_counts.push(quote! { None });
+          step_counts.push(quote! { None });
+        }
+      }
+    }
+  }
+  let gen = quote! {
+      impl ButtplugProtocolCapabilities for #name {
+          fn declared_message_attributes() -> DeviceMessageAttributesMap {
+            let mut attributes = DeviceMessageAttributesMap::new();
+            #(
+              attributes.insert(
+                ButtplugDeviceMessageType::#message_types,
+                DeviceMessageAttributes {
+                  feature_count: #feature_counts,
+                  step_count: #step_counts,
+                  ..Default::default()
+                },
+              );
+            )*
+            attributes
+          }
+        }
+  };
+  gen.into()
+}